@@ -0,0 +1,466 @@
+//! Detection of anomalies in functions, usually caused by obfuscation or virtualization.
+//!
+//! The standard decompiler will probably struggle with functions flagged here. [ObfuscationReport] looks at the whole
+//! bytecode instead : stripped debug information, renamed types and fields, huge dispatchers.
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use hlbc::analysis::annotations::{Annotations, Note, Severity};
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, RefFun, RefString, Type};
+use hlbc::Bytecode;
+
+/// Limits above which something is considered an anomaly
+#[derive(Debug, Clone)]
+pub struct Thresholds {
+    /// Number of registers in a function
+    pub registers: usize,
+    /// Number of cases of a switch used as a dispatcher
+    pub switch_cases: usize,
+    /// Entropy of a string constant in bits per byte
+    pub string_entropy: f64,
+    /// Minimum length of a string constant to compute its entropy, short strings are always low entropy
+    pub string_min_len: usize,
+    /// Number of cases of a switch considered huge, even outside a loop
+    pub huge_switch: usize,
+    /// Minimum number of names of types, fields and methods to judge them
+    pub min_names: usize,
+    /// Share of names of one or two characters
+    pub short_names: f64,
+    /// Share of names with an abnormal entropy
+    pub abnormal_names: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            registers: 200,
+            switch_cases: 8,
+            string_entropy: 4.5,
+            string_min_len: 24,
+            huge_switch: 64,
+            min_names: 20,
+            short_names: 0.3,
+            abnormal_names: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    /// Unusually high number of registers
+    ManyRegisters(usize),
+    /// A big switch inside a loop, a state machine driving the control flow (control flow flattening)
+    FlattenedControlFlow { pos: usize, cases: usize },
+    /// A conditional jump whose outcome is known statically
+    OpaquePredicate { pos: usize },
+    /// A string constant looking like random data, maybe encrypted
+    HighEntropyString {
+        pos: usize,
+        string: RefString,
+        entropy: f64,
+    },
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::ManyRegisters(n) => write!(f, "{n} registers"),
+            Anomaly::FlattenedControlFlow { pos, cases } => {
+                write!(f, "flattened control flow at {pos} ({cases} cases)")
+            }
+            Anomaly::OpaquePredicate { pos } => write!(f, "opaque predicate at {pos}"),
+            Anomaly::HighEntropyString {
+                pos,
+                string,
+                entropy,
+            } => write!(
+                f,
+                "high entropy string@{} at {pos} ({entropy:.2} bits/byte)",
+                string.0
+            ),
+        }
+    }
+}
+
+impl Anomaly {
+    /// Position of the instruction where the anomaly is, if it is about a single instruction
+    pub fn pos(&self) -> Option<usize> {
+        match *self {
+            Anomaly::ManyRegisters(_) => None,
+            Anomaly::FlattenedControlFlow { pos, .. }
+            | Anomaly::OpaquePredicate { pos }
+            | Anomaly::HighEntropyString { pos, .. } => Some(pos),
+        }
+    }
+}
+
+/// Shannon entropy of some data in bits per byte
+pub fn shannon_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Find anomalies in a function
+pub fn anomalies(code: &Bytecode, f: &Function, thresholds: &Thresholds) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    if f.regs.len() > thresholds.registers {
+        anomalies.push(Anomaly::ManyRegisters(f.regs.len()));
+    }
+    for (i, o) in f.ops.iter().enumerate() {
+        match o {
+            Opcode::Switch { offsets, .. } if offsets.len() >= thresholds.switch_cases => {
+                // The dispatcher is jumped back to after each state
+                let dispatched = f.ops.iter().enumerate().skip(i + 1).any(|(j, o)| {
+                    matches!(o, &Opcode::JAlways { offset } if (j as i32 + offset + 1) as usize <= i)
+                });
+                if dispatched {
+                    anomalies.push(Anomaly::FlattenedControlFlow {
+                        pos: i,
+                        cases: offsets.len(),
+                    });
+                }
+            }
+            Opcode::String { ptr, .. } => {
+                let s = ptr.resolve(&code.strings);
+                if s.len() >= thresholds.string_min_len {
+                    let entropy = shannon_entropy(s.as_bytes());
+                    if entropy >= thresholds.string_entropy {
+                        anomalies.push(Anomaly::HighEntropyString {
+                            pos: i,
+                            string: *ptr,
+                            entropy,
+                        });
+                    }
+                }
+            }
+            _ => {
+                if is_opaque_predicate(f, i) {
+                    anomalies.push(Anomaly::OpaquePredicate { pos: i });
+                }
+            }
+        }
+    }
+    anomalies
+}
+
+/// Replace the `anomaly` notes of a function, anomalies about the whole function are on the first instruction
+pub fn annotate_anomalies(
+    code: &Bytecode,
+    f: &Function,
+    thresholds: &Thresholds,
+    annotations: &mut Annotations,
+) {
+    annotations.clear(f.findex, "anomaly");
+    for a in anomalies(code, f, thresholds) {
+        let note = Note::new(Severity::Warning, "anomaly", a.to_string());
+        annotations.add(f.findex, a.pos().unwrap_or(0), note);
+    }
+}
+
+/// Every function with at least one anomaly
+pub fn suspicious_functions<'a>(
+    code: &'a Bytecode,
+    thresholds: &'a Thresholds,
+) -> impl Iterator<Item = (&'a Function, Vec<Anomaly>)> + 'a {
+    code.functions.iter().filter_map(move |f| {
+        let anomalies = anomalies(code, f, thresholds);
+        if anomalies.is_empty() {
+            None
+        } else {
+            Some((f, anomalies))
+        }
+    })
+}
+
+/// Sign of obfuscation of the whole bytecode
+#[derive(Debug, Clone, PartialEq)]
+pub enum Obfuscation {
+    /// Compiled without debug information : no source files, lines or names of locals
+    StrippedDebugInfo,
+    /// Many types, fields and methods have a name of one or two characters
+    ShortNames { count: usize, total: usize },
+    /// Many names have an abnormal entropy : few distinct characters (`lIlIIl`), no vowel (`xkqzvr`) or mostly digits
+    AbnormalNames {
+        count: usize,
+        total: usize,
+        /// A few of the names
+        examples: Vec<String>,
+    },
+    /// Switches with a huge number of cases, like the dispatcher of a virtual machine : function, position and cases
+    HugeSwitches(Vec<(RefFun, usize, usize)>),
+}
+
+impl Display for Obfuscation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Obfuscation::StrippedDebugInfo => write!(f, "no debug information"),
+            Obfuscation::ShortNames { count, total } => {
+                write!(f, "{count}/{total} names of one or two characters")
+            }
+            Obfuscation::AbnormalNames {
+                count,
+                total,
+                examples,
+            } => write!(
+                f,
+                "{count}/{total} names look generated ({})",
+                examples.join(", ")
+            ),
+            Obfuscation::HugeSwitches(switches) => {
+                write!(f, "{} huge switches (", switches.len())?;
+                for (i, (findex, pos, cases)) in switches.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "fn@{}:{pos} {cases} cases", findex.0)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Signs of obfuscation found in a bytecode. Tools warn the user with it and can adjust : names derived from other
+/// names mean nothing once [names_obfuscated](ObfuscationReport::names_obfuscated).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObfuscationReport {
+    pub signs: Vec<Obfuscation>,
+}
+
+/// Types from the standard library keep their names
+fn is_std(name: &str) -> bool {
+    name.starts_with('$') || ["hl.", "haxe.", "sys."].iter().any(|p| name.starts_with(p))
+}
+
+/// Names of the types, fields, methods and enum constructs written by the user
+fn user_names(code: &Bytecode) -> Vec<&str> {
+    let mut names = Vec::new();
+    for t in &code.types {
+        match t {
+            Type::Obj(obj) | Type::Struct(obj) => {
+                let name = obj.name.resolve(&code.strings);
+                if is_std(name) {
+                    continue;
+                }
+                names.push(name.rsplit('.').next().unwrap_or(name));
+                names.extend(obj.own_fields.iter().map(|f| f.name.resolve(&code.strings)));
+                names.extend(obj.protos.iter().map(|p| p.name.resolve(&code.strings)));
+            }
+            Type::Enum {
+                name, constructs, ..
+            } => {
+                let name = name.resolve(&code.strings);
+                if is_std(name) {
+                    continue;
+                }
+                names.push(name.rsplit('.').next().unwrap_or(name));
+                names.extend(constructs.iter().map(|c| c.name.resolve(&code.strings)));
+            }
+            _ => {}
+        }
+    }
+    names.retain(|n| !n.is_empty());
+    names
+}
+
+/// A name too regular or too random to be written by a human
+pub fn is_abnormal_name(name: &str) -> bool {
+    let len = name.chars().count();
+    if len < 6 {
+        return false;
+    }
+    // Entropy relative to the highest possible for this length
+    let low_entropy = shannon_entropy(name.as_bytes()) / (len as f64).log2() < 0.5;
+    let no_vowel = name.chars().all(|c| c.is_ascii_alphabetic())
+        && !name.chars().any(|c| "aeiouyAEIOUY".contains(c));
+    let digits = name.chars().filter(char::is_ascii_digit).count() * 2 >= len;
+    low_entropy || no_vowel || digits
+}
+
+impl ObfuscationReport {
+    pub fn new(code: &Bytecode, thresholds: &Thresholds) -> Self {
+        let mut signs = Vec::new();
+        if code.debug_files.is_none() {
+            signs.push(Obfuscation::StrippedDebugInfo);
+        }
+
+        let names = user_names(code);
+        let total = names.len();
+        if total >= thresholds.min_names {
+            let count = names.iter().filter(|n| n.chars().count() <= 2).count();
+            if count as f64 >= total as f64 * thresholds.short_names {
+                signs.push(Obfuscation::ShortNames { count, total });
+            }
+            let abnormal: Vec<&str> = names
+                .iter()
+                .copied()
+                .filter(|n| is_abnormal_name(n))
+                .collect();
+            if abnormal.len() as f64 >= total as f64 * thresholds.abnormal_names {
+                signs.push(Obfuscation::AbnormalNames {
+                    count: abnormal.len(),
+                    total,
+                    examples: abnormal.iter().take(3).map(|&n| n.to_owned()).collect(),
+                });
+            }
+        }
+
+        let switches: Vec<_> = code
+            .functions
+            .iter()
+            .flat_map(|f| {
+                f.ops.iter().enumerate().filter_map(move |(i, o)| match o {
+                    Opcode::Switch { offsets, .. } if offsets.len() >= thresholds.huge_switch => {
+                        Some((f.findex, i, offsets.len()))
+                    }
+                    _ => None,
+                })
+            })
+            .collect();
+        if !switches.is_empty() {
+            signs.push(Obfuscation::HugeSwitches(switches));
+        }
+        Self { signs }
+    }
+
+    /// No sign of obfuscation
+    pub fn is_empty(&self) -> bool {
+        self.signs.is_empty()
+    }
+
+    /// Names of types and fields were replaced
+    pub fn names_obfuscated(&self) -> bool {
+        self.signs.iter().any(|s| {
+            matches!(
+                s,
+                Obfuscation::ShortNames { .. } | Obfuscation::AbnormalNames { .. }
+            )
+        })
+    }
+}
+
+/// A conditional jump on a constant just loaded or comparing a register with itself
+fn is_opaque_predicate(f: &Function, pos: usize) -> bool {
+    let prev = pos.checked_sub(1).map(|p| &f.ops[p]);
+    match (&f.ops[pos], prev) {
+        (
+            Opcode::JTrue { cond, .. } | Opcode::JFalse { cond, .. },
+            Some(Opcode::Bool { dst, .. } | Opcode::Int { dst, .. }),
+        ) => cond == dst,
+        (Opcode::JNull { reg, .. } | Opcode::JNotNull { reg, .. }, Some(Opcode::Null { dst })) => {
+            reg == dst
+        }
+        (
+            Opcode::JSLt { a, b, .. }
+            | Opcode::JSGte { a, b, .. }
+            | Opcode::JSGt { a, b, .. }
+            | Opcode::JSLte { a, b, .. }
+            | Opcode::JULt { a, b, .. }
+            | Opcode::JUGte { a, b, .. }
+            | Opcode::JNotLt { a, b, .. }
+            | Opcode::JNotGte { a, b, .. }
+            | Opcode::JEq { a, b, .. }
+            | Opcode::JNotEq { a, b, .. },
+            _,
+        ) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::Type;
+
+    use crate::anomaly::{
+        is_abnormal_name, shannon_entropy, Obfuscation, ObfuscationReport, Thresholds,
+    };
+
+    #[test]
+    fn entropy() {
+        assert_eq!(shannon_entropy(b""), 0.0);
+        assert_eq!(shannon_entropy(b"aaaa"), 0.0);
+        assert_eq!(shannon_entropy(b"abab"), 1.0);
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(shannon_entropy(&all), 8.0);
+    }
+
+    #[test]
+    fn names() {
+        assert!(is_abnormal_name("lIlIIlIl"));
+        assert!(is_abnormal_name("xkqzvr"));
+        assert!(is_abnormal_name("_01234"));
+        assert!(!is_abnormal_name("updatePosition"));
+        assert!(!is_abnormal_name("toString"));
+        assert!(!is_abnormal_name("xyz"));
+    }
+
+    #[test]
+    fn obfuscation() {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let void = b.ty(Type::Void);
+        let fields: Vec<String> = (0..20).map(|i| format!("f{i}")).collect();
+        let fields: Vec<(&str, _)> = fields.iter().map(|f| (f.as_str(), i32_)).collect();
+        b.class("a", None, &fields, &[]);
+        b.class("hl.types.ArrayBase", None, &[("length", i32_)], &[]);
+        b.class(
+            "lIlIIlIl",
+            None,
+            &[("xkqzvr", i32_), ("O0O0OO", i32_), ("position", i32_)],
+            &[],
+        );
+
+        let ty = b.fun_type(&[i32_], void);
+        let mut f = FunctionBuilder::new(ty, &[i32_]);
+        let ret = f.reg(void);
+        let end = f.label();
+        let targets = vec![end; 64];
+        f.switch(f.arg(0), &targets, end);
+        f.place(end).emit(Opcode::Ret { ret });
+        let (_, regs, ops) = f.finish().unwrap();
+        let findex = b.findex();
+        b.function(findex, ty, regs, ops);
+        let code = b.build().unwrap();
+
+        let report = ObfuscationReport::new(&code, &Thresholds::default());
+        assert_eq!(report.signs[0], Obfuscation::StrippedDebugInfo);
+        // `a` and `f0` to `f9`, std classes are ignored
+        assert_eq!(
+            report.signs[1],
+            Obfuscation::ShortNames {
+                count: 11,
+                total: 25
+            }
+        );
+        assert!(matches!(
+            &report.signs[2],
+            Obfuscation::AbnormalNames { count: 3, examples, .. } if examples[0] == "lIlIIlIl"
+        ));
+        assert_eq!(
+            report.signs[3],
+            Obfuscation::HugeSwitches(vec![(findex, 0, 64)])
+        );
+        assert!(report.names_obfuscated());
+
+        let report = ObfuscationReport::new(&hlbc::builder::sample(), &Thresholds::default());
+        assert!(!report.names_obfuscated());
+    }
+}
@@ -0,0 +1,160 @@
+//! Heuristic rules to automatically tag functions.
+//!
+//! Rules are written in TOML, each rule gives a tag to every function matching any of its criteria :
+//! ```toml
+//! [[rule]]
+//! tag = "crypto"
+//! # Calls a native whose 'lib/name' starts with one of these
+//! natives = ["hash/", "std/md5"]
+//! # References a string containing one of these
+//! strings = ["BEGIN RSA"]
+//! # Contains this sequence of consecutive opcodes
+//! opcodes = ["Xor", "Shl", "Xor"]
+//! ```
+//! A set of default rules is available with [Rules::default_rules].
+
+use hlbc::analysis::tags::{is_valid_tag, TagTarget, Tags};
+use hlbc::opcodes::Opcode;
+use hlbc::types::{FunPtr, Function};
+use hlbc::Bytecode;
+use serde::Deserialize;
+
+/// Error while loading rules
+#[derive(thiserror::Error, Debug)]
+pub enum RulesError {
+    #[error("Invalid rules file : {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Invalid tag '{0}'")]
+    InvalidTag(String),
+    #[error("Unknown opcode '{opcode}' in rule '{tag}'")]
+    UnknownOpcode { tag: String, opcode: String },
+}
+
+/// A single auto-tagging rule. A function matches if any of the criteria matches.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    /// Tag given to matching functions
+    pub tag: String,
+    /// Prefixes of called natives, matched against `lib/name`
+    #[serde(default)]
+    pub natives: Vec<String>,
+    /// Substrings of referenced strings
+    #[serde(default)]
+    pub strings: Vec<String>,
+    /// Sequence of consecutive opcode names
+    #[serde(default)]
+    pub opcodes: Vec<String>,
+}
+
+impl Rule {
+    /// Returns true if the function matches this rule
+    pub fn matches(&self, code: &Bytecode, f: &Function) -> bool {
+        self.matches_natives(code, f) || self.matches_strings(code, f) || self.matches_opcodes(f)
+    }
+
+    fn matches_natives(&self, code: &Bytecode, f: &Function) -> bool {
+        !self.natives.is_empty()
+            && f.find_fun_refs()
+                .any(|(_, _, fun)| match fun.resolve(code) {
+                    FunPtr::Native(n) => {
+                        let name = format!("{}/{}", n.lib.resolve(&code.strings), n.name(code));
+                        self.natives.iter().any(|p| name.starts_with(p.as_str()))
+                    }
+                    FunPtr::Fun(_) => false,
+                })
+    }
+
+    fn matches_strings(&self, code: &Bytecode, f: &Function) -> bool {
+        !self.strings.is_empty()
+            && f.ops.iter().any(|o| match o {
+                Opcode::String { ptr, .. } => {
+                    let s = ptr.resolve(&code.strings);
+                    self.strings.iter().any(|p| s.contains(p.as_str()))
+                }
+                _ => false,
+            })
+    }
+
+    fn matches_opcodes(&self, f: &Function) -> bool {
+        !self.opcodes.is_empty()
+            && f.ops.windows(self.opcodes.len()).any(|w| {
+                w.iter()
+                    .zip(&self.opcodes)
+                    .all(|(o, name)| o.name() == name.as_str())
+            })
+    }
+}
+
+/// A set of auto-tagging rules
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rules {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Parse and validate rules from their TOML representation
+    pub fn from_toml(s: &str) -> Result<Self, RulesError> {
+        let rules: Rules = toml::from_str(s)?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    /// Check tags are valid and opcodes exist
+    pub fn validate(&self) -> Result<(), RulesError> {
+        for r in &self.rules {
+            if !is_valid_tag(&r.tag) {
+                return Err(RulesError::InvalidTag(r.tag.clone()));
+            }
+            if let Some(op) = r.opcodes.iter().find(|o| Opcode::from_name(o).is_none()) {
+                return Err(RulesError::UnknownOpcode {
+                    tag: r.tag.clone(),
+                    opcode: op.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Rules shipped with hlbc, a good starting point for a new binary
+    pub fn default_rules() -> Self {
+        Self::from_toml(include_str!("default_rules.toml")).expect("Invalid default rules")
+    }
+
+    /// Tag every matching function, the tags aren't saved with the ones of the user. Returns the number of tags added.
+    pub fn apply(&self, code: &Bytecode, tags: &mut Tags) -> usize {
+        let mut count = 0;
+        for f in &code.functions {
+            for r in &self.rules {
+                if r.matches(code, f)
+                    && tags.add_generated(TagTarget::Fun(f.findex), r.tag.as_str())
+                {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::autotag::{Rules, RulesError};
+
+    #[test]
+    fn default_rules() {
+        assert!(!Rules::default_rules().rules.is_empty());
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(matches!(
+            Rules::from_toml("[[rule]]\ntag = \"a\"\nopcodes = [\"Nope\"]"),
+            Err(RulesError::UnknownOpcode { .. })
+        ));
+        assert!(matches!(
+            Rules::from_toml("[[rule]]\ntag = \"a b\""),
+            Err(RulesError::InvalidTag(_))
+        ));
+    }
+}
@@ -0,0 +1,351 @@
+//! Basic blocks and control flow graph of a function.
+//!
+//! A block ends after a jump, a terminator, a [Trap](Opcode::Trap) or an [EndTrap](Opcode::EndTrap). Each block
+//! inside a try section (between a `Trap` and its `EndTrap`, in instruction order) gets an exception edge to the
+//! handler. A [Switch](Opcode::Switch) falls through to the next instruction when no case matches, its `end` offset
+//! isn't an edge.
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::Function;
+
+/// How control goes from a block to another
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum EdgeKind {
+    /// To the next instruction
+    Fallthrough,
+    /// Jump taken or switch case
+    Jump,
+    /// To the exception handler
+    Exception,
+}
+
+/// Instructions executed in sequence
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BasicBlock {
+    /// Position of the first instruction
+    pub start: usize,
+    /// Position after the last instruction
+    pub end: usize,
+    pub successors: Vec<(usize, EdgeKind)>,
+    pub predecessors: Vec<(usize, EdgeKind)>,
+}
+
+impl BasicBlock {
+    /// Position of the last instruction
+    pub fn last(&self) -> usize {
+        self.end - 1
+    }
+}
+
+/// A natural loop, the blocks that can reach a back edge to the header without going through the header
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Loop {
+    pub header: usize,
+    /// Blocks of the loop including the header, sorted
+    pub blocks: Vec<usize>,
+}
+
+/// Control flow graph, blocks are in instruction order and the entry block is the first one
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    /// Block of each instruction
+    block_of: Vec<usize>,
+}
+
+impl Cfg {
+    pub fn new(f: &Function) -> Self {
+        let ops = &f.ops;
+        let targets = |i: usize, o: &Opcode| -> Vec<usize> {
+            match o {
+                Opcode::Switch { .. } => {
+                    let mut t = o.jump_targets(i);
+                    // The end offset
+                    t.pop();
+                    t
+                }
+                _ => o.jump_targets(i),
+            }
+        };
+
+        let mut leaders = vec![false; ops.len()];
+        if !ops.is_empty() {
+            leaders[0] = true;
+        }
+        for (i, o) in ops.iter().enumerate() {
+            let t = targets(i, o);
+            if !t.is_empty() || o.is_terminator() || matches!(o, Opcode::EndTrap { .. }) {
+                if let Some(next) = leaders.get_mut(i + 1) {
+                    *next = true;
+                }
+            }
+            for t in t {
+                if let Some(l) = leaders.get_mut(t) {
+                    *l = true;
+                }
+            }
+        }
+
+        let mut blocks = Vec::new();
+        let mut block_of = Vec::with_capacity(ops.len());
+        for (i, &leader) in leaders.iter().enumerate() {
+            if leader {
+                blocks.push(BasicBlock {
+                    start: i,
+                    end: i + 1,
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                });
+            } else {
+                blocks.last_mut().unwrap().end = i + 1;
+            }
+            block_of.push(blocks.len() - 1);
+        }
+
+        // Handler of the innermost try section for each instruction
+        let mut handlers = vec![None; ops.len()];
+        let mut stack = Vec::new();
+        for (i, o) in ops.iter().enumerate() {
+            match o {
+                Opcode::Trap { .. } => stack.push(o.jump_target(i).unwrap()),
+                Opcode::EndTrap { .. } => {
+                    stack.pop();
+                }
+                _ => handlers[i] = stack.last().copied(),
+            }
+        }
+
+        let mut edges = Vec::new();
+        for (b, block) in blocks.iter().enumerate() {
+            let last = block.last();
+            let o = &ops[last];
+            let mut succ = Vec::new();
+            if !o.is_terminator() {
+                succ.push((last + 1, EdgeKind::Fallthrough));
+            }
+            if !matches!(o, Opcode::Trap { .. }) {
+                succ.extend(targets(last, o).into_iter().map(|t| (t, EdgeKind::Jump)));
+            }
+            for h in handlers[block.start..block.end].iter().flatten() {
+                succ.push((*h, EdgeKind::Exception));
+            }
+            for (t, kind) in succ {
+                // Ignore invalid targets
+                if let Some(&to) = block_of.get(t) {
+                    if !edges.contains(&(b, to, kind)) {
+                        edges.push((b, to, kind));
+                    }
+                }
+            }
+        }
+        for (from, to, kind) in edges {
+            blocks[from].successors.push((to, kind));
+            blocks[to].predecessors.push((from, kind));
+        }
+
+        Self { blocks, block_of }
+    }
+
+    /// Block containing the instruction at `pos`
+    pub fn block_of(&self, pos: usize) -> usize {
+        self.block_of[pos]
+    }
+
+    /// Blocks reachable from the entry, each block coming before its successors except along back edges.
+    /// Useful as the iteration order of forward data flow analyses.
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut order = Vec::with_capacity(self.blocks.len());
+        if self.blocks.is_empty() {
+            return order;
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        // (block, index of the next successor to visit)
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((b, i)) = stack.pop() {
+            match self.blocks[b].successors.get(i) {
+                Some(&(s, _)) => {
+                    stack.push((b, i + 1));
+                    if !visited[s] {
+                        visited[s] = true;
+                        stack.push((s, 0));
+                    }
+                }
+                None => order.push(b),
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Immediate dominator of each block, `None` for the entry and unreachable blocks
+    pub fn dominators(&self) -> Vec<Option<usize>> {
+        let order = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (i, &b) in order.iter().enumerate() {
+            rank[b] = i;
+        }
+        let mut idom: Vec<Option<usize>> = vec![None; self.blocks.len()];
+        if let Some(&entry) = order.first() {
+            idom[entry] = Some(entry);
+        }
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while rank[a] > rank[b] {
+                    a = idom[a].unwrap();
+                }
+                while rank[b] > rank[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in order.iter().skip(1) {
+                let new = self.blocks[b]
+                    .predecessors
+                    .iter()
+                    .map(|&(p, _)| p)
+                    .filter(|&p| idom[p].is_some())
+                    .reduce(|a, p| intersect(&idom, a, p));
+                if new.is_some() && idom[b] != new {
+                    idom[b] = new;
+                    changed = true;
+                }
+            }
+        }
+        if let Some(&entry) = order.first() {
+            idom[entry] = None;
+        }
+        idom
+    }
+
+    /// Natural loops sorted by header, loops with the same header are merged. Only reachable blocks are part of
+    /// loops, irreducible cycles (entered by more than one block) aren't loops.
+    pub fn loops(&self) -> Vec<Loop> {
+        let idom = self.dominators();
+        let dominates = |h: usize, mut b: usize| loop {
+            if b == h {
+                return true;
+            }
+            match idom[b] {
+                Some(d) => b = d,
+                None => return false,
+            }
+        };
+        let mut loops: Vec<Loop> = Vec::new();
+        for (b, block) in self.blocks.iter().enumerate() {
+            // Unreachable blocks have no dominator
+            if b != 0 && idom[b].is_none() {
+                continue;
+            }
+            for &(h, _) in &block.successors {
+                if !dominates(h, b) {
+                    continue;
+                }
+                let mut body = vec![false; self.blocks.len()];
+                body[h] = true;
+                let mut todo = vec![b];
+                while let Some(x) = todo.pop() {
+                    if !body[x] {
+                        body[x] = true;
+                        todo.extend(
+                            self.blocks[x]
+                                .predecessors
+                                .iter()
+                                .map(|&(p, _)| p)
+                                .filter(|&p| p == 0 || idom[p].is_some()),
+                        );
+                    }
+                }
+                let blocks = (0..body.len()).filter(|&x| body[x]);
+                match loops.iter_mut().find(|l| l.header == h) {
+                    Some(l) => {
+                        l.blocks.extend(blocks);
+                        l.blocks.sort_unstable();
+                        l.blocks.dedup();
+                    }
+                    None => loops.push(Loop {
+                        header: h,
+                        blocks: blocks.collect(),
+                    }),
+                }
+            }
+        }
+        loops.sort_unstable_by_key(|l| l.header);
+        loops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Function, RefFun, RefType, Reg};
+
+    use crate::cfg::{Cfg, EdgeKind, Loop};
+
+    #[test]
+    fn blocks() {
+        let ops = vec![
+            Opcode::Trap {
+                exc: Reg(0),
+                offset: 3,
+            },
+            Opcode::Incr { dst: Reg(1) },
+            Opcode::EndTrap { exc: Reg(0) },
+            Opcode::JAlways { offset: 1 },
+            // Handler
+            Opcode::Incr { dst: Reg(1) },
+            Opcode::JFalse {
+                cond: Reg(2),
+                offset: -6,
+            },
+            Opcode::Ret { ret: Reg(1) },
+        ];
+        let f = Function {
+            name: None,
+            t: RefType(0),
+            findex: RefFun(0),
+            regs: Vec::new(),
+            ops,
+            debug_info: None,
+            assigns: None,
+            parent: None,
+        };
+        let cfg = Cfg::new(&f);
+        let starts: Vec<usize> = cfg.blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, [0, 1, 3, 4, 5, 6]);
+        let succ: Vec<&[(usize, EdgeKind)]> =
+            cfg.blocks.iter().map(|b| b.successors.as_slice()).collect();
+        assert_eq!(
+            succ,
+            [
+                &[(1, EdgeKind::Fallthrough)][..],
+                &[(2, EdgeKind::Fallthrough), (3, EdgeKind::Exception)],
+                &[(4, EdgeKind::Jump)],
+                &[(4, EdgeKind::Fallthrough)],
+                &[(5, EdgeKind::Fallthrough), (0, EdgeKind::Jump)],
+                &[],
+            ]
+        );
+        assert_eq!(
+            cfg.blocks[4].predecessors,
+            [(2, EdgeKind::Jump), (3, EdgeKind::Fallthrough)]
+        );
+        assert_eq!(cfg.block_of(2), 1);
+        assert_eq!(cfg.reverse_postorder(), [0, 1, 3, 2, 4, 5]);
+        assert_eq!(
+            cfg.dominators(),
+            [None, Some(0), Some(1), Some(1), Some(1), Some(4)]
+        );
+        assert_eq!(
+            cfg.loops(),
+            [Loop {
+                header: 0,
+                blocks: vec![0, 1, 2, 3, 4]
+            }]
+        );
+    }
+}
@@ -0,0 +1,557 @@
+//! Constant propagation on registers.
+//!
+//! Computes the registers holding a statically known value before each instruction,
+//! following only the branches that can actually be taken.
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, Reg, Type};
+use hlbc::Bytecode;
+
+/// A constant value held by a register
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Lattice {
+    /// Not assigned yet
+    Undef,
+    Const(Value),
+    /// Could be anything
+    Varying,
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Undef, x) | (x, Lattice::Undef) => x,
+            (Lattice::Const(a), Lattice::Const(b)) if a == b => Lattice::Const(a),
+            _ => Lattice::Varying,
+        }
+    }
+}
+
+/// Result of the constant propagation on a function
+#[derive(Debug)]
+pub struct ConstProp {
+    /// Registers state before each instruction, None if the instruction is unreachable
+    states: Vec<Option<Vec<Lattice>>>,
+}
+
+impl ConstProp {
+    pub fn new(code: &Bytecode, f: &Function) -> Self {
+        let nargs = f.ty(code).args.len();
+        // Registers we take a reference to can be modified from anywhere
+        let mut escaping = vec![false; f.regs.len()];
+        for o in &f.ops {
+            if let Opcode::Ref { src, .. } = o {
+                escaping[src.0 as usize] = true;
+            }
+        }
+        let is_int = |reg: Reg| matches!(f.regtype(reg).resolve(&code.types), Type::I32);
+
+        let mut states: Vec<Option<Vec<Lattice>>> = vec![None; f.ops.len()];
+        if f.ops.is_empty() {
+            return Self { states };
+        }
+        states[0] = Some(
+            (0..f.regs.len())
+                .map(|i| {
+                    if i < nargs || escaping[i] {
+                        Lattice::Varying
+                    } else {
+                        Lattice::Undef
+                    }
+                })
+                .collect(),
+        );
+
+        let mut worklist = vec![0];
+        while let Some(i) = worklist.pop() {
+            let before = states[i].clone().unwrap();
+            let o = &f.ops[i];
+
+            let mut after = before.clone();
+            if let Some(dst) = o.dst().filter(|_| !matches!(o, Opcode::Setref { .. })) {
+                let value = if escaping[dst.0 as usize] {
+                    Lattice::Varying
+                } else {
+                    eval(code, o, &before, is_int(dst))
+                };
+                after[dst.0 as usize] = value;
+            }
+
+            // Successors with the state to propagate
+            let mut succ = Vec::with_capacity(2);
+            match branch_outcome(code, f, o, &before) {
+                Some(true) => succ.push((o.jump_target(i).unwrap(), &after)),
+                Some(false) => succ.push((i + 1, &after)),
+                None => {
+                    if !o.is_terminator() {
+                        succ.push((i + 1, &after));
+                    }
+                    for t in o.jump_targets(i) {
+                        succ.push((t, &after));
+                    }
+                }
+            }
+            // An exception can happen anywhere in a try block
+            let varying = vec![Lattice::Varying; f.regs.len()];
+            if let Opcode::Trap { .. } = o {
+                succ.retain(|(t, _)| *t == i + 1);
+                succ.push((o.jump_target(i).unwrap(), &varying));
+            }
+
+            for (t, state) in succ {
+                if t >= f.ops.len() {
+                    continue;
+                }
+                let changed = match &mut states[t] {
+                    Some(existing) => {
+                        let mut changed = false;
+                        for (e, s) in existing.iter_mut().zip(state) {
+                            let m = e.meet(*s);
+                            if m != *e {
+                                *e = m;
+                                changed = true;
+                            }
+                        }
+                        changed
+                    }
+                    slot => {
+                        *slot = Some(state.clone());
+                        true
+                    }
+                };
+                if changed {
+                    worklist.push(t);
+                }
+            }
+        }
+
+        Self { states }
+    }
+
+    /// Returns true if the instruction can be executed
+    pub fn is_reachable(&self, pos: usize) -> bool {
+        self.states[pos].is_some()
+    }
+
+    /// Value of a register just before the instruction at `pos` executes
+    pub fn value(&self, pos: usize, reg: Reg) -> Option<Value> {
+        match self.states[pos].as_ref()?[reg.0 as usize] {
+            Lattice::Const(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Outcome of the conditional jump at `pos` if it is known statically
+    pub fn branch(&self, code: &Bytecode, f: &Function, pos: usize) -> Option<bool> {
+        branch_outcome(code, f, &f.ops[pos], self.states[pos].as_ref()?)
+    }
+}
+
+/// Value written by an instruction
+fn eval(code: &Bytecode, o: &Opcode, state: &[Lattice], int_dst: bool) -> Lattice {
+    let get = |r: Reg| state[r.0 as usize];
+    let int = |r: Reg| match get(r) {
+        Lattice::Const(Value::Int(v)) => Some(v),
+        _ => None,
+    };
+    let binop = |a: Reg, b: Reg, op: fn(i32, i32) -> Option<i32>| {
+        if !int_dst {
+            return Lattice::Varying;
+        }
+        int(a)
+            .zip(int(b))
+            .and_then(|(a, b)| op(a, b))
+            .map(|v| Lattice::Const(Value::Int(v)))
+            .unwrap_or(Lattice::Varying)
+    };
+    match *o {
+        Opcode::Int { ptr, .. } => Lattice::Const(Value::Int(ptr.resolve(&code.ints))),
+        Opcode::Bool { value, .. } => Lattice::Const(Value::Bool(value.0)),
+        Opcode::Null { .. } => Lattice::Const(Value::Null),
+        Opcode::Mov { src, .. } => get(src),
+        Opcode::Add { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_add(b))),
+        Opcode::Sub { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_sub(b))),
+        Opcode::Mul { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_mul(b))),
+        Opcode::SDiv { a, b, .. } => binop(a, b, |a, b| a.checked_div(b)),
+        Opcode::UDiv { a, b, .. } => binop(a, b, |a, b| {
+            (a as u32).checked_div(b as u32).map(|v| v as i32)
+        }),
+        Opcode::SMod { a, b, .. } => binop(a, b, |a, b| a.checked_rem(b)),
+        Opcode::UMod { a, b, .. } => binop(a, b, |a, b| {
+            (a as u32).checked_rem(b as u32).map(|v| v as i32)
+        }),
+        Opcode::Shl { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_shl(b as u32))),
+        Opcode::SShr { a, b, .. } => binop(a, b, |a, b| Some(a.wrapping_shr(b as u32))),
+        Opcode::UShr { a, b, .. } => {
+            binop(a, b, |a, b| Some((a as u32).wrapping_shr(b as u32) as i32))
+        }
+        Opcode::And { a, b, .. } => binop(a, b, |a, b| Some(a & b)),
+        Opcode::Or { a, b, .. } => binop(a, b, |a, b| Some(a | b)),
+        Opcode::Xor { a, b, .. } => binop(a, b, |a, b| Some(a ^ b)),
+        Opcode::Neg { src, .. } => binop(src, src, |a, _| Some(a.wrapping_neg())),
+        Opcode::Incr { dst } => binop(dst, dst, |a, _| Some(a.wrapping_add(1))),
+        Opcode::Decr { dst } => binop(dst, dst, |a, _| Some(a.wrapping_sub(1))),
+        Opcode::Not { src, .. } => match get(src) {
+            Lattice::Const(Value::Bool(b)) => Lattice::Const(Value::Bool(!b)),
+            _ => Lattice::Varying,
+        },
+        _ => Lattice::Varying,
+    }
+}
+
+/// Equality of values of the same kind
+fn eq(a: Value, b: Value) -> Option<bool> {
+    match (a, b) {
+        (Value::Int(_), Value::Int(_))
+        | (Value::Bool(_), Value::Bool(_))
+        | (Value::Null, Value::Null) => Some(a == b),
+        _ => None,
+    }
+}
+
+/// Outcome of a conditional jump : Some(true) if always taken, Some(false) if never taken
+fn branch_outcome(code: &Bytecode, f: &Function, o: &Opcode, state: &[Lattice]) -> Option<bool> {
+    let get = |r: Reg| match state[r.0 as usize] {
+        Lattice::Const(v) => Some(v),
+        _ => None,
+    };
+    // Comparing a register with itself, only valid for integers (NaN != NaN)
+    let same = |a: Reg, b: Reg| {
+        a == b
+            && matches!(
+                f.regtype(a).resolve(&code.types),
+                Type::UI8 | Type::UI16 | Type::I32 | Type::I64 | Type::Bool
+            )
+    };
+    let cmp = |a: Reg, b: Reg, signed: fn(i32, i32) -> bool, unsigned: bool| {
+        if same(a, b) {
+            return Some(signed(0, 0));
+        }
+        match (get(a)?, get(b)?) {
+            (Value::Int(a), Value::Int(b)) if unsigned => Some(signed(a ^ i32::MIN, b ^ i32::MIN)),
+            (Value::Int(a), Value::Int(b)) => Some(signed(a, b)),
+            _ => None,
+        }
+    };
+    match *o {
+        Opcode::JTrue { cond, .. } => match get(cond)? {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        },
+        Opcode::JFalse { cond, .. } => match get(cond)? {
+            Value::Bool(b) => Some(!b),
+            _ => None,
+        },
+        Opcode::JNull { reg, .. } => match get(reg)? {
+            Value::Null => Some(true),
+            _ => None,
+        },
+        Opcode::JNotNull { reg, .. } => match get(reg)? {
+            Value::Null => Some(false),
+            _ => None,
+        },
+        Opcode::JSLt { a, b, .. } => cmp(a, b, |a, b| a < b, false),
+        Opcode::JSGte { a, b, .. } => cmp(a, b, |a, b| a >= b, false),
+        Opcode::JSGt { a, b, .. } => cmp(a, b, |a, b| a > b, false),
+        Opcode::JSLte { a, b, .. } => cmp(a, b, |a, b| a <= b, false),
+        Opcode::JULt { a, b, .. } => cmp(a, b, |a, b| a < b, true),
+        Opcode::JUGte { a, b, .. } => cmp(a, b, |a, b| a >= b, true),
+        Opcode::JNotLt { a, b, .. } => cmp(a, b, |a, b| a >= b, false),
+        Opcode::JNotGte { a, b, .. } => cmp(a, b, |a, b| a < b, false),
+        Opcode::JEq { a, b, .. } => {
+            if same(a, b) {
+                Some(true)
+            } else {
+                eq(get(a)?, get(b)?)
+            }
+        }
+        Opcode::JNotEq { a, b, .. } => {
+            if same(a, b) {
+                Some(false)
+            } else {
+                eq(get(a)?, get(b)?).map(|eq| !eq)
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Function, RefType, Reg, Type};
+    use hlbc::Bytecode;
+
+    use crate::constprop::{ConstProp, Value};
+
+    /// A function taking an int, a float and a bool. `body` gets the builder for the constants and those types, and
+    /// returns the registers to check.
+    fn build<R>(
+        body: impl FnOnce(&mut BytecodeBuilder, &mut FunctionBuilder, [RefType; 3]) -> R,
+    ) -> (Bytecode, ConstProp, R) {
+        let mut b = BytecodeBuilder::new();
+        let types = [Type::I32, Type::F64, Type::Bool].map(|t| b.ty(t));
+        let ty = b.fun_type(&types, types[0]);
+        let findex = b.findex();
+        let mut f = FunctionBuilder::new(ty, &types);
+        let regs = body(&mut b, &mut f, types);
+        let (_, fregs, ops) = f.finish().unwrap();
+        b.function(findex, ty, fregs, ops);
+        let code = b.build().unwrap();
+        let cp = ConstProp::new(&code, &code.functions[0]);
+        (code, cp, regs)
+    }
+
+    fn int(b: &mut BytecodeBuilder, f: &mut FunctionBuilder, dst: Reg, value: i32) {
+        f.emit(Opcode::Int {
+            dst,
+            ptr: b.int(value),
+        });
+    }
+
+    fn function(code: &Bytecode) -> &Function {
+        &code.functions[0]
+    }
+
+    /// ```text
+    /// 0: one = 1
+    /// 1: two = 2
+    /// 2: if one < two goto 4
+    /// 3: ret one
+    /// 4: if one >= two goto 8
+    /// 5: sum = one + two
+    /// 6: if !cond goto 8
+    /// 7: ret sum
+    /// 8: ret one
+    /// ```
+    #[test]
+    fn branches() {
+        let (code, cp, sum) = build(|b, f, [i32_, ..]| {
+            let cond = f.arg(2);
+            let [one, two, sum] = [(); 3].map(|_| f.reg(i32_));
+            let [taken, end] = [(); 2].map(|_| f.label());
+            int(b, f, one, 1);
+            int(b, f, two, 2);
+            f.jump(
+                Opcode::JSLt {
+                    a: one,
+                    b: two,
+                    offset: 0,
+                },
+                taken,
+            );
+            f.emit(Opcode::Ret { ret: one });
+            f.place(taken);
+            f.jump(
+                Opcode::JSGte {
+                    a: one,
+                    b: two,
+                    offset: 0,
+                },
+                end,
+            );
+            f.emit(Opcode::Add {
+                dst: sum,
+                a: one,
+                b: two,
+            });
+            f.jump(Opcode::JFalse { cond, offset: 0 }, end);
+            f.emit(Opcode::Ret { ret: sum });
+            f.place(end);
+            f.emit(Opcode::Ret { ret: one });
+            sum
+        });
+        let f = function(&code);
+        assert_eq!(cp.branch(&code, f, 2), Some(true));
+        assert!(!cp.is_reachable(3));
+        assert_eq!(cp.branch(&code, f, 4), Some(false));
+        assert_eq!(cp.value(5, sum), None);
+        assert_eq!(cp.value(6, sum), Some(Value::Int(3)));
+        // The argument could be anything
+        assert_eq!(cp.branch(&code, f, 6), None);
+        assert!(cp.is_reachable(7));
+        // Reached by the jump at 6 only, with the sum known
+        assert_eq!(cp.value(8, sum), Some(Value::Int(3)));
+    }
+
+    /// -1 is the largest unsigned int
+    #[test]
+    fn unsigned() {
+        let (code, cp, _) = build(|b, f, [i32_, ..]| {
+            let [minus, one] = [(); 2].map(|_| f.reg(i32_));
+            let [signed, end] = [(); 2].map(|_| f.label());
+            int(b, f, minus, -1);
+            int(b, f, one, 1);
+            for (op, target) in [
+                (
+                    Opcode::JULt {
+                        a: minus,
+                        b: one,
+                        offset: 0,
+                    },
+                    end,
+                ),
+                (
+                    Opcode::JSLt {
+                        a: minus,
+                        b: one,
+                        offset: 0,
+                    },
+                    signed,
+                ),
+            ] {
+                f.jump(op, target);
+            }
+            f.emit(Opcode::Ret { ret: one });
+            f.place(signed);
+            f.jump(
+                Opcode::JUGte {
+                    a: minus,
+                    b: one,
+                    offset: 0,
+                },
+                end,
+            );
+            f.emit(Opcode::Ret { ret: one });
+            f.place(end);
+            f.emit(Opcode::Ret { ret: minus });
+        });
+        let f = function(&code);
+        assert_eq!(cp.branch(&code, f, 2), Some(false));
+        assert_eq!(cp.branch(&code, f, 3), Some(true));
+        assert!(!cp.is_reachable(4));
+        assert_eq!(cp.branch(&code, f, 5), Some(true));
+        assert!(!cp.is_reachable(6));
+    }
+
+    /// A register we take a reference to can be written through the reference
+    #[test]
+    fn escaping() {
+        let (code, cp, (x, y)) = build(|b, f, [i32_, ..]| {
+            let [x, y] = [(); 2].map(|_| f.reg(i32_));
+            let ref_t = b.ty(Type::Ref(i32_));
+            let r = f.reg(ref_t);
+            let end = f.label();
+            int(b, f, x, 1);
+            int(b, f, y, 1);
+            f.emit(Opcode::Ref { dst: r, src: x });
+            f.emit(Opcode::Setref {
+                dst: r,
+                value: f.arg(0),
+            });
+            f.jump(
+                Opcode::JEq {
+                    a: x,
+                    b: y,
+                    offset: 0,
+                },
+                end,
+            );
+            f.place(end);
+            f.emit(Opcode::Ret { ret: x });
+            (x, y)
+        });
+        assert_eq!(cp.value(1, x), None);
+        assert_eq!(cp.value(4, x), None);
+        assert_eq!(cp.value(4, y), Some(Value::Int(1)));
+        assert_eq!(cp.branch(&code, function(&code), 4), None);
+    }
+
+    /// The exception can be raised before or after any instruction of the try block
+    #[test]
+    fn trap() {
+        let (code, cp, x) = build(|b, f, [i32_, ..]| {
+            let x = f.reg(i32_);
+            let one = f.reg(i32_);
+            let dyn_ = b.ty(Type::Dyn);
+            let exc = f.reg(dyn_);
+            let [catch, end] = [(); 2].map(|_| f.label());
+            int(b, f, x, 1);
+            int(b, f, one, 1);
+            f.jump(Opcode::Trap { exc, offset: 0 }, catch);
+            int(b, f, x, 2);
+            f.emit(Opcode::EndTrap { exc });
+            f.emit(Opcode::Ret { ret: x });
+            f.place(catch);
+            f.jump(
+                Opcode::JEq {
+                    a: x,
+                    b: one,
+                    offset: 0,
+                },
+                end,
+            );
+            f.place(end);
+            f.emit(Opcode::Ret { ret: x });
+            x
+        });
+        assert_eq!(cp.value(3, x), Some(Value::Int(1)));
+        assert_eq!(cp.value(5, x), Some(Value::Int(2)));
+        assert!(cp.is_reachable(6));
+        assert_eq!(cp.value(6, x), None);
+        assert_eq!(cp.branch(&code, function(&code), 6), None);
+    }
+
+    /// NaN is not equal to itself
+    #[test]
+    fn self_comparison() {
+        let (code, cp, _) = build(|_, f, _| {
+            let (i, x) = (f.arg(0), f.arg(1));
+            let [float_eq, float_lt, int_eq, int_lt] = [(); 4].map(|_| f.label());
+            f.jump(
+                Opcode::JEq {
+                    a: x,
+                    b: x,
+                    offset: 0,
+                },
+                float_eq,
+            );
+            f.place(float_eq);
+            f.jump(
+                Opcode::JSLt {
+                    a: x,
+                    b: x,
+                    offset: 0,
+                },
+                float_lt,
+            );
+            f.place(float_lt);
+            f.jump(
+                Opcode::JEq {
+                    a: i,
+                    b: i,
+                    offset: 0,
+                },
+                int_eq,
+            );
+            f.emit(Opcode::Ret { ret: i });
+            f.place(int_eq);
+            f.jump(
+                Opcode::JSLt {
+                    a: i,
+                    b: i,
+                    offset: 0,
+                },
+                int_lt,
+            );
+            f.emit(Opcode::Ret { ret: i });
+            f.place(int_lt);
+            f.emit(Opcode::Ret { ret: i });
+        });
+        let f = function(&code);
+        assert_eq!(cp.branch(&code, f, 0), None);
+        assert_eq!(cp.branch(&code, f, 1), None);
+        assert_eq!(cp.branch(&code, f, 2), Some(true));
+        assert!(!cp.is_reachable(3));
+        assert_eq!(cp.branch(&code, f, 4), Some(false));
+        assert!(!cp.is_reachable(6));
+    }
+}
@@ -0,0 +1,209 @@
+//! Generic names of the std containers and their element types.
+//!
+//! Haxe generics are erased in the bytecode : an `Array<Int>` is compiled to the specialized class
+//! `hl.types.ArrayBytes_Int` and an `Array<Player>` to `hl.types.ArrayObj`, which lost its element type.
+//! We restore the type parameters from the specialization name, and find the missing element types back from the way
+//! a container is used in a function.
+
+use std::collections::HashMap;
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, RefType, Reg, Type};
+use hlbc::Bytecode;
+
+/// Haxe name of a std container class with its type parameters, from its specialization name.
+/// `elem` is the element type, when known, for containers that don't carry it in their name.
+pub fn container_name(name: &str, elem: Option<&str>) -> Option<String> {
+    let elem = elem.unwrap_or("Dynamic");
+    Some(match name {
+        "hl.types.ArrayObj" | "hl.types.ArrayDyn" => format!("Array<{elem}>"),
+        "haxe.ds.StringMap" => format!("Map<String, {elem}>"),
+        "haxe.ds.IntMap" => format!("Map<Int, {elem}>"),
+        "haxe.ds.ObjectMap" => format!("Map<{{}}, {elem}>"),
+        "haxe.ds.EnumValueMap" => format!("Map<EnumValue, {elem}>"),
+        _ => {
+            let ty = name.strip_prefix("hl.types.ArrayBytes_")?;
+            match ty.strip_prefix("hl_") {
+                Some(ty) => format!("Array<hl.{ty}>"),
+                None => format!("Array<{ty}>"),
+            }
+        }
+    })
+}
+
+/// Infer the element type of the arrays held by each register of a function,
+/// from the values read from and written to them.
+pub fn element_types(code: &Bytecode, f: &Function) -> HashMap<Reg, RefType> {
+    let is_dynamic = |r: Reg| matches!(f.regtype(r).resolve(&code.types), Type::Dyn | Type::DynObj);
+    let is_array = |r: Reg| match f.regtype(r).resolve(&code.types) {
+        Type::Array => true,
+        Type::Obj(obj) => obj.name.resolve(&code.strings) == "hl.types.ArrayObj",
+        _ => false,
+    };
+
+    // Type of a value before it has been made dynamic, or after being cast back
+    let concrete = |r: Reg| -> Option<RefType> {
+        if !is_dynamic(r) {
+            return Some(f.regtype(r));
+        }
+        f.ops.iter().find_map(|o| match *o {
+            Opcode::ToDyn { dst, src } if dst == r && !is_dynamic(src) => Some(f.regtype(src)),
+            Opcode::SafeCast { dst, src } | Opcode::UnsafeCast { dst, src }
+                if src == r && !is_dynamic(dst) =>
+            {
+                Some(f.regtype(dst))
+            }
+            _ => None,
+        })
+    };
+
+    // The native array backing an ArrayObj
+    let mut backing: HashMap<Reg, Reg> = HashMap::new();
+    for o in &f.ops {
+        if let Opcode::Field { dst, obj, field } = *o {
+            if is_array(obj)
+                && f.regtype(obj)
+                    .field(field, code)
+                    .map(|f| f.name.resolve(&code.strings) == "array")
+                    .unwrap_or(false)
+            {
+                backing.insert(dst, obj);
+            }
+        }
+    }
+
+    let mut found: HashMap<Reg, Option<RefType>> = HashMap::new();
+    let mut add = |array: Reg, elem: Option<RefType>| {
+        let array = backing.get(&array).copied().unwrap_or(array);
+        if let Some(elem) = elem {
+            if is_array(array) {
+                found
+                    .entry(array)
+                    .and_modify(|e| {
+                        if *e != Some(elem) {
+                            *e = None;
+                        }
+                    })
+                    .or_insert(Some(elem));
+            }
+        }
+    };
+    for o in &f.ops {
+        match o {
+            Opcode::GetArray { dst, array, .. } => add(*array, concrete(*dst)),
+            Opcode::SetArray { array, src, .. } => add(*array, concrete(*src)),
+            Opcode::CallMethod { field, args, .. } if args.len() == 2 => {
+                let name = f
+                    .regtype(args[0])
+                    .method(field.0, code)
+                    .map(|p| p.name.resolve(&code.strings));
+                if matches!(name, Some("push" | "unshift")) {
+                    add(args[0], concrete(args[1]));
+                }
+            }
+            Opcode::Call2 {
+                fun, arg0, arg1, ..
+            } if matches!(fun.name(code), Some("push" | "unshift")) => add(*arg0, concrete(*arg1)),
+            _ => {}
+        }
+    }
+    found
+        .into_iter()
+        .filter_map(|(r, t)| t.map(|t| (r, t)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefField, Type};
+
+    use crate::containers::{container_name, element_types};
+
+    #[test]
+    fn specialization_names() {
+        assert_eq!(
+            container_name("hl.types.ArrayBytes_Int", None).unwrap(),
+            "Array<Int>"
+        );
+        assert_eq!(
+            container_name("hl.types.ArrayBytes_hl_UI16", None).unwrap(),
+            "Array<hl.UI16>"
+        );
+        assert_eq!(
+            container_name("hl.types.ArrayObj", Some("Player")).unwrap(),
+            "Array<Player>"
+        );
+        assert_eq!(
+            container_name("haxe.ds.StringMap", None).unwrap(),
+            "Map<String, Dynamic>"
+        );
+        assert!(container_name("Player", None).is_none());
+    }
+
+    #[test]
+    fn element_types_from_usage() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let i32_ = b.ty(Type::I32);
+        let f64_ = b.ty(Type::F64);
+        let dyn_ = b.ty(Type::Dyn);
+        let array = b.ty(Type::Array);
+        let player = b.class("Player", None, &[("hp", i32_)], &[]);
+        let array_obj = b.class("hl.types.ArrayObj", None, &[("array", array)], &[]);
+        let ty = b.fun_type(&[], void);
+        let findex = b.findex();
+        let mut f = FunctionBuilder::new(ty, &[]);
+        let [index, int, float, p] = [i32_, i32_, f64_, player].map(|t| f.reg(t));
+        let [players, mixed, dynamic, native] = [(); 4].map(|_| f.reg(array));
+        let (obj, boxed) = (f.reg(array_obj), f.reg(dyn_));
+        let ret = f.reg(void);
+        f.emit(Opcode::SetArray {
+            array: players,
+            index,
+            src: p,
+        })
+        // Two different element types
+        .emit(Opcode::SetArray {
+            array: mixed,
+            index,
+            src: int,
+        })
+        .emit(Opcode::GetArray {
+            dst: float,
+            array: mixed,
+            index,
+        })
+        // The value stored is made dynamic first
+        .emit(Opcode::ToDyn { dst: boxed, src: p })
+        .emit(Opcode::SetArray {
+            array: dynamic,
+            index,
+            src: boxed,
+        })
+        // Read from the native array backing an ArrayObj
+        .emit(Opcode::Field {
+            dst: native,
+            obj,
+            field: RefField(0),
+        })
+        .emit(Opcode::GetArray {
+            dst: int,
+            array: native,
+            index,
+        })
+        .emit(Opcode::Ret { ret });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(findex, ty, regs, ops);
+        let code = b.build().unwrap();
+
+        let types = element_types(&code, &code.functions[0]);
+        assert_eq!(types.get(&players), Some(&player));
+        assert_eq!(types.get(&mixed), None);
+        assert_eq!(types.get(&dynamic), Some(&player));
+        assert_eq!(types.get(&obj), Some(&i32_));
+        assert_eq!(types.get(&native), None);
+        assert_eq!(types.len(), 3);
+    }
+}
@@ -0,0 +1,143 @@
+//! Copy propagation on registers.
+//!
+//! After `dst = src` ([Mov](Opcode::Mov)), a read of `src` can be replaced by a read of `dst` as long as both still
+//! hold the copied value : the copy dominates the read, no other definition of `dst` reaches the read and `src`
+//! holds the same value as when it was copied. Registers a reference is taken of are never propagated, they can be
+//! modified through the reference.
+
+use std::collections::HashMap;
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, Reg};
+
+use crate::slice::DataDeps;
+
+/// Copies available at each register read of a function
+#[derive(Debug, Clone, Default)]
+pub struct Copies {
+    /// Position of the copy for each read position and register
+    copies: HashMap<(usize, Reg), usize>,
+}
+
+impl Copies {
+    pub fn new(f: &Function, deps: &DataDeps) -> Self {
+        let cfg = deps.cfg();
+        let idom = cfg.dominators();
+        let dominates = |a: usize, b: usize| {
+            let (mut block, target) = (cfg.block_of(b), cfg.block_of(a));
+            if block == target {
+                return a < b;
+            }
+            while let Some(d) = idom[block] {
+                if d == target {
+                    return true;
+                }
+                block = d;
+            }
+            false
+        };
+        let mut escaping = vec![false; f.regs.len()];
+        for o in &f.ops {
+            if let Opcode::Ref { src, .. } = o {
+                escaping[src.0 as usize] = true;
+            }
+        }
+        // Definitions of the value read by each instruction
+        let defs_at = |pos: usize, reg: Reg| {
+            deps.deps(pos)
+                .iter()
+                .find(|(r, _)| *r == reg)
+                .map(|(_, d)| d)
+        };
+
+        let mut copies = HashMap::new();
+        for (m, o) in f.ops.iter().enumerate() {
+            let (dst, src) = match *o {
+                Opcode::Mov { dst, src } if dst != src => (dst, src),
+                _ => continue,
+            };
+            if escaping[dst.0 as usize] || escaping[src.0 as usize] {
+                continue;
+            }
+            let copied = match defs_at(m, src) {
+                Some(d) => d,
+                None => continue,
+            };
+            for u in 0..f.ops.len() {
+                if u == m || defs_at(u, src) != Some(copied) || !dominates(m, u) {
+                    continue;
+                }
+                if deps.reaching(u, dst) == [m] {
+                    // The latest copy wins
+                    copies.insert((u, src), m);
+                }
+            }
+        }
+        Self { copies }
+    }
+
+    /// Position of a copy holding the value of `reg` read at `pos`
+    pub fn get(&self, pos: usize, reg: Reg) -> Option<usize> {
+        self.copies.get(&(pos, reg)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Function, Reg, Type};
+
+    use crate::copyprop::Copies;
+    use crate::slice::DataDeps;
+
+    /// ```text
+    /// 0: b = a
+    /// 1: if x < a goto 4
+    /// 2: c = a
+    /// 3: b = x
+    /// 4: r = a + x
+    /// 5: a = x
+    /// 6: ret a
+    /// ```
+    fn function() -> Function {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let ty = b.fun_type(&[i32_, i32_], i32_);
+        let findex = b.findex();
+        let mut f = FunctionBuilder::new(ty, &[i32_, i32_]);
+        let (a, x) = (f.arg(0), f.arg(1));
+        let [bb, c, r] = [(); 3].map(|_| f.reg(i32_));
+        let join = f.label();
+        f.emit(Opcode::Mov { dst: bb, src: a });
+        f.jump(
+            Opcode::JSLt {
+                a: x,
+                b: a,
+                offset: 0,
+            },
+            join,
+        );
+        f.emit(Opcode::Mov { dst: c, src: a });
+        f.emit(Opcode::Mov { dst: bb, src: x });
+        f.place(join);
+        f.emit(Opcode::Add { dst: r, a, b: x });
+        f.emit(Opcode::Mov { dst: a, src: x });
+        f.emit(Opcode::Ret { ret: a });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(findex, ty, regs, ops);
+        b.build().unwrap().functions.remove(0)
+    }
+
+    #[test]
+    fn copies() {
+        let f = function();
+        let copies = Copies::new(&f, &DataDeps::new(&f));
+        assert_eq!(copies.get(1, Reg(0)), Some(0));
+        assert_eq!(copies.get(2, Reg(0)), Some(0));
+        // b is overwritten, and c is only assigned in one branch
+        assert_eq!(copies.get(4, Reg(0)), None);
+        // a is overwritten
+        assert_eq!(copies.get(6, Reg(0)), None);
+    }
+}
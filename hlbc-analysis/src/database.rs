@@ -0,0 +1,262 @@
+//! Portable analysis database, to share the work done on a binary without sharing the binary.
+//!
+//! The database holds everything derived from the bytecode : tags (including auto-tagging results), functions and
+//! types renamed (by hand, by a profile or from signatures) and signature matches. The call graph and string
+//! references are included for tools reading the database without the bytecode, they are not imported back.
+//!
+//! It is saved as TOML and carries a [fingerprint] of the bytecode so it is only imported on the same binary.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use hlbc::analysis::tags::{TagTarget, Tags};
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, RefFun, Type};
+use hlbc::Bytecode;
+use serde::{Deserialize, Serialize};
+
+use crate::diff::function_hash;
+use crate::profile::Names;
+use crate::signatures::SigMatch;
+
+/// Error while loading a database
+#[derive(thiserror::Error, Debug)]
+pub enum DatabaseError {
+    #[error("Invalid database : {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error(
+        "The database has been made for another bytecode (fingerprint {expected}, found {found})"
+    )]
+    Mismatch { expected: String, found: String },
+    #[error("Invalid tag target '{0}'")]
+    InvalidTarget(String),
+}
+
+/// A function named from a signature
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SigEntry {
+    pub findex: usize,
+    pub name: String,
+    pub confidence: f32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Database {
+    /// Fingerprint of the bytecode
+    pub fingerprint: String,
+    /// Tags of each element
+    #[serde(default)]
+    pub tags: BTreeMap<String, Vec<String>>,
+    /// Names that differ from the bytecode
+    #[serde(default)]
+    pub renames: Names,
+    #[serde(default, rename = "signature")]
+    pub signatures: Vec<SigEntry>,
+    /// Functions called by each function
+    #[serde(default)]
+    pub calls: BTreeMap<String, Vec<usize>>,
+    /// Strings referenced by each function
+    #[serde(default)]
+    pub strings: BTreeMap<String, Vec<usize>>,
+}
+
+impl Database {
+    /// Build the database of `code`, with `original` the bytecode as it was loaded (to find renamed elements)
+    pub fn new(
+        original: &Bytecode,
+        code: &Bytecode,
+        tags: &Tags,
+        sig_matches: &[SigMatch],
+    ) -> Self {
+        let mut db = Self {
+            fingerprint: fingerprint(code),
+            ..Default::default()
+        };
+        for (target, t) in tags.iter() {
+            db.tags.insert(
+                target.to_string(),
+                t.into_iter().map(str::to_owned).collect(),
+            );
+        }
+        for (f, o) in code.functions.iter().zip(&original.functions) {
+            if let Some(name) = f.name(code) {
+                if Some(name) != o.name(original) {
+                    db.renames
+                        .functions
+                        .insert(f.findex.0.to_string(), name.to_owned());
+                }
+            }
+            db.renames.locals.extend(
+                renamed_locals(original, o, code, f)
+                    .into_iter()
+                    .map(|(reg, name)| (format!("{}.{reg}", f.findex.0), name)),
+            );
+            let calls: Vec<usize> = f.find_fun_refs().map(|(_, _, fun)| fun.0).collect();
+            if !calls.is_empty() {
+                db.calls.insert(f.findex.0.to_string(), calls);
+            }
+            let strings: Vec<usize> = f
+                .ops
+                .iter()
+                .filter_map(|o| match o {
+                    Opcode::String { ptr, .. } => Some(ptr.0),
+                    _ => None,
+                })
+                .collect();
+            if !strings.is_empty() {
+                db.strings.insert(f.findex.0.to_string(), strings);
+            }
+        }
+        for (i, (t, o)) in code.types.iter().zip(&original.types).enumerate() {
+            if let (Type::Obj(t) | Type::Struct(t), Type::Obj(o) | Type::Struct(o)) = (t, o) {
+                let name = t.name.resolve(&code.strings);
+                if name != o.name.resolve(&original.strings) {
+                    db.renames.types.insert(i.to_string(), name.to_owned());
+                }
+                let start = t.fields.len() - t.own_fields.len();
+                for (j, (f, of)) in t.own_fields.iter().zip(&o.own_fields).enumerate() {
+                    let name = f.name.resolve(&code.strings);
+                    if name != of.name.resolve(&original.strings) {
+                        db.renames
+                            .fields
+                            .insert(format!("{i}.{}", start + j), name.to_owned());
+                    }
+                }
+            }
+        }
+        db.signatures = sig_matches
+            .iter()
+            .map(|m| SigEntry {
+                findex: m.findex.0,
+                name: m.name.clone(),
+                confidence: m.confidence,
+            })
+            .collect();
+        db
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, DatabaseError> {
+        let db: Database = toml::from_str(s)?;
+        if let Some(target) = db.tags.keys().find(|t| t.parse::<TagTarget>().is_err()) {
+            return Err(DatabaseError::InvalidTarget(target.clone()));
+        }
+        Ok(db)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Database can't be serialized")
+    }
+
+    /// Import the database in a bytecode : rename elements and add tags, not saved with the tags of the user.
+    /// Returns the signature matches.
+    pub fn apply(
+        &self,
+        code: &mut Bytecode,
+        tags: &mut Tags,
+    ) -> Result<Vec<SigMatch>, DatabaseError> {
+        let found = fingerprint(code);
+        if found != self.fingerprint {
+            return Err(DatabaseError::Mismatch {
+                expected: self.fingerprint.clone(),
+                found,
+            });
+        }
+        self.renames.apply(code);
+        for (target, t) in &self.tags {
+            // Checked when loading
+            let target: TagTarget = target.parse().unwrap();
+            for tag in t {
+                tags.add_generated(target, tag.as_str());
+            }
+        }
+        Ok(self
+            .signatures
+            .iter()
+            .map(|s| SigMatch {
+                findex: RefFun(s.findex),
+                name: s.name.clone(),
+                confidence: s.confidence,
+            })
+            .collect())
+    }
+}
+
+/// Registers of `f` whose debug name differs from the `original` function, with their new name
+fn renamed_locals(
+    original: &Bytecode,
+    o: &Function,
+    code: &Bytecode,
+    f: &Function,
+) -> BTreeMap<u32, String> {
+    let mut renamed = BTreeMap::new();
+    let Some(assigns) = &f.assigns else {
+        return renamed;
+    };
+    let before = o.assigns.as_deref().unwrap_or_default();
+    let mut arg = 0;
+    for &(name, pos) in assigns {
+        let reg = if pos == 0 {
+            arg += 1;
+            Some(arg - 1)
+        } else {
+            f.ops.get(pos - 1).and_then(|o| o.dst()).map(|r| r.0)
+        };
+        let name = name.resolve(&code.strings);
+        let unchanged = before
+            .iter()
+            .any(|&(n, p)| p == pos && n.resolve(&original.strings) == name);
+        if let (Some(reg), false) = (reg, unchanged) {
+            renamed.insert(reg, name.to_owned());
+        }
+    }
+    renamed
+}
+
+/// Identifies a bytecode regardless of the names given to its functions and types
+pub fn fingerprint(code: &Bytecode) -> String {
+    // FNV-1a over the structure of each function
+    let mut h: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for b in bytes {
+            h ^= *b as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+    };
+    feed(&[code.version]);
+    feed(&code.types.len().to_le_bytes());
+    for f in &code.functions {
+        feed(function_hash(code, f).as_bytes());
+    }
+    let mut s = String::with_capacity(16);
+    write!(s, "{h:016x}").unwrap();
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{Database, DatabaseError};
+
+    #[test]
+    fn roundtrip() {
+        let mut db = Database {
+            fingerprint: "0123456789abcdef".to_owned(),
+            ..Default::default()
+        };
+        db.tags
+            .insert("fn@12".to_owned(), vec!["network".to_owned()]);
+        db.renames
+            .functions
+            .insert("12".to_owned(), "connect".to_owned());
+        db.renames
+            .locals
+            .insert("12.3".to_owned(), "socket".to_owned());
+        let db = Database::from_toml(&db.to_toml()).unwrap();
+        assert_eq!(db.tags["fn@12"], ["network"]);
+        assert_eq!(db.renames.functions["12"], "connect");
+        assert_eq!(db.renames.locals["12.3"], "socket");
+        assert!(matches!(
+            Database::from_toml("fingerprint = \"\"\n[tags]\n\"nope\" = []"),
+            Err(DatabaseError::InvalidTarget(_))
+        ));
+    }
+}
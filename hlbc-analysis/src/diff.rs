@@ -0,0 +1,435 @@
+//! Differences between two versions of a bytecode.
+//!
+//! Functions are matched by their qualified name (`Class.method`) and signature, then by name only, then functions left
+//! unmatched (unnamed or renamed) are matched by their [structural hash](function_hash). A matched function is modified
+//! when its hash or its signature changed. Types are matched by name, or by content for the types without a name
+//! (functions, virtuals, ...). Strings are compared as sets.
+//!
+//! Chaining the differences between successive versions gives the [timeline] of each function.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{FunPtr, Function, RefFun, RefString, RefType, Type, TypeObj};
+use hlbc::Bytecode;
+
+/// What happened to a function between two versions
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+    Unchanged,
+}
+
+/// A function of either version with its change
+#[derive(Debug, Clone)]
+pub struct FunctionChange {
+    /// Qualified name, or `_@findex` for unnamed functions
+    pub name: String,
+    pub change: Change,
+    /// Function in the old version
+    pub old: Option<RefFun>,
+    /// Function in the new version
+    pub new: Option<RefFun>,
+}
+
+/// A type of either version with its change
+#[derive(Debug, Clone)]
+pub struct TypeChange {
+    /// Name of the type, or its description for types without a name
+    pub name: String,
+    pub change: Change,
+    /// Type in the old version
+    pub old: Option<RefType>,
+    /// Type in the new version
+    pub new: Option<RefType>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BytecodeDiff {
+    pub functions: Vec<FunctionChange>,
+    pub types: Vec<TypeChange>,
+    /// Strings of the new version not in the old one
+    pub added_strings: Vec<RefString>,
+    /// Strings of the old version not in the new one
+    pub removed_strings: Vec<RefString>,
+}
+
+impl BytecodeDiff {
+    /// Number of functions with this change
+    pub fn count(&self, change: Change) -> usize {
+        self.functions.iter().filter(|f| f.change == change).count()
+    }
+
+    /// Functions added, removed or modified
+    pub fn changes(&self) -> impl Iterator<Item = &FunctionChange> {
+        self.functions
+            .iter()
+            .filter(|f| f.change != Change::Unchanged)
+    }
+
+    /// Number of types with this change
+    pub fn count_types(&self, change: Change) -> usize {
+        self.types.iter().filter(|t| t.change == change).count()
+    }
+
+    /// Types added, removed or modified
+    pub fn type_changes(&self) -> impl Iterator<Item = &TypeChange> {
+        self.types.iter().filter(|t| t.change != Change::Unchanged)
+    }
+}
+
+/// Compute the differences between two versions of a bytecode
+pub fn diff(old: &Bytecode, new: &Bytecode) -> BytecodeDiff {
+    BytecodeDiff {
+        functions: diff_functions(old, new),
+        types: diff_types(old, new),
+        added_strings: missing_strings(new, old),
+        removed_strings: missing_strings(old, new),
+    }
+}
+
+fn diff_functions(old: &Bytecode, new: &Bytecode) -> Vec<FunctionChange> {
+    let signature = |code: &Bytecode, f: &Function| f.t.display(code);
+    let mut old_named: HashMap<String, Vec<&Function>> = HashMap::new();
+    let mut old_rest: Vec<&Function> = Vec::new();
+    for f in &old.functions {
+        match qualified_name(old, f) {
+            Some(name) => old_named.entry(name).or_default().push(f),
+            None => old_rest.push(f),
+        }
+    }
+
+    let mut functions = Vec::new();
+    let mut new_named: Vec<(String, &Function)> = Vec::new();
+    let mut new_rest: Vec<&Function> = Vec::new();
+    // Same name and signature first, names aren't unique (local functions, closures)
+    for f in &new.functions {
+        let name = match qualified_name(new, f) {
+            Some(name) => name,
+            None => {
+                new_rest.push(f);
+                continue;
+            }
+        };
+        let candidates = old_named.get_mut(&name);
+        match candidates.as_ref().and_then(|c| {
+            c.iter()
+                .position(|o| signature(old, o) == signature(new, f))
+        }) {
+            Some(i) => {
+                let o = candidates.unwrap().remove(i);
+                functions.push(FunctionChange {
+                    name,
+                    change: if function_hash(old, o) == function_hash(new, f) {
+                        Change::Unchanged
+                    } else {
+                        Change::Modified
+                    },
+                    old: Some(o.findex),
+                    new: Some(f.findex),
+                });
+            }
+            None => new_named.push((name, f)),
+        }
+    }
+    // Then the same name with a different signature
+    for (name, f) in new_named {
+        match old_named.get_mut(&name).and_then(|v| v.pop()) {
+            Some(o) => functions.push(FunctionChange {
+                name,
+                change: Change::Modified,
+                old: Some(o.findex),
+                new: Some(f.findex),
+            }),
+            None => new_rest.push(f),
+        }
+    }
+    old_rest.extend(old_named.into_values().flatten());
+
+    // Match what's left by content
+    let mut by_hash: HashMap<String, Vec<&Function>> = HashMap::new();
+    for f in old_rest {
+        by_hash.entry(function_hash(old, f)).or_default().push(f);
+    }
+    for f in new_rest {
+        let name = display_name(new, f);
+        match by_hash
+            .get_mut(&function_hash(new, f))
+            .and_then(|v| v.pop())
+        {
+            Some(o) => functions.push(FunctionChange {
+                name,
+                change: Change::Unchanged,
+                old: Some(o.findex),
+                new: Some(f.findex),
+            }),
+            None => functions.push(FunctionChange {
+                name,
+                change: Change::Added,
+                old: None,
+                new: Some(f.findex),
+            }),
+        }
+    }
+    for f in by_hash.into_values().flatten() {
+        functions.push(FunctionChange {
+            name: display_name(old, f),
+            change: Change::Removed,
+            old: Some(f.findex),
+            new: None,
+        });
+    }
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    functions
+}
+
+fn diff_types(old: &Bytecode, new: &Bytecode) -> Vec<TypeChange> {
+    let mut old_types: HashMap<String, Vec<RefType>> = HashMap::new();
+    for (i, t) in old.types.iter().enumerate().rev() {
+        old_types
+            .entry(type_name(old, t))
+            .or_default()
+            .push(RefType(i));
+    }
+
+    let mut types = Vec::new();
+    for (i, t) in new.types.iter().enumerate() {
+        let name = type_name(new, t);
+        match old_types.get_mut(&name).and_then(|v| v.pop()) {
+            Some(o) => types.push(TypeChange {
+                change: if type_structure(old, o.resolve(&old.types)) == type_structure(new, t) {
+                    Change::Unchanged
+                } else {
+                    Change::Modified
+                },
+                name,
+                old: Some(o),
+                new: Some(RefType(i)),
+            }),
+            None => types.push(TypeChange {
+                name,
+                change: Change::Added,
+                old: None,
+                new: Some(RefType(i)),
+            }),
+        }
+    }
+    for (name, rest) in old_types {
+        for o in rest {
+            types.push(TypeChange {
+                name: name.clone(),
+                change: Change::Removed,
+                old: Some(o),
+                new: None,
+            });
+        }
+    }
+    types.sort_by(|a, b| a.name.cmp(&b.name));
+    types
+}
+
+/// Strings of `code` that are not in `other`
+fn missing_strings(code: &Bytecode, other: &Bytecode) -> Vec<RefString> {
+    let other: HashSet<&str> = other.strings.iter().map(|s| &**s).collect();
+    code.strings
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| !other.contains(&s[..]))
+        .map(|(i, _)| RefString(i))
+        .collect()
+}
+
+/// Name of a type identifying it across versions
+fn type_name(code: &Bytecode, t: &Type) -> String {
+    match t {
+        // Displayed names of the std containers are ambiguous
+        Type::Obj(obj) | Type::Struct(obj) => obj.name.resolve(&code.strings).to_owned(),
+        _ => t.display(code),
+    }
+}
+
+/// Description of the content of a named type, two versions of a type are the same when their descriptions are equal
+fn type_structure(code: &Bytecode, t: &Type) -> String {
+    let obj = |obj: &TypeObj| {
+        let fields: Vec<String> = obj
+            .fields
+            .iter()
+            .map(|f| format!("{}: {}", f.name.resolve(&code.strings), f.t.display(code)))
+            .collect();
+        let protos: Vec<&str> = obj
+            .protos
+            .iter()
+            .map(|p| p.name.resolve(&code.strings))
+            .collect();
+        let parent = obj.super_.map(|s| type_name(code, s.resolve(&code.types)));
+        format!("{parent:?} {} {}", fields.join(","), protos.join(","))
+    };
+    match t {
+        Type::Obj(o) | Type::Struct(o) => obj(o),
+        Type::Virtual { fields } => fields
+            .iter()
+            .map(|f| format!("{}: {}", f.name.resolve(&code.strings), f.t.display(code)))
+            .collect::<Vec<_>>()
+            .join(","),
+        Type::Enum { constructs, .. } => constructs
+            .iter()
+            .map(|c| {
+                let params: Vec<String> = c.params.iter().map(|p| p.display(code)).collect();
+                format!("{}({})", c.name.resolve(&code.strings), params.join(","))
+            })
+            .collect::<Vec<_>>()
+            .join(","),
+        _ => String::new(),
+    }
+}
+
+/// Changes of a function across versions
+#[derive(Debug, Clone)]
+pub struct FunctionHistory {
+    /// Name in the last version the function exists in
+    pub name: String,
+    /// Version index and change, the first version has no events
+    pub events: Vec<(usize, Change)>,
+    /// Function in the last version, `None` if it has been removed
+    pub current: Option<RefFun>,
+}
+
+/// Per function change timeline across an ordered list of versions
+pub fn timeline(versions: &[&Bytecode]) -> Vec<FunctionHistory> {
+    let mut histories = Vec::new();
+    let first = match versions.first() {
+        Some(first) => first,
+        None => return histories,
+    };
+    // History of each function of the previous version, by findex
+    let mut prev: HashMap<usize, usize> = HashMap::new();
+    for f in &first.functions {
+        prev.insert(f.findex.0, histories.len());
+        histories.push(FunctionHistory {
+            name: display_name(first, f),
+            events: Vec::new(),
+            current: Some(f.findex),
+        });
+    }
+    for (i, pair) in versions.windows(2).enumerate() {
+        let mut current = HashMap::new();
+        for c in diff_functions(pair[0], pair[1]) {
+            let h = match c.old.and_then(|o| prev.get(&o.0).copied()) {
+                Some(h) => h,
+                None => {
+                    histories.push(FunctionHistory {
+                        name: c.name.clone(),
+                        events: Vec::new(),
+                        current: None,
+                    });
+                    histories.len() - 1
+                }
+            };
+            let history = &mut histories[h];
+            if c.change != Change::Unchanged {
+                history.events.push((i + 1, c.change));
+            }
+            history.name = c.name;
+            history.current = c.new;
+            if let Some(new) = c.new {
+                current.insert(new.0, h);
+            }
+        }
+        prev = current;
+    }
+    histories
+}
+
+/// Structural hash of a function, independent of the binary it comes from : it only depends on the arguments count,
+/// the opcodes, the strings and the natives used. The hash is stable across hlbc versions.
+pub fn function_hash(code: &Bytecode, f: &Function) -> String {
+    // FNV-1a, std hashers aren't stable
+    let mut h: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for b in bytes {
+            h ^= *b as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+    };
+    feed(&f.ty(code).args.len().to_le_bytes());
+    let calls: HashMap<usize, RefFun> = f.find_fun_refs().map(|(i, _, fun)| (i, fun)).collect();
+    for (i, o) in f.ops.iter().enumerate() {
+        feed(o.name().as_bytes());
+        if let Opcode::String { ptr, .. } = o {
+            feed(ptr.resolve(&code.strings).as_bytes());
+        }
+        if let Some(FunPtr::Native(n)) = calls.get(&i).map(|fun| fun.resolve(code)) {
+            feed(n.lib.resolve(&code.strings).as_bytes());
+            feed(n.name(code).as_bytes());
+        }
+    }
+    let mut s = String::with_capacity(16);
+    write!(s, "{h:016x}").unwrap();
+    s
+}
+
+/// Name of a function with its class, `None` if it has no name
+pub fn qualified_name(code: &Bytecode, f: &Function) -> Option<String> {
+    let name = f.name(code)?;
+    let parent = f
+        .parent
+        .and_then(|p| p.resolve(&code.types).get_type_obj())
+        .map(|o| o.name.resolve(&code.strings).trim_start_matches('$'));
+    Some(match parent {
+        Some(parent) => format!("{parent}.{name}"),
+        None => name.to_owned(),
+    })
+}
+
+fn display_name(code: &Bytecode, f: &Function) -> String {
+    qualified_name(code, f).unwrap_or_else(|| format!("_@{}", f.findex.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::RefString;
+
+    use crate::diff::{diff, Change};
+
+    #[test]
+    fn diff_versions() {
+        let old = sample();
+        let same = diff(&old, &sample());
+        assert_eq!(same.changes().count(), 0);
+        assert_eq!(same.type_changes().count(), 0);
+        assert!(same.added_strings.is_empty() && same.removed_strings.is_empty());
+
+        let mut new = sample();
+        new.strings.push("Added".into());
+        let point = new
+            .types
+            .iter_mut()
+            .filter_map(|t| t.get_type_obj_mut())
+            .find(|o| o.name.resolve(&new.strings) == "Point")
+            .unwrap();
+        point.fields[0].name = RefString(new.strings.len() - 1);
+        let length = new
+            .functions
+            .iter()
+            .position(|f| f.name(&new) == Some("length"))
+            .unwrap();
+        new.functions[length].ops.insert(0, Opcode::Nop);
+
+        let d = diff(&old, &new);
+        assert_eq!(d.added_strings, [RefString(new.strings.len() - 1)]);
+        let point = d.type_changes().next().unwrap();
+        assert_eq!(
+            (point.name.as_str(), point.change),
+            ("Point", Change::Modified)
+        );
+        let modified: Vec<_> = d.changes().collect();
+        assert_eq!(modified.len(), 1);
+        assert_eq!(modified[0].name, "Point.length");
+        assert_eq!(modified[0].change, Change::Modified);
+    }
+}
@@ -0,0 +1,367 @@
+//! Type reconstruction for dynamic registers.
+//!
+//! Registers typed [Type::Dyn] or [Type::DynObj] often hold a value of a well known type.
+//! We look for evidence of that type around the register :
+//! - the value has been converted to dynamic with [Opcode::ToDyn]
+//! - the value is cast back to a concrete type with [Opcode::SafeCast] or [Opcode::UnsafeCast]
+//! - the value comes from or is passed to a function that does one of the above
+//! - the fields accessed with [Opcode::DynGet] and [Opcode::DynSet] only exist in a single class, found with a
+//!   [MemberIndex] built once per bytecode
+//!
+//! The inference is flow-insensitive : a register reused for values of two classes gets no type, and a register only
+//! seen accessing fields gets the class having the fields accessed by all its uses.
+
+use std::collections::{HashMap, HashSet};
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{FunPtr, Function, RefString, RefType, Reg, Str, Type};
+use hlbc::Bytecode;
+
+/// Inferred types for the dynamic registers of a function
+#[derive(Debug, Default, Clone)]
+pub struct DynTypes {
+    types: HashMap<Reg, RefType>,
+}
+
+/// Classes having a field or a method of each name, to find the class of a dynamic value from the fields accessed on it
+#[derive(Debug, Default, Clone)]
+pub struct MemberIndex {
+    classes: HashMap<Str, Vec<RefType>>,
+}
+
+impl MemberIndex {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut classes: HashMap<Str, Vec<RefType>> = HashMap::new();
+        for (i, t) in code.types.iter().enumerate() {
+            let Type::Obj(obj) = t else {
+                continue;
+            };
+            let names = obj
+                .fields
+                .iter()
+                .map(|f| f.name)
+                .chain(obj.protos.iter().map(|p| p.name));
+            for name in names {
+                let types = classes
+                    .entry(name.resolve_shared(&code.strings))
+                    .or_default();
+                // A method can have the name of a field
+                if types.last() != Some(&RefType(i)) {
+                    types.push(RefType(i));
+                }
+            }
+        }
+        Self { classes }
+    }
+
+    /// Classes having all these members, sorted
+    pub fn classes_with<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Vec<RefType> {
+        let mut names = names.into_iter();
+        let Some(first) = names.next() else {
+            return Vec::new();
+        };
+        let mut classes = self.classes.get(first).cloned().unwrap_or_default();
+        for name in names {
+            let with = self.classes.get(name).map_or(&[][..], Vec::as_slice);
+            classes.retain(|ty| with.binary_search(ty).is_ok());
+        }
+        classes
+    }
+}
+
+impl DynTypes {
+    /// Infer the types of the dynamic registers of a function, `members` is the [MemberIndex] of `code`
+    pub fn infer(code: &Bytecode, members: &MemberIndex, f: &Function) -> Self {
+        let mut evidence = local_evidence(code, f);
+
+        // Look into the functions we call, only one level deep
+        let calls = f.ops.iter().filter_map(|o| match o {
+            Opcode::Call0 { dst, fun } => Some((*dst, *fun, vec![])),
+            Opcode::Call1 { dst, fun, arg0 } => Some((*dst, *fun, vec![*arg0])),
+            Opcode::Call2 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+            } => Some((*dst, *fun, vec![*arg0, *arg1])),
+            Opcode::Call3 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+                arg2,
+            } => Some((*dst, *fun, vec![*arg0, *arg1, *arg2])),
+            Opcode::Call4 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                arg3,
+            } => Some((*dst, *fun, vec![*arg0, *arg1, *arg2, *arg3])),
+            Opcode::CallN { dst, fun, args } => Some((*dst, *fun, args.clone())),
+            _ => None,
+        });
+        for (dst, fun, args) in calls {
+            let FunPtr::Fun(callee) = fun.resolve(code) else {
+                continue;
+            };
+            if callee.findex == f.findex {
+                continue;
+            }
+            let callee_evidence = local_evidence(code, callee);
+            for (i, arg) in args.into_iter().enumerate() {
+                if let Some(tys) = callee_evidence.get(&Reg(i as u32)) {
+                    evidence.entry(arg).or_default().extend(tys);
+                }
+            }
+            let returned = callee.ops.iter().filter_map(|o| match o {
+                Opcode::Ret { ret } => callee_evidence.get(ret),
+                _ => None,
+            });
+            for tys in returned {
+                evidence.entry(dst).or_default().extend(tys);
+            }
+        }
+
+        let mut types = HashMap::new();
+        for (reg, tys) in evidence {
+            if !is_dynamic(code, f, reg) {
+                continue;
+            }
+            if let [ty] = tys.into_iter().collect::<Vec<_>>()[..] {
+                types.insert(reg, ty);
+            }
+        }
+
+        // Fall back to the accessed fields
+        for (reg, fields) in accessed_fields(code, f) {
+            if types.contains_key(&reg) {
+                continue;
+            }
+            if let Some(ty) = find_type_with_fields(code, members, &fields) {
+                types.insert(reg, ty);
+            }
+        }
+
+        Self { types }
+    }
+
+    /// Inferred type of a dynamic register
+    pub fn get(&self, reg: Reg) -> Option<RefType> {
+        self.types.get(&reg).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Reg, RefType)> + '_ {
+        self.types.iter().map(|(r, t)| (*r, *t))
+    }
+}
+
+fn is_dynamic(code: &Bytecode, f: &Function, reg: Reg) -> bool {
+    matches!(
+        f.regtype(reg).resolve(&code.types),
+        Type::Dyn | Type::DynObj
+    )
+}
+
+/// Types seen for each dynamic register through conversions, only looking at this function
+fn local_evidence(code: &Bytecode, f: &Function) -> HashMap<Reg, HashSet<RefType>> {
+    let mut evidence: HashMap<Reg, HashSet<RefType>> = HashMap::new();
+    let mut movs = Vec::new();
+    for o in &f.ops {
+        match *o {
+            Opcode::ToDyn { dst, src } if !is_dynamic(code, f, src) => {
+                evidence.entry(dst).or_default().insert(f.regtype(src));
+            }
+            Opcode::SafeCast { dst, src } | Opcode::UnsafeCast { dst, src }
+                if is_dynamic(code, f, src) && !is_dynamic(code, f, dst) =>
+            {
+                evidence.entry(src).or_default().insert(f.regtype(dst));
+            }
+            Opcode::Mov { dst, src } if is_dynamic(code, f, dst) => {
+                movs.push((dst, src));
+            }
+            _ => {}
+        }
+    }
+    // Moves are both ways, a register holds the same value as its copy
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &(a, b) in &movs {
+            for (from, to) in [(a, b), (b, a)] {
+                let Some(tys) = evidence.get(&from).cloned() else {
+                    continue;
+                };
+                let dst = evidence.entry(to).or_default();
+                let len = dst.len();
+                dst.extend(tys);
+                changed |= dst.len() != len;
+            }
+        }
+    }
+    evidence
+}
+
+/// Field names accessed dynamically for each register
+fn accessed_fields(code: &Bytecode, f: &Function) -> HashMap<Reg, HashSet<RefString>> {
+    let mut fields: HashMap<Reg, HashSet<RefString>> = HashMap::new();
+    for o in &f.ops {
+        match *o {
+            Opcode::DynGet { obj, field, .. } | Opcode::DynSet { obj, field, .. }
+                if is_dynamic(code, f, obj) =>
+            {
+                fields.entry(obj).or_default().insert(field);
+            }
+            _ => {}
+        }
+    }
+    fields
+}
+
+/// Returns true if an object type has a field or a method with this name
+pub fn has_member(code: &Bytecode, ty: RefType, name: &str) -> bool {
+    match ty.resolve(&code.types) {
+        Type::Obj(obj) | Type::Struct(obj) => {
+            obj.fields
+                .iter()
+                .any(|f| f.name.resolve(&code.strings) == name)
+                || obj
+                    .protos
+                    .iter()
+                    .any(|p| p.name.resolve(&code.strings) == name)
+        }
+        Type::Virtual { fields } => fields.iter().any(|f| f.name.resolve(&code.strings) == name),
+        _ => false,
+    }
+}
+
+/// The only class having all these fields, excluding subclasses
+fn find_type_with_fields(
+    code: &Bytecode,
+    members: &MemberIndex,
+    fields: &HashSet<RefString>,
+) -> Option<RefType> {
+    let mut candidates = members
+        .classes_with(fields.iter().map(|f| f.resolve(&code.strings)))
+        .into_iter();
+    let mut first = candidates.next()?;
+    for ty in candidates {
+        // Prefer the base class when all candidates are in the same hierarchy
+        if code.is_subclass(first, ty) {
+            first = ty;
+        } else if !code.is_subclass(ty, first) {
+            return None;
+        }
+    }
+    Some(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefType, Reg, Type};
+    use hlbc::Bytecode;
+
+    use crate::dyntypes::{DynTypes, MemberIndex};
+
+    /// Classes `A { a }`, `B { a, b }` and `C extends A { c }`, and a function with a dynamic register. `body` gets the
+    /// builder and the classes, the function returns the dynamic register.
+    fn build(
+        body: impl FnOnce(&mut BytecodeBuilder, &mut FunctionBuilder, Reg, [RefType; 3]),
+    ) -> (Bytecode, [RefType; 3]) {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let dyn_ = b.ty(Type::Dyn);
+        let a = b.class("A", None, &[("a", i32_)], &[]);
+        let bb = b.class("B", None, &[("a", i32_), ("b", i32_)], &[]);
+        let c = b.class("C", Some(a), &[("c", i32_)], &[]);
+        let ty = b.fun_type(&[], dyn_);
+        let findex = b.findex();
+        let mut f = FunctionBuilder::new(ty, &[]);
+        let d = f.reg(dyn_);
+        body(&mut b, &mut f, d, [a, bb, c]);
+        f.emit(Opcode::Ret { ret: d });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(findex, ty, regs, ops);
+        (b.build().unwrap(), [a, bb, c])
+    }
+
+    fn infer(code: &Bytecode) -> DynTypes {
+        DynTypes::infer(code, &MemberIndex::new(code), &code.functions[0])
+    }
+
+    fn get(f: &mut FunctionBuilder, b: &mut BytecodeBuilder, obj: Reg, field: &str) {
+        let i32_ = b.ty(Type::I32);
+        let dst = f.reg(i32_);
+        f.emit(Opcode::DynGet {
+            dst,
+            obj,
+            field: b.string(field),
+        });
+    }
+
+    #[test]
+    fn members() {
+        let (code, [a, bb, c]) = build(|_, _, _, _| {});
+        let index = MemberIndex::new(&code);
+        assert_eq!(index.classes_with(["a"]), [a, bb, c]);
+        assert_eq!(index.classes_with(["a", "b"]), [bb]);
+        assert!(index.classes_with(["b", "c"]).is_empty());
+        assert!(index.classes_with(["d"]).is_empty());
+    }
+
+    #[test]
+    fn conversions() {
+        let (code, [a, ..]) = build(|_, f, d, [a, ..]| {
+            let obj = f.reg(a);
+            f.emit(Opcode::ToDyn { dst: d, src: obj });
+        });
+        assert_eq!(infer(&code).get(Reg(0)), Some(a));
+
+        let (code, [_, bb, _]) = build(|_, f, d, [_, bb, _]| {
+            let obj = f.reg(bb);
+            f.emit(Opcode::SafeCast { dst: obj, src: d });
+        });
+        assert_eq!(infer(&code).get(Reg(0)), Some(bb));
+    }
+
+    #[test]
+    fn fields() {
+        // A, B and C have `a`, C is a subclass of A but B is unrelated
+        let (code, _) = build(|b, f, d, _| get(f, b, d, "a"));
+        assert_eq!(infer(&code).get(Reg(0)), None);
+        let (code, [_, bb, _]) = build(|b, f, d, _| {
+            get(f, b, d, "a");
+            get(f, b, d, "b");
+        });
+        assert_eq!(infer(&code).get(Reg(0)), Some(bb));
+        let (code, [.., c]) = build(|b, f, d, _| get(f, b, d, "c"));
+        assert_eq!(infer(&code).get(Reg(0)), Some(c));
+    }
+
+    /// The inference is flow-insensitive : a register reused for two values has a single type for both
+    #[test]
+    fn reused_register() {
+        // Conflicting conversions, no type
+        let (code, _) = build(|_, f, d, [a, bb, _]| {
+            let (x, y) = (f.reg(a), f.reg(bb));
+            f.emit(Opcode::ToDyn { dst: d, src: x });
+            f.emit(Opcode::ToDyn { dst: d, src: y });
+        });
+        assert_eq!(infer(&code).get(Reg(0)), None);
+
+        // Fields of an A, then of a B in the same register : only B has them all, the A is typed as a B
+        let (code, [_, bb, _]) = build(|b, f, d, _| {
+            get(f, b, d, "a");
+            f.emit(Opcode::Null { dst: d });
+            get(f, b, d, "b");
+        });
+        assert_eq!(infer(&code).get(Reg(0)), Some(bb));
+    }
+}
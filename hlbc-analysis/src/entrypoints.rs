@@ -0,0 +1,506 @@
+//! Heuristics to find where a stripped program starts doing things : its main function, update loops and event
+//! handlers.
+//!
+//! Without names nor debug info, the entrypoint of the bytecode only leads to compiler generated initialization code.
+//! These heuristics rely on what stripping can't remove : the order of the initialization, closures given to the
+//! runtime and methods of the application class that nothing calls directly, the engine calls them.
+//!
+//! The start of the program itself is known : [module_entrypoint] is the function the runtime calls first. It
+//! constructs the objects holding the statics of each class in the order given by [boot_sequence], runs the static
+//! initializers ([static_initializers]) and calls the main function.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use hlbc::analysis::tags::{TagTarget, Tags};
+use hlbc::opcodes::Opcode;
+use hlbc::types::{FunPtr, Function, RefFun, RefGlobal, RefType, Reg, Type};
+use hlbc::Bytecode;
+
+/// What a function is likely to be, in order of importance
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Role {
+    Main,
+    UpdateLoop,
+    EventHandler,
+}
+
+impl Role {
+    /// Tag given to the functions with this role
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Role::Main => "main",
+            Role::UpdateLoop => "update-loop",
+            Role::EventHandler => "event-handler",
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Main => "main",
+            Role::UpdateLoop => "update loop",
+            Role::EventHandler => "event handler",
+        })
+    }
+}
+
+/// Why a function has been given a role
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Evidence {
+    /// Last function called by the entrypoint, once globals are initialized
+    CalledAfterInit,
+    /// A closure of the function is given to a native
+    NativeCallback(RefFun),
+    /// A closure of the function is stored to be called later
+    StoredCallback,
+    /// The function registers a closure of itself, to be called again (e.g. next frame)
+    Reschedules,
+    /// Method of the application class overriding a parent method, never called directly
+    EngineOverride,
+}
+
+impl Display for Evidence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Evidence::CalledAfterInit => f.write_str("called last by the entrypoint"),
+            Evidence::NativeCallback(n) => write!(f, "closure given to native fn@{}", n.0),
+            Evidence::StoredCallback => f.write_str("closure stored as a callback"),
+            Evidence::Reschedules => f.write_str("registers itself again"),
+            Evidence::EngineOverride => f.write_str("application method only called by the engine"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Entrypoint {
+    pub findex: RefFun,
+    pub role: Role,
+    pub evidence: Evidence,
+}
+
+/// Find the likely main function, update loops and event handlers, main first. A function appears once with its most
+/// important role.
+pub fn find_entrypoints(code: &Bytecode) -> Vec<Entrypoint> {
+    let mut found: HashMap<RefFun, (Role, Evidence)> = HashMap::new();
+    let mut add = |findex: RefFun, role: Role, evidence: Evidence| {
+        let entry = found.entry(findex).or_insert((role, evidence));
+        if role < entry.0 {
+            *entry = (role, evidence);
+        }
+    };
+
+    let main = find_main(code);
+    if let Some(main) = main {
+        add(main, Role::Main, Evidence::CalledAfterInit);
+    }
+
+    for f in &code.functions {
+        for (findex, evidence) in registered_callbacks(code, f) {
+            if findex == f.findex {
+                add(findex, Role::UpdateLoop, Evidence::Reschedules);
+            } else {
+                add(findex, Role::EventHandler, evidence);
+            }
+        }
+    }
+
+    if let Some(main) = main.and_then(|m| m.resolve_as_fn(code)) {
+        let calls = call_counts(code);
+        for findex in engine_overrides(code, main) {
+            if !calls.contains_key(&findex) {
+                let role = if takes_frame_time(code, findex) {
+                    Role::UpdateLoop
+                } else {
+                    Role::EventHandler
+                };
+                add(findex, role, Evidence::EngineOverride);
+            }
+        }
+    }
+
+    let mut entries: Vec<Entrypoint> = found
+        .into_iter()
+        .map(|(findex, (role, evidence))| Entrypoint {
+            findex,
+            role,
+            evidence,
+        })
+        .collect();
+    entries.sort_by_key(|e| (e.role, e.findex));
+    entries
+}
+
+/// Tag each function with the tag of its role (not saved with the tags of the user), returns the number of tags added
+pub fn tag_entrypoints(entries: &[Entrypoint], tags: &mut Tags) -> usize {
+    entries
+        .iter()
+        .filter(|e| tags.add_generated(TagTarget::Fun(e.findex), e.role.tag()))
+        .count()
+}
+
+/// A global object constructed by the entrypoint before calling the main function
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BootStep {
+    /// Position of the first assignment of the global in the entrypoint
+    pub pos: usize,
+    pub global: RefGlobal,
+    /// Type of the global, the `$Class` object holding the statics of a class
+    pub ty: RefType,
+    /// Class whose statics are held by the global
+    pub class: Option<RefType>,
+}
+
+/// The function called first by the runtime, generated by the compiler. None if the entrypoint is a native.
+pub fn module_entrypoint(code: &Bytecode) -> Option<&Function> {
+    code.entrypoint.resolve_as_fn(code)
+}
+
+/// Static initializers (`__init__` functions) with the type they belong to, sorted by type
+pub fn static_initializers(code: &Bytecode) -> Vec<(RefType, RefFun)> {
+    let mut inits: Vec<_> = code
+        .functions
+        .iter()
+        .filter(|f| f.name(code) == Some("__init__"))
+        .filter_map(|f| Some((f.parent?, f.findex)))
+        .collect();
+    inits.sort_unstable();
+    inits
+}
+
+/// Objects and structures assigned to globals by the entrypoint, in the order they are first assigned
+pub fn boot_sequence(code: &Bytecode) -> Vec<BootStep> {
+    let init = match module_entrypoint(code) {
+        Some(init) => init,
+        None => return Vec::new(),
+    };
+    // Classes by the global holding their statics
+    let mut classes = HashMap::new();
+    for (t, ty) in code.iter_types() {
+        if let Some(obj) = ty.get_type_obj().filter(|o| o.global.0 > 0) {
+            classes.insert(RefGlobal(obj.global.0 - 1), t);
+        }
+    }
+    let mut steps: Vec<BootStep> = Vec::new();
+    for (pos, o) in init.ops.iter().enumerate() {
+        if let &Opcode::SetGlobal { global, .. } = o {
+            let ty = code.globals[global.0];
+            if matches!(ty.resolve(&code.types), Type::Obj(_) | Type::Struct(_))
+                && steps.iter().all(|s| s.global != global)
+            {
+                steps.push(BootStep {
+                    pos,
+                    global,
+                    ty,
+                    class: classes.get(&global).copied(),
+                });
+            }
+        }
+    }
+    steps
+}
+
+/// The entrypoint initializes the globals and the static variables then calls the main function
+fn find_main(code: &Bytecode) -> Option<RefFun> {
+    let init = code.entrypoint.resolve_as_fn(code)?;
+    let globals_set = init
+        .ops
+        .iter()
+        .rposition(|o| matches!(o, Opcode::SetGlobal { .. }))
+        .map_or(0, |pos| pos + 1);
+    init.ops[globals_set..]
+        .iter()
+        .rev()
+        .filter_map(direct_call)
+        .find(|fun| fun.resolve_as_fn(code).is_some())
+}
+
+/// Closures of functions created in `f` and given to a native or stored somewhere
+fn registered_callbacks(code: &Bytecode, f: &Function) -> Vec<(RefFun, Evidence)> {
+    let is_closure = |reg: Reg| matches!(f.regtype(reg).resolve(&code.types), Type::Fun(_));
+    let mut callbacks = Vec::new();
+    for (pos, o) in f.ops.iter().enumerate() {
+        let (regs, evidence) = match o {
+            Opcode::SetField { src, .. }
+            | Opcode::SetThis { src, .. }
+            | Opcode::DynSet { src, .. }
+            | Opcode::SetGlobal { src, .. }
+            | Opcode::SetArray { src, .. } => (vec![*src], Evidence::StoredCallback),
+            _ => match direct_call(o).map(|fun| fun.resolve(code)) {
+                Some(FunPtr::Native(n)) => (call_args(o), Evidence::NativeCallback(n.findex)),
+                _ => continue,
+            },
+        };
+        for reg in regs.into_iter().filter(|&r| is_closure(r)) {
+            if let Some(fun) = f.find_last_closure_assign(code, reg, pos) {
+                callbacks.push((fun, evidence));
+            }
+        }
+    }
+    callbacks
+}
+
+/// Number of direct call sites of each function
+fn call_counts(code: &Bytecode) -> HashMap<RefFun, usize> {
+    let mut calls = HashMap::new();
+    for (_, (_, o)) in code.ops() {
+        if let Some(fun) = direct_call(o) {
+            *calls.entry(fun).or_insert(0) += 1;
+        }
+    }
+    calls
+}
+
+/// Methods of the application classes (the class of main and the classes it instantiates) overriding a method of a
+/// parent class
+fn engine_overrides(code: &Bytecode, main: &Function) -> Vec<RefFun> {
+    let mut classes: Vec<RefType> = main.parent.into_iter().collect();
+    for o in &main.ops {
+        if let Opcode::New { dst } = o {
+            classes.push(main.regtype(*dst));
+        }
+    }
+    classes.sort_unstable();
+    classes.dedup();
+
+    let mut overrides = Vec::new();
+    for &t in &classes {
+        if let Some(obj) = t.resolve_as_obj(&code.types) {
+            for p in &obj.protos {
+                if code.overridden(t, p.name.resolve(&code.strings)).is_some() {
+                    overrides.push(p.findex);
+                }
+            }
+        }
+    }
+    overrides
+}
+
+/// An update method usually takes the time elapsed since the last frame as its only argument
+fn takes_frame_time(code: &Bytecode, findex: RefFun) -> bool {
+    let args = findex.args(code);
+    // The first argument is the instance
+    args.len() == 2 && matches!(args[1].resolve(&code.types), Type::F64 | Type::F32)
+}
+
+pub(crate) fn direct_call(o: &Opcode) -> Option<RefFun> {
+    match *o {
+        Opcode::Call0 { fun, .. }
+        | Opcode::Call1 { fun, .. }
+        | Opcode::Call2 { fun, .. }
+        | Opcode::Call3 { fun, .. }
+        | Opcode::Call4 { fun, .. }
+        | Opcode::CallN { fun, .. } => Some(fun),
+        _ => None,
+    }
+}
+
+pub(crate) fn call_args(o: &Opcode) -> Vec<Reg> {
+    match o {
+        Opcode::Call1 { arg0, .. } => vec![*arg0],
+        Opcode::Call2 { arg0, arg1, .. } => vec![*arg0, *arg1],
+        Opcode::Call3 {
+            arg0, arg1, arg2, ..
+        } => vec![*arg0, *arg1, *arg2],
+        Opcode::Call4 {
+            arg0,
+            arg1,
+            arg2,
+            arg3,
+            ..
+        } => vec![*arg0, *arg1, *arg2, *arg3],
+        Opcode::CallN { args, .. } => args.clone(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::analysis::tags::{TagTarget, Tags};
+    use hlbc::builder::BytecodeBuilder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefGlobal, RefType, Reg, Type};
+
+    use crate::entrypoints::{
+        boot_sequence, find_entrypoints, module_entrypoint, static_initializers, tag_entrypoints,
+        BootStep, Evidence, Role,
+    };
+
+    #[test]
+    fn entrypoints() {
+        let mut b = BytecodeBuilder::new();
+        let void = RefType(0);
+        let i32_ = b.ty(Type::I32);
+        let f64_ = b.ty(Type::F64);
+        let (init, main, on_click, tick, base_update, app_update) = (
+            b.findex(),
+            b.findex(),
+            b.findex(),
+            b.findex(),
+            b.findex(),
+            b.findex(),
+        );
+        let base = b.class("Base", None, &[], &[("update", base_update)]);
+        let app = b.class("App", Some(base), &[], &[("update", app_update)]);
+        let fun_v_v = b.fun_type(&[], void);
+        let register_t = b.fun_type(&[fun_v_v], void);
+        let set_handler = b.native("ui", "set_handler", register_t);
+        let set_timeout = b.native("ui", "set_timeout", register_t);
+        let g_count = b.global(i32_);
+        let zero = b.int(0);
+
+        b.function(
+            init,
+            fun_v_v,
+            vec![i32_, void],
+            vec![
+                Opcode::Int {
+                    dst: Reg(0),
+                    ptr: zero,
+                },
+                Opcode::SetGlobal {
+                    global: g_count,
+                    src: Reg(0),
+                },
+                Opcode::Call0 {
+                    dst: Reg(1),
+                    fun: main,
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        b.function(
+            main,
+            fun_v_v,
+            vec![app, fun_v_v, void],
+            vec![
+                Opcode::New { dst: Reg(0) },
+                Opcode::StaticClosure {
+                    dst: Reg(1),
+                    fun: on_click,
+                },
+                Opcode::Call1 {
+                    dst: Reg(2),
+                    fun: set_handler,
+                    arg0: Reg(1),
+                },
+                Opcode::Call0 {
+                    dst: Reg(2),
+                    fun: tick,
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        b.function(
+            on_click,
+            fun_v_v,
+            vec![void],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        b.function(
+            tick,
+            fun_v_v,
+            vec![fun_v_v, void],
+            vec![
+                Opcode::StaticClosure {
+                    dst: Reg(0),
+                    fun: tick,
+                },
+                Opcode::Call1 {
+                    dst: Reg(1),
+                    fun: set_timeout,
+                    arg0: Reg(0),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        let method_t = b.fun_type(&[base, f64_], void);
+        b.function(
+            base_update,
+            method_t,
+            vec![base, f64_, void],
+            vec![Opcode::Ret { ret: Reg(2) }],
+        );
+        let method_t = b.fun_type(&[app, f64_], void);
+        b.function(
+            app_update,
+            method_t,
+            vec![app, f64_, void],
+            vec![Opcode::Ret { ret: Reg(2) }],
+        );
+        b.entrypoint(init);
+        let code = b.build().unwrap();
+
+        let entries = find_entrypoints(&code);
+        let found: Vec<_> = entries.iter().map(|e| (e.findex, e.role)).collect();
+        assert_eq!(
+            found,
+            [
+                (main, Role::Main),
+                (tick, Role::UpdateLoop),
+                (app_update, Role::UpdateLoop),
+                (on_click, Role::EventHandler)
+            ]
+        );
+        assert_eq!(entries[3].evidence, Evidence::NativeCallback(set_handler));
+        assert_eq!(entries[2].evidence, Evidence::EngineOverride);
+
+        let mut tags = Tags::new();
+        assert_eq!(tag_entrypoints(&entries, &mut tags), 4);
+        assert!(tags.has(TagTarget::Fun(main), "main"));
+    }
+
+    #[test]
+    fn boot() {
+        let mut b = BytecodeBuilder::new();
+        let void = RefType(0);
+        let [init, static_init] = [(); 2].map(|_| b.findex());
+        let statics = b.class("$Main", None, &[], &[("__init__", static_init)]);
+        let g_statics = b.global(statics);
+        let main = b.class("Main", None, &[], &[]);
+        let fun_v_v = b.fun_type(&[], void);
+        b.function(
+            init,
+            fun_v_v,
+            vec![statics, void],
+            vec![
+                Opcode::New { dst: Reg(0) },
+                Opcode::SetGlobal {
+                    global: g_statics,
+                    src: Reg(0),
+                },
+                Opcode::Call0 {
+                    dst: Reg(1),
+                    fun: static_init,
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        b.function(
+            static_init,
+            fun_v_v,
+            vec![void],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        b.entrypoint(init);
+        let mut code = b.build().unwrap();
+        // The global holding the statics, offset by one
+        if let Type::Obj(obj) = &mut code.types[main.0] {
+            obj.global = RefGlobal(g_statics.0 + 1);
+        }
+
+        assert_eq!(module_entrypoint(&code).unwrap().findex, init);
+        assert_eq!(static_initializers(&code), [(statics, static_init)]);
+        assert_eq!(
+            boot_sequence(&code),
+            [BootStep {
+                pos: 1,
+                global: g_statics,
+                ty: statics,
+                class: Some(main),
+            }]
+        );
+    }
+}
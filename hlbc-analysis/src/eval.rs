@@ -0,0 +1,1231 @@
+//! Sandboxed interpreter for pure functions.
+//!
+//! [eval] runs a function with the given arguments without the game : hash functions, damage formulas, encoders ...
+//! Only the bytecode is executed, a function calling a native that isn't stubbed here (anything else than the math
+//! functions of the std library) is rejected before running. Objects, strings, bytes and exceptions are supported,
+//! arrays, enums, dynamic objects and method calls are not and stop the evaluation with [EvalError::Unsupported].
+//! The evaluation is bounded in steps and call depth, so it always terminates.
+//!
+//! ```
+//! use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+//! use hlbc::prelude::*;
+//! use hlbc_analysis::eval::{eval, Value};
+//!
+//! let mut b = BytecodeBuilder::new();
+//! let i32_ = b.ty(Type::I32);
+//! let double_t = b.fun_type(&[i32_], i32_);
+//! let double = b.findex();
+//! let mut f = FunctionBuilder::new(double_t, &[i32_]);
+//! let x = f.arg(0);
+//! f.emit(Opcode::Add { dst: x, a: x, b: x });
+//! f.emit(Opcode::Ret { ret: x });
+//! let (_, regs, ops) = f.finish().unwrap();
+//! b.function(double, double_t, regs, ops);
+//! let code = b.build().unwrap();
+//!
+//! assert_eq!(eval(&code, double, vec![Value::Int(21)]).unwrap(), Value::Int(42));
+//! ```
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use hlbc::constants::ConstValue;
+use hlbc::opcodes::{JumpOffset, Opcode};
+use hlbc::types::{FunPtr, Function, RefFun, RefGlobal, RefString, RefType, Reg, Type};
+use hlbc::{version, Bytecode};
+
+/// Maximum number of instructions executed by default
+pub const DEFAULT_MAX_STEPS: usize = 10_000_000;
+/// Maximum call depth by default
+pub const DEFAULT_MAX_DEPTH: usize = 512;
+
+/// A runtime value. Boxing to `dyn` is transparent, a boxed int is still an [Value::Int].
+#[derive(Debug, Clone)]
+pub enum Value {
+    Void,
+    Null,
+    /// `i32`, `u8` and `u16`
+    Int(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Bytes(Rc<RefCell<Vec<u8>>>),
+    Obj(Rc<RefCell<Object>>),
+    /// A closure without a bound object
+    Fun(RefFun),
+    Type(RefType),
+}
+
+/// An instance of a class or a struct, fields are in the order of the flattened fields of the type
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub ty: RefType,
+    pub fields: Vec<Value>,
+}
+
+impl PartialEq for Value {
+    /// Numbers and booleans are compared by value, bytes and objects by reference
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Void, Value::Void) | (Value::Null, Value::Null) => true,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::I64(a), Value::I64(b)) => a == b,
+            (Value::F32(a), Value::F32(b)) => a == b,
+            (Value::F64(a), Value::F64(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => Rc::ptr_eq(a, b),
+            (Value::Obj(a), Value::Obj(b)) => Rc::ptr_eq(a, b),
+            (Value::Fun(a), Value::Fun(b)) => a == b,
+            (Value::Type(a), Value::Type(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    /// Zero value of a register or a field of type `ty`
+    pub fn default_of(code: &Bytecode, ty: RefType) -> Value {
+        match ty.resolve(&code.types) {
+            Type::Void => Value::Void,
+            Type::UI8 | Type::UI16 | Type::I32 => Value::Int(0),
+            Type::I64 => Value::I64(0),
+            Type::F32 => Value::F32(0.0),
+            Type::F64 => Value::F64(0.0),
+            Type::Bool => Value::Bool(false),
+            _ => Value::Null,
+        }
+    }
+
+    /// Value of a Haxe `String` if this is one
+    pub fn as_string(&self, code: &Bytecode) -> Option<String> {
+        let Value::Obj(obj) = self else { return None };
+        let obj = obj.borrow();
+        let ty = obj.ty.resolve_as_obj(&code.types)?;
+        if ty.name.resolve(&code.strings) != "String" {
+            return None;
+        }
+        match obj.fields.as_slice() {
+            [Value::Bytes(bytes), Value::Int(len)] => {
+                let bytes = bytes.borrow();
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .take(*len as usize)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                Some(String::from_utf16_lossy(&units))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn display(&self, code: &Bytecode) -> String {
+        match self {
+            Value::Void => "void".to_owned(),
+            Value::Null => "null".to_owned(),
+            Value::Int(v) => v.to_string(),
+            Value::I64(v) => v.to_string(),
+            Value::F32(v) => v.to_string(),
+            Value::F64(v) => v.to_string(),
+            Value::Bool(v) => v.to_string(),
+            Value::Bytes(b) => format!("bytes({})", b.borrow().len()),
+            Value::Obj(obj) => {
+                if let Some(s) = self.as_string(code) {
+                    return format!("{s:?}");
+                }
+                let obj = obj.borrow();
+                let names = obj
+                    .ty
+                    .resolve_as_obj(&code.types)
+                    .map(|o| o.fields.as_slice())
+                    .unwrap_or_default();
+                let fields: Vec<String> = names
+                    .iter()
+                    .zip(&obj.fields)
+                    .map(|(f, v)| format!("{}: {}", f.name.display(code), v.display(code)))
+                    .collect();
+                format!("{} {{ {} }}", obj.ty.display(code), fields.join(", "))
+            }
+            Value::Fun(f) => format!("fn@{}", f.0),
+            Value::Type(t) => t.display_id(code),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum EvalError {
+    #[error("fn@{} is not a function with code", findex.0)]
+    NotAFunction { findex: RefFun },
+    #[error("fn@{} expects {expected} arguments, got {got}", findex.0)]
+    ArgumentCount {
+        findex: RefFun,
+        expected: usize,
+        got: usize,
+    },
+    #[error("The native {0} can't be evaluated")]
+    Impure(String),
+    #[error("fn@{} at {pos} : {op} is not supported", findex.0)]
+    Unsupported {
+        findex: RefFun,
+        pos: usize,
+        op: &'static str,
+    },
+    #[error("fn@{} at {pos} : unexpected value for {op}", findex.0)]
+    TypeMismatch {
+        findex: RefFun,
+        pos: usize,
+        op: &'static str,
+    },
+    #[error("fn@{} at {pos} : division by zero", findex.0)]
+    DivisionByZero { findex: RefFun, pos: usize },
+    #[error("fn@{} at {pos} : out of bounds access", findex.0)]
+    OutOfBounds { findex: RefFun, pos: usize },
+    #[error("global@{} is initialized at runtime", .0 .0)]
+    UninitializedGlobal(RefGlobal),
+    #[error("Uncaught exception : {0}")]
+    Uncaught(String),
+    #[error("Stopped after {0} steps")]
+    StepLimit(usize),
+    #[error("Stopped at call depth {0}")]
+    StackOverflow(usize),
+}
+
+/// Run a function after checking it can be evaluated, see [Interpreter]
+pub fn eval(code: &Bytecode, f: RefFun, args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut interp = Interpreter::new(code);
+    interp.check_pure(f)?;
+    interp.call(f, args)
+}
+
+/// Why the execution of a function stopped early
+enum Interrupt {
+    Error(EvalError),
+    Throw(Value),
+}
+
+impl From<EvalError> for Interrupt {
+    fn from(e: EvalError) -> Self {
+        Interrupt::Error(e)
+    }
+}
+
+/// A native computed in the sandbox
+type Stub = fn(&[Value]) -> Option<Value>;
+
+/// Interpreter state : the globals modified by the code and the limits
+pub struct Interpreter<'a> {
+    code: &'a Bytecode,
+    globals: HashMap<RefGlobal, Value>,
+    stubs: HashMap<&'static str, Stub>,
+    pub max_steps: usize,
+    pub max_depth: usize,
+    steps: usize,
+    depth: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(code: &'a Bytecode) -> Self {
+        Self {
+            code,
+            globals: HashMap::new(),
+            stubs: math_stubs(),
+            max_steps: DEFAULT_MAX_STEPS,
+            max_depth: DEFAULT_MAX_DEPTH,
+            steps: 0,
+            depth: 0,
+        }
+    }
+
+    /// Check every function statically reachable from `f` only calls stubbed natives
+    pub fn check_pure(&self, f: RefFun) -> Result<(), EvalError> {
+        let mut seen = HashSet::new();
+        let mut todo = vec![f];
+        while let Some(f) = todo.pop() {
+            if !seen.insert(f) {
+                continue;
+            }
+            match f.resolve(self.code) {
+                FunPtr::Native(n) => {
+                    let name = format!("{}@{}", n.lib.display(self.code), n.name(self.code));
+                    if !self.stubs.contains_key(name.as_str()) {
+                        return Err(EvalError::Impure(name));
+                    }
+                }
+                FunPtr::Fun(fun) => {
+                    for o in &fun.ops {
+                        match o {
+                            Opcode::Call0 { fun, .. }
+                            | Opcode::Call1 { fun, .. }
+                            | Opcode::Call2 { fun, .. }
+                            | Opcode::Call3 { fun, .. }
+                            | Opcode::Call4 { fun, .. }
+                            | Opcode::CallN { fun, .. }
+                            | Opcode::StaticClosure { fun, .. } => todo.push(*fun),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A Haxe `String`, if the bytecode has the `String` class
+    pub fn string(&self, s: &str) -> Option<Value> {
+        let ty = self.code.types.iter().position(|t| {
+            t.get_type_obj()
+                .map_or(false, |o| o.name.resolve(&self.code.strings) == "String")
+        })?;
+        let units: Vec<u16> = s.encode_utf16().collect();
+        Some(Value::Obj(Rc::new(RefCell::new(Object {
+            ty: RefType(ty),
+            fields: vec![
+                Value::Bytes(Rc::new(RefCell::new(ucs2(s)))),
+                Value::Int(units.len() as i32),
+            ],
+        }))))
+    }
+
+    /// Run a function, natives are not checked beforehand (see [Self::check_pure])
+    pub fn call(&mut self, f: RefFun, args: Vec<Value>) -> Result<Value, EvalError> {
+        match self.call_fun(f, args) {
+            Ok(v) => Ok(v),
+            Err(Interrupt::Error(e)) => Err(e),
+            Err(Interrupt::Throw(v)) => Err(EvalError::Uncaught(v.display(self.code))),
+        }
+    }
+
+    fn call_fun(&mut self, f: RefFun, args: Vec<Value>) -> Result<Value, Interrupt> {
+        let fun = match f.resolve(self.code) {
+            FunPtr::Fun(fun) => fun,
+            FunPtr::Native(n) => {
+                let name = format!("{}@{}", n.lib.display(self.code), n.name(self.code));
+                return self
+                    .stubs
+                    .get(name.as_str())
+                    .and_then(|stub| stub(&args))
+                    .ok_or(Interrupt::Error(EvalError::Impure(name)));
+            }
+        };
+        let nargs = fun.ty(self.code).args.len();
+        if args.len() != nargs {
+            return Err(EvalError::ArgumentCount {
+                findex: f,
+                expected: nargs,
+                got: args.len(),
+            }
+            .into());
+        }
+        if self.depth >= self.max_depth {
+            return Err(EvalError::StackOverflow(self.depth).into());
+        }
+        self.depth += 1;
+        let result = self.run(fun, args);
+        self.depth -= 1;
+        result
+    }
+
+    fn run(&mut self, f: &'a Function, args: Vec<Value>) -> Result<Value, Interrupt> {
+        let code = self.code;
+        let mut regs: Vec<Value> = f.regs.iter().map(|&t| Value::default_of(code, t)).collect();
+        for (i, a) in args.into_iter().enumerate() {
+            regs[i] = a;
+        }
+        // Exception handlers : register receiving the exception and position of the handler
+        let mut traps: Vec<(Reg, usize)> = Vec::new();
+        let mut pc = 0;
+        loop {
+            if self.steps >= self.max_steps {
+                return Err(EvalError::StepLimit(self.steps).into());
+            }
+            self.steps += 1;
+            let o = f.ops.get(pc).ok_or(EvalError::OutOfBounds {
+                findex: f.findex,
+                pos: pc,
+            })?;
+            match self.step(f, pc, o, &mut regs, &mut traps) {
+                Ok(Flow::Next) => pc += 1,
+                Ok(Flow::Jump(offset)) => pc = (pc as JumpOffset + offset + 1) as usize,
+                Ok(Flow::Return(v)) => return Ok(v),
+                Err(Interrupt::Throw(exc)) if !traps.is_empty() => {
+                    let (reg, handler) = traps.pop().unwrap();
+                    regs[reg.0 as usize] = exc;
+                    pc = handler;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn step(
+        &mut self,
+        f: &'a Function,
+        pos: usize,
+        o: &Opcode,
+        regs: &mut [Value],
+        traps: &mut Vec<(Reg, usize)>,
+    ) -> Result<Flow, Interrupt> {
+        let code = self.code;
+        let findex = f.findex;
+        let mismatch = || {
+            Interrupt::Error(EvalError::TypeMismatch {
+                findex,
+                pos,
+                op: o.name(),
+            })
+        };
+        let r = |reg: Reg| regs[reg.0 as usize].clone();
+        let ty = |reg: Reg| f.regtype(reg).resolve(&code.types);
+        let int = |reg: Reg| match regs[reg.0 as usize] {
+            Value::Int(i) => Ok(i),
+            _ => Err(mismatch()),
+        };
+        let obj = |reg: Reg| match &regs[reg.0 as usize] {
+            Value::Obj(o) => Ok(o.clone()),
+            Value::Null => Err(Interrupt::Throw(
+                self.string("Null access").unwrap_or(Value::Null),
+            )),
+            _ => Err(mismatch()),
+        };
+        let bytes = |reg: Reg| match &regs[reg.0 as usize] {
+            Value::Bytes(b) => Ok(b.clone()),
+            _ => Err(mismatch()),
+        };
+        let oob = || Interrupt::Error(EvalError::OutOfBounds { findex, pos });
+
+        let (dst, value) = match o {
+            Opcode::Nop | Opcode::Label => return Ok(Flow::Next),
+            Opcode::Mov { dst, src } => (*dst, r(*src)),
+            Opcode::Int { dst, ptr } => (*dst, Value::Int(ptr.resolve(&code.ints))),
+            Opcode::Float { dst, ptr } => {
+                let v = ptr.resolve(&code.floats);
+                match ty(*dst) {
+                    Type::F32 => (*dst, Value::F32(v as f32)),
+                    _ => (*dst, Value::F64(v)),
+                }
+            }
+            Opcode::Bool { dst, value } => (*dst, Value::Bool(value.0)),
+            Opcode::Bytes { dst, ptr } => {
+                let data = if version::has_bytes(code.version) {
+                    let (data, pos) = code.bytes.as_ref().ok_or_else(mismatch)?;
+                    let start = *pos.get(ptr.0).ok_or_else(oob)?;
+                    let end = pos.get(ptr.0 + 1).copied().unwrap_or(data.len());
+                    data.get(start..end).ok_or_else(oob)?.to_vec()
+                } else {
+                    let mut s = RefString(ptr.0).resolve(&code.strings).as_bytes().to_vec();
+                    s.push(0);
+                    s
+                };
+                (*dst, Value::Bytes(Rc::new(RefCell::new(data))))
+            }
+            Opcode::String { dst, ptr } => {
+                let data = ucs2(ptr.resolve(&code.strings));
+                (*dst, Value::Bytes(Rc::new(RefCell::new(data))))
+            }
+            Opcode::Null { dst } => (*dst, Value::Null),
+            Opcode::Add { dst, a, b }
+            | Opcode::Sub { dst, a, b }
+            | Opcode::Mul { dst, a, b }
+            | Opcode::SDiv { dst, a, b }
+            | Opcode::UDiv { dst, a, b }
+            | Opcode::SMod { dst, a, b }
+            | Opcode::UMod { dst, a, b }
+            | Opcode::Shl { dst, a, b }
+            | Opcode::SShr { dst, a, b }
+            | Opcode::UShr { dst, a, b }
+            | Opcode::And { dst, a, b }
+            | Opcode::Or { dst, a, b }
+            | Opcode::Xor { dst, a, b } => {
+                let v = arith(o, r(*a), r(*b)).map_err(|e| match e {
+                    Arith::DivisionByZero => EvalError::DivisionByZero { findex, pos },
+                    Arith::Mismatch => EvalError::TypeMismatch {
+                        findex,
+                        pos,
+                        op: o.name(),
+                    },
+                })?;
+                (*dst, v)
+            }
+            Opcode::Neg { dst, src } => (
+                *dst,
+                match r(*src) {
+                    Value::Int(v) => Value::Int(v.wrapping_neg()),
+                    Value::I64(v) => Value::I64(v.wrapping_neg()),
+                    Value::F32(v) => Value::F32(-v),
+                    Value::F64(v) => Value::F64(-v),
+                    _ => return Err(mismatch()),
+                },
+            ),
+            Opcode::Not { dst, src } => match r(*src) {
+                Value::Bool(v) => (*dst, Value::Bool(!v)),
+                _ => return Err(mismatch()),
+            },
+            Opcode::Incr { dst } | Opcode::Decr { dst } => {
+                let delta = if matches!(o, Opcode::Incr { .. }) {
+                    1
+                } else {
+                    -1
+                };
+                match r(*dst) {
+                    Value::Int(v) => (*dst, Value::Int(v.wrapping_add(delta))),
+                    Value::I64(v) => (*dst, Value::I64(v.wrapping_add(delta as i64))),
+                    _ => return Err(mismatch()),
+                }
+            }
+            Opcode::Call0 { dst, fun } => (*dst, self.call_fun(*fun, vec![])?),
+            Opcode::Call1 { dst, fun, arg0 } => (*dst, self.call_fun(*fun, vec![r(*arg0)])?),
+            Opcode::Call2 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+            } => (*dst, self.call_fun(*fun, vec![r(*arg0), r(*arg1)])?),
+            Opcode::Call3 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+                arg2,
+            } => (
+                *dst,
+                self.call_fun(*fun, vec![r(*arg0), r(*arg1), r(*arg2)])?,
+            ),
+            Opcode::Call4 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                arg3,
+            } => (
+                *dst,
+                self.call_fun(*fun, vec![r(*arg0), r(*arg1), r(*arg2), r(*arg3)])?,
+            ),
+            Opcode::CallN { dst, fun, args } => {
+                let args = args.iter().map(|&a| r(a)).collect();
+                (*dst, self.call_fun(*fun, args)?)
+            }
+            Opcode::StaticClosure { dst, fun } => (*dst, Value::Fun(*fun)),
+            Opcode::CallClosure { dst, fun, args } => match r(*fun) {
+                Value::Fun(target) => {
+                    let args = args.iter().map(|&a| r(a)).collect();
+                    (*dst, self.call_fun(target, args)?)
+                }
+                _ => return Err(mismatch()),
+            },
+            Opcode::GetGlobal { dst, global } => (*dst, self.global(*global)?),
+            Opcode::SetGlobal { global, src } => {
+                self.globals.insert(*global, r(*src));
+                return Ok(Flow::Next);
+            }
+            Opcode::Field {
+                dst,
+                obj: target,
+                field,
+            } => {
+                let v = obj(*target)?.borrow().fields.get(field.0).cloned();
+                (*dst, v.ok_or_else(oob)?)
+            }
+            Opcode::GetThis { dst, field } => {
+                let v = obj(Reg(0))?.borrow().fields.get(field.0).cloned();
+                (*dst, v.ok_or_else(oob)?)
+            }
+            Opcode::SetField {
+                obj: target,
+                field,
+                src,
+            } => {
+                let target = obj(*target)?;
+                let mut target = target.borrow_mut();
+                *target.fields.get_mut(field.0).ok_or_else(oob)? = r(*src);
+                return Ok(Flow::Next);
+            }
+            Opcode::SetThis { field, src } => {
+                let target = obj(Reg(0))?;
+                let mut target = target.borrow_mut();
+                *target.fields.get_mut(field.0).ok_or_else(oob)? = r(*src);
+                return Ok(Flow::Next);
+            }
+            Opcode::New { dst } => match ty(*dst) {
+                Type::Obj(t) | Type::Struct(t) => {
+                    let fields = t
+                        .fields
+                        .iter()
+                        .map(|f| Value::default_of(code, f.t))
+                        .collect();
+                    let obj = Object {
+                        ty: f.regtype(*dst),
+                        fields,
+                    };
+                    (*dst, Value::Obj(Rc::new(RefCell::new(obj))))
+                }
+                _ => return Err(self.unsupported(findex, pos, o)),
+            },
+            Opcode::JTrue { cond, offset } | Opcode::JFalse { cond, offset } => {
+                let expected = matches!(o, Opcode::JTrue { .. });
+                return match r(*cond) {
+                    Value::Bool(v) if v == expected => Ok(Flow::Jump(*offset)),
+                    Value::Bool(_) => Ok(Flow::Next),
+                    _ => Err(mismatch()),
+                };
+            }
+            Opcode::JNull { reg, offset } | Opcode::JNotNull { reg, offset } => {
+                let expected = matches!(o, Opcode::JNull { .. });
+                let is_null = matches!(r(*reg), Value::Null);
+                return Ok(if is_null == expected {
+                    Flow::Jump(*offset)
+                } else {
+                    Flow::Next
+                });
+            }
+            Opcode::JSLt { a, b, offset }
+            | Opcode::JSGte { a, b, offset }
+            | Opcode::JSGt { a, b, offset }
+            | Opcode::JSLte { a, b, offset }
+            | Opcode::JULt { a, b, offset }
+            | Opcode::JUGte { a, b, offset }
+            | Opcode::JNotLt { a, b, offset }
+            | Opcode::JNotGte { a, b, offset }
+            | Opcode::JEq { a, b, offset }
+            | Opcode::JNotEq { a, b, offset } => {
+                let (a, b) = (r(*a), r(*b));
+                let unsigned = matches!(o, Opcode::JULt { .. } | Opcode::JUGte { .. });
+                let ord = if unsigned {
+                    match (&a, &b) {
+                        (Value::Int(a), Value::Int(b)) => Some((*a as u32).cmp(&(*b as u32))),
+                        (Value::I64(a), Value::I64(b)) => Some((*a as u64).cmp(&(*b as u64))),
+                        _ => return Err(mismatch()),
+                    }
+                } else {
+                    compare(&a, &b)
+                };
+                let taken = match o {
+                    Opcode::JSLt { .. } | Opcode::JULt { .. } => ord == Some(Ordering::Less),
+                    Opcode::JSGte { .. } | Opcode::JUGte { .. } => {
+                        matches!(ord, Some(Ordering::Greater | Ordering::Equal))
+                    }
+                    Opcode::JSGt { .. } => ord == Some(Ordering::Greater),
+                    Opcode::JSLte { .. } => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+                    Opcode::JNotLt { .. } => ord != Some(Ordering::Less),
+                    Opcode::JNotGte { .. } => {
+                        !matches!(ord, Some(Ordering::Greater | Ordering::Equal))
+                    }
+                    Opcode::JEq { .. } => a == b,
+                    _ => a != b,
+                };
+                return Ok(if taken {
+                    Flow::Jump(*offset)
+                } else {
+                    Flow::Next
+                });
+            }
+            Opcode::JAlways { offset } => return Ok(Flow::Jump(*offset)),
+            Opcode::Switch { reg, offsets, end } => {
+                let i = int(*reg)?;
+                return Ok(Flow::Jump(
+                    usize::try_from(i)
+                        .ok()
+                        .and_then(|i| offsets.get(i))
+                        .copied()
+                        .unwrap_or(*end),
+                ));
+            }
+            Opcode::ToDyn { dst, src } | Opcode::UnsafeCast { dst, src } => (*dst, r(*src)),
+            Opcode::SafeCast { dst, src } => match r(*src) {
+                Value::Null => (*dst, Value::default_of(code, f.regtype(*dst))),
+                v => (*dst, v),
+            },
+            Opcode::ToSFloat { dst, src } | Opcode::ToUFloat { dst, src } => {
+                let unsigned = matches!(o, Opcode::ToUFloat { .. });
+                let v = match r(*src) {
+                    Value::Int(v) if unsigned => v as u32 as f64,
+                    Value::I64(v) if unsigned => v as u64 as f64,
+                    Value::Int(v) => v as f64,
+                    Value::I64(v) => v as f64,
+                    Value::F32(v) => v as f64,
+                    Value::F64(v) => v,
+                    _ => return Err(mismatch()),
+                };
+                match ty(*dst) {
+                    Type::F32 => (*dst, Value::F32(v as f32)),
+                    _ => (*dst, Value::F64(v)),
+                }
+            }
+            Opcode::ToInt { dst, src } => {
+                let v = match r(*src) {
+                    Value::Int(v) => v as i64,
+                    Value::I64(v) => v,
+                    Value::F32(v) => v as i64,
+                    Value::F64(v) => v as i64,
+                    _ => return Err(mismatch()),
+                };
+                match ty(*dst) {
+                    Type::I64 => (*dst, Value::I64(v)),
+                    _ => (*dst, Value::Int(v as i32)),
+                }
+            }
+            Opcode::Ret { ret } => return Ok(Flow::Return(r(*ret))),
+            Opcode::Throw { exc } | Opcode::Rethrow { exc } => {
+                return Err(Interrupt::Throw(r(*exc)))
+            }
+            Opcode::NullCheck { reg } => {
+                if matches!(r(*reg), Value::Null) {
+                    return Err(Interrupt::Throw(
+                        self.string("Null access").unwrap_or(Value::Null),
+                    ));
+                }
+                return Ok(Flow::Next);
+            }
+            Opcode::Trap { exc, offset } => {
+                traps.push((*exc, (pos as JumpOffset + offset + 1) as usize));
+                return Ok(Flow::Next);
+            }
+            Opcode::EndTrap { .. } => {
+                traps.pop();
+                return Ok(Flow::Next);
+            }
+            Opcode::GetI8 {
+                dst,
+                bytes: b,
+                index,
+            }
+            | Opcode::GetI16 {
+                dst,
+                bytes: b,
+                index,
+            }
+            | Opcode::GetMem {
+                dst,
+                bytes: b,
+                index,
+            } => {
+                let data = bytes(*b)?;
+                let data = data.borrow();
+                let i = usize::try_from(int(*index)?).map_err(|_| oob())?;
+                let kind = match o {
+                    Opcode::GetI8 { .. } => &Type::UI8,
+                    Opcode::GetI16 { .. } => &Type::UI16,
+                    _ => ty(*dst),
+                };
+                (*dst, read_mem(&data, i, kind).ok_or_else(oob)?)
+            }
+            Opcode::SetI8 {
+                bytes: b,
+                index,
+                src,
+            }
+            | Opcode::SetI16 {
+                bytes: b,
+                index,
+                src,
+            }
+            | Opcode::SetMem {
+                bytes: b,
+                index,
+                src,
+            } => {
+                let data = bytes(*b)?;
+                let mut data = data.borrow_mut();
+                let i = usize::try_from(int(*index)?).map_err(|_| oob())?;
+                let encoded = match (o, r(*src)) {
+                    (Opcode::SetI8 { .. }, Value::Int(v)) => vec![v as u8],
+                    (Opcode::SetI16 { .. }, Value::Int(v)) => (v as u16).to_le_bytes().to_vec(),
+                    (_, Value::Int(v)) => v.to_le_bytes().to_vec(),
+                    (_, Value::I64(v)) => v.to_le_bytes().to_vec(),
+                    (_, Value::F32(v)) => v.to_le_bytes().to_vec(),
+                    (_, Value::F64(v)) => v.to_le_bytes().to_vec(),
+                    _ => return Err(mismatch()),
+                };
+                data.get_mut(i..i + encoded.len())
+                    .ok_or_else(oob)?
+                    .copy_from_slice(&encoded);
+                return Ok(Flow::Next);
+            }
+            Opcode::Type { dst, ty } => (*dst, Value::Type(*ty)),
+            Opcode::GetType { dst, src } => match r(*src) {
+                Value::Obj(v) => (*dst, Value::Type(v.borrow().ty)),
+                _ => return Err(self.unsupported(findex, pos, o)),
+            },
+            _ => return Err(self.unsupported(findex, pos, o)),
+        };
+        regs[dst.0 as usize] = truncate(ty(dst), value);
+        Ok(Flow::Next)
+    }
+
+    fn unsupported(&self, findex: RefFun, pos: usize, o: &Opcode) -> Interrupt {
+        Interrupt::Error(EvalError::Unsupported {
+            findex,
+            pos,
+            op: o.name(),
+        })
+    }
+
+    /// Value of a global, from the constant pool if it hasn't been set by the code
+    fn global(&mut self, g: RefGlobal) -> Result<Value, Interrupt> {
+        if let Some(v) = self.globals.get(&g) {
+            return Ok(v.clone());
+        }
+        let value = self
+            .code
+            .global_value(g)
+            .ok_or(EvalError::UninitializedGlobal(g))?;
+        let v = self.const_value(&value)?;
+        self.globals.insert(g, v.clone());
+        Ok(v)
+    }
+
+    fn const_value(&mut self, value: &ConstValue) -> Result<Value, Interrupt> {
+        Ok(match value {
+            ConstValue::Int(v) => Value::Int(*v),
+            ConstValue::Float(v) => Value::F64(*v),
+            ConstValue::Bool(v) => Value::Bool(*v),
+            ConstValue::Bytes(s) => {
+                Value::Bytes(Rc::new(RefCell::new(ucs2(s.resolve(&self.code.strings)))))
+            }
+            ConstValue::Type(t) => Value::Type(*t),
+            ConstValue::String(s) => self
+                .string(s.resolve(&self.code.strings))
+                .unwrap_or(Value::Null),
+            ConstValue::Obj { ty, fields } => {
+                let obj = ty.resolve_as_obj(&self.code.types);
+                let all = obj.map_or(0, |o| o.fields.len());
+                // Parent fields are not part of the constant
+                let mut values: Vec<Value> = obj
+                    .map(|o| o.fields[..all - fields.len()].to_vec())
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|f| Value::default_of(self.code, f.t))
+                    .collect();
+                for f in fields {
+                    values.push(self.const_value(f)?);
+                }
+                Value::Obj(Rc::new(RefCell::new(Object {
+                    ty: *ty,
+                    fields: values,
+                })))
+            }
+            ConstValue::Global(g) => self.global(*g)?,
+        })
+    }
+}
+
+/// What to do after an instruction
+enum Flow {
+    Next,
+    Jump(JumpOffset),
+    Return(Value),
+}
+
+enum Arith {
+    DivisionByZero,
+    Mismatch,
+}
+
+/// Binary arithmetic and bitwise operations, integers wrap around
+fn arith(o: &Opcode, a: Value, b: Value) -> Result<Value, Arith> {
+    macro_rules! ints {
+        ($a:expr, $b:expr, $variant:ident, $signed:ty, $unsigned:ty, $bits:expr) => {{
+            let (a, b) = ($a, $b);
+            let zero = || {
+                if b == 0 {
+                    Err(Arith::DivisionByZero)
+                } else {
+                    Ok(())
+                }
+            };
+            Value::$variant(match o {
+                Opcode::Add { .. } => a.wrapping_add(b),
+                Opcode::Sub { .. } => a.wrapping_sub(b),
+                Opcode::Mul { .. } => a.wrapping_mul(b),
+                Opcode::SDiv { .. } => zero().map(|_| a.wrapping_div(b))?,
+                Opcode::SMod { .. } => zero().map(|_| a.wrapping_rem(b))?,
+                Opcode::UDiv { .. } => {
+                    zero().map(|_| (a as $unsigned / b as $unsigned) as $signed)?
+                }
+                Opcode::UMod { .. } => {
+                    zero().map(|_| (a as $unsigned % b as $unsigned) as $signed)?
+                }
+                Opcode::Shl { .. } => a.wrapping_shl(b as u32 & ($bits - 1)),
+                Opcode::SShr { .. } => a.wrapping_shr(b as u32 & ($bits - 1)),
+                Opcode::UShr { .. } => ((a as $unsigned) >> (b as u32 & ($bits - 1))) as $signed,
+                Opcode::And { .. } => a & b,
+                Opcode::Or { .. } => a | b,
+                _ => a ^ b,
+            })
+        }};
+    }
+    macro_rules! floats {
+        ($a:expr, $b:expr, $variant:ident) => {{
+            let (a, b) = ($a, $b);
+            Value::$variant(match o {
+                Opcode::Add { .. } => a + b,
+                Opcode::Sub { .. } => a - b,
+                Opcode::Mul { .. } => a * b,
+                Opcode::SDiv { .. } | Opcode::UDiv { .. } => a / b,
+                Opcode::SMod { .. } | Opcode::UMod { .. } => a % b,
+                _ => return Err(Arith::Mismatch),
+            })
+        }};
+    }
+    Ok(match (a, b) {
+        (Value::Int(a), Value::Int(b)) => ints!(a, b, Int, i32, u32, 32),
+        (Value::I64(a), Value::I64(b)) => ints!(a, b, I64, i64, u64, 64),
+        (Value::F32(a), Value::F32(b)) => floats!(a, b, F32),
+        (Value::F64(a), Value::F64(b)) => floats!(a, b, F64),
+        (Value::Bool(a), Value::Bool(b)) => match o {
+            Opcode::And { .. } => Value::Bool(a & b),
+            Opcode::Or { .. } => Value::Bool(a | b),
+            Opcode::Xor { .. } => Value::Bool(a ^ b),
+            _ => return Err(Arith::Mismatch),
+        },
+        _ => return Err(Arith::Mismatch),
+    })
+}
+
+/// Order of two numbers of the same type, `None` for NaN or other values
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::I64(a), Value::I64(b)) => Some(a.cmp(b)),
+        (Value::F32(a), Value::F32(b)) => a.partial_cmp(b),
+        (Value::F64(a), Value::F64(b)) => a.partial_cmp(b),
+        _ => None,
+    }
+}
+
+/// Keep the value in the range of small integer registers
+fn truncate(ty: &Type, v: Value) -> Value {
+    match (ty, v) {
+        (Type::UI8, Value::Int(v)) => Value::Int(v & 0xFF),
+        (Type::UI16, Value::Int(v)) => Value::Int(v & 0xFFFF),
+        (_, v) => v,
+    }
+}
+
+fn read_mem(data: &[u8], i: usize, ty: &Type) -> Option<Value> {
+    let get = |n: usize| data.get(i..i + n);
+    Some(match ty {
+        Type::UI8 => Value::Int(*data.get(i)? as i32),
+        Type::UI16 => Value::Int(u16::from_le_bytes(get(2)?.try_into().ok()?) as i32),
+        Type::I32 => Value::Int(i32::from_le_bytes(get(4)?.try_into().ok()?)),
+        Type::I64 => Value::I64(i64::from_le_bytes(get(8)?.try_into().ok()?)),
+        Type::F32 => Value::F32(f32::from_le_bytes(get(4)?.try_into().ok()?)),
+        Type::F64 => Value::F64(f64::from_le_bytes(get(8)?.try_into().ok()?)),
+        _ => return None,
+    })
+}
+
+/// Nul terminated UTF-16 of a string, like the `String` instruction
+fn ucs2(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .chain([0])
+        .flat_map(|u| u.to_le_bytes())
+        .collect()
+}
+
+/// Math natives of the std library
+fn math_stubs() -> HashMap<&'static str, Stub> {
+    fn f64_arg(args: &[Value], i: usize) -> Option<f64> {
+        match args.get(i)? {
+            Value::F64(v) => Some(*v),
+            Value::F32(v) => Some(*v as f64),
+            Value::Int(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+    let stubs: [(&'static str, Stub); 20] = [
+        ("std@math_sqrt", |a| Some(Value::F64(f64_arg(a, 0)?.sqrt()))),
+        ("std@math_abs", |a| Some(Value::F64(f64_arg(a, 0)?.abs()))),
+        ("std@math_floor", |a| {
+            Some(Value::Int(f64_arg(a, 0)?.floor() as i32))
+        }),
+        ("std@math_ceil", |a| {
+            Some(Value::Int(f64_arg(a, 0)?.ceil() as i32))
+        }),
+        ("std@math_round", |a| {
+            Some(Value::Int((f64_arg(a, 0)? + 0.5).floor() as i32))
+        }),
+        ("std@math_ffloor", |a| {
+            Some(Value::F64(f64_arg(a, 0)?.floor()))
+        }),
+        ("std@math_fceil", |a| {
+            Some(Value::F64(f64_arg(a, 0)?.ceil()))
+        }),
+        ("std@math_fround", |a| {
+            Some(Value::F64((f64_arg(a, 0)? + 0.5).floor()))
+        }),
+        ("std@math_pow", |a| {
+            Some(Value::F64(f64_arg(a, 0)?.powf(f64_arg(a, 1)?)))
+        }),
+        ("std@math_sin", |a| Some(Value::F64(f64_arg(a, 0)?.sin()))),
+        ("std@math_cos", |a| Some(Value::F64(f64_arg(a, 0)?.cos()))),
+        ("std@math_tan", |a| Some(Value::F64(f64_arg(a, 0)?.tan()))),
+        ("std@math_asin", |a| Some(Value::F64(f64_arg(a, 0)?.asin()))),
+        ("std@math_acos", |a| Some(Value::F64(f64_arg(a, 0)?.acos()))),
+        ("std@math_atan", |a| Some(Value::F64(f64_arg(a, 0)?.atan()))),
+        ("std@math_atan2", |a| {
+            Some(Value::F64(f64_arg(a, 0)?.atan2(f64_arg(a, 1)?)))
+        }),
+        ("std@math_exp", |a| Some(Value::F64(f64_arg(a, 0)?.exp()))),
+        ("std@math_log", |a| Some(Value::F64(f64_arg(a, 0)?.ln()))),
+        ("std@math_isnan", |a| {
+            Some(Value::Bool(f64_arg(a, 0)?.is_nan()))
+        }),
+        ("std@math_isfinite", |a| {
+            Some(Value::Bool(f64_arg(a, 0)?.is_finite()))
+        }),
+    ];
+    stubs.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefField, RefFun, RefType, Type, ValBool};
+    use hlbc::Bytecode;
+
+    use crate::eval::{eval, EvalError, Interpreter, Value};
+
+    /// `fact(n)`, `hash(s)` (`h * 31 + c` over the characters), `safe_div(a, b)` catching the exception thrown by
+    /// `check(b)` when `b == 0`, `hypot(a, b)` with `std@math_sqrt` and `now()` calling an unknown native
+    fn program() -> (Bytecode, [RefFun; 5]) {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let f64_ = b.ty(Type::F64);
+        let bytes = b.ty(Type::Bytes);
+        let bool_ = b.ty(Type::Bool);
+        let dyn_ = b.ty(Type::Dyn);
+        let string = b.class("String", None, &[("bytes", bytes), ("length", i32_)], &[]);
+        let int_int = b.fun_type(&[i32_], i32_);
+        let int2_int = b.fun_type(&[i32_, i32_], i32_);
+        let hash_t = b.fun_type(&[string], i32_);
+        let f64_f64 = b.fun_type(&[f64_], f64_);
+        let hypot_t = b.fun_type(&[f64_, f64_], f64_);
+        let now_t = b.fun_type(&[], f64_);
+        let void = RefType(0);
+        let check_t = b.fun_type(&[i32_], void);
+        let zero = b.int(0);
+        let one = b.int(1);
+        let thirty_one = b.int(31);
+        let msg = b.string("zero");
+
+        let sqrt = b.native("std", "math_sqrt", f64_f64);
+        let time = b.native("std", "sys_time", now_t);
+        let [fact, hash, check, safe_div, hypot, now] = [(); 6].map(|_| b.findex());
+
+        let mut f = FunctionBuilder::new(int_int, &[i32_]);
+        let n = f.arg(0);
+        let acc = f.reg(i32_);
+        let tmp = f.reg(i32_);
+        let base = f.label();
+        f.emit(Opcode::Int { dst: tmp, ptr: one });
+        f.jump(
+            Opcode::JSLte {
+                a: n,
+                b: tmp,
+                offset: 0,
+            },
+            base,
+        );
+        f.emit(Opcode::Mov { dst: tmp, src: n });
+        f.emit(Opcode::Decr { dst: tmp });
+        f.emit(Opcode::Call1 {
+            dst: acc,
+            fun: fact,
+            arg0: tmp,
+        });
+        f.emit(Opcode::Mul {
+            dst: acc,
+            a: acc,
+            b: n,
+        });
+        f.emit(Opcode::Ret { ret: acc });
+        f.place(base);
+        f.emit(Opcode::Ret { ret: tmp });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(fact, int_int, regs, ops);
+
+        let mut f = FunctionBuilder::new(hash_t, &[string]);
+        let s = f.arg(0);
+        let [h, i, len, c, k, two] = [(); 6].map(|_| f.reg(i32_));
+        let data = f.reg(bytes);
+        let (head, end) = (f.label(), f.label());
+        f.emit(Opcode::Int { dst: h, ptr: zero });
+        f.emit(Opcode::Int { dst: i, ptr: zero });
+        f.emit(Opcode::Int {
+            dst: k,
+            ptr: thirty_one,
+        });
+        f.emit(Opcode::Field {
+            dst: data,
+            obj: s,
+            field: RefField(0),
+        });
+        f.emit(Opcode::Field {
+            dst: len,
+            obj: s,
+            field: RefField(1),
+        });
+        f.place(head);
+        f.emit(Opcode::Label);
+        f.jump(
+            Opcode::JSGte {
+                a: i,
+                b: len,
+                offset: 0,
+            },
+            end,
+        );
+        f.emit(Opcode::Mov { dst: two, src: i });
+        f.emit(Opcode::Add {
+            dst: two,
+            a: two,
+            b: two,
+        });
+        f.emit(Opcode::GetI16 {
+            dst: c,
+            bytes: data,
+            index: two,
+        });
+        f.emit(Opcode::Mul { dst: h, a: h, b: k });
+        f.emit(Opcode::Add { dst: h, a: h, b: c });
+        f.emit(Opcode::Incr { dst: i });
+        f.jump(Opcode::JAlways { offset: 0 }, head);
+        f.place(end);
+        f.emit(Opcode::Ret { ret: h });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(hash, hash_t, regs, ops);
+
+        let mut f = FunctionBuilder::new(check_t, &[i32_]);
+        let x = f.arg(0);
+        let tmp = f.reg(i32_);
+        let exc = f.reg(bytes);
+        let ret = f.reg(void);
+        let ok = f.label();
+        f.emit(Opcode::Int {
+            dst: tmp,
+            ptr: zero,
+        });
+        f.jump(
+            Opcode::JNotEq {
+                a: x,
+                b: tmp,
+                offset: 0,
+            },
+            ok,
+        );
+        f.emit(Opcode::String { dst: exc, ptr: msg });
+        f.emit(Opcode::Throw { exc });
+        f.place(ok);
+        f.emit(Opcode::Ret { ret });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(check, check_t, regs, ops);
+
+        let mut f = FunctionBuilder::new(int2_int, &[i32_, i32_]);
+        let (a, d) = (f.arg(0), f.arg(1));
+        let r = f.reg(i32_);
+        let exc = f.reg(dyn_);
+        let ret = f.reg(void);
+        let caught = f.label();
+        f.jump(Opcode::Trap { exc, offset: 0 }, caught);
+        f.emit(Opcode::Call1 {
+            dst: ret,
+            fun: check,
+            arg0: d,
+        });
+        f.emit(Opcode::EndTrap { exc });
+        f.emit(Opcode::SDiv { dst: r, a, b: d });
+        f.emit(Opcode::Ret { ret: r });
+        f.place(caught);
+        f.emit(Opcode::Int { dst: r, ptr: zero });
+        f.emit(Opcode::Decr { dst: r });
+        f.emit(Opcode::Ret { ret: r });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(safe_div, int2_int, regs, ops);
+
+        let mut f = FunctionBuilder::new(hypot_t, &[f64_, f64_]);
+        let (x, y) = (f.arg(0), f.arg(1));
+        let r = f.reg(f64_);
+        f.emit(Opcode::Mul { dst: x, a: x, b: x });
+        f.emit(Opcode::Mul { dst: y, a: y, b: y });
+        f.emit(Opcode::Add { dst: r, a: x, b: y });
+        f.emit(Opcode::Call1 {
+            dst: r,
+            fun: sqrt,
+            arg0: r,
+        });
+        f.emit(Opcode::Ret { ret: r });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(hypot, hypot_t, regs, ops);
+
+        let mut f = FunctionBuilder::new(now_t, &[]);
+        let r = f.reg(f64_);
+        let flag = f.reg(bool_);
+        f.emit(Opcode::Bool {
+            dst: flag,
+            value: ValBool(true),
+        });
+        f.emit(Opcode::Call0 { dst: r, fun: time });
+        f.emit(Opcode::Ret { ret: r });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(now, now_t, regs, ops);
+
+        (b.build().unwrap(), [fact, hash, safe_div, hypot, now])
+    }
+
+    #[test]
+    fn pure_functions() {
+        let (code, [fact, hash, safe_div, hypot, _]) = program();
+        assert_eq!(
+            eval(&code, fact, vec![Value::Int(10)]),
+            Ok(Value::Int(3628800))
+        );
+        assert_eq!(
+            eval(&code, hypot, vec![Value::F64(3.0), Value::F64(4.0)]),
+            Ok(Value::F64(5.0))
+        );
+        assert_eq!(
+            eval(&code, safe_div, vec![Value::Int(7), Value::Int(2)]),
+            Ok(Value::Int(3))
+        );
+        assert_eq!(
+            eval(&code, safe_div, vec![Value::Int(7), Value::Int(0)]),
+            Ok(Value::Int(-1))
+        );
+
+        let interp = Interpreter::new(&code);
+        let s = interp.string("abc").unwrap();
+        assert_eq!(s.display(&code), "\"abc\"");
+        let expected = "abc"
+            .chars()
+            .fold(0i32, |h, c| h.wrapping_mul(31).wrapping_add(c as i32));
+        assert_eq!(eval(&code, hash, vec![s]), Ok(Value::Int(expected)));
+    }
+
+    #[test]
+    fn rejected() {
+        let (code, [fact, _, _, _, now]) = program();
+        assert_eq!(
+            eval(&code, now, vec![]),
+            Err(EvalError::Impure("std@sys_time".to_owned()))
+        );
+        assert!(matches!(
+            eval(&code, fact, vec![]),
+            Err(EvalError::ArgumentCount {
+                expected: 1,
+                got: 0,
+                ..
+            })
+        ));
+
+        let mut interp = Interpreter::new(&code);
+        interp.max_depth = 5;
+        assert_eq!(
+            interp.call(fact, vec![Value::Int(10)]),
+            Err(EvalError::StackOverflow(5))
+        );
+    }
+}
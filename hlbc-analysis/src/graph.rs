@@ -0,0 +1,494 @@
+//! Utilities to generate a callgraph and generate dot graphs
+//!
+//! The [Callgraph] of a whole program is built with [Callgraph::new], [call_graph] only follows the calls from a
+//! function up to some depth. Indirect calls are resolved when the closure can be found in the same function or has
+//! been passed as an argument by the caller. [Callgraph::tree] displays the transitive callers or callees of a
+//! function as an indented tree.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use hlbc::analysis::IsFromStd;
+use hlbc::opcodes::Opcode;
+use hlbc::types::{FunPtr, Function, RefField, RefFun, Reg, Type};
+use hlbc::Bytecode;
+pub use petgraph;
+use petgraph::graphmap::DiGraphMap;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences, IntoNodeReferences, NodeIndexable, NodeRef};
+use petgraph::Direction;
+
+use crate::reflection::Reflection;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Call {
+    // Called with Call0, Call1, ...
+    Direct,
+    // Called with CallMethod or CallThis, the method declared by the type of the object
+    Method,
+    // Called a closure
+    Closure,
+    // A closure of the function has been created with StaticClosure or InstanceClosure
+    ClosureCreation,
+    // Reached by name at runtime, see [Reflection](crate::reflection::Reflection)
+    Reflection,
+}
+
+/// Functions and natives linked by their calls. There is a single edge between two functions, with the kind of the
+/// last call found.
+#[derive(Debug, Clone, Default)]
+pub struct Callgraph {
+    pub graph: DiGraphMap<RefFun, Call>,
+}
+
+impl Callgraph {
+    /// Call graph of the whole program, every function and native is a node
+    pub fn new(code: &Bytecode) -> Self {
+        let mut graph = DiGraphMap::new();
+        for f in &code.functions {
+            graph.add_node(f.findex);
+        }
+        for n in &code.natives {
+            graph.add_node(n.findex);
+        }
+        let ctx = RegCtx::new();
+        for f in &code.functions {
+            for (call, fun, _) in find_calls(code, f, &ctx) {
+                // Skip invalid references
+                if graph.contains_node(fun) {
+                    graph.add_edge(f.findex, fun, call);
+                }
+            }
+        }
+        // An actual call is more precise
+        for u in Reflection::new(code).uses {
+            for fun in u.functions {
+                if !graph.contains_edge(u.fun, fun) {
+                    graph.add_edge(u.fun, fun, Call::Reflection);
+                }
+            }
+        }
+        Self { graph }
+    }
+
+    /// Functions calling `f`
+    pub fn callers(&self, f: RefFun) -> impl Iterator<Item = (RefFun, Call)> + '_ {
+        self.graph
+            .edges_directed(f, Direction::Incoming)
+            .map(|(caller, _, call)| (caller, *call))
+    }
+
+    /// Functions called by `f`
+    pub fn callees(&self, f: RefFun) -> impl Iterator<Item = (RefFun, Call)> + '_ {
+        self.graph
+            .edges_directed(f, Direction::Outgoing)
+            .map(|(_, callee, call)| (callee, *call))
+    }
+
+    /// Functions unreachable from the entrypoint, sorted. Functions are reached through static calls, created or
+    /// called closures, method calls (with the methods overriding them in subclasses) and reads of a field a function
+    /// is bound to. Natives aren't listed. Functions used by reflection with a constant name are reached, a function
+    /// only called with a name built at runtime is listed too.
+    pub fn dead(&self, code: &Bytecode) -> Vec<RefFun> {
+        // Methods overriding each method, the whole chain of overrides is followed
+        let mut overrides: HashMap<RefFun, Vec<RefFun>> = HashMap::new();
+        for (t, ty) in code.iter_types() {
+            for p in ty.get_type_obj().into_iter().flat_map(|o| &o.protos) {
+                if let Some((_, parent)) = code.overridden(t, p.name.resolve(&code.strings)) {
+                    overrides.entry(parent).or_default().push(p.findex);
+                }
+            }
+        }
+        // Function bound to a field of the type of a register, declared by the type or a parent
+        let binding = |f: &Function, obj: Reg, field: RefField| {
+            let ty = *f.regs.get(obj.0 as usize)?;
+            std::iter::once(ty)
+                .chain(code.supers(ty))
+                .find_map(|t| t.resolve_as_obj(&code.types)?.bindings.get(&field).copied())
+        };
+
+        let mut reached = HashSet::new();
+        let mut todo = vec![code.entrypoint];
+        while let Some(fun) = todo.pop() {
+            if !reached.insert(fun) {
+                continue;
+            }
+            todo.extend(self.callees(fun).map(|(f, _)| f));
+            todo.extend(overrides.get(&fun).into_iter().flatten().copied());
+            if let Some(FunPtr::Fun(f)) = code.get_fun(fun) {
+                todo.extend(f.ops.iter().filter_map(|o| match *o {
+                    Opcode::Field { obj, field, .. } => binding(f, obj, field),
+                    Opcode::GetThis { field, .. } => binding(f, Reg(0), field),
+                    _ => None,
+                }));
+            }
+        }
+        let mut dead: Vec<RefFun> = code
+            .functions
+            .iter()
+            .map(|f| f.findex)
+            .filter(|f| !reached.contains(f))
+            .collect();
+        dead.sort_unstable();
+        dead
+    }
+
+    /// Generate dot language, see [display_graph]
+    pub fn display<'a>(&'a self, code: &'a Bytecode) -> GraphDisplay<'a> {
+        display_graph(self, code)
+    }
+
+    /// Tree of the transitive callers ([Direction::Incoming]) or callees ([Direction::Outgoing]) of `root`, down to
+    /// `max_depth` levels
+    pub fn tree<'a>(
+        &'a self,
+        code: &'a Bytecode,
+        root: RefFun,
+        direction: Direction,
+        max_depth: usize,
+    ) -> CallTree<'a> {
+        CallTree {
+            graph: self,
+            code,
+            root,
+            direction,
+            max_depth,
+        }
+    }
+}
+
+/// Indented tree of calls, one function per line. A function already in the branch is marked `(cycle)`, a function
+/// already expanded in another branch is marked `(see above)` and not expanded again, a function with more calls past
+/// the maximum depth is marked `(...)`.
+pub struct CallTree<'a> {
+    graph: &'a Callgraph,
+    code: &'a Bytecode,
+    root: RefFun,
+    direction: Direction,
+    max_depth: usize,
+}
+
+impl CallTree<'_> {
+    fn fmt_rec(
+        &self,
+        f: &mut Formatter<'_>,
+        fun: RefFun,
+        path: &mut Vec<RefFun>,
+        expanded: &mut HashSet<RefFun>,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            INDENT.repeat(path.len()),
+            fun.display_id(self.code)
+        )?;
+        let mut next: Vec<RefFun> = self
+            .graph
+            .graph
+            .neighbors_directed(fun, self.direction)
+            .collect();
+        next.sort_unstable();
+        if path.contains(&fun) {
+            return writeln!(f, " (cycle)");
+        } else if next.is_empty() {
+            return writeln!(f);
+        } else if expanded.contains(&fun) {
+            return writeln!(f, " (see above)");
+        } else if path.len() == self.max_depth {
+            return writeln!(f, " (...)");
+        }
+        writeln!(f)?;
+        expanded.insert(fun);
+        path.push(fun);
+        for n in next {
+            self.fmt_rec(f, n, path, expanded)?;
+        }
+        path.pop();
+        Ok(())
+    }
+}
+
+impl Display for CallTree<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.fmt_rec(f, self.root, &mut Vec::new(), &mut HashSet::new())
+    }
+}
+// Function argument number to function ptr
+type RegCtx = HashMap<usize, RefFun>;
+
+pub fn find_calls<'a>(
+    code: &'a Bytecode,
+    f: &'a Function,
+    reg_ctx: &'a RegCtx,
+) -> impl Iterator<Item = (Call, RefFun, RegCtx)> + 'a {
+    macro_rules! build_ctx {
+        ($i:ident; $args:expr) => {{
+            let mut tmp = RegCtx::new();
+            for (p, arg) in $args.into_iter().enumerate() {
+                if matches!(f.regs[arg.0 as usize].resolve(&code.types), Type::Fun(_)) {
+                    if let Some(value) = f
+                        .find_last_closure_assign(code, *arg, $i)
+                        .or_else(|| reg_ctx.get(&(arg.0 as usize)).copied())
+                    {
+                        tmp.insert(p, value);
+                    }
+                }
+            }
+            tmp
+        }};
+    }
+
+    f.ops.iter().enumerate().filter_map(|(i, o)| match o {
+        Opcode::Call0 { fun, .. } => Some((Call::Direct, *fun, RegCtx::new())),
+        Opcode::Call1 { fun, arg0, .. } => Some((Call::Direct, *fun, build_ctx!(i; [arg0]))),
+        Opcode::Call2 {
+            fun, arg0, arg1, ..
+        } => Some((Call::Direct, *fun, build_ctx!(i; [arg0, arg1]))),
+        Opcode::Call3 {
+            fun,
+            arg0,
+            arg1,
+            arg2,
+            ..
+        } => Some((Call::Direct, *fun, build_ctx!(i; [arg0, arg1, arg2]))),
+        Opcode::Call4 {
+            fun,
+            arg0,
+            arg1,
+            arg2,
+            arg3,
+            ..
+        } => Some((Call::Direct, *fun, build_ctx!(i; [arg0, arg1, arg2, arg3]))),
+        Opcode::CallN { fun, args, .. } => Some((Call::Direct, *fun, build_ctx!(i; args))),
+        Opcode::CallClosure { fun, args, .. } => f
+            .find_last_closure_assign(code, *fun, i)
+            .map(|f| (Call::Closure, f, build_ctx!(i; args)))
+            .or_else(|| {
+                reg_ctx
+                    .get(&(fun.0 as usize))
+                    .map(|param| (Call::Closure, *param, build_ctx!(i; args)))
+            }),
+        Opcode::CallMethod { field, args, .. } => f.regs[args[0].0 as usize]
+            .resolve(&code.types)
+            .get_type_obj()
+            .and_then(|o| o.protos.get(field.0))
+            .map(|p| (Call::Method, p.findex, build_ctx!(i; args))),
+        Opcode::CallThis { field, args, .. } => f.regs[0]
+            .resolve(&code.types)
+            .get_type_obj()
+            .and_then(|o| o.protos.get(field.0))
+            .map(|p| (Call::Method, p.findex, build_ctx!(i; args))),
+        Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } => {
+            Some((Call::ClosureCreation, *fun, RegCtx::new()))
+        }
+        _ => None,
+    })
+}
+
+/// Call graph starting from `f`, following calls up to `max_depth`
+pub fn call_graph(code: &Bytecode, f: RefFun, max_depth: usize) -> Callgraph {
+    let mut g = Callgraph::default();
+    match f.resolve(code) {
+        FunPtr::Fun(f) => {
+            g.graph.add_node(f.findex);
+            build_graph_rec(code, &mut g, f, &RegCtx::new(), max_depth);
+        }
+        FunPtr::Native(n) => {
+            g.graph.add_node(n.findex);
+        }
+    }
+    g
+}
+
+fn build_graph_rec(code: &Bytecode, g: &mut Callgraph, f: &Function, ctx: &RegCtx, depth: usize) {
+    if depth == 0 {
+        return;
+    }
+    for (call, fun, ctx) in find_calls(code, f, ctx) {
+        if fun.is_from_std(code) {
+            match fun.resolve(code) {
+                FunPtr::Fun(fun) => {
+                    if !g.graph.contains_node(fun.findex) {
+                        g.graph.add_node(fun.findex);
+                        //println!("call to {} with args: {:?}", fun.display_header(code), ctx);
+                        build_graph_rec(code, g, fun, &ctx, depth - 1);
+                    }
+                    g.graph.add_edge(f.findex, fun.findex, call);
+                }
+                FunPtr::Native(n) => {
+                    if !g.graph.contains_node(n.findex) {
+                        g.graph.add_node(n.findex);
+                    }
+                    g.graph.add_edge(f.findex, n.findex, call);
+                }
+            }
+        }
+    }
+}
+
+static TYPE: [&str; 2] = ["graph", "digraph"];
+static EDGE: [&str; 2] = ["--", "->"];
+static INDENT: &str = "    ";
+
+pub struct GraphDisplay<'a> {
+    g: &'a DiGraphMap<RefFun, Call>,
+    code: &'a Bytecode,
+}
+
+impl Display for GraphDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {{", TYPE[self.g.is_directed() as usize])?;
+
+        writeln!(f, "{}fontname=\"Helvetica,Arial,sans-serif\"", INDENT)?;
+        writeln!(
+            f,
+            "{}node [fontname=\"Helvetica,Arial,sans-serif\" style=filled fillcolor=\"#f8f8f8\"]",
+            INDENT
+        )?;
+        writeln!(
+            f,
+            "{}edge [fontname=\"Helvetica,Arial,sans-serif\"]",
+            INDENT
+        )?;
+
+        // output all labels
+        for node in self.g.node_references() {
+            writeln!(
+                f,
+                "{}{} [ label = \"{}\" fontsize=18 shape=box color=\"#b20400\" fillcolor=\"#edd6d5\" ]",
+                INDENT,
+                self.g.to_index(node.id()),
+                node.weight().display_header(self.code)
+            )?;
+        }
+        // output all edges
+        for edge in self.g.edge_references() {
+            writeln!(
+                f,
+                "{}{} {} {} [ label = \"{}\" ]",
+                INDENT,
+                self.g.to_index(edge.source()),
+                EDGE[self.g.is_directed() as usize],
+                self.g.to_index(edge.target()),
+                match edge.weight() {
+                    Call::Direct => "",
+                    Call::Method => "method",
+                    Call::Closure => "closure",
+                    Call::ClosureCreation => "closure ref",
+                    Call::Reflection => "reflection",
+                }
+            )?;
+        }
+
+        writeln!(f, "}}")?;
+        Ok(())
+    }
+}
+
+/// Generate dot language
+pub fn display_graph<'a>(g: &'a Callgraph, code: &'a Bytecode) -> GraphDisplay<'a> {
+    GraphDisplay { g: &g.graph, code }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{sample, BytecodeBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefField, Reg, Type};
+    use petgraph::Direction;
+
+    use crate::graph::{Call, Callgraph};
+
+    #[test]
+    fn whole_program() {
+        let code = sample();
+        let cg = Callgraph::new(&code);
+        assert_eq!(
+            cg.graph.node_count(),
+            code.functions.len() + code.natives.len()
+        );
+        let main = code.entrypoint;
+        assert_eq!(cg.callers(main).count(), 0);
+        for (callee, call) in cg.callees(main) {
+            assert_eq!(call, Call::Direct);
+            assert!(cg.callers(callee).any(|(f, _)| f == main));
+        }
+        let kinds: Vec<Call> = cg.graph.all_edges().map(|(_, _, c)| *c).collect();
+        for kind in [Call::Method, Call::Closure, Call::ClosureCreation] {
+            assert!(kinds.contains(&kind), "no {kind:?} edge");
+        }
+        assert!(cg.display(&code).to_string().starts_with("digraph {"));
+    }
+
+    #[test]
+    fn tree() {
+        let code = sample();
+        let f: Vec<_> = code.functions.iter().map(|f| f.findex).collect();
+        let mut cg = Callgraph::default();
+        // f0 -> f1 -> f2 -> f1, f0 -> f2
+        cg.graph.add_edge(f[0], f[1], Call::Direct);
+        cg.graph.add_edge(f[1], f[2], Call::Direct);
+        cg.graph.add_edge(f[2], f[1], Call::Direct);
+        cg.graph.add_edge(f[0], f[2], Call::Direct);
+
+        let name = |i: usize| f[i].display_id(&code).to_string();
+        let tree = cg.tree(&code, f[0], Direction::Outgoing, 5).to_string();
+        let lines: Vec<&str> = tree.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], name(0));
+        assert_eq!(lines[3], format!("            {} (cycle)", name(1)));
+        assert_eq!(lines[4], format!("    {} (see above)", name(2)));
+
+        let tree = cg.tree(&code, f[2], Direction::Incoming, 1).to_string();
+        assert_eq!(
+            tree,
+            format!("{}\n    {}\n    {} (...)\n", name(2), name(0), name(1))
+        );
+    }
+
+    #[test]
+    fn dead() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let fun_t = b.fun_type(&[], void);
+        let [main, run, run_sub, bound, unbound, unused] = [(); 6].map(|_| b.findex());
+        let base = b.class("Base", None, &[("f", fun_t)], &[("run", run)]);
+        let sub = b.class("Sub", Some(base), &[], &[("run", run_sub)]);
+        let method_t = b.method_type(&[base], void);
+        // main() { var o = new Base(); o.run(); o.f; }
+        let main_ops = vec![
+            Opcode::New { dst: Reg(0) },
+            Opcode::CallMethod {
+                dst: Reg(1),
+                field: RefField(0),
+                args: vec![Reg(0)],
+            },
+            Opcode::Field {
+                dst: Reg(2),
+                obj: Reg(0),
+                field: RefField(0),
+            },
+            Opcode::Ret { ret: Reg(1) },
+        ];
+        b.function(main, fun_t, vec![base, void, fun_t], main_ops);
+        let ret = || vec![Opcode::Ret { ret: Reg(0) }];
+        b.function(run, method_t, vec![base], ret());
+        let sub_t = b.method_type(&[sub], void);
+        b.function(run_sub, sub_t, vec![sub], ret());
+        for f in [bound, unbound, unused] {
+            b.function(f, fun_t, vec![void], ret());
+        }
+        b.entrypoint(main);
+        let mut code = b.build().unwrap();
+        let mut bind = |ty: hlbc::types::RefType, field, f| {
+            let obj = code.types[ty.0].get_type_obj_mut().unwrap();
+            obj.bindings.insert(RefField(field), f);
+        };
+        bind(base, 0, bound);
+        // Never read, Sub doesn't declare any field
+        bind(sub, 1, unbound);
+
+        let dead = Callgraph::new(&code).dead(&code);
+        assert_eq!(dead, [unbound, unused]);
+    }
+}
@@ -0,0 +1,59 @@
+//! Analyses of [Hashlink](https://hashlink.haxe.org/) bytecode loaded with [hlbc].
+//!
+//! `hlbc` parses, displays and writes bytecode, this crate builds knowledge on top of it : control flow of a function
+//! ([cfg](mod@cfg)), call graph of a program ([graph]) and of its packages ([packages]), cross-references ([xref]),
+//! dataflow ([constprop], [copyprop], [dyntypes], [containers], [slice]), size and complexity ([metrics]), native
+//! dependencies ([natives]), obfuscation detection ([anomaly]), orientation in stripped binaries ([entrypoints],
+//! [summary]), classes used by name at runtime ([reflection]), comparison of versions ([diff]), fast text search
+//! ([search]), evaluation of pure functions ([eval](mod@eval)), export as a SQLite database ([sqlite]) and pointer
+//! maps for live memory tools ([pointers]).
+//! Tagging and naming helpers (`autotag`, `signatures`, `profile`, `database`, `renames`, `symbols`) are behind the `autotag` feature.
+//!
+//! Analyses read a [Bytecode](hlbc::Bytecode) and return plain values owned by the caller. The public items of this
+//! crate follow semver independently of `hlbc`.
+//!
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc_analysis::xref::XrefIndex;
+//!
+//! let code = sample();
+//! let index = XrefIndex::new(&code);
+//! let hello = code.strings.iter().position(|s| s == "Hello").unwrap();
+//! assert!(!index.string(hlbc::types::RefString(hello)).is_empty());
+//! ```
+
+pub mod anomaly;
+#[cfg(feature = "autotag")]
+pub mod autotag;
+pub mod cfg;
+pub mod constprop;
+pub mod containers;
+pub mod copyprop;
+#[cfg(feature = "autotag")]
+pub mod database;
+pub mod diff;
+pub mod dyntypes;
+pub mod entrypoints;
+pub mod eval;
+#[cfg(feature = "graph")]
+pub mod graph;
+pub mod metrics;
+pub mod natives;
+#[cfg(feature = "graph")]
+pub mod packages;
+pub mod pointers;
+#[cfg(feature = "autotag")]
+pub mod profile;
+pub mod reflection;
+#[cfg(feature = "autotag")]
+pub mod renames;
+pub mod search;
+#[cfg(feature = "autotag")]
+pub mod signatures;
+pub mod slice;
+#[cfg(feature = "graph")]
+pub mod sqlite;
+pub mod summary;
+#[cfg(feature = "autotag")]
+pub mod symbols;
+pub mod xref;
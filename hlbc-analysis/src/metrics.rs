@@ -0,0 +1,225 @@
+//! Size and complexity metrics of functions.
+//!
+//! [FunctionMetrics] measures a single function, [ModuleMetrics] aggregates them over a module. Sorting functions by
+//! complexity is a quick way to find the interesting parts of a big game : game logic and state machines are complex,
+//! generated accessors are not.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc_analysis::metrics::{FunctionMetrics, ModuleMetrics};
+//!
+//! let code = sample();
+//! let module = ModuleMetrics::new(&code);
+//! let m = FunctionMetrics::new(&code.functions[0]);
+//! assert!(m.cyclomatic <= module.max_cyclomatic);
+//! ```
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::Function;
+use hlbc::Bytecode;
+
+use crate::cfg::Cfg;
+
+/// Metrics of a single function
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FunctionMetrics {
+    /// Number of instructions
+    pub ops: usize,
+    /// Number of registers, arguments included
+    pub regs: usize,
+    /// Largest number of arguments passed to a call, the object of a method call included
+    pub max_call_arity: usize,
+    /// Depth of the most nested loop, 0 without loops
+    pub loop_depth: usize,
+    /// Cyclomatic complexity, the number of independent paths through the control flow graph
+    pub cyclomatic: usize,
+}
+
+impl FunctionMetrics {
+    pub fn new(f: &Function) -> Self {
+        let cfg = Cfg::new(f);
+        let order = cfg.reverse_postorder();
+
+        // Only the reachable part of the graph
+        let mut reachable = vec![false; cfg.blocks.len()];
+        for &b in &order {
+            reachable[b] = true;
+        }
+        let edges: usize = order
+            .iter()
+            .map(|&b| {
+                cfg.blocks[b]
+                    .successors
+                    .iter()
+                    .filter(|&&(s, _)| reachable[s])
+                    .count()
+            })
+            .sum();
+        let cyclomatic = (edges + 2).saturating_sub(order.len()).max(1);
+
+        let mut depth = vec![0; cfg.blocks.len()];
+        for l in cfg.loops() {
+            for b in l.blocks {
+                depth[b] += 1;
+            }
+        }
+
+        Self {
+            ops: f.ops.len(),
+            regs: f.regs.len(),
+            max_call_arity: f.ops.iter().filter_map(call_arity).max().unwrap_or(0),
+            loop_depth: depth.into_iter().max().unwrap_or(0),
+            cyclomatic,
+        }
+    }
+}
+
+/// Number of arguments of a call instruction
+fn call_arity(o: &Opcode) -> Option<usize> {
+    Some(match o {
+        Opcode::Call0 { .. } => 0,
+        Opcode::Call1 { .. } => 1,
+        Opcode::Call2 { .. } => 2,
+        Opcode::Call3 { .. } => 3,
+        Opcode::Call4 { .. } => 4,
+        Opcode::CallN { args, .. }
+        | Opcode::CallMethod { args, .. }
+        | Opcode::CallClosure { args, .. } => args.len(),
+        // The object is implicit
+        Opcode::CallThis { args, .. } => args.len() + 1,
+        _ => return None,
+    })
+}
+
+/// Totals and maximums of the metrics of every function of a module
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ModuleMetrics {
+    pub functions: usize,
+    pub ops: usize,
+    pub regs: usize,
+    /// Sum of the cyclomatic complexity of every function
+    pub cyclomatic: usize,
+    pub max_ops: usize,
+    pub max_regs: usize,
+    pub max_call_arity: usize,
+    pub max_loop_depth: usize,
+    pub max_cyclomatic: usize,
+}
+
+impl ModuleMetrics {
+    pub fn new(code: &Bytecode) -> Self {
+        code.functions.iter().map(FunctionMetrics::new).collect()
+    }
+
+    pub fn add(&mut self, m: &FunctionMetrics) {
+        self.functions += 1;
+        self.ops += m.ops;
+        self.regs += m.regs;
+        self.cyclomatic += m.cyclomatic;
+        self.max_ops = self.max_ops.max(m.ops);
+        self.max_regs = self.max_regs.max(m.regs);
+        self.max_call_arity = self.max_call_arity.max(m.max_call_arity);
+        self.max_loop_depth = self.max_loop_depth.max(m.loop_depth);
+        self.max_cyclomatic = self.max_cyclomatic.max(m.cyclomatic);
+    }
+
+    /// Average cyclomatic complexity of a function
+    pub fn mean_cyclomatic(&self) -> f64 {
+        if self.functions == 0 {
+            0.0
+        } else {
+            self.cyclomatic as f64 / self.functions as f64
+        }
+    }
+}
+
+impl FromIterator<FunctionMetrics> for ModuleMetrics {
+    fn from_iter<T: IntoIterator<Item = FunctionMetrics>>(iter: T) -> Self {
+        let mut module = Self::default();
+        for m in iter {
+            module.add(&m);
+        }
+        module
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Function, Reg, Type};
+
+    use crate::metrics::{FunctionMetrics, ModuleMetrics};
+
+    /// ```text
+    /// for i in 0...n
+    ///     for j in 0...n
+    ///         if i < j
+    ///             f(i, j)
+    /// ```
+    fn nested_loops() -> Function {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let i32_ = b.ty(Type::I32);
+        let callee_t = b.fun_type(&[i32_, i32_], void);
+        let ty = b.fun_type(&[i32_], void);
+        let zero = b.int(0);
+        let callee = b.findex();
+        let findex = b.findex();
+        let mut f = FunctionBuilder::new(ty, &[i32_]);
+        let n = f.arg(0);
+        let [i, j] = [(); 2].map(|_| f.reg(i32_));
+        let ret = f.reg(void);
+        let [outer, inner, skip, next, end] = [(); 5].map(|_| f.label());
+        let gte = |a, b| Opcode::JSGte { a, b, offset: 0 };
+        f.emit(Opcode::Int { dst: i, ptr: zero });
+        f.place(outer);
+        f.jump(gte(i, n), end);
+        f.emit(Opcode::Int { dst: j, ptr: zero });
+        f.place(inner);
+        f.jump(gte(j, n), next);
+        f.jump(gte(i, j), skip);
+        f.emit(Opcode::Call2 {
+            dst: ret,
+            fun: callee,
+            arg0: i,
+            arg1: j,
+        });
+        f.place(skip);
+        f.emit(Opcode::Incr { dst: j });
+        f.jump(Opcode::JAlways { offset: 0 }, inner);
+        f.place(next);
+        f.emit(Opcode::Incr { dst: i });
+        f.jump(Opcode::JAlways { offset: 0 }, outer);
+        f.place(end);
+        f.emit(Opcode::Ret { ret });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(findex, ty, regs, ops);
+        let body = vec![Opcode::Ret { ret: Reg(2) }];
+        b.function(callee, callee_t, vec![i32_, i32_, void], body);
+        b.build().unwrap().functions.remove(0)
+    }
+
+    #[test]
+    fn metrics() {
+        let f = nested_loops();
+        let m = FunctionMetrics::new(&f);
+        assert_eq!(
+            m,
+            FunctionMetrics {
+                ops: f.ops.len(),
+                regs: 4,
+                max_call_arity: 2,
+                loop_depth: 2,
+                // 3 conditions
+                cyclomatic: 4,
+            }
+        );
+
+        let module: ModuleMetrics = [m.clone(), FunctionMetrics::default()]
+            .into_iter()
+            .collect();
+        assert_eq!(module.functions, 2);
+        assert_eq!(module.max_loop_depth, 2);
+        assert_eq!(module.mean_cyclomatic(), 2.0);
+    }
+}
@@ -0,0 +1,111 @@
+//! Native libraries a program depends on, with the functions calling each native.
+//!
+//! Natives are grouped by their library (`std`, `ssl`, `uv`, a custom plugin ...), which tells at a glance what the
+//! runtime must provide to run the program.
+
+use std::collections::BTreeMap;
+
+use hlbc::types::{FunPtr, RefFun, RefType};
+use hlbc::Bytecode;
+
+/// A library of natives
+#[derive(Debug, Clone)]
+pub struct NativeLib {
+    pub name: String,
+    /// Natives of the library, sorted by name
+    pub natives: Vec<NativeUsage>,
+}
+
+impl NativeLib {
+    /// Functions calling any native of the library, sorted and without duplicates
+    pub fn callers(&self) -> Vec<RefFun> {
+        let mut callers: Vec<RefFun> = self
+            .natives
+            .iter()
+            .flat_map(|n| n.callers.iter().copied())
+            .collect();
+        callers.sort_unstable();
+        callers.dedup();
+        callers
+    }
+}
+
+/// A native with its resolved signature
+#[derive(Debug, Clone)]
+pub struct NativeUsage {
+    pub findex: RefFun,
+    pub name: String,
+    pub args: Vec<RefType>,
+    pub ret: RefType,
+    /// Functions calling the native or creating a closure of it, sorted
+    pub callers: Vec<RefFun>,
+}
+
+impl NativeUsage {
+    /// The signature, like `(bytes, i32) -> void`
+    pub fn signature(&self, code: &Bytecode) -> String {
+        let args: Vec<String> = self.args.iter().map(|a| a.display(code)).collect();
+        format!("({}) -> {}", args.join(", "), self.ret.display(code))
+    }
+}
+
+/// Natives of the program grouped by library, sorted by name
+pub fn native_libs(code: &Bytecode) -> Vec<NativeLib> {
+    let mut callers: BTreeMap<RefFun, Vec<RefFun>> = BTreeMap::new();
+    for f in &code.functions {
+        for (_, _, fun) in f.find_fun_refs() {
+            if let Some(FunPtr::Native(_)) = code.get_fun(fun) {
+                let callers = callers.entry(fun).or_default();
+                if callers.last() != Some(&f.findex) {
+                    callers.push(f.findex);
+                }
+            }
+        }
+    }
+
+    let mut libs: BTreeMap<&str, Vec<NativeUsage>> = BTreeMap::new();
+    for n in &code.natives {
+        let ty = n.ty(code);
+        let mut callers = callers.remove(&n.findex).unwrap_or_default();
+        callers.sort_unstable();
+        libs.entry(n.lib.resolve(&code.strings))
+            .or_default()
+            .push(NativeUsage {
+                findex: n.findex,
+                name: n.name(code).to_owned(),
+                args: ty.args.clone(),
+                ret: ty.ret,
+                callers,
+            });
+    }
+    libs.into_iter()
+        .map(|(name, mut natives)| {
+            natives.sort_by(|a, b| a.name.cmp(&b.name));
+            NativeLib {
+                name: name.to_owned(),
+                natives,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+
+    use crate::natives::native_libs;
+
+    #[test]
+    fn libs() {
+        let code = sample();
+        let libs = native_libs(&code);
+        assert_eq!(libs.len(), 1);
+        assert_eq!(libs[0].name, "std");
+        let names: Vec<&str> = libs[0].natives.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, ["bytes_blit", "sys_print", "sys_time", "ucs2_upper"]);
+        let print = &libs[0].natives[1];
+        assert_eq!(print.signature(&code), "(bytes) -> void");
+        assert!(!print.callers.is_empty());
+        assert!(libs[0].callers().len() <= code.functions.len());
+    }
+}
@@ -0,0 +1,277 @@
+//! Dependencies between packages, aggregated from the call graph.
+//!
+//! A function belongs to the package of its class (`game.entity` for `game.entity.Boss`), a closure to the package of
+//! the function creating it. A package depends on another when one of its functions calls a function of the other.
+//! [PackageGraph::layers] orders the packages from the ones depending on nothing, [PackageGraph::cycles] finds the
+//! packages depending on each other, where the layering is broken.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc_analysis::graph::Callgraph;
+//! use hlbc_analysis::packages::PackageGraph;
+//!
+//! let code = sample();
+//! let packages = PackageGraph::new(&code, &Callgraph::new(&code), true);
+//! assert!(packages.cycles().is_empty());
+//! assert!(packages.to_dot().starts_with("digraph {"));
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use hlbc::analysis::IsFromStd;
+use hlbc::types::{Function, RefFun};
+use hlbc::Bytecode;
+use petgraph::algo::tarjan_scc;
+use petgraph::graphmap::DiGraphMap;
+use petgraph::Direction;
+
+use crate::graph::{Call, Callgraph};
+
+/// Packages linked by the calls between their functions
+#[derive(Debug, Clone, Default)]
+pub struct PackageGraph {
+    /// Names of the packages, sorted. The top level package is the empty string.
+    pub packages: Vec<String>,
+    /// Edges between indexes in `packages`, weighted by the number of pairs of functions calling each other
+    pub graph: DiGraphMap<usize, usize>,
+}
+
+/// Package of the class of a function, `None` for a function without a class
+pub fn package<'a>(code: &'a Bytecode, f: &Function) -> Option<&'a str> {
+    let name = f
+        .parent?
+        .resolve_as_obj(&code.types)?
+        .name
+        .resolve(&code.strings);
+    let name = name.strip_prefix('$').unwrap_or(name);
+    Some(name.rsplit_once('.').map_or("", |(package, _)| package))
+}
+
+impl PackageGraph {
+    /// Aggregate the call graph. Functions of the standard library are only counted with `std`, natives and
+    /// functions without a class or a creator never are.
+    pub fn new(code: &Bytecode, callgraph: &Callgraph, std: bool) -> Self {
+        let mut of: HashMap<RefFun, &str> = code
+            .functions
+            .iter()
+            .filter(|f| std || !f.is_from_std(code))
+            .filter_map(|f| Some((f.findex, package(code, f)?)))
+            .collect();
+        // Closures in closures need several passes
+        loop {
+            let mut changed = false;
+            for f in &code.functions {
+                if of.contains_key(&f.findex) || (!std && f.is_from_std(code)) {
+                    continue;
+                }
+                let creator = callgraph
+                    .callers(f.findex)
+                    .filter(|&(_, call)| call == Call::ClosureCreation)
+                    .find_map(|(c, _)| of.get(&c).copied());
+                if let Some(p) = creator {
+                    of.insert(f.findex, p);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let packages: Vec<String> = of
+            .values()
+            .copied()
+            .collect::<BTreeSet<&str>>()
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        let index = |p: &str| packages.binary_search_by(|q| q.as_str().cmp(p)).unwrap();
+        let mut graph = DiGraphMap::new();
+        for i in 0..packages.len() {
+            graph.add_node(i);
+        }
+        for (caller, callee, _) in callgraph.graph.all_edges() {
+            if let (Some(a), Some(b)) = (of.get(&caller), of.get(&callee)) {
+                let (a, b) = (index(a), index(b));
+                if a == b {
+                    continue;
+                }
+                match graph.edge_weight_mut(a, b) {
+                    Some(n) => *n += 1,
+                    None => {
+                        graph.add_edge(a, b, 1);
+                    }
+                }
+            }
+        }
+        Self { packages, graph }
+    }
+
+    /// Packages used by a package with the number of calls, sorted
+    pub fn dependencies(&self, p: usize) -> Vec<(usize, usize)> {
+        let mut deps: Vec<(usize, usize)> = self
+            .graph
+            .edges_directed(p, Direction::Outgoing)
+            .map(|(_, dep, &n)| (dep, n))
+            .collect();
+        deps.sort_unstable();
+        deps
+    }
+
+    /// Groups of packages depending on each other, each sorted
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        let mut cycles: Vec<Vec<usize>> = tarjan_scc(&self.graph)
+            .into_iter()
+            .filter(|scc| scc.len() > 1)
+            .map(|mut scc| {
+                scc.sort_unstable();
+                scc
+            })
+            .collect();
+        cycles.sort_unstable();
+        cycles
+    }
+
+    /// Layer of each package : 0 when it depends on no other package, else one more than its highest dependency.
+    /// Packages in a cycle share a layer.
+    pub fn layers(&self) -> Vec<usize> {
+        let mut layers = vec![0; self.packages.len()];
+        // Dependencies come first
+        for scc in tarjan_scc(&self.graph) {
+            let layer = scc
+                .iter()
+                .flat_map(|&p| self.graph.neighbors_directed(p, Direction::Outgoing))
+                .filter(|dep| !scc.contains(dep))
+                .map(|dep| layers[dep] + 1)
+                .max()
+                .unwrap_or(0);
+            for p in scc {
+                layers[p] = layer;
+            }
+        }
+        layers
+    }
+
+    fn name(&self, p: usize) -> &str {
+        match self.packages[p].as_str() {
+            "" => "(top level)",
+            name => name,
+        }
+    }
+
+    /// Report of the packages by layer with their dependencies, then the cycles
+    pub fn to_text(&self) -> String {
+        let cycles = self.cycles();
+        let mut out = format!(
+            "{} packages, {} dependencies, {} cycles\n",
+            self.packages.len(),
+            self.graph.edge_count(),
+            cycles.len()
+        );
+        let layers = self.layers();
+        for layer in 0..=layers.iter().copied().max().unwrap_or(0) {
+            let _ = writeln!(out, "layer {layer}");
+            for p in (0..self.packages.len()).filter(|&p| layers[p] == layer) {
+                let _ = writeln!(out, "    {}", self.name(p));
+                for (dep, n) in self.dependencies(p) {
+                    let _ = writeln!(out, "        -> {} ({n})", self.name(dep));
+                }
+            }
+        }
+        for cycle in cycles {
+            let names: Vec<&str> = cycle.iter().map(|&p| self.name(p)).collect();
+            let _ = writeln!(out, "cycle : {}", names.join(", "));
+        }
+        out
+    }
+
+    /// Dot graph of the packages, the dependencies in a cycle are red
+    pub fn to_dot(&self) -> String {
+        let mut scc = vec![0; self.packages.len()];
+        for (i, group) in tarjan_scc(&self.graph).into_iter().enumerate() {
+            for p in group {
+                scc[p] = i;
+            }
+        }
+        let mut out = String::from("digraph {\n    node [shape=box]\n");
+        for p in 0..self.packages.len() {
+            let _ = writeln!(out, "    {p} [ label = \"{}\" ]", self.name(p));
+        }
+        for (a, b, n) in self.graph.all_edges() {
+            let color = if scc[a] == scc[b] { " color=red" } else { "" };
+            let _ = writeln!(out, "    {a} -> {b} [ label = \"{n}\"{color} ]");
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::BytecodeBuilder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+
+    use crate::graph::Callgraph;
+    use crate::packages::PackageGraph;
+
+    #[test]
+    fn packages() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let fun_t = b.fun_type(&[], void);
+        let [main, ui, ui_closure, entity, data, data_back] = [(); 6].map(|_| b.findex());
+        b.class("$Main", None, &[], &[("main", main)]);
+        b.class("$game.ui.Hud", None, &[], &[("draw", ui)]);
+        b.class("$game.entity.Boss", None, &[], &[("update", entity)]);
+        b.class(
+            "$game.data.Items",
+            None,
+            &[],
+            &[("get", data), ("notify", data_back)],
+        );
+        let call = |fun| Opcode::Call0 { dst: Reg(0), fun };
+        let ret = Opcode::Ret { ret: Reg(0) };
+        b.function(
+            main,
+            fun_t,
+            vec![void],
+            vec![call(ui), call(entity), ret.clone()],
+        );
+        // The closure of the hud calls the entities, data calls back into the entities
+        let closure = Opcode::StaticClosure {
+            dst: Reg(1),
+            fun: ui_closure,
+        };
+        let ops = vec![closure, ret.clone()];
+        b.function(ui, fun_t, vec![void, fun_t], ops);
+        b.function(
+            ui_closure,
+            fun_t,
+            vec![void],
+            vec![call(entity), ret.clone()],
+        );
+        b.function(entity, fun_t, vec![void], vec![call(data), ret.clone()]);
+        b.function(data, fun_t, vec![void], vec![call(data_back), ret.clone()]);
+        b.function(data_back, fun_t, vec![void], vec![call(entity), ret]);
+        b.entrypoint(main);
+        let code = b.build().unwrap();
+
+        let packages = PackageGraph::new(&code, &Callgraph::new(&code), true);
+        assert_eq!(
+            packages.packages,
+            ["", "game.data", "game.entity", "game.ui"]
+        );
+        assert_eq!(packages.dependencies(0), [(2, 1), (3, 1)]);
+        assert_eq!(packages.dependencies(3), [(2, 1)]);
+        assert_eq!(packages.cycles(), [vec![1, 2]]);
+        assert_eq!(packages.layers(), [2, 0, 0, 1]);
+        let text = packages.to_text();
+        assert!(text.starts_with("4 packages, 5 dependencies, 1 cycles\n"));
+        assert!(text.contains("    (top level)\n        -> game.entity (1)\n"));
+        assert!(text.ends_with("cycle : game.data, game.entity\n"));
+        assert!(packages
+            .to_dot()
+            .contains("1 -> 2 [ label = \"1\" color=red ]"));
+    }
+}
@@ -0,0 +1,308 @@
+//! Pointer maps from the globals of the VM to the static variables and singletons of a program, for live memory
+//! tools.
+//!
+//! The statics of a class live in an object referenced by a global, see [boot_sequence](crate::entrypoints). From
+//! the offsets computed by [hlbc::layout], each static variable is reached by reading the global then adding the
+//! offset of the field, and the fields of the objects they reference by following more pointers. The address of the
+//! globals area is only known at runtime (`globals_data` of the module in the JIT, static variables with HL/C) : in
+//! Cheat Engine, register it as the symbol [GLOBALS_SYMBOL] and load the table made by [PointerMap::to_cheat_table].
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc_analysis::pointers::{PointerMap, PointerOptions};
+//!
+//! let code = sample();
+//! let map = PointerMap::new(&code, &PointerOptions::default());
+//! assert!(map.to_json(&code).starts_with('{'));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use hlbc::analysis::IsFromStd;
+use hlbc::types::{RefGlobal, RefType, Type};
+use hlbc::Bytecode;
+
+/// Name of the symbol the cheat table uses for the address of the globals area
+pub const GLOBALS_SYMBOL: &str = "hl_globals";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PointerOptions {
+    /// Objects to go through after the statics of a class. 0 only lists the static variables, 1 the fields of the
+    /// objects they reference (singletons) ...
+    pub depth: usize,
+    /// Include the classes of the standard library
+    pub std: bool,
+}
+
+impl Default for PointerOptions {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            std: false,
+        }
+    }
+}
+
+/// How to reach a value from the globals area
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Pointer {
+    /// Class and fields followed, e.g. `Game.instance.hp`
+    pub path: String,
+    /// Global holding the statics of the class
+    pub global: RefGlobal,
+    /// Offset of the global in the globals area
+    pub global_offset: usize,
+    /// Offset of each field followed, the first one in the object referenced by the global. Every offset but the
+    /// last is applied to a pointer read from memory.
+    pub offsets: Vec<usize>,
+    /// Type of the value
+    pub ty: RefType,
+}
+
+/// Every [Pointer] of a program, sorted by path
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PointerMap {
+    pub pointers: Vec<Pointer>,
+}
+
+impl PointerMap {
+    pub fn new(code: &Bytecode, opts: &PointerOptions) -> Self {
+        let globals = code.globals_layout();
+        let mut layouts = HashMap::new();
+        let mut pointers = Vec::new();
+        for (_, ty) in code.iter_types() {
+            let obj = match ty.get_type_obj() {
+                Some(obj) if obj.global.0 > 0 && (opts.std || !obj.is_from_std(code)) => obj,
+                _ => continue,
+            };
+            // Offset by one, 0 is no global
+            let global = RefGlobal(obj.global.0 - 1);
+            let (global_offset, statics) = match (globals.get(global.0), code.globals.get(global.0))
+            {
+                (Some(&offset), Some(&statics)) => (offset, statics),
+                _ => continue,
+            };
+            let root = Pointer {
+                path: obj.name.resolve(&code.strings).to_owned(),
+                global,
+                global_offset,
+                offsets: Vec::new(),
+                ty: statics,
+            };
+            walk(
+                code,
+                opts,
+                &mut layouts,
+                &root,
+                opts.depth + 1,
+                &mut pointers,
+            );
+        }
+        pointers.sort_by(|a, b| a.path.cmp(&b.path));
+        Self { pointers }
+    }
+
+    /// Cheat Engine table (`.CT`), one entry per pointer with the type of the value
+    pub fn to_cheat_table(&self, code: &Bytecode) -> String {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<CheatTable CheatEngineTableVersion=\"42\">\n  <CheatEntries>\n",
+        );
+        for (id, p) in self.pointers.iter().enumerate() {
+            let ty = code.get_type(p.ty);
+            let _ = write!(
+                out,
+                "    <CheatEntry>\n      <ID>{id}</ID>\n      <Description>\"{}\"</Description>\n",
+                xml_escape(&p.path)
+            );
+            if !ty.map_or(false, is_value) {
+                out.push_str("      <ShowAsHex>1</ShowAsHex>\n");
+            }
+            let _ = write!(
+                out,
+                "      <VariableType>{}</VariableType>\n      <Address>{GLOBALS_SYMBOL}+{:X}</Address>\n      <Offsets>\n",
+                ty.map_or("8 Bytes", variable_type),
+                p.global_offset
+            );
+            // Cheat Engine lists the offsets from the last applied
+            for o in p.offsets.iter().rev() {
+                let _ = writeln!(out, "        <Offset>{o:X}</Offset>");
+            }
+            out.push_str("      </Offsets>\n    </CheatEntry>\n");
+        }
+        out.push_str("  </CheatEntries>\n</CheatTable>\n");
+        out
+    }
+
+    /// JSON object with the pointer size and the pointers, offsets in bytes
+    pub fn to_json(&self, code: &Bytecode) -> String {
+        let mut out = format!(
+            "{{\n  \"pointer_size\": {},\n  \"pointers\": [",
+            hlbc::layout::POINTER_SIZE
+        );
+        for (i, p) in self.pointers.iter().enumerate() {
+            let offsets: Vec<String> = p.offsets.iter().map(|o| o.to_string()).collect();
+            let _ = write!(
+                out,
+                "{}\n    {{\"path\": {}, \"global\": {}, \"global_offset\": {}, \"offsets\": [{}], \"type\": {}, \"size\": {}}}",
+                if i == 0 { "" } else { "," },
+                json_string(&p.path),
+                p.global.0,
+                p.global_offset,
+                offsets.join(", "),
+                json_string(&p.ty.display(code).to_string()),
+                code.type_size(p.ty)
+            );
+        }
+        out.push_str("\n  ]\n}\n");
+        out
+    }
+}
+
+/// Add the fields of the object `parent` points to, and the fields of the objects they reference up to `depth`
+fn walk(
+    code: &Bytecode,
+    opts: &PointerOptions,
+    layouts: &mut HashMap<RefType, Option<hlbc::layout::ObjLayout>>,
+    parent: &Pointer,
+    depth: usize,
+    pointers: &mut Vec<Pointer>,
+) {
+    if depth == 0 {
+        return;
+    }
+    // The objects of the standard library are only walked with `std`
+    let from_std = |ty| {
+        code.get_type(ty)
+            .and_then(Type::get_type_obj)
+            .map_or(false, |o| o.is_from_std(code))
+    };
+    if !parent.offsets.is_empty() && !opts.std && from_std(parent.ty) {
+        return;
+    }
+    let layout = layouts
+        .entry(parent.ty)
+        .or_insert_with(|| code.obj_layout(parent.ty))
+        .clone();
+    for f in layout.iter().flat_map(|l| &l.fields) {
+        let mut offsets = parent.offsets.clone();
+        offsets.push(f.offset);
+        let p = Pointer {
+            path: format!("{}.{}", parent.path, f.name.resolve(&code.strings)),
+            global: parent.global,
+            global_offset: parent.global_offset,
+            offsets,
+            ty: f.ty,
+        };
+        walk(code, opts, layouts, &p, depth - 1, pointers);
+        pointers.push(p);
+    }
+}
+
+/// Values stored in place, shown as decimal. The others are pointers.
+fn is_value(ty: &Type) -> bool {
+    matches!(
+        ty,
+        Type::UI8 | Type::UI16 | Type::I32 | Type::I64 | Type::F32 | Type::F64 | Type::Bool
+    )
+}
+
+fn variable_type(ty: &Type) -> &'static str {
+    match ty {
+        Type::UI8 | Type::Bool => "Byte",
+        Type::UI16 => "2 Bytes",
+        Type::I32 => "4 Bytes",
+        Type::F32 => "Float",
+        Type::F64 => "Double",
+        _ => "8 Bytes",
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::BytecodeBuilder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefGlobal, RefType, Reg, Type};
+
+    use crate::pointers::{PointerMap, PointerOptions};
+
+    #[test]
+    fn pointer_map() {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let f64_ = b.ty(Type::F64);
+        let player = b.class("Player", None, &[("hp", i32_), ("speed", f64_)], &[]);
+        let statics = b.class("$Game", None, &[("level", i32_), ("player", player)], &[]);
+        let game = b.class("Game", None, &[], &[]);
+        b.global(i32_);
+        let g_statics = b.global(statics);
+        let main_t = b.fun_type(&[], RefType(0));
+        let main = b.findex();
+        b.function(
+            main,
+            main_t,
+            vec![RefType(0)],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        let mut code = b.build().unwrap();
+        if let Type::Obj(obj) = &mut code.types[game.0] {
+            obj.global = RefGlobal(g_statics.0 + 1);
+        }
+
+        let map = PointerMap::new(&code, &PointerOptions::default());
+        let pointers: Vec<(&str, usize, &[usize])> = map
+            .pointers
+            .iter()
+            .map(|p| (p.path.as_str(), p.global_offset, &p.offsets[..]))
+            .collect();
+        assert_eq!(
+            pointers,
+            [
+                ("Game.level", 8, &[8][..]),
+                ("Game.player", 8, &[16]),
+                ("Game.player.hp", 8, &[16, 8]),
+                ("Game.player.speed", 8, &[16, 16]),
+            ]
+        );
+
+        let statics_only = PointerMap::new(
+            &code,
+            &PointerOptions {
+                depth: 0,
+                ..PointerOptions::default()
+            },
+        );
+        assert_eq!(statics_only.pointers.len(), 2);
+
+        let table = map.to_cheat_table(&code);
+        assert!(table.contains(
+            "<Description>\"Game.player.speed\"</Description>\n      <VariableType>Double</VariableType>\n      \
+             <Address>hl_globals+8</Address>\n      <Offsets>\n        <Offset>10</Offset>\n        <Offset>10</Offset>"
+        ));
+        let json = map.to_json(&code);
+        assert!(json.contains("{\"path\": \"Game.player.hp\", \"global\": 1, \"global_offset\": 8, \"offsets\": [16, 8], \"type\": \"i32\", \"size\": 4}"));
+    }
+}
@@ -0,0 +1,402 @@
+//! Game specific profiles, bundling knowledge about a particular game.
+//!
+//! A profile is a TOML file shared by the community :
+//! ```toml
+//! name = "deadcells"
+//! description = "Dead Cells (Steam build)"
+//!
+//! # Auto-tagging rules, same format as the autotag module
+//! [[rule]]
+//! tag = "save"
+//! strings = ["save.dat"]
+//!
+//! # Code idioms, functions containing this sequence of opcodes are tagged with the idiom name
+//! [[idiom]]
+//! name = "rng"
+//! description = "Custom xorshift random number generator"
+//! opcodes = ["Shl", "Xor", "UShr", "Xor"]
+//!
+//! # Known names of functions and types, by index
+//! [names.functions]
+//! 1234 = "computeDamage"
+//! [names.types]
+//! 56 = "ItemData"
+//! # Fields by type index and field index (including inherited fields), locals by findex and register
+//! [names.fields]
+//! "56.3" = "maxStack"
+//! [names.locals]
+//! "1234.5" = "armor"
+//!
+//! # Where to find interesting data
+//! [[hint]]
+//! name = "items"
+//! description = "Item table, loaded from res.pak at startup"
+//! types = ["ItemData"]
+//! ```
+
+use std::collections::BTreeMap;
+
+use hlbc::analysis::tags::Tags;
+use hlbc::types::{RefFunKnown, RefType, Reg, Type};
+use hlbc::Bytecode;
+use serde::{Deserialize, Serialize};
+
+use crate::autotag::{Rule, Rules, RulesError};
+
+/// Error while loading a profile
+#[derive(thiserror::Error, Debug)]
+pub enum ProfileError {
+    #[error("Invalid profile : {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error(transparent)]
+    Rules(#[from] RulesError),
+    #[error("Invalid index '{0}' in names")]
+    InvalidIndex(String),
+}
+
+/// A recognizable sequence of opcodes
+#[derive(Debug, Clone, Deserialize)]
+pub struct Idiom {
+    /// Used as the tag of matching functions
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub opcodes: Vec<String>,
+}
+
+/// Known names, keyed by index
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Names {
+    /// Function names by findex
+    #[serde(default)]
+    pub functions: BTreeMap<String, String>,
+    /// Class names by type index
+    #[serde(default)]
+    pub types: BTreeMap<String, String>,
+    /// Field names by `type.field`, the field index includes the inherited fields
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// Local variable and argument names by `findex.register`
+    #[serde(default)]
+    pub locals: BTreeMap<String, String>,
+}
+
+/// Number of elements renamed by [Names::apply]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Renamed {
+    pub functions: usize,
+    pub types: usize,
+    pub fields: usize,
+    pub locals: usize,
+}
+
+/// Indication of where to find interesting data
+#[derive(Debug, Clone, Deserialize)]
+pub struct Hint {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Names of the classes holding the data
+    #[serde(default)]
+    pub types: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+    #[serde(default, rename = "idiom")]
+    pub idioms: Vec<Idiom>,
+    #[serde(default)]
+    pub names: Names,
+    #[serde(default, rename = "hint")]
+    pub hints: Vec<Hint>,
+}
+
+/// Parse a `a.b` key of [Names]
+fn pair_key(key: &str) -> Option<(usize, usize)> {
+    let (a, b) = key.split_once('.')?;
+    Some((a.parse().ok()?, b.parse().ok()?))
+}
+
+impl Names {
+    /// Rename functions, types, fields and locals, names with an index out of bounds are ignored.
+    /// Methods are renamed in their class too, and fields in every subclass.
+    pub fn apply(&self, code: &mut Bytecode) -> Renamed {
+        let mut renamed = Renamed::default();
+        for (findex, name) in &self.functions {
+            let findex: usize = findex.parse().unwrap_or(usize::MAX);
+            if let Some(&RefFunKnown::Fun(i)) = code.findexes.get(findex) {
+                let s = code.add_string(name);
+                code.functions[i].name = Some(s);
+                code.fnames.insert(name.clone(), i);
+                for t in &mut code.types {
+                    if let Type::Obj(obj) | Type::Struct(obj) = t {
+                        for p in obj.protos.iter_mut().filter(|p| p.findex.0 == findex) {
+                            p.name = s;
+                        }
+                    }
+                }
+                renamed.functions += 1;
+            }
+        }
+
+        for (idx, name) in &self.types {
+            let idx: usize = idx.parse().unwrap_or(usize::MAX);
+            if idx >= code.types.len() {
+                continue;
+            }
+            let s = code.add_string(name);
+            if let Type::Obj(obj) | Type::Struct(obj) = &mut code.types[idx] {
+                obj.name = s;
+                renamed.types += 1;
+            }
+        }
+
+        for (key, name) in &self.fields {
+            if let Some((ty, field)) = pair_key(key) {
+                if rename_field(code, RefType(ty), field, name) {
+                    renamed.fields += 1;
+                }
+            }
+        }
+
+        for (key, name) in &self.locals {
+            if let Some((findex, reg)) = pair_key(key) {
+                if rename_local(code, findex, Reg(reg as u32), name) {
+                    renamed.locals += 1;
+                }
+            }
+        }
+        renamed
+    }
+}
+
+/// Rename the field at `field` of `ty` (inherited fields included) where it is declared and in every subclass
+fn rename_field(code: &mut Bytecode, ty: RefType, field: usize, name: &str) -> bool {
+    // Find the class declaring the field
+    let mut decl = ty;
+    loop {
+        let Some(obj) = decl.resolve_as_obj(&code.types) else {
+            return false;
+        };
+        if field >= obj.fields.len() {
+            return false;
+        }
+        let start = obj.fields.len() - obj.own_fields.len();
+        if field >= start {
+            break;
+        }
+        match obj.super_ {
+            Some(parent) => decl = parent,
+            None => return false,
+        }
+    }
+
+    let s = code.add_string(name);
+    let mut classes = code.subclasses(decl);
+    classes.push(decl);
+    for t in classes {
+        if let Type::Obj(obj) | Type::Struct(obj) = &mut code.types[t.0] {
+            obj.fields[field].name = s;
+            if t == decl {
+                let start = obj.fields.len() - obj.own_fields.len();
+                obj.own_fields[field - start].name = s;
+            }
+        }
+    }
+    true
+}
+
+/// Rename a register in the debug names of a function. Locals without a debug name are given one at each write.
+fn rename_local(code: &mut Bytecode, findex: usize, reg: Reg, name: &str) -> bool {
+    let Some(&RefFunKnown::Fun(i)) = code.findexes.get(findex) else {
+        return false;
+    };
+    if reg.0 as usize >= code.functions[i].regs.len() {
+        return false;
+    }
+    let s = code.add_string(name);
+    let nargs = code.functions[i].ty(code).args.len();
+    let f = &mut code.functions[i];
+    let assigns = f.assigns.get_or_insert_with(Vec::new);
+    if (reg.0 as usize) < nargs {
+        // Arguments are named by their order in the entries at position 0, they can't be added
+        match assigns
+            .iter_mut()
+            .filter(|(_, pos)| *pos == 0)
+            .nth(reg.0 as usize)
+        {
+            Some(entry) => entry.0 = s,
+            None => return false,
+        }
+        return true;
+    }
+    let writes: Vec<usize> = f
+        .ops
+        .iter()
+        .enumerate()
+        .filter(|(_, o)| o.dst() == Some(reg))
+        .map(|(pos, _)| pos + 1)
+        .collect();
+    if writes.is_empty() {
+        return false;
+    }
+    for pos in writes {
+        match assigns.iter_mut().find(|(_, p)| *p == pos) {
+            Some(entry) => entry.0 = s,
+            None => assigns.push((s, pos)),
+        }
+    }
+    assigns.sort_by_key(|&(_, pos)| pos);
+    true
+}
+
+/// What has been changed by applying a profile
+#[derive(Debug, Clone, Default)]
+pub struct ProfileStats {
+    pub tags: usize,
+    pub functions_renamed: usize,
+    pub types_renamed: usize,
+    pub fields_renamed: usize,
+    pub locals_renamed: usize,
+}
+
+impl Profile {
+    /// Parse and validate a profile from its TOML representation
+    pub fn from_toml(s: &str) -> Result<Self, ProfileError> {
+        let profile: Profile = toml::from_str(s)?;
+        profile.tag_rules().validate()?;
+        for key in profile
+            .names
+            .functions
+            .keys()
+            .chain(profile.names.types.keys())
+        {
+            if key.parse::<usize>().is_err() {
+                return Err(ProfileError::InvalidIndex(key.clone()));
+            }
+        }
+        for key in profile
+            .names
+            .fields
+            .keys()
+            .chain(profile.names.locals.keys())
+        {
+            if pair_key(key).is_none() {
+                return Err(ProfileError::InvalidIndex(key.clone()));
+            }
+        }
+        Ok(profile)
+    }
+
+    /// Auto-tagging rules including the idioms
+    pub fn tag_rules(&self) -> Rules {
+        let mut rules = self.rules.clone();
+        rules.extend(self.idioms.iter().map(|i| Rule {
+            tag: i.name.clone(),
+            natives: Vec::new(),
+            strings: Vec::new(),
+            opcodes: i.opcodes.clone(),
+        }));
+        Rules { rules }
+    }
+
+    /// Rename functions and types and tag functions.
+    /// Names with an index out of bounds are ignored, the profile may be for another version of the game.
+    pub fn apply(&self, code: &mut Bytecode, tags: &mut Tags) -> ProfileStats {
+        let mut stats = ProfileStats {
+            tags: self.tag_rules().apply(code, tags),
+            ..Default::default()
+        };
+
+        let renamed = self.names.apply(code);
+        stats.functions_renamed = renamed.functions;
+        stats.types_renamed = renamed.types;
+        stats.fields_renamed = renamed.fields;
+        stats.locals_renamed = renamed.locals;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::BytecodeBuilder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefField, Reg, Type};
+
+    use crate::profile::{Names, Profile, ProfileError};
+
+    #[test]
+    fn parse() {
+        let profile = Profile::from_toml(
+            r#"
+name = "test"
+[[idiom]]
+name = "rng"
+opcodes = ["Shl", "Xor"]
+[names.functions]
+12 = "update"
+"#,
+        )
+        .unwrap();
+        assert_eq!(profile.tag_rules().rules.len(), 1);
+        assert!(matches!(
+            Profile::from_toml("name = \"test\"\n[names.types]\nabc = \"A\""),
+            Err(ProfileError::InvalidIndex(_))
+        ));
+    }
+
+    #[test]
+    fn apply() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let i32_ = b.ty(Type::I32);
+        let update = b.findex();
+        let base = b.class("Base", None, &[("a", i32_)], &[("update", update)]);
+        let sub = b.class("Sub", Some(base), &[("b", i32_)], &[]);
+        let ty = b.method_type(&[base], void);
+        let ops = vec![
+            Opcode::Int {
+                dst: Reg(1),
+                ptr: b.int(1),
+            },
+            Opcode::SetField {
+                obj: Reg(0),
+                field: RefField(0),
+                src: Reg(1),
+            },
+            Opcode::Ret { ret: Reg(2) },
+        ];
+        b.function(update, ty, vec![base, i32_, void], ops);
+        let mut code = b.build().unwrap();
+
+        let mut names = Names::default();
+        names.functions.insert(update.0.to_string(), "tick".into());
+        names.fields.insert(format!("{}.0", sub.0), "hp".into());
+        names.fields.insert(format!("{}.1", sub.0), "mp".into());
+        names.fields.insert(format!("{}.1", base.0), "nope".into());
+        names.locals.insert(format!("{}.1", update.0), "one".into());
+        let renamed = names.apply(&mut code);
+        assert_eq!(
+            (renamed.functions, renamed.fields, renamed.locals),
+            (1, 2, 1)
+        );
+
+        let field = |ty: hlbc::types::RefType, i: usize| {
+            let obj = ty.resolve_as_obj(&code.types).unwrap();
+            obj.fields[i].name.resolve(&code.strings).to_owned()
+        };
+        assert_eq!(field(base, 0), "hp");
+        assert_eq!(field(sub, 0), "hp");
+        assert_eq!(field(sub, 1), "mp");
+        let obj = base.resolve_as_obj(&code.types).unwrap();
+        assert_eq!(obj.own_fields[0].name.resolve(&code.strings), "hp");
+        assert_eq!(obj.protos[0].name.resolve(&code.strings), "tick");
+        let f = update.resolve_as_fn(&code).unwrap();
+        assert_eq!(&*f.var_name(&code, 0).unwrap(), "one");
+    }
+}
@@ -0,0 +1,363 @@
+//! Classes and methods used by name at runtime, through reflection.
+//!
+//! `Type.resolveClass("game.Boss")` or `Reflect.field(o, "update")` reach classes and methods without referencing
+//! them in the code, a static analysis believes they are unused. [Reflection::new] finds these calls when the name is
+//! a constant string, and the dynamic field accesses ([Opcode::DynGet], [Opcode::DynSet]) matching a method, then
+//! resolves the classes and functions they can reach. The [Callgraph](crate::graph::Callgraph) and the
+//! [XrefIndex](crate::xref::XrefIndex) include them, so they aren't reported as dead code.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc_analysis::reflection::Reflection;
+//!
+//! let code = sample();
+//! let reflection = Reflection::new(&code);
+//! assert!(reflection.to_text(&code).starts_with("Reflection"));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, RefFun, RefType, Reg, Type};
+use hlbc::Bytecode;
+
+use crate::entrypoints::{call_args, direct_call};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ReflectionKind {
+    /// `Type.resolveClass`
+    ResolveClass,
+    /// `Type.resolveEnum`
+    ResolveEnum,
+    /// A field or a method accessed by name, `Reflect.field` and the like
+    Field,
+    /// A field of a dynamic value with the name of a method
+    Dynamic,
+}
+
+impl ReflectionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReflectionKind::ResolveClass => "Type.resolveClass",
+            ReflectionKind::ResolveEnum => "Type.resolveEnum",
+            ReflectionKind::Field => "Reflect",
+            ReflectionKind::Dynamic => "dynamic",
+        }
+    }
+}
+
+/// Std functions taking a name, with the position of the name in the arguments
+const REFLECTION_CALLS: &[(&str, &str, usize, ReflectionKind)] = &[
+    ("Type", "resolveClass", 0, ReflectionKind::ResolveClass),
+    ("Type", "resolveEnum", 0, ReflectionKind::ResolveEnum),
+    ("Reflect", "field", 1, ReflectionKind::Field),
+    ("Reflect", "setField", 1, ReflectionKind::Field),
+    ("Reflect", "getProperty", 1, ReflectionKind::Field),
+    ("Reflect", "setProperty", 1, ReflectionKind::Field),
+    ("Reflect", "hasField", 1, ReflectionKind::Field),
+];
+
+/// An instruction using a class or a field by name
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReflectionUse {
+    pub fun: RefFun,
+    pub pos: usize,
+    pub kind: ReflectionKind,
+    /// Name of the class, the enum or the field
+    pub name: String,
+    /// Classes or enums with this name, with the class holding the statics
+    pub types: Vec<RefType>,
+    /// Methods and static functions of the classes, or functions bound to a field with this name
+    pub functions: Vec<RefFun>,
+}
+
+/// Every use of reflection with a constant name, in instruction order
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Reflection {
+    pub uses: Vec<ReflectionUse>,
+}
+
+impl Reflection {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut types: HashMap<&str, Vec<RefType>> = HashMap::new();
+        for (t, ty) in code.iter_types() {
+            let name = match ty {
+                Type::Obj(obj) => obj.name.resolve(&code.strings),
+                Type::Enum { name, .. } => name.resolve(&code.strings),
+                _ => continue,
+            };
+            types
+                .entry(name.strip_prefix('$').unwrap_or(name))
+                .or_default()
+                .push(t);
+        }
+        let mut members: HashMap<RefType, Vec<RefFun>> = HashMap::new();
+        let mut methods: HashMap<&str, Vec<RefFun>> = HashMap::new();
+        for f in &code.functions {
+            if let (Some(parent), Some(name)) = (f.parent, f.name(code)) {
+                members.entry(parent).or_default().push(f.findex);
+                methods.entry(name).or_default().push(f.findex);
+            }
+        }
+
+        let mut uses = Vec::new();
+        for (f, (pos, o)) in code.ops() {
+            let (kind, name) = match *o {
+                Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                    (ReflectionKind::Dynamic, field.resolve(&code.strings))
+                }
+                _ => match reflection_call(code, f, o, pos) {
+                    Some(found) => found,
+                    None => continue,
+                },
+            };
+            let (types, functions) = match kind {
+                ReflectionKind::ResolveClass | ReflectionKind::ResolveEnum => {
+                    let types: Vec<RefType> = types
+                        .get(name)
+                        .into_iter()
+                        .flatten()
+                        .copied()
+                        .filter(|t| {
+                            matches!(t.resolve(&code.types), Type::Enum { .. })
+                                == (kind == ReflectionKind::ResolveEnum)
+                        })
+                        .collect();
+                    let functions = types
+                        .iter()
+                        .flat_map(|t| members.get(t).into_iter().flatten().copied())
+                        .collect();
+                    (types, functions)
+                }
+                ReflectionKind::Field | ReflectionKind::Dynamic => {
+                    (Vec::new(), methods.get(name).cloned().unwrap_or_default())
+                }
+            };
+            // Most dynamic accesses are fields of anonymous structures
+            if kind == ReflectionKind::Dynamic && functions.is_empty() {
+                continue;
+            }
+            uses.push(ReflectionUse {
+                fun: f.findex,
+                pos,
+                kind,
+                name: name.to_owned(),
+                types,
+                functions,
+            });
+        }
+        Self { uses }
+    }
+
+    /// Classes and enums resolved by name, sorted
+    pub fn types(&self) -> Vec<RefType> {
+        let mut types: Vec<RefType> = self.uses.iter().flat_map(|u| u.types.clone()).collect();
+        types.sort_unstable();
+        types.dedup();
+        types
+    }
+
+    /// Functions reachable by name, sorted
+    pub fn functions(&self) -> Vec<RefFun> {
+        let mut funs: Vec<RefFun> = self.uses.iter().flat_map(|u| u.functions.clone()).collect();
+        funs.sort_unstable();
+        funs.dedup();
+        funs
+    }
+
+    /// Report of the classes used at runtime, then every use with the classes and functions it reaches
+    pub fn to_text(&self, code: &Bytecode) -> String {
+        let mut out = format!(
+            "Reflection : {} uses reaching {} types and {} functions\n",
+            self.uses.len(),
+            self.types().len(),
+            self.functions().len()
+        );
+        for t in self.types() {
+            let _ = writeln!(out, "type {}", t.display_id(code));
+        }
+        for u in &self.uses {
+            let _ = write!(
+                out,
+                "{} at {} : {} {:?}",
+                u.fun.display_id(code),
+                u.pos,
+                u.kind.name(),
+                u.name
+            );
+            if u.types.is_empty() && u.functions.is_empty() {
+                out.push_str(" (not found)");
+            } else if !u.functions.is_empty() {
+                let _ = write!(out, " -> {} functions", u.functions.len());
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Call to a reflection function of the std with a constant name
+fn reflection_call<'a>(
+    code: &'a Bytecode,
+    f: &Function,
+    o: &Opcode,
+    pos: usize,
+) -> Option<(ReflectionKind, &'a str)> {
+    let callee = direct_call(o)?.resolve_as_fn(code)?;
+    let name = callee.name(code)?;
+    let class = callee
+        .parent?
+        .resolve_as_obj(&code.types)?
+        .name
+        .resolve(&code.strings);
+    let class = class.strip_prefix('$').unwrap_or(class);
+    let &(_, _, arg, kind) = REFLECTION_CALLS
+        .iter()
+        .find(|(c, n, _, _)| *c == class && *n == name)?;
+    let reg = *call_args(o).get(arg)?;
+    Some((kind, const_string(code, f, reg, pos)?))
+}
+
+/// Constant string held by `reg` before the instruction at `pos`, through moves. Jumps aren't followed.
+fn const_string<'a>(code: &'a Bytecode, f: &Function, mut reg: Reg, pos: usize) -> Option<&'a str> {
+    for o in f.ops[..pos].iter().rev() {
+        if o.dst() != Some(reg) {
+            continue;
+        }
+        match *o {
+            Opcode::String { ptr, .. } => return Some(ptr.resolve(&code.strings)),
+            Opcode::Mov { src, .. } => reg = src,
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::BytecodeBuilder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefType, Reg, Type};
+
+    use crate::reflection::{Reflection, ReflectionKind};
+    use crate::xref::XrefIndex;
+
+    #[test]
+    fn reflection() {
+        let mut b = BytecodeBuilder::new();
+        let void = RefType(0);
+        let bytes = b.ty(Type::Bytes);
+        let dyn_ = b.ty(Type::Dyn);
+        let string = b.class("String", None, &[("bytes", bytes)], &[]);
+        let type_ = b.ty(Type::Type);
+        let [main, resolve, field, boss_update, boss_new, unused] = [(); 6].map(|_| b.findex());
+        b.class("$Type", None, &[], &[("resolveClass", resolve)]);
+        b.class("$Reflect", None, &[], &[("field", field)]);
+        let boss = b.class("game.Boss", None, &[], &[("update", boss_update)]);
+        b.class("$game.Boss", None, &[], &[("create", boss_new)]);
+        b.class("Unused", None, &[], &[("run", unused)]);
+        let resolve_t = b.fun_type(&[string], type_);
+        let field_t = b.fun_type(&[dyn_, string], dyn_);
+        let boss_name = b.string("game.Boss");
+        let update = b.string("update");
+        let x = b.string("x");
+        let main_t = b.fun_type(&[], void);
+        b.function(
+            main,
+            main_t,
+            vec![string, type_, dyn_, string, void],
+            vec![
+                Opcode::String {
+                    dst: Reg(0),
+                    ptr: boss_name,
+                },
+                Opcode::Call1 {
+                    dst: Reg(1),
+                    fun: resolve,
+                    arg0: Reg(0),
+                },
+                Opcode::String {
+                    dst: Reg(3),
+                    ptr: update,
+                },
+                Opcode::Mov {
+                    dst: Reg(0),
+                    src: Reg(3),
+                },
+                Opcode::Call2 {
+                    dst: Reg(2),
+                    fun: field,
+                    arg0: Reg(2),
+                    arg1: Reg(0),
+                },
+                // A field of an anonymous structure
+                Opcode::DynGet {
+                    dst: Reg(2),
+                    obj: Reg(2),
+                    field: x,
+                },
+                Opcode::DynGet {
+                    dst: Reg(2),
+                    obj: Reg(2),
+                    field: update,
+                },
+                Opcode::Ret { ret: Reg(4) },
+            ],
+        );
+        b.function(
+            resolve,
+            resolve_t,
+            vec![string, type_],
+            vec![Opcode::Ret { ret: Reg(1) }],
+        );
+        b.function(
+            field,
+            field_t,
+            vec![dyn_, string],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        let method_t = b.method_type(&[boss], void);
+        for f in [boss_update, unused] {
+            b.function(
+                f,
+                method_t,
+                vec![boss, void],
+                vec![Opcode::Ret { ret: Reg(1) }],
+            );
+        }
+        b.function(
+            boss_new,
+            main_t,
+            vec![void],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        b.entrypoint(main);
+        let code = b.build().unwrap();
+
+        let reflection = Reflection::new(&code);
+        let uses: Vec<(usize, ReflectionKind, &str)> = reflection
+            .uses
+            .iter()
+            .map(|u| (u.pos, u.kind, u.name.as_str()))
+            .collect();
+        assert_eq!(
+            uses,
+            [
+                (1, ReflectionKind::ResolveClass, "game.Boss"),
+                (4, ReflectionKind::Field, "update"),
+                (6, ReflectionKind::Dynamic, "update"),
+            ]
+        );
+        assert_eq!(reflection.uses[0].types.len(), 2);
+        assert_eq!(reflection.types()[0], boss);
+        assert_eq!(reflection.functions(), [boss_update, boss_new]);
+        assert!(!reflection.functions().contains(&unused));
+        assert!(reflection
+            .to_text(&code)
+            .contains("Type.resolveClass \"game.Boss\""));
+
+        assert_eq!(XrefIndex::new(&code).ty(boss), [(main, 1)]);
+        #[cfg(feature = "graph")]
+        assert_eq!(crate::graph::Callgraph::new(&code).dead(&code), [unused]);
+    }
+}
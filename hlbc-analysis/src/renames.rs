@@ -0,0 +1,501 @@
+//! Rename maps, names given by the user to classes, functions and fields, keyed by their name in the bytecode.
+//!
+//! Unlike the [Names] of profiles and databases keyed by index, a rename map only refers to names so it is easy to
+//! write by hand and to share between people working on the same (stripped or obfuscated) binary. It is applied to
+//! the loaded bytecode, the file itself is never modified.
+//! ```toml
+//! [classes]
+//! "a.b" = "game.Player"
+//! # Methods and static functions qualified by their class, or a function name alone
+//! [functions]
+//! "a.b.c" = "update"
+//! # Fields qualified by their class
+//! [fields]
+//! "a.b.d" = "health"
+//! ```
+//! Classes are matched by their name in the bytecode even when they are renamed by the same map, so `a.b.c` above
+//! targets the method `c` of the class `a.b` renamed to `game.Player`. Elements without a unique name are designated
+//! by their index : `@12` is the function 12 or the type 12, `@4.d` the field `d` of the type 4.
+//!
+//! Entries imported from another tool remember where they come from, see [symbols](crate::symbols) :
+//! ```toml
+//! [sources]
+//! "fn @12" = "ghidra:symbols.csv"
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use hlbc::types::{RefFun, RefFunKnown, RefString, Type};
+use hlbc::Bytecode;
+use serde::{Deserialize, Serialize};
+
+use crate::profile::{Names, Renamed};
+
+/// Kind of element renamed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RenameKind {
+    Class,
+    Function,
+    Field,
+}
+
+impl FromStr for RenameKind {
+    type Err = RenameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "class" => Ok(RenameKind::Class),
+            "fn" => Ok(RenameKind::Function),
+            "field" => Ok(RenameKind::Field),
+            _ => Err(RenameError::InvalidKind(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for RenameKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RenameKind::Class => "class",
+            RenameKind::Function => "fn",
+            RenameKind::Field => "field",
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RenameError {
+    #[error("Invalid rename map : {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Unknown kind of element '{0}', expected class, fn or field")]
+    InvalidKind(String),
+}
+
+/// New names by name in the bytecode, see [renames](crate::renames)
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RenameMap {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub classes: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub functions: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Where the imported entries come from, by `kind name`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<String, String>,
+}
+
+/// What to do when an imported entry renames an element already renamed to something else
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum OnConflict {
+    /// Keep the current name
+    #[default]
+    Keep,
+    /// Take the imported name
+    Replace,
+}
+
+/// An element renamed differently by the map and by an import, see [RenameMap::import]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Conflict {
+    pub kind: RenameKind,
+    /// Name in the bytecode
+    pub name: String,
+    pub current: String,
+    pub imported: String,
+}
+
+/// Result of [RenameMap::apply]
+#[derive(Debug, Clone, Default)]
+pub struct Applied {
+    pub renamed: Renamed,
+    /// Entries matching nothing in the bytecode
+    pub missing: Vec<(RenameKind, String)>,
+}
+
+impl RenameMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, RenameError> {
+        Ok(toml::from_str(s)?)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Rename map can't be serialized")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty() && self.functions.is_empty() && self.fields.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.len() + self.functions.len() + self.fields.len()
+    }
+
+    fn entries(&self, kind: RenameKind) -> &BTreeMap<String, String> {
+        match kind {
+            RenameKind::Class => &self.classes,
+            RenameKind::Function => &self.functions,
+            RenameKind::Field => &self.fields,
+        }
+    }
+
+    fn entries_mut(&mut self, kind: RenameKind) -> &mut BTreeMap<String, String> {
+        match kind {
+            RenameKind::Class => &mut self.classes,
+            RenameKind::Function => &mut self.functions,
+            RenameKind::Field => &mut self.fields,
+        }
+    }
+
+    /// Every entry with its kind
+    pub fn iter(&self) -> impl Iterator<Item = (RenameKind, &str, &str)> {
+        [RenameKind::Class, RenameKind::Function, RenameKind::Field]
+            .into_iter()
+            .flat_map(move |kind| {
+                self.entries(kind)
+                    .iter()
+                    .map(move |(old, new)| (kind, old.as_str(), new.as_str()))
+            })
+    }
+
+    /// Name in the bytecode of an element currently named `name` after the renames of this map
+    fn original<'a>(&'a self, kind: RenameKind, name: &'a str) -> &'a str {
+        self.entries(kind)
+            .iter()
+            .find(|(_, new)| *new == name)
+            .map_or(name, |(old, _)| old)
+    }
+
+    /// Where an entry comes from, `None` for renames made by hand
+    pub fn source(&self, kind: RenameKind, name: &str) -> Option<&str> {
+        self.sources
+            .get(&source_key(kind, name))
+            .map(String::as_str)
+    }
+
+    /// Rename an element designated by its current name, which can come from this map. The class qualifying a
+    /// function or a field can be renamed too.
+    pub fn insert(&mut self, kind: RenameKind, current: &str, new: &str) {
+        let key = match kind {
+            RenameKind::Class => self.original(kind, current).to_owned(),
+            _ => match self.original(kind, current) {
+                // Renamed element
+                key if key != current => key.to_owned(),
+                _ => match current.rsplit_once('.') {
+                    Some((class, name)) => {
+                        format!("{}.{name}", self.original(RenameKind::Class, class))
+                    }
+                    None => current.to_owned(),
+                },
+            },
+        };
+        // Renamed by hand
+        self.sources.remove(&source_key(kind, &key));
+        self.entries_mut(kind).insert(key, new.to_owned());
+    }
+
+    /// Remove the rename of an element designated by its name in the bytecode or its new name, returns the new name
+    pub fn remove(&mut self, kind: RenameKind, name: &str) -> Option<String> {
+        let key = self.original(kind, name).to_owned();
+        self.sources.remove(&source_key(kind, &key));
+        self.entries_mut(kind).remove(&key)
+    }
+
+    /// Add the entries of another map, replacing the existing ones
+    pub fn merge(&mut self, other: &RenameMap) {
+        self.import(other, OnConflict::Replace);
+    }
+
+    /// Add the entries of another map with their source. The elements this map already renames to another name are
+    /// returned, and renamed according to `on_conflict`.
+    pub fn import(&mut self, other: &RenameMap, on_conflict: OnConflict) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        for (kind, old, new) in other.iter() {
+            if let Some(current) = self.entries(kind).get(old) {
+                if current == new {
+                    continue;
+                }
+                conflicts.push(Conflict {
+                    kind,
+                    name: old.to_owned(),
+                    current: current.clone(),
+                    imported: new.to_owned(),
+                });
+                if on_conflict == OnConflict::Keep {
+                    continue;
+                }
+            }
+            let key = source_key(kind, old);
+            match other.sources.get(&key) {
+                Some(source) => self.sources.insert(key, source.clone()),
+                None => self.sources.remove(&key),
+            };
+            self.entries_mut(kind)
+                .insert(old.to_owned(), new.to_owned());
+        }
+        conflicts
+    }
+
+    /// Find the elements to rename in the bytecode, by index
+    pub fn resolve(&self, code: &Bytecode) -> (Names, Vec<(RenameKind, String)>) {
+        let mut names = Names::default();
+        let mut missing = Vec::new();
+
+        for (old, new) in &self.classes {
+            let mut found = false;
+            for i in classes(code, old) {
+                names.types.insert(i.to_string(), new.clone());
+                found = true;
+            }
+            // The type holding the statics follows its class
+            for i in classes(code, &format!("${old}")) {
+                names.types.insert(i.to_string(), format!("${new}"));
+            }
+            if !found {
+                missing.push((RenameKind::Class, old.clone()));
+            }
+        }
+
+        let index = code.function_index();
+        for (old, new) in &self.functions {
+            let mut found = false;
+            let found_fns = match index_key(old) {
+                Some(findex) => vec![RefFun(findex)],
+                None => index.lookup(code, old),
+            };
+            for f in found_fns {
+                if let Some(RefFunKnown::Fun(_)) = code.findexes.get(f.0) {
+                    names.functions.insert(f.0.to_string(), new.clone());
+                    found = true;
+                }
+            }
+            if !found {
+                missing.push((RenameKind::Function, old.clone()));
+            }
+        }
+
+        for (old, new) in &self.fields {
+            let mut found = false;
+            if let Some((class, field)) = old.rsplit_once('.') {
+                for i in classes(code, class).chain(classes(code, &format!("${class}"))) {
+                    let (Type::Obj(obj) | Type::Struct(obj)) = &code.types[i] else {
+                        continue;
+                    };
+                    if let Some(pos) = obj
+                        .fields
+                        .iter()
+                        .position(|f| f.name.resolve(&code.strings) == field)
+                    {
+                        names.fields.insert(format!("{i}.{pos}"), new.clone());
+                        found = true;
+                    }
+                }
+            }
+            if !found {
+                missing.push((RenameKind::Field, old.clone()));
+            }
+        }
+        (names, missing)
+    }
+
+    /// Rename the elements in the loaded bytecode. Every entry is resolved before renaming anything.
+    pub fn apply(&self, code: &mut Bytecode) -> Applied {
+        let (names, missing) = self.resolve(code);
+        Applied {
+            renamed: names.apply(code),
+            missing,
+        }
+    }
+}
+
+/// Names of a bytecode before it is renamed, to write it without the renames, see [Self::restore]
+#[derive(Debug, Clone, Default)]
+pub struct OriginalNames {
+    /// Size of the string pool, renaming only adds strings
+    strings: usize,
+    /// Name and local names of each function
+    functions: Vec<FunNames>,
+    /// Names of each class : its own, its fields, its own fields and its methods
+    types: Vec<Option<ObjNames>>,
+}
+
+#[derive(Debug, Clone)]
+struct FunNames {
+    name: Option<RefString>,
+    assigns: Option<Vec<(RefString, usize)>>,
+}
+
+#[derive(Debug, Clone)]
+struct ObjNames {
+    name: RefString,
+    fields: Vec<RefString>,
+    own_fields: Vec<RefString>,
+    protos: Vec<RefString>,
+}
+
+impl OriginalNames {
+    /// Record the names of a bytecode not renamed yet
+    pub fn new(code: &Bytecode) -> Self {
+        Self {
+            strings: code.strings.len(),
+            functions: code
+                .functions
+                .iter()
+                .map(|f| FunNames {
+                    name: f.name,
+                    assigns: f.assigns.clone(),
+                })
+                .collect(),
+            types: code
+                .types
+                .iter()
+                .map(|t| match t {
+                    Type::Obj(obj) | Type::Struct(obj) => Some(ObjNames {
+                        name: obj.name,
+                        fields: obj.fields.iter().map(|f| f.name).collect(),
+                        own_fields: obj.own_fields.iter().map(|f| f.name).collect(),
+                        protos: obj.protos.iter().map(|p| p.name).collect(),
+                    }),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Put the recorded names back and remove the strings added since from the string pool. The bytecode must not
+    /// have been modified otherwise, this is meant for a copy of the renamed bytecode about to be written.
+    pub fn restore(&self, code: &mut Bytecode) {
+        for (f, names) in code.functions.iter_mut().zip(&self.functions) {
+            f.name = names.name;
+            f.assigns = names.assigns.clone();
+        }
+        for (t, names) in code.types.iter_mut().zip(&self.types) {
+            if let (Type::Obj(obj) | Type::Struct(obj), Some(names)) = (t, names) {
+                obj.name = names.name;
+                for (f, &name) in obj.fields.iter_mut().zip(&names.fields) {
+                    f.name = name;
+                }
+                for (f, &name) in obj.own_fields.iter_mut().zip(&names.own_fields) {
+                    f.name = name;
+                }
+                for (p, &name) in obj.protos.iter_mut().zip(&names.protos) {
+                    p.name = name;
+                }
+            }
+        }
+        code.strings.truncate(self.strings);
+    }
+}
+
+fn source_key(kind: RenameKind, name: &str) -> String {
+    format!("{kind} {name}")
+}
+
+/// Index of an element designated as `@index`
+fn index_key(name: &str) -> Option<usize> {
+    name.strip_prefix('@')?.parse().ok()
+}
+
+/// Indexes of the classes named `name`, or of the class `@index`
+fn classes<'a>(code: &'a Bytecode, name: &'a str) -> impl Iterator<Item = usize> + 'a {
+    let index = index_key(name);
+    code.types
+        .iter()
+        .enumerate()
+        .filter(move |&(i, t)| match t {
+            Type::Obj(obj) | Type::Struct(obj) => match index {
+                Some(index) => i == index,
+                None => obj.name.resolve(&code.strings) == name,
+            },
+            _ => false,
+        })
+        .map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{sample, BytecodeBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+
+    use crate::renames::{OriginalNames, RenameKind, RenameMap};
+
+    #[test]
+    fn apply() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let i32_ = b.ty(Type::I32);
+        let update = b.findex();
+        let player = b.class("a.b", None, &[("d", i32_)], &[("c", update)]);
+        let ty = b.method_type(&[player], void);
+        b.function(
+            update,
+            ty,
+            vec![player, void],
+            vec![Opcode::Ret { ret: Reg(1) }],
+        );
+        let mut code = b.build().unwrap();
+
+        let mut map = RenameMap::new();
+        map.insert(RenameKind::Class, "a.b", "game.Player");
+        // Qualified by the new name of the class
+        map.insert(RenameKind::Function, "game.Player.c", "update");
+        map.insert(RenameKind::Field, "a.b.d", "health");
+        map.insert(RenameKind::Field, "a.b.nope", "nope");
+        assert_eq!(map.functions["a.b.c"], "update");
+        // Renaming again replaces the entry
+        map.insert(RenameKind::Function, "update", "tick");
+        assert_eq!(map.functions["a.b.c"], "tick");
+
+        let map = RenameMap::from_toml(&map.to_toml()).unwrap();
+        assert_eq!(map.len(), 4);
+        let applied = map.apply(&mut code);
+        assert_eq!(
+            (
+                applied.renamed.types,
+                applied.renamed.functions,
+                applied.renamed.fields
+            ),
+            (1, 1, 1)
+        );
+        assert_eq!(
+            applied.missing,
+            [(RenameKind::Field, "a.b.nope".to_owned())]
+        );
+
+        let obj = player.resolve_as_obj(&code.types).unwrap();
+        assert_eq!(obj.name.resolve(&code.strings), "game.Player");
+        assert_eq!(obj.fields[0].name.resolve(&code.strings), "health");
+        assert_eq!(
+            update.resolve_as_fn(&code).unwrap().name(&code),
+            Some("tick")
+        );
+        // The constant pool is only extended
+        assert!(code.strings.iter().any(|s| s == "a.b"));
+    }
+
+    #[test]
+    fn restore() {
+        let code = sample();
+        let mut original = Vec::new();
+        code.serialize(&mut original).unwrap();
+
+        let mut renamed = sample();
+        let names = OriginalNames::new(&renamed);
+        let mut map = RenameMap::new();
+        map.insert(RenameKind::Class, "Point", "geom.Point");
+        map.insert(RenameKind::Function, "length", "norm");
+        map.insert(RenameKind::Field, "Point.x", "px");
+        assert_eq!(map.apply(&mut renamed).missing, []);
+        assert!(renamed.strings.iter().any(|s| s == "geom.Point"));
+
+        names.restore(&mut renamed);
+        let mut restored = Vec::new();
+        renamed.serialize(&mut restored).unwrap();
+        assert_eq!(restored, original);
+    }
+}
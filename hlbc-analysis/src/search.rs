@@ -0,0 +1,141 @@
+//! Substring search index over the strings, debug file names and function names.
+//!
+//! Big games have hundreds of thousands of strings, scanning all of them for each search is noticeably slow in
+//! interactive use. The index maps each trigram (3 consecutive bytes) to the strings containing it, a search only
+//! checks the strings containing every trigram of the query. Queries shorter than a trigram fall back to a scan.
+
+use std::collections::{HashMap, HashSet};
+
+use hlbc::types::{RefFun, RefString};
+use hlbc::Bytecode;
+
+/// Search index over the constant pools of a bytecode, build it once and reuse it for every search
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    strings: Trigrams,
+    debug_files: Trigrams,
+}
+
+impl SearchIndex {
+    pub fn new(code: &Bytecode) -> Self {
+        Self {
+            strings: Trigrams::new(&code.strings),
+            debug_files: code
+                .debug_files
+                .as_ref()
+                .map(|files| Trigrams::new(files))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Strings containing `query`, in pool order
+    pub fn strings(&self, code: &Bytecode, query: &str) -> Vec<RefString> {
+        self.strings
+            .find(&code.strings, query)
+            .map(RefString)
+            .collect()
+    }
+
+    /// Indexes of the debug files containing `query`, in pool order
+    pub fn debug_files(&self, code: &Bytecode, query: &str) -> Vec<usize> {
+        match &code.debug_files {
+            Some(files) => self.debug_files.find(files, query).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Functions whose name contains `query`, in pool order
+    pub fn functions(&self, code: &Bytecode, query: &str) -> Vec<RefFun> {
+        let names: HashSet<RefString> = self.strings(code, query).into_iter().collect();
+        code.functions
+            .iter()
+            .filter(|f| f.name.map_or(false, |n| names.contains(&n)))
+            .map(|f| f.findex)
+            .collect()
+    }
+}
+
+/// Trigrams of a list of strings, each one with the sorted indexes of the strings containing it
+#[derive(Debug, Clone, Default)]
+struct Trigrams {
+    postings: HashMap<[u8; 3], Vec<u32>>,
+}
+
+impl Trigrams {
+    fn new(items: &[impl AsRef<str>]) -> Self {
+        let mut postings: HashMap<[u8; 3], Vec<u32>> = HashMap::new();
+        for (i, s) in items.iter().enumerate() {
+            for t in s.as_ref().as_bytes().windows(3) {
+                let list = postings.entry([t[0], t[1], t[2]]).or_default();
+                // Strings are visited in order, a repeated trigram is at the end
+                if list.last() != Some(&(i as u32)) {
+                    list.push(i as u32);
+                }
+            }
+        }
+        Self { postings }
+    }
+
+    /// Indexes of the items containing `query`
+    fn find<'a, S: AsRef<str>>(
+        &self,
+        items: &'a [S],
+        query: &'a str,
+    ) -> impl Iterator<Item = usize> + 'a {
+        let candidates: Vec<usize> = if query.len() < 3 {
+            (0..items.len()).collect()
+        } else {
+            self.candidates(query)
+        };
+        candidates
+            .into_iter()
+            .filter(move |&i| items[i].as_ref().contains(query))
+    }
+
+    /// Items containing every trigram of `query`, they still need to be checked
+    fn candidates(&self, query: &str) -> Vec<usize> {
+        let mut lists = Vec::new();
+        for t in query.as_bytes().windows(3) {
+            match self.postings.get(&[t[0], t[1], t[2]]) {
+                Some(list) => lists.push(list),
+                None => return Vec::new(),
+            }
+        }
+        // Intersecting from the rarest trigram keeps the candidate set small
+        lists.sort_by_key(|l| l.len());
+        let mut candidates = lists[0].clone();
+        for list in &lists[1..] {
+            candidates.retain(|i| list.binary_search(i).is_ok());
+        }
+        candidates.into_iter().map(|i| i as usize).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+    use hlbc::types::RefString;
+
+    use crate::search::SearchIndex;
+
+    #[test]
+    fn search() {
+        let mut code = sample();
+        code.strings.push("say Hello twice, Hello".into());
+        let index = SearchIndex::new(&code);
+        for query in ["Hello", "ll", "", "Point", "o tw", "nothing"] {
+            let expected: Vec<RefString> = (0..code.strings.len())
+                .filter(|&i| code.strings[i].contains(query))
+                .map(RefString)
+                .collect();
+            assert_eq!(index.strings(&code, query), expected, "{query}");
+        }
+
+        let length = index.functions(&code, "engt");
+        assert!(length
+            .iter()
+            .all(|f| f.name(&code).map_or(false, |n| n.contains("engt"))));
+        assert!(!length.is_empty());
+        assert_eq!(index.debug_files(&code, ".hx"), [0]);
+    }
+}
@@ -0,0 +1,398 @@
+//! Known function signatures, to name the std library functions of stripped or obfuscated binaries.
+//!
+//! Like FLIRT signatures for native code, a signature recognizes a function from its structure only. Signatures are
+//! written in TOML :
+//! ```toml
+//! [[signature]]
+//! name = "Std.string"
+//! # Number of arguments, including `this`
+//! args = 1
+//! # Natives called by the function, as 'lib/name'
+//! natives = ["std/value_to_string"]
+//! # Contains this sequence of consecutive opcodes
+//! opcodes = ["Call2", "Call3"]
+//! # Calls at most this number of functions, natives included
+//! max_calls = 2
+//! # Structural hash of the whole function, see [function_hash](crate::diff::function_hash)
+//! hash = "8c3f2a5e0b1d4f67"
+//! ```
+//! Every criterion given must match. The confidence of a match grows with the number of criteria,
+//! a matching hash is a certain match. It is divided by the number of functions matching the same signature.
+//! Only functions without a debug name are named, in an [InferredNames] overlay : the bytecode itself is unchanged.
+//! The overlay is passed to the display functions with [InferredNames::with].
+//!
+//! A set of signatures for the Haxe std library is available with [Signatures::default_signatures].
+//! Signatures can be generated from a binary with debug names using [Signatures::generate].
+
+use std::collections::HashMap;
+
+use hlbc::fmt::FunctionNames;
+use hlbc::opcodes::Opcode;
+use hlbc::types::{FunPtr, Function, RefFun};
+use hlbc::Bytecode;
+use serde::{Deserialize, Serialize};
+
+use crate::diff::{function_hash, qualified_name};
+
+/// Confidence above which a match is considered good enough to name a function
+pub const DEFAULT_MIN_CONFIDENCE: f32 = 0.5;
+
+/// Error while loading signatures
+#[derive(thiserror::Error, Debug)]
+pub enum SignaturesError {
+    #[error("Invalid signatures file : {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Unknown opcode '{opcode}' in signature '{name}'")]
+    UnknownOpcode { name: String, opcode: String },
+    #[error("Signature '{0}' matches any function")]
+    Empty(String),
+}
+
+/// Structural signature of a function
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Signature {
+    /// Name given to matching functions
+    pub name: String,
+    /// Number of arguments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<usize>,
+    /// Natives called, as 'lib/name'
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub natives: Vec<String>,
+    /// Sequence of consecutive opcode names
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub opcodes: Vec<String>,
+    /// Maximum number of calls to functions and natives
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_calls: Option<usize>,
+    /// Structural hash of the function
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl Signature {
+    /// Confidence in `[0, 1]` that the function is the one described by this signature,
+    /// `None` if it doesn't match.
+    pub fn score(&self, code: &Bytecode, f: &Function) -> Option<f32> {
+        if let Some(hash) = &self.hash {
+            return if *hash == function_hash(code, f) {
+                Some(1.0)
+            } else {
+                None
+            };
+        }
+        let mut score = 0.0;
+        if let Some(args) = self.args {
+            if f.ty(code).args.len() != args {
+                return None;
+            }
+            score += 0.1;
+        }
+        if !self.natives.is_empty() {
+            let called = natives_called(code, f);
+            if !self.natives.iter().all(|n| called.contains(n)) {
+                return None;
+            }
+            score += 0.4 + 0.1 * (self.natives.len() - 1) as f32;
+        }
+        if !self.opcodes.is_empty() {
+            if !f.ops.windows(self.opcodes.len()).any(|w| {
+                w.iter()
+                    .zip(&self.opcodes)
+                    .all(|(o, name)| o.name() == name.as_str())
+            }) {
+                return None;
+            }
+            score += 0.3;
+        }
+        if let Some(max_calls) = self.max_calls {
+            if f.find_fun_refs().count() > max_calls {
+                return None;
+            }
+            score += 0.1;
+        }
+        Some(f32::min(score, 0.95))
+    }
+}
+
+/// A match between a function and a signature
+#[derive(Debug, Clone)]
+pub struct SigMatch {
+    pub findex: RefFun,
+    pub name: String,
+    pub confidence: f32,
+}
+
+/// Names given to functions without modifying the bytecode
+#[derive(Debug, Clone, Default)]
+pub struct InferredNames(HashMap<RefFun, String>);
+
+impl InferredNames {
+    pub fn get(&self, findex: RefFun) -> Option<&str> {
+        self.0.get(&findex).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, findex: RefFun, name: String) {
+        self.0.insert(findex, name);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (RefFun, &str)> {
+        self.0.iter().map(|(&fi, name)| (fi, name.as_str()))
+    }
+
+    /// Name of the function, the inferred one first
+    pub fn name<'a>(&'a self, code: &'a Bytecode, findex: RefFun) -> Option<&'a str> {
+        self.get(findex)
+            .or_else(|| findex.resolve_as_fn(code).and_then(|f| f.name(code)))
+    }
+
+    /// Display context showing the inferred names instead of the debug names
+    pub fn with<'a>(&'a self, code: &'a Bytecode) -> Named<'a> {
+        Named { code, names: self }
+    }
+}
+
+/// A bytecode with its [InferredNames], to be passed to the display functions
+#[derive(Copy, Clone)]
+pub struct Named<'a> {
+    pub code: &'a Bytecode,
+    pub names: &'a InferredNames,
+}
+
+impl FunctionNames for Named<'_> {
+    fn bytecode(&self) -> &Bytecode {
+        self.code
+    }
+
+    fn function_name(&self, findex: RefFun) -> Option<&str> {
+        self.names.get(findex)
+    }
+}
+
+/// A signature database
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Signatures {
+    #[serde(default, rename = "signature")]
+    pub signatures: Vec<Signature>,
+}
+
+impl Signatures {
+    /// Parse and validate signatures from their TOML representation
+    pub fn from_toml(s: &str) -> Result<Self, SignaturesError> {
+        let sigs: Signatures = toml::from_str(s)?;
+        for s in &sigs.signatures {
+            if let Some(op) = s.opcodes.iter().find(|o| Opcode::from_name(o).is_none()) {
+                return Err(SignaturesError::UnknownOpcode {
+                    name: s.name.clone(),
+                    opcode: op.clone(),
+                });
+            }
+            if s.hash.is_none() && s.natives.is_empty() && s.opcodes.is_empty() {
+                return Err(SignaturesError::Empty(s.name.clone()));
+            }
+        }
+        Ok(sigs)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Signatures can't be serialized")
+    }
+
+    /// Signatures of the Haxe std library shipped with hlbc
+    pub fn default_signatures() -> Self {
+        Self::from_toml(include_str!("default_signatures.toml"))
+            .expect("Invalid default signatures")
+    }
+
+    /// Add the signatures of another database
+    pub fn extend(&mut self, other: Signatures) {
+        self.signatures.extend(other.signatures);
+    }
+
+    /// Generate hash signatures for the named functions of a binary.
+    /// Functions too small to be recognized reliably are skipped.
+    pub fn generate(code: &Bytecode) -> Self {
+        let signatures = code
+            .functions
+            .iter()
+            .filter(|f| f.ops.len() >= 8)
+            .filter_map(|f| {
+                Some(Signature {
+                    name: qualified_name(code, f)?,
+                    args: Some(f.ty(code).args.len()),
+                    natives: Vec::new(),
+                    opcodes: Vec::new(),
+                    max_calls: None,
+                    hash: Some(function_hash(code, f)),
+                })
+            })
+            .collect();
+        Self { signatures }
+    }
+
+    /// Find the best signature for every function, at most one match per function
+    pub fn find(&self, code: &Bytecode) -> Vec<SigMatch> {
+        let mut best: HashMap<usize, (usize, f32)> = HashMap::new();
+        for (i, s) in self.signatures.iter().enumerate() {
+            let matches: Vec<(usize, f32)> = code
+                .functions
+                .iter()
+                .enumerate()
+                .filter_map(|(fi, f)| s.score(code, f).map(|score| (fi, score)))
+                .collect();
+            let n = matches.len() as f32;
+            for (fi, score) in matches {
+                let score = score / n;
+                if best.get(&fi).map(|&(_, s)| score > s).unwrap_or(true) {
+                    best.insert(fi, (i, score));
+                }
+            }
+        }
+        let mut matches: Vec<SigMatch> = best
+            .into_iter()
+            .map(|(fi, (i, confidence))| SigMatch {
+                findex: code.functions[fi].findex,
+                name: self.signatures[i].name.clone(),
+                confidence,
+            })
+            .collect();
+        matches.sort_by_key(|m| m.findex.0);
+        matches
+    }
+
+    /// Name every function without a debug name matching a signature with a confidence above `min_confidence`.
+    /// The names are added to `names`, returns the functions named.
+    pub fn apply(
+        &self,
+        code: &Bytecode,
+        names: &mut InferredNames,
+        min_confidence: f32,
+    ) -> Vec<SigMatch> {
+        let matches: Vec<SigMatch> = self
+            .find(code)
+            .into_iter()
+            .filter(|m| m.confidence > min_confidence)
+            .filter(|m| {
+                m.findex
+                    .resolve_as_fn(code)
+                    .map_or(false, |f| f.name.is_none())
+            })
+            .collect();
+        for m in &matches {
+            names.insert(m.findex, m.name.clone());
+        }
+        matches
+    }
+}
+
+/// Natives called by a function, as 'lib/name'
+fn natives_called(code: &Bytecode, f: &Function) -> Vec<String> {
+    f.find_fun_refs()
+        .filter_map(|(_, _, fun)| match fun.resolve(code) {
+            FunPtr::Native(n) => Some(format!("{}/{}", n.lib.resolve(&code.strings), n.name(code))),
+            FunPtr::Fun(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{sample, BytecodeBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+
+    use crate::signatures::{InferredNames, Signatures, SignaturesError, DEFAULT_MIN_CONFIDENCE};
+
+    #[test]
+    fn default_signatures() {
+        let sigs = Signatures::default_signatures();
+        assert!(!sigs.signatures.is_empty());
+        assert_eq!(
+            Signatures::from_toml(&sigs.to_toml())
+                .unwrap()
+                .signatures
+                .len(),
+            sigs.signatures.len()
+        );
+    }
+
+    #[test]
+    fn invalid_signatures() {
+        assert!(matches!(
+            Signatures::from_toml("[[signature]]\nname = \"a\"\nargs = 1"),
+            Err(SignaturesError::Empty(_))
+        ));
+    }
+
+    #[test]
+    fn apply() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let f64_ = b.ty(Type::F64);
+        let time_t = b.fun_type(&[], f64_);
+        let sys_time = b.native("std", "sys_time", time_t);
+        let [time, big, main] = [(); 3].map(|_| b.findex());
+        let call = Opcode::Call0 {
+            dst: Reg(0),
+            fun: sys_time,
+        };
+        b.function(
+            time,
+            time_t,
+            vec![f64_],
+            vec![call.clone(), Opcode::Ret { ret: Reg(0) }],
+        );
+        // Calls the native too, but makes more calls than a wrapper
+        let ops = vec![
+            call.clone(),
+            call.clone(),
+            call,
+            Opcode::Ret { ret: Reg(0) },
+        ];
+        b.function(big, time_t, vec![f64_], ops);
+        let main_t = b.fun_type(&[], void);
+        let ops = vec![
+            Opcode::Call0 {
+                dst: Reg(1),
+                fun: time,
+            },
+            Opcode::Call0 {
+                dst: Reg(1),
+                fun: big,
+            },
+            Opcode::Ret { ret: Reg(0) },
+        ];
+        b.function(main, main_t, vec![void, f64_], ops);
+        b.entrypoint(main);
+        let code = b.build().unwrap();
+
+        let mut names = InferredNames::default();
+        let named =
+            Signatures::default_signatures().apply(&code, &mut names, DEFAULT_MIN_CONFIDENCE);
+        assert_eq!(named.len(), 1);
+        assert_eq!(names.name(&code, time), Some("Sys.time"));
+        assert_eq!(names.name(&code, big), None);
+        // Only the display changes
+        assert_eq!(time.name(&code), None);
+        assert_eq!(
+            time.display_id(&names.with(&code)).to_string(),
+            "Sys.time@1"
+        );
+    }
+
+    #[test]
+    fn apply_sample() {
+        let code = sample();
+        let mut names = InferredNames::default();
+        Signatures::default_signatures().apply(&code, &mut names, DEFAULT_MIN_CONFIDENCE);
+        assert_eq!(names.name(&code, code.entrypoint), None);
+    }
+}
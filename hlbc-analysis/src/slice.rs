@@ -0,0 +1,286 @@
+//! Data dependences between the instructions of a function, and program slices.
+//!
+//! [DataDeps] computes the reaching definitions of every register read, on the [Cfg](crate::cfg::Cfg). The backward
+//! slice of an instruction is every instruction its value depends on, the forward slice every instruction depending on
+//! the value it computes. Only registers are tracked : values going through memory (fields, arrays, references) and
+//! control dependences are not part of a slice.
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, Reg};
+
+use crate::cfg::{Cfg, EdgeKind};
+
+/// Definitions reaching each register read of a function
+#[derive(Debug, Clone)]
+pub struct DataDeps {
+    /// Register written by each instruction
+    defs: Vec<Option<Reg>>,
+    /// Definitions reaching the start of each block, indexed by instruction position
+    block_in: Vec<Vec<bool>>,
+    cfg: Cfg,
+    /// Registers read by each instruction with the instructions defining their value
+    deps: Vec<Vec<(Reg, Vec<usize>)>>,
+    /// Instructions reading the value written by each instruction
+    users: Vec<Vec<usize>>,
+}
+
+/// Register written by an instruction. The exception register of a [Trap](Opcode::Trap) is written when the
+/// handler is entered.
+fn def(o: &Opcode) -> Option<Reg> {
+    match o {
+        Opcode::Setref { .. } => None,
+        Opcode::Trap { exc, .. } => Some(*exc),
+        _ => o.dst(),
+    }
+}
+
+/// Registers read by an instruction
+fn uses(o: &Opcode) -> Vec<Reg> {
+    let mut regs = o.regs();
+    match o {
+        Opcode::Incr { .. } | Opcode::Decr { .. } | Opcode::Setref { .. } => {}
+        Opcode::Trap { .. } | Opcode::EndTrap { .. } => regs.clear(),
+        _ => {
+            // Only the destination field, it can be an operand too
+            if let Some(i) = o.dst().and_then(|dst| regs.iter().position(|&r| r == dst)) {
+                regs.remove(i);
+            }
+        }
+    }
+    regs.sort_unstable_by_key(|r| r.0);
+    regs.dedup();
+    regs
+}
+
+impl DataDeps {
+    pub fn new(f: &Function) -> Self {
+        let n = f.ops.len();
+        let cfg = Cfg::new(f);
+        let defs: Vec<Option<Reg>> = f.ops.iter().map(def).collect();
+        // Definitions of each register, to kill them
+        let mut reg_defs = vec![Vec::new(); f.regs.len()];
+        for (i, d) in defs.iter().enumerate() {
+            if let Some(r) = d {
+                reg_defs[r.0 as usize].push(i);
+            }
+        }
+        let transfer = |set: &mut [bool], i: usize| {
+            if let Some(r) = defs[i] {
+                for &d in &reg_defs[r.0 as usize] {
+                    set[d] = false;
+                }
+                set[i] = true;
+            }
+        };
+
+        let mut block_in = vec![vec![false; n]; cfg.blocks.len()];
+        let order = cfg.reverse_postorder();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in &order {
+                let block = &cfg.blocks[b];
+                let mut out = block_in[b].clone();
+                for i in block.start..block.end {
+                    transfer(&mut out, i);
+                }
+                // An exception can be thrown after any instruction of the block
+                let mut thrown = out.clone();
+                for (i, t) in thrown.iter_mut().enumerate() {
+                    *t |= block_in[b][i]
+                        || ((block.start..block.end).contains(&i) && defs[i].is_some());
+                }
+                for &(succ, kind) in &block.successors {
+                    let set = if kind == EdgeKind::Exception {
+                        &thrown
+                    } else {
+                        &out
+                    };
+                    for (t, &d) in block_in[succ].iter_mut().zip(set) {
+                        if d && !*t {
+                            *t = true;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut deps = vec![Vec::new(); n];
+        let mut users = vec![Vec::new(); n];
+        for &b in &order {
+            let block = &cfg.blocks[b];
+            let mut set = block_in[b].clone();
+            let range = block.start..block.end;
+            for (i, o) in range.clone().zip(&f.ops[range]) {
+                for r in uses(o) {
+                    let reaching: Vec<usize> = reg_defs
+                        .get(r.0 as usize)
+                        .into_iter()
+                        .flatten()
+                        .copied()
+                        .filter(|&d| set[d])
+                        .collect();
+                    for &d in &reaching {
+                        users[d].push(i);
+                    }
+                    deps[i].push((r, reaching));
+                }
+                transfer(&mut set, i);
+            }
+        }
+        for u in &mut users {
+            u.sort_unstable();
+            u.dedup();
+        }
+
+        Self {
+            defs,
+            block_in,
+            cfg,
+            deps,
+            users,
+        }
+    }
+
+    /// Instructions whose value of `reg` can be read before the instruction at `pos`. Empty if the register still
+    /// holds its initial value (an argument or the default value).
+    pub fn reaching(&self, pos: usize, reg: Reg) -> Vec<usize> {
+        let block = self.cfg.block_of(pos);
+        let mut set = self.block_in[block].clone();
+        for i in self.cfg.blocks[block].start..pos {
+            if let Some(r) = self.defs[i] {
+                for (d, s) in set.iter_mut().enumerate() {
+                    if self.defs[d] == Some(r) {
+                        *s = false;
+                    }
+                }
+                set[i] = true;
+            }
+        }
+        (0..set.len())
+            .filter(|&d| set[d] && self.defs[d] == Some(reg))
+            .collect()
+    }
+
+    /// Control flow graph the definitions are computed on
+    pub fn cfg(&self) -> &Cfg {
+        &self.cfg
+    }
+
+    /// Registers read by an instruction and the instructions defining their value
+    pub fn deps(&self, pos: usize) -> &[(Reg, Vec<usize>)] {
+        &self.deps[pos]
+    }
+
+    /// Instructions reading the value written by the instruction at `pos`
+    pub fn users(&self, pos: usize) -> &[usize] {
+        &self.users[pos]
+    }
+
+    /// Instructions the value of `reg` before `pos` depends on, or the instruction at `pos` and everything it
+    /// depends on if no register is given. Positions are sorted.
+    pub fn backward(&self, pos: usize, reg: Option<Reg>) -> Vec<usize> {
+        let start = match reg {
+            Some(reg) => self.reaching(pos, reg),
+            None => vec![pos],
+        };
+        self.closure(start, |i| {
+            self.deps[i]
+                .iter()
+                .flat_map(|(_, d)| d.iter().copied())
+                .collect()
+        })
+    }
+
+    /// The instruction at `pos` and every instruction depending on the value it writes. Positions are sorted.
+    pub fn forward(&self, pos: usize) -> Vec<usize> {
+        self.closure(vec![pos], |i| self.users[i].clone())
+    }
+
+    fn closure(&self, start: Vec<usize>, next: impl Fn(usize) -> Vec<usize>) -> Vec<usize> {
+        let mut seen = vec![false; self.defs.len()];
+        let mut todo = start;
+        while let Some(i) = todo.pop() {
+            if !seen[i] {
+                seen[i] = true;
+                todo.extend(next(i));
+            }
+        }
+        (0..seen.len()).filter(|&i| seen[i]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Function, Reg, Type};
+
+    use crate::slice::DataDeps;
+
+    /// ```text
+    /// 0: a = 1
+    /// 1: b = 2
+    /// 2: if x < a goto 5
+    /// 3: a = a + b
+    /// 4: goto 6
+    /// 5: b = x
+    /// 6: r = a * x
+    /// 7: ret r
+    /// ```
+    fn function() -> Function {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let ty = b.fun_type(&[i32_], i32_);
+        let one = b.int(1);
+        let two = b.int(2);
+        let findex = b.findex();
+        let mut f = FunctionBuilder::new(ty, &[i32_]);
+        let x = f.arg(0);
+        let [a, bb, r] = [(); 3].map(|_| f.reg(i32_));
+        let (other, join) = (f.label(), f.label());
+        f.emit(Opcode::Int { dst: a, ptr: one });
+        f.emit(Opcode::Int { dst: bb, ptr: two });
+        f.jump(
+            Opcode::JSLt {
+                a: x,
+                b: a,
+                offset: 0,
+            },
+            other,
+        );
+        f.emit(Opcode::Add { dst: a, a, b: bb });
+        f.jump(Opcode::JAlways { offset: 0 }, join);
+        f.place(other);
+        f.emit(Opcode::Mov { dst: bb, src: x });
+        f.place(join);
+        f.emit(Opcode::Mul { dst: r, a, b: x });
+        f.emit(Opcode::Ret { ret: r });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(findex, ty, regs, ops);
+        b.build().unwrap().functions.remove(0)
+    }
+
+    #[test]
+    fn reaching_definitions() {
+        let f = function();
+        let deps = DataDeps::new(&f);
+        assert_eq!(deps.reaching(6, Reg(1)), [0, 3]);
+        assert_eq!(deps.reaching(6, Reg(2)), [1, 5]);
+        // Arguments have no definition
+        assert_eq!(deps.deps(5), [(Reg(0), vec![])]);
+        assert_eq!(deps.users(0), [2, 3, 6]);
+    }
+
+    #[test]
+    fn slices() {
+        let f = function();
+        let deps = DataDeps::new(&f);
+        assert_eq!(deps.backward(7, None), [0, 1, 3, 6, 7]);
+        assert_eq!(deps.backward(6, Some(Reg(1))), [0, 1, 3]);
+        assert_eq!(deps.forward(1), [1, 3, 6, 7]);
+        // Nothing reads b after 5
+        assert_eq!(deps.forward(5), [5]);
+    }
+}
@@ -0,0 +1,333 @@
+//! Writer of the SQLite file format, for tables filled once and never modified.
+//!
+//! See <https://www.sqlite.org/fileformat2.html>. Rows are packed in table b-trees built bottom up, payloads too big
+//! for a page continue in overflow pages. There is no index, no free page and no journal : the file is written in a
+//! single pass and SQLite can modify it afterwards like any other database.
+
+use std::io;
+use std::io::Write;
+
+const PAGE_SIZE: usize = 4096;
+/// Space for the content of a page, there are no reserved bytes at the end of pages
+const USABLE: usize = PAGE_SIZE;
+/// Size of the database header, at the start of the first page
+const HEADER_SIZE: usize = 100;
+/// Largest payload stored in a table leaf without overflow
+const MAX_LOCAL: usize = USABLE - 35;
+/// Smallest payload stored in a table leaf when the rest overflows
+const MIN_LOCAL: usize = (USABLE - 12) * 32 / 255 - 23;
+/// Children of an interior page, the largest interior cell (4 bytes of pointer, 9 bytes of key, 2 bytes of offset)
+/// always fits this many times
+const MAX_CHILDREN: usize = (PAGE_SIZE - 12) / 15;
+
+const LEAF_TABLE: u8 = 0x0d;
+const INTERIOR_TABLE: u8 = 0x05;
+
+/// A column value
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Int(i64),
+    Text(String),
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Int(v)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(v: usize) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::Text(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::Text(v.to_owned())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+/// A table and its rows, by increasing rowid
+pub(crate) struct Table {
+    pub(crate) name: &'static str,
+    /// The `CREATE TABLE` statement, stored in the schema
+    pub(crate) sql: &'static str,
+    pub(crate) rows: Vec<(i64, Vec<Value>)>,
+}
+
+impl Table {
+    pub(crate) fn new(name: &'static str, sql: &'static str) -> Self {
+        Self {
+            name,
+            sql,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Add a row, numbered after the last one
+    pub(crate) fn push(&mut self, row: Vec<Value>) {
+        let rowid = self.rows.last().map_or(1, |(id, _)| id + 1);
+        self.rows.push((rowid, row));
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, v: u64) {
+    if v > 0x00ff_ffff_ffff_ffff {
+        // The 9th byte holds 8 bits
+        let mut bytes = [0u8; 9];
+        bytes[8] = v as u8;
+        let mut rest = v >> 8;
+        for b in bytes[..8].iter_mut().rev() {
+            *b = (rest as u8 & 0x7f) | 0x80;
+            rest >>= 7;
+        }
+        out.extend_from_slice(&bytes);
+        return;
+    }
+    let mut groups = Vec::with_capacity(8);
+    let mut rest = v;
+    loop {
+        groups.push((rest & 0x7f) as u8);
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    for (i, g) in groups.iter().enumerate().rev() {
+        out.push(if i == 0 { *g } else { g | 0x80 });
+    }
+}
+
+fn varint_len(v: u64) -> usize {
+    let mut buf = Vec::with_capacity(9);
+    write_varint(&mut buf, v);
+    buf.len()
+}
+
+/// Encode a row as a record : a header with the serial type of each column, then the values
+fn record(row: &[Value]) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut body = Vec::new();
+    for v in row {
+        match v {
+            Value::Null => write_varint(&mut header, 0),
+            Value::Int(0) => write_varint(&mut header, 8),
+            Value::Int(1) => write_varint(&mut header, 9),
+            &Value::Int(i) => {
+                let (serial, len) = match i {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                write_varint(&mut header, serial);
+                body.extend_from_slice(&i.to_be_bytes()[8 - len..]);
+            }
+            Value::Text(s) => {
+                write_varint(&mut header, 13 + 2 * s.len() as u64);
+                body.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+    // The size of the header includes itself
+    let mut size = header.len() + 1;
+    while varint_len(size as u64) + header.len() != size {
+        size = varint_len(size as u64) + header.len();
+    }
+    let mut out = Vec::with_capacity(size + body.len());
+    write_varint(&mut out, size as u64);
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&body);
+    out
+}
+
+/// Pages of the file, page `n` is at index `n - 1`
+struct Pages {
+    pages: Vec<Vec<u8>>,
+}
+
+impl Pages {
+    /// Allocate a page, returns its number
+    fn alloc(&mut self) -> u32 {
+        self.pages.push(vec![0; PAGE_SIZE]);
+        self.pages.len() as u32
+    }
+
+    fn page(&mut self, n: u32) -> &mut Vec<u8> {
+        &mut self.pages[n as usize - 1]
+    }
+
+    /// Cell of a table leaf, the end of a big payload is written to overflow pages
+    fn leaf_cell(&mut self, rowid: i64, payload: &[u8]) -> Vec<u8> {
+        let mut cell = Vec::new();
+        write_varint(&mut cell, payload.len() as u64);
+        write_varint(&mut cell, rowid as u64);
+        if payload.len() <= MAX_LOCAL {
+            cell.extend_from_slice(payload);
+            return cell;
+        }
+        let k = MIN_LOCAL + (payload.len() - MIN_LOCAL) % (USABLE - 4);
+        let local = if k <= MAX_LOCAL { k } else { MIN_LOCAL };
+        cell.extend_from_slice(&payload[..local]);
+
+        let chunks: Vec<&[u8]> = payload[local..].chunks(USABLE - 4).collect();
+        let first = self.pages.len() as u32 + 1;
+        cell.extend_from_slice(&first.to_be_bytes());
+        for (i, chunk) in chunks.iter().enumerate() {
+            let n = self.alloc();
+            let next = if i + 1 < chunks.len() { n + 1 } else { 0 };
+            let page = self.page(n);
+            page[..4].copy_from_slice(&next.to_be_bytes());
+            page[4..4 + chunk.len()].copy_from_slice(chunk);
+        }
+        cell
+    }
+
+    /// Fill a b-tree page with cells, `start` is where the page header begins
+    fn fill(&mut self, n: u32, start: usize, kind: u8, cells: &[Vec<u8>], right: Option<u32>) {
+        let page = self.page(n);
+        let header = if right.is_some() { 12 } else { 8 };
+        page[start] = kind;
+        page[start + 3..start + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+        let mut content = PAGE_SIZE;
+        for (i, cell) in cells.iter().enumerate() {
+            content -= cell.len();
+            page[content..content + cell.len()].copy_from_slice(cell);
+            let ptr = start + header + 2 * i;
+            page[ptr..ptr + 2].copy_from_slice(&(content as u16).to_be_bytes());
+        }
+        // 0 stands for 65536, only possible with bigger pages
+        page[start + 5..start + 7].copy_from_slice(&(content as u16).to_be_bytes());
+        if let Some(right) = right {
+            page[start + 8..start + 12].copy_from_slice(&right.to_be_bytes());
+        }
+    }
+
+    /// Write the b-tree of a table, returns its root page
+    fn table(&mut self, rows: &[(i64, Vec<Value>)]) -> u32 {
+        // Leaves, with the largest rowid of each
+        let mut level: Vec<(u32, i64)> = Vec::new();
+        let mut cells = Vec::new();
+        let mut used = 8;
+        let mut last = 0;
+        for (rowid, row) in rows {
+            let cell = self.leaf_cell(*rowid, &record(row));
+            if used + cell.len() + 2 > PAGE_SIZE {
+                let n = self.alloc();
+                self.fill(n, 0, LEAF_TABLE, &cells, None);
+                level.push((n, last));
+                cells.clear();
+                used = 8;
+            }
+            used += cell.len() + 2;
+            cells.push(cell);
+            last = *rowid;
+        }
+        if !cells.is_empty() || level.is_empty() {
+            let n = self.alloc();
+            self.fill(n, 0, LEAF_TABLE, &cells, None);
+            level.push((n, last));
+        }
+
+        // Interior pages until a single root remains
+        while level.len() > 1 {
+            let mut groups: Vec<Vec<(u32, i64)>> =
+                level.chunks(MAX_CHILDREN).map(<[_]>::to_vec).collect();
+            // An interior page needs a cell besides its right child
+            if groups.len() > 1 && groups.last().unwrap().len() == 1 {
+                let prev = groups.len() - 2;
+                let moved = groups[prev].pop().unwrap();
+                groups.last_mut().unwrap().insert(0, moved);
+            }
+            level = groups
+                .into_iter()
+                .map(|children| {
+                    let (&(right, max), left) = children.split_last().unwrap();
+                    let cells: Vec<Vec<u8>> = left
+                        .iter()
+                        .map(|&(child, key)| {
+                            let mut cell = child.to_be_bytes().to_vec();
+                            write_varint(&mut cell, key as u64);
+                            cell
+                        })
+                        .collect();
+                    let n = self.alloc();
+                    self.fill(n, 0, INTERIOR_TABLE, &cells, Some(right));
+                    (n, max)
+                })
+                .collect();
+        }
+        level[0].0
+    }
+}
+
+/// Write a database with these tables
+pub(crate) fn write(tables: &[Table], mut w: impl Write) -> io::Result<()> {
+    // The first page holds the header and the schema, it is filled last
+    let mut pages = Pages {
+        pages: vec![vec![0; PAGE_SIZE]],
+    };
+    let mut schema = Vec::new();
+    for (i, t) in tables.iter().enumerate() {
+        let root = pages.table(&t.rows);
+        let row = record(&[
+            "table".into(),
+            t.name.into(),
+            t.name.into(),
+            (root as i64).into(),
+            t.sql.into(),
+        ]);
+        schema.push(pages.leaf_cell(i as i64 + 1, &row));
+    }
+    if schema.iter().map(|c| c.len() + 2).sum::<usize>() + HEADER_SIZE + 8 > PAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The schema doesn't fit in the first page",
+        ));
+    }
+    pages.fill(1, HEADER_SIZE, LEAF_TABLE, &schema, None);
+
+    let count = pages.pages.len() as u32;
+    let header = &mut pages.page(1)[..HEADER_SIZE];
+    header[..16].copy_from_slice(b"SQLite format 3\0");
+    header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    // Legacy journal mode for reading and writing
+    header[18] = 1;
+    header[19] = 1;
+    // Payload fractions, fixed by the format
+    header[21] = 64;
+    header[22] = 32;
+    header[23] = 32;
+    // File change counter
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&count.to_be_bytes());
+    // Schema cookie and schema format
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    // UTF-8
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());
+    // The size in the header is valid for this change counter
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+    header[96..100].copy_from_slice(&3_040_000u32.to_be_bytes());
+
+    for page in &pages.pages {
+        w.write_all(page)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,281 @@
+//! Export of the structure of a bytecode as a SQLite database, to query it with SQL and join it with other data.
+//!
+//! JSON exports get unwieldy for big games, a database scales to millions of rows and any SQLite client can read it.
+//! Elements are identified by their index in the bytecode, the columns named `type`, `class`, `super` or `field_type`
+//! are indexes in `types`, `findex`, `caller` and `callee` are indexes in `functions`. The schema :
+//! ```sql
+//! -- Every type, `name` is its display (`i32`, `Player`, `(i32, bytes) -> void` ...)
+//! CREATE TABLE types (idx INTEGER PRIMARY KEY, kind TEXT NOT NULL, name TEXT NOT NULL, super INTEGER);
+//! -- Fields of classes, structs and virtuals, inherited fields are listed with the subclasses too
+//! CREATE TABLE fields (type INTEGER NOT NULL, idx INTEGER NOT NULL, name TEXT NOT NULL, field_type INTEGER NOT NULL);
+//! -- Functions and natives, `lib` is the library of a native and NULL for a function
+//! CREATE TABLE functions (findex INTEGER PRIMARY KEY, name TEXT, class INTEGER, type INTEGER NOT NULL,
+//!     signature TEXT NOT NULL, lib TEXT, ops INTEGER, regs INTEGER);
+//! CREATE TABLE strings (idx INTEGER PRIMARY KEY, value TEXT NOT NULL);
+//! CREATE TABLE globals (idx INTEGER PRIMARY KEY, type INTEGER NOT NULL);
+//! -- Instructions referencing a string, a global, a field or a type, see `xref::XrefIndex`.
+//! -- `kind` is 'string', 'global', 'field' or 'type', `target` the index of the element, `field` the index of the
+//! -- field in the type `target`.
+//! CREATE TABLE xrefs (kind TEXT NOT NULL, target INTEGER NOT NULL, field INTEGER, findex INTEGER NOT NULL,
+//!     pos INTEGER NOT NULL);
+//! -- Edges of the call graph, `kind` is 'direct', 'method', 'closure' or 'closure_creation'
+//! CREATE TABLE calls (caller INTEGER NOT NULL, callee INTEGER NOT NULL, kind TEXT NOT NULL);
+//! ```
+//! There are no indexes, create the ones your queries need :
+//! ```sql
+//! CREATE INDEX xrefs_target ON xrefs (kind, target);
+//! SELECT f.name, count(*) FROM xrefs x JOIN functions f USING (findex) WHERE x.kind = 'string' GROUP BY findex;
+//! ```
+
+use std::io;
+use std::io::Write;
+
+use hlbc::types::{RefType, Type};
+use hlbc::Bytecode;
+
+use crate::graph::{Call, Callgraph};
+use crate::sqlite::file::{Table, Value};
+use crate::xref::{XrefIndex, XrefTarget};
+
+mod file;
+
+/// The `CREATE TABLE` statements of the database, in order
+pub const SCHEMA: [&str; 7] = [
+    "CREATE TABLE types (idx INTEGER PRIMARY KEY, kind TEXT NOT NULL, name TEXT NOT NULL, super INTEGER)",
+    "CREATE TABLE fields (type INTEGER NOT NULL, idx INTEGER NOT NULL, name TEXT NOT NULL, field_type INTEGER NOT NULL)",
+    "CREATE TABLE functions (findex INTEGER PRIMARY KEY, name TEXT, class INTEGER, type INTEGER NOT NULL, signature TEXT NOT NULL, lib TEXT, ops INTEGER, regs INTEGER)",
+    "CREATE TABLE strings (idx INTEGER PRIMARY KEY, value TEXT NOT NULL)",
+    "CREATE TABLE globals (idx INTEGER PRIMARY KEY, type INTEGER NOT NULL)",
+    "CREATE TABLE xrefs (kind TEXT NOT NULL, target INTEGER NOT NULL, field INTEGER, findex INTEGER NOT NULL, pos INTEGER NOT NULL)",
+    "CREATE TABLE calls (caller INTEGER NOT NULL, callee INTEGER NOT NULL, kind TEXT NOT NULL)",
+];
+
+fn kind(ty: &Type) -> &'static str {
+    match ty {
+        Type::Fun(_) => "fun",
+        Type::Method(_) => "method",
+        Type::Obj(_) => "obj",
+        Type::Struct(_) => "struct",
+        Type::Virtual { .. } => "virtual",
+        Type::Abstract { .. } => "abstract",
+        Type::Enum { .. } => "enum",
+        Type::Ref(_) => "ref",
+        Type::Null(_) => "null",
+        Type::Packed(_) => "packed",
+        _ => "primitive",
+    }
+}
+
+/// Write the database of a bytecode
+pub fn export(code: &Bytecode, w: impl Write) -> io::Result<()> {
+    let mut tables = SCHEMA.map(|sql| {
+        let name = sql["CREATE TABLE ".len()..].split(' ').next().unwrap();
+        Table::new(name, sql)
+    });
+    let [types, fields, functions, strings, globals, xrefs, calls] = &mut tables;
+
+    for (i, ty) in code.types.iter().enumerate() {
+        let obj = ty.get_type_obj();
+        types.rows.push((
+            i as i64,
+            vec![
+                Value::Null,
+                kind(ty).into(),
+                RefType(i).display(code).into(),
+                obj.and_then(|o| o.super_).map(|s| s.0).into(),
+            ],
+        ));
+        let own = match ty {
+            Type::Virtual { fields } => fields,
+            _ => obj.map_or(&[][..], |o| &o.fields),
+        };
+        for (idx, f) in own.iter().enumerate() {
+            fields.push(vec![
+                i.into(),
+                idx.into(),
+                f.name.resolve(&code.strings).into(),
+                f.t.0.into(),
+            ]);
+        }
+    }
+
+    let mut funs: Vec<(usize, Vec<Value>)> = code
+        .functions
+        .iter()
+        .map(|f| {
+            (
+                f.findex.0,
+                vec![
+                    Value::Null,
+                    f.name(code).into(),
+                    f.parent.map(|p| p.0).into(),
+                    f.t.0.into(),
+                    f.t.display(code).into(),
+                    Value::Null,
+                    f.ops.len().into(),
+                    f.regs.len().into(),
+                ],
+            )
+        })
+        .chain(code.natives.iter().map(|n| {
+            (
+                n.findex.0,
+                vec![
+                    Value::Null,
+                    n.name(code).into(),
+                    Value::Null,
+                    n.t.0.into(),
+                    n.t.display(code).into(),
+                    n.lib.resolve(&code.strings).into(),
+                    Value::Null,
+                    Value::Null,
+                ],
+            )
+        }))
+        .collect();
+    funs.sort_by_key(|(findex, _)| *findex);
+    functions.rows = funs.into_iter().map(|(i, row)| (i as i64, row)).collect();
+
+    strings.rows = code
+        .strings
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (i as i64, vec![Value::Null, s.to_string().into()]))
+        .collect();
+    globals.rows = code
+        .globals
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (i as i64, vec![Value::Null, t.0.into()]))
+        .collect();
+
+    let index = XrefIndex::new(code);
+    let mut refs: Vec<(&str, usize, Option<usize>, usize, usize)> = index
+        .iter()
+        .flat_map(|(target, xrefs)| {
+            let (kind, target, field) = match target {
+                XrefTarget::String(s) => ("string", s.0, None),
+                XrefTarget::Global(g) => ("global", g.0, None),
+                XrefTarget::Field(t, f) => ("field", t.0, Some(f.0)),
+                XrefTarget::Type(t) => ("type", t.0, None),
+            };
+            xrefs
+                .iter()
+                .map(move |&(f, pos)| (kind, target, field, f.0, pos))
+        })
+        .collect();
+    refs.sort_unstable();
+    for (kind, target, field, findex, pos) in refs {
+        xrefs.push(vec![
+            kind.into(),
+            target.into(),
+            field.into(),
+            findex.into(),
+            pos.into(),
+        ]);
+    }
+
+    let graph = Callgraph::new(code);
+    let mut edges: Vec<_> = graph.graph.all_edges().collect();
+    edges.sort_unstable_by_key(|(caller, callee, _)| (*caller, *callee));
+    for (caller, callee, call) in edges {
+        let kind = match call {
+            Call::Direct => "direct",
+            Call::Method => "method",
+            Call::Closure => "closure",
+            Call::ClosureCreation => "closure_creation",
+            Call::Reflection => "reflection",
+        };
+        calls.push(vec![caller.0.into(), callee.0.into(), kind.into()]);
+    }
+
+    file::write(&tables, w)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+    use rusqlite::Connection;
+
+    use crate::graph::Callgraph;
+    use crate::sqlite::export;
+    use crate::sqlite::file::{write, Table, Value};
+    use crate::xref::XrefIndex;
+
+    /// Open a database with SQLite and check its integrity
+    fn open(db: &[u8], name: &str) -> Connection {
+        let path = std::env::temp_dir().join(format!("hlbc-{name}-{}.db", std::process::id()));
+        std::fs::write(&path, db).unwrap();
+        let conn = Connection::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let check: String = conn
+            .query_row("PRAGMA integrity_check", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(check, "ok");
+        conn
+    }
+
+    fn count(conn: &Connection, table: &str) -> usize {
+        conn.query_row(&format!("SELECT count(*) FROM {table}"), [], |r| r.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn export_sample() {
+        let code = sample();
+        let mut db = Vec::new();
+        export(&code, &mut db).unwrap();
+        assert!(db.starts_with(b"SQLite format 3\0"));
+        assert_eq!(db.len() % 4096, 0);
+        let pages = u32::from_be_bytes(db[28..32].try_into().unwrap());
+        assert_eq!(pages as usize, db.len() / 4096);
+
+        let conn = open(&db, "export");
+        assert_eq!(count(&conn, "types"), code.types.len());
+        assert_eq!(
+            count(&conn, "functions"),
+            code.functions.len() + code.natives.len()
+        );
+        assert_eq!(count(&conn, "strings"), code.strings.len());
+        assert_eq!(count(&conn, "globals"), code.globals.len());
+        let xrefs: usize = XrefIndex::new(&code).iter().map(|(_, x)| x.len()).sum();
+        assert_eq!(count(&conn, "xrefs"), xrefs);
+        assert_eq!(
+            count(&conn, "calls"),
+            Callgraph::new(&code).graph.edge_count()
+        );
+        let name: String = conn
+            .query_row(
+                "SELECT value FROM strings WHERE idx = ?1",
+                [code.strings.len() as i64 - 1],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(name, &*code.strings[code.strings.len() - 1]);
+    }
+
+    #[test]
+    fn big_table() {
+        // Enough rows for interior pages and text overflowing a page
+        let mut t = Table::new("t", "CREATE TABLE t (v TEXT)");
+        for i in 0..5000 {
+            t.push(vec![Value::Text("x".repeat(i % 17 * 500))]);
+        }
+        let mut db = Vec::new();
+        write(&[t], &mut db).unwrap();
+        // The root of the table is written last
+        let root = db.len() / 4096 - 1;
+        assert_eq!(db[root * 4096], 0x05);
+
+        let conn = open(&db, "big");
+        assert_eq!(count(&conn, "t"), 5000);
+        let total: i64 = conn
+            .query_row("SELECT sum(length(v)) FROM t", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(total, (0..5000).map(|i| i % 17 * 500).sum::<i64>());
+        let longest: String = conn
+            .query_row("SELECT v FROM t WHERE rowid = 17", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(longest, "x".repeat(16 * 500));
+    }
+}
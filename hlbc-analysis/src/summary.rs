@@ -0,0 +1,155 @@
+//! Class cards, a short summary of what a class does.
+//!
+//! A card lists the fields of a class, its methods biggest first, the natives they call and the strings they
+//! reference. It gives an overview of the responsibilities of a class before reading its code, and can be exported as
+//! Markdown to write notes.
+
+use std::cmp::Reverse;
+use std::fmt::Write;
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{FunPtr, ObjField, RefFun, RefString, RefType};
+use hlbc::Bytecode;
+
+/// Number of methods and strings listed in the Markdown export, the others are only counted
+pub const MARKDOWN_MAX_ITEMS: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct ClassCard {
+    pub ty: RefType,
+    pub name: String,
+    pub super_: Option<RefType>,
+    /// Fields declared by this class, without the inherited ones
+    pub fields: Vec<ObjField>,
+    /// Methods with their number of instructions, biggest first
+    pub methods: Vec<(RefFun, usize)>,
+    /// Natives called by the methods, in order of first call
+    pub natives: Vec<RefFun>,
+    /// Strings loaded by the methods, in order of first use
+    pub strings: Vec<RefString>,
+}
+
+impl ClassCard {
+    /// Summarize a class, returns `None` if the type is not a class
+    pub fn new(code: &Bytecode, ty: RefType) -> Option<Self> {
+        let obj = ty.resolve_as_obj(&code.types)?;
+        let mut methods = Vec::new();
+        let mut natives = Vec::new();
+        let mut strings = Vec::new();
+        for f in code.functions.iter().filter(|f| f.parent == Some(ty)) {
+            methods.push((f.findex, f.ops.len()));
+            for o in &f.ops {
+                if let Opcode::String { ptr, .. } = o {
+                    if !strings.contains(ptr) {
+                        strings.push(*ptr);
+                    }
+                }
+            }
+            for (_, _, fun) in f.find_fun_refs() {
+                if matches!(fun.resolve(code), FunPtr::Native(_)) && !natives.contains(&fun) {
+                    natives.push(fun);
+                }
+            }
+        }
+        // Stable, methods of the same size stay in declaration order
+        methods.sort_by_key(|&(_, size)| Reverse(size));
+        Some(Self {
+            ty,
+            name: obj.name.resolve(&code.strings).to_owned(),
+            super_: obj.super_,
+            fields: obj.own_fields.clone(),
+            methods,
+            natives,
+            strings,
+        })
+    }
+
+    /// Cards of every class of the program
+    pub fn all(code: &Bytecode) -> Vec<Self> {
+        (0..code.types.len())
+            .filter_map(|i| Self::new(code, RefType(i)))
+            .collect()
+    }
+
+    /// Render the card as a Markdown section
+    pub fn to_markdown(&self, code: &Bytecode) -> String {
+        let mut md = format!("## {}\n\n", self.name);
+        if let Some(super_) = self.super_ {
+            let _ = writeln!(md, "extends `{}`\n", super_.display(code));
+        }
+
+        if !self.fields.is_empty() {
+            md.push_str("### Fields\n\n");
+            for f in &self.fields {
+                let _ = writeln!(
+                    md,
+                    "- `{}` : `{}`",
+                    f.name.resolve(&code.strings),
+                    f.t.display(code)
+                );
+            }
+            md.push('\n');
+        }
+
+        list(&mut md, "Methods", &self.methods, |md, &(f, size)| {
+            let _ = write!(md, "`{}` ({size} instructions)", f.name_default(code));
+        });
+        list(&mut md, "Natives", &self.natives, |md, n| {
+            if let FunPtr::Native(n) = n.resolve(code) {
+                let _ = write!(md, "`{}/{}`", n.lib.resolve(&code.strings), n.name(code));
+            }
+        });
+        list(&mut md, "Strings", &self.strings, |md, s| {
+            let _ = write!(md, "{:?}", s.resolve(&code.strings));
+        });
+        md
+    }
+}
+
+/// A Markdown list with a heading, truncated to [MARKDOWN_MAX_ITEMS]
+fn list<T>(md: &mut String, title: &str, items: &[T], mut item: impl FnMut(&mut String, &T)) {
+    if items.is_empty() {
+        return;
+    }
+    let _ = writeln!(md, "### {title}\n");
+    for i in items.iter().take(MARKDOWN_MAX_ITEMS) {
+        md.push_str("- ");
+        item(md, i);
+        md.push('\n');
+    }
+    if items.len() > MARKDOWN_MAX_ITEMS {
+        let _ = writeln!(md, "- ... and {} more", items.len() - MARKDOWN_MAX_ITEMS);
+    }
+    md.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+    use hlbc::types::RefType;
+
+    use crate::summary::ClassCard;
+
+    #[test]
+    fn class_card() {
+        let code = sample();
+        let point = (0..code.types.len())
+            .map(RefType)
+            .find(|t| {
+                t.resolve_as_obj(&code.types)
+                    .map_or(false, |o| o.name.resolve(&code.strings) == "Point")
+            })
+            .unwrap();
+        let card = ClassCard::new(&code, point).unwrap();
+        assert_eq!(card.fields.len(), 2);
+        assert_eq!(card.methods.len(), 2);
+        assert!(card.methods[0].1 >= card.methods[1].1);
+        assert!(ClassCard::all(&code).iter().any(|c| c.ty == point));
+
+        let md = card.to_markdown(&code);
+        assert!(md.starts_with("## Point\n"));
+        assert!(md.contains("- `x` : `i32`"));
+        assert!(md.contains("### Methods"));
+        assert!(ClassCard::new(&code, RefType(0)).is_none());
+    }
+}
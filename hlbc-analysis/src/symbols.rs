@@ -0,0 +1,517 @@
+//! Import of symbol maps written by other tools, to apply names found in another disassembler or collected by the
+//! community in a spreadsheet as [renames](crate::renames).
+//!
+//! Symbols designate elements by index. The supported formats :
+//! - CSV, one symbol per line as `kind,index,name` with the kinds `fn`, `type` and `field` (indexed `type.field`, the
+//!   field index includes the inherited fields), or `findex,name`. A header line and lines starting with `#` are
+//!   ignored.
+//! - IDA, the `set_name(address, "name")` and `MakeName(address, "name")` calls of an IDC or IDAPython script
+//! - Ghidra, the CSV export of the symbol table with its `Name` and `Location` columns
+//!
+//! IDA and Ghidra only name functions, the address of a symbol is taken as the findex of the function. Their default
+//! names (`sub_1a`, `FUN_0000001a`) are skipped.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc_analysis::renames::RenameKind;
+//! use hlbc_analysis::symbols::{parse, to_renames, SymbolFormat};
+//!
+//! let code = sample();
+//! let text = "kind,index,name\nfn,0,add\n";
+//! assert_eq!(SymbolFormat::detect(text), SymbolFormat::Csv);
+//! let symbols = parse(SymbolFormat::Csv, text).unwrap();
+//! let imported = to_renames(&code, &symbols, "csv:names.csv");
+//! // The function has no name, it is designated by its findex
+//! assert_eq!(imported.renames.functions["@0"], "add");
+//! assert_eq!(
+//!     imported.renames.source(RenameKind::Function, "@0"),
+//!     Some("csv:names.csv")
+//! );
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use hlbc::types::{RefFun, RefFunKnown, RefType, Type};
+use hlbc::Bytecode;
+
+use crate::renames::{RenameKind, RenameMap};
+
+/// Format of a symbol map
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SymbolFormat {
+    Csv,
+    Ida,
+    Ghidra,
+}
+
+impl SymbolFormat {
+    /// Guess the format of a file from its content
+    pub fn detect(text: &str) -> Self {
+        if text.contains("set_name(") || text.contains("MakeName(") {
+            SymbolFormat::Ida
+        } else if text.trim_start().starts_with("\"Name\"") {
+            SymbolFormat::Ghidra
+        } else {
+            SymbolFormat::Csv
+        }
+    }
+}
+
+impl FromStr for SymbolFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(SymbolFormat::Csv),
+            "ida" => Ok(SymbolFormat::Ida),
+            "ghidra" => Ok(SymbolFormat::Ghidra),
+            _ => Err(format!(
+                "Unknown symbol format '{s}', expected csv, ida or ghidra"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SymbolFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SymbolFormat::Csv => "csv",
+            SymbolFormat::Ida => "ida",
+            SymbolFormat::Ghidra => "ghidra",
+        })
+    }
+}
+
+/// Element named by a symbol
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SymbolTarget {
+    Fun(RefFun),
+    Type(RefType),
+    /// A field of a class, the index includes the inherited fields
+    Field(RefType, usize),
+}
+
+impl fmt::Display for SymbolTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolTarget::Fun(findex) => write!(f, "fn@{}", findex.0),
+            SymbolTarget::Type(ty) => write!(f, "type@{}", ty.0),
+            SymbolTarget::Field(ty, field) => write!(f, "type@{}.{field}", ty.0),
+        }
+    }
+}
+
+/// A name given to an element by a symbol map
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Symbol {
+    pub target: SymbolTarget,
+    pub name: String,
+    /// Line in the file, starting at 1
+    pub line: usize,
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("Invalid symbol at line {line} : {message}")]
+pub struct SymbolError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Parse a symbol map
+pub fn parse(format: SymbolFormat, text: &str) -> Result<Vec<Symbol>, SymbolError> {
+    match format {
+        SymbolFormat::Csv => parse_csv(text),
+        SymbolFormat::Ida => Ok(parse_ida(text)),
+        SymbolFormat::Ghidra => parse_ghidra(text),
+    }
+}
+
+/// Lines with their number, without the empty lines and comments
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+}
+
+/// Fields of a CSV line, quoted fields can contain commas and `""` for a quote
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    fields.iter_mut().for_each(|f| *f = f.trim().to_owned());
+    fields
+}
+
+/// A decimal index or an hexadecimal address
+fn number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Symbol>, SymbolError> {
+    let mut symbols = Vec::new();
+    for (n, (line, l)) in lines(text).enumerate() {
+        let fields = csv_fields(l);
+        let target = match fields.as_slice() {
+            [index, _] => number(index).map(|i| SymbolTarget::Fun(RefFun(i))),
+            [kind, index, _] => match kind.as_str() {
+                "fn" => number(index).map(|i| SymbolTarget::Fun(RefFun(i))),
+                "type" => number(index).map(|i| SymbolTarget::Type(RefType(i))),
+                "field" => index.split_once('.').and_then(|(ty, field)| {
+                    Some(SymbolTarget::Field(RefType(number(ty)?), number(field)?))
+                }),
+                _ => None,
+            },
+            _ => None,
+        };
+        match target {
+            Some(target) => symbols.push(Symbol {
+                target,
+                name: fields.last().unwrap().clone(),
+                line,
+            }),
+            // The header
+            None if n == 0 => {}
+            None => {
+                return Err(SymbolError {
+                    line,
+                    message: format!("expected 'kind,index,name' or 'findex,name', found '{l}'"),
+                })
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+fn is_default_name(name: &str) -> bool {
+    ["sub_", "FUN_"].iter().any(|p| {
+        name.strip_prefix(p)
+            .map_or(false, |a| number(&format!("0x{a}")).is_some())
+    })
+}
+
+fn parse_ida(text: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for (line, l) in lines(text) {
+        // set_name(0x1a, "name", SN_NOWARN) or MakeName(0x1a, "name")
+        let Some(args) = ["set_name(", "MakeName("]
+            .iter()
+            .find_map(|call| l.find(call).map(|i| &l[i + call.len()..]))
+        else {
+            continue;
+        };
+        let Some((address, rest)) = args.split_once(',') else {
+            continue;
+        };
+        let name = rest
+            .trim_start()
+            .strip_prefix('"')
+            .and_then(|rest| rest.split_once('"'))
+            .map(|(name, _)| name);
+        if let (Some(findex), Some(name)) = (number(address.trim()), name) {
+            if !name.is_empty() && !is_default_name(name) {
+                symbols.push(Symbol {
+                    target: SymbolTarget::Fun(RefFun(findex)),
+                    name: name.to_owned(),
+                    line,
+                });
+            }
+        }
+    }
+    symbols
+}
+
+fn parse_ghidra(text: &str) -> Result<Vec<Symbol>, SymbolError> {
+    let mut lines = lines(text);
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let columns = csv_fields(header);
+    let column = |name: &str| columns.iter().position(|c| c == name);
+    let (Some(name_col), Some(location_col)) = (column("Name"), column("Location")) else {
+        return Err(SymbolError {
+            line: 1,
+            message: "expected the columns 'Name' and 'Location'".to_owned(),
+        });
+    };
+    let type_col = column("Type");
+
+    let mut symbols = Vec::new();
+    for (line, l) in lines {
+        let fields = csv_fields(l);
+        if type_col.map_or(false, |c| {
+            fields.get(c).map(String::as_str) != Some("Function")
+        }) {
+            continue;
+        }
+        let (Some(name), Some(location)) = (fields.get(name_col), fields.get(location_col)) else {
+            return Err(SymbolError {
+                line,
+                message: format!("missing columns in '{l}'"),
+            });
+        };
+        // Addresses can be prefixed with their address space, `ram:0000001a`
+        let address = location.rsplit(':').next().unwrap_or(location);
+        let Some(findex) = number(&format!("0x{address}")) else {
+            return Err(SymbolError {
+                line,
+                message: format!("invalid location '{location}'"),
+            });
+        };
+        if !is_default_name(name) {
+            symbols.push(Symbol {
+                target: SymbolTarget::Fun(RefFun(findex)),
+                name: name.clone(),
+                line,
+            });
+        }
+    }
+    Ok(symbols)
+}
+
+/// Result of [to_renames]
+#[derive(Debug, Clone, Default)]
+pub struct Imported {
+    pub renames: RenameMap,
+    /// Symbols not designating a function, class or field of the bytecode, with the reason
+    pub invalid: Vec<(Symbol, String)>,
+    /// Symbols naming an element already named differently by a previous symbol of the map, which is kept
+    pub duplicates: Vec<Symbol>,
+}
+
+/// Convert symbols to renames of the elements of a bytecode, whose entries come from `source`. Elements are
+/// designated by their name when it is unique, by their index otherwise. `code` must be the bytecode before renames,
+/// the entries of a rename map refer to the names in the file.
+pub fn to_renames(code: &Bytecode, symbols: &[Symbol], source: &str) -> Imported {
+    let index = code.function_index();
+    let mut imported = Imported::default();
+    let mut targets: Vec<(SymbolTarget, &str)> = Vec::new();
+
+    for s in symbols {
+        if let Some(&(_, name)) = targets.iter().find(|(t, _)| *t == s.target) {
+            if name != s.name {
+                imported.duplicates.push(s.clone());
+            }
+            continue;
+        }
+        let key = match s.target {
+            SymbolTarget::Fun(findex) => match code.findexes.get(findex.0) {
+                Some(RefFunKnown::Fun(_)) => {
+                    Ok((RenameKind::Function, fun_key(code, &index, findex)))
+                }
+                Some(RefFunKnown::Native(_)) => Err("natives can't be renamed".to_owned()),
+                None => Err(format!("there are {} functions", code.findexes.len())),
+            },
+            SymbolTarget::Type(ty) => class_key(code, ty).map(|key| (RenameKind::Class, key)),
+            SymbolTarget::Field(ty, field) => class_key(code, ty).and_then(|class| {
+                let fields = &ty.resolve_as_obj(&code.types).unwrap().fields;
+                let Some(f) = fields.get(field) else {
+                    return Err(format!("the class has {} fields", fields.len()));
+                };
+                let name = f.name.resolve(&code.strings);
+                // Fields are found by name
+                if fields
+                    .iter()
+                    .position(|f| f.name.resolve(&code.strings) == name)
+                    != Some(field)
+                {
+                    return Err(format!(
+                        "the name of the field '{name}' isn't unique in the class"
+                    ));
+                }
+                Ok((RenameKind::Field, format!("{class}.{name}")))
+            }),
+        };
+        match key {
+            Ok((kind, key)) => {
+                let renames = &mut imported.renames;
+                renames
+                    .sources
+                    .insert(format!("{kind} {key}"), source.to_owned());
+                match kind {
+                    RenameKind::Class => &mut renames.classes,
+                    RenameKind::Function => &mut renames.functions,
+                    RenameKind::Field => &mut renames.fields,
+                }
+                .insert(key, s.name.clone());
+                targets.push((s.target, &s.name));
+            }
+            Err(reason) => imported.invalid.push((s.clone(), reason)),
+        }
+    }
+    imported
+}
+
+/// Qualified name of a function if it designates it alone, `@findex` otherwise
+fn fun_key(code: &Bytecode, index: &hlbc::lookup::FunctionIndex, findex: RefFun) -> String {
+    let key = findex.resolve_as_fn(code).and_then(|f| {
+        let name = f.name(code)?;
+        let class = f
+            .parent
+            .and_then(|p| p.resolve_as_obj(&code.types))
+            .map(|obj| obj.name.resolve(&code.strings));
+        Some(match class {
+            Some(class) => format!("{}.{name}", class.strip_prefix('$').unwrap_or(class)),
+            None => name.to_owned(),
+        })
+    });
+    match key {
+        Some(key) if index.lookup(code, &key) == [findex] => key,
+        _ => format!("@{}", findex.0),
+    }
+}
+
+/// Name of a class if it designates it alone, `@index` otherwise
+fn class_key(code: &Bytecode, ty: RefType) -> Result<String, String> {
+    let Some(obj) = code.types.get(ty.0).and_then(Type::get_type_obj) else {
+        return Err(format!("type@{} is not a class", ty.0));
+    };
+    let name = obj.name.resolve(&code.strings);
+    let unique = code
+        .types
+        .iter()
+        .filter_map(Type::get_type_obj)
+        .filter(|o| o.name.resolve(&code.strings) == name)
+        .count()
+        == 1;
+    Ok(if unique && !name.is_empty() && !name.starts_with('@') {
+        name.to_owned()
+    } else {
+        format!("@{}", ty.0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::BytecodeBuilder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefFun, RefType, Reg, Type};
+
+    use crate::renames::{Conflict, OnConflict, RenameKind, RenameMap};
+    use crate::symbols::{parse, to_renames, SymbolFormat, SymbolTarget};
+
+    #[test]
+    fn formats() {
+        let csv = "kind,index,name\n# comment\nfn,3,update\n\"type\",0x2,\"game.Player\"\nfield,2.1,\"hp, max\"\n7,tick\n";
+        assert_eq!(SymbolFormat::detect(csv), SymbolFormat::Csv);
+        let symbols = parse(SymbolFormat::Csv, csv).unwrap();
+        let targets: Vec<_> = symbols
+            .iter()
+            .map(|s| (s.target, s.name.as_str(), s.line))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (SymbolTarget::Fun(RefFun(3)), "update", 3),
+                (SymbolTarget::Type(RefType(2)), "game.Player", 4),
+                (SymbolTarget::Field(RefType(2), 1), "hp, max", 5),
+                (SymbolTarget::Fun(RefFun(7)), "tick", 6),
+            ]
+        );
+        assert_eq!(
+            parse(SymbolFormat::Csv, "fn,1,a\nnope\n").unwrap_err().line,
+            2
+        );
+
+        let idc = "static main() {\n  set_name(0x1A, \"update\", SN_NOWARN);\n  MakeName(0x1B, \"sub_1B\");\n}\n";
+        assert_eq!(SymbolFormat::detect(idc), SymbolFormat::Ida);
+        let symbols = parse(SymbolFormat::Ida, idc).unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(
+            (symbols[0].target, symbols[0].line),
+            (SymbolTarget::Fun(RefFun(26)), 2)
+        );
+
+        let ghidra = "\"Name\",\"Location\",\"Type\"\n\"update\",\"ram:0000001a\",\"Function\"\n\"FUN_0000001b\",\"0000001b\",\"Function\"\n\"DAT_1\",\"00000001\",\"Data Label\"\n";
+        assert_eq!(SymbolFormat::detect(ghidra), SymbolFormat::Ghidra);
+        let symbols = parse(SymbolFormat::Ghidra, ghidra).unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(
+            (symbols[0].target, symbols[0].name.as_str()),
+            (SymbolTarget::Fun(RefFun(26)), "update")
+        );
+    }
+
+    #[test]
+    fn import() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let i32_ = b.ty(Type::I32);
+        let c = b.findex();
+        let anon = b.findex();
+        let class = b.class("a.b", None, &[("d", i32_)], &[("c", c)]);
+        let ty = b.method_type(&[class], void);
+        for f in [c, anon] {
+            b.function(f, ty, vec![class, void], vec![Opcode::Ret { ret: Reg(1) }]);
+        }
+        let code = b.build().unwrap();
+
+        let csv = format!(
+            "fn,{},update\nfn,{},callback\nfn,{},other\ntype,{},game.Player\nfield,{}.0,health\ntype,{},nope\nfn,999,nope\n",
+            c.0, anon.0, c.0, class.0, class.0, i32_.0
+        );
+        let symbols = parse(SymbolFormat::Csv, &csv).unwrap();
+        let imported = to_renames(&code, &symbols, "csv:community.csv");
+        assert_eq!(imported.renames.functions["a.b.c"], "update");
+        assert_eq!(
+            imported.renames.functions[&format!("@{}", anon.0)],
+            "callback"
+        );
+        assert_eq!(imported.renames.classes["a.b"], "game.Player");
+        assert_eq!(imported.renames.fields["a.b.d"], "health");
+        assert_eq!(imported.duplicates.len(), 1);
+        assert_eq!(imported.invalid.len(), 2);
+
+        let mut map = RenameMap::new();
+        map.insert(RenameKind::Class, "a.b", "Player");
+        let conflicts = map.import(&imported.renames, OnConflict::Keep);
+        assert_eq!(
+            conflicts,
+            [Conflict {
+                kind: RenameKind::Class,
+                name: "a.b".to_owned(),
+                current: "Player".to_owned(),
+                imported: "game.Player".to_owned(),
+            }]
+        );
+        assert_eq!(map.classes["a.b"], "Player");
+        assert_eq!(map.source(RenameKind::Class, "a.b"), None);
+        assert_eq!(
+            map.source(RenameKind::Field, "a.b.d"),
+            Some("csv:community.csv")
+        );
+        map.import(&imported.renames, OnConflict::Replace);
+        assert_eq!(map.classes["a.b"], "game.Player");
+        assert_eq!(
+            map.source(RenameKind::Class, "a.b"),
+            Some("csv:community.csv")
+        );
+        // Renaming by hand forgets the source
+        map.insert(RenameKind::Class, "game.Player", "Hero");
+        assert_eq!(map.source(RenameKind::Class, "a.b"), None);
+
+        let map = RenameMap::from_toml(&map.to_toml()).unwrap();
+        let mut code = code;
+        let applied = map.apply(&mut code);
+        assert!(applied.missing.is_empty());
+        assert_eq!(
+            anon.resolve_as_fn(&code).unwrap().name(&code),
+            Some("callback")
+        );
+    }
+}
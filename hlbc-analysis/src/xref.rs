@@ -0,0 +1,253 @@
+//! Cross-references index, which instructions use a string, a global, a field or a type.
+//!
+//! The index is built with a single pass over every instruction, queries are then a lookup.
+
+use std::collections::HashMap;
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{RefField, RefFun, RefGlobal, RefString, RefType, Reg};
+use hlbc::{version, Bytecode};
+
+use crate::reflection::Reflection;
+
+/// An instruction referencing an element : the function and the position of the instruction
+pub type Xref = (RefFun, usize);
+
+/// How an instruction uses a field
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// An element referenced by instructions
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum XrefTarget {
+    String(RefString),
+    Global(RefGlobal),
+    /// A field of an object type, the type declaring it
+    Field(RefType, RefField),
+    Type(RefType),
+}
+
+/// References to strings, globals, fields and types from the code, in instruction order
+#[derive(Debug, Clone, Default)]
+pub struct XrefIndex {
+    strings: HashMap<RefString, Vec<Xref>>,
+    globals: HashMap<RefGlobal, Vec<Xref>>,
+    fields: HashMap<(RefType, RefField), Vec<Xref>>,
+    types: HashMap<RefType, Vec<Xref>>,
+}
+
+impl XrefIndex {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut index = Self::default();
+        for (f, (pos, o)) in code.ops() {
+            let at = (f.findex, pos);
+            match *o {
+                Opcode::String { ptr, .. } => index.strings.entry(ptr).or_default().push(at),
+                // Bytes are strings before v5
+                Opcode::Bytes { ptr, .. } if !version::has_bytes(code.version) => {
+                    index.strings.entry(RefString(ptr.0)).or_default().push(at)
+                }
+                Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                    index.strings.entry(field).or_default().push(at)
+                }
+                Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                    index.globals.entry(global).or_default().push(at)
+                }
+                Opcode::Field { obj, field, .. } | Opcode::SetField { obj, field, .. } => {
+                    let owner = owner(code, f.regtype(obj), field);
+                    index.fields.entry((owner, field)).or_default().push(at)
+                }
+                Opcode::GetThis { field, .. } | Opcode::SetThis { field, .. } => {
+                    let owner = owner(code, f.regtype(Reg(0)), field);
+                    index.fields.entry((owner, field)).or_default().push(at)
+                }
+                Opcode::Type { ty, .. } => index.types.entry(ty).or_default().push(at),
+                Opcode::New { dst }
+                | Opcode::SafeCast { dst, .. }
+                | Opcode::UnsafeCast { dst, .. }
+                | Opcode::ToVirtual { dst, .. }
+                | Opcode::MakeEnum { dst, .. }
+                | Opcode::EnumAlloc { dst, .. } => {
+                    index.types.entry(f.regtype(dst)).or_default().push(at)
+                }
+                _ => {}
+            }
+        }
+        // Classes resolved by name are used by the call to Type.resolveClass
+        for u in Reflection::new(code).uses {
+            for ty in u.types {
+                let xrefs = index.types.entry(ty).or_default();
+                xrefs.push((u.fun, u.pos));
+                xrefs.sort_unstable();
+            }
+        }
+        index
+    }
+
+    /// Instructions loading the string, or accessing a dynamic field with this name
+    pub fn string(&self, s: RefString) -> &[Xref] {
+        self.strings.get(&s).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Instructions reading or writing the global
+    pub fn global(&self, g: RefGlobal) -> &[Xref] {
+        self.globals.get(&g).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Instructions reading or writing the field of an object type. Accesses through a subclass are included, the
+    /// field index is the same in the whole hierarchy.
+    pub fn field(&self, ty: RefType, field: RefField) -> &[Xref] {
+        self.fields
+            .get(&(ty, field))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Instructions reading or writing a field, with the kind of access. `ty` can be the class declaring the field or
+    /// any subclass inheriting it.
+    pub fn field_usages(
+        &self,
+        code: &Bytecode,
+        ty: RefType,
+        field: RefField,
+    ) -> Vec<(Xref, Access)> {
+        self.field(owner(code, ty, field), field)
+            .iter()
+            .map(|&(f, pos)| {
+                let write = f.resolve_as_fn(code).map_or(false, |f| {
+                    matches!(f.ops[pos], Opcode::SetField { .. } | Opcode::SetThis { .. })
+                });
+                ((f, pos), if write { Access::Write } else { Access::Read })
+            })
+            .collect()
+    }
+
+    /// Instructions creating a value of this type (allocation, cast), loading the type itself or resolving it by name
+    pub fn ty(&self, ty: RefType) -> &[Xref] {
+        self.types.get(&ty).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Every referenced element with its references, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (XrefTarget, &[Xref])> + '_ {
+        let strings = self
+            .strings
+            .iter()
+            .map(|(&s, x)| (XrefTarget::String(s), x));
+        let globals = self
+            .globals
+            .iter()
+            .map(|(&g, x)| (XrefTarget::Global(g), x));
+        let fields = self
+            .fields
+            .iter()
+            .map(|(&(t, f), x)| (XrefTarget::Field(t, f), x));
+        let types = self.types.iter().map(|(&t, x)| (XrefTarget::Type(t), x));
+        strings
+            .chain(globals)
+            .chain(fields)
+            .chain(types)
+            .map(|(target, x)| (target, x.as_slice()))
+    }
+
+    /// Functions in a list of references, without duplicates
+    pub fn functions(xrefs: &[Xref]) -> Vec<RefFun> {
+        let mut funs: Vec<RefFun> = xrefs.iter().map(|(f, _)| *f).collect();
+        funs.dedup();
+        funs
+    }
+}
+
+/// The type declaring a field, fields of the parent come first in an object
+fn owner(code: &Bytecode, mut ty: RefType, field: RefField) -> RefType {
+    while let Some(parent) = ty
+        .resolve_as_obj(&code.types)
+        .and_then(|obj| obj.super_)
+        .filter(|s| {
+            s.resolve_as_obj(&code.types)
+                .map_or(false, |p| field.0 < p.fields.len())
+        })
+    {
+        ty = parent;
+    }
+    ty
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{sample, BytecodeBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefField, RefGlobal, RefString, RefType, Reg, Type};
+
+    use crate::xref::{Access, XrefIndex};
+
+    #[test]
+    fn xrefs() {
+        let code = sample();
+        let index = XrefIndex::new(&code);
+        let hello = code.strings.iter().position(|s| s == "Hello").unwrap();
+        let main = code.entrypoint.resolve_as_fn(&code).unwrap();
+        assert!(index
+            .string(RefString(hello))
+            .iter()
+            .any(|&(f, pos)| f == main.findex && matches!(main.ops[pos], Opcode::String { .. })));
+
+        for (f, (pos, o)) in code.ops() {
+            if let Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } = o {
+                assert!(index.global(*global).contains(&(f.findex, pos)));
+            }
+        }
+        assert!(index.global(RefGlobal(1000)).is_empty());
+
+        let point = code.types.iter().position(|t| {
+            t.get_type_obj()
+                .map_or(false, |o| o.name.resolve(&code.strings) == "Point")
+        });
+        let point = RefType(point.unwrap());
+        let x = index.field(point, RefField(0));
+        assert!(!x.is_empty());
+        assert!(!index.ty(point).is_empty());
+        assert!(XrefIndex::functions(x).len() <= x.len());
+    }
+
+    #[test]
+    fn field_usages() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let i32_ = b.ty(Type::I32);
+        let base = b.class("Base", None, &[("x", i32_)], &[]);
+        let sub = b.class("Sub", Some(base), &[("y", i32_)], &[]);
+        let ty = b.fun_type(&[sub], void);
+        let findex = b.findex();
+        let ops = vec![
+            Opcode::Field {
+                dst: Reg(1),
+                obj: Reg(0),
+                field: RefField(0),
+            },
+            Opcode::SetThis {
+                field: RefField(1),
+                src: Reg(1),
+            },
+            Opcode::SetThis {
+                field: RefField(0),
+                src: Reg(1),
+            },
+            Opcode::Ret { ret: Reg(2) },
+        ];
+        b.function(findex, ty, vec![sub, i32_, void], ops);
+        let code = b.build().unwrap();
+        let index = XrefIndex::new(&code);
+
+        let x = [((findex, 0), Access::Read), ((findex, 2), Access::Write)];
+        // Inherited by Sub
+        assert_eq!(index.field_usages(&code, base, RefField(0)), x);
+        assert_eq!(index.field_usages(&code, sub, RefField(0)), x);
+        assert_eq!(
+            index.field_usages(&code, sub, RefField(1)),
+            [((findex, 1), Access::Write)]
+        );
+    }
+}
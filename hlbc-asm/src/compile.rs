@@ -0,0 +1,618 @@
+//! Compilation of a [Program] into a bytecode.
+
+use std::collections::{BTreeMap, HashMap};
+
+use hlbc::lookup::FunctionIndex;
+use hlbc::opcodes::{JumpOffset, Opcode, OperandMut};
+use hlbc::types::{
+    RefBytes, RefEnumConstruct, RefField, RefFun, RefGlobal, RefString, RefType, Reg, Type,
+    TypeFun, ValBool,
+};
+use hlbc::Bytecode;
+
+use crate::parse::{signature, Function, Program, Stmt};
+use crate::source::{split_list, string};
+use crate::{AsmError, AsmErrorKind, Origin};
+
+/// Functions, natives and globals declared by a [Program], by name
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    pub functions: BTreeMap<String, RefFun>,
+    pub globals: BTreeMap<String, RefGlobal>,
+}
+
+/// Registers and labels of the function being compiled
+struct Body {
+    regs: Vec<RefType>,
+    names: HashMap<String, Reg>,
+    labels: HashMap<String, usize>,
+}
+
+impl Body {
+    fn reg(&self, s: &str) -> Result<Reg, AsmErrorKind> {
+        if let Some(&r) = self.names.get(s) {
+            return Ok(r);
+        }
+        s.strip_prefix("reg")
+            .and_then(|r| r.parse::<usize>().ok())
+            .filter(|&r| r < self.regs.len())
+            .map(|r| Reg(r as u32))
+            .ok_or_else(|| AsmErrorKind::UnknownRegister(s.to_owned()))
+    }
+
+    /// Offset of a jump at `pos` to a label
+    fn offset(&self, pos: usize, s: &str) -> Result<JumpOffset, AsmErrorKind> {
+        self.labels
+            .get(s)
+            .map(|&target| (target as i64 - pos as i64 - 1) as JumpOffset)
+            .ok_or_else(|| AsmErrorKind::UnknownLabel(s.to_owned()))
+    }
+}
+
+/// Registers, instructions and the origin of each instruction of a compiled function
+struct Compiled {
+    regs: Vec<RefType>,
+    ops: Vec<Opcode>,
+    origins: Vec<Origin>,
+}
+
+/// Index of an element written `@N`
+fn index(s: &str) -> Option<usize> {
+    s.strip_prefix('@')?.parse().ok()
+}
+
+/// Items of a list written `[a, b]`
+fn list(s: &str) -> Result<Vec<String>, AsmErrorKind> {
+    s.strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .map(split_list)
+        .ok_or(AsmErrorKind::Expected("a list in brackets"))
+}
+
+fn int(s: &str) -> Result<i32, AsmErrorKind> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).map(|n| n as i32).ok(),
+        None => s.parse().ok(),
+    }
+    .ok_or_else(|| AsmErrorKind::InvalidNumber(s.to_owned()))
+}
+
+struct Compiler<'a> {
+    code: &'a mut Bytecode,
+    /// Functions of the bytecode before the merge
+    index: FunctionIndex,
+    symbols: Symbols,
+}
+
+impl Compiler<'_> {
+    fn ty(&mut self, s: &str) -> Result<RefType, AsmErrorKind> {
+        let s = s.trim();
+        if let Some(i) = index(s) {
+            return Ok(RefType(i));
+        }
+        let generic = |name: &str| s.strip_prefix(name)?.strip_prefix('<')?.strip_suffix('>');
+        let ty = match s {
+            "void" => Type::Void,
+            "u8" => Type::UI8,
+            "u16" => Type::UI16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "bool" => Type::Bool,
+            "bytes" => Type::Bytes,
+            "dyn" => Type::Dyn,
+            "array" => Type::Array,
+            "type" => Type::Type,
+            "dynobj" => Type::DynObj,
+            _ => {
+                if let Some(inner) = generic("null") {
+                    Type::Null(self.ty(inner)?)
+                } else if let Some(inner) = generic("ref") {
+                    Type::Ref(self.ty(inner)?)
+                } else if let Some(inner) = generic("packed") {
+                    Type::Packed(self.ty(inner)?)
+                } else if let Some((_, args, ret)) = s
+                    .strip_prefix("fn")
+                    .filter(|sig| sig.starts_with('('))
+                    .and_then(signature)
+                {
+                    return self.fun_type(&args, &ret);
+                } else {
+                    // Class, enum or abstract of the bytecode
+                    let strings = &self.code.strings;
+                    return self
+                        .code
+                        .types
+                        .iter()
+                        .position(|t| match t {
+                            Type::Obj(obj) | Type::Struct(obj) => obj.name.resolve(strings) == s,
+                            Type::Enum { name, .. } | Type::Abstract { name } => {
+                                name.resolve(strings) == s
+                            }
+                            _ => false,
+                        })
+                        .map(RefType)
+                        .ok_or_else(|| AsmErrorKind::UnknownType(s.to_owned()));
+                }
+            }
+        };
+        Ok(self.code.edit().ty(ty))
+    }
+
+    fn fun_type(&mut self, args: &[String], ret: &str) -> Result<RefType, AsmErrorKind> {
+        let args = args.iter().map(|a| self.ty(a)).collect::<Result<_, _>>()?;
+        let ret = self.ty(ret)?;
+        Ok(self.code.edit().ty(Type::Fun(TypeFun { args, ret })))
+    }
+
+    /// Function of the program, then of the bytecode
+    fn function(&self, s: &str) -> Result<RefFun, AsmErrorKind> {
+        if let Some(&f) = self.symbols.functions.get(s) {
+            return Ok(f);
+        }
+        if let Some(i) = index(s) {
+            return Ok(RefFun(i));
+        }
+        match self.index.lookup(self.code, s).as_slice() {
+            [f] => Ok(*f),
+            [] => Err(AsmErrorKind::UnknownFunction(s.to_owned())),
+            found => Err(AsmErrorKind::AmbiguousFunction(s.to_owned(), found.len())),
+        }
+    }
+
+    fn global(&self, s: &str) -> Result<RefGlobal, AsmErrorKind> {
+        self.symbols
+            .globals
+            .get(s)
+            .copied()
+            .or_else(|| index(s).map(RefGlobal))
+            .ok_or_else(|| AsmErrorKind::UnknownGlobal(s.to_owned()))
+    }
+
+    /// Field of an object by name, or its index
+    fn field(&self, ty: Option<RefType>, s: &str) -> Result<usize, AsmErrorKind> {
+        if let Some(i) = s.parse().ok().or_else(|| index(s)) {
+            return Ok(i);
+        }
+        let fields = match ty.map(|t| t.resolve(&self.code.types)) {
+            Some(Type::Obj(obj) | Type::Struct(obj)) => obj.fields.as_slice(),
+            Some(Type::Virtual { fields }) => fields.as_slice(),
+            _ => &[],
+        };
+        fields
+            .iter()
+            .position(|f| f.name.resolve(&self.code.strings) == s)
+            .ok_or_else(|| AsmErrorKind::UnknownField(s.to_owned()))
+    }
+
+    /// Construct of an enum by name, or its index
+    fn construct(&self, ty: Option<RefType>, s: &str) -> Result<usize, AsmErrorKind> {
+        if let Some(i) = s.parse().ok().or_else(|| index(s)) {
+            return Ok(i);
+        }
+        let constructs = match ty.map(|t| t.resolve(&self.code.types)) {
+            Some(Type::Enum { constructs, .. }) => constructs.as_slice(),
+            _ => &[],
+        };
+        constructs
+            .iter()
+            .position(|c| c.name.0 != 0 && c.name.resolve(&self.code.strings) == s)
+            .ok_or_else(|| AsmErrorKind::UnknownConstruct(s.to_owned()))
+    }
+
+    fn op(
+        &mut self,
+        body: &Body,
+        pos: usize,
+        name: &str,
+        operands: &[String],
+    ) -> Result<Opcode, AsmErrorKind> {
+        let mut op =
+            Opcode::from_name(name).ok_or_else(|| AsmErrorKind::UnknownOpcode(name.to_owned()))?;
+        let op_name = op.name();
+        let slots = op.operands_mut();
+        if slots.len() != operands.len() {
+            return Err(AsmErrorKind::OperandCount {
+                op: op_name,
+                expected: slots.len(),
+                got: operands.len(),
+            });
+        }
+        for (slot, s) in slots.into_iter().zip(operands) {
+            match slot {
+                OperandMut::Reg(r) => *r = body.reg(s)?,
+                OperandMut::Regs(v) => {
+                    *v = list(s)?
+                        .iter()
+                        .map(|r| body.reg(r))
+                        .collect::<Result<_, _>>()?
+                }
+                OperandMut::Offset(o) => *o = body.offset(pos, s)?,
+                OperandMut::Offsets(v) => {
+                    *v = list(s)?
+                        .iter()
+                        .map(|l| body.offset(pos, l))
+                        .collect::<Result<_, _>>()?
+                }
+                OperandMut::Int(r) => *r = self.code.edit().int(int(s)?),
+                OperandMut::Float(r) => {
+                    let value = s
+                        .parse()
+                        .map_err(|_| AsmErrorKind::InvalidNumber(s.to_owned()))?;
+                    *r = self.code.edit().float(value);
+                }
+                OperandMut::Bytes(r) => {
+                    *r = RefBytes(index(s).ok_or(AsmErrorKind::Expected("bytes as @N"))?)
+                }
+                OperandMut::String(r) => {
+                    *r = match string(s) {
+                        Some(value) => self.code.edit().string(&value),
+                        None => RefString(index(s).ok_or(AsmErrorKind::Expected("a string"))?),
+                    }
+                }
+                OperandMut::Type(r) => *r = self.ty(s)?,
+                OperandMut::Bool(b) => {
+                    *b = ValBool(
+                        s.parse()
+                            .map_err(|_| AsmErrorKind::Expected("true or false"))?,
+                    )
+                }
+                OperandMut::Fun(r) => *r = self.function(s)?,
+                OperandMut::Global(r) => *r = self.global(s)?,
+                // Resolved once the registers are known
+                OperandMut::Field(_) | OperandMut::Construct(_) => {}
+            }
+        }
+
+        let regtype = |r: Reg| body.regs.get(r.0 as usize).copied();
+        let obj = match &op {
+            Opcode::Field { obj, .. } | Opcode::SetField { obj, .. } => Some(*obj),
+            Opcode::GetThis { .. } | Opcode::SetThis { .. } | Opcode::CallThis { .. } => {
+                Some(Reg(0))
+            }
+            Opcode::CallMethod { args, .. } => args.first().copied(),
+            _ => None,
+        };
+        let enum_reg = match &op {
+            Opcode::MakeEnum { dst, .. } | Opcode::EnumAlloc { dst, .. } => Some(*dst),
+            Opcode::EnumField { value, .. } => Some(*value),
+            _ => None,
+        };
+        let (obj, enum_) = (obj.and_then(regtype), enum_reg.and_then(regtype));
+        for (slot, s) in op.operands_mut().into_iter().zip(operands) {
+            match slot {
+                OperandMut::Field(f) => *f = RefField(self.field(obj, s)?),
+                OperandMut::Construct(c) => *c = RefEnumConstruct(self.construct(enum_, s)?),
+                _ => {}
+            }
+        }
+        Ok(op)
+    }
+
+    fn body(&mut self, f: &Function) -> Result<Compiled, AsmError> {
+        let mut body = Body {
+            regs: Vec::new(),
+            names: HashMap::new(),
+            labels: HashMap::new(),
+        };
+        for r in f.args.iter().chain(&f.regs) {
+            let at = |kind| AsmError {
+                origin: r.origin.clone(),
+                kind,
+            };
+            let reg = Reg(body.regs.len() as u32);
+            if body.names.insert(r.name.clone(), reg).is_some() {
+                return Err(at(AsmErrorKind::Duplicate(r.name.clone())));
+            }
+            body.regs.push(self.ty(&r.ty).map_err(at)?);
+        }
+
+        let mut pos = 0;
+        for (origin, stmt) in &f.body {
+            match stmt {
+                Stmt::Label(label) => {
+                    if body.labels.insert(label.clone(), pos).is_some() {
+                        return Err(AsmError {
+                            origin: origin.clone(),
+                            kind: AsmErrorKind::Duplicate(label.clone()),
+                        });
+                    }
+                }
+                Stmt::Op { .. } => pos += 1,
+            }
+        }
+
+        let mut ops = Vec::with_capacity(pos);
+        let mut origins = Vec::with_capacity(pos);
+        for (origin, stmt) in &f.body {
+            if let Stmt::Op { name, operands } = stmt {
+                let op = self
+                    .op(&body, ops.len(), name, operands)
+                    .map_err(|kind| AsmError {
+                        origin: origin.clone(),
+                        kind,
+                    })?;
+                ops.push(op);
+                origins.push(origin.clone());
+            }
+        }
+        Ok(Compiled {
+            regs: body.regs,
+            ops,
+            origins,
+        })
+    }
+
+    fn declare(&mut self, origin: &Origin, name: &str, f: RefFun) -> Result<(), AsmError> {
+        match self.symbols.functions.insert(name.to_owned(), f) {
+            Some(_) => Err(AsmError {
+                origin: origin.clone(),
+                kind: AsmErrorKind::Duplicate(name.to_owned()),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Program {
+    /// Compile into a new module. The entrypoint is the function given to `.entry`, or the function named `main`.
+    pub fn compile(&self) -> Result<Bytecode, AsmError> {
+        // Same as a BytecodeBuilder, with debug info pointing to the sources
+        let mut code = Bytecode {
+            version: 5,
+            entrypoint: RefFun(0),
+            ints: Vec::new(),
+            floats: Vec::new(),
+            strings: Vec::new(),
+            bytes: Some((Vec::new(), Vec::new())),
+            debug_files: Some(Vec::new()),
+            types: vec![Type::Void],
+            globals: Vec::new(),
+            natives: Vec::new(),
+            functions: Vec::new(),
+            constants: Some(Vec::new()),
+            metadata: None,
+            findexes: Vec::new(),
+            fnames: HashMap::new(),
+            globals_initializers: HashMap::new(),
+            virtual_names: HashMap::new(),
+            global_offsets: Vec::new(),
+            warnings: Vec::new(),
+            source: None,
+        };
+        let symbols = self.merge(&mut code)?;
+        if self.entry.is_none() {
+            code.entrypoint = *symbols.functions.get("main").ok_or_else(|| AsmError {
+                origin: Origin {
+                    file: self.file.clone(),
+                    line: 0,
+                },
+                kind: AsmErrorKind::NoEntrypoint,
+            })?;
+        }
+        Ok(code)
+    }
+
+    /// Add the natives, globals and functions to an existing bytecode. Natives already declared by the bytecode are
+    /// reused, and `.entry` replaces its entrypoint. The functions get a name, so they can be found by the lookups.
+    ///
+    /// Nothing is added if there is an error, except types and constants to the pools.
+    pub fn merge(&self, code: &mut Bytecode) -> Result<Symbols, AsmError> {
+        let mut c = Compiler {
+            index: code.function_index(),
+            code,
+            symbols: Symbols::default(),
+        };
+        // Functions can reference each other, every findex is allocated before compiling them
+        let mut next = c.code.findexes.len();
+        let mut natives = Vec::new();
+        for n in &self.natives {
+            let at = |kind| AsmError {
+                origin: n.origin.clone(),
+                kind,
+            };
+            let ty = c.fun_type(&n.args, &n.ret).map_err(at)?;
+            let strings = &c.code.strings;
+            let findex = match c
+                .code
+                .natives
+                .iter()
+                .find(|x| x.lib.resolve(strings) == n.lib && x.name.resolve(strings) == n.name)
+            {
+                Some(x) => x.findex,
+                None => {
+                    natives.push((n, ty));
+                    next += 1;
+                    RefFun(next - 1)
+                }
+            };
+            c.declare(&n.origin, &n.name, findex)?;
+        }
+        let mut globals = Vec::new();
+        for g in &self.globals {
+            let ty = c.ty(&g.ty).map_err(|kind| AsmError {
+                origin: g.origin.clone(),
+                kind,
+            })?;
+            let global = RefGlobal(c.code.globals.len() + globals.len());
+            if c.symbols.globals.insert(g.name.clone(), global).is_some() {
+                return Err(AsmError {
+                    origin: g.origin.clone(),
+                    kind: AsmErrorKind::Duplicate(g.name.clone()),
+                });
+            }
+            globals.push(ty);
+        }
+        let mut types = Vec::with_capacity(self.functions.len());
+        for f in &self.functions {
+            let args: Vec<String> = f.args.iter().map(|a| a.ty.clone()).collect();
+            types.push(c.fun_type(&args, &f.ret).map_err(|kind| AsmError {
+                origin: f.origin.clone(),
+                kind,
+            })?);
+            c.declare(&f.origin, &f.name, RefFun(next))?;
+            next += 1;
+        }
+
+        let mut bodies = Vec::with_capacity(self.functions.len());
+        for f in &self.functions {
+            bodies.push(c.body(f)?);
+        }
+        let entry = match &self.entry {
+            Some((origin, name)) => Some(c.function(name).map_err(|kind| AsmError {
+                origin: origin.clone(),
+                kind,
+            })?),
+            None => None,
+        };
+
+        // Everything compiled, the bytecode can be modified
+        for (n, ty) in natives {
+            c.code.edit().native(&n.lib, &n.name, ty);
+        }
+        for ty in globals {
+            c.code.edit().global(ty);
+        }
+        for ((f, ty), compiled) in self.functions.iter().zip(types).zip(bodies) {
+            c.code.edit().function(ty, compiled.regs, compiled.ops);
+            let name = c.code.edit().string(&f.name);
+            let x = c.code.functions.len() - 1;
+            if let Some(files) = &mut c.code.debug_files {
+                let mut debug = Vec::with_capacity(compiled.origins.len());
+                for origin in compiled.origins {
+                    let file = match files.iter().position(|file| *file == origin.file) {
+                        Some(i) => i,
+                        None => {
+                            files.push(origin.file);
+                            files.len() - 1
+                        }
+                    };
+                    debug.push((file, origin.line));
+                }
+                c.code.functions[x].debug_info = Some(debug);
+            }
+            c.code.functions[x].name = Some(name);
+            c.code.fnames.insert(f.name.clone(), x);
+        }
+        if let Some(entry) = entry {
+            c.code.entrypoint = entry;
+        }
+        Ok(c.symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefFunKnown, Reg};
+    use hlbc::verify::verify;
+    use hlbc::Bytecode;
+
+    use crate::{AsmErrorKind, Assembler};
+
+    #[test]
+    fn compile() {
+        let program = Assembler::new()
+            .parse(
+                "abs.hla",
+                r#"
+                .global calls: i32
+
+                .fn abs(x: i32) -> i32
+                    .reg zero: i32
+                    Int zero, 0
+                    JSGte x, zero, positive
+                    Neg x, x
+                positive:
+                    Ret x
+                .end
+
+                .fn main() -> i32
+                    .reg x: i32
+                    Int x, -5
+                    SetGlobal calls, x
+                    Call1 x, abs, x
+                    Ret x
+                .end
+                "#,
+            )
+            .unwrap();
+        let code = program.compile().unwrap();
+        assert!(verify(&code).is_empty());
+        assert_eq!(code.globals.len(), 1);
+        let main = code.entrypoint.resolve_as_fn(&code).unwrap();
+        assert_eq!(main.name(&code), Some("main"));
+        assert_eq!(main.debug_info.as_ref().unwrap()[0], (0, 15));
+        assert!(matches!(main.ops[2], Opcode::Call1 { arg0: Reg(0), .. }));
+        let abs = &code.functions[0];
+        assert!(matches!(abs.ops[1], Opcode::JSGte { offset: 1, .. }));
+
+        // The module can be saved and loaded
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let loaded = Bytecode::from_bytes(&data).unwrap();
+        assert_eq!(loaded.functions.len(), 2);
+    }
+
+    #[test]
+    fn merge() {
+        let mut code = sample();
+        let (functions, natives) = (code.functions.len(), code.natives.len());
+        let program = Assembler::new()
+            .parse(
+                "inject.hla",
+                r#"
+                .native std sys_print(bytes) -> void
+                .fn hook(p: Point) -> i32
+                    .reg len: i32
+                    .reg msg: bytes
+                    .reg unit: void
+                    String msg, "hooked"
+                    Call1 unit, sys_print, msg
+                    SetField p, x, len
+                    Call1 len, Point.length, p
+                    Ret len
+                .end
+                "#,
+            )
+            .unwrap();
+        let symbols = program.merge(&mut code).unwrap();
+        assert!(verify(&code).is_empty());
+        assert_eq!(code.functions.len(), functions + 1);
+        // The native is reused
+        assert_eq!(code.natives.len(), natives);
+        let hook = symbols.functions["hook"];
+        assert!(matches!(code.findexes[hook.0], RefFunKnown::Fun(_)));
+        assert_eq!(code.function_index().get("hook"), [hook]);
+        let ops = &hook.resolve_as_fn(&code).unwrap().ops;
+        assert!(matches!(ops[2], Opcode::SetField { field, .. } if field.0 == 0));
+        assert!(code.strings.iter().any(|s| s == "hooked"));
+
+        let mut err = |text| {
+            Assembler::new()
+                .parse("bad.hla", text)
+                .unwrap()
+                .merge(&mut code)
+                .unwrap_err()
+        };
+        let e = err(".fn f() -> void\n    Ret nope\n.end");
+        assert_eq!(e.origin.line, 2);
+        assert_eq!(e.kind, AsmErrorKind::UnknownRegister("nope".to_owned()));
+        assert_eq!(
+            err(".fn f() -> void\n    JAlways end\n.end").kind,
+            AsmErrorKind::UnknownLabel("end".to_owned())
+        );
+        assert_eq!(
+            err(".fn f(x: Nope) -> void\n.end").kind,
+            AsmErrorKind::UnknownType("Nope".to_owned())
+        );
+        assert!(matches!(
+            err(".fn f() -> void\n    Ret\n.end").kind,
+            AsmErrorKind::OperandCount { expected: 1, .. }
+        ));
+        // Nothing was added
+        assert_eq!(code.functions.len(), functions + 1);
+    }
+}
@@ -0,0 +1,295 @@
+//! Assembly language for [**H**ash**l**ink](https://hashlink.haxe.org/) **b**yte**c**ode.
+//!
+//! Unlike [hlbc::asm], which reads back the listing of a single function, this is a small language to write whole
+//! functions by hand : registers and functions have names, jumps target labels and constants are written as literals.
+//! A [Program] is compiled into a new module with [Program::compile], or merged into an existing bytecode with
+//! [Program::merge] to inject code into a game without the Haxe compiler.
+//! ```text
+//! ; Comments start with a semicolon
+//! .include "common.hla"            ; Relative to this file
+//!
+//! .native std log(bytes) -> void   ; Native function of a library
+//! .global counter: i32
+//!
+//! ; Lines between .macro and .endmacro are expanded where the macro is used, with its arguments replaced.
+//! ; \@ is a number unique to each expansion, to declare labels.
+//! .macro clamp value, max
+//!     JSLte value, max, ok\@
+//!     Mov value, max
+//! ok\@:
+//! .endmacro
+//!
+//! .fn add(a: i32, b: i32) -> i32
+//!     .reg sum: i32
+//!     Add sum, a, b
+//!     Ret sum
+//! .end
+//!
+//! .entry main
+//! ```
+//! Instructions are an opcode followed by its operands separated by commas, in the order of the fields of the
+//! [Opcode](hlbc::Opcode) :
+//! - registers by name (arguments and `.reg` declarations) or `regN`, lists of registers or labels in brackets `[a, b]`
+//! - jump targets by label, labels are declared with `name:`
+//! - constants as literals : `42`, `1.5`, `"text"` (with `\n`, `\t`, `\"` and `\\` escapes), `true`
+//! - functions, natives and globals by name. Functions of the bytecode can be referenced by their (qualified) name
+//!   when merging.
+//! - fields and enum constructs by name (resolved in the type of the object register) or index
+//! - types : `void`, `u8`, `u16`, `i32`, `i64`, `f32`, `f64`, `bool`, `bytes`, `dyn`, `array`, `type`, `dynobj`,
+//!   `null<T>`, `ref<T>`, `packed<T>`, `fn(T, ..) -> T`, the name of a class, enum or abstract of the bytecode
+//! - any element of the bytecode by index : `@N`
+//!
+//! ```
+//! use hlbc_asm::Assembler;
+//!
+//! let program = Assembler::new()
+//!     .parse(
+//!         "main.hla",
+//!         r#"
+//!         .macro twice reg
+//!             Add reg, reg, reg
+//!         .endmacro
+//!
+//!         .fn main() -> i32
+//!             .reg x: i32
+//!             Int x, 21
+//!             twice x
+//!             Ret x
+//!         .end
+//!         "#,
+//!     )
+//!     .unwrap();
+//! let code = program.compile().unwrap();
+//! assert_eq!(code.functions[0].ops.len(), 3);
+//! ```
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+pub use compile::Symbols;
+pub use parse::Program;
+
+mod compile;
+mod parse;
+mod source;
+
+/// Position in the sources, lines start at 1
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Origin {
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("{origin} : {kind}")]
+pub struct AsmError {
+    pub origin: Origin,
+    pub kind: AsmErrorKind,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum AsmErrorKind {
+    #[error("Can't read {path} : {message}")]
+    Io { path: String, message: String },
+    #[error("{0} includes itself")]
+    IncludeCycle(String),
+    #[error("Expected {0}")]
+    Expected(&'static str),
+    #[error("Unknown directive {0}")]
+    UnknownDirective(String),
+    #[error("{0} is never closed")]
+    Unterminated(&'static str),
+    #[error("Macro {name} takes {expected} arguments, got {got}")]
+    MacroArguments {
+        name: String,
+        expected: usize,
+        got: usize,
+    },
+    #[error("Macro {0} is expanded too many times, it probably uses itself")]
+    MacroDepth(String),
+    #[error("{0} is already declared")]
+    Duplicate(String),
+    #[error("Unknown opcode {0}")]
+    UnknownOpcode(String),
+    #[error("{op} takes {expected} operands, got {got}")]
+    OperandCount {
+        op: &'static str,
+        expected: usize,
+        got: usize,
+    },
+    #[error("Invalid number {0}")]
+    InvalidNumber(String),
+    #[error("Unknown type {0}")]
+    UnknownType(String),
+    #[error("Unknown register {0}")]
+    UnknownRegister(String),
+    #[error("Unknown label {0}")]
+    UnknownLabel(String),
+    #[error("Unknown function {0}")]
+    UnknownFunction(String),
+    #[error("{0} names {1} functions, qualify it with its class")]
+    AmbiguousFunction(String, usize),
+    #[error("Unknown global {0}")]
+    UnknownGlobal(String),
+    #[error("No field {0} in the type of the object")]
+    UnknownField(String),
+    #[error("No construct {0} in the enum")]
+    UnknownConstruct(String),
+    #[error("No entrypoint, use .entry or name a function main")]
+    NoEntrypoint,
+}
+
+type Loader<'a> = Box<dyn FnMut(&Path) -> io::Result<String> + 'a>;
+
+/// Reads sources and the files they include into a [Program]
+pub struct Assembler<'a> {
+    loader: Loader<'a>,
+}
+
+impl Default for Assembler<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Assembler<'a> {
+    /// Included files are read from the file system
+    pub fn new() -> Self {
+        Self::with_loader(|path| std::fs::read_to_string(path))
+    }
+
+    /// Included files are read with `loader`, from an archive or memory for example
+    pub fn with_loader(loader: impl FnMut(&Path) -> io::Result<String> + 'a) -> Self {
+        Self {
+            loader: Box::new(loader),
+        }
+    }
+
+    /// Read a source file with the loader
+    pub fn parse_file(&mut self, path: impl AsRef<Path>) -> Result<Program, AsmError> {
+        let path = path.as_ref();
+        let text = (self.loader)(path).map_err(|e| AsmError {
+            origin: Origin {
+                file: path.display().to_string(),
+                line: 0,
+            },
+            kind: AsmErrorKind::Io {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            },
+        })?;
+        self.parse(path, &text)
+    }
+
+    /// Parse a source, `path` is used for errors and to resolve the includes
+    pub fn parse(&mut self, path: impl AsRef<Path>, text: &str) -> Result<Program, AsmError> {
+        let path = path.as_ref();
+        let lines = source::preprocess(&mut self.loader, path, text)?;
+        parse::parse(path.display().to_string(), lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::fmt::Write;
+
+    use hlbc::builder::sample;
+    use hlbc::opcodes::OperandMut;
+    use hlbc::types::Function;
+    use hlbc::Bytecode;
+
+    use crate::Assembler;
+
+    /// Source of a function, every element referenced by index
+    fn disassemble(code: &Bytecode, f: &Function) -> String {
+        let ty = f.t.resolve_as_fun(&code.types).unwrap();
+        let args: Vec<_> = (0..ty.args.len())
+            .map(|i| format!("reg{i}: @{}", f.regs[i].0))
+            .collect();
+        let mut out = format!(
+            ".fn copy{}({}) -> @{}\n",
+            f.findex.0,
+            args.join(", "),
+            ty.ret.0
+        );
+        for (i, r) in f.regs.iter().enumerate().skip(args.len()) {
+            writeln!(out, "    .reg reg{i}: @{}", r.0).unwrap();
+        }
+        let targets: BTreeSet<usize> = f
+            .ops
+            .iter()
+            .enumerate()
+            .flat_map(|(pos, op)| op.jump_targets(pos))
+            .collect();
+        let label = |pos: usize, offset: i32| format!("L{}", pos as i32 + offset + 1);
+        for (pos, op) in f.ops.iter().enumerate() {
+            if targets.contains(&pos) {
+                writeln!(out, "L{pos}:").unwrap();
+            }
+            let mut op = op.clone();
+            let operands: Vec<_> = op
+                .operands_mut()
+                .into_iter()
+                .map(|slot| match slot {
+                    OperandMut::Reg(r) => format!("reg{}", r.0),
+                    OperandMut::Regs(v) => {
+                        let regs: Vec<_> = v.iter().map(|r| format!("reg{}", r.0)).collect();
+                        format!("[{}]", regs.join(", "))
+                    }
+                    OperandMut::Offset(o) => label(pos, *o),
+                    OperandMut::Offsets(v) => {
+                        let labels: Vec<_> = v.iter().map(|o| label(pos, *o)).collect();
+                        format!("[{}]", labels.join(", "))
+                    }
+                    OperandMut::Int(r) => code.ints[r.0].to_string(),
+                    OperandMut::Float(r) => format!("{:?}", code.floats[r.0]),
+                    OperandMut::Bytes(r) => format!("@{}", r.0),
+                    OperandMut::String(r) => format!("@{}", r.0),
+                    OperandMut::Type(r) => format!("@{}", r.0),
+                    OperandMut::Bool(b) => b.0.to_string(),
+                    OperandMut::Fun(r) => format!("@{}", r.0),
+                    OperandMut::Field(r) => r.0.to_string(),
+                    OperandMut::Global(r) => format!("@{}", r.0),
+                    OperandMut::Construct(r) => r.0.to_string(),
+                })
+                .collect();
+            writeln!(out, "    {} {}", op.name(), operands.join(", ")).unwrap();
+        }
+        if targets.contains(&f.ops.len()) {
+            writeln!(out, "L{}:", f.ops.len()).unwrap();
+        }
+        out.push_str(".end\n");
+        out
+    }
+
+    #[test]
+    fn sample_roundtrip() {
+        let mut code = sample();
+        let functions = code.functions.len();
+        let source: String = code
+            .functions
+            .iter()
+            .map(|f| disassemble(&code, f))
+            .collect();
+        let program = Assembler::new().parse("sample.hla", &source).unwrap();
+        program.merge(&mut code).unwrap();
+
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let code = Bytecode::from_bytes(&data).unwrap();
+        assert_eq!(code.functions.len(), functions * 2);
+        let (original, copies) = code.functions.split_at(functions);
+        for (f, copy) in original.iter().zip(copies) {
+            assert_eq!(f.regs, copy.regs);
+            assert_eq!(format!("{:?}", f.ops), format!("{:?}", copy.ops));
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Directives of the preprocessed sources.
+
+use crate::source::{split_list, split_word, Line};
+use crate::{AsmError, AsmErrorKind, Origin};
+
+/// Declarations and functions read by an [Assembler](crate::Assembler), ready to be compiled
+#[derive(Debug, Clone)]
+pub struct Program {
+    /// Main file
+    pub(crate) file: String,
+    pub(crate) natives: Vec<Native>,
+    pub(crate) globals: Vec<Decl>,
+    pub(crate) functions: Vec<Function>,
+    pub(crate) entry: Option<(Origin, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Native {
+    pub(crate) origin: Origin,
+    pub(crate) lib: String,
+    pub(crate) name: String,
+    pub(crate) args: Vec<String>,
+    pub(crate) ret: String,
+}
+
+/// A name with a type : an argument, a register or a global
+#[derive(Debug, Clone)]
+pub(crate) struct Decl {
+    pub(crate) origin: Origin,
+    pub(crate) name: String,
+    pub(crate) ty: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Stmt {
+    Label(String),
+    Op { name: String, operands: Vec<String> },
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Function {
+    pub(crate) origin: Origin,
+    pub(crate) name: String,
+    pub(crate) args: Vec<Decl>,
+    pub(crate) ret: String,
+    /// Registers declared in the body, after the arguments
+    pub(crate) regs: Vec<Decl>,
+    pub(crate) body: Vec<(Origin, Stmt)>,
+}
+
+impl Program {
+    /// Names of the functions, in the order they're compiled
+    pub fn functions(&self) -> impl Iterator<Item = &str> {
+        self.functions.iter().map(|f| f.name.as_str())
+    }
+}
+
+/// Split a signature like `name(a, b) -> ret` into the name, the arguments and the return type (void by default)
+pub(crate) fn signature(s: &str) -> Option<(&str, Vec<String>, String)> {
+    let (name, rest) = s.split_once('(')?;
+    let end = rest.rfind(')')?;
+    let ret = match rest[end + 1..].trim() {
+        "" => "void".to_owned(),
+        ret => ret.strip_prefix("->")?.trim().to_owned(),
+    };
+    Some((name.trim(), split_list(&rest[..end]), ret))
+}
+
+/// `name: type`
+fn typed(origin: &Origin, s: &str) -> Option<Decl> {
+    let (name, ty) = s.split_once(':')?;
+    Some(Decl {
+        origin: origin.clone(),
+        name: name.trim().to_owned(),
+        ty: ty.trim().to_owned(),
+    })
+}
+
+pub(crate) fn parse(file: String, lines: Vec<Line>) -> Result<Program, AsmError> {
+    let mut program = Program {
+        file,
+        natives: Vec::new(),
+        globals: Vec::new(),
+        functions: Vec::new(),
+        entry: None,
+    };
+    let mut current: Option<Function> = None;
+
+    for Line { origin, text } in lines {
+        let at = |kind| AsmError {
+            origin: origin.clone(),
+            kind,
+        };
+        let (word, rest) = split_word(&text);
+        match (word, &mut current) {
+            (".fn", None) => {
+                let (name, args, ret) = signature(rest)
+                    .ok_or_else(|| at(AsmErrorKind::Expected("name(args) -> type")))?;
+                let args = args
+                    .iter()
+                    .map(|a| typed(&origin, a))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| at(AsmErrorKind::Expected("name: type")))?;
+                current = Some(Function {
+                    origin: origin.clone(),
+                    name: name.to_owned(),
+                    args,
+                    ret,
+                    regs: Vec::new(),
+                    body: Vec::new(),
+                });
+            }
+            (".end", Some(_)) => program.functions.extend(current.take()),
+            (".reg", Some(f)) => {
+                f.regs.push(
+                    typed(&origin, rest).ok_or_else(|| at(AsmErrorKind::Expected("name: type")))?,
+                );
+            }
+            (_, Some(f)) if word.ends_with(':') && !word.starts_with('.') => {
+                let label = word.trim_end_matches(':').to_owned();
+                f.body.push((origin, Stmt::Label(label)));
+            }
+            (_, Some(f)) if !word.starts_with('.') => {
+                f.body.push((
+                    origin,
+                    Stmt::Op {
+                        name: word.to_owned(),
+                        operands: split_list(rest),
+                    },
+                ));
+            }
+            (".native", None) => {
+                let (lib, rest) = split_word(rest);
+                let (name, args, ret) = signature(rest)
+                    .ok_or_else(|| at(AsmErrorKind::Expected("lib name(args) -> type")))?;
+                program.natives.push(Native {
+                    origin: origin.clone(),
+                    lib: lib.to_owned(),
+                    name: name.to_owned(),
+                    args,
+                    ret,
+                });
+            }
+            (".global", None) => {
+                program.globals.push(
+                    typed(&origin, rest).ok_or_else(|| at(AsmErrorKind::Expected("name: type")))?,
+                );
+            }
+            (".entry", None) if !rest.is_empty() => {
+                program.entry = Some((origin.clone(), rest.to_owned()));
+            }
+            (_, None) if !word.starts_with('.') => {
+                return Err(at(AsmErrorKind::Expected(".fn before instructions")))
+            }
+            _ => return Err(at(AsmErrorKind::UnknownDirective(word.to_owned()))),
+        }
+    }
+    match current {
+        Some(f) => Err(AsmError {
+            origin: f.origin,
+            kind: AsmErrorKind::Unterminated(".fn"),
+        }),
+        None => Ok(program),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AsmErrorKind, Assembler};
+
+    #[test]
+    fn error_positions() {
+        let err = |text| Assembler::new().parse("bad.hla", text).unwrap_err();
+        let e = err(".global count: i32\n\n.fn main(x) -> void\n.end");
+        assert_eq!((e.origin.file.as_str(), e.origin.line), ("bad.hla", 3));
+        assert_eq!(e.kind, AsmErrorKind::Expected("name: type"));
+        let e = err(".fn f() -> void\n    .reg x\n.end");
+        assert_eq!(e.origin.line, 2);
+        assert_eq!(e.kind, AsmErrorKind::Expected("name: type"));
+        let e = err("; Comment\n.fn f -> void\n.end");
+        assert_eq!(e.origin.line, 2);
+        assert_eq!(e.kind, AsmErrorKind::Expected("name(args) -> type"));
+        let e = err(".native std\n");
+        assert_eq!(e.origin.line, 1);
+        assert_eq!(e.kind, AsmErrorKind::Expected("lib name(args) -> type"));
+        let e = err(".fn f() -> void\n.end\n    Ret x");
+        assert_eq!(e.origin.line, 3);
+        assert_eq!(e.kind, AsmErrorKind::Expected(".fn before instructions"));
+        // Directives are only valid in or outside functions
+        let e = err(".fn f() -> void\n    .global g: i32\n.end");
+        assert_eq!(e.origin.line, 2);
+        assert_eq!(e.kind, AsmErrorKind::UnknownDirective(".global".to_owned()));
+        assert_eq!(
+            err(".reg x: i32").kind,
+            AsmErrorKind::UnknownDirective(".reg".to_owned())
+        );
+        assert_eq!(
+            err(".entry").kind,
+            AsmErrorKind::UnknownDirective(".entry".to_owned())
+        );
+        // Functions can't be nested, an unclosed function is reported where it starts
+        let e = err(".fn f() -> void\n\n.fn g() -> void\n    Nop\n.end");
+        assert_eq!(e.origin.line, 3);
+        assert_eq!(e.kind, AsmErrorKind::UnknownDirective(".fn".to_owned()));
+        let e = err("\n.fn f() -> void\n    Nop");
+        assert_eq!(e.origin.line, 2);
+        assert_eq!(e.kind, AsmErrorKind::Unterminated(".fn"));
+    }
+
+    #[test]
+    fn declarations() {
+        let program = Assembler::new()
+            .parse(
+                "decl.hla",
+                ".native std log(bytes, i32)\n.global g: null<i32>\n.fn f(a: i32, b: fn(i32) -> i32)\n    .reg r: i32\nend:\n    Ret r\n.end\n.entry f",
+            )
+            .unwrap();
+        assert_eq!(program.natives[0].args, ["bytes", "i32"]);
+        assert_eq!(program.natives[0].ret, "void");
+        assert_eq!(program.globals[0].ty, "null<i32>");
+        let f = &program.functions[0];
+        assert_eq!(f.args[1].ty, "fn(i32) -> i32");
+        assert_eq!((f.regs[0].name.as_str(), f.regs[0].origin.line), ("r", 4));
+        assert!(matches!(&f.body[0], (o, super::Stmt::Label(l)) if l == "end" && o.line == 5));
+        assert_eq!(program.entry.as_ref().unwrap().1, "f");
+        assert_eq!(program.functions().collect::<Vec<_>>(), ["f"]);
+    }
+}
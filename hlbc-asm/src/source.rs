@@ -0,0 +1,367 @@
+//! Preprocessing of the sources : comments, includes and macros.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{AsmError, AsmErrorKind, Loader, Origin};
+
+/// Expansions of macros inside macros before giving up
+const MAX_DEPTH: usize = 64;
+
+/// A line without its comment, after includes and macros are expanded
+#[derive(Debug, Clone)]
+pub(crate) struct Line {
+    pub(crate) origin: Origin,
+    pub(crate) text: String,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+struct Preprocessor<'l, 'a> {
+    loader: &'l mut Loader<'a>,
+    macros: HashMap<String, Macro>,
+    /// Files being read, to detect include cycles
+    files: Vec<PathBuf>,
+    expansions: usize,
+    lines: Vec<Line>,
+}
+
+pub(crate) fn preprocess(
+    loader: &mut Loader<'_>,
+    path: &Path,
+    text: &str,
+) -> Result<Vec<Line>, AsmError> {
+    let mut pre = Preprocessor {
+        loader,
+        macros: HashMap::new(),
+        files: Vec::new(),
+        expansions: 0,
+        lines: Vec::new(),
+    };
+    pre.file(path, text)?;
+    Ok(pre.lines)
+}
+
+impl Preprocessor<'_, '_> {
+    fn file(&mut self, path: &Path, text: &str) -> Result<(), AsmError> {
+        self.files.push(path.to_path_buf());
+        let mut lines = text.lines().enumerate();
+        while let Some((i, line)) = lines.next() {
+            let origin = Origin {
+                file: path.display().to_string(),
+                line: i + 1,
+            };
+            let at = |kind| AsmError {
+                origin: origin.clone(),
+                kind,
+            };
+            let line = strip_comment(line).trim();
+            let (word, rest) = split_word(line);
+            match word {
+                "" => {}
+                ".include" => {
+                    let name = string(rest).ok_or_else(|| at(AsmErrorKind::Expected("a path")))?;
+                    let included = path.parent().unwrap_or(Path::new("")).join(name);
+                    if self.files.contains(&included) {
+                        return Err(at(AsmErrorKind::IncludeCycle(
+                            included.display().to_string(),
+                        )));
+                    }
+                    let text = (self.loader)(&included).map_err(|e| {
+                        at(AsmErrorKind::Io {
+                            path: included.display().to_string(),
+                            message: e.to_string(),
+                        })
+                    })?;
+                    self.file(&included, &text)?;
+                }
+                ".macro" => {
+                    let (name, params) = split_word(rest);
+                    if name.is_empty() {
+                        return Err(at(AsmErrorKind::Expected("a macro name")));
+                    }
+                    let mut body = Vec::new();
+                    loop {
+                        let (_, line) = lines
+                            .next()
+                            .ok_or_else(|| at(AsmErrorKind::Unterminated(".macro")))?;
+                        match strip_comment(line).trim() {
+                            ".endmacro" => break,
+                            "" => {}
+                            line => body.push(line.to_owned()),
+                        }
+                    }
+                    let params = split_list(params);
+                    if self.macros.contains_key(name) {
+                        return Err(at(AsmErrorKind::Duplicate(name.to_owned())));
+                    }
+                    self.macros.insert(name.to_owned(), Macro { params, body });
+                }
+                ".endmacro" => return Err(at(AsmErrorKind::UnknownDirective(word.to_owned()))),
+                _ => self.line(&origin, line, 0)?,
+            }
+        }
+        self.files.pop();
+        Ok(())
+    }
+
+    /// Expand the macros of a line
+    fn line(&mut self, origin: &Origin, line: &str, depth: usize) -> Result<(), AsmError> {
+        let at = |kind| AsmError {
+            origin: origin.clone(),
+            kind,
+        };
+        let (mut word, mut rest) = split_word(line);
+        // Labels are on their own line
+        if let Some(label) = word.strip_suffix(':').filter(|_| !word.starts_with('.')) {
+            self.lines.push(Line {
+                origin: origin.clone(),
+                text: format!("{label}:"),
+            });
+            (word, rest) = split_word(rest);
+            if word.is_empty() {
+                return Ok(());
+            }
+        }
+        let Some(m) = self.macros.get(word) else {
+            self.lines.push(Line {
+                origin: origin.clone(),
+                text: format!("{word} {rest}").trim_end().to_owned(),
+            });
+            return Ok(());
+        };
+        let args = split_list(rest);
+        if args.len() != m.params.len() {
+            return Err(at(AsmErrorKind::MacroArguments {
+                name: word.to_owned(),
+                expected: m.params.len(),
+                got: args.len(),
+            }));
+        }
+        if depth >= MAX_DEPTH {
+            return Err(at(AsmErrorKind::MacroDepth(word.to_owned())));
+        }
+        self.expansions += 1;
+        let id = self.expansions.to_string();
+        let body: Vec<String> = m
+            .body
+            .iter()
+            .map(|line| substitute(line, &m.params, &args).replace("\\@", &id))
+            .collect();
+        for line in body {
+            self.line(origin, &line, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replace the words of a macro body matching a parameter with the argument. Directives and strings are kept.
+fn substitute(line: &str, params: &[String], args: &[String]) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let flush = |word: &mut String, out: &mut String| {
+        match params.iter().position(|p| p == word) {
+            Some(i) => out.push_str(&args[i]),
+            None => out.push_str(word),
+        }
+        word.clear();
+    };
+    for c in line.chars() {
+        if quoted {
+            out.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                _ => {}
+            }
+        } else if c.is_alphanumeric() || matches!(c, '_' | '$' | '.') {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut out);
+            quoted = c == '"';
+            out.push(c);
+        }
+    }
+    flush(&mut word, &mut out);
+    out
+}
+
+/// Remove the comment at the end of a line, semicolons in strings are kept
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// First word of a line and the rest, trimmed
+pub(crate) fn split_word(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    line.split_once(char::is_whitespace)
+        .map_or((line, ""), |(word, rest)| (word, rest.trim()))
+}
+
+/// Split a list separated by commas, commas in strings and between brackets are kept
+pub(crate) fn split_list(s: &str) -> Vec<String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Vec::new();
+    }
+    let mut items = Vec::new();
+    let mut depth = 0i32;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    let mut prev = ' ';
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '(' | '[' | '<' => depth += 1,
+            // The arrow of a function type
+            '>' if prev == '-' => {}
+            ')' | ']' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(s[start..i].trim().to_owned());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    items.push(s[start..].trim().to_owned());
+    items
+}
+
+/// Value of a string literal
+pub(crate) fn string(s: &str) -> Option<String> {
+    let s = s.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            '0' => out.push('\0'),
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::Path;
+
+    use crate::{AsmErrorKind, Assembler};
+
+    fn files(files: &[(&str, &str)]) -> Assembler<'static> {
+        let files: HashMap<String, String> = files
+            .iter()
+            .map(|(path, text)| (path.to_string(), text.to_string()))
+            .collect();
+        Assembler::with_loader(move |path: &Path| {
+            files
+                .get(&path.display().to_string())
+                .cloned()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        })
+    }
+
+    #[test]
+    fn includes_and_macros() {
+        let mut asm = files(&[
+            (
+                "lib/macros.hla",
+                r#"
+                .macro clamp value, max ; Comment
+                    JSLte value, max, ok\@
+                    Mov value, max
+                ok\@:
+                .endmacro
+                .macro clamp_twice value, max
+                    clamp value, max
+                    clamp value, max
+                .endmacro
+                "#,
+            ),
+            (
+                "lib/main.hla",
+                r#"
+                .include "macros.hla"
+                .fn main(x: i32, max: i32) -> i32
+                    clamp_twice x, max
+                    String x, "; not a comment, clamp x"
+                    Ret x
+                .end
+                "#,
+            ),
+        ]);
+        let program = asm.parse_file("lib/main.hla").unwrap();
+        let f = &program.functions[0];
+        assert_eq!(f.body.len(), 8);
+        let code = program.compile().unwrap();
+        let ops = &code.functions[0].ops;
+        assert_eq!(ops.len(), 6);
+        assert!(code.strings.iter().any(|s| s == "; not a comment, clamp x"));
+
+        let err = |text| {
+            files(&[
+                ("a.hla", ".include \"b.hla\""),
+                ("b.hla", ".include \"a.hla\""),
+            ])
+            .parse("main.hla", text)
+            .unwrap_err()
+        };
+        assert_eq!(
+            err(".include \"a.hla\"").kind,
+            AsmErrorKind::IncludeCycle("a.hla".to_owned())
+        );
+        assert!(matches!(
+            err(".include \"c.hla\"").kind,
+            AsmErrorKind::Io { .. }
+        ));
+        let e = err(".macro m a\n    Ret a\n.endmacro\n\nm x, y");
+        assert_eq!(e.origin.line, 5);
+        assert!(matches!(
+            e.kind,
+            AsmErrorKind::MacroArguments {
+                expected: 1,
+                got: 2,
+                ..
+            }
+        ));
+        assert_eq!(
+            err(".macro m\n    m\n.endmacro\n.fn f() -> void\n    m\n.end").kind,
+            AsmErrorKind::MacroDepth("m".to_owned())
+        );
+        assert_eq!(
+            err(".macro m\n    Nop").kind,
+            AsmErrorKind::Unterminated(".macro")
+        );
+    }
+}
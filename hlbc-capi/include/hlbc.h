@@ -0,0 +1,71 @@
+/*
+ * C API of hlbc, the Hashlink bytecode disassembler and decompiler.
+ *
+ * Every function taking a HlbcBytecode accepts a null pointer. Functions returning a pointer return null on error,
+ * hlbc_last_error() describes the error. Strings returned are owned by the caller and must be released with
+ * hlbc_string_free().
+ */
+
+#ifndef HLBC_H
+#define HLBC_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A loaded bytecode */
+typedef struct HlbcBytecode HlbcBytecode;
+
+/* Description of the last error on this thread, null if there was none. Valid until the next call failing. */
+const char *hlbc_last_error(void);
+
+/* Release a string returned by hlbc */
+void hlbc_string_free(char *s);
+
+/* Load a bytecode file, returns null on error */
+HlbcBytecode *hlbc_load_file(const char *path);
+
+/* Load a bytecode from memory, the data is copied. Returns null on error. */
+HlbcBytecode *hlbc_load_bytes(const uint8_t *data, size_t len);
+
+/* Release a bytecode */
+void hlbc_free(HlbcBytecode *code);
+
+/* Number of findexes, functions and natives share the same indexes */
+size_t hlbc_findex_count(const HlbcBytecode *code);
+
+/* Number of functions with code */
+size_t hlbc_function_count(const HlbcBytecode *code);
+
+/* Findex of the function with code at index, SIZE_MAX if out of bounds */
+size_t hlbc_function_findex(const HlbcBytecode *code, size_t index);
+
+/* 1 if findex is a native, 0 if it's a function with code, -1 if it doesn't exist */
+int hlbc_is_native(const HlbcBytecode *code, size_t findex);
+
+/* Name of a function like name@findex, or lib/name@findex for a native */
+char *hlbc_function_name(const HlbcBytecode *code, size_t findex);
+
+/* Disassembly of a function, the header only for a native */
+char *hlbc_function_disassembly(const HlbcBytecode *code, size_t findex);
+
+/* Decompiled Haxe source of a function */
+char *hlbc_function_decompile(const HlbcBytecode *code, size_t findex);
+
+/* Number of types */
+size_t hlbc_type_count(const HlbcBytecode *code);
+
+/* Name of a type like name@index */
+char *hlbc_type_name(const HlbcBytecode *code, size_t index);
+
+/* Decompiled Haxe source of a class */
+char *hlbc_class_decompile(const HlbcBytecode *code, size_t index);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HLBC_H */
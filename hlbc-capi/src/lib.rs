@@ -0,0 +1,336 @@
+//! C API for hlbc, to use the parser and the decompiler from C, C++ or any language with a C FFI (Frida scripts,
+//! modding frameworks, ...). The declarations are in `include/hlbc.h`.
+//!
+//! Every function taking a `HlbcBytecode` accepts a null pointer. Functions returning a pointer return null on error,
+//! and [hlbc_last_error] describes the error. Strings returned are owned by the caller and must be released with
+//! [hlbc_string_free].
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::fmt::Display;
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use hlbc::types::{FunPtr, RefFun, RefType, Type};
+use hlbc::Bytecode;
+use hlbc_decompiler::fmt::FormatOptions;
+
+/// A loaded bytecode, opaque to C
+pub struct HlbcBytecode(Bytecode);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_error(e: impl Display) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(c_string(e.to_string())));
+}
+
+fn c_string(s: String) -> CString {
+    // Interior nul bytes would truncate the string
+    CString::new(s.replace('\0', "\\0")).unwrap()
+}
+
+/// Run `f` and give the result to C, errors and panics return null
+fn guard(f: impl FnOnce() -> Result<String, String>) -> *mut c_char {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(s)) => c_string(s).into_raw(),
+        Ok(Err(e)) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+        Err(_) => {
+            set_error("hlbc panicked, this is a bug");
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn code<'a>(code: *const HlbcBytecode) -> Result<&'a Bytecode, String> {
+    code.as_ref()
+        .map(|c| &c.0)
+        .ok_or_else(|| "Null bytecode".to_owned())
+}
+
+fn fun(code: &Bytecode, findex: usize) -> Result<FunPtr<'_>, String> {
+    RefFun(findex)
+        .try_resolve(code)
+        .map_err(|_| format!("fn@{findex} doesn't exist"))
+}
+
+fn load(load: impl FnOnce() -> hlbc::Result<Bytecode>) -> *mut HlbcBytecode {
+    match catch_unwind(AssertUnwindSafe(load)) {
+        Ok(Ok(code)) => Box::into_raw(Box::new(HlbcBytecode(code))),
+        Ok(Err(e)) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+        Err(_) => {
+            set_error("hlbc panicked, this is a bug");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Description of the last error on this thread, null if there was none. Valid until the next call failing.
+#[no_mangle]
+pub extern "C" fn hlbc_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Release a string returned by hlbc
+///
+/// # Safety
+/// `s` must have been returned by hlbc and not released already, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Load a bytecode file, returns null on error
+///
+/// # Safety
+/// `path` must be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_load_file(path: *const c_char) -> *mut HlbcBytecode {
+    if path.is_null() {
+        set_error("Null path");
+        return ptr::null_mut();
+    }
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    load(|| Bytecode::from_file(path))
+}
+
+/// Load a bytecode from memory, the data is copied. Returns null on error.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_load_bytes(data: *const u8, len: usize) -> *mut HlbcBytecode {
+    if data.is_null() {
+        set_error("Null data");
+        return ptr::null_mut();
+    }
+    let data = std::slice::from_raw_parts(data, len);
+    load(|| Bytecode::from_bytes(data))
+}
+
+/// Release a bytecode
+///
+/// # Safety
+/// `code` must have been returned by a load function and not released already, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_free(code: *mut HlbcBytecode) {
+    if !code.is_null() {
+        drop(Box::from_raw(code));
+    }
+}
+
+/// Number of findexes, functions and natives share the same indexes
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_findex_count(code: *const HlbcBytecode) -> usize {
+    self::code(code).map_or(0, |code| code.findexes.len())
+}
+
+/// Number of functions with code
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_function_count(code: *const HlbcBytecode) -> usize {
+    self::code(code).map_or(0, |code| code.functions.len())
+}
+
+/// Findex of the function with code at `index`, `SIZE_MAX` if out of bounds
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_function_findex(code: *const HlbcBytecode, index: usize) -> usize {
+    self::code(code)
+        .ok()
+        .and_then(|code| code.functions.get(index))
+        .map_or(usize::MAX, |f| f.findex.0)
+}
+
+/// 1 if `findex` is a native, 0 if it's a function with code, -1 if it doesn't exist
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_is_native(code: *const HlbcBytecode, findex: usize) -> c_int {
+    match self::code(code).and_then(|code| fun(code, findex)) {
+        Ok(FunPtr::Native(_)) => 1,
+        Ok(FunPtr::Fun(_)) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Name of a function like `name@findex`, or `lib/name@findex` for a native
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_function_name(
+    code: *const HlbcBytecode,
+    findex: usize,
+) -> *mut c_char {
+    guard(|| {
+        let code = self::code(code)?;
+        Ok(fun(code, findex)?.display_id(code).to_string())
+    })
+}
+
+/// Disassembly of a function, the header only for a native
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_function_disassembly(
+    code: *const HlbcBytecode,
+    findex: usize,
+) -> *mut c_char {
+    guard(|| {
+        let code = self::code(code)?;
+        Ok(match fun(code, findex)? {
+            FunPtr::Fun(f) => f.display(code).to_string(),
+            FunPtr::Native(n) => n.display_header(code).to_string(),
+        })
+    })
+}
+
+/// Decompiled Haxe source of a function
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_function_decompile(
+    code: *const HlbcBytecode,
+    findex: usize,
+) -> *mut c_char {
+    guard(|| {
+        let code = self::code(code)?;
+        match fun(code, findex)? {
+            FunPtr::Fun(f) => hlbc_decompiler::decompile_function(code, f)
+                .map(|m| m.display(code, &FormatOptions::new("  ")).to_string())
+                .map_err(|e| e.to_string()),
+            FunPtr::Native(_) => Err(format!("fn@{findex} is a native")),
+        }
+    })
+}
+
+/// Number of types
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_type_count(code: *const HlbcBytecode) -> usize {
+    self::code(code).map_or(0, |code| code.types.len())
+}
+
+/// Name of a type like `name@index`
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_type_name(code: *const HlbcBytecode, index: usize) -> *mut c_char {
+    guard(|| {
+        let code = self::code(code)?;
+        match RefType(index).try_resolve(&code.types) {
+            Ok(_) => Ok(RefType(index).display_id(code)),
+            Err(_) => Err(format!("type@{index} doesn't exist")),
+        }
+    })
+}
+
+/// Decompiled Haxe source of a class
+///
+/// # Safety
+/// `code` must be a valid bytecode or null.
+#[no_mangle]
+pub unsafe extern "C" fn hlbc_class_decompile(
+    code: *const HlbcBytecode,
+    index: usize,
+) -> *mut c_char {
+    guard(|| {
+        let code = self::code(code)?;
+        match code.types.get(index) {
+            Some(Type::Obj(obj)) => hlbc_decompiler::decompile_class(code, obj)
+                .map(|c| c.display(code, &FormatOptions::new("  ")).to_string())
+                .map_err(|e| e.to_string()),
+            Some(_) => Err(format!("type@{index} is not a class")),
+            None => Err(format!("type@{index} doesn't exist")),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+    use std::ptr;
+
+    use hlbc::builder::sample;
+
+    use crate::*;
+
+    /// Copy and release a string returned by hlbc
+    unsafe fn take(s: *mut c_char) -> Option<String> {
+        if s.is_null() {
+            return None;
+        }
+        let owned = CStr::from_ptr(s).to_string_lossy().into_owned();
+        hlbc_string_free(s);
+        Some(owned)
+    }
+
+    #[test]
+    fn api() {
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+        unsafe {
+            let code = hlbc_load_bytes(data.as_ptr(), data.len());
+            assert!(!code.is_null());
+            assert!(hlbc_function_count(code) > 0);
+            let findex = hlbc_function_findex(code, 0);
+            assert_eq!(hlbc_is_native(code, findex), 0);
+            assert!(take(hlbc_function_name(code, findex))
+                .unwrap()
+                .ends_with(&format!("@{findex}")));
+            assert!(take(hlbc_function_disassembly(code, findex))
+                .unwrap()
+                .contains("Ret"));
+            // Decompiler panics are caught, not all functions of the sample can be decompiled
+            let decompiled = (0..hlbc_function_count(code))
+                .filter_map(|i| take(hlbc_function_decompile(code, hlbc_function_findex(code, i))))
+                .count();
+            assert!(decompiled > 0);
+            assert!(hlbc_type_count(code) > 0);
+            assert!(take(hlbc_type_name(code, 0)).is_some());
+
+            let count = hlbc_findex_count(code);
+            assert_eq!(hlbc_is_native(code, count), -1);
+            assert!(take(hlbc_function_disassembly(code, count)).is_none());
+            let error = CStr::from_ptr(hlbc_last_error()).to_str().unwrap();
+            assert_eq!(error, format!("fn@{count} doesn't exist"));
+            hlbc_free(code);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        unsafe {
+            assert!(hlbc_load_bytes([0u8; 4].as_ptr(), 4).is_null());
+            assert!(!hlbc_last_error().is_null());
+            assert_eq!(hlbc_function_count(ptr::null()), 0);
+            assert!(take(hlbc_function_name(ptr::null(), 0)).is_none());
+            hlbc_free(ptr::null_mut());
+        }
+    }
+}
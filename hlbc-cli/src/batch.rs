@@ -0,0 +1,353 @@
+//! Batch mode, runs an analysis on every bytecode file of a directory.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::iter::Peekable;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str::{Chars, FromStr};
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::{anyhow, bail};
+
+use hlbc::Bytecode;
+use hlbc_analysis::diff::{diff, timeline, Change};
+
+/// An analysis to run on each file
+#[derive(Debug, Clone)]
+pub enum Analysis {
+    /// Size of the constant pools
+    Stats,
+    /// Strings containing the pattern
+    Search(String),
+    /// Functions matching known signatures
+    Sigs,
+    /// Functions changed compared to a baseline file
+    Diff(PathBuf),
+    /// When each function changed, files being successive versions
+    Timeline,
+}
+
+impl FromStr for Analysis {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = s.split_once(' ').unwrap_or((s, ""));
+        let arg = arg.trim();
+        Ok(match (name, arg) {
+            ("stats", _) => Analysis::Stats,
+            ("sigs", _) => Analysis::Sigs,
+            ("timeline", _) => Analysis::Timeline,
+            ("search", s) if !s.is_empty() => Analysis::Search(s.to_owned()),
+            ("diff", s) if !s.is_empty() => Analysis::Diff(PathBuf::from(s)),
+            _ => bail!("Unknown analysis '{s}', expected one of 'stats', 'search <str>', 'sigs', 'diff <baseline>', 'timeline'"),
+        })
+    }
+}
+
+/// Result of an analysis on a single file
+enum Report {
+    Stats {
+        version: u8,
+        functions: usize,
+        types: usize,
+        strings: usize,
+        natives: usize,
+    },
+    Search(Vec<String>),
+    Sigs(Vec<String>),
+    Diff {
+        added: usize,
+        removed: usize,
+        modified: usize,
+        types: usize,
+        added_strings: usize,
+        removed_strings: usize,
+    },
+}
+
+/// Run `f` on every item with a pool of `threads` workers. Results are in the same order as the items.
+/// A panic in `f` is caught and reported as an error for this item only.
+pub fn run_pool<T, R, F>(items: Vec<T>, threads: usize, f: F) -> Vec<anyhow::Result<R>>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> anyhow::Result<R> + Sync,
+{
+    let count = items.len();
+    let queue = Mutex::new(items.into_iter().enumerate().collect::<VecDeque<_>>());
+    let results = Mutex::new(Vec::with_capacity(count));
+    thread::scope(|s| {
+        for _ in 0..threads.clamp(1, count.max(1)) {
+            s.spawn(|| loop {
+                let Some((i, item)) = queue.lock().unwrap().pop_front() else {
+                    break;
+                };
+                let res = catch_unwind(AssertUnwindSafe(|| f(item)))
+                    .unwrap_or_else(|_| Err(anyhow!("panicked during the analysis")));
+                results.lock().unwrap().push((i, res));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Find every bytecode file in a directory, recursively
+fn find_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_files(&path, files)?;
+        } else if path.extension().map(|e| e == "hl").unwrap_or(false) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn load(path: &Path) -> anyhow::Result<Bytecode> {
+    Ok(Bytecode::from_file(path)?)
+}
+
+fn analyze(
+    path: &Path,
+    analysis: &Analysis,
+    baseline: Option<&Bytecode>,
+) -> anyhow::Result<Report> {
+    let code = load(path)?;
+    Ok(match analysis {
+        Analysis::Stats => Report::Stats {
+            version: code.version,
+            functions: code.functions.len(),
+            types: code.types.len(),
+            strings: code.strings.len(),
+            natives: code.natives.len(),
+        },
+        Analysis::Search(pattern) => Report::Search(
+            code.strings
+                .iter()
+                .filter(|s| s.contains(pattern.as_str()))
+                .map(|s| s.to_string())
+                .collect(),
+        ),
+        Analysis::Sigs => {
+            #[cfg(feature = "autotag")]
+            {
+                use hlbc_analysis::signatures::{Signatures, DEFAULT_MIN_CONFIDENCE};
+
+                Report::Sigs(
+                    Signatures::default_signatures()
+                        .find(&code)
+                        .into_iter()
+                        .filter(|m| m.confidence > DEFAULT_MIN_CONFIDENCE)
+                        .map(|m| m.name)
+                        .collect(),
+                )
+            }
+            #[cfg(not(feature = "autotag"))]
+            bail!("Signatures require the feature 'autotag'")
+        }
+        Analysis::Timeline => unreachable!(),
+        Analysis::Diff(_) => {
+            let diff = diff(baseline.unwrap(), &code);
+            Report::Diff {
+                added: diff.count(Change::Added),
+                removed: diff.count(Change::Removed),
+                modified: diff.count(Change::Modified),
+                types: diff.type_changes().count(),
+                added_strings: diff.added_strings.len(),
+                removed_strings: diff.removed_strings.len(),
+            }
+        }
+    })
+}
+
+/// Run the analysis on every file in `dir` and print the results
+pub fn run(dir: &Path, analysis: &Analysis, threads: usize) -> anyhow::Result<()> {
+    let mut files = Vec::new();
+    find_files(dir, &mut files)?;
+    files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    if files.is_empty() {
+        bail!("No bytecode file in {}", dir.display());
+    }
+    if let Analysis::Timeline = analysis {
+        return run_timeline(&files, threads);
+    }
+
+    let baseline = match analysis {
+        Analysis::Diff(path) => Some(load(path)?),
+        _ => None,
+    };
+    let results = run_pool(files.clone(), threads, |path| {
+        analyze(&path, analysis, baseline.as_ref())
+    });
+
+    let mut errors = 0;
+    // Number of files each signature was found in
+    let mut sigs: BTreeMap<String, usize> = BTreeMap::new();
+    let mut found = 0;
+    for (path, res) in files.iter().zip(results) {
+        let path = path.strip_prefix(dir).unwrap_or(path).display();
+        match res {
+            Ok(Report::Stats {
+                version,
+                functions,
+                types,
+                strings,
+                natives,
+            }) => println!(
+                "{path} : v{version}, {functions} functions, {types} types, {strings} strings, {natives} natives"
+            ),
+            Ok(Report::Search(strings)) => {
+                if !strings.is_empty() {
+                    found += 1;
+                    println!("{path} :");
+                    for s in strings {
+                        println!("  {s}");
+                    }
+                }
+            }
+            Ok(Report::Sigs(names)) => {
+                println!("{path} : {} functions recognized", names.len());
+                for name in names {
+                    *sigs.entry(name).or_default() += 1;
+                }
+            }
+            Ok(Report::Diff {
+                added,
+                removed,
+                modified,
+                types,
+                added_strings,
+                removed_strings,
+            }) => println!(
+                "{path} : {added} added, {removed} removed, {modified} modified functions, {types} types changed, \
+                +{added_strings}/-{removed_strings} strings"
+            ),
+            Err(e) => {
+                errors += 1;
+                println!("{path} : error : {e}");
+            }
+        }
+    }
+
+    match analysis {
+        Analysis::Search(_) => println!("\nFound in {found}/{} files", files.len()),
+        Analysis::Sigs => {
+            println!();
+            for (name, count) in sigs {
+                println!("{name} ({count}/{} files)", files.len());
+            }
+        }
+        _ => {}
+    }
+    if errors > 0 {
+        println!("\n{errors}/{} files failed", files.len());
+    }
+    Ok(())
+}
+
+/// Print the timeline of every function that changed at least once
+fn run_timeline(files: &[PathBuf], threads: usize) -> anyhow::Result<()> {
+    let versions: Vec<String> = files
+        .iter()
+        .map(|p| {
+            p.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    let code = run_pool(files.to_vec(), threads, |path| load(&path))
+        .into_iter()
+        .zip(&versions)
+        .map(|(res, v)| res.map_err(|e| anyhow!("{v} : {e}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let code: Vec<&Bytecode> = code.iter().collect();
+
+    for f in timeline(&code) {
+        if f.events.is_empty() {
+            continue;
+        }
+        let events: Vec<String> = f
+            .events
+            .iter()
+            .map(|(v, change)| {
+                let change = match change {
+                    Change::Added => "added",
+                    Change::Removed => "removed",
+                    Change::Modified => "modified",
+                    Change::Unchanged => "unchanged",
+                };
+                format!("{change} in {}", versions[*v])
+            })
+            .collect();
+        println!("{} : {}", f.name, events.join(", "));
+    }
+    Ok(())
+}
+
+/// Compare strings with numbers compared by value, so that `v1.10` comes after `v1.9`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let number = |it: &mut Peekable<Chars>| {
+                    let mut n = String::new();
+                    while let Some(c) = it.next_if(|c| c.is_ascii_digit()) {
+                        n.push(c);
+                    }
+                    n.trim_start_matches('0').to_owned()
+                };
+                let (x, y) = (number(&mut a), number(&mut b));
+                let ord = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::batch::{natural_cmp, run_pool};
+
+    #[test]
+    fn pool_isolates_errors() {
+        let results = run_pool((0..20).collect(), 4, |i: i32| {
+            if i == 7 {
+                panic!("boom");
+            }
+            Ok(i * 2)
+        });
+        assert_eq!(results.len(), 20);
+        assert!(results[7].is_err());
+        assert_eq!(*results[8].as_ref().unwrap(), 16);
+    }
+
+    #[test]
+    fn natural_order() {
+        assert_eq!(natural_cmp("v1.9.hl", "v1.10.hl"), Ordering::Less);
+        assert_eq!(natural_cmp("v2.hl", "v10.hl"), Ordering::Less);
+        assert_eq!(natural_cmp("b.hl", "a.hl"), Ordering::Greater);
+    }
+}
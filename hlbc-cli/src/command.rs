@@ -4,8 +4,16 @@ use chumsky::prelude::*;
 use chumsky::text::*;
 pub use chumsky::Parser;
 
+use hlbc::analysis::tags::TagTarget;
+use hlbc::syntax::Dialect;
+use hlbc::types::{RefFun, RefType};
+use hlbc::Bytecode;
+
 pub type IndexRange = Range<usize>;
 
+/// Depth of a call tree when only `--tree` is given
+pub const DEFAULT_TREE_DEPTH: usize = 5;
+
 #[derive(Debug, Clone)]
 pub enum FileOrIndex {
     File(String),
@@ -17,6 +25,54 @@ pub enum ElementRef {
     String(usize),
     Global(usize),
     Fn(usize),
+    Type(usize),
+    /// Type and field index
+    Field(usize, usize),
+}
+
+/// An argument given to a function on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Null,
+}
+
+/// A change to the inlining heuristics of the decompiler
+#[derive(Debug, Clone, PartialEq)]
+pub enum InlineSetting {
+    Compact,
+    Flat,
+    /// Largest expression inlined, no limit if none
+    Size(Option<usize>),
+    /// Largest number of reads of an inlined value, no limit if none
+    Uses(Option<usize>),
+    /// Inline calls and other expressions with side effects
+    SideEffects(bool),
+    /// Name the variables without a debug name from their use
+    Names(bool),
+    /// Undo control flow flattening
+    Unflatten(bool),
+}
+
+/// Export or import a rename map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenamesAction {
+    Export(String),
+    Import(String),
+    /// Import a symbol map (CSV, IDA or Ghidra), replacing the conflicting renames or not
+    Symbols(String, bool),
+}
+
+/// What to do with the debug information when writing the bytecode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebugInfo {
+    /// Remove file names, line numbers and variable names
+    Strip,
+    /// Replace them with ones pointing to the decompiled output
+    Inject,
 }
 
 #[derive(Debug, Clone)]
@@ -50,11 +106,94 @@ pub enum Command {
     SearchFunction(String),
     InFile(FileOrIndex),
     FileOf(usize),
-    SaveTo(String),
+    SaveTo(String, Option<DebugInfo>),
+    /// Show the manifest of a modified file
+    Provenance,
+    /// Extract a function and its dependencies to a standalone file
+    Extract(usize, String),
+    /// Reduce a function making the decompiler panic to a small standalone file
+    Minimize(usize, String),
+    /// Write a copy of the bytecode printing registers before an instruction : function, position, registers, file
+    Instrument(usize, usize, Vec<u32>, String),
+    /// Assemble a listing over a function and write the bytecode to a file : function, listing, file
+    Reassemble(usize, String, String),
     Callgraph(usize, usize),
+    /// Run a pure function in the sandboxed interpreter with arguments
+    Eval(usize, Vec<Literal>),
+    /// Backward and forward slices of an instruction : function, position, register
+    Slice(usize, usize, Option<u32>),
+    /// Functions calling a function, as a tree of transitive callers up to a depth
+    Callers(usize, Option<usize>),
+    /// Functions called by a function, as a tree of transitive callees up to a depth
+    Callees(usize, Option<usize>),
+    /// Functions unreachable from the entrypoint
+    Dead,
+    /// Classes and functions used by name at runtime, through reflection
+    Reflection,
+    /// Dependencies between packages by layer with the cycles, as a dot graph if true
+    Packages(bool),
     RefTo(ElementRef),
     DecompType(usize),
     Decomp(usize),
+    /// Decompile every class to a directory, one file per class
+    Export(String),
+    /// Declare every class as an extern in a directory, one file per class
+    Externs(String),
+    /// Show or change the inlining heuristics of the decompiler
+    Inline(Option<InlineSetting>),
+    /// Show or change the syntax of the disassembly
+    Syntax(Option<Dialect>),
+    /// Search the decompiled functions for an expression pattern
+    AstFind(String),
+    /// Add a rewrite rule applied by the decompiler, list them if empty, `clear` to remove them
+    Rewrite(String),
+    /// Attach a tag to a function or a type
+    Tag(TagTarget, String),
+    /// Detach a tag from a function or a type
+    Untag(TagTarget, String),
+    /// List all tags or every element with a tag
+    Tags(Option<String>),
+    /// Only show elements with a tag in listings, no tag to reset
+    TagFilter(Option<String>),
+    /// Attach a note to an instruction of a function
+    Note(usize, usize, String),
+    /// List functions with anomalies (likely obfuscated)
+    Anomalies,
+    /// Size and complexity of a function, or of the whole module with the most complex functions
+    Metrics(Option<usize>),
+    /// List the likely main function, update loops and event handlers
+    Entries,
+    /// Show how the program starts : the globals constructed by the entrypoint and the static initializers
+    Boot,
+    /// List the native libraries, or the natives of a library with their callers
+    Libs(Option<String>),
+    /// Show the summary of a class as Markdown
+    Card(usize),
+    /// Show the offsets of the fields and the virtual table of a class or a structure, by index or by name
+    Layout(String),
+    /// Export the summaries of every class to a Markdown file
+    Cards(String),
+    /// Export the bytecode as a SQLite database
+    Sqlite(String),
+    /// Export the offsets of the static variables and singletons as a Cheat Engine table (`.ct`) or JSON, with the
+    /// number of objects to go through
+    Pointers(String, Option<usize>),
+    /// Show the deobfuscated bytecode of a function
+    Deobf(usize),
+    /// Show the game profile in use
+    Profile,
+    /// List functions named from signatures
+    Sigs,
+    /// Generate signatures for the named functions to a file
+    SigMake(String),
+    /// Export the analysis database to a file
+    DbExport(String),
+    /// Rename an element : kind (class, fn or field), current name and new name
+    Rename(String, String, String),
+    /// List the renames, or export or import them
+    Renames(Option<RenamesAction>),
+    /// Any other command, handled by a plugin : name and arguments
+    Plugin(String, String),
 }
 
 // Used a default max values for index ranges
@@ -71,6 +210,23 @@ pub struct ParseContext {
     pub findex_max: usize,
 }
 
+impl ParseContext {
+    /// Index ranges of a bytecode
+    pub fn new(code: &Bytecode) -> Self {
+        Self {
+            int_max: code.ints.len(),
+            float_max: code.floats.len(),
+            string_max: code.strings.len(),
+            debug_file_max: code.debug_files.as_ref().map(|v| v.len()).unwrap_or(0),
+            type_max: code.types.len(),
+            global_max: code.globals.len(),
+            native_max: code.natives.len(),
+            constant_max: code.constants.as_ref().map(|v| v.len()).unwrap_or(0),
+            findex_max: code.findexes.len(),
+        }
+    }
+}
+
 /// Parse a command
 pub fn parse_command(ctx: &ParseContext, line: &str) -> Result<Command, Vec<Simple<char>>> {
     command_parser(ctx).padded().parse(line)
@@ -111,17 +267,135 @@ pub fn command_parser(ctx: &ParseContext) -> impl Parser<char, Command, Error =
 
     let string = string();
 
-    // We split the parsers in 2 to not overflow the tuple maximum size
+    // We split the parsers in groups to not overflow the tuple maximum size
 
     let core_cmds = choice((
         cmd!("exit" => Exit),
         cmd!("help" => Help),
         cmd!("explain"; string.clone() => Explain),
         cmd!("wiki" => Wiki),
+        cmd!("inline"; inline_setting().or_not() => Inline),
+        cmd!("syntax"; dialect().or_not() => Syntax),
+        cmd!("astfind"; string.clone() => AstFind),
+        cmd!("rewrite"; string.clone() => Rewrite),
+    ));
+
+    let tag_cmds = choice((
+        cmd!("tagfilter"; word().or_not() => TagFilter),
+        cmd!("tags"; word().or_not() => Tags),
+        cmd!("tag")
+            .ignore_then(tag_target())
+            .then(word().padded())
+            .map(|(t, tag)| Tag(t, tag)),
+        cmd!("untag")
+            .ignore_then(tag_target())
+            .then(word().padded())
+            .map(|(t, tag)| Untag(t, tag)),
+        cmd!("note")
+            .ignore_then(num())
+            .then(num().padded())
+            .then(string.clone())
+            .map(|((f, pos), text)| Note(f, pos, text.trim().to_owned())),
+    ));
+
+    let save_cmds = choice((
+        cmd!("saveto")
+            .ignore_then(debug_info().or_not())
+            .then(string.clone())
+            .map(|(debug, file)| SaveTo(file.trim().to_owned(), debug)),
+        cmd!("provenance" => Provenance),
+        cmd!("export"; string.clone() => Export),
+        cmd!("externs"; string.clone() => Externs),
+        cmd!("extract")
+            .ignore_then(num())
+            .then(string.clone())
+            .map(|(f, file)| Extract(f, file.trim().to_owned())),
+        cmd!("minimize")
+            .ignore_then(num())
+            .then(string.clone())
+            .map(|(f, file)| Minimize(f, file.trim().to_owned())),
+        cmd!("instrument")
+            .ignore_then(num())
+            .then(num().padded())
+            .then(num().map(|r| r as u32).separated_by(just(',')).at_least(1))
+            .then(string.clone())
+            .map(|(((f, pos), regs), file)| Instrument(f, pos, regs, file.trim().to_owned())),
+        cmd!("reassemble")
+            .ignore_then(num())
+            .then(word().padded())
+            .then(string.clone())
+            .map(|((f, listing), file)| Reassemble(f, listing, file.trim().to_owned())),
+    ));
+
+    let analysis_cmds = choice((
+        cmd!("anomalies" => Anomalies),
+        cmd!("metrics"; num().or_not() => Metrics),
+        cmd!("entries" => Entries),
+        cmd!("boot" => Boot),
+        cmd!("libs"; word().or_not() => Libs),
+        cmd!("card"; num() => Card),
+        cmd!("layout"; word() => Layout),
+        cmd!("cards"; string.clone() => Cards),
+        cmd!("sqlite"; string.clone() => Sqlite),
+        cmd!("pointers")
+            .ignore_then(word().padded())
+            .then(num().or_not())
+            .map(|(file, depth)| Pointers(file, depth)),
+        cmd!("deobf"; num() => Deobf),
+        cmd!("profile" => Profile),
+        cmd!("sigs" => Sigs),
+        cmd!("sigmake"; string.clone() => SigMake),
+        cmd!("dbexport"; string.clone() => DbExport),
+        cmd!("renames")
+            .ignore_then(
+                choice((
+                    just("export")
+                        .ignore_then(string.clone())
+                        .map(|f| RenamesAction::Export(f.trim().to_owned())),
+                    just("import")
+                        .ignore_then(string.clone())
+                        .map(|f| RenamesAction::Import(f.trim().to_owned())),
+                    just("symbols")
+                        .ignore_then(word().padded())
+                        .then(just("replace").or_not())
+                        .map(|(f, replace)| RenamesAction::Symbols(f, replace.is_some())),
+                ))
+                .or_not(),
+            )
+            .map(Renames),
+        cmd!("rename")
+            .ignore_then(word())
+            .then(word().padded())
+            .then(word())
+            .map(|((kind, old), new)| Rename(kind, old, new)),
+        cmd!("callers")
+            .ignore_then(num())
+            .then(tree_depth())
+            .map(|(f, d)| Callers(f, d)),
+        cmd!("callees")
+            .ignore_then(num())
+            .then(tree_depth())
+            .map(|(f, d)| Callees(f, d)),
+        cmd!("dead" => Dead),
+        cmd!("reflection" => Reflection),
+        cmd!("packages")
+            .ignore_then(just("dot").padded().or_not())
+            .map(|dot| Packages(dot.is_some())),
+        cmd!("slice")
+            .ignore_then(just("fn@").or(just("f@")).ignore_then(num()))
+            .then_ignore(just(':'))
+            .then(num())
+            .then(just("reg").padded().ignore_then(num()).or_not())
+            .map(|((f, pos), reg)| Slice(f, pos, reg.map(|r| r as u32))),
+        cmd!("eval")
+            .ignore_then(just("fn@").or(just("f@")).or_not().ignore_then(num()))
+            .then(literal().padded().repeated())
+            .map(|(f, args)| Eval(f, args)),
     ));
 
     choice((
         core_cmds,
+        tag_cmds,
         cmd!("info" => Info),
         cmd!("entrypoint" => Entrypoint),
         cmd!("int", "i"; index_range(ctx.int_max) => Int),
@@ -145,7 +419,7 @@ pub fn command_parser(ctx: &ParseContext) -> impl Parser<char, Command, Error =
                 .map(|v| InFile(FileOrIndex::File(v.into_iter().collect()))),
         ))),
         cmd!("fileof"; num() => FileOf),
-        cmd!("saveto"; string.clone() => SaveTo),
+        save_cmds,
         cmd!("callgraph")
             .ignore_then(num())
             .then(num().padded())
@@ -155,11 +429,22 @@ pub fn command_parser(ctx: &ParseContext) -> impl Parser<char, Command, Error =
                 just("string@").ignore_then(num()).map(ElementRef::String),
                 just("global@").ignore_then(num()).map(ElementRef::Global),
                 just("fn@").ignore_then(num()).map(ElementRef::Fn),
+                just("type@").ignore_then(num()).map(ElementRef::Type),
+                just("field@")
+                    .ignore_then(num())
+                    .then_ignore(just('.'))
+                    .then(num())
+                    .map(|(t, f)| ElementRef::Field(t, f)),
             )))
             .map(RefTo),
         cmd!("decomp"; num() => Decomp),
         cmd!("decompt"; num() => DecompType),
     ))
+    .or(analysis_cmds)
+    // Fallback to plugin commands
+    .or(word()
+        .then(string)
+        .map(|(name, args)| Plugin(name, args.trim().to_owned())))
 }
 
 fn string() -> impl Parser<char, String, Error = Simple<char>> + Clone {
@@ -168,6 +453,116 @@ fn string() -> impl Parser<char, String, Error = Simple<char>> + Clone {
         .map(|v| v.into_iter().collect())
 }
 
+/// A sequence of non whitespace characters
+fn word() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    filter(|c: &char| !c.is_whitespace() && c != &';')
+        .repeated()
+        .at_least(1)
+        .map(|v| v.into_iter().collect())
+}
+
+fn tag_target() -> impl Parser<char, TagTarget, Error = Simple<char>> {
+    choice((
+        just("fn@")
+            .ignore_then(num())
+            .map(|i| TagTarget::Fun(RefFun(i))),
+        just("type@")
+            .ignore_then(num())
+            .map(|i| TagTarget::Type(RefType(i))),
+    ))
+    .labelled("tag target")
+}
+
+/// Optional `--tree` and `--depth <n>` switches, `--depth` alone implies `--tree`
+fn tree_depth() -> impl Parser<char, Option<usize>, Error = Simple<char>> {
+    just("--tree")
+        .padded()
+        .or_not()
+        .then(just("--depth").padded().ignore_then(num()).or_not())
+        .map(|(tree, depth)| depth.or(tree.map(|_| DEFAULT_TREE_DEPTH)))
+}
+
+/// `--strip` or `--inject`
+fn debug_info() -> impl Parser<char, DebugInfo, Error = Simple<char>> {
+    choice((
+        just("--strip").to(DebugInfo::Strip),
+        just("--inject").to(DebugInfo::Inject),
+    ))
+    .padded()
+}
+
+/// `compact`, `flat`, `size [n]`, `uses [n]` or `calls on|off`
+fn inline_setting() -> impl Parser<char, InlineSetting, Error = Simple<char>> {
+    choice((
+        just("compact").to(InlineSetting::Compact),
+        just("flat").to(InlineSetting::Flat),
+        just("size")
+            .ignore_then(num().padded().or_not())
+            .map(InlineSetting::Size),
+        just("uses")
+            .ignore_then(num().padded().or_not())
+            .map(InlineSetting::Uses),
+        just("calls")
+            .ignore_then(just("on").to(true).or(just("off").to(false)).padded())
+            .map(InlineSetting::SideEffects),
+        just("names")
+            .ignore_then(just("on").to(true).or(just("off").to(false)).padded())
+            .map(InlineSetting::Names),
+        just("unflatten")
+            .ignore_then(just("on").to(true).or(just("off").to(false)).padded())
+            .map(InlineSetting::Unflatten),
+    ))
+}
+
+/// `enhanced`, `canonical` or `hldump`
+fn dialect() -> impl Parser<char, Dialect, Error = Simple<char>> {
+    choice((
+        just("enhanced").to(Dialect::Enhanced),
+        just("canonical").to(Dialect::Canonical),
+        just("hldump").to(Dialect::HlDump),
+    ))
+}
+
+/// A number, a boolean, `null` or a double quoted string with `\"` and `\\` escapes
+fn literal() -> impl Parser<char, Literal, Error = Simple<char>> {
+    let escape = just('\\').ignore_then(one_of("\\\""));
+    let quoted = just('"')
+        .ignore_then(
+            filter(|c: &char| *c != '\\' && *c != '"')
+                .or(escape)
+                .repeated(),
+        )
+        .then_ignore(just('"'))
+        .collect::<String>()
+        .map(Literal::Str);
+    let sign = just('-')
+        .or_not()
+        .map(|s| if s.is_some() { "-" } else { "" });
+    let float = sign
+        .then(int(10))
+        .then_ignore(just('.'))
+        .then(digits(10))
+        .map(|((s, i), d)| Literal::Float(format!("{s}{i}.{d}").parse().unwrap()));
+    let integer =
+        sign.then(int(10))
+            .validate(|(s, i), span, emit| match format!("{s}{i}").parse() {
+                Ok(i) => Literal::Int(i),
+                Err(e) => {
+                    emit(Simple::custom(span, e));
+                    Literal::Int(0)
+                }
+            });
+    choice((
+        quoted,
+        float,
+        integer,
+        just("true").to(Literal::Bool(true)),
+        just("false").to(Literal::Bool(false)),
+        just("null").to(Literal::Null),
+    ))
+    .labelled("literal")
+}
+
 fn num() -> impl Parser<char, usize, Error = Simple<char>> {
     int::<_, Simple<char>>(10)
         .map(|s: String| s.parse::<usize>().unwrap())
@@ -203,8 +598,13 @@ fn index_range(max: usize) -> impl Parser<char, IndexRange, Error = Simple<char>
 mod tests {
     use chumsky::Parser;
 
+    use hlbc::analysis::tags::TagTarget;
+    use hlbc::syntax::Dialect;
+    use hlbc::types::RefFun;
+
     use crate::command::{
-        index_range, parse_command, parse_commands, Command, FileOrIndex, ParseContext,
+        index_range, parse_command, parse_commands, Command, DebugInfo, FileOrIndex, InlineSetting,
+        Literal, ParseContext, RenamesAction, DEFAULT_TREE_DEPTH,
     };
 
     #[test]
@@ -239,7 +639,7 @@ mod tests {
     fn test_index_single() {
         assert_eq!(
             (4..5).sum::<usize>(),
-            index_range(10).parse("4").unwrap().sum()
+            index_range(10).parse("4").unwrap().sum::<usize>()
         );
     }
 
@@ -322,4 +722,221 @@ mod tests {
             _ => false,
         });
     }
+
+    #[test]
+    fn test_tag() {
+        let parsed = parse_command(&ParseContext::default(), "tag fn@12 network");
+        assert!(match parsed {
+            Ok(Command::Tag(TagTarget::Fun(RefFun(12)), tag)) => {
+                tag == "network"
+            }
+            _ => false,
+        });
+        let parsed = parse_command(&ParseContext::default(), "tags");
+        assert!(matches!(parsed, Ok(Command::Tags(None))));
+        let parsed = parse_command(&ParseContext::default(), "tagfilter crypto");
+        assert!(match parsed {
+            Ok(Command::TagFilter(Some(tag))) => tag == "crypto",
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_plugin_command() {
+        let parsed = parse_command(&ParseContext::default(), "items weapons rare");
+        assert!(match parsed {
+            Ok(Command::Plugin(name, args)) => name == "items" && args == "weapons rare",
+            _ => false,
+        });
+        // Builtin commands take precedence
+        let parsed = parse_command(&ParseContext::default(), "exit");
+        assert!(matches!(parsed, Ok(Command::Exit)));
+    }
+
+    #[test]
+    fn test_instrument() {
+        let parsed = parse_command(&ParseContext::default(), "instrument 12 4 3,5 out.hl");
+        assert!(match parsed {
+            Ok(Command::Instrument(12, 4, regs, file)) => regs == [3, 5] && file == "out.hl",
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_reassemble() {
+        let parsed = parse_command(&ParseContext::default(), "reassemble 12 main.hlasm out.hl");
+        assert!(match parsed {
+            Ok(Command::Reassemble(12, listing, file)) =>
+                listing == "main.hlasm" && file == "out.hl",
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_inline() {
+        let parse = |line| parse_command(&ParseContext::default(), line);
+        assert!(matches!(parse("inline"), Ok(Command::Inline(None))));
+        assert!(matches!(
+            parse("inline flat"),
+            Ok(Command::Inline(Some(InlineSetting::Flat)))
+        ));
+        assert!(matches!(
+            parse("inline size 4"),
+            Ok(Command::Inline(Some(InlineSetting::Size(Some(4)))))
+        ));
+        assert!(matches!(
+            parse("inline uses"),
+            Ok(Command::Inline(Some(InlineSetting::Uses(None))))
+        ));
+        assert!(matches!(
+            parse("inline calls off"),
+            Ok(Command::Inline(Some(InlineSetting::SideEffects(false))))
+        ));
+        assert!(matches!(
+            parse("inline unflatten off"),
+            Ok(Command::Inline(Some(InlineSetting::Unflatten(false))))
+        ));
+        // Still the int command
+        assert!(matches!(parse("i 0"), Ok(Command::Int(_))));
+    }
+
+    #[test]
+    fn test_syntax() {
+        let parse = |s| parse_command(&ParseContext::default(), s);
+        assert!(matches!(parse("syntax"), Ok(Command::Syntax(None))));
+        assert!(matches!(
+            parse("syntax hldump"),
+            Ok(Command::Syntax(Some(Dialect::HlDump)))
+        ));
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let parse = |s| parse_command(&ParseContext::default(), s);
+        assert!(
+            matches!(parse("rewrite $a + 0 => $a"), Ok(Command::Rewrite(r)) if r.trim() == "$a + 0 => $a")
+        );
+        assert!(matches!(parse("rewrite"), Ok(Command::Rewrite(r)) if r.is_empty()));
+        assert!(
+            matches!(parse("astfind f($x, \"a\")"), Ok(Command::AstFind(p)) if p.trim() == "f($x, \"a\")")
+        );
+    }
+
+    #[test]
+    fn test_saveto() {
+        let parse = |s| parse_command(&ParseContext::default(), s);
+        assert!(matches!(parse("saveto out.hl"), Ok(Command::SaveTo(f, None)) if f == "out.hl"));
+        assert!(
+            matches!(parse("saveto --inject out.hl"), Ok(Command::SaveTo(f, Some(DebugInfo::Inject))) if f == "out.hl")
+        );
+        assert!(matches!(
+            parse("saveto --strip out.hl"),
+            Ok(Command::SaveTo(_, Some(DebugInfo::Strip)))
+        ));
+    }
+
+    #[test]
+    fn test_renames() {
+        let parse = |s| parse_command(&ParseContext::default(), s);
+        assert!(matches!(
+            parse("rename class a.b game.Player"),
+            Ok(Command::Rename(k, old, new)) if k == "class" && old == "a.b" && new == "game.Player"
+        ));
+        assert!(matches!(parse("renames"), Ok(Command::Renames(None))));
+        assert!(matches!(
+            parse("renames export map.toml"),
+            Ok(Command::Renames(Some(RenamesAction::Export(f)))) if f == "map.toml"
+        ));
+        assert!(matches!(
+            parse("renames symbols names.csv"),
+            Ok(Command::Renames(Some(RenamesAction::Symbols(f, false)))) if f == "names.csv"
+        ));
+        assert!(matches!(
+            parse("renames symbols names.idc replace"),
+            Ok(Command::Renames(Some(RenamesAction::Symbols(f, true)))) if f == "names.idc"
+        ));
+    }
+
+    #[test]
+    fn test_externs() {
+        assert!(matches!(
+            parse_command(&ParseContext::default(), "externs stubs/"),
+            Ok(Command::Externs(dir)) if dir.trim() == "stubs/"
+        ));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let parsed = parse_command(&ParseContext::default(), "metrics");
+        assert!(matches!(parsed, Ok(Command::Metrics(None))));
+        let parsed = parse_command(&ParseContext::default(), "metrics 12");
+        assert!(matches!(parsed, Ok(Command::Metrics(Some(12)))));
+        let parsed = parse_command(&ParseContext::default(), "boot");
+        assert!(matches!(parsed, Ok(Command::Boot)));
+        let parsed = parse_command(&ParseContext::default(), "libs");
+        assert!(matches!(parsed, Ok(Command::Libs(None))));
+        let parsed = parse_command(&ParseContext::default(), "libs ssl");
+        assert!(matches!(parsed, Ok(Command::Libs(Some(lib))) if lib == "ssl"));
+        let parsed = parse_command(&ParseContext::default(), "layout game.Player");
+        assert!(matches!(parsed, Ok(Command::Layout(name)) if name == "game.Player"));
+        let parsed = parse_command(&ParseContext::default(), "sqlite out.db");
+        assert!(matches!(parsed, Ok(Command::Sqlite(file)) if file.trim() == "out.db"));
+        let parsed = parse_command(&ParseContext::default(), "pointers game.ct 2");
+        assert!(matches!(parsed, Ok(Command::Pointers(file, Some(2))) if file == "game.ct"));
+    }
+
+    #[test]
+    fn test_slice() {
+        let parsed = parse_command(&ParseContext::default(), "slice f@12:4");
+        assert!(matches!(parsed, Ok(Command::Slice(12, 4, None))));
+        let parsed = parse_command(&ParseContext::default(), "slice fn@12:4 reg3");
+        assert!(matches!(parsed, Ok(Command::Slice(12, 4, Some(3)))));
+    }
+
+    #[test]
+    fn test_eval() {
+        let parsed = parse_command(
+            &ParseContext::default(),
+            r#"eval f@123 1 -2 0.5 true null "a \"b\"""#,
+        );
+        assert!(match parsed {
+            Ok(Command::Eval(123, args)) =>
+                args == [
+                    Literal::Int(1),
+                    Literal::Int(-2),
+                    Literal::Float(0.5),
+                    Literal::Bool(true),
+                    Literal::Null,
+                    Literal::Str("a \"b\"".to_owned())
+                ],
+            _ => false,
+        });
+        let parsed = parse_command(&ParseContext::default(), "eval 7");
+        assert!(matches!(parsed, Ok(Command::Eval(7, args)) if args.is_empty()));
+        let parsed = parse_command(&ParseContext::default(), "eval 7 99999999999999999999");
+        assert!(!matches!(parsed, Ok(Command::Eval(..))));
+    }
+
+    #[test]
+    fn test_call_tree() {
+        let parsed = parse_command(&ParseContext::default(), "callers 12");
+        assert!(matches!(parsed, Ok(Command::Callers(12, None))));
+        let parsed = parse_command(&ParseContext::default(), "callees 12 --tree");
+        assert!(matches!(
+            parsed,
+            Ok(Command::Callees(12, Some(DEFAULT_TREE_DEPTH)))
+        ));
+        let parsed = parse_command(&ParseContext::default(), "callers 12 --tree --depth 3");
+        assert!(matches!(parsed, Ok(Command::Callers(12, Some(3)))));
+        let parsed = parse_command(&ParseContext::default(), "callers 12 --depth 2");
+        assert!(matches!(parsed, Ok(Command::Callers(12, Some(2)))));
+        let parsed = parse_command(&ParseContext::default(), "dead");
+        assert!(matches!(parsed, Ok(Command::Dead)));
+        let parsed = parse_command(&ParseContext::default(), "reflection");
+        assert!(matches!(parsed, Ok(Command::Reflection)));
+        let parsed = parse_command(&ParseContext::default(), "packages");
+        assert!(matches!(parsed, Ok(Command::Packages(false))));
+        let parsed = parse_command(&ParseContext::default(), "packages dot");
+        assert!(matches!(parsed, Ok(Command::Packages(true))));
+    }
 }
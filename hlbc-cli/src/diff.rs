@@ -0,0 +1,84 @@
+//! Diff mode, functions changed between two versions of a bytecode file, optionally as decompiled sources.
+
+use std::path::PathBuf;
+
+use hlbc::Bytecode;
+use hlbc_analysis::diff::{diff, Change};
+use hlbc_decompiler::diff::diff_function;
+use hlbc_decompiler::inline::InlineOptions;
+
+#[derive(Debug, clap::Args)]
+pub struct DiffArgs {
+    /// The older version
+    old: PathBuf,
+    /// The newer version
+    new: PathBuf,
+    /// Show the changes of the modified functions as a unified diff of their decompiled sources. Moved lines are
+    /// marked with '<' where they were and '>' where they are now
+    #[clap(short, long)]
+    decompile: bool,
+    /// Only show the functions whose name contains this
+    #[clap(short, long)]
+    function: Option<String>,
+    /// Number of unchanged lines shown around the changes
+    #[clap(short = 'U', long, default_value = "3")]
+    context: usize,
+}
+
+/// Print the changes between two files
+pub fn run(args: &DiffArgs) -> anyhow::Result<()> {
+    let old = Bytecode::from_file(&args.old)?;
+    let new = Bytecode::from_file(&args.new)?;
+    let diff = diff(&old, &new);
+    let changes = diff.changes().filter(|f| {
+        args.function
+            .as_ref()
+            .map_or(true, |name| f.name.contains(name.as_str()))
+    });
+
+    if !args.decompile {
+        for f in changes {
+            let prefix = match f.change {
+                Change::Added => '+',
+                Change::Removed => '-',
+                _ => '~',
+            };
+            println!("{prefix} {}", f.name);
+        }
+        println!(
+            "{} added, {} removed, {} modified",
+            diff.count(Change::Added),
+            diff.count(Change::Removed),
+            diff.count(Change::Modified)
+        );
+        return Ok(());
+    }
+
+    let options = InlineOptions::default();
+    // Functions with different opcodes but the same source, a register or a constant index changed
+    let mut same_source = 0;
+    for f in changes.filter(|f| f.change == Change::Modified) {
+        let (Some(of), Some(nf)) = (
+            f.old.and_then(|i| i.resolve_as_fn(&old)),
+            f.new.and_then(|i| i.resolve_as_fn(&new)),
+        ) else {
+            continue;
+        };
+        match diff_function(&old, of, &new, nf, &options) {
+            Ok(source) if source.is_empty() => same_source += 1,
+            Ok(source) => print!(
+                "{}",
+                source.unified(
+                    &format!("a/{}", f.name),
+                    &format!("b/{}", f.name),
+                    args.context
+                )
+            ),
+            Err(e) => println!("{} : can't decompile : {e}", f.name),
+        }
+    }
+    if same_source > 0 {
+        println!("{same_source} modified functions decompile to the same source");
+    }
+    Ok(())
+}
@@ -0,0 +1,135 @@
+//! Inject mode, compiles a Haxe snippet and calls it at the start of a function of a bytecode file.
+//!
+//! The snippet is a class named after its file with a static `main`, compiled against the stubs written by the
+//! `externs` command to use the classes of the bytecode. It is [merged](hlbc::merge) into the bytecode, sharing the
+//! classes it uses, and its `main` is called before the first instruction of the hooked function.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use temp_dir::TempDir;
+
+use hlbc::merge::MergeReport;
+use hlbc::opcodes::Opcode;
+use hlbc::types::{RefFun, Type};
+use hlbc::verify::verify_function;
+use hlbc::Bytecode;
+
+use crate::compile;
+use crate::replace::target;
+
+#[derive(Debug, clap::Args)]
+pub struct InjectArgs {
+    /// The bytecode file to patch
+    file: PathBuf,
+    /// The Haxe snippet, a class named after the file with a static `main`
+    snippet: PathBuf,
+    /// The function calling the snippet first : 'fn@<findex>' or a name like 'Player.update'
+    #[clap(long)]
+    at: String,
+    /// The extern stubs of the file written by the 'externs' command
+    #[clap(long)]
+    externs: Option<PathBuf>,
+    /// Where to write the patched file
+    #[clap(short, long)]
+    output: PathBuf,
+}
+
+/// Merge the snippet and call the `main` of `class` at the start of `target`
+fn inject(
+    code: &mut Bytecode,
+    snippet: &Bytecode,
+    class: &str,
+    target: RefFun,
+) -> anyhow::Result<MergeReport> {
+    if target.resolve_as_fn(code).is_none() {
+        bail!("fn@{} is a native", target.0);
+    }
+    let main = match snippet
+        .function_index()
+        .lookup(snippet, &format!("{class}.main"))[..]
+    {
+        [main] => main,
+        _ => bail!("The snippet has no {class}.main"),
+    };
+    if !main.args(snippet).is_empty() {
+        bail!("{class}.main takes arguments");
+    }
+
+    let report = code.merge(snippet)?;
+    let hook = report.findexes[main.0];
+    let mut editor = code.edit();
+    let void = editor.ty(Type::Void);
+    let dst = editor.reg(target, void);
+    editor.insert_ops(target, 0, vec![Opcode::Call0 { dst, fun: hook }]);
+    Ok(report)
+}
+
+/// Inject the snippet, returns false without writing anything if the hooked function doesn't verify
+pub fn run(args: &InjectArgs) -> anyhow::Result<bool> {
+    let mut code = Bytecode::from_file(&args.file)?;
+    let target = target(&code, &args.at)?;
+    let class = args
+        .snippet
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .with_context(|| format!("{} is not a Haxe class", args.snippet.display()))?;
+
+    let dir = TempDir::new()?;
+    let compiled = dir.child("snippet.hl");
+    compile(&args.snippet, &compiled, args.externs.as_deref())?;
+    let snippet = Bytecode::from_file(&compiled)?;
+    let report = inject(&mut code, &snippet, class, target)?;
+    eprint!("{report}");
+
+    let f = target.resolve_as_fn(&code).unwrap();
+    let errors = verify_function(&code, f);
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("{e}");
+        }
+        eprintln!("{} errors, nothing written", errors.len());
+        return Ok(false);
+    }
+
+    let mut data = Vec::new();
+    code.serialize(&mut data)?;
+    fs::write(&args.output, data)?;
+    println!("fn@{} calls {class}.main first", target.0);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{sample, BytecodeBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+    use hlbc::verify::verify;
+
+    use crate::inject::inject;
+
+    #[test]
+    fn hook() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let main = b.findex();
+        b.class("$Snippet", None, &[], &[("main", main)]);
+        let ty = b.fun_type(&[], void);
+        b.function(main, ty, vec![void], vec![Opcode::Ret { ret: Reg(0) }]);
+        let snippet = b.build().unwrap();
+
+        let mut code = sample();
+        let target = code.functions[0].findex;
+        let report = inject(&mut code, &snippet, "Snippet", target).unwrap();
+        let hook = report.findexes[main.0];
+        let f = target.resolve_as_fn(&code).unwrap();
+        assert!(matches!(f.ops[0], Opcode::Call0 { fun, .. } if fun == hook));
+        assert_eq!(hook.name(&code), Some("main"));
+        assert_eq!(verify(&code), []);
+
+        assert!(inject(&mut sample(), &snippet, "Other", target).is_err());
+        let native = code.natives[0].findex;
+        assert!(inject(&mut sample(), &snippet, "Snippet", native).is_err());
+    }
+}
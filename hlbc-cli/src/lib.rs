@@ -0,0 +1,5 @@
+//! Command interpreter of hlbc-cli. The parser and the interpreter are also used by the console of the gui.
+
+/// Command parser
+pub mod command;
+pub mod session;
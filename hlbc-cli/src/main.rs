@@ -1,44 +1,181 @@
+#[cfg(feature = "autotag")]
 use std::fs;
-use std::io::{stdin, BufReader, BufWriter, Write};
-use std::iter::repeat;
+use std::io::{stdin, Write};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use clap::Parser as ClapParser;
 
-use hlbc::opcodes::Opcode;
-use hlbc::types::{FunPtr, RefFun, RefGlobal, Type};
 use hlbc::*;
+use hlbc_analysis::anomaly::{ObfuscationReport, Thresholds};
+#[cfg(feature = "autotag")]
+use hlbc_analysis::database::Database;
+use hlbc_analysis::entrypoints;
+#[cfg(feature = "autotag")]
+use hlbc_analysis::profile::Profile;
+#[cfg(feature = "autotag")]
+use hlbc_analysis::renames::{OriginalNames, RenameMap};
+#[cfg(feature = "autotag")]
+use hlbc_analysis::signatures::{self, InferredNames, Signatures};
+use hlbc_cli::command::{commands_parser, Command, ParseContext, Parser};
+use hlbc_cli::session::{process_command, Session};
 use temp_dir::TempDir;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
-use crate::command::{commands_parser, Command, ElementRef, FileOrIndex, ParseContext, Parser};
-
-/// Command parser
-mod command;
+use crate::transform::Transform;
+
+/// Batch analysis of many files
+mod batch;
+/// Changes between two versions
+mod diff;
+/// Snippet injection
+mod inject;
+/// Decompilation quality report
+mod quality;
+/// Function replacement
+mod replace;
+/// Opcode and size statistics
+mod stats;
+/// Release transforms
+mod transform;
+/// Bytecode verification
+mod verify;
 
 #[derive(ClapParser, Debug)]
-#[clap(author, version, about)]
+#[clap(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// The file to open, can be Hashlink bytecode or Haxe source file
-    file: PathBuf,
+    /// The file to open, can be Hashlink bytecode or Haxe source file, or a directory in batch mode
+    #[clap(required = true)]
+    file: Option<PathBuf>,
     /// Execute the command each time the file changes
     #[clap(short, long)]
     watch: Option<String>,
     /// Execute the command at startup
     #[clap(short, long)]
     command: Option<String>,
+    /// Run an analysis on every bytecode file of a directory : 'stats', 'search <str>', 'sigs' or 'diff <baseline>'
+    #[clap(short, long)]
+    batch: Option<batch::Analysis>,
+    /// Number of threads used in batch mode, defaults to the number of cpus
+    #[clap(short, long)]
+    jobs: Option<usize>,
+    /// Write a manifest describing the changes next to files written by 'saveto'
+    #[clap(short, long, value_name = "DESCRIPTION")]
+    manifest: Option<String>,
+    /// Auto-tagging rules file (TOML), replaces the default rules
+    #[cfg(feature = "autotag")]
+    #[clap(short, long)]
+    rules: Option<PathBuf>,
+    /// Additional function signatures file (TOML), can be repeated
+    #[cfg(feature = "autotag")]
+    #[clap(long)]
+    sigs: Vec<PathBuf>,
+    /// Game profile to use, a path to a TOML file or the name of a profile in the profiles directory
+    #[cfg(feature = "autotag")]
+    #[clap(long)]
+    profile: Option<String>,
+    /// Analysis database to import, exported with 'dbexport'
+    #[cfg(feature = "autotag")]
+    #[clap(long)]
+    db: Option<PathBuf>,
+    /// Rename map to import (TOML), added to the renames saved next to the file
+    #[cfg(feature = "autotag")]
+    #[clap(long)]
+    renames: Option<PathBuf>,
+    /// Plugin to load (dynamic library), can be repeated
+    #[cfg(feature = "plugins")]
+    #[clap(short, long)]
+    plugin: Vec<PathBuf>,
+    #[clap(subcommand)]
+    tool: Option<Tool>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Tool {
+    /// Check a bytecode file for invalid references, registers and calls, exits with an error code if any is found
+    Verify(verify::VerifyArgs),
+    /// Remove the debug information (file names, line numbers and variable names)
+    Strip(transform::TransformArgs),
+    /// Remove the instructions without effect
+    Optimize(transform::TransformArgs),
+    /// Replace the names of classes, enums and enum constructors
+    Obfuscate(transform::TransformArgs),
+    /// Replace the debug information with one pointing to the decompiled output, for stack traces of patched files
+    InjectDebug(transform::TransformArgs),
+    /// Remove the strings, ints, floats and types nothing refers to, usually left over by patches
+    Gc(transform::TransformArgs),
+    /// Merge a bytecode compiled separately, like a mod, sharing the types, natives and methods already in the input
+    Merge(transform::MergeArgs),
+    /// Replace a function with an assembly listing, checked against the original signature
+    ReplaceFn(replace::ReplaceArgs),
+    /// Compile a Haxe snippet, merge it and call its main at the start of a function
+    Inject(inject::InjectArgs),
+    /// Decompile every function and report the success rate of each class and the problems met
+    Quality(quality::QualityArgs),
+    /// Count the instructions by opcode, the pools, the methods of each class and find the largest functions
+    Stats(stats::StatsArgs),
+    /// List the functions changed between two versions of a file, or show them as a diff of the decompiled sources
+    Diff(diff::DiffArgs),
 }
 
 fn main() -> anyhow::Result<()> {
     let args: Args = Args::parse();
 
+    match &args.tool {
+        Some(Tool::Verify(verify)) => {
+            if !verify::run(verify)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Tool::Strip(args)) => return transform::run(Transform::Strip, args),
+        Some(Tool::Optimize(args)) => return transform::run(Transform::Optimize, args),
+        Some(Tool::Obfuscate(args)) => return transform::run(Transform::Obfuscate, args),
+        Some(Tool::InjectDebug(args)) => return transform::run(Transform::InjectDebug, args),
+        Some(Tool::Gc(args)) => return transform::run(Transform::Gc, args),
+        Some(Tool::Merge(args)) => {
+            return transform::run(Transform::Merge(args.module.clone()), &args.transform)
+        }
+        Some(Tool::Quality(args)) => return quality::run(args),
+        Some(Tool::Stats(args)) => return stats::run(args),
+        Some(Tool::Diff(args)) => return diff::run(args),
+        Some(Tool::ReplaceFn(args)) => {
+            if !replace::run(args)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Tool::Inject(args)) => {
+            if !inject::run(args)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    // Required when there is no subcommand
+    let path = args.file.clone().unwrap();
+
     #[cfg(not(feature = "watch"))]
     if args.watch.is_some() {
         println!("The program was not compiled with the 'watch' feature enabled.");
         return Ok(());
     }
 
+    if let Some(analysis) = &args.batch {
+        let threads = args
+            .jobs
+            .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+            .unwrap_or(1);
+        return batch::run(&path, analysis, threads);
+    }
+
     let tty = atty::is(atty::Stream::Stdout);
 
     let mut stdout = StandardStream::stdout(if tty {
@@ -47,11 +184,7 @@ fn main() -> anyhow::Result<()> {
         ColorChoice::Never
     });
 
-    let is_source = args
-        .file
-        .extension()
-        .map(|ext| ext == "hx")
-        .unwrap_or(false);
+    let is_source = path.extension().map(|ext| ext == "hx").unwrap_or(false);
 
     let dir = TempDir::new()?;
     let file = if is_source {
@@ -60,42 +193,150 @@ fn main() -> anyhow::Result<()> {
             stdout.flush()?;
         }
         let path = dir.child("bytecode.hl");
-        compile(&args.file, &path)?;
+        compile(&path, &path, None)?;
         if tty {
             println!(" OK");
         }
         path
     } else {
-        args.file.clone()
+        path.clone()
     };
 
     let start = Instant::now();
 
-    let code = {
-        let mut r = BufReader::new(fs::File::open(&file)?);
-        Bytecode::load(&mut r)?
-    };
+    #[allow(unused_mut)]
+    let mut code = Bytecode::from_file(&file)?;
 
     if tty {
         println!("Loaded ! ({} ms)", start.elapsed().as_millis());
     }
 
-    let parse_ctx = ParseContext {
-        int_max: code.ints.len(),
-        float_max: code.floats.len(),
-        string_max: code.strings.len(),
-        debug_file_max: code.debug_files.as_ref().map(|v| v.len()).unwrap_or(0),
-        type_max: code.types.len(),
-        global_max: code.globals.len(),
-        native_max: code.natives.len(),
-        constant_max: code.constants.as_ref().map(|v| v.len()).unwrap_or(0),
-        findex_max: code.findexes.len(),
-    };
+    let parse_ctx = ParseContext::new(&code);
 
     let parser = commands_parser(&parse_ctx);
 
+    let mut session = Session::new(&path, &file)?;
+    session.manifest = args.manifest.clone();
+    // Before the renames of the profile, the database and the rename map
+    #[cfg(feature = "autotag")]
+    {
+        session.original_names = Some(OriginalNames::new(&code));
+    }
+
+    #[cfg(feature = "autotag")]
+    {
+        use hlbc_analysis::autotag::Rules;
+
+        let rules = if let Some(path) = &args.rules {
+            Rules::from_toml(&fs::read_to_string(path)?)?
+        } else {
+            Rules::default_rules()
+        };
+        let count = rules.apply(&code, &mut session.tags);
+        if tty && count > 0 {
+            println!("Auto-tagged {count} functions");
+        }
+    }
+
+    let count =
+        entrypoints::tag_entrypoints(&entrypoints::find_entrypoints(&code), &mut session.tags);
+    if tty && count > 0 {
+        println!("Tagged {count} likely entrypoints, see 'entries'");
+    }
+
+    // Before the renames of the signatures and the profile
+    let obfuscation = ObfuscationReport::new(&code, &Thresholds::default());
+    if obfuscation.names_obfuscated() {
+        // Names derived from getters and fields would be as meaningless as them
+        session.inline.naming.calls = false;
+        session.inline.naming.fields = false;
+    }
+    if tty && !obfuscation.is_empty() {
+        println!("The bytecode looks obfuscated, see 'info'");
+        if obfuscation.names_obfuscated() {
+            println!("Variables are only named from loops, use 'inline names on' to name them from calls and fields");
+        }
+    }
+
+    #[cfg(feature = "autotag")]
+    {
+        for path in &args.sigs {
+            session
+                .signatures
+                .extend(Signatures::from_toml(&fs::read_to_string(path)?)?);
+        }
+        session.sig_matches = session.signatures.apply(
+            &code,
+            &mut session.names,
+            signatures::DEFAULT_MIN_CONFIDENCE,
+        );
+        if tty && !session.sig_matches.is_empty() {
+            println!(
+                "Named {} functions from signatures",
+                session.sig_matches.len()
+            );
+        }
+    }
+
+    #[cfg(feature = "autotag")]
+    if let Some(name) = &args.profile {
+        let profile = Profile::from_toml(&fs::read_to_string(find_profile(name)?)?)?;
+        let stats = profile.apply(&mut code, &mut session.tags);
+        if tty {
+            println!(
+                "Applied profile '{}' ({} tags, {} functions, {} types, {} fields and {} locals renamed)",
+                profile.name,
+                stats.tags,
+                stats.functions_renamed,
+                stats.types_renamed,
+                stats.fields_renamed,
+                stats.locals_renamed
+            );
+        }
+        session.profile = Some(profile);
+    }
+
+    #[cfg(feature = "autotag")]
+    if let Some(path) = &args.db {
+        let db = Database::from_toml(&fs::read_to_string(path)?)?;
+        session.sig_matches = db.apply(&mut code, &mut session.tags)?;
+        session.save_tags()?;
+        if tty {
+            println!("Imported analysis database {}", path.display());
+        }
+    }
+
+    #[cfg(feature = "autotag")]
+    {
+        if let Some(path) = &args.renames {
+            session
+                .renames
+                .merge(&RenameMap::from_toml(&fs::read_to_string(path)?)?);
+            session.save_renames()?;
+        }
+        if !session.renames.is_empty() {
+            let applied = session.renames.apply(&mut code);
+            if tty {
+                let r = applied.renamed;
+                println!(
+                    "Renamed {} classes, {} functions and {} fields",
+                    r.types, r.functions, r.fields
+                );
+                for (kind, name) in &applied.missing {
+                    println!("No {kind} named {name}, see 'renames'");
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "plugins")]
+    for path in &args.plugin {
+        // Safety : the user asked to load this library
+        unsafe { session.plugins.load(path) }?;
+    }
+
     macro_rules! execute_commands {
-        ($code:expr, $commands:expr; $onexit:stmt) => {
+        ($code:ident, $commands:expr; $onexit:stmt) => {
             for cmd in $commands {
                 match cmd {
                     #[allow(redundant_semicolons)]
@@ -103,7 +344,9 @@ fn main() -> anyhow::Result<()> {
                         $onexit;
                     }
                     cmd => {
-                        process_command(&mut stdout, $code, cmd)?;
+                        process_command(&mut stdout, &$code, &mut session, cmd)?;
+                        #[cfg(feature = "autotag")]
+                        session.apply_pending_renames(&mut $code);
                     }
                 }
                 println!();
@@ -113,7 +356,7 @@ fn main() -> anyhow::Result<()> {
 
     // Execute the -c
     if let Some(initial_cmd) = args.command {
-        execute_commands!(&code, parser.parse(initial_cmd.as_str()).expect("Error while parsing command."); return Ok(()));
+        execute_commands!(code, parser.parse(initial_cmd.as_str()).expect("Error while parsing command."); return Ok(()));
     }
 
     #[cfg(feature = "watch")]
@@ -128,29 +371,57 @@ fn main() -> anyhow::Result<()> {
 
         debouncer
             .watcher()
-            .watch(&args.file, RecursiveMode::NonRecursive)
+            .watch(&path, RecursiveMode::NonRecursive)
             .expect("Can't watch file");
 
-        println!("Watching file '{}', command : {watch}", args.file.display());
+        println!("Watching file '{}', command : {watch}", path.display());
 
         let commands = parser.parse(watch.as_str()).expect("Can't parse command");
 
-        execute_commands!(&code, commands.clone(); return Ok(()));
+        execute_commands!(code, commands.clone(); return Ok(()));
 
         'watch: loop {
             match rx.recv() {
                 Ok(Ok(events)) => {
                     for e in events {
                         if is_source {
-                            compile(&args.file, &file)?;
+                            compile(&path, &file, None)?;
                         }
 
-                        let code = {
-                            let mut r = BufReader::new(fs::File::open(&file)?);
-                            Bytecode::load(&mut r)?
+                        // Saved without changes
+                        let Some(changes) = code.reload_if_changed(&file)? else {
+                            continue;
                         };
+                        println!(
+                            "Reloaded, {} functions changed, {} removed",
+                            changes.functions.len(),
+                            changes.removed.len()
+                        );
+                        session.search = None;
+                        session.functions = None;
+                        if changes.is_structural() {
+                            session.xrefs = None;
+                            #[cfg(feature = "graph")]
+                            {
+                                session.callgraph = None;
+                            }
+                        }
+                        #[cfg(feature = "autotag")]
+                        {
+                            session.original_names = Some(OriginalNames::new(&code));
+                            session.names = InferredNames::default();
+                            session.sig_matches = session.signatures.apply(
+                                &code,
+                                &mut session.names,
+                                signatures::DEFAULT_MIN_CONFIDENCE,
+                            );
+                            if let Some(profile) = &session.profile {
+                                profile.apply(&mut code, &mut session.tags);
+                            }
+                            session.renames.apply(&mut code);
+                        }
 
-                        execute_commands!(&code, commands.clone(); break 'watch);
+                        execute_commands!(code, commands.clone(); break 'watch);
                     }
                 }
                 Ok(Err(e)) => {
@@ -179,437 +450,43 @@ fn main() -> anyhow::Result<()> {
         let commands = parser
             .parse(line.trim())
             .expect("Error while parsing command.");
-        execute_commands!(&code, commands; break 'main);
+        execute_commands!(code, commands; break 'main);
     }
     Ok(())
 }
 
-fn process_command(
-    stdout: &mut StandardStream,
-    code: &Bytecode,
-    cmd: Command,
-) -> anyhow::Result<()> {
-    macro_rules! print_i {
-        ($i:expr) => {
-            stdout.set_color(ColorSpec::new().set_fg(Some(Color::Ansi256(242))))?;
-            write!(stdout, "{:<3}: ", $i)?;
-            stdout.reset()?;
-        };
-    }
-
-    macro_rules! require_debug_info {
-        () => {
-            if let Some(debug_files) = &code.debug_files {
-                debug_files
-            } else {
-                println!("No debug info in this binary");
-                return Ok(());
-            }
-        };
+/// Find a profile file from a path or a name. Profiles are searched in the directory given by the `HLBC_PROFILES`
+/// environment variable, or in `~/.hlbc/profiles`.
+#[cfg(feature = "autotag")]
+fn find_profile(name: &str) -> anyhow::Result<PathBuf> {
+    let path = PathBuf::from(name);
+    if path.is_file() {
+        return Ok(path);
     }
-
-    match cmd {
-        Command::Exit => unreachable!(),
-        Command::Help => {
-            println!(
-                r#"Commands :
-exit                         | Exit hlbc-cli
-help                         | This message
-explain     <opcode>         | Get information about an opcode
-wiki                         | Open the bytecode wiki in a browser
-info                         | General information about the bytecode
-entrypoint                   | Get the bytecode entrypoint
-i,int       <idx>            | Get the int at index
-f,float     <idx>            | Get the float at index
-s,string    <idx>            | Get the string at index
-sstr        <str>            | Find a string
-file,debugfile <idx>         | Get the debug file name at index
-sfile       <str>            | Find the debug file named
-t,type      <idx>            | Get the type at index
-g,global    <idx>            | Get global at index
-c,constant  <idx>            | Get constant at index
-n,native    <idx>            | Get native at index
-fnh         <findex>         | Get header of function at index
-fn          <findex>         | Get a function by findex
-fnn,fnamed  <str>            | Get a function by name
-sfn         <str>            | Find a function by name
-infile      <idx|str>        | Find functions in file
-fileof      <findex>         | Get the file where findex is defined
-refto       <any@idx>        | Find references to a given bytecode element
-saveto      <filename>       | Serialize the bytecode to a file
-callgraph   <findex> <depth> | Create a dot call graph from a function and a max depth
-decomp      <findex>         | Decompile a function
-decompt     <idx>            | Decompile a type
-
-Remember you can use the range notation in place of an index to navigate through data : a..b
-This is the same range notation as Rust and is supported with most commands."#
-            );
-        }
-        Command::Explain(s) => {
-            if let Some(o) = Opcode::from_name(&s) {
-                print!("{} :\n{}", o.name(), o.description());
-                println!("Example : {}", o.display(code, &code.functions[0], 0, 0));
-            } else {
-                println!("No opcode named '{s}' exists.");
-            }
-        }
-        Command::Wiki => webbrowser::open("https://github.com/Gui-Yom/hlbc/wiki")?,
-        Command::Info => {
-            println!(
-                "version: {}\ndebug: {}\nnints: {}\nnfloats: {}\nnstrings: {}\nntypes: {}\nnnatives: {}\nnfunctions: {}\nnconstants: {}",
-                code.version,
-                code.debug_files.is_some(),
-                code.ints.len(),
-                code.floats.len(),
-                code.strings.len(),
-                code.types.len(),
-                code.natives.len(),
-                code.functions.len(),
-                code.constants.as_ref().map_or(0, |c| c.len())
-            );
-        }
-        Command::Entrypoint => {
-            println!("{}", code.entrypoint.display_header(code));
-        }
-        Command::Int(range) => {
-            for i in range {
-                print_i!(i);
-                println!("{}", code.ints[i]);
-            }
-        }
-        Command::Float(range) => {
-            for i in range {
-                print_i!(i);
-                println!("{}", code.floats[i]);
-            }
-        }
-        Command::String(range) => {
-            for i in range {
-                print_i!(i);
-                println!("{}", code.strings[i]);
-            }
-        }
-        Command::SearchStr(str) => {
-            for (i, s) in code.strings.iter().enumerate() {
-                if s.contains(&str) {
-                    print_i!(i);
-                    println!("{}", s);
-                }
-            }
-        }
-        Command::Debugfile(range) => {
-            let debug_files = require_debug_info!();
-            for i in range {
-                print_i!(i);
-                println!("{}", debug_files[i]);
-            }
-        }
-        Command::SearchDebugfile(str) => {
-            let debug_files = require_debug_info!();
-            for (i, s) in debug_files.iter().enumerate() {
-                if s.contains(&str) {
-                    print_i!(i);
-                    println!("{}", s);
-                }
-            }
-        }
-        Command::Type(range) => {
-            let range_len = range.len();
-            for i in range {
-                print_i!(i);
-                let t = &code.types[i];
-                println!("{}", t.display(code));
-                // Only display full info if selecting a single item
-                if range_len == 1 {
-                    match t {
-                        Type::Obj(obj) => {
-                            if let Some(sup) = obj.super_ {
-                                println!("extends {}", sup.display_id(code));
-                            }
-                            println!("global: {}", obj.global.0);
-                            println!("fields:");
-                            for f in &obj.own_fields {
-                                println!("  {}: {}", f.name.display(code), f.t.display_id(code));
-                            }
-                            println!("protos:");
-                            for p in &obj.protos {
-                                println!(
-                                    "  {}: {} ({})",
-                                    p.name.display(code),
-                                    p.findex.display_header(code),
-                                    p.pindex
-                                );
-                            }
-                            println!("bindings:");
-                            for (fi, fun) in &obj.bindings {
-                                println!(
-                                    "  {}: {}",
-                                    fi.display_obj(t, code),
-                                    fun.display_header(code)
-                                );
-                            }
-                        }
-                        Type::Enum {
-                            global, constructs, ..
-                        } => {
-                            println!("global: {}", global.0);
-                            println!("constructs:");
-                            for c in constructs {
-                                println!(
-                                    "  {}:",
-                                    if c.name.0 == 0 {
-                                        "_".to_string()
-                                    } else {
-                                        c.name.display(code)
-                                    }
-                                );
-                                for (i, p) in c.params.iter().enumerate() {
-                                    println!("    {i}: {}", p.display_id(code));
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            }
-        }
-        Command::Global(range) => {
-            for i in range {
-                print_i!(i);
-                println!("{}", code.globals[i].display_id(code));
-                if let Some(&cst) = code.globals_initializers.get(&RefGlobal(i)) {
-                    for init in &code.constants.as_ref().unwrap()[cst].fields {
-                        println!("    {}", init);
-                    }
-                }
-            }
-        }
-        Command::Native(range) => {
-            for i in range {
-                print_i!(i);
-                println!("{}", code.natives[i].display_header(code));
-            }
-        }
-        Command::Constant(range) => {
-            for i in range {
-                print_i!(i);
-                println!("{:#?}", code.constants.as_ref().unwrap()[i]);
-            }
-        }
-        Command::FunctionHeader(range) => {
-            for findex in range {
-                print_i!(findex);
-                match RefFun(findex).resolve(code) {
-                    FunPtr::Fun(f) => println!("{}", f.display_header(code)),
-                    FunPtr::Native(n) => println!("{}", n.display_header(code)),
-                }
-            }
-        }
-        Command::Function(range) => {
-            for findex in range {
-                print_i!(findex);
-                match RefFun(findex).resolve(code) {
-                    FunPtr::Fun(f) => println!("{}", f.display(code)),
-                    FunPtr::Native(n) => println!("{}", n.display_header(code)),
-                }
-            }
-        }
-        Command::FunctionNamed(str) => {
-            if let Some(&i) = code.fnames.get(&str) {
-                println!("{}", code.functions[i].display(code));
-            } else {
-                println!("unknown '{str}'");
-            }
-        }
-        Command::SearchFunction(str) => {
-            // TODO search for function
-            if let Some(&i) = code.fnames.get(&str) {
-                println!("{}", code.functions[i].display_header(code));
-            } else {
-                println!("unknown");
-            }
-        }
-        Command::InFile(foi) => {
-            let debug_files = require_debug_info!();
-            match foi {
-                FileOrIndex::File(str) => {
-                    if let Some(idx) =
-                        debug_files
-                            .iter()
-                            .enumerate()
-                            .find_map(
-                                |(i, d): (usize, &String)| {
-                                    if d == &str {
-                                        Some(i)
-                                    } else {
-                                        None
-                                    }
-                                },
-                            )
-                    {
-                        println!("Functions in file@{idx} : {}", debug_files[idx]);
-                        for (i, f) in code.functions.iter().enumerate() {
-                            if f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0 == idx {
-                                print_i!(i);
-                                println!("{}", f.display_header(code));
-                            }
-                        }
-                    } else {
-                        println!("File {str} not found !");
-                    }
-                }
-                FileOrIndex::Index(idx) => {
-                    println!("Functions in file@{idx} : {}", debug_files[idx]);
-                    for (i, f) in code.functions.iter().enumerate() {
-                        if f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0 == idx {
-                            print_i!(i);
-                            println!("{}", f.display_header(code));
-                        }
-                    }
-                }
-            }
-        }
-        Command::FileOf(idx) => {
-            let debug_files = require_debug_info!();
-            match RefFun(idx).resolve(code) {
-                FunPtr::Fun(f) => {
-                    let idx = f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0;
-                    println!(
-                        "{} is in file@{idx} : {}",
-                        f.display_header(code),
-                        &debug_files[idx]
-                    );
-                }
-                FunPtr::Native(n) => {
-                    println!(
-                        "native {} is in the module {}",
-                        n.display_header(code),
-                        n.lib.resolve(&code.strings)
-                    )
-                }
-            }
-        }
-        Command::SaveTo(file) => {
-            let mut w = BufWriter::new(fs::File::create(file)?);
-            code.serialize(&mut w)?;
-        }
-        Command::Callgraph(idx, depth) => {
-            #[cfg(feature = "graph")]
-            {
-                use hlbc::analysis::graph::{call_graph, display_graph};
-
-                let graph = call_graph(code, RefFun(idx), depth);
-                println!("{}", display_graph(&graph, code));
-            }
-
-            #[cfg(not(feature = "graph"))]
-            {
-                println!("hlbc-cli has been built without graph support. Build with feature 'graph' to enable callgraph generation");
-            }
-        }
-        Command::RefTo(elem) => match elem {
-            ElementRef::String(idx) => {
-                println!(
-                    "Finding references to string@{idx} : {}\n",
-                    code.strings[idx]
-                );
-                if let Some(constants) = &code.constants {
-                    for (i, c) in constants.iter().enumerate() {
-                        if c.fields[0] == idx {
-                            println!(
-                                "constant@{i} expanding to global@{} (now also searching for global)",
-                                c.global.0
-                            );
-                            code.ops().for_each(|(f, (i, o))| match o {
-                                Opcode::GetGlobal { global, .. } => {
-                                    if *global == c.global {
-                                        println!("in {} at {i}: GetGlobal", f.display_header(code));
-                                    }
-                                }
-                                _ => {}
-                            });
-                            println!();
-                        }
-                    }
-                }
-                code.ops().for_each(|(f, (i, o))| match o {
-                    Opcode::String { ptr, .. } => {
-                        if ptr.0 == idx {
-                            println!("{} at {i}: String", f.display_header(code));
-                        }
-                    }
-                    _ => {}
-                });
-            }
-            ElementRef::Global(idx) => {
-                println!(
-                    "Finding references to global@{idx} : {}\n",
-                    code.globals[idx].display_id(code)
-                );
-                if let Some(constants) = &code.constants {
-                    for (i, c) in constants.iter().enumerate() {
-                        if c.global.0 == idx {
-                            println!("constant@{i} : {:?}", c);
-                        }
-                    }
-                }
-                println!();
-
-                code.ops().for_each(|(f, (i, o))| match o {
-                    Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
-                        if global.0 == idx {
-                            println!("{} at {i}: {}", f.display_header(code), o.name());
-                        }
-                    }
-                    _ => {}
-                });
-            }
-            ElementRef::Fn(idx) => {
-                println!(
-                    "Finding references to fn@{idx} : {}\n",
-                    RefFun(idx).display_header(code)
-                );
-                code.functions
-                    .iter()
-                    .flat_map(|f| repeat(f).zip(f.find_fun_refs()))
-                    .for_each(|(f, (i, o, fun))| {
-                        if fun.0 == idx {
-                            println!("{} at {i}: {}", f.display_header(code), o.name());
-                        }
-                    });
-            }
-        },
-        Command::Decomp(idx) => {
-            if let Some(fun) = RefFun(idx).resolve_as_fn(code) {
-                println!(
-                    "{}",
-                    hlbc_decompiler::decompile_function(code, fun)
-                        .display(code, &hlbc_decompiler::fmt::FormatOptions::new("  "))
-                );
-            }
-        }
-        Command::DecompType(idx) => {
-            let ty = &code.types[idx];
-            match ty {
-                Type::Obj(obj) => {
-                    println!("Dumping type@{idx} : {}", ty.display(code));
-                    println!(
-                        "{}",
-                        hlbc_decompiler::decompile_class(code, obj)
-                            .display(code, &hlbc_decompiler::fmt::FormatOptions::new("  "))
-                    );
-                }
-                _ => println!("Type {idx} is not an obj"),
-            }
-        }
+    let dir = if let Some(dir) = std::env::var_os("HLBC_PROFILES") {
+        PathBuf::from(dir)
+    } else if let Some(home) = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))
+    {
+        PathBuf::from(home).join(".hlbc").join("profiles")
+    } else {
+        anyhow::bail!("Can't find the profiles directory, set HLBC_PROFILES")
+    };
+    let path = dir.join(format!("{name}.toml"));
+    if path.is_file() {
+        Ok(path)
+    } else {
+        anyhow::bail!("No profile named '{name}' in {}", dir.display())
     }
-    Ok(())
 }
 
-/// Compile a Haxe source file to Hashlink bytecode by directly calling the Haxe compiler.
-/// Requires having the haxe compiler in the `PATH`.
-fn compile(source: &Path, bytecode: &Path) -> anyhow::Result<()> {
-    let result = std::process::Command::new("haxe")
+/// Compile a Haxe source file to Hashlink bytecode by directly calling the Haxe compiler, with another class path
+/// if any. Requires having the haxe compiler in the `PATH`.
+fn compile(source: &Path, bytecode: &Path, class_path: Option<&Path>) -> anyhow::Result<()> {
+    let mut haxe = std::process::Command::new("haxe");
+    if let Some(cp) = class_path {
+        haxe.arg("-cp").arg(cp.canonicalize()?);
+    }
+    let result = haxe
         .arg("-hl")
         .arg(bytecode)
         .arg("-main")
@@ -0,0 +1,233 @@
+//! Quality mode, decompiles a whole bytecode file and reports where the decompiler fails.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use hlbc::Bytecode;
+use hlbc_decompiler::inline::InlineOptions;
+use hlbc_decompiler::quality::{ClassQuality, QualityReport};
+
+#[derive(Debug, clap::Args)]
+pub struct QualityArgs {
+    /// The bytecode file to decompile
+    file: PathBuf,
+    /// Print the report as JSON
+    #[clap(long)]
+    json: bool,
+    /// Number of weakest classes shown
+    #[clap(short, long, default_value = "10")]
+    top: usize,
+    /// History file (JSON lines), the totals are appended and compared with the previous run
+    #[clap(long)]
+    history: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Counts {
+    name: String,
+    functions: usize,
+    decompiled: usize,
+    clean: usize,
+    fallbacks: usize,
+    unsupported: usize,
+    warnings: usize,
+}
+
+impl From<&ClassQuality> for Counts {
+    fn from(c: &ClassQuality) -> Self {
+        Self {
+            name: c.name.clone(),
+            functions: c.functions,
+            decompiled: c.decompiled,
+            clean: c.clean,
+            fallbacks: c.fallbacks,
+            unsupported: c.unsupported,
+            warnings: c.warnings,
+        }
+    }
+}
+
+impl Counts {
+    fn success_rate(&self) -> f64 {
+        if self.functions == 0 {
+            1.0
+        } else {
+            self.decompiled as f64 / self.functions as f64
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FunctionError {
+    findex: usize,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    file: String,
+    total: Counts,
+    /// Weakest first
+    classes: Vec<Counts>,
+    unsupported_opcodes: BTreeMap<String, usize>,
+    warnings: BTreeMap<String, usize>,
+    errors: Vec<FunctionError>,
+}
+
+impl Report {
+    fn new(file: String, report: &QualityReport) -> Self {
+        Self {
+            file,
+            total: (&report.total()).into(),
+            classes: report.weakest().into_iter().map(Counts::from).collect(),
+            unsupported_opcodes: report.unsupported_opcodes.clone(),
+            warnings: report.warnings.clone(),
+            errors: report
+                .errors
+                .iter()
+                .map(|(findex, message)| FunctionError {
+                    findex: findex.0,
+                    message: message.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A line of the history file
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    file: String,
+    /// Seconds since the Unix epoch
+    time: u64,
+    #[serde(flatten)]
+    total: Counts,
+}
+
+/// Compare with the last entry of the history then append the new one, returns the previous entry
+fn track(path: &Path, entry: &Entry) -> anyhow::Result<Option<Entry>> {
+    let previous = match fs::read_to_string(path) {
+        Ok(history) => history
+            .lines()
+            .rev()
+            .find(|l| !l.trim().is_empty())
+            .map(serde_json::from_str)
+            .transpose()?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(previous)
+}
+
+fn print_counts(c: &Counts) {
+    println!(
+        "{:<40} {:>5}/{:<5} {:>6.1}% {:>6} clean {:>5} fallbacks {:>5} unsupported {:>5} warnings",
+        c.name,
+        c.decompiled,
+        c.functions,
+        c.success_rate() * 100.0,
+        c.clean,
+        c.fallbacks,
+        c.unsupported,
+        c.warnings
+    );
+}
+
+/// Print the report of a file
+pub fn run(args: &QualityArgs) -> anyhow::Result<()> {
+    let code = Bytecode::from_file(&args.file)?;
+    let quality = QualityReport::new(&code, &InlineOptions::default());
+    let report = Report::new(args.file.display().to_string(), &quality);
+
+    let previous = if let Some(path) = &args.history {
+        let entry = Entry {
+            file: report.file.clone(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            total: report.total.clone(),
+        };
+        track(path, &entry)?
+    } else {
+        None
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!("Weakest classes :");
+    for c in report.classes.iter().take(args.top) {
+        print_counts(c);
+    }
+    if !report.unsupported_opcodes.is_empty() {
+        println!("\nUnsupported opcodes :");
+        for (op, count) in &report.unsupported_opcodes {
+            println!("{op:<40} {count}");
+        }
+    }
+    if !report.warnings.is_empty() {
+        println!("\nWarnings :");
+        for (message, count) in &report.warnings {
+            println!("{message:<40} {count}");
+        }
+    }
+    if !report.errors.is_empty() {
+        println!("\nErrors :");
+        for e in &report.errors {
+            println!("fn@{} : {}", e.findex, e.message);
+        }
+    }
+    println!();
+    print_counts(&report.total);
+    if let Some(previous) = previous {
+        println!(
+            "{:+.1}% success rate, {:+} clean functions since the previous run ({})",
+            (report.total.success_rate() - previous.total.success_rate()) * 100.0,
+            report.total.clean as i64 - previous.total.clean as i64,
+            previous.file
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+    use hlbc_decompiler::inline::InlineOptions;
+    use hlbc_decompiler::quality::QualityReport;
+
+    use crate::quality::{track, Entry, Report};
+
+    #[test]
+    fn history() {
+        let code = sample();
+        let report = Report::new(
+            "sample.hl".to_owned(),
+            &QualityReport::new(&code, &InlineOptions::default()),
+        );
+        assert_eq!(report.total.functions, code.functions.len());
+
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.child("history.jsonl");
+        let entry = Entry {
+            file: report.file.clone(),
+            time: 1,
+            total: report.total.clone(),
+        };
+        assert!(track(&path, &entry).unwrap().is_none());
+        let previous = track(&path, &entry).unwrap().unwrap();
+        assert_eq!(previous.total, report.total);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+}
@@ -0,0 +1,115 @@
+//! Replace mode, patches a function of a bytecode file with an assembly listing.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+
+use hlbc::asm::assemble;
+use hlbc::types::RefFun;
+use hlbc::verify::verify_function;
+use hlbc::Bytecode;
+
+#[derive(Debug, clap::Args)]
+pub struct ReplaceArgs {
+    /// The bytecode file to patch
+    file: PathBuf,
+    /// The function to replace : 'fn@<findex>' or a name like 'Player.update'
+    function: String,
+    /// The new function, an edited listing as printed by the 'fn' command
+    listing: PathBuf,
+    /// Where to write the patched file
+    #[clap(short, long)]
+    output: PathBuf,
+}
+
+/// Find the function to replace, names must match a single function
+pub(crate) fn target(code: &Bytecode, function: &str) -> anyhow::Result<RefFun> {
+    let index = function
+        .strip_prefix("fn@")
+        .or_else(|| function.strip_prefix("f@"))
+        .unwrap_or(function);
+    if let Ok(findex) = index.parse::<usize>() {
+        if findex >= code.findexes.len() {
+            bail!("fn@{findex} doesn't exist");
+        }
+        return Ok(RefFun(findex));
+    }
+    match code.function_index().lookup(code, function)[..] {
+        [] => bail!("No function named '{function}'"),
+        [findex] => Ok(findex),
+        ref found => bail!(
+            "'{function}' is ambiguous, use one of {}",
+            found
+                .iter()
+                .map(|f| format!("fn@{}", f.0))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Replace the function, returns false without writing anything if the new function is invalid
+pub fn run(args: &ReplaceArgs) -> anyhow::Result<bool> {
+    let mut code = Bytecode::from_file(&args.file)?;
+    let listing = fs::read_to_string(&args.listing)
+        .with_context(|| format!("Can't read {}", args.listing.display()))?;
+    let findex = target(&code, &args.function)?;
+    let before = findex.resolve_as_fn(&code).map_or(0, |f| f.ops.len());
+
+    let mut assembly = match assemble(&mut code, findex, &listing) {
+        Ok(assembly) => assembly,
+        Err(e) => {
+            eprintln!("{}:{} : {}", args.listing.display(), e.line, e.kind);
+            return Ok(false);
+        }
+    };
+    let removed = match assembly.fit(&code, findex) {
+        Ok(removed) => removed,
+        Err(e) => {
+            eprintln!("{} : {}", args.listing.display(), e.kind);
+            return Ok(false);
+        }
+    };
+    assembly.apply(&mut code, findex);
+
+    let f = findex.resolve_as_fn(&code).unwrap();
+    let errors = verify_function(&code, f);
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("{e}");
+        }
+        eprintln!("{} errors, nothing written", errors.len());
+        return Ok(false);
+    }
+
+    let mut data = Vec::new();
+    code.serialize(&mut data)?;
+    fs::write(&args.output, data)?;
+    println!(
+        "Replaced fn@{} : {} instructions (was {before}), {removed} unused registers removed",
+        findex.0,
+        f.ops.len()
+    );
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+
+    use crate::replace::target;
+
+    #[test]
+    fn targets() {
+        let code = sample();
+        let f = &code.functions[0];
+        assert_eq!(
+            target(&code, &format!("fn@{}", f.findex.0)).unwrap(),
+            f.findex
+        );
+        assert_eq!(target(&code, &f.findex.0.to_string()).unwrap(), f.findex);
+        assert!(target(&code, &format!("f@{}", code.findexes.len())).is_err());
+        assert!(target(&code, "does.not.exist").is_err());
+    }
+}
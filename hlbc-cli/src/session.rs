@@ -0,0 +1,1833 @@
+//! The command interpreter, shared by the prompt and the console of the gui.
+
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::iter::repeat;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use hlbc::analysis::annotations::{Annotations, Note, Severity};
+use hlbc::analysis::tags::{TagTarget, Tags};
+use hlbc::lookup::FunctionIndex;
+use hlbc::manifest::{self, Manifest};
+use hlbc::opcodes::Opcode;
+use hlbc::syntax::Dialect;
+use hlbc::types::{FunPtr, Function, RefField, RefFun, RefGlobal, RefString, RefType, Reg, Type};
+use hlbc::*;
+use hlbc_analysis::anomaly::{self, ObfuscationReport, Thresholds};
+#[cfg(feature = "autotag")]
+use hlbc_analysis::database::Database;
+use hlbc_analysis::entrypoints;
+use hlbc_analysis::eval::{Interpreter, Value};
+#[cfg(feature = "graph")]
+use hlbc_analysis::graph::{petgraph::Direction, Callgraph};
+use hlbc_analysis::metrics::{FunctionMetrics, ModuleMetrics};
+use hlbc_analysis::natives;
+use hlbc_analysis::pointers::{PointerMap, PointerOptions, GLOBALS_SYMBOL};
+#[cfg(feature = "autotag")]
+use hlbc_analysis::profile::Profile;
+use hlbc_analysis::reflection::Reflection;
+#[cfg(feature = "autotag")]
+use hlbc_analysis::renames::{OnConflict, OriginalNames, RenameKind, RenameMap};
+use hlbc_analysis::search::SearchIndex;
+#[cfg(feature = "autotag")]
+use hlbc_analysis::signatures::{InferredNames, Named, SigMatch, Signatures};
+use hlbc_analysis::slice::DataDeps;
+use hlbc_analysis::summary::ClassCard;
+#[cfg(feature = "autotag")]
+use hlbc_analysis::symbols::{self, SymbolFormat};
+use hlbc_analysis::xref::{Xref, XrefIndex};
+use hlbc_decompiler::debuginfo::inject_debug_info;
+use hlbc_decompiler::inline::InlineOptions;
+use hlbc_decompiler::naming::NamingOptions;
+use hlbc_decompiler::pattern::{Pattern, Rewrite};
+use hlbc_plugin::{PluginCtx, Plugins};
+use termcolor::{Color, ColorSpec, WriteColor};
+
+#[cfg(feature = "autotag")]
+use crate::command::RenamesAction;
+use crate::command::{Command, DebugInfo, ElementRef, FileOrIndex, InlineSetting, Literal};
+
+/// State kept between commands
+pub struct Session {
+    /// Tags attached to functions and types, persisted next to the bytecode file
+    pub tags: Tags,
+    pub tags_file: PathBuf,
+    /// Bytecode file, to compare with the original bytecode
+    pub bytecode_file: PathBuf,
+    /// Only show elements with this tag in listings
+    pub tag_filter: Option<String>,
+    /// Description of the changes, to write a manifest when saving
+    pub manifest: Option<String>,
+    /// Plugins providing additional commands
+    pub plugins: Plugins,
+    /// Cross-references, built on the first lookup
+    pub xrefs: Option<XrefIndex>,
+    /// Index for string searches, built on the first search
+    pub search: Option<SearchIndex>,
+    /// Functions by name, built on the first lookup
+    pub functions: Option<FunctionIndex>,
+    /// Call graph of the whole program, built on the first query
+    #[cfg(feature = "graph")]
+    pub callgraph: Option<Callgraph>,
+    /// Notes on instructions, shown with the functions
+    pub annotations: Annotations,
+    /// Inlining heuristics of the decompiler
+    pub inline: InlineOptions,
+    /// Syntax of the disassembly
+    pub syntax: Dialect,
+    /// Game profile in use
+    #[cfg(feature = "autotag")]
+    pub profile: Option<Profile>,
+    /// Known function signatures
+    #[cfg(feature = "autotag")]
+    pub signatures: Signatures,
+    /// Functions named from signatures
+    #[cfg(feature = "autotag")]
+    pub sig_matches: Vec<SigMatch>,
+    /// Names inferred from the signatures, shown instead of the debug names
+    #[cfg(feature = "autotag")]
+    pub names: InferredNames,
+    /// Names given by the user, persisted next to the bytecode file and applied at load time
+    #[cfg(feature = "autotag")]
+    pub renames: RenameMap,
+    #[cfg(feature = "autotag")]
+    pub renames_file: PathBuf,
+    /// Renames by current name made by the last command, to be applied to the loaded bytecode by the caller
+    #[cfg(feature = "autotag")]
+    pub pending_renames: RenameMap,
+    /// Names of the loaded bytecode before the renames of the profile, the database and the rename map, recorded by
+    /// the caller. Files written by commands keep them.
+    #[cfg(feature = "autotag")]
+    pub original_names: Option<OriginalNames>,
+}
+
+impl Session {
+    pub fn new(file: &Path, bytecode_file: &Path) -> anyhow::Result<Self> {
+        let tags_file = PathBuf::from(format!("{}.tags", file.display()));
+        let tags = if tags_file.exists() {
+            Tags::load(BufReader::new(fs::File::open(&tags_file)?))?
+        } else {
+            Tags::new()
+        };
+        #[cfg(feature = "autotag")]
+        let renames_file = PathBuf::from(format!("{}.renames", file.display()));
+        #[cfg(feature = "autotag")]
+        let renames = if renames_file.exists() {
+            RenameMap::from_toml(&fs::read_to_string(&renames_file)?)?
+        } else {
+            RenameMap::new()
+        };
+        Ok(Self {
+            tags,
+            tags_file,
+            bytecode_file: bytecode_file.to_path_buf(),
+            tag_filter: None,
+            manifest: None,
+            plugins: Plugins::new(),
+            xrefs: None,
+            search: None,
+            functions: None,
+            #[cfg(feature = "graph")]
+            callgraph: None,
+            annotations: Annotations::new(),
+            inline: InlineOptions::default(),
+            syntax: Dialect::Enhanced,
+            #[cfg(feature = "autotag")]
+            profile: None,
+            #[cfg(feature = "autotag")]
+            signatures: Signatures::default_signatures(),
+            #[cfg(feature = "autotag")]
+            sig_matches: Vec::new(),
+            #[cfg(feature = "autotag")]
+            names: InferredNames::default(),
+            #[cfg(feature = "autotag")]
+            renames,
+            #[cfg(feature = "autotag")]
+            renames_file,
+            #[cfg(feature = "autotag")]
+            pending_renames: RenameMap::new(),
+            #[cfg(feature = "autotag")]
+            original_names: None,
+        })
+    }
+
+    #[cfg(feature = "autotag")]
+    pub fn save_renames(&self) -> anyhow::Result<()> {
+        if self.renames.is_empty() {
+            if self.renames_file.exists() {
+                fs::remove_file(&self.renames_file)?;
+            }
+        } else {
+            fs::write(&self.renames_file, self.renames.to_toml())?;
+        }
+        Ok(())
+    }
+
+    /// Apply the pending renames to the loaded bytecode, the indexes built on names are rebuilt on the next query
+    #[cfg(feature = "autotag")]
+    pub fn apply_pending_renames(&mut self, code: &mut Bytecode) {
+        if !self.pending_renames.is_empty() {
+            mem::take(&mut self.pending_renames).apply(code);
+            self.search = None;
+            self.functions = None;
+        }
+    }
+
+    pub fn save_tags(&self) -> anyhow::Result<()> {
+        if !self.tags.has_user_tags() {
+            if self.tags_file.exists() {
+                fs::remove_file(&self.tags_file)?;
+            }
+        } else {
+            let mut w = BufWriter::new(fs::File::create(&self.tags_file)?);
+            self.tags.save(&mut w)?;
+        }
+        Ok(())
+    }
+
+    /// Copy of the loaded bytecode with its original names, to be written to a file
+    fn unrenamed(&self, code: &Bytecode) -> Bytecode {
+        #[allow(unused_mut)]
+        let mut code = code.clone();
+        #[cfg(feature = "autotag")]
+        if let Some(names) = &self.original_names {
+            names.restore(&mut code);
+        }
+        code
+    }
+
+    /// Should this element be displayed with the current tag filter
+    fn shown(&self, target: TagTarget) -> bool {
+        self.tag_filter
+            .as_ref()
+            .map(|tag| self.tags.has(target, tag))
+            .unwrap_or(true)
+    }
+
+    /// Display a function in the syntax of the session, with its notes in the default syntax. Anomalies are refreshed
+    /// first.
+    fn display_fun(&mut self, code: &Bytecode, f: &Function) -> String {
+        if self.syntax != Dialect::Enhanced {
+            return f.display_with(code, self.syntax.syntax()).to_string();
+        }
+        anomaly::annotate_anomalies(code, f, &Thresholds::default(), &mut self.annotations);
+        f.display_annotated(&self.named(code), &self.annotations)
+            .to_string()
+    }
+
+    /// Display context of the functions, showing the names inferred from the signatures
+    #[cfg(feature = "autotag")]
+    pub fn named<'a>(&'a self, code: &'a Bytecode) -> Named<'a> {
+        self.names.with(code)
+    }
+
+    #[cfg(not(feature = "autotag"))]
+    pub fn named<'a>(&'a self, code: &'a Bytecode) -> &'a Bytecode {
+        code
+    }
+
+    /// Display the tags of an element like ` #tag1 #tag2`
+    fn display_tags(&self, target: TagTarget) -> String {
+        self.tags
+            .tags_of(target)
+            .map(|t| format!(" #{t}"))
+            .collect()
+    }
+}
+
+/// Run a command and write its output to `out`, [Command::Exit] must be handled by the caller
+pub fn process_command<W: WriteColor>(
+    out: &mut W,
+    code: &Bytecode,
+    session: &mut Session,
+    cmd: Command,
+) -> anyhow::Result<()> {
+    macro_rules! print_i {
+        ($i:expr) => {
+            out.set_color(ColorSpec::new().set_fg(Some(Color::Ansi256(242))))?;
+            write!(out, "{:<3}: ", $i)?;
+            out.reset()?;
+        };
+    }
+
+    macro_rules! require_debug_info {
+        () => {
+            if let Some(debug_files) = &code.debug_files {
+                debug_files
+            } else {
+                writeln!(out, "No debug info in this binary")?;
+                return Ok(());
+            }
+        };
+    }
+
+    match cmd {
+        Command::Exit => unreachable!(),
+        Command::Help => {
+            writeln!(
+                out,
+                r#"Commands :
+exit                         | Exit hlbc-cli
+help                         | This message
+explain     <opcode>         | Get information about an opcode
+wiki                         | Open the bytecode wiki in a browser
+info                         | General information about the bytecode
+entrypoint                   | Get the bytecode entrypoint
+i,int       <idx>            | Get the int at index
+f,float     <idx>            | Get the float at index
+s,string    <idx>            | Get the string at index
+sstr        <str>            | Find a string
+file,debugfile <idx>         | Get the debug file name at index
+sfile       <str>            | Find the debug file named
+t,type      <idx>            | Get the type at index
+g,global    <idx>            | Get global at index
+c,constant  <idx>            | Get constant at index
+n,native    <idx>            | Get native at index
+fnh         <findex>         | Get header of function at index
+fn          <findex>         | Get a function by findex
+fnn,fnamed  <str>            | Get the functions named <str>, can be qualified (Player.update)
+sfn         <str>            | Find functions by name, best matches first
+infile      <idx|str>        | Find functions in file
+fileof      <findex>         | Get the file where findex is defined
+refto       <any@idx>        | Find references to a given bytecode element (string, global, fn, type, field@t.f)
+saveto      [--strip|--inject] <file> | Serialize the bytecode to a file, removing or regenerating the debug info
+provenance                   | Show the manifest of a modified file
+extract     <findex> <file>  | Extract a function and its dependencies to a standalone file
+minimize    <findex> <file>  | Reduce a function making the decompiler panic to a small file
+instrument  <findex> <pos> <regs> <file> | Write a copy printing registers (e.g. 3,5) before an instruction
+reassemble  <findex> <listing> <file> | Write a copy with the function assembled from an edited listing
+callgraph   <findex> <depth> | Create a dot call graph from a function and a max depth
+callers     <findex> [--tree] [--depth n] | Functions calling a function, transitively with --tree
+callees     <findex> [--tree] [--depth n] | Functions called by a function, transitively with --tree
+dead                         | List the functions unreachable from the entrypoint
+reflection                   | List the classes and functions used by name (Type.resolveClass, Reflect.field)
+packages    [dot]            | Dependencies between packages by layer with the cycles, or a dot graph
+slice       f@<findex>:<pos> [reg] | Instructions a value depends on and instructions depending on it
+eval        <findex> <args>  | Run a pure function in a sandbox, e.g. eval f@12 1 2.5 "abc"
+decomp      <findex>         | Decompile a function
+decompt     <idx>            | Decompile a type
+export      <dir>            | Decompile every class to a source tree, with the renames of the profile and database
+externs     <dir>            | Declare every class as a Haxe extern, to compile code using them
+inline      [setting]        | Show or change which expressions the decompiler inlines
+syntax      [enhanced|canonical|hldump] | Show or change the syntax of the disassembly
+astfind     <pattern>        | Find expressions in the decompiled code, e.g. astfind Reflect.field($o, $_)
+rewrite     [rule|clear]     | Add a rewrite of the decompiled code (pattern => replacement), list or clear them
+tag         <fn|type@idx> <tag> | Attach a tag to a function or a type
+untag       <fn|type@idx> <tag> | Detach a tag from a function or a type
+tags        [tag]            | List all tags or elements having a tag
+note        <findex> <pos> <text> | Attach a note to an instruction, shown with the function
+tagfilter   [tag]            | Only show elements with a tag in listings (no tag to reset)
+anomalies                    | List functions with anomalies (likely obfuscated)
+metrics     [findex]         | Size and complexity of a function, or of the module and its most complex functions
+boot                         | Show the globals constructed at startup and the static initializers
+libs        [lib]            | List the native libraries used, or the natives of a library with their callers
+layout      <idx|name>       | Offsets of the fields and virtual table of a class or a structure, e.g. layout game.Player
+sqlite      <filename>       | Export the bytecode as a SQLite database
+pointers    <filename> [depth] | Export the offsets of the statics and singletons as a Cheat Engine table (.ct) or JSON
+deobf       <findex>         | Show the deobfuscated bytecode of a function
+profile                      | Show the game profile in use
+sigs                         | List functions named from known signatures
+sigmake     <filename>       | Generate signatures for the named functions
+dbexport    <filename>       | Export the analysis database (tags, renames, signatures)
+rename      <class|fn|field> <name> <new> | Rename an element (fields and methods as Class.name), saved next to the file
+renames     [export|import <file>] | List the renames, or export or import them as a rename map
+renames     symbols <file> [replace] | Import the names of a CSV, IDA or Ghidra symbol map
+
+Remember you can use the range notation in place of an index to navigate through data : a..b
+This is the same range notation as Rust and is supported with most commands."#
+            )?;
+            for plugin in session.plugins.iter() {
+                writeln!(out, "\nCommands from plugin '{}' :", plugin.name())?;
+                for c in plugin.commands() {
+                    writeln!(out, "{:<12} {:<16} | {}", c.name, c.usage, c.help)?;
+                }
+            }
+        }
+        Command::Explain(s) => {
+            if let Some(o) = Opcode::from_name(&s) {
+                write!(out, "{} :\n{}", o.name(), o.description())?;
+                writeln!(
+                    out,
+                    "Example : {}",
+                    o.display(code, &code.functions[0], 0, 0)
+                )?;
+            } else {
+                writeln!(out, "No opcode named '{s}' exists.")?;
+            }
+        }
+        Command::Wiki => webbrowser::open("https://github.com/Gui-Yom/hlbc/wiki")?,
+        Command::Info => {
+            writeln!(out,
+                "version: {}\ndebug: {}\nnints: {}\nnfloats: {}\nnstrings: {}\nntypes: {}\nnnatives: {}\nnfunctions: {}\nnconstants: {}\nnsuspicious: {}",
+                code.version,
+                code.debug_files.is_some(),
+                code.ints.len(),
+                code.floats.len(),
+                code.strings.len(),
+                code.types.len(),
+                code.natives.len(),
+                code.functions.len(),
+                code.constants.as_ref().map_or(0, |c| c.len()),
+                anomaly::suspicious_functions(code, &Thresholds::default()).count()
+            )?;
+            let obfuscation = ObfuscationReport::new(code, &Thresholds::default());
+            if !obfuscation.is_empty() {
+                writeln!(out, "obfuscation:")?;
+                for sign in &obfuscation.signs {
+                    writeln!(out, "  {sign}")?;
+                }
+            }
+            if let Some(metadata) = &code.metadata {
+                writeln!(out, "metadata:")?;
+                for (key, value) in metadata.iter() {
+                    writeln!(out, "  {key} ({} bytes)", value.len())?;
+                }
+            }
+            if !code.warnings.is_empty() {
+                writeln!(out, "warnings:")?;
+                for w in &code.warnings {
+                    writeln!(out, "  {w}")?;
+                }
+            }
+        }
+        Command::Entrypoint => {
+            writeln!(
+                out,
+                "{}",
+                code.entrypoint.display_header(&session.named(code))
+            )?;
+        }
+        Command::Int(range) => {
+            for i in range {
+                print_i!(i);
+                writeln!(out, "{}", code.ints[i])?;
+            }
+        }
+        Command::Float(range) => {
+            for i in range {
+                print_i!(i);
+                writeln!(out, "{}", code.floats[i])?;
+            }
+        }
+        Command::String(range) => {
+            for i in range {
+                print_i!(i);
+                writeln!(out, "{}", code.strings[i])?;
+            }
+        }
+        Command::SearchStr(str) => {
+            let search = session.search.get_or_insert_with(|| SearchIndex::new(code));
+            for s in search.strings(code, &str) {
+                print_i!(s.0);
+                writeln!(out, "{}", code.strings[s.0])?;
+            }
+        }
+        Command::Debugfile(range) => {
+            let debug_files = require_debug_info!();
+            for i in range {
+                print_i!(i);
+                writeln!(out, "{}", debug_files[i])?;
+            }
+        }
+        Command::SearchDebugfile(str) => {
+            let debug_files = require_debug_info!();
+            let search = session.search.get_or_insert_with(|| SearchIndex::new(code));
+            for i in search.debug_files(code, &str) {
+                print_i!(i);
+                writeln!(out, "{}", debug_files[i])?;
+            }
+        }
+        Command::Type(range) => {
+            let range_len = range.len();
+            for i in range {
+                let target = TagTarget::Type(RefType(i));
+                if !session.shown(target) {
+                    continue;
+                }
+                print_i!(i);
+                let t = &code.types[i];
+                writeln!(
+                    out,
+                    "{}{}",
+                    RefType(i).display(code),
+                    session.display_tags(target)
+                )?;
+                // Only display full info if selecting a single item
+                if range_len == 1 {
+                    match t {
+                        Type::Obj(obj) => {
+                            if let Some(sup) = obj.super_ {
+                                writeln!(out, "extends {}", sup.display_id(code))?;
+                            }
+                            let subclasses = code.subclasses(RefType(i));
+                            if !subclasses.is_empty() {
+                                writeln!(out, "subclasses:")?;
+                                for sub in subclasses {
+                                    writeln!(out, "  {}", sub.display_id(code))?;
+                                }
+                            }
+                            writeln!(out, "global: {}", obj.global.0)?;
+                            writeln!(out, "fields:")?;
+                            for f in &obj.own_fields {
+                                writeln!(
+                                    out,
+                                    "  {}: {}",
+                                    f.name.display(code),
+                                    f.t.display_id(code)
+                                )?;
+                            }
+                            writeln!(out, "protos:")?;
+                            for p in &obj.protos {
+                                writeln!(
+                                    out,
+                                    "  {}: {} ({})",
+                                    p.name.display(code),
+                                    p.findex.display_header(&session.named(code)),
+                                    p.pindex
+                                )?;
+                            }
+                            writeln!(out, "bindings:")?;
+                            for (fi, fun) in &obj.bindings {
+                                writeln!(
+                                    out,
+                                    "  {}: {}",
+                                    fi.display_obj(t, code),
+                                    fun.display_header(&session.named(code))
+                                )?;
+                            }
+                        }
+                        Type::Enum {
+                            global, constructs, ..
+                        } => {
+                            writeln!(out, "global: {}", global.0)?;
+                            writeln!(out, "constructs:")?;
+                            for c in constructs {
+                                writeln!(
+                                    out,
+                                    "  {}:",
+                                    if c.name.0 == 0 {
+                                        "_".to_string()
+                                    } else {
+                                        c.name.display(code)
+                                    }
+                                )?;
+                                for (i, p) in c.params.iter().enumerate() {
+                                    writeln!(out, "    {i}: {}", p.display_id(code))?;
+                                }
+                            }
+                        }
+                        Type::Virtual { fields } => {
+                            writeln!(out, "fields:")?;
+                            for f in fields {
+                                writeln!(
+                                    out,
+                                    "  {}: {}",
+                                    f.name.display(code),
+                                    f.t.display_id(code)
+                                )?;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Command::Global(range) => {
+            for i in range {
+                print_i!(i);
+                writeln!(out, "{}", code.globals[i].display_id(code))?;
+                if let Some(value) = code.global_value(RefGlobal(i)) {
+                    writeln!(out, "    = {}", value.display(code))?;
+                }
+            }
+        }
+        Command::Native(range) => {
+            for i in range {
+                print_i!(i);
+                writeln!(out, "{}", code.natives[i].display_header(code))?;
+            }
+        }
+        Command::Constant(range) => {
+            for i in range {
+                print_i!(i);
+                writeln!(out, "{:#?}", code.constants.as_ref().unwrap()[i])?;
+            }
+        }
+        Command::FunctionHeader(range) => {
+            for findex in range {
+                let target = TagTarget::Fun(RefFun(findex));
+                if !session.shown(target) {
+                    continue;
+                }
+                print_i!(findex);
+                match RefFun(findex).resolve(code) {
+                    FunPtr::Fun(f) => writeln!(
+                        out,
+                        "{}{}",
+                        f.display_header(&session.named(code)),
+                        session.display_tags(target)
+                    )?,
+                    FunPtr::Native(n) => writeln!(
+                        out,
+                        "{}{}",
+                        n.display_header(code),
+                        session.display_tags(target)
+                    )?,
+                }
+            }
+        }
+        Command::Function(range) => {
+            for findex in range {
+                if !session.shown(TagTarget::Fun(RefFun(findex))) {
+                    continue;
+                }
+                print_i!(findex);
+                match RefFun(findex).resolve(code) {
+                    FunPtr::Fun(f) => writeln!(out, "{}", session.display_fun(code, f))?,
+                    FunPtr::Native(n) => writeln!(out, "{}", n.display_header(code))?,
+                }
+            }
+        }
+        Command::FunctionNamed(str) => {
+            let index = session
+                .functions
+                .get_or_insert_with(|| code.function_index());
+            let found = index.lookup(code, &str);
+            if found.is_empty() {
+                writeln!(out, "unknown '{str}'")?;
+            }
+            for findex in found {
+                match findex.resolve(code) {
+                    FunPtr::Fun(f) => writeln!(out, "{}", session.display_fun(code, f))?,
+                    FunPtr::Native(n) => writeln!(out, "{}", n.display_header(code))?,
+                }
+            }
+        }
+        Command::SearchFunction(str) => {
+            let index = session
+                .functions
+                .get_or_insert_with(|| code.function_index());
+            let found = index.search(&str);
+            if found.is_empty() {
+                writeln!(out, "unknown")?;
+            }
+            for findex in found {
+                if session.shown(TagTarget::Fun(findex)) {
+                    writeln!(
+                        out,
+                        "{}{}",
+                        findex.display_header(&session.named(code)),
+                        session.display_tags(TagTarget::Fun(findex))
+                    )?;
+                }
+            }
+        }
+        Command::InFile(foi) => {
+            let debug_files = require_debug_info!();
+            match foi {
+                FileOrIndex::File(str) => {
+                    if let Some(idx) =
+                        debug_files
+                            .iter()
+                            .enumerate()
+                            .find_map(
+                                |(i, d): (usize, &String)| {
+                                    if d == &str {
+                                        Some(i)
+                                    } else {
+                                        None
+                                    }
+                                },
+                            )
+                    {
+                        writeln!(out, "Functions in file@{idx} : {}", debug_files[idx])?;
+                        for (i, f) in code.functions.iter().enumerate() {
+                            if f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0 == idx
+                                && session.shown(TagTarget::Fun(f.findex))
+                            {
+                                print_i!(i);
+                                writeln!(out, "{}", f.display_header(&session.named(code)))?;
+                            }
+                        }
+                    } else {
+                        writeln!(out, "File {str} not found !")?;
+                    }
+                }
+                FileOrIndex::Index(idx) => {
+                    writeln!(out, "Functions in file@{idx} : {}", debug_files[idx])?;
+                    for (i, f) in code.functions.iter().enumerate() {
+                        if f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0 == idx
+                            && session.shown(TagTarget::Fun(f.findex))
+                        {
+                            print_i!(i);
+                            writeln!(out, "{}", f.display_header(&session.named(code)))?;
+                        }
+                    }
+                }
+            }
+        }
+        Command::FileOf(idx) => {
+            let debug_files = require_debug_info!();
+            match RefFun(idx).try_resolve(code)? {
+                FunPtr::Fun(f) => {
+                    let idx = f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0;
+                    writeln!(
+                        out,
+                        "{} is in file@{idx} : {}",
+                        f.display_header(&session.named(code)),
+                        &debug_files[idx]
+                    )?;
+                }
+                FunPtr::Native(n) => writeln!(
+                    out,
+                    "native {} is in the module {}",
+                    n.display_header(code),
+                    n.lib.resolve(&code.strings)
+                )?,
+            }
+        }
+        Command::SaveTo(file, debug) => {
+            let mut code = session.unrenamed(code);
+            match debug {
+                None => {}
+                Some(DebugInfo::Strip) => {
+                    let strings = hlbc::transform::strip_debug_info(&mut code);
+                    writeln!(out, "Removed the debug information and {strings} strings")?;
+                }
+                Some(DebugInfo::Inject) => {
+                    let injected = inject_debug_info(&mut code, &session.inline);
+                    writeln!(
+                        out,
+                        "Mapped {} functions to {} files with {} variable names",
+                        injected.functions, injected.files, injected.assigns
+                    )?;
+                }
+            }
+            let mut data = Vec::new();
+            code.serialize(&mut data)?;
+            fs::write(&file, &data)?;
+            write_manifest(session, Path::new(&file), &data)?;
+        }
+        Command::Extract(findex, file) => {
+            let extracted = hlbc::extract::extract(code, RefFun(findex))?;
+            let mut data = Vec::new();
+            extracted.serialize(&mut data)?;
+            fs::write(&file, &data)?;
+            writeln!(
+                out,
+                "Extracted {} functions and {} natives",
+                extracted.functions.len(),
+                extracted.natives.len()
+            )?;
+        }
+        Command::Instrument(findex, pos, regs, file) => {
+            use hlbc::instrument::{instrument, Logger, Probe};
+
+            let mut instrumented = session.unrenamed(code);
+            let probe = Probe::new(RefFun(findex), pos, regs.into_iter().map(Reg).collect());
+            match instrument(&mut instrumented, &Logger::Print, &[probe]) {
+                Ok(()) => {
+                    let mut data = Vec::new();
+                    instrumented.serialize(&mut data)?;
+                    fs::write(&file, &data)?;
+                    write_manifest(session, Path::new(&file), &data)?;
+                }
+                Err(e) => writeln!(out, "{e}")?,
+            }
+        }
+        Command::Reassemble(findex, listing, file) => {
+            let text = fs::read_to_string(&listing)?;
+            let mut edited = session.unrenamed(code);
+            match hlbc::asm::reassemble(&mut edited, RefFun(findex), &text) {
+                Ok(()) => {
+                    let mut data = Vec::new();
+                    edited.serialize(&mut data)?;
+                    fs::write(&file, &data)?;
+                    write_manifest(session, Path::new(&file), &data)?;
+                }
+                Err(e) => writeln!(out, "{e}")?,
+            }
+        }
+        Command::Minimize(findex, file) => {
+            use hlbc_decompiler::minimize::{failure, minimize};
+
+            let location = code
+                .findexes
+                .get(findex)
+                .and_then(|_| RefFun(findex).resolve_as_fn(code))
+                .and_then(|f| failure(code, f));
+            if let Some(location) = location {
+                // Only keep candidates failing at the same place
+                let minimized = minimize(code, RefFun(findex), |code, f| {
+                    failure(code, f).as_ref() == Some(&location)
+                })?;
+                if let Some(m) = minimized {
+                    let mut data = Vec::new();
+                    m.code.serialize(&mut data)?;
+                    fs::write(&file, &data)?;
+                    writeln!(
+                        out,
+                        "Decompiler failure at {location} reduced to {} instructions",
+                        m.findex.resolve_as_fn(&m.code).unwrap().ops.len()
+                    )?;
+                } else {
+                    writeln!(
+                        out,
+                        "The failure at {location} doesn't happen once the function is extracted"
+                    )?;
+                }
+            } else {
+                writeln!(out, "Decompiling fn@{findex} doesn't fail")?;
+            }
+        }
+        Command::Callgraph(idx, depth) => {
+            #[cfg(feature = "graph")]
+            {
+                use hlbc_analysis::graph::{call_graph, display_graph};
+
+                let graph = call_graph(code, RefFun(idx), depth);
+                writeln!(out, "{}", display_graph(&graph, code))?;
+            }
+
+            #[cfg(not(feature = "graph"))]
+            {
+                writeln!(out, "hlbc-cli has been built without graph support. Build with feature 'graph' to enable callgraph generation")?;
+            }
+        }
+        Command::Callers(_, _) | Command::Callees(_, _) => {
+            #[cfg(feature = "graph")]
+            {
+                let (idx, depth, direction) = match cmd {
+                    Command::Callers(idx, depth) => (idx, depth, Direction::Incoming),
+                    Command::Callees(idx, depth) => (idx, depth, Direction::Outgoing),
+                    _ => unreachable!(),
+                };
+                let graph = session
+                    .callgraph
+                    .get_or_insert_with(|| Callgraph::new(code));
+                if !graph.graph.contains_node(RefFun(idx)) {
+                    writeln!(out, "fn@{idx} doesn't exist")?;
+                } else if let Some(depth) = depth {
+                    write!(out, "{}", graph.tree(code, RefFun(idx), direction, depth))?;
+                } else {
+                    let mut calls: Vec<_> = if direction == Direction::Incoming {
+                        graph.callers(RefFun(idx)).collect()
+                    } else {
+                        graph.callees(RefFun(idx)).collect()
+                    };
+                    calls.sort_unstable_by_key(|&(f, _)| f);
+                    for (f, call) in calls {
+                        print_i!(f.0);
+                        writeln!(out, "{} ({call:?})", f.display_id(&session.named(code)))?;
+                    }
+                }
+            }
+
+            #[cfg(not(feature = "graph"))]
+            {
+                writeln!(out, "hlbc-cli has been built without graph support. Build with feature 'graph' to enable call trees")?;
+            }
+        }
+        Command::Dead => {
+            #[cfg(feature = "graph")]
+            {
+                let graph = session
+                    .callgraph
+                    .get_or_insert_with(|| Callgraph::new(code));
+                let dead = graph.dead(code);
+                writeln!(
+                    out,
+                    "{} of {} functions unreachable from the entrypoint",
+                    dead.len(),
+                    code.functions.len()
+                )?;
+                for f in dead {
+                    if session.shown(TagTarget::Fun(f)) {
+                        print_i!(f.0);
+                        writeln!(out, "{}", f.display_header(&session.named(code)))?;
+                    }
+                }
+            }
+
+            #[cfg(not(feature = "graph"))]
+            {
+                writeln!(out, "hlbc-cli has been built without graph support. Build with feature 'graph' to enable dead functions detection")?;
+            }
+        }
+        Command::Reflection => {
+            write!(out, "{}", Reflection::new(code).to_text(code))?;
+        }
+        Command::Packages(dot) => {
+            #[cfg(feature = "graph")]
+            {
+                let graph = session
+                    .callgraph
+                    .get_or_insert_with(|| Callgraph::new(code));
+                let packages = hlbc_analysis::packages::PackageGraph::new(code, graph, false);
+                if dot {
+                    write!(out, "{}", packages.to_dot())?;
+                } else {
+                    write!(out, "{}", packages.to_text())?;
+                }
+            }
+
+            #[cfg(not(feature = "graph"))]
+            {
+                writeln!(out, "hlbc-cli has been built without graph support. Build with feature 'graph' to enable the package graph")?;
+            }
+        }
+        Command::RefTo(elem) => {
+            if session.xrefs.is_none() {
+                session.xrefs = Some(XrefIndex::new(code));
+            }
+            let xrefs = session.xrefs.as_ref().unwrap();
+            let print_xrefs = |out: &mut W, refs: &[Xref]| -> io::Result<()> {
+                for &(f, i) in refs {
+                    let fun = f.resolve_as_fn(code).unwrap();
+                    writeln!(
+                        out,
+                        "{} at {i}: {}",
+                        fun.display_header(&session.named(code)),
+                        fun.ops[i].name()
+                    )?;
+                }
+                Ok(())
+            };
+            match elem {
+                ElementRef::String(idx) => {
+                    writeln!(
+                        out,
+                        "Finding references to string@{idx} : {}\n",
+                        code.strings[idx]
+                    )?;
+                    if let Some(constants) = &code.constants {
+                        for (i, c) in constants.iter().enumerate() {
+                            if c.fields[0] == idx {
+                                writeln!(out,
+                                    "constant@{i} expanding to global@{} (now also searching for global)",
+                                    c.global.0
+                                )?;
+                                for (f, (i, o)) in code.ops() {
+                                    if let Opcode::GetGlobal { global, .. } = o {
+                                        if *global == c.global {
+                                            writeln!(
+                                                out,
+                                                "in {} at {i}: GetGlobal",
+                                                f.display_header(&session.named(code))
+                                            )?;
+                                        }
+                                    }
+                                }
+                                writeln!(out)?;
+                            }
+                        }
+                    }
+                    print_xrefs(out, xrefs.string(RefString(idx)))?;
+                }
+                ElementRef::Global(idx) => {
+                    writeln!(
+                        out,
+                        "Finding references to global@{idx} : {}\n",
+                        code.globals[idx].display_id(code)
+                    )?;
+                    if let Some(constants) = &code.constants {
+                        for (i, c) in constants.iter().enumerate() {
+                            if c.global.0 == idx {
+                                writeln!(out, "constant@{i} : {:?}", c)?;
+                            }
+                        }
+                    }
+                    writeln!(out)?;
+
+                    print_xrefs(out, xrefs.global(RefGlobal(idx)))?;
+                }
+                ElementRef::Type(idx) => {
+                    writeln!(
+                        out,
+                        "Finding references to type@{idx} : {}\n",
+                        RefType(idx).display_id(code)
+                    )?;
+                    print_xrefs(out, xrefs.ty(RefType(idx)))?;
+                }
+                ElementRef::Field(ty, field) => {
+                    let name = RefType(ty)
+                        .resolve_as_obj(&code.types)
+                        .and_then(|obj| obj.fields.get(field))
+                        .map(|f| f.name.display(code));
+                    if let Some(name) = name {
+                        writeln!(out, "Finding references to field@{ty}.{field} : {name}\n")?;
+                        for ((f, i), access) in
+                            xrefs.field_usages(code, RefType(ty), RefField(field))
+                        {
+                            let fun = f.resolve_as_fn(code).unwrap();
+                            writeln!(
+                                out,
+                                "{} at {i}: {access:?}",
+                                fun.display_header(&session.named(code))
+                            )?;
+                        }
+                    } else {
+                        writeln!(out, "type@{ty} has no field {field}")?;
+                    }
+                }
+                ElementRef::Fn(idx) => {
+                    writeln!(
+                        out,
+                        "Finding references to fn@{idx} : {}\n",
+                        RefFun(idx).display_header(&session.named(code))
+                    )?;
+                    for (f, (i, o, fun)) in code
+                        .functions
+                        .iter()
+                        .flat_map(|f| repeat(f).zip(f.find_fun_refs()))
+                    {
+                        if fun.0 == idx && session.shown(TagTarget::Fun(f.findex)) {
+                            writeln!(
+                                out,
+                                "{} at {i}: {}",
+                                f.display_header(&session.named(code)),
+                                o.name()
+                            )?;
+                        }
+                    }
+                }
+            }
+        }
+        Command::Decomp(idx) => {
+            if let Some(fun) = RefFun(idx).resolve_as_fn(code) {
+                match hlbc_decompiler::decompile_function_with(code, fun, &session.inline) {
+                    Ok(method) => writeln!(
+                        out,
+                        "{}",
+                        method.display(code, &hlbc_decompiler::fmt::FormatOptions::new("  "))
+                    )?,
+                    Err(e) => print_decomp_error(out, &e)?,
+                }
+            }
+        }
+        Command::Export(dir) => {
+            let dir = Path::new(dir.trim());
+            let modules = hlbc_decompiler::project::decompile_project(code, &session.inline);
+            let failed = write_modules(out, dir, &modules)?;
+            writeln!(
+                out,
+                "Exported {} classes to {} ({failed} failed)",
+                modules.len(),
+                dir.display()
+            )?;
+        }
+        Command::Externs(dir) => {
+            let dir = Path::new(dir.trim());
+            let modules = hlbc_decompiler::project::extern_stubs(code);
+            let failed = write_modules(out, dir, &modules)?;
+            writeln!(
+                out,
+                "Declared {} extern classes in {} ({failed} failed)",
+                modules.len(),
+                dir.display()
+            )?;
+        }
+        Command::Inline(setting) => {
+            let inline = &mut session.inline;
+            match setting {
+                Some(InlineSetting::Compact) => {
+                    *inline = InlineOptions {
+                        rewrites: mem::take(&mut inline.rewrites),
+                        unflatten: inline.unflatten,
+                        ..InlineOptions::compact()
+                    }
+                }
+                Some(InlineSetting::Flat) => {
+                    *inline = InlineOptions {
+                        rewrites: mem::take(&mut inline.rewrites),
+                        unflatten: inline.unflatten,
+                        ..InlineOptions::flat()
+                    }
+                }
+                Some(InlineSetting::Size(size)) => inline.max_size = size,
+                Some(InlineSetting::Uses(uses)) => inline.max_uses = uses,
+                Some(InlineSetting::SideEffects(b)) => inline.side_effects = b,
+                Some(InlineSetting::Names(true)) => inline.naming = NamingOptions::default(),
+                Some(InlineSetting::Names(false)) => inline.naming = NamingOptions::none(),
+                Some(InlineSetting::Unflatten(b)) => inline.unflatten = b,
+                None => {}
+            }
+            let limit = |l: Option<usize>| l.map_or("none".to_owned(), |l| l.to_string());
+            writeln!(
+                out,
+                "Max expression size : {}, max uses : {}, inline calls : {}, variable names : {}, unflatten : {}",
+                limit(inline.max_size),
+                limit(inline.max_uses),
+                inline.side_effects,
+                inline.naming != NamingOptions::none(),
+                inline.unflatten
+            )?;
+        }
+        Command::Syntax(dialect) => {
+            if let Some(dialect) = dialect {
+                session.syntax = dialect;
+            }
+            writeln!(out, "Disassembly syntax : {}", session.syntax)?;
+        }
+        Command::AstFind(pattern) => match pattern.trim().parse::<Pattern>() {
+            Ok(pattern) => {
+                let opts = hlbc_decompiler::fmt::FormatOptions::new("");
+                let mut count = 0;
+                for f in code.functions.iter() {
+                    // Functions the decompiler can't handle are skipped
+                    let Ok(stmts) = hlbc_decompiler::decompile_code_with(code, f, &session.inline)
+                    else {
+                        continue;
+                    };
+                    for m in hlbc_decompiler::pattern::find(code, f, &stmts, &pattern) {
+                        writeln!(
+                            out,
+                            "{} : {}",
+                            f.display_header(&session.named(code)),
+                            m.expr.display(&opts, code, f)
+                        )?;
+                        count += 1;
+                    }
+                }
+                writeln!(out, "{count} matches")?;
+            }
+            Err(e) => writeln!(out, "{e}")?,
+        },
+        Command::Rewrite(rule) => {
+            let rewrites = &mut session.inline.rewrites;
+            match rule.trim() {
+                "" => {}
+                "clear" => rewrites.clear(),
+                rule => match rule.parse::<Rewrite>() {
+                    Ok(rewrite) => rewrites.push(rewrite),
+                    Err(e) => writeln!(out, "{e}")?,
+                },
+            }
+            for (i, rewrite) in rewrites.iter().enumerate() {
+                writeln!(out, "{i}: {rewrite}")?;
+            }
+        }
+        Command::DecompType(idx) => {
+            let ty = &code.types[idx];
+            match ty {
+                Type::Obj(obj) => {
+                    writeln!(out, "Dumping type@{idx} : {}", ty.display(code))?;
+                    match hlbc_decompiler::decompile_class_with(code, obj, &session.inline) {
+                        Ok(class) => writeln!(
+                            out,
+                            "{}",
+                            class.display(code, &hlbc_decompiler::fmt::FormatOptions::new("  "))
+                        )?,
+                        Err(e) => print_decomp_error(out, &e)?,
+                    }
+                }
+                Type::Virtual { .. } => {
+                    writeln!(
+                        out,
+                        "{}",
+                        hlbc_decompiler::decompile_typedef(code, RefType(idx))
+                            .unwrap()
+                            .display(code, &hlbc_decompiler::fmt::FormatOptions::new("  "))
+                    )?;
+                }
+                _ => writeln!(out, "Type {idx} is not an obj or a virtual")?,
+            }
+        }
+        Command::Tag(target, tag) => {
+            if !target_exists(code, target) {
+                writeln!(out, "{target} doesn't exist")?;
+            } else if session.tags.add(target, tag.as_str()) {
+                session.save_tags()?;
+            } else {
+                writeln!(out, "{target} is already tagged with '{tag}'")?;
+            }
+        }
+        Command::Note(findex, pos, text) => match RefFun(findex).resolve_as_fn(code) {
+            Some(f) if pos < f.ops.len() => {
+                session
+                    .annotations
+                    .add(f.findex, pos, Note::new(Severity::Info, "user", text));
+            }
+            Some(_) => writeln!(out, "fn@{findex} has no instruction {pos}")?,
+            None => writeln!(out, "fn@{findex} is not a function")?,
+        },
+        Command::Untag(target, tag) => {
+            if session.tags.remove(target, &tag) {
+                session.save_tags()?;
+            } else {
+                writeln!(out, "{target} is not tagged with '{tag}'")?;
+            }
+        }
+        Command::Tags(None) => {
+            for (tag, count) in session.tags.all() {
+                writeln!(out, "{tag} ({count})")?;
+            }
+        }
+        Command::Tags(Some(tag)) => {
+            // A stale sidecar may refer to elements that don't exist anymore
+            for target in session
+                .tags
+                .with_tag(&tag)
+                .filter(|&t| target_exists(code, t))
+            {
+                match target {
+                    TagTarget::Fun(fun) => {
+                        writeln!(out, "{}", fun.display_header(&session.named(code)))?
+                    }
+                    TagTarget::Type(ty) => writeln!(out, "{}", ty.display_id(code))?,
+                }
+            }
+        }
+        Command::TagFilter(tag) => {
+            session.tag_filter = tag;
+        }
+        Command::Anomalies => {
+            for (f, anomalies) in anomaly::suspicious_functions(code, &Thresholds::default()) {
+                if !session.shown(TagTarget::Fun(f.findex)) {
+                    continue;
+                }
+                print_i!(f.findex.0);
+                writeln!(out, "{}", f.display_header(&session.named(code)))?;
+                for a in anomalies {
+                    writeln!(out, "  - {a}")?;
+                }
+            }
+        }
+        Command::Metrics(Some(findex)) => {
+            if let Some(f) = RefFun(findex).resolve_as_fn(code) {
+                let m = FunctionMetrics::new(f);
+                writeln!(out, "{}", f.display_header(&session.named(code)))?;
+                writeln!(out, "ops : {}, registers : {}", m.ops, m.regs)?;
+                writeln!(out, "max call arity : {}", m.max_call_arity)?;
+                writeln!(out, "loop depth : {}", m.loop_depth)?;
+                writeln!(out, "cyclomatic complexity : {}", m.cyclomatic)?;
+            }
+        }
+        Command::Metrics(None) => {
+            let mut metrics: Vec<(&Function, FunctionMetrics)> = code
+                .functions
+                .iter()
+                .filter(|f| session.shown(TagTarget::Fun(f.findex)))
+                .map(|f| (f, FunctionMetrics::new(f)))
+                .collect();
+            let module: ModuleMetrics = metrics.iter().map(|(_, m)| m.clone()).collect();
+            writeln!(
+                out,
+                "{} functions, {} ops, {} registers",
+                module.functions, module.ops, module.regs
+            )?;
+            writeln!(
+                out,
+                "max ops : {}, max registers : {}, max call arity : {}, max loop depth : {}",
+                module.max_ops, module.max_regs, module.max_call_arity, module.max_loop_depth
+            )?;
+            writeln!(
+                out,
+                "cyclomatic complexity : {:.2} on average, {} at most",
+                module.mean_cyclomatic(),
+                module.max_cyclomatic
+            )?;
+            writeln!(out, "\nMost complex functions :")?;
+            metrics.sort_by_key(|(_, m)| std::cmp::Reverse(m.cyclomatic));
+            for (f, m) in metrics.iter().take(10) {
+                print_i!(f.findex.0);
+                writeln!(
+                    out,
+                    "{} : complexity {}, loop depth {}, {} ops",
+                    f.display_header(&session.named(code)),
+                    m.cyclomatic,
+                    m.loop_depth,
+                    m.ops
+                )?;
+            }
+        }
+        Command::Entries => {
+            for e in entrypoints::find_entrypoints(code) {
+                print_i!(e.findex.0);
+                writeln!(
+                    out,
+                    "{} : {} ({})",
+                    e.findex.display_header(&session.named(code)),
+                    e.role,
+                    e.evidence
+                )?;
+            }
+        }
+        Command::Boot => {
+            match entrypoints::module_entrypoint(code) {
+                Some(init) => writeln!(
+                    out,
+                    "Entrypoint : {}",
+                    init.display_header(&session.named(code))
+                )?,
+                None => writeln!(
+                    out,
+                    "Entrypoint : {}",
+                    code.entrypoint.display_header(&session.named(code))
+                )?,
+            }
+            for step in entrypoints::boot_sequence(code) {
+                write!(
+                    out,
+                    "global@{} at {} : {}",
+                    step.global.0,
+                    step.pos,
+                    step.ty.display_id(code)
+                )?;
+                match step.class {
+                    Some(class) => writeln!(out, " (statics of {})", class.display_id(code))?,
+                    None => writeln!(out)?,
+                }
+            }
+            for (ty, findex) in entrypoints::static_initializers(code) {
+                writeln!(
+                    out,
+                    "{} : {}",
+                    ty.display_id(code),
+                    findex.display_header(&session.named(code))
+                )?;
+            }
+        }
+        Command::Libs(None) => {
+            for lib in natives::native_libs(code) {
+                writeln!(
+                    out,
+                    "{} : {} natives called by {} functions",
+                    lib.name,
+                    lib.natives.len(),
+                    lib.callers().len()
+                )?;
+            }
+        }
+        Command::Libs(Some(name)) => {
+            match natives::native_libs(code)
+                .into_iter()
+                .find(|lib| lib.name == name)
+            {
+                Some(lib) => {
+                    for n in &lib.natives {
+                        writeln!(out, "{}@{} {}", n.name, n.findex.0, n.signature(code))?;
+                        for caller in &n.callers {
+                            writeln!(out, "  {}", caller.display_header(&session.named(code)))?;
+                        }
+                    }
+                }
+                None => writeln!(out, "No native library named {name}")?,
+            }
+        }
+        Command::Card(idx) => match ClassCard::new(code, RefType(idx)) {
+            Some(card) => write!(out, "{}", card.to_markdown(code))?,
+            None => writeln!(out, "type@{idx} is not a class")?,
+        },
+        Command::Layout(name) => {
+            let ty = name.parse().ok().map(RefType).or_else(|| {
+                code.iter_types()
+                    .find(|(_, t)| {
+                        t.get_type_obj()
+                            .map_or(false, |o| o.name.resolve(&code.strings) == name)
+                    })
+                    .map(|(t, _)| t)
+            });
+            match ty.and_then(|t| Some((t, code.obj_layout(t)?))) {
+                Some((t, layout)) => {
+                    writeln!(
+                        out,
+                        "{} : {} bytes, aligned on {}",
+                        t.display_id(code),
+                        layout.size,
+                        layout.align
+                    )?;
+                    for f in &layout.fields {
+                        writeln!(
+                            out,
+                            "{:>6} {} : {}",
+                            f.offset,
+                            f.name.resolve(&code.strings),
+                            f.ty.display_id(code)
+                        )?;
+                    }
+                    for m in &layout.vtable {
+                        writeln!(
+                            out,
+                            "slot {:>2} {}",
+                            m.slot,
+                            m.findex.display_id(&session.named(code))
+                        )?;
+                    }
+                }
+                None => writeln!(out, "{name} is not a class nor a structure")?,
+            }
+        }
+        Command::Cards(file) => {
+            let cards = ClassCard::all(code);
+            let md: String = cards.iter().map(|c| c.to_markdown(code)).collect();
+            fs::write(file.trim(), md)?;
+            writeln!(out, "Exported {} class cards", cards.len())?;
+        }
+        Command::Sqlite(file) => {
+            #[cfg(feature = "graph")]
+            {
+                let file = file.trim();
+                hlbc_analysis::sqlite::export(code, BufWriter::new(fs::File::create(file)?))?;
+                writeln!(out, "Exported the database to {file}")?;
+            }
+
+            #[cfg(not(feature = "graph"))]
+            {
+                writeln!(out, "hlbc-cli has been built without graph support. Build with feature 'graph' to enable the SQLite export")?;
+            }
+        }
+        Command::Pointers(file, depth) => {
+            let mut opts = PointerOptions::default();
+            if let Some(depth) = depth {
+                opts.depth = depth;
+            }
+            let map = PointerMap::new(code, &opts);
+            let cheat_table = Path::new(&file)
+                .extension()
+                .map_or(false, |ext| ext.eq_ignore_ascii_case("ct"));
+            fs::write(
+                &file,
+                if cheat_table {
+                    map.to_cheat_table(code)
+                } else {
+                    map.to_json(code)
+                },
+            )?;
+            writeln!(out, "Exported {} pointers to {file}", map.pointers.len())?;
+            if cheat_table {
+                writeln!(
+                    out,
+                    "Register the address of the globals area as the symbol '{GLOBALS_SYMBOL}' before loading the table"
+                )?;
+            }
+        }
+        Command::Sigs => {
+            #[cfg(feature = "autotag")]
+            for m in &session.sig_matches {
+                print_i!(m.findex.0);
+                writeln!(out, "{} ({:.0}%)", m.name, m.confidence * 100.0)?;
+            }
+            #[cfg(not(feature = "autotag"))]
+            writeln!(out, "Signatures require the feature 'autotag'")?;
+        }
+        Command::SigMake(file) => {
+            #[cfg(feature = "autotag")]
+            {
+                let sigs = Signatures::generate(code);
+                fs::write(file, sigs.to_toml())?;
+                writeln!(out, "Generated {} signatures", sigs.signatures.len())?;
+            }
+            #[cfg(not(feature = "autotag"))]
+            writeln!(out, "Signatures require the feature 'autotag'")?;
+        }
+        Command::DbExport(file) => {
+            #[cfg(feature = "autotag")]
+            {
+                let original = session.unrenamed(code);
+                let db = Database::new(&original, code, &session.tags, &session.sig_matches);
+                fs::write(file, db.to_toml())?;
+            }
+            #[cfg(not(feature = "autotag"))]
+            writeln!(out, "The analysis database requires the feature 'autotag'")?;
+        }
+        Command::Rename(kind, old, new) => {
+            #[cfg(feature = "autotag")]
+            {
+                let kind: RenameKind = kind.parse()?;
+                let mut rename = RenameMap::new();
+                rename.insert(kind, &old, &new);
+                if rename.resolve(code).1.is_empty() {
+                    session.renames.insert(kind, &old, &new);
+                    session.pending_renames.merge(&rename);
+                    session.save_renames()?;
+                    writeln!(out, "Renamed {kind} {old} to {new}")?;
+                } else {
+                    writeln!(out, "No {kind} named {old}")?;
+                }
+            }
+            #[cfg(not(feature = "autotag"))]
+            writeln!(out, "Renames require the feature 'autotag'")?;
+        }
+        Command::Renames(action) => {
+            #[cfg(feature = "autotag")]
+            match action {
+                None => {
+                    for (kind, old, new) in session.renames.iter() {
+                        match session.renames.source(kind, old) {
+                            Some(source) => writeln!(out, "{kind:<5} {old} -> {new} ({source})")?,
+                            None => writeln!(out, "{kind:<5} {old} -> {new}")?,
+                        }
+                    }
+                    writeln!(
+                        out,
+                        "{} renames, saved in {}",
+                        session.renames.len(),
+                        session.renames_file.display()
+                    )?;
+                }
+                Some(RenamesAction::Export(file)) => {
+                    fs::write(&file, session.renames.to_toml())?;
+                    writeln!(out, "Exported {} renames", session.renames.len())?;
+                }
+                Some(RenamesAction::Import(file)) => {
+                    let imported = RenameMap::from_toml(&fs::read_to_string(&file)?)?;
+                    let missing = imported.resolve(code).1;
+                    for (kind, name) in &missing {
+                        writeln!(out, "No {kind} named {name}")?;
+                    }
+                    session.renames.merge(&imported);
+                    session.pending_renames.merge(&imported);
+                    session.save_renames()?;
+                    writeln!(out, "Imported {} renames", imported.len() - missing.len())?;
+                }
+                Some(RenamesAction::Symbols(file, replace)) => {
+                    let text = fs::read_to_string(&file)?;
+                    let format = SymbolFormat::detect(&text);
+                    let symbols = symbols::parse(format, &text)?;
+                    // Rename maps refer to the names in the file
+                    let original = session.unrenamed(code);
+                    let name = Path::new(&file)
+                        .file_name()
+                        .map_or(file.clone(), |n| n.to_string_lossy().into_owned());
+                    let imported =
+                        symbols::to_renames(&original, &symbols, &format!("{format}:{name}"));
+                    for (s, reason) in &imported.invalid {
+                        writeln!(
+                            out,
+                            "line {} : can't rename {} : {reason}",
+                            s.line, s.target
+                        )?;
+                    }
+                    for s in &imported.duplicates {
+                        writeln!(
+                            out,
+                            "line {} : {} is already named by a previous line",
+                            s.line, s.target
+                        )?;
+                    }
+                    let on_conflict = if replace {
+                        OnConflict::Replace
+                    } else {
+                        OnConflict::Keep
+                    };
+                    let conflicts = session.renames.import(&imported.renames, on_conflict);
+                    let mut taken = imported.renames.clone();
+                    for c in &conflicts {
+                        let kept = if replace { &c.imported } else { &c.current };
+                        writeln!(
+                            out,
+                            "{} {} is renamed {} and {} by the symbols, keeping {kept}",
+                            c.kind, c.name, c.current, c.imported
+                        )?;
+                        if !replace {
+                            taken.remove(c.kind, &c.name);
+                        }
+                    }
+                    session.pending_renames.merge(&taken);
+                    session.save_renames()?;
+                    writeln!(
+                        out,
+                        "Imported {} symbols from {file} ({format}), {} conflicts",
+                        imported.renames.len(),
+                        conflicts.len()
+                    )?;
+                }
+            }
+            #[cfg(not(feature = "autotag"))]
+            writeln!(out, "Renames require the feature 'autotag'")?;
+        }
+        Command::Provenance => {
+            let path = manifest::sidecar_path(&session.bytecode_file);
+            if path.exists() {
+                let m = Manifest::load(BufReader::new(fs::File::open(&path)?))?;
+                writeln!(out, "{}", m.description)?;
+                writeln!(out, "tool : {}", m.tool)?;
+                writeln!(out, "original file : {}", m.original)?;
+                if !m.matches(&fs::read(&session.bytecode_file)?) {
+                    writeln!(
+                        out,
+                        "The file has been modified since the manifest was written"
+                    )?;
+                }
+                writeln!(out, "{} functions changed", m.functions.len())?;
+                for f in &m.functions {
+                    writeln!(out, "{}", f.display_header(&session.named(code)))?;
+                }
+                writeln!(out, "{} strings changed", m.strings.len())?;
+                for s in &m.strings {
+                    writeln!(out, "{}", s.resolve(&code.strings))?;
+                }
+            } else {
+                writeln!(out, "No manifest found ({})", path.display())?;
+            }
+        }
+        Command::Profile => {
+            #[cfg(feature = "autotag")]
+            if let Some(profile) = &session.profile {
+                writeln!(out, "{} : {}", profile.name, profile.description)?;
+                for idiom in &profile.idioms {
+                    writeln!(out, "idiom {} : {}", idiom.name, idiom.description)?;
+                }
+                for hint in &profile.hints {
+                    writeln!(
+                        out,
+                        "hint {} : {} ({})",
+                        hint.name,
+                        hint.description,
+                        hint.types.join(", ")
+                    )?;
+                }
+            } else {
+                writeln!(out, "No profile loaded, use --profile <name>")?;
+            }
+            #[cfg(not(feature = "autotag"))]
+            writeln!(out, "Profiles require the feature 'autotag'")?;
+        }
+        Command::Slice(idx, pos, reg) => match RefFun(idx).resolve_as_fn(code) {
+            Some(f) if pos < f.ops.len() => {
+                let deps = DataDeps::new(f);
+                let backward = deps.backward(pos, reg.map(Reg));
+                let forward = deps.forward(pos);
+                for (title, slice) in [("Backward", backward), ("Forward", forward)] {
+                    writeln!(out, "{title} slice ({} instructions)", slice.len())?;
+                    for i in slice {
+                        print_i!(i);
+                        writeln!(
+                            out,
+                            "{}",
+                            f.ops[i].display(&session.named(code), f, i as i32, 11)
+                        )?;
+                    }
+                }
+            }
+            Some(f) => writeln!(out, "fn@{idx} has {} instructions", f.ops.len())?,
+            None => writeln!(out, "fn@{idx} is not a function with code")?,
+        },
+        Command::Eval(idx, args) => {
+            if idx >= code.findexes.len() {
+                writeln!(out, "fn@{idx} doesn't exist")?;
+                return Ok(());
+            }
+            let findex = RefFun(idx);
+            let params = findex.args(code);
+            let mut interp = Interpreter::new(code);
+            let values: Option<Vec<Value>> = params
+                .iter()
+                .zip(&args)
+                .map(|(&ty, arg)| literal_value(code, &interp, ty, arg))
+                .collect();
+            match values {
+                Some(values) if values.len() == args.len() => match interp
+                    .check_pure(findex)
+                    .and_then(|_| interp.call(findex, values))
+                {
+                    Ok(v) => writeln!(out, "{}", v.display(code))?,
+                    Err(e) => writeln!(out, "{e}")?,
+                },
+                _ => {
+                    let params: Vec<String> = params.iter().map(|t| t.display(code)).collect();
+                    writeln!(
+                        out,
+                        "Arguments don't match the parameters ({})",
+                        params.join(", ")
+                    )?;
+                }
+            }
+        }
+        Command::Deobf(idx) => {
+            if let Some(fun) = RefFun(idx).resolve_as_fn(code) {
+                if let Some((fun, report)) =
+                    hlbc_decompiler::deobf::deobfuscate(code, fun, session.inline.unflatten)
+                {
+                    write!(out, "{report}")?;
+                    writeln!(out, "{}", fun.display(&session.named(code)))?;
+                } else {
+                    writeln!(out, "Nothing to deobfuscate")?;
+                }
+            }
+        }
+        Command::Plugin(name, args) => {
+            if let Some(plugin) = session.plugins.find_command(&name) {
+                let ctx = PluginCtx {
+                    code,
+                    tags: &session.tags,
+                };
+                plugin.run_command(&ctx, &name, &args, out)?;
+            } else {
+                writeln!(
+                    out,
+                    "Unknown command '{name}' or invalid arguments, see 'help'"
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Convert an argument of the command line to a value of type `ty`
+fn literal_value(
+    code: &Bytecode,
+    interp: &Interpreter,
+    ty: RefType,
+    arg: &Literal,
+) -> Option<Value> {
+    Some(match (ty.resolve(&code.types), arg) {
+        (Type::UI8 | Type::UI16 | Type::I32, Literal::Int(v)) => {
+            Value::Int(i32::try_from(*v).ok()?)
+        }
+        (Type::I64, Literal::Int(v)) => Value::I64(*v),
+        (Type::F32, Literal::Int(v)) => Value::F32(*v as f32),
+        (Type::F32, Literal::Float(v)) => Value::F32(*v as f32),
+        (Type::F64, Literal::Int(v)) => Value::F64(*v as f64),
+        (Type::F64, Literal::Float(v)) => Value::F64(*v),
+        (Type::Bool, Literal::Bool(v)) => Value::Bool(*v),
+        (_, Literal::Str(s)) => interp.string(s)?,
+        (_, Literal::Null) if Value::default_of(code, ty) == Value::Null => Value::Null,
+        _ => return None,
+    })
+}
+
+/// Write the manifest of a saved file if a description has been given with `--manifest`
+/// Returns true if the tagged element is in the bytecode
+fn target_exists(code: &Bytecode, target: TagTarget) -> bool {
+    match target {
+        TagTarget::Fun(fun) => code.get_fun(fun).is_some(),
+        TagTarget::Type(ty) => code.get_type(ty).is_some(),
+    }
+}
+
+fn write_manifest(session: &Session, file: &Path, data: &[u8]) -> anyhow::Result<()> {
+    if let Some(description) = &session.manifest {
+        let original = fs::read(&session.bytecode_file)?;
+        let manifest = Manifest::new(&original, data, description)?;
+        let mut w = BufWriter::new(fs::File::create(manifest::sidecar_path(file))?);
+        manifest.save(&mut w)?;
+    }
+    Ok(())
+}
+
+/// Write the modules of a project under `dir`, returns the number of classes that failed
+fn write_modules(
+    out: &mut impl Write,
+    dir: &Path,
+    modules: &[hlbc_decompiler::project::Module],
+) -> anyhow::Result<usize> {
+    let mut failed = 0;
+    for m in modules {
+        let path = dir.join(&m.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &m.source)?;
+        if let Some(e) = &m.error {
+            writeln!(out, "{} : {e}", m.name)?;
+            failed += 1;
+        }
+    }
+    Ok(failed)
+}
+
+fn print_decomp_error(out: &mut impl Write, e: &hlbc_decompiler::Error) -> io::Result<()> {
+    if e.is_bug() {
+        writeln!(
+            out,
+            "{e}\nThis is a bug in the decompiler, please report it (see the 'minimize' command)"
+        )
+    } else {
+        writeln!(out, "Can't decompile : {e}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use termcolor::NoColor;
+
+    use hlbc::analysis::tags::TagTarget;
+    use hlbc::builder::sample;
+    use hlbc::types::RefFun;
+
+    use crate::command::{commands_parser, Command, ParseContext, Parser};
+    use crate::session::{process_command, Session};
+
+    #[test]
+    fn output() {
+        let code = sample();
+        let path = Path::new("sample.hl");
+        let mut session = Session::new(path, path).unwrap();
+        let mut out = NoColor::new(Vec::new());
+        let commands = commands_parser(&ParseContext::new(&code))
+            .parse("fnh 0; i 0")
+            .unwrap();
+        for cmd in commands {
+            process_command(&mut out, &code, &mut session, cmd).unwrap();
+        }
+        let out = String::from_utf8(out.into_inner()).unwrap();
+        assert!(out.starts_with(&format!("0  : {}", code.functions[0].display_header(&code))));
+        assert!(out.ends_with(&format!("0  : {}\n", code.ints[0])));
+    }
+
+    #[test]
+    fn tags() {
+        let code = sample();
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.child("sample.hl");
+        // Left by an older version of the file
+        std::fs::write(dir.child("sample.hl.tags"), "fn@999999 todo\n").unwrap();
+        let mut session = Session::new(&path, &path).unwrap();
+        session
+            .tags
+            .add_generated(TagTarget::Fun(RefFun(0)), "entry");
+
+        let mut out = NoColor::new(Vec::new());
+        for cmd in [
+            Command::Tag(TagTarget::Fun(RefFun(888888)), "todo".to_owned()),
+            Command::Tag(TagTarget::Fun(RefFun(0)), "todo".to_owned()),
+            Command::Tags(Some("todo".to_owned())),
+        ] {
+            process_command(&mut out, &code, &mut session, cmd).unwrap();
+        }
+        let out = String::from_utf8(out.into_inner()).unwrap();
+        assert_eq!(
+            out,
+            format!(
+                "fn@888888 doesn't exist\n{}\n",
+                RefFun(0).display_header(&code)
+            )
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.child("sample.hl.tags")).unwrap(),
+            "fn@0 todo\nfn@999999 todo\n"
+        );
+    }
+
+    #[cfg(feature = "autotag")]
+    #[test]
+    fn save_without_renames() {
+        use hlbc_analysis::renames::{OriginalNames, RenameKind, RenameMap};
+
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+
+        let mut code = sample();
+        let names = OriginalNames::new(&code);
+        let mut renames = RenameMap::new();
+        renames.insert(RenameKind::Class, "Point", "geom.Point");
+        renames.insert(RenameKind::Function, "length", "norm");
+        assert_eq!(renames.apply(&mut code).missing, []);
+
+        // The bytecode is written from memory, the file it comes from doesn't exist
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.child("sample.hl");
+        let mut session = Session::new(&path, &path).unwrap();
+        session.original_names = Some(names);
+        let mut out = NoColor::new(Vec::new());
+        let saved = dir.child("saved.hl");
+        let cmd = Command::SaveTo(saved.to_string_lossy().into_owned(), None);
+        process_command(&mut out, &code, &mut session, cmd).unwrap();
+        assert_eq!(std::fs::read(&saved).unwrap(), data);
+    }
+}
@@ -0,0 +1,113 @@
+//! Stats mode, opcode frequencies and sizes of a bytecode file, compared with an older build.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use hlbc::stats::{Stats, StatsDiff};
+use hlbc::Bytecode;
+
+#[derive(Debug, clap::Args)]
+pub struct StatsArgs {
+    /// The bytecode file
+    file: PathBuf,
+    /// Print the report as JSON, it can be given to --compare later
+    #[clap(long)]
+    json: bool,
+    /// Number of entries shown in each list
+    #[clap(short, long, default_value = "10")]
+    top: usize,
+    /// Older build to compare with, a bytecode file or a JSON report
+    #[clap(long)]
+    compare: Option<PathBuf>,
+}
+
+/// Load the stats of a bytecode file or a JSON report
+fn load(path: &Path, top: usize) -> anyhow::Result<Stats> {
+    if path.extension().map_or(false, |ext| ext == "json") {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    } else {
+        Ok(Stats::new(&Bytecode::from_file(path)?, top))
+    }
+}
+
+/// The `top` entries with the largest magnitude
+fn biggest<T: Copy>(
+    map: &BTreeMap<String, T>,
+    top: usize,
+    magnitude: fn(T) -> u64,
+) -> Vec<(&str, T)> {
+    let mut entries: Vec<_> = map.iter().map(|(k, &v)| (k.as_str(), v)).collect();
+    entries.sort_by_key(|&(_, v)| std::cmp::Reverse(magnitude(v)));
+    entries.truncate(top);
+    entries
+}
+
+fn print_diff(diff: &StatsDiff, top: usize) {
+    if diff.is_empty() {
+        println!("No changes");
+        return;
+    }
+    for (title, map) in [
+        ("Pools", &diff.pools),
+        ("Opcodes", &diff.opcodes),
+        ("Methods", &diff.methods),
+    ] {
+        if !map.is_empty() {
+            println!("{title} :");
+            for (name, delta) in biggest(map, top, i64::unsigned_abs) {
+                println!("  {name:<30} {delta:+}");
+            }
+        }
+    }
+}
+
+/// Print the stats of a file
+pub fn run(args: &StatsArgs) -> anyhow::Result<()> {
+    let stats = Stats::new(&Bytecode::from_file(&args.file)?, args.top);
+    let old = args
+        .compare
+        .as_ref()
+        .map(|path| load(path, args.top))
+        .transpose()?;
+
+    if args.json {
+        match old {
+            Some(old) => println!("{}", serde_json::to_string_pretty(&stats.compare(&old))?),
+            None => println!("{}", serde_json::to_string_pretty(&stats)?),
+        }
+        return Ok(());
+    }
+    if let (Some(old), Some(path)) = (old, &args.compare) {
+        println!("Changes since {} :", path.display());
+        print_diff(&stats.compare(&old), args.top);
+        return Ok(());
+    }
+
+    println!("Pools :");
+    for (name, size) in stats.pools.entries() {
+        println!("  {name:<30} {size}");
+    }
+    println!("Opcodes :");
+    for (name, count) in biggest(&stats.opcodes, args.top, |v| v as u64) {
+        println!(
+            "  {name:<30} {count:<8} {} bytes",
+            stats.opcode_bytes.get(name).copied().unwrap_or(0)
+        );
+    }
+    println!("Largest functions :");
+    for f in &stats.largest {
+        println!(
+            "  {:<30} {} ops, {} regs, {} bytes",
+            format!("{}@{}", f.name, f.findex),
+            f.ops,
+            f.regs,
+            f.bytes
+        );
+    }
+    println!("Classes with the most methods :");
+    for (name, methods) in biggest(&stats.methods, args.top, |v| v as u64) {
+        println!("  {name:<30} {methods}");
+    }
+    Ok(())
+}
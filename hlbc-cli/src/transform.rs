@@ -0,0 +1,103 @@
+//! Transform mode, rewrites a bytecode file for release. Commands read from and write to the standard streams so they
+//! can be chained : `hlbc strip game.hl | hlbc optimize - -o release.hl`.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use anyhow::bail;
+
+use hlbc::transform::{anonymize, optimize, strip_debug_info};
+use hlbc::Bytecode;
+use hlbc_decompiler::debuginfo::inject_debug_info;
+use hlbc_decompiler::inline::InlineOptions;
+
+#[derive(Debug, clap::Args)]
+pub struct TransformArgs {
+    /// The bytecode file to transform, '-' to read from stdin
+    input: PathBuf,
+    /// Where to write the result, stdout by default
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// The file to merge comes after the base
+#[derive(Debug, clap::Args)]
+pub struct MergeArgs {
+    #[clap(flatten)]
+    pub transform: TransformArgs,
+    /// The bytecode file to merge into the input, like a mod compiled separately
+    pub module: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub enum Transform {
+    Strip,
+    Optimize,
+    Obfuscate,
+    InjectDebug,
+    Gc,
+    /// The file to merge
+    Merge(PathBuf),
+}
+
+/// Apply a transform, the summary is printed to stderr to keep stdout for the bytecode
+pub fn run(transform: Transform, args: &TransformArgs) -> anyhow::Result<()> {
+    if args.output.is_none() && atty::is(atty::Stream::Stdout) {
+        bail!("Refusing to write bytecode to a terminal, use '-o <file>' or redirect the output");
+    }
+    let mut code = if args.input.as_os_str() == "-" {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        Bytecode::from_bytes(&data)?
+    } else {
+        Bytecode::from_file(&args.input)?
+    };
+
+    match transform {
+        Transform::Strip => {
+            let debug = code.has_debug_info();
+            let strings = strip_debug_info(&mut code);
+            if debug {
+                eprintln!("Removed the debug information and {strings} strings");
+            } else {
+                eprintln!("No debug information, removed {strings} strings");
+            }
+        }
+        Transform::Optimize => {
+            let stats = optimize(&mut code);
+            eprintln!(
+                "Removed {} instructions in {} functions",
+                stats.removed, stats.functions
+            );
+        }
+        Transform::Obfuscate => {
+            let stats = anonymize(&mut code);
+            eprintln!(
+                "Renamed {} types and {} enum constructors",
+                stats.types, stats.constructs
+            );
+        }
+        Transform::InjectDebug => {
+            // Functions the decompiler fails on keep their location without variable names
+            let injected = inject_debug_info(&mut code, &InlineOptions::default());
+            eprintln!(
+                "Mapped {} functions to {} files with {} variable names",
+                injected.functions, injected.files, injected.assigns
+            );
+        }
+        Transform::Gc => eprint!("{}", code.gc_pools()),
+        Transform::Merge(module) => {
+            let module = Bytecode::from_file(module)?;
+            eprint!("{}", code.merge(&module)?);
+        }
+    }
+
+    let mut data = Vec::new();
+    code.serialize(&mut data)?;
+    match &args.output {
+        Some(path) => fs::write(path, data)?,
+        None => io::stdout().lock().write_all(&data)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+//! Verify mode, checks a bytecode file after patching or linking and reports the problems found.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use hlbc::types::RefFun;
+use hlbc::verify::verify;
+use hlbc::Bytecode;
+
+/// Severity of a problem
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Warnings found while loading, the file is usable but has likely been modified
+    Warning,
+    /// Invalid references, registers or calls, the file can't be run
+    Error,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct VerifyArgs {
+    /// The bytecode file to check
+    file: PathBuf,
+    /// Only report the problems of this level or above
+    #[clap(short, long, value_enum, default_value = "warning")]
+    level: Level,
+    /// Print the report as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Problem {
+    level: Level,
+    /// Instruction in the function
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pos: Option<usize>,
+    message: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    errors: usize,
+    warnings: usize,
+    /// Problems not related to a function
+    module: Vec<Problem>,
+    /// Problems of each function by findex
+    functions: BTreeMap<usize, Vec<Problem>>,
+}
+
+impl Report {
+    fn new(code: &Bytecode, level: Level) -> Self {
+        let mut report = Report::default();
+        let warnings = code
+            .warnings
+            .iter()
+            .map(|w| (Level::Warning, w.location(), w.to_string()));
+        let errors = verify(code)
+            .into_iter()
+            .map(|e| (Level::Error, e.location(), e.to_string()));
+        for (l, location, message) in warnings.chain(errors) {
+            report.add(level, l, location, message);
+        }
+        report
+    }
+
+    fn add(
+        &mut self,
+        min: Level,
+        level: Level,
+        location: Option<(RefFun, Option<usize>)>,
+        message: String,
+    ) {
+        // Always counted so the exit code doesn't depend on the filter
+        match level {
+            Level::Warning => self.warnings += 1,
+            Level::Error => self.errors += 1,
+        }
+        if level < min {
+            return;
+        }
+        match location {
+            Some((findex, pos)) => self.functions.entry(findex.0).or_default().push(Problem {
+                level,
+                pos,
+                message,
+            }),
+            None => self.module.push(Problem {
+                level,
+                pos: None,
+                message,
+            }),
+        }
+    }
+}
+
+/// Print the report of a file, returns false if it has errors
+pub fn run(args: &VerifyArgs) -> anyhow::Result<bool> {
+    let report = match Bytecode::from_file(&args.file) {
+        Ok(code) => Report::new(&code, args.level),
+        Err(hlbc::Error::InvalidReferences(errors)) => {
+            let mut report = Report::default();
+            for e in errors {
+                report.add(args.level, Level::Error, e.location(), e.to_string());
+            }
+            report
+        }
+        Err(e) => {
+            let mut report = Report::default();
+            report.add(args.level, Level::Error, None, e.to_string());
+            report
+        }
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for p in &report.module {
+            println!("{:?} : {}", p.level, p.message);
+        }
+        for (findex, problems) in &report.functions {
+            println!("fn@{findex}");
+            for p in problems {
+                println!("  {:?} : {}", p.level, p.message);
+            }
+        }
+        println!(
+            "{} : {} errors, {} warnings",
+            args.file.display(),
+            report.errors,
+            report.warnings
+        );
+    }
+    Ok(report.errors == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefInt, Reg};
+    use hlbc::warnings::Warning;
+
+    use crate::verify::{Level, Report};
+
+    #[test]
+    fn report() {
+        let mut code = sample();
+        code.warnings.push(Warning::UnknownFlags(2));
+        let findex = code.functions[0].findex;
+        code.functions[0].ops.insert(
+            0,
+            Opcode::Int {
+                dst: Reg(0),
+                ptr: RefInt(1000),
+            },
+        );
+
+        let report = Report::new(&code, Level::Warning);
+        assert_eq!((report.errors, report.warnings), (1, 1));
+        assert_eq!(report.module.len(), 1);
+        assert_eq!(report.functions[&findex.0][0].pos, Some(0));
+
+        // Filtered out but still counted
+        let report = Report::new(&code, Level::Error);
+        assert!(report.module.is_empty());
+        assert_eq!(report.warnings, 1);
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use hlbc::types::{RefEnumConstruct, RefField, RefFun, RefString, RefType, Reg};
+use hlbc::types::{RefEnumConstruct, RefField, RefFun, RefString, RefType, Reg, Str, Type};
 use hlbc::Bytecode;
 
 #[derive(Debug)]
@@ -23,6 +23,13 @@ pub struct ClassField {
     pub static_: bool,
 }
 
+/// A typedef naming an anonymous structure
+#[derive(Debug)]
+pub struct Typedef {
+    pub name: String,
+    pub fields: Vec<(String, RefType)>,
+}
+
 #[derive(Debug)]
 pub struct Method {
     pub fun: RefFun,
@@ -33,15 +40,17 @@ pub struct Method {
 
 // TODO make this zero copy by accepting the Ref* types instead and only resolving on demand
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Constant {
     Int(i32),
     Float(f64),
-    String(String),
+    String(Str),
     Bool(bool),
     Null,
     /// 'this' instance
     This,
+    /// 'super', the implementation of the parent class
+    Super,
 }
 
 #[derive(Debug, Clone)]
@@ -124,12 +133,14 @@ impl Call {
 /// An expression with a value
 #[derive(Debug, Clone)]
 pub enum Expr {
-    /// An anonymous structure : { field: value }
+    /// An anonymous structure or the literal of a `@:structInit` class : { field: value }
     Anonymous(RefType, HashMap<RefField, Expr>),
     /// Array access : array\[index]
     Array(Box<Expr>, Box<Expr>),
     /// Function call
     Call(Box<Call>),
+    /// Safe cast : cast(expr, Type)
+    Cast(Box<Expr>, RefType),
     /// Constant value
     Constant(Constant),
     /// Constructor call
@@ -138,7 +149,7 @@ pub enum Expr {
     Closure(RefFun, Vec<Statement>),
     EnumConstr(RefType, RefEnumConstruct, Vec<Expr>),
     /// Field access : obj.field
-    Field(Box<Expr>, String),
+    Field(Box<Expr>, Str),
     /// Function reference
     FunRef(RefFun),
     /// If/Else expression, both branches expressions types must unify (https://haxe.org/manual/expression-if.html)
@@ -154,7 +165,7 @@ pub enum Expr {
     // For when there should be something, but we don't known what
     Unknown(String),
     /// Variable identifier
-    Variable(Reg, Option<String>),
+    Variable(Reg, Option<Str>),
 }
 
 pub fn cst_int(cst: i32) -> Expr {
@@ -169,13 +180,12 @@ pub fn cst_bool(cst: bool) -> Expr {
     Expr::Constant(Constant::Bool(cst))
 }
 
-pub fn cst_string(cst: String) -> Expr {
-    Expr::Constant(Constant::String(cst))
+pub fn cst_string(cst: impl Into<Str>) -> Expr {
+    Expr::Constant(Constant::String(cst.into()))
 }
 
-// TODO make an ast node to contain a RefString directly
 pub fn cst_refstring(cst: RefString, code: &Bytecode) -> Expr {
-    cst_string(cst.resolve(&code.strings).to_owned())
+    cst_string(cst.resolve_shared(&code.strings))
 }
 
 pub fn cst_null() -> Expr {
@@ -186,6 +196,10 @@ pub fn cst_this() -> Expr {
     Expr::Constant(Constant::This)
 }
 
+pub fn cst_super() -> Expr {
+    Expr::Constant(Constant::Super)
+}
+
 /// Create a shorthand function to create an expression from an operator
 macro_rules! make_op_shorthand {
     ($name:ident, $op:ident, $( $e:ident ),+) => {
@@ -260,11 +274,16 @@ pub fn call_fun(fun: RefFun, args: Vec<Expr>) -> Expr {
 }
 
 pub fn field(expr: Expr, obj: RefType, field: RefField, code: &Bytecode) -> Expr {
+    let name = match obj.resolve(&code.types) {
+        Type::Virtual { fields } => fields.get(field.0),
+        ty => ty.get_type_obj().and_then(|obj| obj.fields.get(field.0)),
+    };
     Expr::Field(
         Box::new(expr),
-        field
-            .display_obj(obj.resolve(&code.types), code)
-            .to_string(),
+        name.map_or_else(
+            || format!("field{}", field.0).into(),
+            |f| f.name.resolve_shared(&code.strings),
+        ),
     )
 }
 
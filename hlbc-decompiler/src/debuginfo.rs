@@ -0,0 +1,204 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use hlbc::types::{RefFun, RefString, Reg, Str};
+use hlbc::{version, Bytecode};
+use hlbc_analysis::dyntypes::MemberIndex;
+
+use crate::ast::Expr;
+use crate::inline::InlineOptions;
+use crate::post::{visit, AstVisitor};
+use crate::project::decompile_project;
+use crate::{created_closures, decompile_in};
+
+/// Debug file of the functions outside the exported classes, their line is their findex
+pub const UNKNOWN_FILE: &str = "<fn>";
+
+/// Result of [inject_debug_info]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Injected {
+    /// Number of debug files, one per exported class
+    pub files: usize,
+    /// Functions mapped to the declaration of a method
+    pub functions: usize,
+    /// Variable assignments written
+    pub assigns: usize,
+}
+
+/// Names of the registers in the decompiled code, closures excluded
+#[derive(Default)]
+struct Names(HashMap<Reg, Str>);
+
+impl AstVisitor for Names {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        if let Expr::Variable(reg, Some(name)) = expr {
+            self.0.entry(*reg).or_insert_with(|| name.clone());
+        }
+    }
+}
+
+/// Reuse a string of the constant pool or add it
+fn intern(code: &mut Bytecode, s: &str) -> RefString {
+    match code.strings.iter().position(|x| &**x == s) {
+        Some(i) => RefString(i),
+        None => {
+            code.strings.push(s.into());
+            RefString(code.strings.len() - 1)
+        }
+    }
+}
+
+/// Replace the debug information with one pointing to the decompiled output, so stack traces of the patched program
+/// lead to the decompiled sources. The debug files are the paths of the modules of
+/// [decompile_project](crate::project::decompile_project) with the same options, every instruction of a method is on
+/// the line of its declaration as statements don't keep the instructions they come from. Closures are on the line of
+/// the method creating them. Variable assignments use the names of the decompiled code, naming heuristics included.
+pub fn inject_debug_info(code: &mut Bytecode, options: &InlineOptions) -> Injected {
+    let modules = decompile_project(code, options);
+    let mut files: Vec<String> = modules
+        .iter()
+        .map(|m| m.path.to_string_lossy().replace('\\', "/"))
+        .collect();
+    let unknown = files.len();
+    files.push(UNKNOWN_FILE.to_owned());
+
+    let mut lines: HashMap<RefFun, (usize, usize)> = HashMap::new();
+    for (i, m) in modules.iter().enumerate() {
+        for &(f, line) in &m.methods {
+            lines.insert(f, (i, line));
+        }
+    }
+    let mut stack: Vec<RefFun> = lines.keys().copied().collect();
+    while let Some(findex) = stack.pop() {
+        let Some(f) = findex.resolve_as_fn(code) else {
+            continue;
+        };
+        let location = lines[&findex];
+        for fun in created_closures(f) {
+            if let Entry::Vacant(e) = lines.entry(fun) {
+                e.insert(location);
+                stack.push(fun);
+            }
+        }
+    }
+
+    let mut injected = Injected {
+        files: modules.len(),
+        ..Injected::default()
+    };
+    let has_assigns = version::has_assigns(code.version);
+    let members = MemberIndex::new(code);
+    for i in 0..code.functions.len() {
+        let f = &code.functions[i];
+        let location = match lines.get(&f.findex) {
+            Some(&location) => {
+                injected.functions += 1;
+                location
+            }
+            None => (unknown, f.findex.0),
+        };
+        let debug_info = vec![location; f.ops.len()];
+
+        let assigns = if has_assigns {
+            let mut names = Names::default();
+            if let Ok((mut stmts, _)) = decompile_in(code, &members, f, options) {
+                visit(code, &mut stmts, &mut [Box::new(&mut names)]);
+            }
+            let nargs = f.ty(code).args.len();
+            // The nth assignment at 0 names the nth argument
+            let mut assigns: Vec<(String, usize)> = (0..nargs)
+                .map(|a| {
+                    let name = f
+                        .arg_name(code, a)
+                        .map(str::to_owned)
+                        .or_else(|| names.0.get(&Reg(a as u32)).map(|n| n.to_string()))
+                        .unwrap_or_else(|| format!("reg{a}"));
+                    (name, 0)
+                })
+                .collect();
+            for (pos, o) in f.ops.iter().enumerate() {
+                if let Some(name) = o
+                    .dst()
+                    .filter(|r| r.0 as usize >= nargs)
+                    .and_then(|r| names.0.get(&r))
+                {
+                    assigns.push((name.to_string(), pos + 1));
+                }
+            }
+            Some(assigns)
+        } else {
+            None
+        };
+
+        let assigns = assigns.map(|assigns| {
+            injected.assigns += assigns.len();
+            assigns
+                .into_iter()
+                .map(|(name, pos)| (intern(code, &name), pos))
+                .collect()
+        });
+        let f = &mut code.functions[i];
+        f.debug_info = Some(debug_info);
+        f.assigns = assigns;
+    }
+    code.debug_files = Some(files);
+    injected
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::BytecodeBuilder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+    use hlbc::Bytecode;
+
+    use crate::debuginfo::{inject_debug_info, UNKNOWN_FILE};
+    use crate::inline::InlineOptions;
+
+    #[test]
+    fn inject() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let (update, closure) = (b.findex(), b.findex());
+        let player = b.class("game.Player", None, &[], &[("update", update)]);
+        let method = b.method_type(&[player], void);
+        let dyn_ = b.ty(Type::Dyn);
+        b.function(
+            update,
+            method,
+            vec![player, dyn_, void],
+            vec![
+                Opcode::StaticClosure {
+                    dst: Reg(1),
+                    fun: closure,
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        let ty = b.fun_type(&[], void);
+        b.function(closure, ty, vec![void], vec![Opcode::Ret { ret: Reg(0) }]);
+        let other = b.findex();
+        b.function(other, ty, vec![void], vec![Opcode::Ret { ret: Reg(0) }]);
+        let mut code = b.build().unwrap();
+        assert!(!code.has_debug_info());
+
+        let injected = inject_debug_info(&mut code, &InlineOptions::default());
+        assert_eq!((injected.files, injected.functions), (1, 2));
+        let files = code.debug_files.as_ref().unwrap();
+        assert_eq!(files, &["game/Player.hx", UNKNOWN_FILE]);
+        let (file, line) = code.functions[0].debug_info.as_ref().unwrap()[0];
+        assert_eq!(file, 0);
+        assert_eq!(code.functions[1].debug_info.as_ref().unwrap()[0], (0, line));
+        assert_eq!(
+            code.functions[2].debug_info.as_ref().unwrap()[0],
+            (1, other.0)
+        );
+        assert_eq!(code.functions[0].arg_name(&code, 0), Some("reg0"));
+
+        // The file is still valid
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let code = Bytecode::from_bytes(&data).unwrap();
+        assert!(code.has_debug_info());
+    }
+}
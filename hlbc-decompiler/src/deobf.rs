@@ -0,0 +1,755 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use hlbc::opcodes::{JumpOffset, Opcode};
+use hlbc::types::Function;
+use hlbc::Bytecode;
+use hlbc_analysis::constprop::ConstProp;
+
+/// What the deobfuscation transforms did to a function
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Positions of the conditional jumps that are always taken, now unconditional
+    pub always_taken: Vec<usize>,
+    /// Positions of the conditional jumps that are never taken, now removed
+    pub never_taken: Vec<usize>,
+    /// Number of unreachable instructions removed
+    pub unreachable: usize,
+    /// The control flow has been unflattened
+    pub unflattened: bool,
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.always_taken.is_empty()
+            && self.never_taken.is_empty()
+            && self.unreachable == 0
+            && !self.unflattened
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for pos in &self.always_taken {
+            writeln!(f, "{pos}: conditional jump always taken")?;
+        }
+        for pos in &self.never_taken {
+            writeln!(f, "{pos}: conditional jump never taken")?;
+        }
+        if self.unreachable > 0 {
+            writeln!(f, "{} unreachable instructions removed", self.unreachable)?;
+        }
+        if self.unflattened {
+            writeln!(f, "control flow unflattened")?;
+        }
+        Ok(())
+    }
+}
+
+/// Apply every deobfuscation transform on a function, control flow flattening is only undone with `unflatten`.
+/// Positions in the report refer to the original function.
+///
+/// Returns `None` if there was nothing to do.
+pub fn deobfuscate(code: &Bytecode, f: &Function, unflatten: bool) -> Option<(Function, Report)> {
+    let mut ops = f.ops.clone();
+    let mut report = Report::default();
+    remove_opaque_predicates(code, f, &mut ops, &mut report);
+    if unflatten {
+        report.unflattened = unflatten_all(code, &mut ops);
+    }
+    if report.is_empty() {
+        None
+    } else {
+        // Only move code around when the original layout is meaningless
+        Some((relayout(f, ops, report.unflattened), report))
+    }
+}
+
+/// Remove conditional jumps whose outcome is known with constant propagation,
+/// often inserted by obfuscators to make the control flow harder to follow.
+fn remove_opaque_predicates(
+    code: &Bytecode,
+    f: &Function,
+    ops: &mut [Opcode],
+    report: &mut Report,
+) {
+    let cp = ConstProp::new(code, f);
+    for (i, o) in ops.iter_mut().enumerate() {
+        if !cp.is_reachable(i) || matches!(o, Opcode::JAlways { .. } | Opcode::Trap { .. }) {
+            continue;
+        }
+        match cp.branch(code, f, i) {
+            Some(true) => {
+                *o = Opcode::JAlways {
+                    offset: o.jump_offset().unwrap(),
+                };
+                report.always_taken.push(i);
+            }
+            Some(false) => {
+                *o = Opcode::Nop;
+                report.never_taken.push(i);
+            }
+            None => {}
+        }
+    }
+    if report.always_taken.is_empty() && report.never_taken.is_empty() {
+        return;
+    }
+
+    // Remove the code we can't reach anymore.
+    // The compiler also generates unreachable code but the decompiler needs it.
+    let reachable = reachable(&f.ops);
+    for (i, o) in ops.iter_mut().enumerate() {
+        if reachable[i] && !cp.is_reachable(i) && !matches!(o, Opcode::Nop) {
+            *o = Opcode::Nop;
+            report.unreachable += 1;
+        }
+    }
+}
+
+/// Instructions reachable from the start of the function
+fn reachable(ops: &[Opcode]) -> Vec<bool> {
+    let mut reachable = vec![false; ops.len()];
+    let mut worklist = vec![0];
+    while let Some(i) = worklist.pop() {
+        if i >= ops.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        if !ops[i].is_terminator() {
+            worklist.push(i + 1);
+        }
+        worklist.extend(ops[i].jump_targets(i));
+    }
+    reachable
+}
+
+/// Undo control flow flattening.
+///
+/// A flattened function looks like this :
+/// ```text
+/// state = 0
+/// loop {
+///     switch state {
+///         0 => { ...; state = 2 }
+///         1 => { ...; return }
+///         2 => { ...; state = 1 }
+///     }
+/// }
+/// ```
+/// Each jump back to the dispatcher is preceded by a constant assignment to the state variable,
+/// so we can jump directly to the next case instead. The blocks are then laid out again
+/// so the decompiler can find the original structure. Every switch is tried as a dispatcher.
+///
+/// Returns `false` if no switch matches this pattern.
+fn unflatten_all(code: &Bytecode, ops: &mut [Opcode]) -> bool {
+    let switches: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, o)| matches!(o, Opcode::Switch { .. }))
+        .map(|(i, _)| i)
+        .collect();
+    let mut unflattened = false;
+    for switch_pos in switches {
+        unflattened |= unflatten(code, ops, switch_pos).is_some();
+    }
+    unflattened
+}
+
+/// Unflatten the dispatcher of the switch at `switch_pos`. The dispatcher must only be entered from the initial
+/// assignment of the state before it and from the jumps back to it, each just after a constant assignment.
+fn unflatten(code: &Bytecode, ops: &mut [Opcode], switch_pos: usize) -> Option<()> {
+    let Opcode::Switch {
+        reg: state,
+        offsets,
+        ..
+    } = &ops[switch_pos]
+    else {
+        return None;
+    };
+    let (state, offsets) = (*state, offsets.clone());
+    let case_target = |value: i32| -> usize {
+        if value >= 0 && (value as usize) < offsets.len() {
+            (switch_pos as i32 + offsets[value as usize] + 1) as usize
+        } else {
+            // The default case is just after the switch
+            switch_pos + 1
+        }
+    };
+
+    // The dispatcher starts at the loop head, only labels can be before the switch
+    let mut head = switch_pos;
+    while head > 0 && matches!(ops[head - 1], Opcode::Label) {
+        head -= 1;
+    }
+
+    let state_value = |pos: usize| -> Option<i32> {
+        match ops.get(pos.checked_sub(1)?)? {
+            Opcode::Int { dst, ptr } if *dst == state => Some(ptr.resolve(&code.ints)),
+            _ => None,
+        }
+    };
+
+    // Every jump back to the dispatcher must set the state to a known value, any other jump into it would be
+    // redirected to the initial case
+    let mut back_jumps = Vec::new();
+    for (i, o) in ops.iter().enumerate() {
+        if o.jump_targets(i)
+            .iter()
+            .any(|t| (head..=switch_pos).contains(t))
+        {
+            if i <= switch_pos || !matches!(o, Opcode::JAlways { .. }) {
+                return None;
+            }
+            back_jumps.push((i, state_value(i)?));
+        }
+    }
+    if back_jumps.is_empty() {
+        return None;
+    }
+    let initial = state_value(head)?;
+    // Jumping between the assignment and the jump would skip the assignment
+    for (i, o) in ops.iter().enumerate() {
+        if o.jump_targets(i)
+            .iter()
+            .any(|t| back_jumps.iter().any(|(j, _)| t == j))
+        {
+            return None;
+        }
+    }
+
+    for (i, value) in back_jumps {
+        ops[i] = Opcode::JAlways {
+            offset: case_target(value) as JumpOffset - i as JumpOffset - 1,
+        };
+    }
+    ops[switch_pos] = Opcode::JAlways {
+        offset: case_target(initial) as JumpOffset - switch_pos as JumpOffset - 1,
+    };
+    // The loop is gone
+    for o in &mut ops[head..switch_pos] {
+        *o = Opcode::Nop;
+    }
+
+    Some(())
+}
+
+/// Remove nops and useless jumps then fix jump offsets.
+/// With `reorder`, the basic blocks are first ordered so that most jumps become fallthroughs.
+fn relayout(f: &Function, ops: Vec<Opcode>, reorder: bool) -> Function {
+    // Split in basic blocks
+    let mut leaders = BTreeSet::from([0]);
+    for (i, o) in ops.iter().enumerate() {
+        let t = o.jump_targets(i);
+        if !t.is_empty() || o.is_terminator() {
+            leaders.insert(i + 1);
+        }
+        leaders.extend(t);
+    }
+    leaders.retain(|&l| l < ops.len());
+    let leaders: Vec<usize> = leaders.into_iter().collect();
+    let block_of = |pos: usize| leaders.partition_point(|&l| l <= pos) - 1;
+    let block_end = |b: usize| leaders.get(b + 1).copied().unwrap_or(ops.len());
+
+    // Follow the fallthrough and unconditional jumps first
+    let mut order = Vec::with_capacity(leaders.len());
+    let mut placed = HashSet::new();
+    let mut stack = if reorder { vec![0] } else { Vec::new() };
+    while let Some(mut b) = stack.pop() {
+        while placed.insert(b) {
+            order.push(b);
+            let last = block_end(b) - 1;
+            let mut t = ops[last].jump_targets(last);
+            let next = if let Opcode::JAlways { .. } = ops[last] {
+                t.pop()
+            } else if ops[last].is_terminator() || last + 1 >= ops.len() {
+                None
+            } else {
+                Some(last + 1)
+            };
+            stack.extend(t.into_iter().rev().map(block_of));
+            match next {
+                Some(next) => b = block_of(next),
+                None => break,
+            }
+        }
+    }
+    // Unreachable blocks are kept at the end
+    order.extend((0..leaders.len()).filter(|b| !placed.contains(b)));
+
+    let rank: HashMap<usize, usize> = order.iter().enumerate().map(|(r, &b)| (b, r)).collect();
+    // Blocks jumped to from below need a label so the decompiler knows it's a loop
+    let mut loop_heads = HashSet::new();
+    for &b in &order {
+        let last = block_end(b) - 1;
+        let mut t = ops[last].jump_targets(last);
+        if !ops[last].is_terminator() && last + 1 < ops.len() {
+            t.push(last + 1);
+        }
+        for t in t {
+            if rank[&block_of(t)] <= rank[&b] && !matches!(ops[t], Opcode::Label) {
+                loop_heads.insert(block_of(t));
+            }
+        }
+    }
+
+    // Returns true if execution goes from `from` to `to` by only going through nops in the new order
+    let seq: Vec<usize> = order
+        .iter()
+        .flat_map(|&b| leaders[b]..block_end(b))
+        .collect();
+    let mut seq_pos = vec![0; ops.len()];
+    for (k, &i) in seq.iter().enumerate() {
+        seq_pos[i] = k;
+    }
+    let falls_into = |from: usize, to: usize| {
+        seq_pos[to] > seq_pos[from]
+            && seq[seq_pos[from] + 1..seq_pos[to]]
+                .iter()
+                .all(|&i| matches!(ops[i], Opcode::Nop))
+    };
+
+    // Emit the instructions, each new instruction remembers the old position of its target
+    let mut new_ops: Vec<(Opcode, Option<usize>, Option<Vec<usize>>)> = Vec::new();
+    let mut debug_info = f.debug_info.as_ref().map(|_| Vec::new());
+    let mut map = vec![0; ops.len()];
+    // Old positions waiting for the next emitted instruction
+    let mut pending = Vec::new();
+    for &b in &order {
+        let start = leaders[b];
+        let end = block_end(b);
+        let loop_head = loop_heads.contains(&b);
+        if loop_head {
+            for p in pending.drain(..).chain(std::iter::once(start)) {
+                map[p] = new_ops.len();
+            }
+            new_ops.push((Opcode::Label, None, None));
+            if let (Some(di), Some(old)) = (&mut debug_info, &f.debug_info) {
+                di.push(old[start]);
+            }
+        }
+        for i in start..end {
+            if !(loop_head && i == start) {
+                pending.push(i);
+            }
+            let o = &ops[i];
+            let skip = match o {
+                Opcode::Nop => true,
+                Opcode::JAlways { .. } => falls_into(i, o.jump_target(i).unwrap()),
+                _ => false,
+            };
+            if skip {
+                continue;
+            }
+            for p in pending.drain(..) {
+                map[p] = new_ops.len();
+            }
+            let t = o.jump_target(i);
+            let switch = match o {
+                Opcode::Switch { .. } => Some(o.jump_targets(i)),
+                _ => None,
+            };
+            new_ops.push((o.clone(), t, switch));
+            if let (Some(di), Some(old)) = (&mut debug_info, &f.debug_info) {
+                di.push(old[i]);
+            }
+        }
+        // Restore the fallthrough if the next block isn't the one we fall into
+        let last = end - 1;
+        if !ops[last].is_terminator() && end < ops.len() && !falls_into(last, end) {
+            for p in pending.drain(..) {
+                map[p] = new_ops.len();
+            }
+            new_ops.push((Opcode::JAlways { offset: 0 }, Some(end), None));
+            if let (Some(di), Some(old)) = (&mut debug_info, &f.debug_info) {
+                di.push(old[last]);
+            }
+        }
+    }
+    for p in pending {
+        map[p] = new_ops.len().saturating_sub(1);
+    }
+
+    let new_ops = new_ops
+        .into_iter()
+        .enumerate()
+        .map(|(i, (mut o, target, switch))| {
+            let rel = |t: usize| map[t] as JumpOffset - i as JumpOffset - 1;
+            if let Some(t) = target {
+                if let Some(offset) = o.jump_offset_mut() {
+                    *offset = rel(t);
+                }
+            }
+            if let (Opcode::Switch { offsets, end, .. }, Some(t)) = (&mut o, switch) {
+                for (offset, &t) in offsets.iter_mut().zip(&t) {
+                    *offset = rel(t);
+                }
+                *end = rel(t[t.len() - 1]);
+            }
+            o
+        })
+        .collect();
+
+    Function {
+        ops: new_ops,
+        debug_info,
+        assigns: f.assigns.as_ref().map(|assigns| {
+            assigns
+                .iter()
+                .map(|&(name, pos)| (name, map[pos.saturating_sub(1).min(map.len() - 1)] + 1))
+                .collect()
+        }),
+        ..f.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefFun, RefType, Type};
+    use hlbc::Bytecode;
+    use hlbc_analysis::eval::{eval, Value};
+
+    use crate::deobf::deobfuscate;
+
+    /// A function taking and returning an int `x`, `body` gets the builder for the constants and the int type
+    fn build(
+        body: impl FnOnce(&mut BytecodeBuilder, &mut FunctionBuilder, RefType),
+    ) -> (Bytecode, RefFun) {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let ty = b.fun_type(&[i32_], i32_);
+        let findex = b.findex();
+        let mut f = FunctionBuilder::new(ty, &[i32_]);
+        body(&mut b, &mut f, i32_);
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(findex, ty, regs, ops);
+        (b.build().unwrap(), findex)
+    }
+
+    /// Deobfuscate the function and check it computes the same results
+    fn unflatten(code: &Bytecode, findex: RefFun, inputs: &[i32]) -> Option<Vec<Opcode>> {
+        let (f, report) = deobfuscate(code, findex.resolve_as_fn(code).unwrap(), true)?;
+        let mut deobfuscated = code.clone();
+        deobfuscated.functions[0] = f;
+        for &x in inputs {
+            assert_eq!(
+                eval(&deobfuscated, findex, vec![Value::Int(x)]),
+                eval(code, findex, vec![Value::Int(x)])
+            );
+        }
+        report
+            .unflattened
+            .then(|| deobfuscated.functions[0].ops.clone())
+    }
+
+    fn has_switch(ops: &[Opcode]) -> bool {
+        ops.iter().any(|o| matches!(o, Opcode::Switch { .. }))
+    }
+
+    /// ```text
+    /// 0: one = 1
+    /// 1: two = 2
+    /// 2: if one >= two goto 4
+    /// 3: if one < two goto 6
+    /// 4: x = 100
+    /// 5: ret x
+    /// 6: x++
+    /// 7: ret x
+    /// ```
+    #[test]
+    fn opaque_predicates() {
+        let (code, findex) = build(|b, f, i32_| {
+            let x = f.arg(0);
+            let [one, two] = [(); 2].map(|_| f.reg(i32_));
+            let [fake, real] = [(); 2].map(|_| f.label());
+            f.emit(Opcode::Int {
+                dst: one,
+                ptr: b.int(1),
+            })
+            .emit(Opcode::Int {
+                dst: two,
+                ptr: b.int(2),
+            })
+            .jump(
+                Opcode::JSGte {
+                    a: one,
+                    b: two,
+                    offset: 0,
+                },
+                fake,
+            )
+            .jump(
+                Opcode::JSLt {
+                    a: one,
+                    b: two,
+                    offset: 0,
+                },
+                real,
+            )
+            .place(fake)
+            .emit(Opcode::Int {
+                dst: x,
+                ptr: b.int(100),
+            })
+            .emit(Opcode::Ret { ret: x })
+            .place(real)
+            .emit(Opcode::Incr { dst: x })
+            .emit(Opcode::Ret { ret: x });
+        });
+        let f = findex.resolve_as_fn(&code).unwrap();
+        let (deobfuscated, report) = deobfuscate(&code, f, true).unwrap();
+        assert_eq!(report.never_taken, [2]);
+        assert_eq!(report.always_taken, [3]);
+        assert_eq!(report.unreachable, 2);
+        let mut result = code.clone();
+        result.functions[0] = deobfuscated;
+        assert_eq!(
+            eval(&result, findex, vec![Value::Int(3)]),
+            Ok(Value::Int(4))
+        );
+        assert!(!result.functions[0]
+            .ops
+            .iter()
+            .any(|o| matches!(o, Opcode::JSLt { .. } | Opcode::JSGte { .. })));
+    }
+
+    /// Conditions that only look constant : a float compared with itself, a value written through a reference, a
+    /// value changed in a try block seen from the catch
+    #[test]
+    fn opaque_lookalikes() {
+        let (code, findex) = build(|b, f, i32_| {
+            let x = f.arg(0);
+            let f64_ = b.ty(Type::F64);
+            let ref_t = b.ty(Type::Ref(i32_));
+            let dyn_ = b.ty(Type::Dyn);
+            let (d, r, exc) = (f.reg(f64_), f.reg(ref_t), f.reg(dyn_));
+            let [one, y, k] = [(); 3].map(|_| f.reg(i32_));
+            let [nan, escaped, catch, end] = [(); 4].map(|_| f.label());
+            f.emit(Opcode::ToSFloat { dst: d, src: x })
+                .jump(
+                    Opcode::JNotEq {
+                        a: d,
+                        b: d,
+                        offset: 0,
+                    },
+                    nan,
+                )
+                .place(nan)
+                .emit(Opcode::Int {
+                    dst: one,
+                    ptr: b.int(1),
+                })
+                .emit(Opcode::Int {
+                    dst: y,
+                    ptr: b.int(1),
+                })
+                .emit(Opcode::Ref { dst: r, src: y })
+                .emit(Opcode::Setref { dst: r, value: x })
+                .jump(
+                    Opcode::JEq {
+                        a: y,
+                        b: one,
+                        offset: 0,
+                    },
+                    escaped,
+                )
+                .place(escaped)
+                .emit(Opcode::Int {
+                    dst: k,
+                    ptr: b.int(1),
+                })
+                .jump(Opcode::Trap { exc, offset: 0 }, catch)
+                .emit(Opcode::Int {
+                    dst: one,
+                    ptr: b.int(2),
+                })
+                .emit(Opcode::EndTrap { exc })
+                .emit(Opcode::Ret { ret: one })
+                .place(catch)
+                .jump(
+                    Opcode::JEq {
+                        a: one,
+                        b: k,
+                        offset: 0,
+                    },
+                    end,
+                )
+                .place(end)
+                .emit(Opcode::Ret { ret: x });
+        });
+        let f = findex.resolve_as_fn(&code).unwrap();
+        assert!(deobfuscate(&code, f, true).is_none());
+    }
+
+    /// `while x < 10 { x++ }` driven by a state machine
+    fn flattened_loop() -> (Bytecode, RefFun) {
+        build(|b, f, i32_| {
+            let x = f.arg(0);
+            let [state, one, ten] = [(); 3].map(|_| f.reg(i32_));
+            let [c0, c1, c2, again, end, dispatch] = [(); 6].map(|_| f.label());
+            let [s0, s1, s2] = [0, 1, 2].map(|v| b.int(v));
+            f.emit(Opcode::Int { dst: one, ptr: s1 })
+                .emit(Opcode::Int {
+                    dst: ten,
+                    ptr: b.int(10),
+                })
+                .emit(Opcode::Int {
+                    dst: state,
+                    ptr: s0,
+                })
+                .place(dispatch)
+                .emit(Opcode::Label)
+                .switch(state, &[c0, c1, c2], end)
+                .place(end)
+                .emit(Opcode::Ret { ret: x })
+                .place(c0)
+                .emit(Opcode::Add {
+                    dst: x,
+                    a: x,
+                    b: one,
+                })
+                .emit(Opcode::Int {
+                    dst: state,
+                    ptr: s1,
+                })
+                .jump(Opcode::JAlways { offset: 0 }, dispatch)
+                .place(c1)
+                .jump(
+                    Opcode::JSLt {
+                        a: x,
+                        b: ten,
+                        offset: 0,
+                    },
+                    again,
+                )
+                .emit(Opcode::Int {
+                    dst: state,
+                    ptr: s2,
+                })
+                .jump(Opcode::JAlways { offset: 0 }, dispatch)
+                .place(again)
+                .emit(Opcode::Int {
+                    dst: state,
+                    ptr: s0,
+                })
+                .jump(Opcode::JAlways { offset: 0 }, dispatch)
+                .place(c2)
+                .emit(Opcode::Ret { ret: x });
+        })
+    }
+
+    #[test]
+    fn flattened() {
+        let (code, findex) = flattened_loop();
+        assert_eq!(eval(&code, findex, vec![Value::Int(3)]), Ok(Value::Int(10)));
+        let ops = unflatten(&code, findex, &[3, 12]).unwrap();
+        assert!(!has_switch(&ops));
+        // The loop is found again
+        assert!(ops.iter().any(|o| matches!(o, Opcode::Label)));
+        // Opt-out
+        let f = findex.resolve_as_fn(&code).unwrap();
+        assert!(deobfuscate(&code, f, false).is_none());
+    }
+
+    #[test]
+    fn two_dispatchers() {
+        let (code, findex) = build(|b, f, i32_| {
+            let x = f.arg(0);
+            let [a, bs, one] = [(); 3].map(|_| f.reg(i32_));
+            let [a0, a1, a_end, a_dispatch] = [(); 4].map(|_| f.label());
+            let [b0, b1, b_end, b_dispatch, second] = [(); 5].map(|_| f.label());
+            let [s0, s1] = [0, 1].map(|v| b.int(v));
+            // x + 1
+            f.emit(Opcode::Int { dst: one, ptr: s1 })
+                .emit(Opcode::Int { dst: a, ptr: s0 })
+                .place(a_dispatch)
+                .emit(Opcode::Label)
+                .switch(a, &[a0, a1], a_end)
+                .place(a_end)
+                .jump(Opcode::JAlways { offset: 0 }, second)
+                .place(a0)
+                .emit(Opcode::Add {
+                    dst: x,
+                    a: x,
+                    b: one,
+                })
+                .emit(Opcode::Int { dst: a, ptr: s1 })
+                .jump(Opcode::JAlways { offset: 0 }, a_dispatch)
+                .place(a1)
+                .jump(Opcode::JAlways { offset: 0 }, second);
+            // Then doubled
+            f.place(second)
+                .emit(Opcode::Int { dst: bs, ptr: s1 })
+                .place(b_dispatch)
+                .emit(Opcode::Label)
+                .switch(bs, &[b0, b1], b_end)
+                .place(b_end)
+                .emit(Opcode::Ret { ret: x })
+                .place(b0)
+                .emit(Opcode::Ret { ret: x })
+                .place(b1)
+                .emit(Opcode::Add { dst: x, a: x, b: x })
+                .emit(Opcode::Int { dst: bs, ptr: s0 })
+                .jump(Opcode::JAlways { offset: 0 }, b_dispatch);
+        });
+        assert_eq!(eval(&code, findex, vec![Value::Int(3)]), Ok(Value::Int(8)));
+        let ops = unflatten(&code, findex, &[3, -1]).unwrap();
+        assert!(!has_switch(&ops));
+    }
+
+    #[test]
+    fn outside_jump() {
+        let (code, findex) = build(|b, f, i32_| {
+            let x = f.arg(0);
+            let [state, one, ten] = [(); 3].map(|_| f.reg(i32_));
+            let [c0, c1, end, dispatch] = [(); 4].map(|_| f.label());
+            let [s0, s1] = [0, 1].map(|v| b.int(v));
+            f.emit(Opcode::Int { dst: one, ptr: s1 })
+                .emit(Opcode::Int {
+                    dst: ten,
+                    ptr: b.int(10),
+                })
+                .emit(Opcode::Int {
+                    dst: state,
+                    ptr: s0,
+                })
+                // Enters the dispatcher with the state 0
+                .jump(
+                    Opcode::JSLt {
+                        a: x,
+                        b: ten,
+                        offset: 0,
+                    },
+                    dispatch,
+                )
+                .emit(Opcode::Int {
+                    dst: state,
+                    ptr: s1,
+                })
+                .place(dispatch)
+                .emit(Opcode::Label)
+                .switch(state, &[c0, c1], end)
+                .place(end)
+                .emit(Opcode::Ret { ret: x })
+                .place(c0)
+                .emit(Opcode::Add {
+                    dst: x,
+                    a: x,
+                    b: one,
+                })
+                .emit(Opcode::Int {
+                    dst: state,
+                    ptr: s1,
+                })
+                .jump(Opcode::JAlways { offset: 0 }, dispatch)
+                .place(c1)
+                .emit(Opcode::Ret { ret: x });
+        });
+        assert_eq!(eval(&code, findex, vec![Value::Int(3)]), Ok(Value::Int(4)));
+        let f = findex.resolve_as_fn(&code).unwrap();
+        assert!(deobfuscate(&code, f, true).is_none());
+    }
+}
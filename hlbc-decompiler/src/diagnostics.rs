@@ -0,0 +1,56 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use hlbc::types::RefFun;
+
+/// What the decompiler had to work around
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum DiagnosticKind {
+    /// The instruction is decompiled, but the output might not match the control flow of the bytecode
+    Warning,
+    /// A value couldn't be recovered, a placeholder is displayed in its place
+    Fallback,
+    /// The instruction has no equivalent in the output
+    UnsupportedOpcode,
+}
+
+impl Display for DiagnosticKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DiagnosticKind::Warning => "warning",
+            DiagnosticKind::Fallback => "fallback",
+            DiagnosticKind::UnsupportedOpcode => "unsupported opcode",
+        })
+    }
+}
+
+/// A problem found while decompiling an instruction. The output is still produced but may be incomplete around it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub findex: RefFun,
+    /// Position of the instruction in the function
+    pub pos: usize,
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Text of the output marking the affected statement : the placeholder of a fallback, the comment inserted
+    /// before the statement otherwise.
+    pub fn marker(&self) -> String {
+        match self.kind {
+            DiagnosticKind::Fallback => format!("[{}]", self.message),
+            _ => format!("// {} : {}", self.kind, self.message),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fn@{} at {} : {} : {}",
+            self.findex.0, self.pos, self.kind, self.message
+        )
+    }
+}
@@ -0,0 +1,242 @@
+//! Differences between the decompiled sources of two versions of a function.
+//!
+//! Opcode differences are noisy : a register renumbered or a constant moved in the pool changes many instructions
+//! without changing the behavior. Comparing the decompiled code shows what actually changed. Lines are compared with
+//! a longest common subsequence, then a line removed in one place and added in another (ignoring the indentation) is
+//! marked as moved instead.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use hlbc::types::Function;
+use hlbc::Bytecode;
+
+use crate::fmt::FormatOptions;
+use crate::inline::InlineOptions;
+use crate::{decompile_function_with, Result};
+
+/// What happened to a line between the two versions
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LineChange {
+    Unchanged,
+    Added,
+    Removed,
+    /// The line was removed here and is added somewhere else
+    MovedFrom,
+    /// The line was removed somewhere else and is added here
+    MovedTo,
+}
+
+impl LineChange {
+    /// The line exists in the old version
+    pub fn in_old(self) -> bool {
+        matches!(self, Self::Unchanged | Self::Removed | Self::MovedFrom)
+    }
+
+    /// The line exists in the new version
+    pub fn in_new(self) -> bool {
+        matches!(self, Self::Unchanged | Self::Added | Self::MovedTo)
+    }
+
+    /// Prefix of the line in a unified diff, moved lines use `<` and `>`
+    pub fn prefix(self) -> char {
+        match self {
+            Self::Unchanged => ' ',
+            Self::Added => '+',
+            Self::Removed => '-',
+            Self::MovedFrom => '<',
+            Self::MovedTo => '>',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DiffLine {
+    pub change: LineChange,
+    pub text: String,
+}
+
+/// Lines of both versions, in the order of a unified diff
+#[derive(Debug, Clone, Default)]
+pub struct SourceDiff {
+    pub lines: Vec<DiffLine>,
+}
+
+impl SourceDiff {
+    /// Compare two texts line by line
+    pub fn new(old: &str, new: &str) -> Self {
+        let old: Vec<&str> = old.lines().collect();
+        let new: Vec<&str> = new.lines().collect();
+        let mut lines = Vec::with_capacity(old.len().max(new.len()));
+        let line = |change, text: &str| DiffLine {
+            change,
+            text: text.to_owned(),
+        };
+
+        // Only the middle part differs most of the time, the table is only built for it
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let (a, b) = (
+            &old[prefix..old.len() - suffix],
+            &new[prefix..new.len() - suffix],
+        );
+        // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+        let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i][j] = if a[i] == b[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+
+        lines.extend(old[..prefix].iter().map(|l| line(LineChange::Unchanged, l)));
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                lines.push(line(LineChange::Unchanged, a[i]));
+                i += 1;
+                j += 1;
+            } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+                lines.push(line(LineChange::Removed, a[i]));
+                i += 1;
+            } else {
+                lines.push(line(LineChange::Added, b[j]));
+                j += 1;
+            }
+        }
+        lines.extend(
+            old[old.len() - suffix..]
+                .iter()
+                .map(|l| line(LineChange::Unchanged, l)),
+        );
+
+        let mut diff = Self { lines };
+        diff.find_moves();
+        diff
+    }
+
+    /// Pair the removed and added lines with the same content
+    fn find_moves(&mut self) {
+        // Braces and blank lines are everywhere, they don't tell anything moved
+        let significant = |text: &str| text.trim().chars().any(char::is_alphanumeric);
+        let mut removed: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, l) in self.lines.iter().enumerate() {
+            if l.change == LineChange::Removed && significant(&l.text) {
+                removed.entry(l.text.trim()).or_default().push(i);
+            }
+        }
+        let mut moves = Vec::new();
+        for (i, l) in self.lines.iter().enumerate() {
+            if l.change == LineChange::Added {
+                if let Some(from) = removed.get_mut(l.text.trim()).and_then(|r| r.pop()) {
+                    moves.push((from, i));
+                }
+            }
+        }
+        for (from, to) in moves {
+            self.lines[from].change = LineChange::MovedFrom;
+            self.lines[to].change = LineChange::MovedTo;
+        }
+    }
+
+    /// True if both versions are the same
+    pub fn is_empty(&self) -> bool {
+        self.lines.iter().all(|l| l.change == LineChange::Unchanged)
+    }
+
+    /// Number of lines with this change
+    pub fn count(&self, change: LineChange) -> usize {
+        self.lines.iter().filter(|l| l.change == change).count()
+    }
+
+    /// Render as a unified diff with `context` unchanged lines around the changes
+    pub fn unified(&self, old_name: &str, new_name: &str, context: usize) -> String {
+        let mut out = String::new();
+        if self.is_empty() {
+            return out;
+        }
+        let _ = writeln!(out, "--- {old_name}\n+++ {new_name}");
+
+        // Ranges of lines shown in each hunk, changes closer than twice the context are in the same hunk
+        let mut hunks: Vec<(usize, usize)> = Vec::new();
+        for (i, l) in self.lines.iter().enumerate() {
+            if l.change == LineChange::Unchanged {
+                continue;
+            }
+            let start = i.saturating_sub(context);
+            let end = (i + context + 1).min(self.lines.len());
+            match hunks.last_mut() {
+                Some(last) if start <= last.1 => last.1 = end,
+                _ => hunks.push((start, end)),
+            }
+        }
+
+        for (start, end) in hunks {
+            // Line numbers of both versions at the start of the hunk, starting at 1
+            let before = &self.lines[..start];
+            let old_start = before.iter().filter(|l| l.change.in_old()).count() + 1;
+            let new_start = before.iter().filter(|l| l.change.in_new()).count() + 1;
+            let hunk = &self.lines[start..end];
+            let _ = writeln!(
+                out,
+                "@@ -{},{} +{},{} @@",
+                old_start,
+                hunk.iter().filter(|l| l.change.in_old()).count(),
+                new_start,
+                hunk.iter().filter(|l| l.change.in_new()).count()
+            );
+            for l in hunk {
+                let _ = writeln!(out, "{}{}", l.change.prefix(), l.text);
+            }
+        }
+        out
+    }
+}
+
+/// Decompile both versions of a function and compare them
+pub fn diff_function(
+    old_code: &Bytecode,
+    old: &Function,
+    new_code: &Bytecode,
+    new: &Function,
+    options: &InlineOptions,
+) -> Result<SourceDiff> {
+    let opts = FormatOptions::new("  ");
+    let old = decompile_function_with(old_code, old, options)?
+        .display(old_code, &opts)
+        .to_string();
+    let new = decompile_function_with(new_code, new, options)?
+        .display(new_code, &opts)
+        .to_string();
+    Ok(SourceDiff::new(&old, &new))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{LineChange, SourceDiff};
+
+    #[test]
+    fn unified() {
+        let old = "function f() {\n  var a = 1;\n  log(a);\n  if (a > 0) {\n    a++;\n  }\n  return a;\n}";
+        let new = "function f() {\n  var a = 2;\n  if (a > 0) {\n    log(a);\n    a++;\n  }\n  return a;\n}";
+        let diff = SourceDiff::new(old, new);
+        assert_eq!(diff.count(LineChange::Removed), 1);
+        assert_eq!(diff.count(LineChange::Added), 1);
+        assert_eq!(diff.count(LineChange::MovedFrom), 1);
+        assert_eq!(diff.count(LineChange::MovedTo), 1);
+        assert_eq!(
+            diff.unified("old", "new", 1),
+            "--- old\n+++ new\n@@ -1,5 +1,5 @@\n function f() {\n-  var a = 1;\n<  log(a);\n+  var a = 2;\n   if (a > 0) {\n>    log(a);\n     a++;\n"
+        );
+        assert!(SourceDiff::new(old, old).is_empty());
+        assert_eq!(SourceDiff::new(old, old).unified("old", "new", 3), "");
+    }
+}
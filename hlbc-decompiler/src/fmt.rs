@@ -1,9 +1,12 @@
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
-use crate::ast::{Class, Constant, ConstructorCall, Expr, Method, Operation, Statement};
-use hlbc::types::{Function, RefField, Type};
+use crate::ast::{Class, Constant, ConstructorCall, Expr, Method, Operation, Statement, Typedef};
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, RefField, RefFun, RefType, Reg, Type};
 use hlbc::Bytecode;
+use hlbc_analysis::containers::{container_name, element_types};
 
 #[derive(Clone)]
 pub struct FormatOptions {
@@ -40,18 +43,42 @@ impl Display for FormatOptions {
     }
 }
 
-fn to_haxe_type<'a>(ty: &Type, ctx: &'a Bytecode) -> impl Display + 'a {
+fn to_haxe_type(ty: RefType, ctx: &Bytecode) -> String {
+    to_haxe_type_of(ty, None, ctx)
+}
+
+/// Class of a static function, static functions are bound to the fields of the `$Class` type holding the statics
+pub(crate) fn static_class(code: &Bytecode, fun: RefFun) -> Option<&str> {
+    let f = fun.resolve_as_fn(code)?;
+    if f.is_method() {
+        return None;
+    }
+    f.parent?
+        .resolve_as_obj(&code.types)?
+        .name
+        .resolve(&code.strings)
+        .strip_prefix('$')
+}
+
+/// Haxe type, with the element type of the containers if we know it
+fn to_haxe_type_of(ty: RefType, elem: Option<RefType>, ctx: &Bytecode) -> String {
     use crate::Type::*;
-    match ty {
-        Void => "Void",
-        I32 => "Int",
-        F64 => "Float",
-        Bool => "Bool",
-        Bytes => "hl.Bytes",
-        Dyn => "Dynamic",
-        Fun(_) => "Function",
-        Obj(obj) => obj.name.resolve(&ctx.strings),
-        _ => "other",
+    let elem = elem.map(|e| to_haxe_type(e, ctx));
+    match ty.resolve(&ctx.types) {
+        Void => "Void".to_owned(),
+        I32 => "Int".to_owned(),
+        F64 => "Float".to_owned(),
+        Bool => "Bool".to_owned(),
+        Bytes => "hl.Bytes".to_owned(),
+        Dyn => "Dynamic".to_owned(),
+        Fun(_) => "Function".to_owned(),
+        Obj(obj) => {
+            let name = obj.name.resolve(&ctx.strings);
+            container_name(name, elem.as_deref()).unwrap_or_else(|| name.to_owned())
+        }
+        Array => format!("hl.NativeArray<{}>", elem.as_deref().unwrap_or("Dynamic")),
+        Virtual { .. } => ctx.virtual_name(ty).unwrap_or("Dynamic").to_owned(),
+        _ => "other".to_owned(),
     }
 }
 
@@ -61,7 +88,7 @@ impl Class {
         fmtools::fmt! { move
             {opts}"class "{self.name} if let Some(parent) = self.parent.as_ref() { " extends "{parent} } " {\n"
             for f in &self.fields {
-                {new_opts} if f.static_ { "static " } "var "{f.name}": "{to_haxe_type(f.ty.resolve(&ctx.types), ctx)}";\n"
+                {new_opts} if f.static_ { "static " } "var "{f.name}": "{to_haxe_type(f.ty, ctx)}";\n"
             }
             for m in &self.methods {
                 "\n"
@@ -70,20 +97,68 @@ impl Class {
             {opts}"}"
         }
     }
+
+    /// Extern declaration of the class : its fields and the signatures of its methods
+    pub fn display_extern<'a>(
+        &'a self,
+        ctx: &'a Bytecode,
+        opts: &'a FormatOptions,
+    ) -> impl Display + 'a {
+        let new_opts = opts.inc_nesting();
+        fmtools::fmt! { move
+            {opts}"extern class "{self.name} if let Some(parent) = self.parent.as_ref() { " extends "{parent} } " {\n"
+            for f in &self.fields {
+                {new_opts} if f.static_ { "static " } "var "{f.name}": "{to_haxe_type(f.ty, ctx)}";\n"
+            }
+            for m in &self.methods {
+                // Externs must be typed
+                let void = m.fun.resolve_as_fn(ctx).map_or(false, |f| f.ty(ctx).ret.is_void());
+                {m.signature(ctx, &new_opts)} if void { ": Void" } ";\n"
+            }
+            {opts}"}"
+        }
+    }
 }
 
-impl Method {
+impl Typedef {
     pub fn display<'a>(&'a self, ctx: &'a Bytecode, opts: &'a FormatOptions) -> impl Display + 'a {
         let new_opts = opts.inc_nesting();
+        fmtools::fmt! { move
+            {opts}"typedef "{self.name}" = {\n"
+            for (name, ty) in &self.fields {
+                {new_opts}"var "{name}": "{to_haxe_type(*ty, ctx)}";\n"
+            }
+            {opts}"}"
+        }
+    }
+}
+
+impl Method {
+    /// Declaration of the method, without its body
+    fn signature<'a>(&'a self, ctx: &'a Bytecode, opts: &'a FormatOptions) -> impl Display + 'a {
         let fun = self.fun.resolve_as_fn(ctx).unwrap();
+        let elems = element_types(ctx, fun);
+        let ret_elem = fun.ops.iter().find_map(|o| match o {
+            Opcode::Ret { ret } => elems.get(ret).copied(),
+            _ => None,
+        });
         fmtools::fmt! { move
             {opts} if self.static_ { "static " } if self.dynamic { "dynamic " }
             "function "{fun.name_default(ctx)}"("
+            let elems = &elems;
             {fmtools::join(", ", fun.args(ctx).iter().enumerate().skip(if self.static_ { 0 } else { 1 })
                 .map(move |(i, arg)| fmtools::fmt! {move
-                    {fun.arg_name(ctx, i).unwrap_or("_")}": "{to_haxe_type(arg.resolve(&ctx.types), ctx)}
+                    {fun.arg_name(ctx, i).unwrap_or("_")}": "{to_haxe_type_of(*arg, elems.get(&Reg(i as u32)).copied(), ctx)}
                 }))}
-            ")" if !fun.ty(ctx).ret.is_void() { ": "{to_haxe_type(fun.ty(ctx).ret.resolve(&ctx.types), ctx)} } " {"
+            ")" if !fun.ty(ctx).ret.is_void() { ": "{to_haxe_type_of(fun.ty(ctx).ret, ret_elem, ctx)} }
+        }
+    }
+
+    pub fn display<'a>(&'a self, ctx: &'a Bytecode, opts: &'a FormatOptions) -> impl Display + 'a {
+        let new_opts = opts.inc_nesting();
+        let fun = self.fun.resolve_as_fn(ctx).unwrap();
+        fmtools::fmt! { move
+            {self.signature(ctx, opts)}" {"
 
             if self.statements.is_empty() {
                 "}"
@@ -109,6 +184,7 @@ impl Display for Constant {
             Bool(c) => Display::fmt(c, f),
             Null => Display::fmt("null", f),
             This => Display::fmt("this", f),
+            Super => Display::fmt("super", f),
         }
     }
 }
@@ -178,6 +254,18 @@ impl Expr {
                                 }
                             })) }"}"
                     }
+                    // Structure literal of a @:structInit class
+                    Type::Obj(obj) | Type::Struct(obj) => {
+                        let values = values.iter().map(|(f, v)| (f.0, v)).collect::<BTreeMap<_, _>>();
+                        "{"{ fmtools::join(", ", values
+                            .iter()
+                            .map(|(&f, v)| {
+                                let name = obj.fields.get(f).map_or("_", |f| f.name.resolve(&code.strings));
+                                fmtools::fmt! { move
+                                    {name}": "{disp!(v)}
+                                }
+                            })) }"}"
+                    }
                     _ => "[invalid anonymous type]",
                 },
                 Expr::Array(array, index) => {
@@ -186,15 +274,23 @@ impl Expr {
                 Expr::Call(call) => {
                     {disp!(call.fun)}"("{fmtools::join(", ", call.args.iter().map(|e| disp!(e)))}")"
                 }
+                Expr::Cast(expr, ty) => {
+                    "cast("{disp!(expr)}", "{to_haxe_type(*ty, code)}")"
+                }
                 Expr::Constant(c) => {{c}},
                 Expr::Constructor(ConstructorCall { ty, args }) => {
-                    "new "{ty.display(code)}"("{fmtools::join(", ", args.iter().map(|e| disp!(e)))}")"
+                    // With the type parameters of the std containers
+                    let name = match ty.resolve(&code.types) {
+                        Type::Obj(_) => to_haxe_type(*ty, code),
+                        _ => ty.display(code),
+                    };
+                    "new "{name}"("{fmtools::join(", ", args.iter().map(|e| disp!(e)))}")"
                 }
                 Expr::Closure(f, stmts) => {
                     let fun = f.resolve_as_fn(code).unwrap();
                     "("{fmtools::join(", ", fun.ty(code).args.iter().enumerate().map(move |(i, arg)|
                         fmtools::fmt! { move
-                            {fun.arg_name(code, i).unwrap_or("_")}": "{to_haxe_type(arg.resolve(&code.types), code)}
+                            {fun.arg_name(code, i).unwrap_or("_")}": "{to_haxe_type(*arg, code)}
                         }
                     ))}") -> {\n"
                     let indent2 = indent.inc_nesting();
@@ -209,7 +305,12 @@ impl Expr {
                 Expr::Field(receiver, name) => {
                     {disp!(receiver)}"."{name}
                 }
-                Expr::FunRef(fun) => {{fun.name_default(code)}},
+                Expr::FunRef(fun) => {
+                    if let Some(class) = static_class(code, *fun) {
+                        {class}"."
+                    }
+                    {fun.name_default(code)}
+                }
                 Expr::IfElse { cond, if_, else_ } => {
                     "if ("{disp!(cond)}") {\n"
                     let indent2 = indent.inc_nesting();
@@ -228,7 +329,7 @@ impl Expr {
                 }
                 Expr::Variable(x, name) => {{
                     if let Some(name) = name {
-                        name.clone()
+                        name.to_string()
                     } else {
                         x.to_string()
                     }
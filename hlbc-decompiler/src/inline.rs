@@ -0,0 +1,173 @@
+use crate::ast::{Expr, Operation};
+use crate::naming::NamingOptions;
+use crate::pattern::Rewrite;
+
+/// Decides which values are inlined in the expressions using them and which are assigned to a variable.
+///
+/// Registers with a debug name are always variables. The default inlines every other value, for compact
+/// expression-heavy output. [InlineOptions::flat] assigns every value to a variable, one statement per
+/// instruction, for output that is easier to diff. The [NamingOptions] of the remaining variables and the [Rewrite]s
+/// applied to the output are set here too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineOptions {
+    /// Largest expression inlined, counted in nodes (`a + b` is 3)
+    pub max_size: Option<usize>,
+    /// Largest number of instructions reading a value for it to be inlined
+    pub max_uses: Option<usize>,
+    /// Inline expressions with side effects, like calls
+    pub side_effects: bool,
+    /// Naming of the variables without a debug name
+    pub naming: NamingOptions,
+    /// Applied in order to every decompiled function
+    pub rewrites: Vec<Rewrite>,
+    /// Undo control flow flattening before decompiling, see [deobfuscate](crate::deobf::deobfuscate)
+    pub unflatten: bool,
+}
+
+impl Default for InlineOptions {
+    fn default() -> Self {
+        Self::compact()
+    }
+}
+
+impl InlineOptions {
+    /// Inline everything without a debug name
+    pub fn compact() -> Self {
+        Self {
+            max_size: None,
+            max_uses: None,
+            side_effects: true,
+            naming: NamingOptions::default(),
+            rewrites: Vec::new(),
+            unflatten: true,
+        }
+    }
+
+    /// Never inline, every value is assigned to a variable
+    pub fn flat() -> Self {
+        Self {
+            max_size: Some(0),
+            max_uses: Some(0),
+            side_effects: false,
+            naming: NamingOptions::default(),
+            rewrites: Vec::new(),
+            unflatten: true,
+        }
+    }
+
+    /// Returns true if `expr` can be inlined, `uses` counts the instructions reading it.
+    pub(crate) fn inline(&self, expr: &Expr, uses: impl FnOnce() -> usize) -> bool {
+        // Constructor calls and anonymous structures are built over many instructions reading the value
+        let multi = matches!(expr, Expr::Constructor(_) | Expr::Anonymous(..));
+        self.max_size.map_or(true, |max| size(expr) <= max)
+            && (self.side_effects || !has_side_effects(expr))
+            && (multi || self.max_uses.map_or(true, |max| uses() <= max))
+    }
+}
+
+/// Number of nodes of an expression
+pub fn size(expr: &Expr) -> usize {
+    1 + match expr {
+        Expr::Anonymous(_, fields) => fields.values().map(size).sum(),
+        Expr::Array(array, index) => size(array) + size(index),
+        Expr::Call(call) => size(&call.fun) + call.args.iter().map(size).sum::<usize>(),
+        Expr::Cast(e, _) | Expr::Field(e, _) => size(e),
+        Expr::Constructor(c) => c.args.iter().map(size).sum(),
+        Expr::EnumConstr(_, _, args) => args.iter().map(size).sum(),
+        Expr::IfElse { cond, .. } => size(cond),
+        Expr::Op(op) => operands(op).into_iter().map(size).sum(),
+        Expr::Closure(..)
+        | Expr::Constant(_)
+        | Expr::FunRef(_)
+        | Expr::Unknown(_)
+        | Expr::Variable(..) => 0,
+    }
+}
+
+/// Returns true if evaluating the expression does more than computing a value. Calls are assumed to have side
+/// effects, allocations and reads don't.
+pub fn has_side_effects(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call(_) | Expr::Constructor(_) | Expr::IfElse { .. } => true,
+        Expr::Op(Operation::Incr(_) | Operation::Decr(_)) => true,
+        Expr::Op(op) => operands(op).into_iter().any(has_side_effects),
+        Expr::Anonymous(_, fields) => fields.values().any(has_side_effects),
+        Expr::Array(array, index) => has_side_effects(array) || has_side_effects(index),
+        Expr::Cast(e, _) | Expr::Field(e, _) => has_side_effects(e),
+        Expr::EnumConstr(_, _, args) => args.iter().any(has_side_effects),
+        Expr::Closure(..)
+        | Expr::Constant(_)
+        | Expr::FunRef(_)
+        | Expr::Unknown(_)
+        | Expr::Variable(..) => false,
+    }
+}
+
+fn operands(op: &Operation) -> Vec<&Expr> {
+    use Operation::*;
+    match op {
+        Add(a, b)
+        | Sub(a, b)
+        | Mul(a, b)
+        | Div(a, b)
+        | Mod(a, b)
+        | Shl(a, b)
+        | Shr(a, b)
+        | And(a, b)
+        | Or(a, b)
+        | Xor(a, b)
+        | Eq(a, b)
+        | NotEq(a, b)
+        | Gt(a, b)
+        | Gte(a, b)
+        | Lt(a, b)
+        | Lte(a, b) => vec![a, b],
+        Neg(a) | Not(a) | Incr(a) | Decr(a) => vec![a],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::sample;
+
+    use crate::ast::{add, call_fun, cst_int, Expr};
+    use crate::inline::{has_side_effects, size, InlineOptions};
+    use crate::{decompile_code, decompile_code_with};
+
+    #[test]
+    fn heuristics() {
+        let sum = add(cst_int(1), cst_int(2));
+        let call = call_fun(hlbc::types::RefFun(0), vec![sum.clone()]);
+        assert_eq!(size(&sum), 3);
+        assert_eq!(size(&call), 5);
+        assert!(!has_side_effects(&sum));
+        assert!(has_side_effects(&call));
+
+        let options = InlineOptions {
+            max_size: Some(3),
+            max_uses: Some(1),
+            side_effects: false,
+            ..InlineOptions::default()
+        };
+        assert!(options.inline(&sum, || 1));
+        assert!(!options.inline(&sum, || 2));
+        assert!(!options.inline(&call, || 1));
+        assert!(!InlineOptions::flat().inline(&Expr::Variable(hlbc::types::Reg(0), None), || 0));
+    }
+
+    #[test]
+    fn flat() {
+        let code = sample();
+        let (mut compact, mut flat) = (0, 0);
+        // Only the functions the decompiler handles
+        for f in &code.functions {
+            if let Ok(stmts) = decompile_code(&code, f) {
+                compact += stmts.len();
+                flat += decompile_code_with(&code, f, &InlineOptions::flat())
+                    .unwrap()
+                    .len();
+            }
+        }
+        assert!(flat > compact);
+    }
+}
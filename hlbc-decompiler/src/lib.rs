@@ -3,25 +3,177 @@
 //!
 //! The decompiler takes bytecode elements as input and outputs [ast] structures that can be displayed.
 
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::Once;
 
 use ast::*;
+use diagnostics::{Diagnostic, DiagnosticKind};
+use hlbc::constants::ConstValue;
 use hlbc::opcodes::Opcode;
-use hlbc::types::{Function, RefField, RefFun, Reg, Type, TypeObj};
-use hlbc::Bytecode;
+use hlbc::types::{
+    FunPtr, Function, RefField, RefFun, RefString, RefType, Reg, Str, Type, TypeObj,
+};
+use hlbc::{Bytecode, VerifyError};
+use hlbc_analysis::copyprop::Copies;
+use hlbc_analysis::dyntypes::{has_member, DynTypes, MemberIndex};
+use hlbc_analysis::slice::DataDeps;
+use inline::InlineOptions;
 use scopes::*;
 
 #[cfg(feature = "alt")]
 mod alt;
 /// A simple representation for the Haxe source code generated by the decompiler
 pub mod ast;
+/// Debug information generated from the decompiled output
+pub mod debuginfo;
+/// Deobfuscation transforms applied on the bytecode before decompilation
+pub mod deobf;
+/// Problems the decompiler worked around, with the instruction they come from
+pub mod diagnostics;
+/// Differences between the decompiled sources of two versions of a function
+pub mod diff;
 /// Functions to render the [ast] to a string
 pub mod fmt;
+/// Heuristics deciding which values are inlined in expressions
+pub mod inline;
+/// Shrink functions making the decompiler fail to small test cases for bug reports
+pub mod minimize;
+/// Names of the variables without debug information, inferred from their use
+pub mod naming;
+/// Expression patterns to search and rewrite the decompiled code
+pub mod pattern;
 /// AST post-processing
 mod post;
+/// Export of a whole bytecode as a Haxe source tree
+pub mod project;
+/// Decompilation success and diagnostics of a whole bytecode, by class
+pub mod quality;
 /// Scope handling structures
 mod scopes;
 
+/// Errors while decompiling
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The bytecode is invalid, the decompiler can't do anything about it
+    #[error(transparent)]
+    Bytecode(#[from] hlbc::Error),
+    /// The decompiler failed on valid bytecode, this is a bug
+    #[error(
+        "Decompiler bug in fn@{} : {message}{}",
+        findex.0,
+        location.as_ref().map(|l| format!(" (at {l})")).unwrap_or_default()
+    )]
+    Internal {
+        findex: RefFun,
+        message: String,
+        /// Where the decompiler panicked, if it did
+        location: Option<String>,
+    },
+}
+
+impl Error {
+    /// Returns true if the error is a bug in the decompiler rather than a problem with the input
+    pub fn is_bug(&self) -> bool {
+        matches!(self, Error::Internal { .. })
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn bug(f: &Function, message: impl Into<String>) -> Error {
+    Error::Internal {
+        findex: f.findex,
+        message: message.into(),
+        location: None,
+    }
+}
+
+thread_local! {
+    /// Number of functions being decompiled on this thread, closures included
+    static DECOMPILING: Cell<usize> = const { Cell::new(0) };
+    /// Where the decompiler last panicked on this thread
+    static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Install, once, a panic hook recording where the decompiler panics instead of printing the message. The other panics
+/// go to the previous hook.
+fn install_panic_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if DECOMPILING.try_with(Cell::get).unwrap_or(0) > 0 {
+                let location = info.location().map(|l| l.to_string());
+                PANIC_LOCATION.with(|l| *l.borrow_mut() = location);
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+/// Closures created by a function, they are displayed in its body
+pub(crate) fn created_closures(f: &Function) -> impl Iterator<Item = RefFun> + '_ {
+    f.ops.iter().filter_map(|o| match *o {
+        Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } => Some(fun),
+        _ => None,
+    })
+}
+
+/// Resolve a function that must have code
+fn resolve_fn(code: &Bytecode, fun: RefFun) -> Result<&Function> {
+    match fun.try_resolve(code).map_err(hlbc::Error::from)? {
+        FunPtr::Fun(f) => Ok(f),
+        FunPtr::Native(_) => {
+            Err(hlbc::Error::from(VerifyError::UnexpectedNative { findex: fun }).into())
+        }
+    }
+}
+
+/// Reject functions the decompiler can't work with
+/// Number of times an instruction reads a register, `a + a` reads it twice
+fn reads(op: &Opcode, reg: Reg) -> usize {
+    let n = op.regs().into_iter().filter(|&r| r == reg).count();
+    match op {
+        Opcode::Incr { .. } | Opcode::Decr { .. } => n,
+        // The destination is written, not read
+        _ if op.dst() == Some(reg) => n - 1,
+        _ => n,
+    }
+}
+
+/// Fields assigned by a constructor made of nothing else than assigning each argument to a field, in order
+fn struct_init_fields(code: &Bytecode, fun: RefFun) -> Option<Vec<RefField>> {
+    let f = fun.resolve_as_fn(code)?;
+    let mut fields = Vec::new();
+    for o in &f.ops {
+        let (obj, field, src) = match *o {
+            Opcode::SetField { obj, field, src } => (obj, field, src),
+            Opcode::SetThis { field, src } => (Reg(0), field, src),
+            Opcode::Ret { ret } if f.regtype(ret).is_void() => continue,
+            _ => return None,
+        };
+        if obj != Reg(0) || src.0 as usize != fields.len() + 1 {
+            return None;
+        }
+        fields.push(field);
+    }
+    if fields.is_empty() || fields.len() + 1 != f.ty(code).args.len() {
+        return None;
+    }
+    Some(fields)
+}
+
+fn check(code: &Bytecode, f: &Function) -> Result<()> {
+    match hlbc::verify::verify_function(code, f).into_iter().next() {
+        Some(e) => Err(hlbc::Error::from(e).into()),
+        None => Ok(()),
+    }
+}
+
 enum ExprCtx {
     Constructor {
         reg: Reg,
@@ -43,17 +195,36 @@ struct DecompilerState<'c> {
     // TODO move this to another pass on the generated ast
     expr_ctx: Vec<ExprCtx>,
     // Variable names we already declared
-    seen: HashSet<String>,
+    seen: HashSet<Str>,
+    // Inferred types of the dynamic registers
+    dyn_types: DynTypes,
+    // Classes by member name, for the dynamic registers and the closures
+    members: &'c MemberIndex,
+    options: &'c InlineOptions,
+    // Readers of each value, an expression with side effects is only inlined in its single reader
+    deps: DataDeps,
+    // Copies to display instead of the register they were copied from
+    copies: Copies,
+    // Instruction being decompiled
+    current: usize,
+    // Problems worked around so far, fallbacks are found while building expressions
+    diagnostics: RefCell<Vec<Diagnostic>>,
     f: &'c Function,
     code: &'c Bytecode,
 }
 
 impl<'c> DecompilerState<'c> {
-    fn new(code: &'c Bytecode, f: &'c Function) -> DecompilerState<'c> {
+    fn new(
+        code: &'c Bytecode,
+        members: &'c MemberIndex,
+        f: &'c Function,
+        options: &'c InlineOptions,
+    ) -> DecompilerState<'c> {
         let scopes = Scopes::new();
         let mut reg_state = HashMap::with_capacity(f.regs.len());
         let expr_ctx = Vec::new();
         let mut seen = HashSet::new();
+        let deps = DataDeps::new(f);
 
         let mut start = 0;
         // First argument / First register is 'this'
@@ -68,7 +239,7 @@ impl<'c> DecompilerState<'c> {
 
         // Initialize register state with the function arguments
         for i in start..f.ty(code).args.len() {
-            let name = f.arg_name(code, i - start).map(ToOwned::to_owned);
+            let name = f.arg_name(code, i - start).map(Str::from);
             reg_state.insert(Reg(i as u32), Expr::Variable(Reg(i as u32), name.clone()));
             if let Some(name) = name {
                 seen.insert(name);
@@ -80,11 +251,52 @@ impl<'c> DecompilerState<'c> {
             reg_state,
             expr_ctx,
             seen,
+            dyn_types: DynTypes::infer(code, members, f),
+            members,
+            options,
+            copies: Copies::new(f, &deps),
+            deps,
+            current: 0,
+            diagnostics: RefCell::new(Vec::new()),
             f,
             code,
         }
     }
 
+    /// Placeholder for a value that couldn't be recovered
+    fn fallback(&self, message: &str) -> Expr {
+        self.diagnose(DiagnosticKind::Fallback, message);
+        Expr::Unknown(message.to_owned())
+    }
+
+    /// Report a problem with the current instruction, with a comment before the statements it affects
+    fn report(&mut self, kind: DiagnosticKind, message: String) {
+        self.diagnose(kind, &message);
+        self.push_stmt(comment(format!("{kind} : {message}")));
+    }
+
+    /// Decompile a closure, keeping its diagnostics
+    fn closure(&mut self, fun: RefFun) -> Result<Expr> {
+        let f = resolve_fn(self.code, fun)?;
+        let (statements, diagnostics) = decompile_in(self.code, self.members, f, self.options)?;
+        self.diagnostics.get_mut().extend(diagnostics);
+        Ok(Expr::Closure(fun, statements))
+    }
+
+    fn diagnose(&self, kind: DiagnosticKind, message: &str) {
+        let diagnostic = Diagnostic {
+            findex: self.f.findex,
+            pos: self.current,
+            kind,
+            message: message.to_owned(),
+        };
+        let mut diagnostics = self.diagnostics.borrow_mut();
+        // The same register might be read more than once
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
     fn push_stmt(&mut self, stmt: Statement) {
         self.scopes.push_stmt(stmt);
     }
@@ -92,13 +304,28 @@ impl<'c> DecompilerState<'c> {
     // Update the register state and create a statement depending on inline rules
     fn push_expr(&mut self, i: usize, dst: Reg, expr: Expr) {
         let name = self.f.var_name(self.code, i);
-        // Inline check
-        if name.is_none() {
+        // Reads of the value, without the instruction completing an expression spanning many instructions (like
+        // the call of a constructor) and the reads displayed as a copy
+        let uses = self
+            .deps
+            .users(i)
+            .iter()
+            .filter(|&&u| u != self.current && self.copy(u, dst).is_none())
+            .map(|&u| reads(&self.f.ops[u], dst))
+            .sum();
+        // Duplicating or dropping an expression with side effects would change how many times it is evaluated
+        let effects = inline::has_side_effects(&expr);
+        if name.is_none() && effects && uses == 0 {
+            self.reg_state.remove(&dst);
+            self.push_stmt(stmt(expr));
+        } else if name.is_none() && (!effects || uses == 1) && self.options.inline(&expr, || uses) {
             self.reg_state.insert(dst, expr);
         } else {
             self.reg_state
                 .insert(dst, Expr::Variable(dst, name.clone()));
-            let declaration = self.seen.insert(name.clone().unwrap());
+            let declaration = self
+                .seen
+                .insert(name.clone().unwrap_or_else(|| dst.to_string().into()));
             self.push_stmt(Statement::Assign {
                 declaration,
                 variable: Expr::Variable(dst, name),
@@ -109,10 +336,40 @@ impl<'c> DecompilerState<'c> {
 
     // Get the expr for a register
     fn expr(&self, reg: Reg) -> Expr {
-        self.reg_state
-            .get(&reg)
-            .cloned()
-            .unwrap_or_else(|| Expr::Unknown("missing expr".to_owned()))
+        self.copy(self.current, reg)
+            .or_else(|| self.reg_state.get(&reg).cloned())
+            .unwrap_or_else(|| self.fallback("missing expr"))
+    }
+
+    /// Named variable holding a copy of the value of `reg` read at `pos`. The instructions after a copy often use
+    /// both registers interchangeably, we prefer the name.
+    fn copy(&self, pos: usize, reg: Reg) -> Option<Expr> {
+        let m = self.copies.get(pos, reg)?;
+        let dst = self.f.ops[m].dst()?;
+        Some(Expr::Variable(dst, Some(self.f.var_name(self.code, m)?)))
+    }
+
+    /// Dynamic field access, using a plain field access when we know the type of the object
+    fn dyn_field(&self, obj: Reg, field: RefString) -> Expr {
+        let name = field.resolve_shared(&self.code.strings);
+        match self.dyn_types.get(obj) {
+            Some(ty) if has_member(self.code, ty, &name) => {
+                Expr::Field(Box::new(self.expr(obj)), name)
+            }
+            _ => array(self.expr(obj), cst_refstring(field, self.code)),
+        }
+    }
+
+    /// Safe cast of a dynamic value to a class
+    fn dyn_cast(&self, src: Reg, dst: Reg) -> Expr {
+        let ty = self.f.regtype(dst);
+        match (
+            self.f.regtype(src).resolve(&self.code.types),
+            ty.resolve(&self.code.types),
+        ) {
+            (Type::Dyn | Type::DynObj, Type::Obj(_)) => Expr::Cast(Box::new(self.expr(src)), ty),
+            _ => self.expr(src),
+        }
     }
 
     /// Expands the expression of many registers
@@ -120,30 +377,64 @@ impl<'c> DecompilerState<'c> {
         args.iter().map(|&r| self.expr(r)).collect()
     }
 
+    /// Constructor call of the object in `args[0]`, a structure literal when the constructor only initializes the
+    /// fields like the ones generated for `@:structInit` classes
+    fn constructor(&self, fun: RefFun, args: &[Reg]) -> Expr {
+        let ty = self.f.regtype(args[0]);
+        let values = self.args_expr(&args[1..]);
+        match struct_init_fields(self.code, fun) {
+            Some(fields) if fields.len() == values.len() => {
+                Expr::Anonymous(ty, fields.into_iter().zip(values).collect())
+            }
+            _ => Expr::Constructor(ConstructorCall::new(ty, values)),
+        }
+    }
+
+    /// `super` in a constructor calling the constructor of the parent class, or `super.method` when calling the
+    /// implementation of the parent class on `this` (a method overridden by the current class)
+    fn super_target(&self, func: &Function, args: &[Reg]) -> Option<Expr> {
+        let name = func.name(self.code)?;
+        let class = self.f.parent?;
+        if !matches!(self.expr(*args.first()?), Expr::Constant(Constant::This))
+            || !func.is_method()
+            || !self.code.is_subclass(class, func.parent?)
+        {
+            return None;
+        }
+        if name == "__constructor__" {
+            return (self.f.name(self.code) == Some(name)).then(cst_super);
+        }
+        // The implementation a call on `this` would dispatch to, a direct call to any other is a super call
+        let dispatched = class
+            .resolve_as_obj(&self.code.types)?
+            .protos
+            .iter()
+            .find(|p| p.name.resolve(&self.code.strings) == name)
+            .map(|p| p.findex)
+            .or_else(|| self.code.overridden(class, name).map(|(_, f)| f));
+        (dispatched != Some(func.findex)).then(|| Expr::Field(Box::new(cst_super()), name.into()))
+    }
+
     /// Push a call to a function, which might be a constructor call.
     fn push_call(&mut self, i: usize, dst: Reg, fun: RefFun, args: &[Reg]) {
         if let Some(&ExprCtx::Constructor { reg, pos }) = self.expr_ctx.last() {
             if reg == args[0] {
-                self.push_expr(
-                    pos,
-                    reg,
-                    Expr::Constructor(ConstructorCall::new(
-                        self.f.regtype(reg),
-                        self.args_expr(&args[1..]),
-                    )),
-                );
+                self.push_expr(pos, reg, self.constructor(fun, args));
                 self.expr_ctx.pop();
             }
         } else {
-            self.push_stmt(comment(fun.display_id(self.code).to_string()));
-            let call = if let Some((func, true)) = fun
-                .resolve_as_fn(self.code)
-                .map(|func| (func, func.is_method()))
-            {
+            // The qualified name of a static function is enough
+            if fmt::static_class(self.code, fun).is_none() {
+                self.push_stmt(comment(fun.display_id(self.code).to_string()));
+            }
+            let func = fun.resolve_as_fn(self.code);
+            let call = if let Some(target) = func.and_then(|func| self.super_target(func, args)) {
+                call(target, self.args_expr(&args[1..]))
+            } else if let Some((func, true)) = func.map(|func| (func, func.is_method())) {
                 call(
                     Expr::Field(
                         Box::new(self.expr(args[0])),
-                        func.name.unwrap().resolve(&self.code.strings).to_owned(),
+                        func.name.unwrap().resolve_shared(&self.code.strings),
                     ),
                     self.args_expr(&args[1..]),
                 )
@@ -165,7 +456,6 @@ impl<'c> DecompilerState<'c> {
             if matches!(self.f.ops[i + offset as usize], Opcode::JAlways { offset } if offset < 0) {
                 if let Some(loop_cond) = self.scopes.last_loop_cond_mut() {
                     if matches!(loop_cond, Expr::Unknown(_)) {
-                        println!("old loop cond : {:?}", loop_cond);
                         *loop_cond = cond;
                     } else {
                         self.scopes.push_if(offset + 1, cond);
@@ -183,11 +473,74 @@ impl<'c> DecompilerState<'c> {
 
 /// Decompile a function code to a list of [Statement]s.
 /// This works by analyzing each opcodes in order while trying to reconstruct scopes, contexts and intents.
-pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
-    let mut state = DecompilerState::new(code, f);
+///
+/// A panic in the decompiler is returned as an [Error::Internal] with its location, the message isn't printed.
+pub fn decompile_code(code: &Bytecode, f: &Function) -> Result<Vec<Statement>> {
+    decompile_code_with(code, f, &InlineOptions::default())
+}
+
+/// Decompile a function code, choosing which values are inlined, see [decompile_code]
+pub fn decompile_code_with(
+    code: &Bytecode,
+    f: &Function,
+    options: &InlineOptions,
+) -> Result<Vec<Statement>> {
+    decompile_code_diagnostics(code, f, options).map(|(statements, _)| statements)
+}
+
+/// Decompile a function code with the problems worked around, those of its closures included. Positions are in the
+/// deobfuscated function when [deobf::deobfuscate] changed it.
+pub fn decompile_code_diagnostics(
+    code: &Bytecode,
+    f: &Function,
+    options: &InlineOptions,
+) -> Result<(Vec<Statement>, Vec<Diagnostic>)> {
+    decompile_in(code, &MemberIndex::new(code), f, options)
+}
+
+/// [decompile_code_diagnostics] with the [MemberIndex] of the bytecode, to build it once when decompiling many functions
+pub(crate) fn decompile_in(
+    code: &Bytecode,
+    members: &MemberIndex,
+    f: &Function,
+    options: &InlineOptions,
+) -> Result<(Vec<Statement>, Vec<Diagnostic>)> {
+    check(code, f)?;
+    install_panic_hook();
+    let body = || decompile_body(code, members, f, options);
+    DECOMPILING.with(|d| d.set(d.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(body));
+    DECOMPILING.with(|d| d.set(d.get() - 1));
+    result.unwrap_or_else(|payload| {
+        let message = if let Some(s) = payload.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "panic".to_owned()
+        };
+        Err(Error::Internal {
+            findex: f.findex,
+            message,
+            location: PANIC_LOCATION.with(|l| l.borrow_mut().take()),
+        })
+    })
+}
+
+fn decompile_body(
+    code: &Bytecode,
+    members: &MemberIndex,
+    f: &Function,
+    options: &InlineOptions,
+) -> Result<(Vec<Statement>, Vec<Diagnostic>)> {
+    let deobfuscated = deobf::deobfuscate(code, f, options.unflatten).map(|(f, _)| f);
+    let f = deobfuscated.as_ref().unwrap_or(f);
+
+    let mut state = DecompilerState::new(code, members, f, options);
 
     let iter = f.ops.iter().enumerate();
     for (i, o) in iter {
+        state.current = i;
         // Opcodes are grouped by semantic
         // Control flow first because they are the most important
         match o {
@@ -225,7 +578,7 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                     let loop_start = state
                         .scopes
                         .last_loop_start()
-                        .expect("Backward jump but we aren't in a loop ?");
+                        .ok_or_else(|| bug(f, "Backward jump but we aren't in a loop"))?;
 
                     // Scan the next instructions in order to find another jump to the same place
                     if f.ops.iter().enumerate().skip(i + 1).find_map(|(j, o)| {
@@ -244,7 +597,7 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                         if let Some(stmt) = state.scopes.end_last_loop() {
                             state.push_stmt(stmt);
                         } else {
-                            panic!("Last scope is not a loop !");
+                            return Err(bug(f, "Last scope is not a loop"));
                         }
                     }
                 } else {
@@ -252,7 +605,10 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                         if let Some(pos) = offsets.iter().position(|o| *o == i) {
                             state.scopes.push_switch_case(pos);
                         } else {
-                            panic!("no matching offset for switch case ({i})");
+                            return Err(bug(
+                                f,
+                                format!("No matching offset for switch case ({i})"),
+                            ));
                         }
                     } else if state.scopes.last_loop_start().is_some() {
                         // Check the instruction just before the jump target
@@ -266,9 +622,9 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                         // It's the jump over of an else clause
                         state.scopes.push_else(offset + 1);
                     } else {
-                        eprintln!(
-                            "{i}: JAlways has no matching scope (last: {:?})",
-                            state.scopes.scopes.last()
+                        state.report(
+                            DiagnosticKind::Warning,
+                            "jump without a matching scope".to_owned(),
                         );
                     }
                 }
@@ -330,10 +686,6 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
             //region OPERATORS
             &Opcode::Mov { dst, src } => {
                 state.push_expr(i, dst, state.expr(src));
-                // Workaround for when the instructions after this one use dst and src interchangeably.
-                state
-                    .reg_state
-                    .insert(src, Expr::Variable(dst, f.var_name(code, i)));
             }
             &Opcode::Add { dst, a, b } => {
                 state.push_expr(i, dst, add(state.expr(a), state.expr(b)));
@@ -421,17 +773,13 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
             Opcode::CallN { dst, fun, args } => {
                 if let Some(&ExprCtx::Constructor { reg, pos }) = state.expr_ctx.last() {
                     if reg == args[0] {
-                        state.push_expr(
-                            pos,
-                            reg,
-                            Expr::Constructor(ConstructorCall::new(
-                                f.regtype(reg),
-                                state.args_expr(&args[1..]),
-                            )),
-                        );
+                        state.push_expr(pos, reg, state.constructor(*fun, args));
+                        state.expr_ctx.pop();
                     }
                 } else {
-                    state.push_stmt(comment(fun.display_id(code).to_string()));
+                    if fmt::static_class(code, *fun).is_none() {
+                        state.push_stmt(comment(fun.display_id(code).to_string()));
+                    }
                     let call = call_fun(*fun, state.args_expr(args));
                     if fun.ty(code).ret.is_void() {
                         state.push_stmt(stmt(call));
@@ -461,7 +809,7 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 let call = call(
                     Expr::Field(
                         Box::new(cst_this()),
-                        method.name.resolve(&code.strings).to_owned(),
+                        method.name.resolve_shared(&code.strings),
                     ),
                     state.args_expr(args),
                 );
@@ -493,25 +841,16 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
             //region CLOSURES
             &Opcode::StaticClosure { dst, fun } => {
                 state.push_stmt(comment(format!("closure : {}", fun.display_id(code))));
-                state.push_expr(
-                    i,
-                    dst,
-                    Expr::Closure(fun, decompile_code(code, fun.resolve_as_fn(code).unwrap())),
-                );
+                let closure = state.closure(fun)?;
+                state.push_expr(i, dst, closure);
             }
             &Opcode::InstanceClosure { dst, obj, fun } => {
                 state.push_stmt(comment(format!("closure : {}", fun.display_id(code))));
                 match f.regtype(obj).resolve(&code.types) {
                     // This is an anonymous enum holding the capture for the closure
                     Type::Enum { .. } => {
-                        state.push_expr(
-                            i,
-                            dst,
-                            Expr::Closure(
-                                fun,
-                                decompile_code(code, fun.resolve_as_fn(code).unwrap()),
-                            ),
-                        );
+                        let closure = state.closure(fun)?;
+                        state.push_expr(i, dst, closure);
                     }
                     _ => {
                         state.push_expr(
@@ -519,11 +858,10 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                             dst,
                             Expr::Field(
                                 Box::new(state.expr(obj)),
-                                fun.resolve_as_fn(code)
-                                    .unwrap()
-                                    .name(code)
-                                    .unwrap_or("_")
-                                    .to_owned(),
+                                resolve_fn(code, fun)?.name.map_or_else(
+                                    || "_".into(),
+                                    |n| n.resolve_shared(&code.strings),
+                                ),
                             ),
                         );
                     }
@@ -533,37 +871,19 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
 
             //region ACCESSES
             &Opcode::GetGlobal { dst, global } => {
-                // Is a string
-                if f.regtype(dst).0 == 13 {
-                    state.push_expr(
-                        i,
-                        dst,
-                        cst_string(
-                            code.globals_initializers
-                                .get(&global)
-                                .and_then(|&x| {
-                                    code.constants.as_ref().map(|constants| {
-                                        code.strings[constants[x].fields[0]].to_owned()
-                                    })
-                                })
-                                .unwrap(),
-                        ),
-                    );
+                if let Some(ConstValue::String(s)) = code.global_value(global) {
+                    state.push_expr(i, dst, cst_refstring(s, code));
                 } else {
                     match f.regtype(dst).resolve(&code.types) {
                         Type::Obj(obj) | Type::Struct(obj) => {
                             state.push_expr(
                                 i,
                                 dst,
-                                Expr::Variable(dst, Some(obj.name.display(code))),
+                                Expr::Variable(dst, Some(obj.name.resolve_shared(&code.strings))),
                             );
                         }
                         Type::Enum { .. } => {
-                            state.push_expr(
-                                i,
-                                dst,
-                                Expr::Unknown("unknown enum variant".to_owned()),
-                            );
+                            state.push_expr(i, dst, state.fallback("unknown enum variant"));
                         }
                         _ => {}
                     }
@@ -619,23 +939,25 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 });
             }
             &Opcode::DynGet { dst, obj, field } => {
-                state.push_expr(i, dst, array(state.expr(obj), cst_refstring(field, code)));
+                state.push_expr(i, dst, state.dyn_field(obj, field));
             }
             &Opcode::DynSet { obj, field, src } => {
                 state.push_stmt(Statement::Assign {
                     declaration: false,
-                    variable: array(state.expr(obj), cst_refstring(field, code)),
+                    variable: state.dyn_field(obj, field),
                     assign: state.expr(src),
                 });
             }
             //endregion
 
             //region VALUES
+            &Opcode::SafeCast { dst, src } => {
+                state.push_expr(i, dst, state.dyn_cast(src, dst));
+            }
             &Opcode::ToDyn { dst, src }
             | &Opcode::ToSFloat { dst, src }
             | &Opcode::ToUFloat { dst, src }
             | &Opcode::ToInt { dst, src }
-            | &Opcode::SafeCast { dst, src }
             | &Opcode::UnsafeCast { dst, src }
             | &Opcode::ToVirtual { dst, src } => {
                 state.push_expr(i, dst, state.expr(src));
@@ -706,7 +1028,7 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 state.push_expr(
                     i,
                     dst,
-                    Expr::Field(Box::new(state.expr(value)), "constructorIndex".to_owned()),
+                    Expr::Field(Box::new(state.expr(value)), "constructorIndex".into()),
                 );
                 //state.push_expr(i, dst, state.expr(value));
             }
@@ -719,14 +1041,17 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 state.push_expr(
                     i,
                     dst,
-                    Expr::Field(Box::new(state.expr(value)), field.0.to_string()),
+                    Expr::Field(Box::new(state.expr(value)), field.0.to_string().into()),
                 );
             }
             &Opcode::SetEnumField { value, field, src } => match state.expr(value) {
                 Expr::Variable(r, name) => {
                     state.push_stmt(Statement::Assign {
                         declaration: false,
-                        variable: Expr::Field(Box::new(state.expr(value)), field.0.to_string()),
+                        variable: Expr::Field(
+                            Box::new(state.expr(value)),
+                            field.0.to_string().into(),
+                        ),
                         assign: state.expr(src),
                     });
                 }
@@ -734,7 +1059,10 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                     state.push_stmt(comment("closure capture"));
                     state.push_stmt(Statement::Assign {
                         declaration: false,
-                        variable: Expr::Field(Box::new(state.expr(value)), field.0.to_string()),
+                        variable: Expr::Field(
+                            Box::new(state.expr(value)),
+                            field.0.to_string().into(),
+                        ),
                         assign: state.expr(src),
                     });
                 }
@@ -746,7 +1074,7 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 state.push_expr(
                     i,
                     dst,
-                    Expr::Field(Box::new(state.expr(array)), "length".to_owned()),
+                    Expr::Field(Box::new(state.expr(array)), "length".into()),
                 );
             }
             &Opcode::GetArray { dst, array, index } => {
@@ -773,7 +1101,12 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
                 });
             }
             //endregion
-            _ => {}
+            // Runtime checks without an equivalent in the source
+            Opcode::NullCheck { .. } | Opcode::Assert | Opcode::Nop => {}
+            _ => state.report(
+                DiagnosticKind::UnsupportedOpcode,
+                format!("{} is not decompiled", o.name()),
+            ),
         }
         state.scopes.advance();
     }
@@ -791,22 +1124,80 @@ pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
             Box::new(post::Trace),
         ],
     );
+    naming::name_variables(code, f, &mut statements, &options.naming);
+    for rewrite in &options.rewrites {
+        rewrite.apply(code, f, &mut statements);
+    }
 
-    statements
+    Ok((statements, state.diagnostics.into_inner()))
 }
 
 /// Decompile a function out of context
-pub fn decompile_function(code: &Bytecode, f: &Function) -> Method {
-    Method {
+pub fn decompile_function(code: &Bytecode, f: &Function) -> Result<Method> {
+    decompile_function_with(code, f, &InlineOptions::default())
+}
+
+/// Decompile a function out of context, choosing which values are inlined
+pub fn decompile_function_with(
+    code: &Bytecode,
+    f: &Function,
+    options: &InlineOptions,
+) -> Result<Method> {
+    Ok(Method {
         fun: f.findex,
         static_: true,
         dynamic: false,
-        statements: decompile_code(code, f),
+        statements: decompile_code_with(code, f, options)?,
+    })
+}
+
+/// Decompile a virtual type to a typedef, using its synthesized name.
+pub fn decompile_typedef(code: &Bytecode, ty: RefType) -> Option<Typedef> {
+    match ty.resolve(&code.types) {
+        Type::Virtual { fields } => Some(Typedef {
+            name: ty.display(code),
+            fields: fields.iter().map(|f| (f.name.display(code), f.t)).collect(),
+        }),
+        _ => None,
     }
 }
 
 /// Decompile a class with its static and instance fields and methods.
-pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
+pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Result<Class> {
+    decompile_class_with(code, obj, &InlineOptions::default())
+}
+
+/// Decompile a class, choosing which values are inlined in its methods
+pub fn decompile_class_with(
+    code: &Bytecode,
+    obj: &TypeObj,
+    options: &InlineOptions,
+) -> Result<Class> {
+    decompile_class_in(code, &MemberIndex::new(code), obj, options)
+}
+
+/// [decompile_class_with] with the [MemberIndex] of the bytecode
+pub(crate) fn decompile_class_in(
+    code: &Bytecode,
+    members: &MemberIndex,
+    obj: &TypeObj,
+    options: &InlineOptions,
+) -> Result<Class> {
+    class(code, obj, |f| {
+        decompile_in(code, members, f, options).map(|(statements, _)| statements)
+    })
+}
+
+/// Fields and methods of a class without decompiling the methods, to declare it as an extern
+pub fn declare_class(code: &Bytecode, obj: &TypeObj) -> Result<Class> {
+    class(code, obj, |_| Ok(Vec::new()))
+}
+
+fn class(
+    code: &Bytecode,
+    obj: &TypeObj,
+    body: impl Fn(&Function) -> Result<Vec<Statement>>,
+) -> Result<Class> {
     let static_type = obj.get_static_type(code);
 
     let mut fields = Vec::new();
@@ -847,7 +1238,7 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
             fun: *fun,
             static_: false,
             dynamic: true,
-            statements: decompile_code(code, fun.resolve_as_fn(code).unwrap()),
+            statements: body(resolve_fn(code, *fun)?)?,
         })
     }
     if let Some(ty) = static_type {
@@ -856,7 +1247,7 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
                 fun: *fun,
                 static_: true,
                 dynamic: false,
-                statements: decompile_code(code, fun.resolve_as_fn(code).unwrap()),
+                statements: body(resolve_fn(code, *fun)?)?,
             })
         }
     }
@@ -865,11 +1256,11 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
             fun: f.findex,
             static_: false,
             dynamic: false,
-            statements: decompile_code(code, f.findex.resolve_as_fn(code).unwrap()),
+            statements: body(resolve_fn(code, f.findex)?)?,
         })
     }
 
-    Class {
+    Ok(Class {
         name: obj.name.resolve(&code.strings).to_owned(),
         parent: obj
             .super_
@@ -877,5 +1268,283 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
             .map(|ty| ty.name.display(code)),
         fields,
         methods,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefField, RefFun, RefType, Reg, Type};
+    use hlbc::Bytecode;
+
+    use crate::ast::Statement;
+    use crate::diagnostics::DiagnosticKind;
+    use crate::fmt::FormatOptions;
+    use crate::inline::InlineOptions;
+    use crate::{decompile_code, decompile_code_diagnostics};
+
+    /// Decompile a function taking an int, `body` gets the int type and `next(): Int`. `names` are the debug names
+    /// of the values written by instructions. Comments are left out.
+    fn decompile(
+        names: &[(&str, usize)],
+        body: impl FnOnce(&mut FunctionBuilder, RefType, RefFun),
+    ) -> Vec<String> {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let next_t = b.fun_type(&[], i32_);
+        let ty = b.fun_type(&[i32_], i32_);
+        let next = b.findex();
+        let findex = b.findex();
+        b.function(next, next_t, vec![i32_], vec![Opcode::Ret { ret: Reg(0) }]);
+        let mut f = FunctionBuilder::new(ty, &[i32_]);
+        body(&mut f, i32_, next);
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(findex, ty, regs, ops);
+        let mut code: Bytecode = b.build().unwrap();
+        let assigns = names
+            .iter()
+            .map(|&(name, pos)| (code.add_string(name), pos + 1))
+            .collect();
+        code.functions[1].assigns = Some(assigns);
+        statements(&code, findex)
+    }
+
+    /// Decompiled statements of a function, without the comments
+    fn statements(code: &Bytecode, findex: RefFun) -> Vec<String> {
+        let f = findex.resolve_as_fn(code).unwrap();
+        decompile_code(code, f)
+            .unwrap()
+            .iter()
+            .filter(|s| !matches!(s, Statement::Comment(_)))
+            .map(|s| s.display(&FormatOptions::new(""), code, f).to_string())
+            .collect()
+    }
+
+    /// `new Point(x, 2)`, the constructor assigns its arguments to the fields in order with `struct_init`
+    fn construct(struct_init: bool) -> Vec<String> {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let i32_ = b.ty(Type::I32);
+        let point = b.class("Point", None, &[("x", i32_), ("y", i32_)], &[]);
+        let ctor_t = b.method_type(&[point, i32_, i32_], void);
+        let ty = b.fun_type(&[i32_], point);
+        let two = b.int(2);
+        let [ctor, findex] = [(); 2].map(|_| b.findex());
+        let (x, y) = if struct_init {
+            (Reg(1), Reg(2))
+        } else {
+            (Reg(2), Reg(1))
+        };
+        let set = |field, src| Opcode::SetField {
+            obj: Reg(0),
+            field: RefField(field),
+            src,
+        };
+        let body = vec![set(0, x), set(1, y), Opcode::Ret { ret: Reg(3) }];
+        b.function(ctor, ctor_t, vec![point, i32_, i32_, void], body);
+
+        let mut f = FunctionBuilder::new(ty, &[i32_]);
+        let (p, c, r) = (f.reg(point), f.reg(i32_), f.reg(void));
+        f.emit(Opcode::New { dst: p });
+        f.emit(Opcode::Int { dst: c, ptr: two });
+        f.emit(Opcode::Call3 {
+            dst: r,
+            fun: ctor,
+            arg0: p,
+            arg1: f.arg(0),
+            arg2: c,
+        });
+        f.emit(Opcode::Ret { ret: p });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(findex, ty, regs, ops);
+        statements(&b.build().unwrap(), findex)
+    }
+
+    #[test]
+    fn call_used_twice() {
+        let stmts = decompile(&[], |f, int, next| {
+            let [r, sum] = [(); 2].map(|_| f.reg(int));
+            f.emit(Opcode::Call0 { dst: r, fun: next });
+            f.emit(Opcode::Add {
+                dst: sum,
+                a: r,
+                b: r,
+            });
+            f.emit(Opcode::Ret { ret: sum });
+        });
+        assert_eq!(stmts, ["var reg1 = _();", "return reg1 + reg1;"]);
+    }
+
+    #[test]
+    fn unused_call() {
+        let stmts = decompile(&[], |f, int, next| {
+            let x = f.arg(0);
+            let r = f.reg(int);
+            f.emit(Opcode::Call0 { dst: r, fun: next });
+            f.emit(Opcode::Ret { ret: x });
+        });
+        assert_eq!(stmts, ["_();", "return reg0;"]);
+    }
+
+    #[test]
+    fn call_used_once() {
+        let stmts = decompile(&[], |f, int, next| {
+            let x = f.arg(0);
+            let [r, sum] = [(); 2].map(|_| f.reg(int));
+            f.emit(Opcode::Call0 { dst: r, fun: next });
+            f.emit(Opcode::Add {
+                dst: sum,
+                a: r,
+                b: x,
+            });
+            f.emit(Opcode::Ret { ret: sum });
+        });
+        assert_eq!(stmts, ["return _() + reg0;"]);
+    }
+
+    #[test]
+    fn copy_modified() {
+        // The copy is modified, the original value is still read after
+        let stmts = decompile(&[("b", 0)], |f, int, _| {
+            let x = f.arg(0);
+            let b = f.reg(int);
+            f.emit(Opcode::Mov { dst: b, src: x });
+            f.emit(Opcode::Incr { dst: b });
+            f.emit(Opcode::Ret { ret: x });
+        });
+        assert_eq!(stmts, ["var b = reg0;", "b++;", "return reg0;"]);
+    }
+
+    #[test]
+    fn copy_in_branch() {
+        // The copy only happens on one path
+        let stmts = decompile(&[("b", 1)], |f, int, _| {
+            let x = f.arg(0);
+            let b = f.reg(int);
+            let end = f.label();
+            f.jump(Opcode::JNotNull { reg: x, offset: 0 }, end);
+            f.emit(Opcode::Mov { dst: b, src: x });
+            f.place(end);
+            f.emit(Opcode::Ret { ret: x });
+        });
+        assert_eq!(stmts.last().unwrap(), "return reg0;");
+    }
+
+    #[test]
+    fn copy() {
+        let stmts = decompile(&[("b", 0)], |f, int, _| {
+            let x = f.arg(0);
+            let b = f.reg(int);
+            f.emit(Opcode::Mov { dst: b, src: x });
+            f.emit(Opcode::Ret { ret: x });
+        });
+        assert_eq!(stmts, ["var b = reg0;", "return b;"]);
+    }
+
+    #[test]
+    fn struct_init() {
+        assert_eq!(construct(true), ["return {x: reg0, y: 2};"]);
+        assert_eq!(construct(false), ["return new Point(reg0, 2);"]);
+    }
+
+    /// `B extends A`, `B.run` calls `A.run` and `A.helper` on `this`, `B.__constructor__` calls the constructor of A
+    fn super_calls(ctor: &str) -> Vec<String> {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let [run_a, helper, run_b] = [(); 3].map(|_| b.findex());
+        let a = b.class("A", None, &[], &[(ctor, run_a), ("helper", helper)]);
+        let bb = b.class("B", Some(a), &[], &[(ctor, run_b)]);
+        for (f, ty) in [(run_a, a), (helper, a)] {
+            let t = b.method_type(&[ty], void);
+            b.function(f, t, vec![ty, void], vec![Opcode::Ret { ret: Reg(1) }]);
+        }
+        let t = b.method_type(&[bb], void);
+        let call = |fun| Opcode::Call1 {
+            dst: Reg(1),
+            fun,
+            arg0: Reg(0),
+        };
+        let body = vec![call(run_a), call(helper), Opcode::Ret { ret: Reg(1) }];
+        b.function(run_b, t, vec![bb, void], body);
+        statements(&b.build().unwrap(), run_b)
+    }
+
+    #[test]
+    fn super_call() {
+        assert_eq!(super_calls("run"), ["super.run();", "this.helper();"]);
+        assert_eq!(
+            super_calls("__constructor__"),
+            ["super();", "this.helper();"]
+        );
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let ty = b.fun_type(&[i32_], i32_);
+        let findex = b.findex();
+        let body = vec![
+            Opcode::GetTID {
+                dst: Reg(1),
+                src: Reg(0),
+            },
+            Opcode::Add {
+                dst: Reg(2),
+                a: Reg(1),
+                b: Reg(0),
+            },
+            Opcode::Ret { ret: Reg(2) },
+        ];
+        b.function(findex, ty, vec![i32_; 3], body);
+        let code = b.build().unwrap();
+        let f = findex.resolve_as_fn(&code).unwrap();
+        let (_, diagnostics) =
+            decompile_code_diagnostics(&code, f, &InlineOptions::default()).unwrap();
+        let found: Vec<_> = diagnostics.iter().map(|d| (d.pos, d.kind)).collect();
+        assert_eq!(
+            found,
+            [
+                (0, DiagnosticKind::UnsupportedOpcode),
+                (1, DiagnosticKind::Fallback)
+            ]
+        );
+        assert_eq!(diagnostics[1].marker(), "[missing expr]");
+        assert_eq!(statements(&code, findex), ["return [missing expr] + reg0;"]);
+    }
+
+    #[test]
+    fn static_call() {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let ty = b.fun_type(&[i32_], i32_);
+        let [twice, findex] = [(); 2].map(|_| b.findex());
+        // Statics of the class Util
+        b.class("$Util", None, &[], &[("twice", twice)]);
+        let body = vec![
+            Opcode::Add {
+                dst: Reg(0),
+                a: Reg(0),
+                b: Reg(0),
+            },
+            Opcode::Ret { ret: Reg(0) },
+        ];
+        b.function(twice, ty, vec![i32_], body);
+        let body = vec![
+            Opcode::Call1 {
+                dst: Reg(1),
+                fun: twice,
+                arg0: Reg(0),
+            },
+            Opcode::Ret { ret: Reg(1) },
+        ];
+        b.function(findex, ty, vec![i32_, i32_], body);
+        let code = b.build().unwrap();
+        let f = findex.resolve_as_fn(&code).unwrap();
+        let stmts = decompile_code(&code, f).unwrap();
+        // No comment with the function index
+        assert_eq!(stmts.len(), 1);
+        assert_eq!(statements(&code, findex), ["return Util.twice(reg0);"]);
     }
 }
@@ -0,0 +1,211 @@
+use hlbc::extract::extract;
+use hlbc::types::{Function, RefFun};
+use hlbc::Bytecode;
+
+use crate::{decompile_function, Error, Result};
+
+/// A standalone bytecode reproducing a failure
+#[derive(Debug)]
+pub struct Minimized {
+    pub code: Bytecode,
+    /// The reduced function, called by the entrypoint
+    pub findex: RefFun,
+}
+
+/// Decompile a function and return how the decompiler failed, if it did. This is where the decompiler panicked or
+/// the message of the [Error::Internal]. Invalid bytecode isn't a failure.
+pub fn failure(code: &Bytecode, f: &Function) -> Option<String> {
+    match decompile_function(code, f) {
+        Err(Error::Internal {
+            message, location, ..
+        }) => Some(location.unwrap_or(message)),
+        _ => None,
+    }
+}
+
+/// Extract the function to a standalone bytecode and remove as many instructions as possible while `fails` still
+/// returns true. Returns `None` if the extracted function doesn't fail to begin with.
+///
+/// Candidates keep every jump in bounds and end with a terminator so they are still valid functions.
+pub fn minimize(
+    code: &Bytecode,
+    f: RefFun,
+    mut fails: impl FnMut(&Bytecode, &Function) -> bool,
+) -> Result<Option<Minimized>> {
+    let mut current = extract(code, f)?;
+    let findex = root(&current);
+    if !fails(&current, findex.resolve_as_fn(&current).unwrap()) {
+        return Ok(None);
+    }
+
+    // Remove chunks of instructions, halving the chunk size when no chunk can be removed
+    let x = current
+        .functions
+        .iter()
+        .position(|fun| fun.findex == findex)
+        .unwrap();
+    let mut chunk = current.functions[x].ops.len() / 2;
+    while chunk > 0 {
+        let mut removed = false;
+        let mut pos = 0;
+        while pos < current.functions[x].ops.len() {
+            let saved = current.functions[x].clone();
+            let end = (pos + chunk).min(saved.ops.len());
+            current.edit().remove_ops(findex, pos..end);
+            if is_valid(&current.functions[x]) && fails(&current, &current.functions[x]) {
+                removed = true;
+            } else {
+                current.functions[x] = saved;
+                pos += chunk;
+            }
+        }
+        if !removed {
+            chunk /= 2;
+        }
+    }
+
+    // Extract again to drop what the reduced function doesn't use anymore
+    let code = extract(&current, findex)?;
+    let reduced = root(&code);
+    Ok(Some(
+        if fails(&code, reduced.resolve_as_fn(&code).unwrap()) {
+            Minimized {
+                code,
+                findex: reduced,
+            }
+        } else {
+            Minimized {
+                code: current,
+                findex,
+            }
+        },
+    ))
+}
+
+/// The function called by the entrypoint of an extracted bytecode
+fn root(code: &Bytecode) -> RefFun {
+    let main = code.entrypoint.resolve_as_fn(code).unwrap();
+    main.find_fun_refs().next().unwrap().2
+}
+
+fn is_valid(f: &Function) -> bool {
+    f.ops.last().map_or(false, |o| o.is_terminator())
+        && f.ops.iter().enumerate().all(|(pos, o)| {
+            // Negative targets wrap around
+            o.jump_targets(pos).into_iter().all(|t| t < f.ops.len())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefField, RefFun, Type};
+    use hlbc::Bytecode;
+
+    use crate::minimize::{failure, minimize};
+
+    /// Bytecode with a function calling the function built by `body` and made of padding around it
+    fn build(body: impl FnOnce(&mut BytecodeBuilder, &mut FunctionBuilder)) -> (Bytecode, RefFun) {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let i32_ = b.ty(Type::I32);
+        let one = b.int(1);
+        let ty = b.fun_type(&[i32_], void);
+        let main_ty = b.fun_type(&[], void);
+        let [findex, main] = [(); 2].map(|_| b.findex());
+        let mut f = FunctionBuilder::new(ty, &[i32_]);
+        let [x, y] = [(); 2].map(|_| f.reg(i32_));
+        let padding = [
+            Opcode::Int { dst: x, ptr: one },
+            Opcode::Int { dst: y, ptr: one },
+            Opcode::Mul { dst: y, a: x, b: y },
+        ];
+        for o in padding.clone() {
+            f.emit(o);
+        }
+        body(&mut b, &mut f);
+        for o in padding {
+            f.emit(o);
+        }
+        let ret = f.reg(void);
+        f.emit(Opcode::Ret { ret });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(findex, ty, regs, ops);
+
+        let mut f = FunctionBuilder::new(main_ty, &[]);
+        let (arg, ret) = (f.reg(i32_), f.reg(void));
+        f.emit(Opcode::Int { dst: arg, ptr: one })
+            .emit(Opcode::Call1 {
+                dst: ret,
+                fun: findex,
+                arg0: arg,
+            })
+            .emit(Opcode::Ret { ret });
+        let (_, regs, ops) = f.finish().unwrap();
+        b.function(main, main_ty, regs, ops);
+        b.entrypoint(main);
+        (b.build().unwrap(), findex)
+    }
+
+    fn fails(code: &Bytecode, findex: RefFun) -> Option<String> {
+        failure(code, findex.resolve_as_fn(code).unwrap())
+    }
+
+    #[test]
+    fn no_failure() {
+        let (code, findex) = build(|_, _| {});
+        assert_eq!(fails(&code, findex), None);
+        assert!(
+            minimize(&code, findex, |code, f| failure(code, f).is_some())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn backward_jump() {
+        // A loop without its Label
+        let (code, findex) = build(|_, f| {
+            let (start, arg) = (f.label(), f.arg(0));
+            f.place(start)
+                .emit(Opcode::Incr { dst: arg })
+                .jump(Opcode::JAlways { offset: 0 }, start);
+        });
+        let location = fails(&code, findex).unwrap();
+        assert_eq!(location, "Backward jump but we aren't in a loop");
+
+        let m = minimize(&code, findex, |code, f| {
+            failure(code, f).as_ref() == Some(&location)
+        })
+        .unwrap()
+        .unwrap();
+        let reduced = m.findex.resolve_as_fn(&m.code).unwrap();
+        assert!(reduced.ops.len() < 3, "{}", reduced.display(&m.code));
+        assert_eq!(fails(&m.code, m.findex).as_ref(), Some(&location));
+    }
+
+    #[test]
+    fn panic_location() {
+        // Calls a method of `this`, which isn't an object
+        let (code, findex) = build(|b, f| {
+            let void = b.ty(Type::Void);
+            let dst = f.reg(void);
+            f.emit(Opcode::CallThis {
+                dst,
+                field: RefField(0),
+                args: Vec::new(),
+            });
+        });
+        let location = fails(&code, findex).unwrap();
+        assert!(location.starts_with("hlbc-decompiler/src/"), "{location}");
+
+        let m = minimize(&code, findex, |code, f| {
+            failure(code, f).as_ref() == Some(&location)
+        })
+        .unwrap()
+        .unwrap();
+        let reduced = m.findex.resolve_as_fn(&m.code).unwrap();
+        assert_eq!(reduced.ops.len(), 2, "{}", reduced.display(&m.code));
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::{HashMap, HashSet};
+
+use hlbc::types::{Function, Reg, Str};
+use hlbc::Bytecode;
+
+use crate::ast::{Constant, Expr, Operation, Statement};
+use crate::post::{visit, AstVisitor};
+
+/// Which heuristics name the variables without a debug name, from how their value is used. A variable gets a single
+/// name in the whole function, names already taken get a number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamingOptions {
+    /// Loop indices become `i`, `j`, `k` from the outermost loop
+    pub loops: bool,
+    /// Results of getters take the name of the property : `getPosition()` gives `position`
+    pub calls: bool,
+    /// Values stored in a field take its name : `this.health = x` names `x` `health`
+    pub fields: bool,
+}
+
+impl Default for NamingOptions {
+    fn default() -> Self {
+        Self {
+            loops: true,
+            calls: true,
+            fields: true,
+        }
+    }
+}
+
+impl NamingOptions {
+    /// Keep the register names
+    pub fn none() -> Self {
+        Self {
+            loops: false,
+            calls: false,
+            fields: false,
+        }
+    }
+}
+
+/// Unnamed variable of an expression, looking through casts
+fn unnamed(expr: &Expr) -> Option<Reg> {
+    match expr {
+        Expr::Variable(r, None) => Some(*r),
+        Expr::Cast(e, _) => unnamed(e),
+        _ => None,
+    }
+}
+
+/// Name of the property read by a getter : `getPosition()` or `obj.getPosition()` gives `position`
+fn getter_name(code: &Bytecode, fun: &Expr) -> Option<String> {
+    let name = match fun {
+        Expr::FunRef(fun) => fun.name(code)?,
+        Expr::Field(_, name) => name,
+        _ => return None,
+    };
+    let mut chars = name.strip_prefix("get")?.chars();
+    let first = chars.next().filter(char::is_ascii_uppercase)?;
+    Some(first.to_ascii_lowercase().to_string() + chars.as_str())
+}
+
+/// Finds `reg++` and `reg = reg + 1`
+struct Increments {
+    reg: Reg,
+    found: bool,
+}
+
+impl AstVisitor for Increments {
+    fn visit_stmt(&mut self, _code: &Bytecode, stmt: &mut Statement) {
+        if let Statement::Assign {
+            variable,
+            assign: Expr::Op(Operation::Add(a, b)),
+            ..
+        } = stmt
+        {
+            self.found |= unnamed(variable) == Some(self.reg)
+                && unnamed(a) == Some(self.reg)
+                && matches!(**b, Expr::Constant(Constant::Int(1)));
+        }
+    }
+
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        if let Expr::Op(Operation::Incr(e)) = expr {
+            self.found |= unnamed(e) == Some(self.reg);
+        }
+    }
+}
+
+/// Unnamed variables compared in the condition of a loop and incremented in its body, with the loop depth
+fn loop_indices(
+    code: &Bytecode,
+    stmts: &mut [Statement],
+    depth: usize,
+    indices: &mut Vec<(Reg, usize)>,
+) {
+    for stmt in stmts {
+        match stmt {
+            Statement::While { cond, stmts } => {
+                if let Expr::Op(
+                    Operation::Lt(a, b)
+                    | Operation::Lte(a, b)
+                    | Operation::Gt(a, b)
+                    | Operation::Gte(a, b),
+                ) = cond
+                {
+                    for reg in [unnamed(a), unnamed(b)].into_iter().flatten() {
+                        let mut incr = Increments { reg, found: false };
+                        visit(code, stmts, &mut [Box::new(&mut incr)]);
+                        if incr.found {
+                            indices.push((reg, depth));
+                            break;
+                        }
+                    }
+                }
+                loop_indices(code, stmts, depth + 1, indices);
+            }
+            Statement::IfElse { if_, else_, .. } => {
+                loop_indices(code, if_, depth, indices);
+                loop_indices(code, else_, depth, indices);
+            }
+            Statement::Switch { default, cases, .. } => {
+                loop_indices(code, default, depth, indices);
+                for (_, case) in cases {
+                    loop_indices(code, case, depth, indices);
+                }
+            }
+            Statement::Try { stmts } | Statement::Catch { stmts } => {
+                loop_indices(code, stmts, depth, indices)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collects the names in use and the names suggested by getters and field stores
+#[derive(Default)]
+struct Usages {
+    taken: HashSet<Str>,
+    calls: Vec<(Reg, String)>,
+    fields: Vec<(Reg, String)>,
+}
+
+impl AstVisitor for Usages {
+    fn visit_stmt(&mut self, code: &Bytecode, stmt: &mut Statement) {
+        if let Statement::Assign {
+            variable, assign, ..
+        } = stmt
+        {
+            if let (Some(reg), Expr::Call(call)) = (unnamed(variable), &*assign) {
+                if let Some(name) = getter_name(code, &call.fun) {
+                    self.calls.push((reg, name));
+                }
+            }
+            if let (Expr::Field(_, name), Some(reg)) = (&*variable, unnamed(assign)) {
+                self.fields.push((reg, name.to_string()));
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        if let Expr::Variable(_, Some(name)) = expr {
+            self.taken.insert(name.clone());
+        }
+    }
+}
+
+/// Gives the chosen names to the variables
+struct Rename(HashMap<Reg, Str>);
+
+impl AstVisitor for Rename {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        if let Expr::Variable(reg, name @ None) = expr {
+            *name = self.0.get(reg).cloned();
+        }
+    }
+}
+
+/// Name the unnamed variables of a function body. Arguments are left alone as their name is in the signature.
+pub(crate) fn name_variables(
+    code: &Bytecode,
+    f: &Function,
+    stmts: &mut [Statement],
+    options: &NamingOptions,
+) {
+    if *options == NamingOptions::none() {
+        return;
+    }
+    let mut usages = Usages::default();
+    visit(code, stmts, &mut [Box::new(&mut usages)]);
+    let nargs = f.ty(code).args.len();
+    for i in 0..nargs {
+        if let Some(name) = f.arg_name(code, i) {
+            usages.taken.insert(name.into());
+        }
+    }
+
+    let mut indices = Vec::new();
+    if options.loops {
+        loop_indices(code, stmts, 0, &mut indices);
+    }
+    let candidates = indices
+        .into_iter()
+        .map(|(reg, depth)| {
+            let name = match depth {
+                0 => "i".to_owned(),
+                1 => "j".to_owned(),
+                2 => "k".to_owned(),
+                _ => format!("i{depth}"),
+            };
+            (reg, name)
+        })
+        .chain(usages.calls.into_iter().filter(|_| options.calls))
+        .chain(usages.fields.into_iter().filter(|_| options.fields));
+
+    let mut names = HashMap::new();
+    let mut taken = usages.taken;
+    for (reg, base) in candidates {
+        if reg.0 as usize >= nargs && !names.contains_key(&reg) {
+            let mut name = base.clone();
+            let mut n = 2;
+            while taken.contains(name.as_str()) {
+                name = format!("{base}{n}");
+                n += 1;
+            }
+            let name: Str = name.as_str().into();
+            taken.insert(name.clone());
+            names.insert(reg, name);
+        }
+    }
+    if !names.is_empty() {
+        visit(code, stmts, &mut [Box::new(Rename(names))]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefField, Reg, Type};
+
+    use crate::decompile_code_with;
+    use crate::fmt::FormatOptions;
+    use crate::inline::InlineOptions;
+    use crate::naming::NamingOptions;
+
+    /// A loop over `n` storing `getPosition(n)` in `p.health`
+    fn decompile(naming: NamingOptions) -> Vec<String> {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let i32_ = b.ty(Type::I32);
+        let get = b.findex();
+        let player = b.class("Player", None, &[("health", i32_)], &[("getPosition", get)]);
+        let get_t = b.fun_type(&[i32_], i32_);
+        b.function(get, get_t, vec![i32_], vec![Opcode::Ret { ret: Reg(0) }]);
+
+        let ty = b.fun_type(&[player, i32_], void);
+        let mut f = FunctionBuilder::new(ty, &[player, i32_]);
+        let (p, n) = (f.arg(0), f.arg(1));
+        let (i, pos, ret) = (f.reg(i32_), f.reg(i32_), f.reg(void));
+        let (head, end) = (f.label(), f.label());
+        let zero = b.int(0);
+        f.emit(Opcode::Int { dst: i, ptr: zero }).place(head);
+        f.emit(Opcode::Label);
+        f.jump(
+            Opcode::JSGte {
+                a: i,
+                b: n,
+                offset: 0,
+            },
+            end,
+        );
+        f.emit(Opcode::Call1 {
+            dst: pos,
+            fun: get,
+            arg0: n,
+        });
+        f.emit(Opcode::SetField {
+            obj: p,
+            field: RefField(0),
+            src: pos,
+        });
+        f.emit(Opcode::Incr { dst: i });
+        f.jump(Opcode::JAlways { offset: 0 }, head);
+        f.place(end).emit(Opcode::Ret { ret });
+        let (_, regs, ops) = f.finish().unwrap();
+        let findex = b.findex();
+        b.function(findex, ty, regs, ops);
+        let code = b.build().unwrap();
+
+        let f = findex.resolve_as_fn(&code).unwrap();
+        let options = InlineOptions {
+            naming,
+            ..InlineOptions::flat()
+        };
+        decompile_code_with(&code, f, &options)
+            .unwrap()
+            .iter()
+            .map(|s| s.display(&FormatOptions::new(""), &code, f).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn heuristics() {
+        let stmts = decompile(NamingOptions::default());
+        assert_eq!(stmts[0], "var i = 0;");
+        assert!(stmts[1].starts_with("while (reg1 > i) {"));
+        assert!(
+            stmts[1].contains("var position = getPosition(reg1);\nreg0.health = position;\ni++;")
+        );
+
+        let stmts = decompile(NamingOptions {
+            fields: true,
+            ..NamingOptions::none()
+        });
+        assert!(stmts[1].contains("var health = getPosition(reg1);"));
+
+        let stmts = decompile(NamingOptions::none());
+        assert_eq!(stmts[0], "var reg2 = 0;");
+        assert!(stmts[1].contains("var reg3 = getPosition(reg1);"));
+    }
+}
@@ -0,0 +1,729 @@
+//! Find and replace expressions in the decompiled code.
+//!
+//! Patterns are written like Haxe expressions : calls, field accesses, operators, literals and identifiers, with
+//! wildcards. `$name` matches any expression and captures it, a name used twice must match the same expression. `$_`
+//! matches anything without capturing it. A field name can be a wildcard too, `obj.$f` captures the name as a string.
+//!
+//! Names are compared with the decompiled output : `Reflect.field` matches the call to the static function. A
+//! [Rewrite] replaces every match with another expression using the captures, a string captured as a field name
+//! becomes a field access :
+//! ```text
+//! Reflect.field($o, $f) => $o.$f
+//! ```
+//! turns `Reflect.field(cfg, "debug")` into `cfg.debug`. Rewrites are applied by the decompiler after the other
+//! passes, add them to [InlineOptions::rewrites](crate::inline::InlineOptions::rewrites).
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use hlbc::types::{Function, Reg};
+use hlbc::Bytecode;
+
+use crate::ast::{Call, Constant, Expr, Operation, Statement};
+use crate::fmt::FormatOptions;
+use crate::post::{visit, AstVisitor};
+
+/// Error while parsing a pattern
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid pattern at {pos} : {message}")]
+pub struct PatternError {
+    /// Position in the pattern, in chars
+    pub pos: usize,
+    pub message: String,
+}
+
+/// Name of a field, fixed or captured
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldName {
+    Name(String),
+    Capture(String),
+}
+
+/// An expression pattern, see [pattern](crate::pattern)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `$name`, `$_` if None
+    Wildcard(Option<String>),
+    Ident(String),
+    Literal(Constant),
+    Field(Box<Pattern>, FieldName),
+    Call(Box<Pattern>, Vec<Pattern>),
+    /// Unary operator (`!` or `-`) and its operand
+    Unary(&'static str, Box<Pattern>),
+    /// Binary operator and its operands
+    Binary(&'static str, Box<Pattern>, Box<Pattern>),
+}
+
+/// Binary operators by increasing precedence
+const BINARY: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<=", ">=", "<", ">"],
+    &["^"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    src: &'a str,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, PatternError> {
+        Err(PatternError {
+            pos: self.pos,
+            message: message.into(),
+        })
+    }
+
+    fn skip_ws(&mut self) {
+        while self
+            .chars
+            .get(self.pos)
+            .map_or(false, |c| c.is_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume `token` if it's next
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_ws();
+        let len = token.chars().count();
+        if self.chars.len() >= self.pos + len
+            && self.chars[self.pos..self.pos + len]
+                .iter()
+                .copied()
+                .eq(token.chars())
+        {
+            self.pos += len;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), PatternError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{token}'"))
+        }
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        self.skip_ws();
+        let start = self.pos;
+        while self.chars.get(self.pos).map_or(false, |&c| {
+            c.is_alphanumeric() || c == '_' || (c == '$' && self.pos == start)
+        }) {
+            self.pos += 1;
+        }
+        if self.pos > start && !self.chars[start].is_ascii_digit() {
+            Some(self.chars[start..self.pos].iter().collect())
+        } else {
+            self.pos = start;
+            None
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Pattern, PatternError> {
+        let Some(ops) = BINARY.get(level) else {
+            return self.unary();
+        };
+        let mut lhs = self.binary(level + 1)?;
+        'outer: loop {
+            for &op in *ops {
+                if self.eat_op(op) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Pattern::Binary(op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    /// Consume an operator, `<` and `>` aren't taken from the start of `<<`, `<=` ...
+    fn eat_op(&mut self, op: &str) -> bool {
+        let save = self.pos;
+        if !self.eat(op) {
+            return false;
+        }
+        if (op == "<" || op == ">") && matches!(self.chars.get(self.pos), Some('<' | '>' | '=')) {
+            self.pos = save;
+            return false;
+        }
+        true
+    }
+
+    fn unary(&mut self) -> Result<Pattern, PatternError> {
+        if self.eat("!") {
+            return Ok(Pattern::Unary("!", Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            // Negative numbers are constants in the output
+            return Ok(match self.unary()? {
+                Pattern::Literal(Constant::Int(i)) => Pattern::Literal(Constant::Int(-i)),
+                Pattern::Literal(Constant::Float(f)) => Pattern::Literal(Constant::Float(-f)),
+                p => Pattern::Unary("-", Box::new(p)),
+            });
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Pattern, PatternError> {
+        let mut p = self.primary()?;
+        loop {
+            if self.eat(".") {
+                let name = match self.ident() {
+                    Some(name) => match name.strip_prefix('$') {
+                        Some(capture) => FieldName::Capture(capture.to_owned()),
+                        None => FieldName::Name(name),
+                    },
+                    None => return self.error("expected a field name"),
+                };
+                p = Pattern::Field(Box::new(p), name);
+            } else if self.eat("(") {
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.binary(0)?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                p = Pattern::Call(Box::new(p), args);
+            } else {
+                return Ok(p);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Pattern, PatternError> {
+        if self.eat("(") {
+            let p = self.binary(0)?;
+            self.expect(")")?;
+            return Ok(p);
+        }
+        if self.eat("\"") {
+            let mut s = String::new();
+            loop {
+                match self.chars.get(self.pos) {
+                    Some('"') => break,
+                    Some('\\') => {
+                        self.pos += 1;
+                        match self.chars.get(self.pos) {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(&c) => s.push(c),
+                            None => return self.error("unterminated string"),
+                        }
+                    }
+                    Some(&c) => s.push(c),
+                    None => return self.error("unterminated string"),
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            return Ok(Pattern::Literal(Constant::String(s.as_str().into())));
+        }
+        if let Some(name) = self.ident() {
+            return Ok(match name.as_str() {
+                "$_" => Pattern::Wildcard(None),
+                "true" => Pattern::Literal(Constant::Bool(true)),
+                "false" => Pattern::Literal(Constant::Bool(false)),
+                "null" => Pattern::Literal(Constant::Null),
+                "this" => Pattern::Literal(Constant::This),
+                _ => match name.strip_prefix('$') {
+                    Some(capture) => Pattern::Wildcard(Some(capture.to_owned())),
+                    None => Pattern::Ident(name),
+                },
+            });
+        }
+        let start = self.pos;
+        while self
+            .chars
+            .get(self.pos)
+            .map_or(false, |c| c.is_ascii_digit() || *c == '.')
+        {
+            self.pos += 1;
+        }
+        let number: String = self.chars[start..self.pos].iter().collect();
+        if let Ok(i) = number.parse::<i32>() {
+            Ok(Pattern::Literal(Constant::Int(i)))
+        } else if let Ok(f) = number.parse::<f64>() {
+            Ok(Pattern::Literal(Constant::Float(f)))
+        } else {
+            self.pos = start;
+            self.error(format!("unexpected input in '{}'", self.src))
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            pos: 0,
+            src: s,
+        };
+        let p = parser.binary(0)?;
+        parser.skip_ws();
+        if parser.pos < parser.chars.len() {
+            return parser.error("unexpected input after the expression");
+        }
+        Ok(p)
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Wildcard(None) => write!(f, "$_"),
+            Pattern::Wildcard(Some(name)) => write!(f, "${name}"),
+            Pattern::Ident(name) => write!(f, "{name}"),
+            Pattern::Literal(c) => write!(f, "{c}"),
+            Pattern::Field(obj, FieldName::Name(name)) => write!(f, "{obj}.{name}"),
+            Pattern::Field(obj, FieldName::Capture(name)) => write!(f, "{obj}.${name}"),
+            Pattern::Call(fun, args) => {
+                write!(f, "{fun}(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                write!(f, ")")
+            }
+            Pattern::Unary(op, e) => write!(f, "{op}{}", Operand(e)),
+            Pattern::Binary(op, a, b) => write!(f, "{} {op} {}", Operand(a), Operand(b)),
+        }
+    }
+}
+
+/// Operations are parenthesized when nested
+struct Operand<'a>(&'a Pattern);
+
+impl Display for Operand<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Pattern::Binary(..) => write!(f, "({})", self.0),
+            p => write!(f, "{p}"),
+        }
+    }
+}
+
+/// Operator of a binary operation with its operands
+fn binary(op: &Operation) -> Option<(&'static str, &Expr, &Expr)> {
+    use Operation::*;
+    Some(match op {
+        Add(a, b) => ("+", a, b),
+        Sub(a, b) => ("-", a, b),
+        Mul(a, b) => ("*", a, b),
+        Div(a, b) => ("/", a, b),
+        Mod(a, b) => ("%", a, b),
+        Shl(a, b) => ("<<", a, b),
+        Shr(a, b) => (">>", a, b),
+        And(a, b) => ("&&", a, b),
+        Or(a, b) => ("||", a, b),
+        Xor(a, b) => ("^", a, b),
+        Eq(a, b) => ("==", a, b),
+        NotEq(a, b) => ("!=", a, b),
+        Gt(a, b) => (">", a, b),
+        Gte(a, b) => (">=", a, b),
+        Lt(a, b) => ("<", a, b),
+        Lte(a, b) => ("<=", a, b),
+        Neg(_) | Not(_) | Incr(_) | Decr(_) => return None,
+    })
+}
+
+fn operation(op: &str, a: Expr, b: Expr) -> Operation {
+    use Operation::*;
+    let (a, b) = (Box::new(a), Box::new(b));
+    match op {
+        "+" => Add(a, b),
+        "-" => Sub(a, b),
+        "*" => Mul(a, b),
+        "/" => Div(a, b),
+        "%" => Mod(a, b),
+        "<<" => Shl(a, b),
+        ">>" => Shr(a, b),
+        "&&" => And(a, b),
+        "||" => Or(a, b),
+        "^" => Xor(a, b),
+        "==" => Eq(a, b),
+        "!=" => NotEq(a, b),
+        ">" => Gt(a, b),
+        ">=" => Gte(a, b),
+        "<" => Lt(a, b),
+        _ => Lte(a, b),
+    }
+}
+
+/// Expressions captured by the wildcards of a pattern
+pub type Captures = BTreeMap<String, Expr>;
+
+/// Context to display expressions
+struct Ctx<'a> {
+    code: &'a Bytecode,
+    f: &'a Function,
+    opts: FormatOptions,
+}
+
+impl Ctx<'_> {
+    fn text(&self, e: &Expr) -> String {
+        e.display(&self.opts, self.code, self.f).to_string()
+    }
+
+    fn capture(&self, name: &str, e: &Expr, captures: &mut Captures) -> bool {
+        match captures.get(name) {
+            Some(prev) => self.text(prev) == self.text(e),
+            None => {
+                captures.insert(name.to_owned(), e.clone());
+                true
+            }
+        }
+    }
+
+    fn matches(&self, p: &Pattern, e: &Expr, captures: &mut Captures) -> bool {
+        match (p, e) {
+            (Pattern::Wildcard(None), _) => true,
+            (Pattern::Wildcard(Some(name)), _) => self.capture(name, e, captures),
+            (Pattern::Literal(c), Expr::Constant(ec)) => c.to_string() == ec.to_string(),
+            (Pattern::Call(fun, args), Expr::Call(call)) => {
+                args.len() == call.args.len()
+                    && self.matches(fun, &call.fun, captures)
+                    && args
+                        .iter()
+                        .zip(&call.args)
+                        .all(|(p, e)| self.matches(p, e, captures))
+            }
+            (Pattern::Field(obj, name), Expr::Field(eobj, ename)) => {
+                let name = match name {
+                    FieldName::Name(name) => name == &**ename,
+                    FieldName::Capture(c) => {
+                        let name = Expr::Constant(Constant::String(ename.clone()));
+                        self.capture(c, &name, captures)
+                    }
+                };
+                name && self.matches(obj, eobj, captures)
+            }
+            (Pattern::Unary(op, p), Expr::Op(Operation::Not(e))) if *op == "!" => {
+                self.matches(p, e, captures)
+            }
+            (Pattern::Unary(op, p), Expr::Op(Operation::Neg(e))) if *op == "-" => {
+                self.matches(p, e, captures)
+            }
+            (Pattern::Binary(op, pa, pb), Expr::Op(o)) => match binary(o) {
+                Some((eop, a, b)) if eop == *op => {
+                    self.matches(pa, a, captures) && self.matches(pb, b, captures)
+                }
+                _ => false,
+            },
+            // Names and paths like `Reflect.field` are compared with the output
+            (Pattern::Ident(_) | Pattern::Field(_, FieldName::Name(_)), _) => {
+                p.to_string() == self.text(e)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Pattern {
+    /// Names of the wildcards capturing expressions or field names
+    fn captures(&self, names: &mut Vec<String>) {
+        match self {
+            Pattern::Wildcard(Some(name)) => names.push(name.clone()),
+            Pattern::Field(obj, name) => {
+                if let FieldName::Capture(name) = name {
+                    names.push(name.clone());
+                }
+                obj.captures(names);
+            }
+            Pattern::Call(fun, args) => {
+                fun.captures(names);
+                args.iter().for_each(|a| a.captures(names));
+            }
+            Pattern::Unary(_, e) => e.captures(names),
+            Pattern::Binary(_, a, b) => {
+                a.captures(names);
+                b.captures(names);
+            }
+            Pattern::Wildcard(None) | Pattern::Ident(_) | Pattern::Literal(_) => {}
+        }
+    }
+
+    /// Build the expression, None if a field name isn't a captured string
+    fn build(&self, captures: &Captures) -> Option<Expr> {
+        Some(match self {
+            Pattern::Wildcard(name) => captures.get(name.as_ref()?)?.clone(),
+            // Only displayed, the register doesn't matter
+            Pattern::Ident(name) => Expr::Variable(Reg(0), Some(name.as_str().into())),
+            Pattern::Literal(c) => Expr::Constant(c.clone()),
+            Pattern::Field(obj, name) => {
+                let name = match name {
+                    FieldName::Name(name) => name.as_str().into(),
+                    FieldName::Capture(c) => match captures.get(c)? {
+                        Expr::Constant(Constant::String(s)) => s.clone(),
+                        _ => return None,
+                    },
+                };
+                Expr::Field(Box::new(obj.build(captures)?), name)
+            }
+            Pattern::Call(fun, args) => Expr::Call(Box::new(Call::new(
+                fun.build(captures)?,
+                args.iter()
+                    .map(|a| a.build(captures))
+                    .collect::<Option<_>>()?,
+            ))),
+            Pattern::Unary("!", e) => Expr::Op(Operation::Not(Box::new(e.build(captures)?))),
+            Pattern::Unary(_, e) => Expr::Op(Operation::Neg(Box::new(e.build(captures)?))),
+            Pattern::Binary(op, a, b) => {
+                Expr::Op(operation(op, a.build(captures)?, b.build(captures)?))
+            }
+        })
+    }
+}
+
+/// An expression matching a pattern
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub expr: Expr,
+    pub captures: Captures,
+}
+
+struct Finder<'a> {
+    ctx: Ctx<'a>,
+    pattern: &'a Pattern,
+    found: Vec<Match>,
+}
+
+impl AstVisitor for Finder<'_> {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        let mut captures = Captures::new();
+        if self.ctx.matches(self.pattern, expr, &mut captures) {
+            self.found.push(Match {
+                expr: expr.clone(),
+                captures,
+            });
+        }
+    }
+}
+
+/// Expressions of the decompiled statements of `f` matching `pattern`, innermost first
+pub fn find(code: &Bytecode, f: &Function, stmts: &[Statement], pattern: &Pattern) -> Vec<Match> {
+    let mut finder = Finder {
+        ctx: Ctx {
+            code,
+            f,
+            opts: FormatOptions::new(""),
+        },
+        pattern,
+        found: Vec::new(),
+    };
+    // The visitor doesn't change anything
+    let mut stmts = stmts.to_vec();
+    visit(code, &mut stmts, &mut [Box::new(&mut finder)]);
+    finder.found
+}
+
+/// Replace the expressions matching a pattern, see [pattern](crate::pattern)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewrite {
+    pub pattern: Pattern,
+    pub replacement: Pattern,
+}
+
+impl Rewrite {
+    /// Fails if the replacement uses a wildcard not captured by the pattern
+    pub fn new(pattern: Pattern, replacement: Pattern) -> Result<Self, PatternError> {
+        let (mut captured, mut used) = (Vec::new(), Vec::new());
+        pattern.captures(&mut captured);
+        replacement.captures(&mut used);
+        if let Some(name) = used.iter().find(|n| !captured.contains(n)) {
+            return Err(PatternError {
+                pos: 0,
+                message: format!("${name} isn't captured by the pattern"),
+            });
+        }
+        if replacement.to_string().contains("$_") {
+            return Err(PatternError {
+                pos: 0,
+                message: "$_ can't be used in a replacement".to_owned(),
+            });
+        }
+        Ok(Self {
+            pattern,
+            replacement,
+        })
+    }
+
+    /// Rewrite the statements of `f`, returns the number of expressions replaced
+    pub fn apply(&self, code: &Bytecode, f: &Function, stmts: &mut [Statement]) -> usize {
+        let mut replacer = Replacer {
+            ctx: Ctx {
+                code,
+                f,
+                opts: FormatOptions::new(""),
+            },
+            rewrite: self,
+            count: 0,
+        };
+        visit(code, stmts, &mut [Box::new(&mut replacer)]);
+        replacer.count
+    }
+}
+
+/// `pattern => replacement`
+impl FromStr for Rewrite {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((pattern, replacement)) = s.split_once("=>") else {
+            return Err(PatternError {
+                pos: s.chars().count(),
+                message: "expected 'pattern => replacement'".to_owned(),
+            });
+        };
+        let offset = pattern.chars().count() + 2;
+        let replacement = replacement
+            .parse()
+            .map_err(|e: PatternError| PatternError {
+                pos: e.pos + offset,
+                ..e
+            })?;
+        Self::new(pattern.parse()?, replacement)
+    }
+}
+
+impl Display for Rewrite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} => {}", self.pattern, self.replacement)
+    }
+}
+
+struct Replacer<'a> {
+    ctx: Ctx<'a>,
+    rewrite: &'a Rewrite,
+    count: usize,
+}
+
+impl AstVisitor for Replacer<'_> {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        let mut captures = Captures::new();
+        if self.ctx.matches(&self.rewrite.pattern, expr, &mut captures) {
+            if let Some(new) = self.rewrite.replacement.build(&captures) {
+                *expr = new;
+                self.count += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::{BytecodeBuilder, FunctionBuilder};
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::Type;
+
+    use crate::decompile_code_with;
+    use crate::fmt::FormatOptions;
+    use crate::inline::InlineOptions;
+    use crate::pattern::{find, Pattern, Rewrite};
+
+    #[test]
+    fn parse() {
+        for src in [
+            "$_",
+            "field($o, \"debug\")",
+            "$a.$f(1, -2.5, null) + this.x * 2",
+            "!($a || $b) == false",
+            "$a << 1 < $b >> 2",
+        ] {
+            let p: Pattern = src.parse().unwrap();
+            let again: Pattern = p.to_string().parse().unwrap();
+            assert_eq!(p, again, "{src}");
+        }
+        let p: Pattern = "(a + b) * c".parse().unwrap();
+        assert!(matches!(p, Pattern::Binary("*", ..)));
+        assert_eq!("a +".parse::<Pattern>().unwrap_err().pos, 3);
+        assert!("f(a".parse::<Pattern>().is_err());
+        assert!("a b".parse::<Pattern>().is_err());
+        assert!("$a => $b".parse::<Rewrite>().is_err());
+        assert!("$_ + 1 => $_".parse::<Rewrite>().is_err());
+    }
+
+    #[test]
+    fn find_and_rewrite() {
+        let mut b = BytecodeBuilder::new();
+        let bytes = b.ty(Type::Bytes);
+        let dyn_ = b.ty(Type::Dyn);
+        let cfg = b.class("Config", None, &[], &[]);
+        let field_t = b.fun_type(&[cfg, bytes], dyn_);
+        let field = b.native("std", "field", field_t);
+
+        let ty = b.fun_type(&[cfg], dyn_);
+        let mut f = FunctionBuilder::new(ty, &[cfg]);
+        let c = f.arg(0);
+        let (name, value) = (f.reg(bytes), f.reg(dyn_));
+        let debug = b.string("debug");
+        f.emit(Opcode::String {
+            dst: name,
+            ptr: debug,
+        })
+        .emit(Opcode::Call2 {
+            dst: value,
+            fun: field,
+            arg0: c,
+            arg1: name,
+        })
+        .emit(Opcode::Ret { ret: value });
+        let (_, regs, ops) = f.finish().unwrap();
+        let findex = b.findex();
+        b.function(findex, ty, regs, ops);
+        let code = b.build().unwrap();
+        let f = findex.resolve_as_fn(&code).unwrap();
+        // The last statement, after the comment naming the native
+        let display = |options: &InlineOptions| {
+            decompile_code_with(&code, f, options)
+                .unwrap()
+                .last()
+                .unwrap()
+                .display(&FormatOptions::new(""), &code, f)
+                .to_string()
+        };
+        assert_eq!(
+            display(&InlineOptions::default()),
+            "return field(reg0, \"debug\");"
+        );
+
+        let stmts = decompile_code_with(&code, f, &InlineOptions::default()).unwrap();
+        let found = find(&code, f, &stmts, &"field($o, $_)".parse().unwrap());
+        assert_eq!(found.len(), 1);
+        assert!(found[0].captures.contains_key("o"));
+        assert!(find(&code, f, &stmts, &"field($o, \"x\")".parse().unwrap()).is_empty());
+        assert!(find(&code, f, &stmts, &"other($o, $_)".parse().unwrap()).is_empty());
+
+        let options = InlineOptions {
+            rewrites: vec!["field($o, $f) => $o.$f".parse().unwrap()],
+            ..InlineOptions::default()
+        };
+        assert_eq!(display(&options), "return reg0.debug;");
+        // Same capture twice
+        let options = InlineOptions {
+            rewrites: vec!["field($o, $o) => $o".parse().unwrap()],
+            ..InlineOptions::default()
+        };
+        assert_eq!(display(&options), "return field(reg0, \"debug\");");
+    }
+}
@@ -8,11 +8,22 @@ pub(crate) trait AstVisitor {
     fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {}
 }
 
+/// To get the results of a visitor back
+impl<T: AstVisitor> AstVisitor for &mut T {
+    fn visit_stmt(&mut self, code: &Bytecode, stmt: &mut Statement) {
+        (**self).visit_stmt(code, stmt)
+    }
+
+    fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {
+        (**self).visit_expr(code, expr)
+    }
+}
+
 /// Visit everything depth-first
 pub(crate) fn visit(
     code: &Bytecode,
     stmts: &mut [Statement],
-    visitors: &mut [Box<dyn AstVisitor>],
+    visitors: &mut [Box<dyn AstVisitor + '_>],
 ) {
     // Recurse
     macro_rules! rec {
@@ -81,7 +92,11 @@ pub(crate) fn visit(
 }
 
 /// Visit expressions by depth-first recursion into [Expr].
-pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<dyn AstVisitor>]) {
+pub(crate) fn visit_expr(
+    code: &Bytecode,
+    expr: &mut Expr,
+    visitors: &mut [Box<dyn AstVisitor + '_>],
+) {
     // Recurse
     macro_rules! rec {
         ($e:expr) => {
@@ -111,6 +126,9 @@ pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<d
                 rec!(arg);
             }
         }
+        Expr::Cast(expr, _) => {
+            rec!(expr);
+        }
         Expr::Constant(_) => {}
         Expr::Constructor(ConstructorCall { args, .. }) => {
             for arg in args {
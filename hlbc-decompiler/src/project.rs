@@ -0,0 +1,244 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+
+use hlbc::types::{RefFun, RefType, Type, TypeObj};
+use hlbc::Bytecode;
+use hlbc_analysis::dyntypes::MemberIndex;
+
+use crate::ast::Class;
+use crate::fmt::FormatOptions;
+use crate::inline::InlineOptions;
+use crate::{declare_class, decompile_class_in, Error};
+
+/// A Haxe source file of an exported project
+#[derive(Debug)]
+pub struct Module {
+    /// Path relative to the root of the project, following the package (`game/entity/Player.hx`)
+    pub path: PathBuf,
+    /// Full name of the class
+    pub name: String,
+    pub source: String,
+    /// Line of the declaration of each method in the source, starting at 1
+    pub methods: Vec<(RefFun, usize)>,
+    /// Set if the class couldn't be decompiled, the source then only has the declaration and the error
+    pub error: Option<Error>,
+}
+
+/// Split a full class name in its package and its name
+fn split_name(name: &str) -> (&str, &str) {
+    name.rsplit_once('.').unwrap_or(("", name))
+}
+
+/// Replace the characters that can't be in a file name or an identifier
+fn sanitize(s: &str) -> String {
+    let s: String = s
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if s.is_empty() {
+        "_".to_owned()
+    } else {
+        s
+    }
+}
+
+/// Name of a class or enum that can be imported
+fn importable(code: &Bytecode, ty: RefType) -> Option<&str> {
+    let name = match ty.resolve(&code.types) {
+        Type::Obj(obj) | Type::Struct(obj) => obj.name.resolve(&code.strings),
+        Type::Enum { name, .. } => name.resolve(&code.strings),
+        _ => return None,
+    };
+    if name.is_empty() || name.starts_with('$') {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Imports of a class : types of its fields and of the registers of its methods in another package, sorted
+fn imports(code: &Bytecode, ty: RefType, obj: &TypeObj) -> BTreeSet<String> {
+    let mut types: Vec<RefType> = obj.super_.into_iter().collect();
+    let mut methods = Vec::new();
+    for o in [Some(obj), obj.get_static_type(code)].into_iter().flatten() {
+        types.extend(o.own_fields.iter().map(|f| f.t));
+        methods.extend(o.bindings.values().copied());
+        methods.extend(o.protos.iter().map(|p| p.findex));
+    }
+    for f in methods.iter().filter_map(|f| f.resolve_as_fn(code)) {
+        types.extend(f.regs.iter().copied());
+    }
+
+    let package = importable(code, ty).map_or("", |name| split_name(name).0);
+    types
+        .into_iter()
+        .filter(|&t| t != ty)
+        .filter_map(|t| importable(code, t))
+        .filter(|name| {
+            let p = split_name(name).0;
+            !p.is_empty() && p != package
+        })
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Decompile every class of the bytecode to a module, placed in a directory tree following its package, with its
+/// imports. Names come from the bytecode : rename classes, methods, fields and locals before exporting (with
+/// `hlbc_analysis::profile::Names`) and the files, declarations and imports all use the new names.
+pub fn decompile_project(code: &Bytecode, options: &InlineOptions) -> Vec<Module> {
+    let members = MemberIndex::new(code);
+    modules(code, false, |obj| {
+        decompile_class_in(code, &members, obj, options)
+    })
+}
+
+/// Extern declarations of every class of the bytecode, in the same layout as [decompile_project]. Haxe code compiled
+/// against them can use the classes, fields and methods of the bytecode, like a snippet meant to be injected in it.
+pub fn extern_stubs(code: &Bytecode) -> Vec<Module> {
+    modules(code, true, |obj| declare_class(code, obj))
+}
+
+fn modules(
+    code: &Bytecode,
+    extern_: bool,
+    class: impl Fn(&TypeObj) -> Result<Class, Error>,
+) -> Vec<Module> {
+    let opts = FormatOptions::new("  ");
+    let keyword = if extern_ { "extern class" } else { "class" };
+    let mut modules = Vec::new();
+    let mut paths = HashSet::new();
+    for (i, t) in code.types.iter().enumerate() {
+        let Type::Obj(obj) = t else {
+            continue;
+        };
+        let Some(full) = importable(code, RefType(i)) else {
+            continue;
+        };
+        let (package, name) = split_name(full);
+        let mut path: PathBuf = package
+            .split('.')
+            .filter(|p| !p.is_empty())
+            .map(sanitize)
+            .collect();
+        let mut file = sanitize(name);
+        // Obfuscated or sanitized names can collide
+        if !paths.insert(path.join(&file)) {
+            file = format!("{file}_{i}");
+            paths.insert(path.join(&file));
+        }
+        path.push(format!("{file}.hx"));
+
+        let mut source = String::new();
+        if !package.is_empty() {
+            source.push_str(&format!("package {package};\n\n"));
+        }
+        let imports = imports(code, RefType(i), obj);
+        for import in &imports {
+            source.push_str(&format!("import {import};\n"));
+        }
+        if !imports.is_empty() {
+            source.push('\n');
+        }
+
+        let mut methods = Vec::new();
+        let error = match class(obj) {
+            Ok(mut class) => {
+                class.name = file.clone();
+                let start = source.lines().count();
+                let decl = if extern_ {
+                    format!("{}\n", class.display_extern(code, &opts))
+                } else {
+                    format!("{}\n", class.display(code, &opts))
+                };
+                for (i, line) in decl.lines().enumerate() {
+                    let Some(m) = class.methods.iter().find(|m| {
+                        line.contains(&format!("function {}(", m.fun.name_default(code)))
+                    }) else {
+                        continue;
+                    };
+                    if !methods.iter().any(|&(f, _)| f == m.fun) {
+                        methods.push((m.fun, start + i + 1));
+                    }
+                }
+                source.push_str(&decl);
+                None
+            }
+            Err(e) => {
+                source.push_str(&format!("{keyword} {file} {{\n  // {e}\n}}\n"));
+                Some(e)
+            }
+        };
+        modules.push(Module {
+            path,
+            name: full.to_owned(),
+            source,
+            methods,
+            error,
+        });
+    }
+    modules
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use hlbc::builder::BytecodeBuilder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+
+    use crate::inline::InlineOptions;
+    use crate::project::{decompile_project, extern_stubs};
+
+    #[test]
+    fn project() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let item = b.class("game.data.Item", None, &[], &[]);
+        let update = b.findex();
+        let player = b.class(
+            "game.Player",
+            None,
+            &[("weapon", item)],
+            &[("update", update)],
+        );
+        b.class("$game.Player", None, &[], &[]);
+        let ty = b.method_type(&[player], void);
+        b.function(
+            update,
+            ty,
+            vec![player, void],
+            vec![Opcode::Ret { ret: Reg(1) }],
+        );
+        let code = b.build().unwrap();
+
+        let modules = decompile_project(&code, &InlineOptions::default());
+        assert_eq!(modules.len(), 2);
+        let item = &modules[0];
+        assert_eq!(item.path, Path::new("game/data/Item.hx"));
+        assert!(item
+            .source
+            .starts_with("package game.data;\n\nclass Item {"));
+        let player = &modules[1];
+        assert_eq!(player.path, Path::new("game/Player.hx"));
+        assert!(player.error.is_none());
+        assert!(player
+            .source
+            .starts_with("package game;\n\nimport game.data.Item;\n\nclass Player {"));
+        assert!(player.source.contains("function update()"));
+        let line = player.source.lines().nth(player.methods[0].1 - 1).unwrap();
+        assert!(line.contains("function update()"));
+
+        let externs = extern_stubs(&code);
+        assert_eq!(externs[1].path, player.path);
+        assert!(externs[1].source.contains(
+            "extern class Player {\n  var weapon: game.data.Item;\n  function update(): Void;\n}"
+        ));
+    }
+}
@@ -0,0 +1,258 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, RefFun, Type};
+use hlbc::Bytecode;
+use hlbc_analysis::dyntypes::MemberIndex;
+
+use crate::diagnostics::DiagnosticKind;
+use crate::inline::InlineOptions;
+use crate::{created_closures, decompile_in, deobf};
+
+/// Name of the group of the functions outside any class
+pub const NO_CLASS: &str = "<global>";
+
+/// How well the functions of a class decompile. Closures count with the class of the method creating them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassQuality {
+    pub name: String,
+    pub functions: usize,
+    /// Functions decompiled without error
+    pub decompiled: usize,
+    /// Functions decompiled without any diagnostic
+    pub clean: usize,
+    /// Values replaced by a placeholder
+    pub fallbacks: usize,
+    /// Instructions without an equivalent in the output
+    pub unsupported: usize,
+    /// Control flow the decompiler isn't sure about
+    pub warnings: usize,
+}
+
+impl ClassQuality {
+    /// Share of the functions decompiled without error, 1 without functions
+    pub fn success_rate(&self) -> f64 {
+        if self.functions == 0 {
+            1.0
+        } else {
+            self.decompiled as f64 / self.functions as f64
+        }
+    }
+
+    fn add(&mut self, other: &ClassQuality) {
+        self.functions += other.functions;
+        self.decompiled += other.decompiled;
+        self.clean += other.clean;
+        self.fallbacks += other.fallbacks;
+        self.unsupported += other.unsupported;
+        self.warnings += other.warnings;
+    }
+}
+
+/// Decompilation quality of a whole bytecode, to find where the decompiler is weakest and follow it across versions
+#[derive(Debug, Clone, Default)]
+pub struct QualityReport {
+    /// Sorted by name, functions outside any class are in [NO_CLASS]
+    pub classes: Vec<ClassQuality>,
+    /// Names of the opcodes not decompiled with their number of occurrences
+    pub unsupported_opcodes: BTreeMap<String, usize>,
+    /// Messages of the warnings with their number of occurrences
+    pub warnings: BTreeMap<String, usize>,
+    /// Functions that failed to decompile with the error
+    pub errors: Vec<(RefFun, String)>,
+}
+
+/// Class owning each function : methods, then the closures they create
+fn owners(code: &Bytecode) -> HashMap<RefFun, &str> {
+    let mut owners = HashMap::new();
+    for t in &code.types {
+        let Type::Obj(obj) = t else {
+            continue;
+        };
+        let name = obj.name.resolve(&code.strings);
+        let methods = obj
+            .protos
+            .iter()
+            .map(|p| p.findex)
+            .chain(obj.bindings.values().copied());
+        let statics = obj
+            .get_static_type(code)
+            .into_iter()
+            .flat_map(|s| s.bindings.values().copied());
+        for f in methods.chain(statics) {
+            owners.entry(f).or_insert(name);
+        }
+    }
+    // Closures of closures
+    let mut stack: Vec<RefFun> = owners.keys().copied().collect();
+    while let Some(findex) = stack.pop() {
+        let Some(f) = findex.resolve_as_fn(code) else {
+            continue;
+        };
+        let owner = owners[&findex];
+        for fun in created_closures(f) {
+            if let Entry::Vacant(e) = owners.entry(fun) {
+                e.insert(owner);
+                stack.push(fun);
+            }
+        }
+    }
+    owners
+}
+
+impl QualityReport {
+    /// Decompile every function of the bytecode
+    pub fn new(code: &Bytecode, options: &InlineOptions) -> Self {
+        let owners = owners(code);
+        let members = MemberIndex::new(code);
+        let mut report = QualityReport::default();
+        let mut classes: BTreeMap<&str, ClassQuality> = BTreeMap::new();
+        for f in &code.functions {
+            let name = owners.get(&f.findex).copied().unwrap_or(NO_CLASS);
+            let class = classes.entry(name).or_insert_with(|| ClassQuality {
+                name: name.to_owned(),
+                ..ClassQuality::default()
+            });
+            class.functions += 1;
+            match decompile_in(code, &members, f, options) {
+                Ok((_, diagnostics)) => {
+                    class.decompiled += 1;
+                    // Closures are counted as functions of their own
+                    let own: Vec<_> = diagnostics
+                        .iter()
+                        .filter(|d| d.findex == f.findex)
+                        .collect();
+                    if own.is_empty() {
+                        class.clean += 1;
+                    }
+                    for d in own {
+                        match d.kind {
+                            DiagnosticKind::Fallback => class.fallbacks += 1,
+                            DiagnosticKind::Warning => {
+                                class.warnings += 1;
+                                *report.warnings.entry(d.message.clone()).or_default() += 1;
+                            }
+                            DiagnosticKind::UnsupportedOpcode => {
+                                class.unsupported += 1;
+                                let name = decompiled_op(code, f, d.pos, options)
+                                    .as_ref()
+                                    .map_or("?", Opcode::name);
+                                *report
+                                    .unsupported_opcodes
+                                    .entry(name.to_owned())
+                                    .or_default() += 1;
+                            }
+                        }
+                    }
+                }
+                Err(e) => report.errors.push((f.findex, e.to_string())),
+            }
+        }
+        report.classes = classes.into_values().collect();
+        report
+    }
+
+    /// Sum of every class
+    pub fn total(&self) -> ClassQuality {
+        let mut total = ClassQuality {
+            name: "total".to_owned(),
+            ..ClassQuality::default()
+        };
+        for c in &self.classes {
+            total.add(c);
+        }
+        total
+    }
+
+    /// Classes sorted by increasing success rate, then by number of diagnostics
+    pub fn weakest(&self) -> Vec<&ClassQuality> {
+        let mut classes: Vec<_> = self.classes.iter().collect();
+        classes.sort_by(|a, b| {
+            a.success_rate()
+                .partial_cmp(&b.success_rate())
+                .unwrap_or(Ordering::Equal)
+                .then((b.functions - b.clean).cmp(&(a.functions - a.clean)))
+        });
+        classes
+    }
+}
+
+/// Instruction at a position of a diagnostic, which is in the deobfuscated function
+fn decompiled_op(
+    code: &Bytecode,
+    f: &Function,
+    pos: usize,
+    options: &InlineOptions,
+) -> Option<Opcode> {
+    match deobf::deobfuscate(code, f, options.unflatten) {
+        Some((f, _)) => f.ops.get(pos).cloned(),
+        None => f.ops.get(pos).cloned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::BytecodeBuilder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+
+    use crate::inline::InlineOptions;
+    use crate::quality::{QualityReport, NO_CLASS};
+
+    #[test]
+    fn report() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let dyn_ = b.ty(Type::Dyn);
+        let i32_ = b.ty(Type::I32);
+        let ty = b.fun_type(&[], void);
+        let (update, closure, broken) = (b.findex(), b.findex(), b.findex());
+        let player = b.class("Player", None, &[], &[("update", update)]);
+        let method = b.method_type(&[player], void);
+        b.function(
+            update,
+            method,
+            vec![player, dyn_, void],
+            vec![
+                Opcode::StaticClosure {
+                    dst: Reg(1),
+                    fun: closure,
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        let closure_t = b.fun_type(&[i32_], void);
+        b.function(
+            closure,
+            closure_t,
+            vec![i32_, i32_, void],
+            vec![
+                Opcode::GetTID {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        b.function(broken, ty, vec![void], vec![Opcode::Ret { ret: Reg(0) }]);
+        let mut code = b.build().unwrap();
+        // Out of bounds register
+        code.functions[2].ops[0] = Opcode::Ret { ret: Reg(3) };
+
+        let report = QualityReport::new(&code, &InlineOptions::default());
+        assert_eq!(report.classes.len(), 2);
+        let global = &report.classes[0];
+        assert_eq!(global.name, NO_CLASS);
+        assert_eq!((global.functions, global.decompiled), (1, 0));
+        let player = &report.classes[1];
+        assert_eq!(player.name, "Player");
+        assert_eq!((player.functions, player.decompiled), (2, 2));
+        assert_eq!((player.clean, player.unsupported), (1, 1));
+        assert_eq!(report.unsupported_opcodes["GetTID"], 1);
+        assert_eq!(report.errors[0].0, broken);
+        assert_eq!(report.total().functions, 3);
+        assert_eq!(report.weakest()[0].name, NO_CLASS);
+    }
+}
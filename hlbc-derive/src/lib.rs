@@ -21,12 +21,14 @@ pub fn derive_opcode_helper(input: TokenStream) -> TokenStream {
         .iter()
         .enumerate()
         .map(|(i, v)| gen_initw(name, v, i as u8));
+    let vsize = variants.iter().map(|v| gen_size(name, v));
     let vname = variants.iter().map(|v| &v.ident);
     let vname2 = vname.clone();
     let vname_str = variants
         .iter()
         .map(|v| LitStr::new(&v.ident.to_string(), v.ident.span()));
     let vname_str2 = vname_str.clone();
+    let vname_str3 = vname_str.clone();
     let vdesc = variants.iter().map(|v| {
         let mut acc = String::new();
         for attr in &v.attrs {
@@ -53,8 +55,77 @@ pub fn derive_opcode_helper(input: TokenStream) -> TokenStream {
         }
     });
 
+    let vdst = variants.iter().map(|v| {
+        let vname = &v.ident;
+        let has_dst = v.fields.iter().any(|f| {
+            f.ident.as_ref().map(|i| i == "dst").unwrap_or(false) && ident(&f.ty) == "Reg"
+        });
+        if has_dst {
+            quote! { #name::#vname { dst, .. } => Some(*dst), }
+        } else {
+            quote! { #name::#vname { .. } => None, }
+        }
+    });
+
+    let vregs = variants.iter().map(|v| {
+        let vname = &v.ident;
+        let fields: Vec<_> = v
+            .fields
+            .iter()
+            .filter(|f| matches!(ident(&f.ty).as_str(), "Reg" | "Vec<Reg>"))
+            .collect();
+        let fname = fields.iter().map(|f| f.ident.as_ref().unwrap());
+        let push = fields.iter().map(|f| {
+            let fname = f.ident.as_ref().unwrap();
+            if ident(&f.ty) == "Reg" {
+                quote! { regs.push(*#fname); }
+            } else {
+                quote! { regs.extend_from_slice(#fname); }
+            }
+        });
+        quote! {
+            #name::#vname { #( #fname, )* .. } => {
+                #( #push )*
+            }
+        }
+    });
+
+    let voperands = variants.iter().map(|v| {
+        let vname = &v.ident;
+        let fname = v.fields.iter().map(|f| f.ident.as_ref().unwrap());
+        let push = v.fields.iter().map(|f| {
+            let fname = f.ident.as_ref().unwrap();
+            let kind = match ident(&f.ty).as_str() {
+                "Reg" => quote!(Reg),
+                "Vec<Reg>" => quote!(Regs),
+                "JumpOffset" | "i32" => quote!(Offset),
+                "Vec<JumpOffset>" => quote!(Offsets),
+                "RefInt" => quote!(Int),
+                "RefFloat" => quote!(Float),
+                "RefBytes" => quote!(Bytes),
+                "RefString" => quote!(String),
+                "RefType" => quote!(Type),
+                "ValBool" => quote!(Bool),
+                "RefFun" => quote!(Fun),
+                "RefField" => quote!(Field),
+                "RefGlobal" => quote!(Global),
+                "RefEnumConstruct" => quote!(Construct),
+                other => unreachable!("unknown operand type {other}"),
+            };
+            quote! { operands.push(crate::opcodes::OperandMut::#kind(#fname)); }
+        });
+        quote! {
+            #name::#vname { #( #fname, )* } => {
+                #( #push )*
+            }
+        }
+    });
+
     TokenStream::from(quote! {
         impl #name {
+            /// Names of every opcode, in declaration order
+            pub const NAMES: &'static [&'static str] = &[ #( #vname_str3, )* ];
+
             /// Decode an instruction
             pub fn decode(r: &mut impl std::io::Read) -> crate::Result<#name> {
 
@@ -65,7 +136,7 @@ pub fn derive_opcode_helper(input: TokenStream) -> TokenStream {
                 let op = r.read_u8()?;
                 match op {
                     #( #i => #initr, )*
-                    other => Err(crate::Error::MalformedBytecode(format!("Unknown opcode {}", op))),
+                    other => Err(crate::ParseError::InvalidOpcode(other).into()),
                 }
             }
 
@@ -83,6 +154,16 @@ pub fn derive_opcode_helper(input: TokenStream) -> TokenStream {
                 Ok(())
             }
 
+            /// Number of bytes taken by this instruction once encoded with [Self::encode]
+            pub fn encoded_size(&self) -> usize {
+
+                use crate::ser::vi32_size;
+
+                match self {
+                    #( #vsize )*
+                }
+            }
+
             /// Get the opcode name
             pub fn name(&self) -> &'static str {
                 match self {
@@ -97,6 +178,33 @@ pub fn derive_opcode_helper(input: TokenStream) -> TokenStream {
                 }
             }
 
+            /// Get the destination register (the `dst` field) of this instruction.
+            ///
+            /// Note that `Setref` writes through the reference in its `dst` register.
+            pub fn dst(&self) -> Option<crate::types::Reg> {
+                match self {
+                    #( #vdst )*
+                }
+            }
+
+            /// Get every register used by this instruction, read or written
+            pub fn regs(&self) -> Vec<crate::types::Reg> {
+                let mut regs = Vec::new();
+                match self {
+                    #( #vregs )*
+                }
+                regs
+            }
+
+            /// Get mutable references to every operand of this instruction, in declaration order
+            pub fn operands_mut(&mut self) -> Vec<crate::opcodes::OperandMut<'_>> {
+                let mut operands = Vec::new();
+                match self {
+                    #( #voperands )*
+                }
+                operands
+            }
+
             /// Get an opcode from its name. Returns a default value for the variant.
             pub fn from_name(name: &str) -> Option<Self> {
                 match name {
@@ -284,3 +392,25 @@ fn gen_initw(enum_name: &Ident, v: &Variant, i: u8) -> TokenStream2 {
         }
     }
 }
+
+fn gen_size(enum_name: &Ident, v: &Variant) -> TokenStream2 {
+    let vname = &v.ident;
+    let fname = v.fields.iter().map(|f| &f.ident);
+    let fsize = v.fields.iter().map(|f| {
+        let fname = f.ident.as_ref().unwrap();
+        match ident(&f.ty).as_str() {
+            "usize" => quote!(vi32_size(*#fname as i32)),
+            "i32" | "JumpOffset" => quote!(vi32_size(*#fname)),
+            "Vec<JumpOffset>" => quote! {
+                vi32_size(#fname.len() as i32) + #fname.iter().map(|r__| vi32_size(*r__)).sum::<usize>()
+            },
+            "Vec<Reg>" => quote! {
+                1 + #fname.iter().map(|r__| vi32_size(r__.0 as i32)).sum::<usize>()
+            },
+            _ => quote!(vi32_size(#fname.0 as i32)),
+        }
+    });
+    quote! {
+        #enum_name::#vname { #( #fname, )* } => 1 #( + #fsize )*,
+    }
+}
@@ -1,10 +1,12 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-use std::cell::Cell;
+use std::cell::{Cell, Ref, RefCell};
 use std::io::{BufReader, Cursor};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
 use std::{env, fs};
 
 use eframe::egui::style::Margin;
@@ -14,14 +16,21 @@ use egui_dock::{DockArea, NodeIndex, Tree};
 use poll_promise::Promise;
 use rfd::FileHandle;
 
+use hlbc::analysis::tags::{TagTarget, Tags};
 use hlbc::types::{RefFun, RefGlobal, RefString, RefType};
 use hlbc::Bytecode;
+use hlbc_analysis::entrypoints;
+use hlbc_analysis::search::SearchIndex;
+use hlbc_analysis::xref::XrefIndex;
+use hlbc_plugin::Plugins;
 
+use crate::tour::Tour;
 use crate::views::{
-    AppView, ClassesView, DynamicTabViewer, FunctionsView, GlobalsView, InfoView, StringsView,
-    SyncInspectorView,
+    AppView, ClassesView, DecompilerView, DiagnosticsView, DynamicTabViewer, FunctionsView,
+    GlobalsView, InfoView, PluginView, StringsView, SyncInspectorView,
 };
 
+mod tour;
 mod views;
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -39,8 +48,7 @@ fn main() -> eframe::Result<()> {
                 None
             } else {
                 let path = PathBuf::from(args);
-                let code =
-                    Bytecode::load(&mut BufReader::new(fs::File::open(&path).unwrap())).unwrap();
+                let code = Bytecode::from_file(&path).unwrap();
                 Some(AppCtxHandle::new(AppCtx::new_from_code(
                     path.display().to_string(),
                     code,
@@ -64,11 +72,31 @@ fn main() -> eframe::Result<()> {
                 style,
                 options_window_open: false,
                 about_window_open: false,
+                plugins: Rc::new(load_plugins()),
+                tour: None,
+                watched: None,
             })
         }),
     )
 }
 
+/// Load the plugins listed in the `HLBC_PLUGINS` environment variable
+#[cfg(not(target_arch = "wasm32"))]
+fn load_plugins() -> Plugins {
+    #[allow(unused_mut)]
+    let mut plugins = Plugins::new();
+    #[cfg(feature = "plugins")]
+    if let Some(paths) = env::var_os("HLBC_PLUGINS") {
+        for path in env::split_paths(&paths) {
+            // Safety : the user asked to load this library
+            if let Err(e) = unsafe { plugins.load(&path) } {
+                eprintln!("Can't load plugin {} : {e}", path.display());
+            }
+        }
+    }
+    plugins
+}
+
 #[cfg(target_arch = "wasm32")]
 fn main() {
     // Make sure panics are logged using `console.error`.
@@ -94,6 +122,8 @@ fn main() {
                     style,
                     options_window_open: false,
                     about_window_open: false,
+                    plugins: Rc::new(Plugins::new()),
+                    tour: None,
                 })
             }),
         )
@@ -111,6 +141,66 @@ struct App {
     style: egui_dock::Style,
     options_window_open: bool,
     about_window_open: bool,
+    /// Plugins providing additional panels
+    plugins: Rc<Plugins>,
+    /// Some while the guided tour is running
+    tour: Option<Tour>,
+    /// State of the opened file when it was last checked for changes
+    #[cfg(not(target_arch = "wasm32"))]
+    watched: Option<hlbc::reload::Source>,
+}
+
+impl App {
+    /// Open the sample bytecode generated by hlbc, which uses every instruction
+    fn open_sample(&mut self) {
+        self.ctx = Some(AppCtxHandle::new(AppCtx::new_from_code(
+            tour::SAMPLE_NAME.to_owned(),
+            hlbc::builder::sample(),
+        )));
+        self.tree = default_tabs_ui();
+    }
+
+    /// Start the tour, on the sample if no file is open
+    fn start_tour(&mut self) {
+        if self.ctx.is_none() {
+            self.open_sample();
+        }
+        self.tour = Some(Tour::default());
+    }
+
+    /// Reload the opened file when it is recompiled, the panels are reset once it is loaded
+    #[cfg(not(target_arch = "wasm32"))]
+    fn watch_file(&mut self, ctx: &egui::Context) {
+        if self.loader.is_some() {
+            return;
+        }
+        let Some(source) = self.ctx.as_ref().and_then(|app| app.code().source.clone()) else {
+            return;
+        };
+        ctx.request_repaint_after(Duration::from_secs(1));
+        let watched = self.watched.get_or_insert(source);
+        if watched.is_fresh(&watched.path) {
+            return;
+        }
+        let path = watched.path.clone();
+        // Still being written, checked again on the next frame
+        let Ok(data) = fs::read(&path) else {
+            return;
+        };
+        let Ok(source) = hlbc::reload::Source::new(&path, &data) else {
+            return;
+        };
+        let changed = source.hash != watched.hash;
+        *watched = source.clone();
+        if changed {
+            self.loader = Some(Promise::spawn_thread("bg-loader", move || {
+                // Retried when the file changes again
+                let mut code = Bytecode::from_bytes(&data).ok()?;
+                code.source = Some(source);
+                Some((path.display().to_string(), code))
+            }));
+        }
+    }
 }
 
 impl eframe::App for App {
@@ -119,6 +209,10 @@ impl eframe::App for App {
             if let Some(loader) = self.loader.take() {
                 match loader.try_take() {
                     Ok(Some((file, code))) => {
+                        #[cfg(not(target_arch = "wasm32"))]
+                        {
+                            self.watched = code.source.clone();
+                        }
                         self.ctx = Some(AppCtxHandle::new(AppCtx::new_from_code(file, code)));
                         self.tree = default_tabs_ui();
                     }
@@ -133,6 +227,9 @@ impl eframe::App for App {
             if let Some(tab) = self.ctx.as_ref().and_then(|app| app.take_tab_to_open()) {
                 self.tree[NodeIndex::root().left()].append_tab(tab);
             }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            self.watch_file(ctx);
         }
 
         TopBottomPanel::top("menu bar")
@@ -149,7 +246,7 @@ impl eframe::App for App {
                                     {
                                         Some((
                                             file.file_name(),
-                                            Bytecode::load(&mut &file.read().await[..]).unwrap(),
+                                            Bytecode::from_bytes(&file.read().await).unwrap(),
                                         ))
                                     } else {
                                         None
@@ -162,10 +259,7 @@ impl eframe::App for App {
                                     if let Some(file) = rfd::FileDialog::new().pick_file() {
                                         Some((
                                             file.display().to_string(),
-                                            Bytecode::load(&mut BufReader::new(
-                                                fs::File::open(&file).unwrap(),
-                                            ))
-                                            .unwrap(),
+                                            Bytecode::from_file(&file).unwrap(),
                                         ))
                                     } else {
                                         None
@@ -173,9 +267,17 @@ impl eframe::App for App {
                                 }));
                             }
                         }
+                        if ui.button("Open the sample").clicked() {
+                            self.open_sample();
+                        }
                         if ui.button("Close").clicked() {
                             self.ctx = None;
                             self.tree = Tree::new(vec![]);
+                            self.tour = None;
+                            #[cfg(not(target_arch = "wasm32"))]
+                            {
+                                self.watched = None;
+                            }
                         }
                     });
                     if self.ctx.is_some() {
@@ -188,12 +290,56 @@ impl eframe::App for App {
                                 self.tree[NodeIndex::root().left()]
                                     .append_tab(Box::<InfoView>::default());
                             }
+                            if ui.button("Decompiler").clicked() {
+                                self.tree[NodeIndex::root().right()]
+                                    .append_tab(Box::<DecompilerView>::default());
+                            }
+                            if ui.button("Diagnostics").clicked() {
+                                self.tree[NodeIndex::root().left()]
+                                    .append_tab(Box::<DiagnosticsView>::default());
+                            }
+                            #[cfg(feature = "console")]
+                            if ui.button("Console").clicked() {
+                                self.tree[NodeIndex::root().right()]
+                                    .append_tab(Box::<views::ConsoleView>::default());
+                            }
+                            #[cfg(not(target_arch = "wasm32"))]
+                            if ui.button("Timeline").clicked() {
+                                self.tree[NodeIndex::root().left()]
+                                    .append_tab(Box::<views::TimelineView>::default());
+                            }
+                            #[cfg(not(target_arch = "wasm32"))]
+                            if ui.button("Diff").clicked() {
+                                self.tree[NodeIndex::root().right()]
+                                    .append_tab(Box::<views::DiffView>::default());
+                            }
+                        });
+                    }
+                    if self.ctx.is_some() && self.plugins.iter().any(|p| !p.panels().is_empty()) {
+                        ui.menu_button("Plugins", |ui| {
+                            for plugin in self.plugins.iter() {
+                                for panel in plugin.panels() {
+                                    if ui.button(&panel).clicked() {
+                                        self.tree[NodeIndex::root().left()].append_tab(Box::new(
+                                            PluginView::new(
+                                                self.plugins.clone(),
+                                                plugin.name(),
+                                                &panel,
+                                            ),
+                                        ));
+                                    }
+                                }
+                            }
                         });
                     }
                     if ui.button("Options").clicked() {
                         self.options_window_open = !self.options_window_open;
                     }
                     ui.menu_button("Help", |ui| {
+                        if ui.button("Tour").clicked() {
+                            self.start_tour();
+                            ui.close_menu();
+                        }
                         if ui.button("Wiki").clicked() {
                             webbrowser::open("https://github.com/Gui-Yom/hlbc/wiki")
                                 .expect("Failed to open web browser");
@@ -229,15 +375,33 @@ impl eframe::App for App {
         if let Some(appctx) = self.ctx.clone() {
             DockArea::new(&mut self.tree)
                 .style(self.style.clone())
-                .show(ctx, &mut DynamicTabViewer(appctx));
+                .show(ctx, &mut DynamicTabViewer(appctx.clone()));
+            if let Some(tour) = &mut self.tour {
+                if !tour.show(ctx, &appctx, &mut self.tree) {
+                    self.tour = None;
+                }
+            }
         } else {
+            let mut sample = false;
+            let mut tour = false;
             CentralPanel::default()
                 .frame(Frame::group(ctx.style().as_ref()).outer_margin(Margin::same(4.0)))
                 .show(ctx, |ui| {
                     ui.vertical_centered(|ui| {
-                        ui.label("Load a bytecode file to start");
+                        ui.add_space(ui.available_height() / 3.0);
+                        ui.heading("Hashlink bytecode tools");
+                        ui.label("Load a bytecode file with 'File > Open' to start");
+                        ui.add_space(8.0);
+                        ui.label("New here ? Explore a sample bytecode using every instruction");
+                        sample = ui.button("Open the sample").clicked();
+                        tour = ui.button("Take the tour").clicked();
                     });
                 });
+            if tour {
+                self.start_tour();
+            } else if sample {
+                self.open_sample();
+            }
         }
     }
 }
@@ -262,6 +426,27 @@ impl AppCtxHandle {
         &self.0.code
     }
 
+    #[cfg(feature = "autotag")]
+    fn original_names(&self) -> &hlbc_analysis::renames::OriginalNames {
+        &self.0.original_names
+    }
+
+    #[cfg(feature = "autotag")]
+    fn names(&self) -> &hlbc_analysis::signatures::InferredNames {
+        &self.0.names
+    }
+
+    /// Display context of the functions, with the names inferred from the signatures
+    #[cfg(feature = "autotag")]
+    fn named(&self) -> hlbc_analysis::signatures::Named<'_> {
+        self.0.names.with(&self.0.code)
+    }
+
+    #[cfg(not(feature = "autotag"))]
+    fn named(&self) -> &Bytecode {
+        &self.0.code
+    }
+
     /// mut lock
     fn open_tab(&self, tab: impl AppView + 'static) {
         self.0.new_tab.set(Some(Box::new(tab)));
@@ -278,28 +463,176 @@ impl AppCtxHandle {
     fn set_selected(&self, s: ItemSelection) {
         self.0.selected.set(s);
     }
+
+    /// Select a function and show one of its instructions in the inspector
+    fn jump_to(&self, fun: RefFun, pos: usize) {
+        self.set_selected(ItemSelection::Fun(fun));
+        self.0.location.set(Some((fun, pos)));
+    }
+
+    /// Instruction to show once the inspector displays `fun`
+    fn take_location(&self, fun: RefFun) -> Option<usize> {
+        match self.0.location.get() {
+            Some((f, pos)) if f == fun => {
+                self.0.location.set(None);
+                Some(pos)
+            }
+            _ => None,
+        }
+    }
+
+    /// immutable lock
+    fn tags(&self) -> Ref<Tags> {
+        self.0.tags.borrow()
+    }
+
+    /// immutable lock
+    fn search(&self) -> Ref<SearchIndex> {
+        if self.0.search.borrow().is_none() {
+            *self.0.search.borrow_mut() = Some(SearchIndex::new(&self.0.code));
+        }
+        Ref::map(self.0.search.borrow(), |s| s.as_ref().unwrap())
+    }
+
+    /// immutable lock
+    fn xrefs(&self) -> Ref<XrefIndex> {
+        if self.0.xrefs.borrow().is_none() {
+            *self.0.xrefs.borrow_mut() = Some(XrefIndex::new(&self.0.code));
+        }
+        Ref::map(self.0.xrefs.borrow(), |x| x.as_ref().unwrap())
+    }
+
+    /// Incremented each time the tags change, views can use it to invalidate their cache.
+    fn tags_generation(&self) -> u64 {
+        self.0.tags_gen.get()
+    }
+
+    /// mut lock
+    fn add_tag(&self, target: TagTarget, tag: &str) {
+        if self.0.tags.borrow_mut().add(target, tag) {
+            self.tags_changed();
+        }
+    }
+
+    /// mut lock
+    fn remove_tag(&self, target: TagTarget, tag: &str) {
+        if self.0.tags.borrow_mut().remove(target, tag) {
+            self.tags_changed();
+        }
+    }
+
+    /// mut lock
+    fn set_tags(&self, tags: Tags) {
+        *self.0.tags.borrow_mut() = tags;
+        self.tags_changed();
+    }
+
+    fn tags_changed(&self) {
+        self.0.tags_gen.set(self.0.tags_gen.get() + 1);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = tags_file(&self.0.file);
+            let tags = self.tags();
+            let res = if !tags.has_user_tags() {
+                if path.exists() {
+                    fs::remove_file(&path)
+                } else {
+                    Ok(())
+                }
+            } else {
+                fs::File::create(&path).and_then(|f| tags.save(&mut std::io::BufWriter::new(f)))
+            };
+            if let Err(e) = res {
+                eprintln!("Can't save tags to {} : {e}", path.display());
+            }
+        }
+    }
 }
 
 struct AppCtx {
     file: String,
     code: Bytecode,
+    /// Names before the renames, the console writes files with them
+    #[cfg(feature = "autotag")]
+    original_names: hlbc_analysis::renames::OriginalNames,
+    /// Names inferred from the signatures, shown instead of the debug names
+    #[cfg(feature = "autotag")]
+    names: hlbc_analysis::signatures::InferredNames,
     selected: Cell<ItemSelection>,
+    /// Instruction to show after jumping to a location
+    location: Cell<Option<(RefFun, usize)>>,
     /// To open a tab from another tab.
     /// This can't be done directly because this would need a mutable reference to a tree and the tree owns the tab.
     new_tab: Cell<Option<Box<dyn AppView>>>,
+    /// User tags, persisted next to the bytecode file.
+    tags: RefCell<Tags>,
+    tags_gen: Cell<u64>,
+    /// Index for text searches, built on the first search.
+    search: RefCell<Option<SearchIndex>>,
+    /// Cross-references, built on the first lookup.
+    xrefs: RefCell<Option<XrefIndex>>,
 }
 
 impl AppCtx {
-    fn new_from_code(file: String, code: Bytecode) -> Self {
+    fn new_from_code(file: String, #[allow(unused_mut)] mut code: Bytecode) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let tags = fs::File::open(tags_file(&file))
+            .ok()
+            .and_then(|f| Tags::load(BufReader::new(f)).ok())
+            .unwrap_or_default();
+        #[cfg(target_arch = "wasm32")]
+        let tags = Tags::new();
+        #[cfg(feature = "autotag")]
+        let tags = {
+            let mut tags = tags;
+            hlbc_analysis::autotag::Rules::default_rules().apply(&code, &mut tags);
+            tags
+        };
+        let mut tags = tags;
+        entrypoints::tag_entrypoints(&entrypoints::find_entrypoints(&code), &mut tags);
+        #[cfg(feature = "autotag")]
+        let names = {
+            use hlbc_analysis::signatures::{InferredNames, Signatures, DEFAULT_MIN_CONFIDENCE};
+            let mut names = InferredNames::default();
+            Signatures::default_signatures().apply(&code, &mut names, DEFAULT_MIN_CONFIDENCE);
+            names
+        };
+        #[cfg(feature = "autotag")]
+        let original_names = hlbc_analysis::renames::OriginalNames::new(&code);
+        // Renames made with the console or the cli
+        #[cfg(all(feature = "autotag", not(target_arch = "wasm32")))]
+        if let Ok(s) = fs::read_to_string(format!("{file}.renames")) {
+            match hlbc_analysis::renames::RenameMap::from_toml(&s) {
+                Ok(renames) => {
+                    renames.apply(&mut code);
+                }
+                Err(e) => eprintln!("Can't load the renames of {file} : {e}"),
+            }
+        }
         Self {
             file,
             code,
+            #[cfg(feature = "autotag")]
+            original_names,
+            #[cfg(feature = "autotag")]
+            names,
             selected: Cell::new(ItemSelection::None),
+            location: Cell::new(None),
             new_tab: Cell::new(None),
+            tags: RefCell::new(tags),
+            tags_gen: Cell::new(0),
+            search: RefCell::new(None),
+            xrefs: RefCell::new(None),
         }
     }
 }
 
+/// Path of the sidecar file storing the tags of a bytecode file
+#[cfg(not(target_arch = "wasm32"))]
+fn tags_file(file: &str) -> PathBuf {
+    PathBuf::from(format!("{file}.tags"))
+}
+
 fn default_tabs_ui() -> Tree<Box<dyn AppView>> {
     let mut tree: Tree<Box<dyn AppView>> = Tree::new(vec![
         Box::<SyncInspectorView>::default(),
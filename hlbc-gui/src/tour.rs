@@ -0,0 +1,134 @@
+//! Guided tour of the main panels on the sample bytecode, walking through search, references and decompilation.
+
+use eframe::egui::{Align2, Color32, Context, Id, LayerId, Order, RichText, Stroke, Vec2, Window};
+use egui_dock::{Node, TabIndex, Tree};
+
+use crate::views::AppView;
+use crate::{AppCtxHandle, ItemSelection};
+
+/// File name given to the sample bytecode
+pub(crate) const SAMPLE_NAME: &str = "sample.hl";
+
+struct Step {
+    /// Beginning of the title of the panel to highlight
+    panel: Option<&'static str>,
+    text: &'static str,
+    /// The step is done when the user did what it asks, otherwise with the 'Next' button
+    done: Option<fn(&AppCtxHandle, &Tree<Box<dyn AppView>>) -> bool>,
+}
+
+const STEPS: &[Step] = &[
+    Step {
+        panel: Some("ƒ Functions"),
+        text: "The panels on the left list the functions, globals and strings of the bytecode. \
+        Std functions and natives are hidden by default, use the checkboxes to show them.",
+        done: None,
+    },
+    Step {
+        panel: Some("Inspector (sync)"),
+        text: "The inspector shows the element selected in any panel : the instructions of a function, \
+        the fields and methods of a class, or the value of a global.",
+        done: None,
+    },
+    Step {
+        panel: Some("Classes"),
+        text: "Classes and enums are listed here, select one to inspect its fields and methods.",
+        done: None,
+    },
+    Step {
+        panel: Some("Strings"),
+        text: "Let's find where a string is used. Type 'Hello' in the search box of the Strings panel \
+        and click the result.",
+        done: Some(|ctx, _| matches!(ctx.selected(), ItemSelection::String(_))),
+    },
+    Step {
+        panel: Some("Inspector (sync)"),
+        text: "The inspector lists the instructions referencing the string. Open the references and \
+        click one to jump to the function using it.",
+        done: Some(|ctx, _| matches!(ctx.selected(), ItemSelection::Fun(_))),
+    },
+    Step {
+        panel: None,
+        text: "Now open 'Views > Decompiler' in the menu bar. The decompiler follows the selection.",
+        done: Some(|_, tree| tree.tabs().any(|t| is_panel(t.as_ref(), "Decompilation output"))),
+    },
+    Step {
+        panel: Some("Decompilation output"),
+        text: "This is the Haxe source of the selected function. You know the basic workflow, \
+        open your own file with 'File > Open'. The tour is available again from 'Help > Tour'.",
+        done: None,
+    },
+];
+
+fn is_panel(tab: &dyn AppView, prefix: &str) -> bool {
+    tab.title().text().starts_with(prefix)
+}
+
+/// Current step of the tour
+#[derive(Default)]
+pub(crate) struct Tour {
+    step: usize,
+}
+
+impl Tour {
+    /// Show the instructions of the current step and highlight its panel, returns false once the tour is over
+    pub(crate) fn show(
+        &mut self,
+        ctx: &Context,
+        app: &AppCtxHandle,
+        tree: &mut Tree<Box<dyn AppView>>,
+    ) -> bool {
+        let Some(step) = STEPS.get(self.step) else {
+            return false;
+        };
+        if step.done.map_or(false, |done| done(app, tree)) {
+            self.step += 1;
+            ctx.request_repaint();
+            return true;
+        }
+        if let Some(panel) = step.panel {
+            highlight(ctx, tree, panel);
+        }
+
+        let mut open = true;
+        Window::new("Tour")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::RIGHT_BOTTOM, Vec2::new(-16.0, -16.0))
+            .show(ctx, |ui| {
+                ui.set_max_width(320.0);
+                ui.label(RichText::new(format!("Step {}/{}", self.step + 1, STEPS.len())).weak());
+                ui.label(step.text);
+                ui.add_space(6.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Skip the tour").clicked() {
+                        open = false;
+                    }
+                    if step.done.is_none() {
+                        let last = self.step + 1 == STEPS.len();
+                        if ui.button(if last { "Finish" } else { "Next" }).clicked() {
+                            self.step += 1;
+                        }
+                    }
+                });
+            });
+        open && self.step < STEPS.len()
+    }
+}
+
+/// Bring the panel to the front of its node and draw a frame around it
+fn highlight(ctx: &Context, tree: &mut Tree<Box<dyn AppView>>, panel: &str) {
+    for node in tree.iter_mut() {
+        if let Node::Leaf {
+            rect, tabs, active, ..
+        } = node
+        {
+            if let Some(i) = tabs.iter().position(|t| is_panel(t.as_ref(), panel)) {
+                *active = TabIndex(i);
+                ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("tour")))
+                    .rect_stroke(rect.shrink(1.0), 4.0, Stroke::new(2.0, Color32::GOLD));
+                return;
+            }
+        }
+    }
+}
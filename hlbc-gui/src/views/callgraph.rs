@@ -5,10 +5,10 @@ use eframe::egui::style::Margin;
 use eframe::egui::{Area, Color32, DragValue, Frame, Id, ScrollArea, Stroke, Ui, Vec2, Widget};
 use eframe::epaint::CubicBezierShape;
 
-use hlbc::analysis::graph::petgraph::visit::EdgeRef;
-use hlbc::analysis::graph::petgraph::visit::IntoEdgeReferences;
-use hlbc::analysis::graph::{call_graph, Callgraph};
 use hlbc::types::RefFun;
+use hlbc_analysis::graph::petgraph::visit::EdgeRef;
+use hlbc_analysis::graph::petgraph::visit::IntoEdgeReferences;
+use hlbc_analysis::graph::{call_graph, Callgraph};
 
 use crate::AppCtxHandle;
 
@@ -62,7 +62,7 @@ impl CallgraphView {
                     .auto_shrink([false, false])
                     .show_viewport(ui, |ui, rect| {
                         let mut nodes_pos = HashMap::new();
-                        for n in cg.nodes() {
+                        for n in cg.graph.nodes() {
                             let pos = ui.next_widget_position();
                             nodes_pos.insert(
                                 n,
@@ -71,16 +71,14 @@ impl CallgraphView {
                                     .drag_bounds(rect.translate(start))
                                     .show(ui.ctx(), |ui| {
                                         Frame::window(ui.style().as_ref()).show(ui, |ui| {
-                                            ui.code(
-                                                n.display_header(ctx.code().deref()).to_string(),
-                                            )
+                                            ui.code(n.display_header(&ctx.named()).to_string())
                                         })
                                     })
                                     .response
                                     .rect,
                             );
                         }
-                        for e in cg.edge_references() {
+                        for e in cg.graph.edge_references() {
                             // Paint a nice bezier curve as the link between nodes
                             let s = nodes_pos.get(&e.source()).unwrap().center_bottom();
                             let t = nodes_pos.get(&e.target()).unwrap().center_top();
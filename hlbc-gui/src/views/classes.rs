@@ -1,8 +1,9 @@
 use std::ops::Deref;
 
 use eframe::egui::style::Margin;
-use eframe::egui::{Color32, Frame, RichText, ScrollArea, TextStyle, Ui, WidgetText};
+use eframe::egui::{Color32, Frame, RichText, ScrollArea, TextEdit, TextStyle, Ui, WidgetText};
 
+use hlbc::analysis::tags::TagTarget;
 use hlbc::analysis::IsFromStd;
 use hlbc::types::{RefType, Type};
 
@@ -12,8 +13,12 @@ use crate::{AppCtxHandle, AppView, ItemSelection};
 #[derive(Default)]
 pub(crate) struct ClassesView {
     show_std: bool,
+    /// Only show classes with this tag
+    tag_filter: String,
     cache: Vec<(RefType, String)>,
     cache_valid: bool,
+    /// Tags generation the cache was built with
+    tags_gen: u64,
 }
 
 impl AppView for ClassesView {
@@ -22,15 +27,19 @@ impl AppView for ClassesView {
     }
 
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
-        if !self.cache_valid {
+        if !self.cache_valid || self.tags_gen != ctx.tags_generation() {
             let code = ctx.code();
             let code = code.deref();
+            let tags = ctx.tags();
+            let tag_filter = self.tag_filter.trim();
 
             self.cache = Vec::new();
             for (i, t) in code.types.iter().enumerate() {
                 match t {
                     Type::Obj(obj) => {
-                        let should_show = self.show_std || !obj.is_from_std(code);
+                        let should_show = (self.show_std || !obj.is_from_std(code))
+                            && (tag_filter.is_empty()
+                                || tags.has(TagTarget::Type(RefType(i)), tag_filter));
                         if should_show {
                             self.cache
                                 .push((RefType(i), obj.name.resolve(&code.strings).to_string()));
@@ -41,14 +50,27 @@ impl AppView for ClassesView {
             }
 
             self.cache_valid = true;
+            self.tags_gen = ctx.tags_generation();
         }
 
         Frame::none()
             .inner_margin(Margin::same(4.0))
             .show(ui, |ui| {
-                if ui.checkbox(&mut self.show_std, "Show stdlib").changed() {
-                    self.cache_valid = false;
-                }
+                ui.horizontal_wrapped(|ui| {
+                    if ui.checkbox(&mut self.show_std, "Show stdlib").changed() {
+                        self.cache_valid = false;
+                    }
+                    if ui
+                        .add(
+                            TextEdit::singleline(&mut self.tag_filter)
+                                .hint_text("Filter by tag")
+                                .desired_width(100.0),
+                        )
+                        .changed()
+                    {
+                        self.cache_valid = false;
+                    }
+                });
 
                 ui.add_space(4.0);
 
@@ -0,0 +1,327 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use eframe::egui::style::Margin;
+use eframe::egui::{
+    Color32, Frame, Key, RichText, ScrollArea, TextEdit, TextStyle, Ui, WidgetText,
+};
+use termcolor::{ColorSpec, WriteColor};
+
+use hlbc::types::{RefFun, RefGlobal, RefString, RefType};
+use hlbc::Bytecode;
+use hlbc_cli::command::{commands_parser, Command, ParseContext, Parser};
+use hlbc_cli::session::{process_command, Session};
+
+use crate::{AppCtxHandle, AppView, ItemSelection};
+
+/// Number of commands kept in the console
+const MAX_ENTRIES: usize = 100;
+
+/// The command interpreter of hlbc-cli, operating on the loaded file. Elements in the output can be clicked to
+/// select them.
+#[derive(Default)]
+pub(crate) struct ConsoleView {
+    /// Created on the first command
+    session: Option<Session>,
+    input: String,
+    /// Commands entered, for the up and down keys
+    history: Vec<String>,
+    history_pos: usize,
+    entries: Vec<Entry>,
+}
+
+/// A command and its output
+struct Entry {
+    command: String,
+    lines: Vec<Vec<Piece>>,
+}
+
+/// Part of an output line
+struct Piece {
+    text: String,
+    color: Option<termcolor::Color>,
+    link: Option<ItemSelection>,
+}
+
+/// Output of the interpreter, keeps the colors
+#[derive(Default)]
+struct Output {
+    spans: Vec<(String, Option<termcolor::Color>)>,
+    color: Option<termcolor::Color>,
+}
+
+impl io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        match self.spans.last_mut() {
+            Some((last, color)) if *color == self.color => last.push_str(&text),
+            _ => self.spans.push((text.into_owned(), self.color)),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WriteColor for Output {
+    fn supports_color(&self) -> bool {
+        true
+    }
+
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.color = spec.fg().copied();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.color = None;
+        Ok(())
+    }
+}
+
+impl Output {
+    /// Split the output in lines and find the elements referenced
+    fn into_lines(self, code: &Bytecode) -> Vec<Vec<Piece>> {
+        let mut lines = vec![Vec::new()];
+        for (text, color) in self.spans {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    lines.push(Vec::new());
+                }
+                let pieces = lines.last_mut().unwrap();
+                let mut plain = String::new();
+                let mut prev = "";
+                for word in line.split_inclusive(' ') {
+                    let token = word.trim_end();
+                    if let Some(link) = link(code, prev, token) {
+                        pieces.push(Piece {
+                            text: std::mem::take(&mut plain),
+                            color,
+                            link: None,
+                        });
+                        pieces.push(Piece {
+                            text: token.to_owned(),
+                            color,
+                            link: Some(link),
+                        });
+                        plain.push_str(&word[token.len()..]);
+                    } else {
+                        plain.push_str(word);
+                    }
+                    if !token.is_empty() {
+                        prev = token;
+                    }
+                }
+                pieces.push(Piece {
+                    text: plain,
+                    color,
+                    link: None,
+                });
+            }
+        }
+        // The output of a command ends with a new line
+        if lines
+            .last()
+            .map_or(false, |l| l.iter().all(|p| p.text.is_empty()))
+        {
+            lines.pop();
+        }
+        lines
+    }
+}
+
+/// Element referenced by a word of the output like `fn@12`, `type@3` or `name@12` after `fn`
+fn link(code: &Bytecode, prev: &str, token: &str) -> Option<ItemSelection> {
+    let (name, index) = token.trim_end_matches([',', ':', ')']).rsplit_once('@')?;
+    let index: usize = index.parse().ok()?;
+    let item = match name {
+        "fn" | "f" => ItemSelection::Fun(RefFun(index)),
+        "type" | "t" => ItemSelection::Class(RefType(index)),
+        "global" | "g" => ItemSelection::Global(RefGlobal(index)),
+        "string" | "s" => ItemSelection::String(RefString(index)),
+        _ if prev == "fn" || prev == "fn:native" => ItemSelection::Fun(RefFun(index)),
+        _ => return None,
+    };
+    let valid = match item {
+        ItemSelection::Fun(f) => f.0 < code.findexes.len(),
+        ItemSelection::Class(t) => t.resolve_as_obj(&code.types).is_some(),
+        ItemSelection::Global(g) => g.0 < code.globals.len(),
+        ItemSelection::String(s) => s.0 < code.strings.len(),
+        ItemSelection::None => false,
+    };
+    valid.then_some(item)
+}
+
+fn color(color: Option<termcolor::Color>) -> Color32 {
+    use termcolor::Color;
+    match color {
+        Some(Color::Red) => Color32::LIGHT_RED,
+        Some(Color::Green) => Color32::LIGHT_GREEN,
+        Some(Color::Yellow) => Color32::YELLOW,
+        Some(Color::Blue) => Color32::LIGHT_BLUE,
+        Some(Color::Magenta) => Color32::from_rgb(255, 128, 255),
+        Some(Color::Cyan) => Color32::from_rgb(128, 255, 255),
+        Some(Color::Black) => Color32::BLACK,
+        Some(Color::Ansi256(_)) => Color32::GRAY,
+        Some(Color::Rgb(r, g, b)) => Color32::from_rgb(r, g, b),
+        _ => Color32::WHITE,
+    }
+}
+
+impl ConsoleView {
+    fn run(&mut self, ctx: &AppCtxHandle, line: String) {
+        let code = ctx.code();
+        let mut out = Output::default();
+        if self.session.is_none() {
+            let file = ctx.file();
+            match Session::new(Path::new(&file), Path::new(&file)) {
+                #[allow(unused_mut)]
+                Ok(mut session) => {
+                    #[cfg(feature = "autotag")]
+                    {
+                        session.original_names = Some(ctx.original_names().clone());
+                        session.names = ctx.names().clone();
+                    }
+                    self.session = Some(session);
+                }
+                Err(e) => {
+                    self.push(code, line, format!("Can't start the session : {e}"));
+                    return;
+                }
+            }
+        }
+        let session = self.session.as_mut().unwrap();
+        // The tags are shared with the other views
+        session.tags = ctx.tags().clone();
+
+        match commands_parser(&ParseContext::new(code)).parse(line.as_str()) {
+            Ok(commands) => {
+                for cmd in commands {
+                    let res = match cmd {
+                        Command::Exit => {
+                            self.entries.clear();
+                            continue;
+                        }
+                        cmd => process_command(&mut out, code, session, cmd),
+                    };
+                    if let Err(e) = res {
+                        let _ = writeln!(out, "{e}");
+                    }
+                    // The views share the loaded bytecode, which isn't modified
+                    #[cfg(feature = "autotag")]
+                    if !session.pending_renames.is_empty() {
+                        session.pending_renames = Default::default();
+                        let _ =
+                            writeln!(out, "The renames are shown when the file is opened again");
+                    }
+                }
+            }
+            Err(errors) => {
+                for e in errors {
+                    let _ = writeln!(out, "{e}");
+                }
+            }
+        }
+
+        if session.tags != *ctx.tags() {
+            ctx.set_tags(session.tags.clone());
+        }
+        let lines = out.into_lines(code);
+        self.entries.push(Entry {
+            command: line,
+            lines,
+        });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+
+    fn push(&mut self, code: &Bytecode, command: String, message: String) {
+        let out = Output {
+            spans: vec![(message, Some(termcolor::Color::Red))],
+            color: None,
+        };
+        self.entries.push(Entry {
+            command,
+            lines: out.into_lines(code),
+        });
+    }
+}
+
+impl AppView for ConsoleView {
+    fn title(&self) -> WidgetText {
+        RichText::new("Console").color(Color32::WHITE).into()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        Frame::none()
+            .inner_margin(Margin::same(4.0))
+            .show(ui, |ui| {
+                let input = ui.add(
+                    TextEdit::singleline(&mut self.input)
+                        .font(TextStyle::Monospace)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("Command, 'help' to list them"),
+                );
+                if input.has_focus() && !self.history.is_empty() {
+                    let (up, down) =
+                        ui.input(|i| (i.key_pressed(Key::ArrowUp), i.key_pressed(Key::ArrowDown)));
+                    if up && self.history_pos > 0 {
+                        self.history_pos -= 1;
+                        self.input = self.history[self.history_pos].clone();
+                    } else if down && self.history_pos < self.history.len() {
+                        self.history_pos += 1;
+                        self.input = self
+                            .history
+                            .get(self.history_pos)
+                            .cloned()
+                            .unwrap_or_default();
+                    }
+                }
+                if input.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                    let line = std::mem::take(&mut self.input);
+                    if !line.trim().is_empty() {
+                        self.history.push(line.clone());
+                        self.history_pos = self.history.len();
+                        self.run(&ctx, line);
+                    }
+                    input.request_focus();
+                }
+
+                ui.add_space(4.0);
+
+                ScrollArea::both()
+                    .auto_shrink([false, false])
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        ui.spacing_mut().item_spacing.x = 0.0;
+                        for entry in &self.entries {
+                            ui.label(
+                                RichText::new(format!("> {}", entry.command))
+                                    .monospace()
+                                    .color(Color32::YELLOW),
+                            );
+                            for line in &entry.lines {
+                                ui.horizontal(|ui| {
+                                    for piece in line {
+                                        if piece.text.is_empty() {
+                                            continue;
+                                        }
+                                        let text = RichText::new(&piece.text).monospace();
+                                        if let Some(item) = piece.link {
+                                            if ui.link(text).clicked() {
+                                                ctx.set_selected(item);
+                                            }
+                                        } else {
+                                            ui.label(text.color(color(piece.color)));
+                                        }
+                                    }
+                                });
+                            }
+                        }
+                    });
+            });
+    }
+}
@@ -1,9 +1,10 @@
 use std::ops::Deref;
 
-use eframe::egui::text::{LayoutJob, LayoutSection};
+use eframe::egui::text::{CCursor, LayoutJob, LayoutSection};
 use eframe::egui::util::cache::{ComputerMut, FrameCache};
 use eframe::egui::{
-    Color32, FontId, RichText, ScrollArea, Stroke, TextEdit, TextFormat, Ui, WidgetText,
+    Align, Color32, FontId, Rect, RichText, ScrollArea, Stroke, TextEdit, TextFormat, Ui,
+    WidgetText,
 };
 use syntect::easy::HighlightLines;
 use syntect::highlighting::{FontStyle, ThemeSet};
@@ -21,6 +22,22 @@ pub(crate) struct DecompilerView {
     output: String,
     // Cache key for decompilation
     cache_selected: ItemSelection,
+    /// Text of the line to highlight in the output of an item, see
+    /// [hlbc_decompiler::diagnostics::Diagnostic::marker]
+    focus: Option<(ItemSelection, String)>,
+    /// Scroll to the highlighted line on the next frame
+    scroll_to_focus: bool,
+}
+
+impl DecompilerView {
+    /// Highlight the line containing `marker` in the output of `item`
+    pub(crate) fn focused(item: ItemSelection, marker: String) -> Self {
+        Self {
+            focus: Some((item, marker)),
+            scroll_to_focus: true,
+            ..Self::default()
+        }
+    }
 }
 
 impl AppView for DecompilerView {
@@ -37,15 +54,17 @@ impl AppView for DecompilerView {
 
             self.output = match ctx.selected() {
                 ItemSelection::Fun(fun) => match fun.resolve(code) {
-                    FunPtr::Fun(func) => decompile_function(code, func)
-                        .display(code, &FormatOptions::new("  "))
-                        .to_string(),
+                    FunPtr::Fun(func) => match decompile_function(code, func) {
+                        Ok(method) => method.display(code, &FormatOptions::new("  ")).to_string(),
+                        Err(e) => e.to_string(),
+                    },
                     FunPtr::Native(n) => n.display_header(code).to_string(),
                 },
                 ItemSelection::Class(t) => {
-                    decompile_class(code, t.resolve_as_obj(&code.types).unwrap())
-                        .display(code, &FormatOptions::new("  "))
-                        .to_string()
+                    match decompile_class(code, t.resolve_as_obj(&code.types).unwrap()) {
+                        Ok(class) => class.display(code, &FormatOptions::new("  ")).to_string(),
+                        Err(e) => e.to_string(),
+                    }
                 }
                 _ => String::new(),
             };
@@ -57,21 +76,46 @@ impl AppView for DecompilerView {
             .show(ui, |ui| {
                 // TextEdit will show us text we can edit (we don't want that)
                 // We need to pass a mut reference to an immutable str
-                ui.add(
-                    TextEdit::multiline(&mut self.output.as_ref())
-                        .code_editor()
-                        .lock_focus(false)
-                        .layouter(&mut |ui, code, _wrap| {
-                            let job = {
-                                ui.memory_mut(|mem| {
-                                    let cache =
-                                        mem.caches.cache::<FrameCache<LayoutJob, Highlighter>>();
-                                    cache.get(("base16-mocha.dark", code, "hx"))
-                                })
-                            };
-                            ui.fonts(|fonts| fonts.layout_job(job))
-                        }),
-                );
+                let output = TextEdit::multiline(&mut self.output.as_ref())
+                    .code_editor()
+                    .lock_focus(false)
+                    .layouter(&mut |ui, code, _wrap| {
+                        let job = {
+                            ui.memory_mut(|mem| {
+                                let cache =
+                                    mem.caches.cache::<FrameCache<LayoutJob, Highlighter>>();
+                                cache.get(("base16-mocha.dark", code, "hx"))
+                            })
+                        };
+                        ui.fonts(|fonts| fonts.layout_job(job))
+                    })
+                    .show(ui);
+
+                let focus = self
+                    .focus
+                    .as_ref()
+                    .filter(|(item, _)| *item == self.cache_selected)
+                    .and_then(|(_, marker)| self.output.find(marker.as_str()));
+                if let Some(start) = focus {
+                    let galley = &output.galley;
+                    let cursor =
+                        galley.from_ccursor(CCursor::new(self.output[..start].chars().count()));
+                    let row = galley
+                        .pos_from_cursor(&cursor)
+                        .translate(output.text_draw_pos.to_vec2());
+                    // The whole line, the cursor is only as wide as a character
+                    let line =
+                        Rect::from_x_y_ranges(output.text_clip_rect.x_range(), row.y_range());
+                    ui.painter().rect_filled(
+                        line,
+                        0.0,
+                        Color32::from_rgba_unmultiplied(255, 255, 0, 24),
+                    );
+                    if self.scroll_to_focus {
+                        ui.scroll_to_rect(line, Some(Align::Center));
+                        self.scroll_to_focus = false;
+                    }
+                }
             });
     }
 }
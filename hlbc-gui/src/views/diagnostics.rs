@@ -0,0 +1,122 @@
+use eframe::egui::style::Margin;
+use eframe::egui::{Color32, Frame, Grid, RichText, ScrollArea, Ui, WidgetText};
+
+use hlbc::types::{FunPtr, RefFun};
+use hlbc::Bytecode;
+use hlbc_decompiler::decompile_code_diagnostics;
+use hlbc_decompiler::diagnostics::{Diagnostic, DiagnosticKind};
+use hlbc_decompiler::inline::InlineOptions;
+
+use crate::views::DecompilerView;
+use crate::{AppCtxHandle, AppView, ItemSelection};
+
+/// Problems the decompiler worked around in the selected function or class, with links to the instruction and to
+/// the statement they affect
+#[derive(Default)]
+pub(crate) struct DiagnosticsView {
+    diagnostics: Vec<Diagnostic>,
+    /// Functions failing to decompile
+    errors: Vec<String>,
+    // Cache key for decompilation
+    cache_selected: ItemSelection,
+    /// Functions decompiled for the cached selection
+    functions: Vec<RefFun>,
+}
+
+/// Functions decompiled with an item, the methods and statics of a class
+fn functions(code: &Bytecode, item: ItemSelection) -> Vec<RefFun> {
+    match item {
+        ItemSelection::Fun(fun) => vec![fun],
+        ItemSelection::Class(t) => {
+            let statics = t
+                .resolve_as_obj(&code.types)
+                .filter(|obj| obj.global.0 > 0)
+                .map(|obj| code.globals[obj.global.0 - 1]);
+            code.functions
+                .iter()
+                .filter(|f| f.parent.is_some() && (f.parent == Some(t) || f.parent == statics))
+                .map(|f| f.findex)
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+impl AppView for DiagnosticsView {
+    fn title(&self) -> WidgetText {
+        RichText::new("Diagnostics").color(Color32::WHITE).into()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        let selected = ctx.selected();
+        // Jumping to one of the diagnostics selects its function, keep showing the whole list
+        let jumped = matches!(selected, ItemSelection::Fun(f) if self.functions.contains(&f));
+        if selected != self.cache_selected && !jumped {
+            let code = ctx.code();
+            self.diagnostics.clear();
+            self.errors.clear();
+            self.functions = functions(code, selected);
+            for &fun in &self.functions {
+                if let FunPtr::Fun(f) = fun.resolve(code) {
+                    match decompile_code_diagnostics(code, f, &InlineOptions::default()) {
+                        Ok((_, diagnostics)) => self.diagnostics.extend(diagnostics),
+                        Err(e) => self.errors.push(e.to_string()),
+                    }
+                }
+            }
+            self.cache_selected = selected;
+        }
+
+        Frame::none()
+            .inner_margin(Margin::same(4.0))
+            .show(ui, |ui| {
+                if self.functions.is_empty() {
+                    ui.label("Select a function or a class.");
+                    return;
+                }
+                for e in &self.errors {
+                    ui.colored_label(Color32::RED, e);
+                }
+                if self.diagnostics.is_empty() && self.errors.is_empty() {
+                    ui.label("Decompiled without problems.");
+                }
+                ScrollArea::vertical()
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        Grid::new("diagnostics")
+                            .striped(true)
+                            .num_columns(4)
+                            .show(ui, |ui| {
+                                for d in &self.diagnostics {
+                                    let color = match d.kind {
+                                        DiagnosticKind::Warning => Color32::YELLOW,
+                                        DiagnosticKind::Fallback => Color32::LIGHT_RED,
+                                        DiagnosticKind::UnsupportedOpcode => Color32::RED,
+                                    };
+                                    ui.colored_label(color, d.kind.to_string());
+                                    let location =
+                                        format!("{}:{}", d.findex.display_id(&ctx.named()), d.pos);
+                                    if ui
+                                        .link(location)
+                                        .on_hover_text("Show the instruction")
+                                        .clicked()
+                                    {
+                                        ctx.jump_to(d.findex, d.pos);
+                                    }
+                                    if ui
+                                        .link("statement")
+                                        .on_hover_text("Show the affected statement")
+                                        .clicked()
+                                    {
+                                        let item = ItemSelection::Fun(d.findex);
+                                        ctx.set_selected(item);
+                                        ctx.open_tab(DecompilerView::focused(item, d.marker()));
+                                    }
+                                    ui.label(&d.message);
+                                    ui.end_row();
+                                }
+                            });
+                    });
+            });
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use eframe::egui::style::Margin;
+use eframe::egui::{Color32, Frame, RichText, ScrollArea, TextStyle, Ui, WidgetText};
+use poll_promise::Promise;
+
+use hlbc::types::{Function, RefFun};
+use hlbc::Bytecode;
+use hlbc_analysis::diff::{diff, Change, FunctionChange};
+use hlbc_decompiler::diff::{diff_function, LineChange, SourceDiff};
+use hlbc_decompiler::inline::InlineOptions;
+
+use crate::views::AppView;
+use crate::{AppCtxHandle, ItemSelection};
+
+type Versions = (Bytecode, Bytecode, Vec<FunctionChange>);
+
+/// Modified functions between an older version and the opened file, as a diff of their decompiled sources
+#[derive(Default)]
+pub(crate) struct DiffView {
+    loader: Option<Promise<Result<Versions, String>>>,
+    /// The older version and the opened file, as loaded from disk
+    versions: Option<(Bytecode, Bytecode)>,
+    functions: Vec<FunctionChange>,
+    /// Index in `functions` of the function shown with its diff
+    selected: Option<(usize, Result<SourceDiff, String>)>,
+    error: Option<String>,
+}
+
+fn load(path: &Path) -> Result<Bytecode, String> {
+    fs::File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| Bytecode::load(&mut BufReader::new(f)).map_err(|e| e.to_string()))
+        .map_err(|e| format!("{} : {e}", path.display()))
+}
+
+fn compute(old: PathBuf, current: PathBuf) -> Result<Versions, String> {
+    let old = load(&old)?;
+    let new = load(&current)?;
+    let mut functions: Vec<FunctionChange> = diff(&old, &new)
+        .functions
+        .into_iter()
+        .filter(|f| f.change == Change::Modified)
+        .collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((old, new, functions))
+}
+
+fn source_diff(old: &Bytecode, new: &Bytecode, f: &FunctionChange) -> Result<SourceDiff, String> {
+    fn resolve(code: &Bytecode, fun: Option<RefFun>) -> Result<&Function, String> {
+        fun.and_then(|fun| fun.resolve_as_fn(code))
+            .ok_or_else(|| "Not a function".to_owned())
+    }
+    diff_function(
+        old,
+        resolve(old, f.old)?,
+        new,
+        resolve(new, f.new)?,
+        &InlineOptions::default(),
+    )
+    .map_err(|e| e.to_string())
+}
+
+impl AppView for DiffView {
+    fn title(&self) -> WidgetText {
+        RichText::new("± Diff").color(Color32::WHITE).into()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        if let Some(loader) = self.loader.take() {
+            match loader.try_take() {
+                Ok(Ok((old, new, functions))) => {
+                    self.versions = Some((old, new));
+                    self.functions = functions;
+                    self.selected = None;
+                    self.error = None;
+                }
+                Ok(Err(e)) => self.error = Some(e),
+                Err(loader) => {
+                    self.loader = Some(loader);
+                    ui.ctx().request_repaint();
+                }
+            }
+        }
+
+        Frame::none()
+            .inner_margin(Margin::same(4.0))
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Select older version")
+                        .on_hover_text("Compare the decompiled functions with the opened file")
+                        .clicked()
+                    {
+                        let current = PathBuf::from(ctx.file());
+                        self.loader =
+                            Some(Promise::spawn_thread(
+                                "diff",
+                                move || match rfd::FileDialog::new().pick_file() {
+                                    Some(file) => compute(file, current),
+                                    None => Err("No file selected".to_owned()),
+                                },
+                            ));
+                    }
+                    if self.loader.is_some() {
+                        ui.spinner();
+                    }
+                });
+                if let Some(e) = &self.error {
+                    ui.colored_label(Color32::RED, e);
+                }
+                ui.add_space(4.0);
+
+                let Some((old, new)) = &self.versions else {
+                    return;
+                };
+                ui.columns(2, |cols| {
+                    ScrollArea::vertical()
+                        .id_source("diff_functions_scroll_area")
+                        .auto_shrink([false, false])
+                        .show(&mut cols[0], |ui| {
+                            for (i, f) in self.functions.iter().enumerate() {
+                                let checked = matches!(self.selected, Some((s, _)) if s == i);
+                                if ui.selectable_label(checked, &f.name).clicked() {
+                                    self.selected = Some((i, source_diff(old, new, f)));
+                                    if let Some(findex) = f.new {
+                                        ctx.set_selected(ItemSelection::Fun(findex));
+                                    }
+                                }
+                            }
+                        });
+                    ScrollArea::both()
+                        .id_source("diff_source_scroll_area")
+                        .auto_shrink([false, false])
+                        .show(&mut cols[1], |ui| match &self.selected {
+                            Some((_, Ok(source))) if source.is_empty() => {
+                                ui.label("Same source, only the bytecode changed");
+                            }
+                            Some((_, Ok(source))) => {
+                                for line in &source.lines {
+                                    let color = match line.change {
+                                        LineChange::Unchanged => Color32::GRAY,
+                                        LineChange::Added => Color32::GREEN,
+                                        LineChange::Removed => Color32::RED,
+                                        LineChange::MovedFrom | LineChange::MovedTo => {
+                                            Color32::LIGHT_BLUE
+                                        }
+                                    };
+                                    ui.label(
+                                        RichText::new(format!(
+                                            "{}{}",
+                                            line.change.prefix(),
+                                            line.text
+                                        ))
+                                        .text_style(TextStyle::Monospace)
+                                        .color(color),
+                                    );
+                                }
+                            }
+                            Some((_, Err(e))) => {
+                                ui.colored_label(Color32::RED, e);
+                            }
+                            None => {}
+                        });
+                });
+            });
+    }
+}
@@ -1,8 +1,9 @@
 use std::ops::Deref;
 
 use eframe::egui::style::Margin;
-use eframe::egui::{Color32, Frame, RichText, ScrollArea, TextStyle, Ui, WidgetText};
+use eframe::egui::{Color32, Frame, RichText, ScrollArea, TextEdit, TextStyle, Ui, WidgetText};
 
+use hlbc::analysis::tags::TagTarget;
 use hlbc::analysis::IsFromStd;
 use hlbc::types::RefFun;
 
@@ -13,8 +14,12 @@ use crate::{AppCtxHandle, AppView, ItemSelection};
 pub(crate) struct FunctionsView {
     show_natives: bool,
     show_std: bool,
+    /// Only show functions with this tag
+    tag_filter: String,
     cache: Vec<RefFun>,
     cache_valid: bool,
+    /// Tags generation the cache was built with
+    tags_gen: u64,
 }
 
 impl AppView for FunctionsView {
@@ -24,19 +29,23 @@ impl AppView for FunctionsView {
 
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
         // Function list cache
-        if !self.cache_valid {
+        if !self.cache_valid || self.tags_gen != ctx.tags_generation() {
             self.cache = Vec::new();
             let code = ctx.code();
+            let tags = ctx.tags();
+            let tag_filter = self.tag_filter.trim();
             for fk in &ctx.code().findexes {
                 let f = fk.resolve(code.deref());
                 let findex = f.findex();
                 if (self.show_std || !findex.is_from_std(code.deref()))
                     && (self.show_natives || f.is_fun())
+                    && (tag_filter.is_empty() || tags.has(TagTarget::Fun(findex), tag_filter))
                 {
                     self.cache.push(findex);
                 }
             }
             self.cache_valid = true;
+            self.tags_gen = ctx.tags_generation();
         }
 
         Frame::none()
@@ -52,6 +61,16 @@ impl AppView for FunctionsView {
                     if ui.checkbox(&mut self.show_std, "Show stdlib").changed() {
                         self.cache_valid = false;
                     }
+                    if ui
+                        .add(
+                            TextEdit::singleline(&mut self.tag_filter)
+                                .hint_text("Filter by tag")
+                                .desired_width(100.0),
+                        )
+                        .changed()
+                    {
+                        self.cache_valid = false;
+                    }
                 });
 
                 ui.add_space(4.0);
@@ -65,7 +84,7 @@ impl AppView for FunctionsView {
                         self.cache.len(),
                         |ui, range| {
                             for f in range.map(|i| self.cache[i]) {
-                                let text = { f.display_header(ctx.code().deref()).to_string() };
+                                let text = { f.display_header(&ctx.named()).to_string() };
                                 let selected = match ctx.selected() {
                                     ItemSelection::Fun(f2) => f == f2,
                                     _ => false,
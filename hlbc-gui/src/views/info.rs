@@ -3,11 +3,18 @@ use std::ops::Deref;
 use eframe::egui::style::Margin;
 use eframe::egui::{Color32, Frame, Grid, RichText, ScrollArea, Ui, WidgetText};
 
+use hlbc_analysis::anomaly::{self, ObfuscationReport, Thresholds};
+
 use crate::views::AppView;
 use crate::AppCtxHandle;
 
 #[derive(Default)]
-pub(crate) struct InfoView;
+pub(crate) struct InfoView {
+    /// Number of functions with anomalies, computed once
+    suspicious: Option<usize>,
+    /// Signs of obfuscation, computed once
+    obfuscation: Option<ObfuscationReport>,
+}
 
 impl AppView for InfoView {
     fn title(&self) -> WidgetText {
@@ -80,6 +87,14 @@ impl AppView for InfoView {
                                     .on_hover_text("Functions, methods, closures");
                                 ui.label(code.functions.len().to_string());
                                 ui.end_row();
+                                ui.label("Suspicious functions").on_hover_text(
+                                    "Functions with anomalies, likely obfuscated",
+                                );
+                                let suspicious = *self.suspicious.get_or_insert_with(|| {
+                                    anomaly::suspicious_functions(code, &Thresholds::default()).count()
+                                });
+                                ui.label(suspicious.to_string());
+                                ui.end_row();
                                 if let Some(cst) = code.constants.as_ref() {
                                     ui.label("Constant definitions").on_hover_text(
                                         "Global variables initializers (since bytecode v4)",
@@ -87,7 +102,22 @@ impl AppView for InfoView {
                                     ui.label(cst.len().to_string());
                                     ui.end_row();
                                 }
+                                ui.label("Warnings").on_hover_text(
+                                    "Suspicious but valid contents, the file may have been modified",
+                                );
+                                ui.label(code.warnings.len().to_string());
+                                ui.end_row();
                             });
+                        let code = ctx.code();
+                        let obfuscation = self.obfuscation.get_or_insert_with(|| {
+                            ObfuscationReport::new(&code, &Thresholds::default())
+                        });
+                        for sign in &obfuscation.signs {
+                            ui.colored_label(Color32::YELLOW, format!("⚠ Obfuscation : {sign}"));
+                        }
+                        for w in &code.warnings {
+                            ui.colored_label(Color32::YELLOW, w.to_string());
+                        }
                     });
             });
     }
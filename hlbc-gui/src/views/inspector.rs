@@ -1,10 +1,19 @@
 use std::ops::Deref;
 
 use eframe::egui::style::Margin;
-use eframe::egui::{Color32, Frame, Grid, Link, RichText, ScrollArea, TextStyle, Ui, WidgetText};
+use eframe::egui::{
+    Color32, Frame, Grid, Id, Label, Link, RichText, ScrollArea, Sense, TextEdit, TextStyle, Ui,
+    WidgetText,
+};
 
+use hlbc::analysis::annotations::{Annotations, Severity};
+use hlbc::analysis::tags::{is_valid_tag, TagTarget};
 use hlbc::types::{FunPtr, RefField, RefFun, RefGlobal, RefString, RefType};
 use hlbc::Bytecode;
+use hlbc_analysis::anomaly::{self, Thresholds};
+use hlbc_analysis::slice::DataDeps;
+use hlbc_analysis::summary::ClassCard;
+use hlbc_analysis::xref::Xref;
 
 use crate::{AppCtxHandle, AppView, ItemSelection};
 
@@ -102,15 +111,59 @@ fn inspector_link(ui: &mut Ui, ctx: AppCtxHandle, item: ItemSelection) {
     }
 }
 
+/// Instructions referencing an element, clicking one jumps to it
+fn references_ui(ui: &mut Ui, ctx: AppCtxHandle, xrefs: &[Xref]) {
+    if xrefs.is_empty() {
+        ui.label("Not referenced by any instruction");
+        return;
+    }
+    ui.collapsing(format!("{} references", xrefs.len()), |ui| {
+        for &(f, pos) in xrefs {
+            let text = format!("{} at {pos}", f.display_id(&ctx.named()));
+            if ui.link(text).clicked() {
+                ctx.jump_to(f, pos);
+            }
+        }
+    });
+}
+
+/// Display and edit the tags of an element
+fn tags_ui(ui: &mut Ui, ctx: AppCtxHandle, target: TagTarget) {
+    ui.horizontal_wrapped(|ui| {
+        ui.label("Tags :");
+        let tags: Vec<String> = ctx.tags().tags_of(target).map(str::to_owned).collect();
+        for tag in tags {
+            if ui
+                .small_button(format!("{tag} ✖"))
+                .on_hover_text("Remove tag")
+                .clicked()
+            {
+                ctx.remove_tag(target, &tag);
+            }
+        }
+
+        let id = Id::new("inspector::tags").with(target.to_string());
+        let mut input = ui
+            .data_mut(|d| d.get_temp::<String>(id))
+            .unwrap_or_default();
+        let res = ui.add(
+            TextEdit::singleline(&mut input)
+                .hint_text("new tag")
+                .desired_width(80.0),
+        );
+        if res.lost_focus() && is_valid_tag(input.trim()) {
+            ctx.add_tag(target, input.trim());
+            input.clear();
+        }
+        ui.data_mut(|d| d.insert_temp(id, input));
+    });
+}
+
 fn function_inspector(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
     let code = ctx.code();
     match fun.resolve(code) {
         FunPtr::Fun(f) => {
-            ui.heading(format!(
-                "Function : {}@{}",
-                f.name_default(code),
-                f.findex.0
-            ));
+            ui.heading(format!("Function : {}", f.display_id(&ctx.named())));
             if let Some(parent) = f.parent {
                 ui.horizontal(|ui| {
                     ui.label("static/instance method of");
@@ -119,6 +172,20 @@ fn function_inspector(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
             } else {
                 ui.label("Probably a closure.");
             }
+            tags_ui(ui, ctx.clone(), TagTarget::Fun(fun));
+            let anomalies = anomaly::anomalies(code, f, &Thresholds::default());
+            if !anomalies.is_empty() {
+                ui.collapsing(
+                    RichText::new(format!("⚠ {} anomalies", anomalies.len()))
+                        .color(Color32::YELLOW),
+                    |ui| {
+                        ui.label("This function is probably obfuscated, decompilation may fail.");
+                        for a in anomalies {
+                            ui.label(a.to_string());
+                        }
+                    },
+                );
+            }
             ui.separator();
             ui.collapsing("Registers", |ui| {
                 Grid::new("inspector::function::registers")
@@ -133,27 +200,85 @@ fn function_inspector(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
                     });
             });
 
+            let mut annotations = Annotations::new();
+            anomaly::annotate_anomalies(code, f, &Thresholds::default(), &mut annotations);
+
+            // Selected instruction with its backward and forward slices
+            let slice_id = Id::new("inspector::function::slice").with(fun.0);
+            let mut slice =
+                ui.data_mut(|d| d.get_temp::<(usize, Vec<usize>, Vec<usize>)>(slice_id));
+            // Jumping to an instruction selects it
+            let location = ctx.take_location(fun);
+            if let Some(pos) = location {
+                let deps = DataDeps::new(f);
+                slice = Some((pos, deps.backward(pos, None), deps.forward(pos)));
+            }
+
             ui.add_space(6.0);
-            ScrollArea::vertical()
+            ui.label(
+                "Click an instruction to highlight its dependencies (blue) and dependents (green).",
+            );
+            let row_height = ui.text_style_height(&TextStyle::Monospace);
+            let mut instructions = ScrollArea::vertical()
                 .id_source("inspector::function::instructions")
-                .auto_shrink([false, false])
-                .show_rows(
-                    ui,
-                    ui.text_style_height(&TextStyle::Monospace),
-                    f.ops.len(),
-                    |ui, range| {
-                        for (i, o) in f
-                            .ops
-                            .iter()
-                            .enumerate()
-                            .skip(range.start)
-                            .take(range.end - range.start)
-                        {
-                            // TODO syntax highlighting here
-                            ui.monospace(format!("{i:>3}: {}", o.display(code, f, i as i32, 11)));
-                        }
-                    },
+                .auto_shrink([false, false]);
+            if let Some(pos) = location {
+                instructions = instructions.vertical_scroll_offset(
+                    pos as f32 * (row_height + ui.spacing().item_spacing.y),
                 );
+            }
+            instructions.show_rows(ui, row_height, f.ops.len(), |ui, range| {
+                for (i, o) in f
+                    .ops
+                    .iter()
+                    .enumerate()
+                    .skip(range.start)
+                    .take(range.end - range.start)
+                {
+                    // TODO syntax highlighting here
+                    ui.horizontal(|ui| {
+                        let mut text = RichText::new(format!(
+                            "{i:>3}: {}",
+                            o.display(&ctx.named(), f, i as i32, 11)
+                        ))
+                        .monospace();
+                        if let Some((pos, backward, forward)) = &slice {
+                            if *pos == i {
+                                text = text.strong().color(Color32::WHITE);
+                            } else if backward.binary_search(&i).is_ok() {
+                                text = text.color(Color32::LIGHT_BLUE);
+                            } else if forward.binary_search(&i).is_ok() {
+                                text = text.color(Color32::LIGHT_GREEN);
+                            }
+                        }
+                        if ui.add(Label::new(text).sense(Sense::click())).clicked() {
+                            slice = match slice.take() {
+                                // Clicking the selection again clears it
+                                Some((pos, ..)) if pos == i => None,
+                                _ => {
+                                    let deps = DataDeps::new(f);
+                                    Some((i, deps.backward(i, None), deps.forward(i)))
+                                }
+                            };
+                        }
+                        // Inline markers
+                        for note in annotations.get(f.findex, i) {
+                            let color = match note.severity {
+                                Severity::Info => Color32::LIGHT_BLUE,
+                                Severity::Warning => Color32::YELLOW,
+                                Severity::Error => Color32::RED,
+                            };
+                            ui.colored_label(color, "⚠").on_hover_text(note.to_string());
+                        }
+                    });
+                }
+            });
+            ui.data_mut(|d| match slice {
+                Some(slice) => d.insert_temp(slice_id, slice),
+                None => {
+                    d.remove::<(usize, Vec<usize>, Vec<usize>)>(slice_id);
+                }
+            });
         }
         FunPtr::Native(n) => {
             ui.heading("Native function");
@@ -161,6 +286,7 @@ fn function_inspector(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
             ui.label(format!("function name : {}", n.name.resolve(&code.strings)));
             ui.label(format!("function index : {}", n.findex.0))
                 .on_hover_text("This is the native function unique index in the function pool.");
+            tags_ui(ui, ctx.clone(), TagTarget::Fun(fun));
         }
     }
 }
@@ -168,6 +294,7 @@ fn function_inspector(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
 fn class_inspector(ui: &mut Ui, ctx: AppCtxHandle, t: RefType) {
     let code = ctx.code();
     ui.heading(format!("Class : {}", t.display_id(code)));
+    tags_ui(ui, ctx.clone(), TagTarget::Type(t));
     if let Some(obj) = t.resolve_as_obj(&code.types) {
         if let Some(super_) = obj.super_ {
             ui.horizontal(|ui| {
@@ -230,6 +357,69 @@ fn class_inspector(ui: &mut Ui, ctx: AppCtxHandle, t: RefType) {
                     });
             });
         }
+
+        if let Some(layout) = code.obj_layout(t) {
+            ui.add_space(6.0);
+            ui.collapsing("Memory layout", |ui| {
+                ui.label(format!(
+                    "{} bytes, aligned on {}",
+                    layout.size, layout.align
+                ));
+                Grid::new("inspector::class::layout")
+                    .striped(true)
+                    .num_columns(3)
+                    .show(ui, |ui| {
+                        for f in &layout.fields {
+                            ui.monospace(format!("+{}", f.offset));
+                            ui.label(f.name.resolve(&code.strings));
+                            ui.label(f.ty.display_id(code));
+                            ui.end_row();
+                        }
+                    });
+                if !layout.vtable.is_empty() {
+                    ui.label("Virtual table");
+                    Grid::new("inspector::class::vtable")
+                        .striped(true)
+                        .num_columns(2)
+                        .show(ui, |ui| {
+                            for m in &layout.vtable {
+                                ui.monospace(format!("slot {}", m.slot));
+                                inspector_link(ui, ctx.clone(), ItemSelection::Fun(m.findex));
+                                ui.end_row();
+                            }
+                        });
+                }
+            });
+        }
+
+        if let Some(card) = ClassCard::new(code, t) {
+            ui.add_space(6.0);
+            ui.collapsing("Summary", |ui| {
+                if ui.button("Copy as Markdown").clicked() {
+                    let md = card.to_markdown(code);
+                    ui.output_mut(|o| o.copied_text = md);
+                }
+                ui.label("Biggest methods");
+                for &(f, size) in card.methods.iter().take(5) {
+                    ui.horizontal(|ui| {
+                        inspector_link(ui, ctx.clone(), ItemSelection::Fun(f));
+                        ui.label(format!("{size} instructions"));
+                    });
+                }
+                if !card.natives.is_empty() {
+                    ui.label("Natives called");
+                    for &n in &card.natives {
+                        ui.monospace(n.display_header(code).to_string());
+                    }
+                }
+                if !card.strings.is_empty() {
+                    ui.label("Strings");
+                    for &s in &card.strings {
+                        inspector_link(ui, ctx.clone(), ItemSelection::String(s));
+                    }
+                }
+            });
+        }
     } else {
         ui.label("Invalid type");
     }
@@ -242,19 +432,19 @@ fn global_inspector(ui: &mut Ui, ctx: AppCtxHandle, g: RefGlobal) {
         ctx.code().globals[g.0].display_id(ctx.code().deref())
     ));
 
-    if let (Some(&cst), Some(constants)) = (
-        ctx.code().globals_initializers.get(&g),
-        &ctx.code().constants,
-    ) {
-        let def = &constants[cst];
-        ui.label(format!("{:?}", def.fields));
+    if let Some(value) = ctx.code().global_value(g) {
+        ui.label(format!("Value : {}", value.display(ctx.code().deref())));
     } else {
         ui.label("This global is initialized with code");
     }
+    let xrefs = ctx.xrefs().global(g).to_vec();
+    references_ui(ui, ctx, &xrefs);
 }
 
 fn string_inspector(ui: &mut Ui, ctx: AppCtxHandle, s: RefString) {
     ui.heading(format!("String@{}", s.0));
+    let xrefs = ctx.xrefs().string(s).to_vec();
+    references_ui(ui, ctx.clone(), &xrefs);
     ui.separator();
     ui.add_space(4.0);
     ui.label(RichText::new(s.resolve(&ctx.code().strings)).monospace());
@@ -4,24 +4,40 @@ use egui_dock::TabViewer;
 #[cfg(feature = "callgraph")]
 pub(crate) use callgraph::*;
 pub(crate) use classes::*;
+#[cfg(feature = "console")]
+pub(crate) use console::*;
 pub(crate) use decompiler::*;
+pub(crate) use diagnostics::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use diff::*;
 pub(crate) use functions::*;
 pub(crate) use globals::*;
 pub(crate) use info::*;
 pub(crate) use inspector::*;
+pub(crate) use plugin::*;
 pub(crate) use strings::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use timeline::*;
 
 use crate::AppCtxHandle;
 
 #[cfg(feature = "callgraph")]
 mod callgraph;
 mod classes;
+#[cfg(feature = "console")]
+mod console;
 mod decompiler;
+mod diagnostics;
+#[cfg(not(target_arch = "wasm32"))]
+mod diff;
 mod functions;
 mod globals;
 mod info;
 mod inspector;
+mod plugin;
 mod strings;
+#[cfg(not(target_arch = "wasm32"))]
+mod timeline;
 
 /// Tab viewer with dynamic dispatch because I don't care
 pub(crate) struct DynamicTabViewer(pub(crate) AppCtxHandle);
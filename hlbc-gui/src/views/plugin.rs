@@ -0,0 +1,74 @@
+use std::ops::Deref;
+use std::rc::Rc;
+
+use eframe::egui::style::Margin;
+use eframe::egui::{Color32, Frame, RichText, ScrollArea, Ui, WidgetText};
+
+use hlbc_plugin::{PluginCtx, Plugins};
+
+use crate::views::AppView;
+use crate::AppCtxHandle;
+
+/// A panel provided by a plugin
+pub(crate) struct PluginView {
+    plugins: Rc<Plugins>,
+    plugin: String,
+    panel: String,
+    /// Panel content, recomputed when the tags change
+    cache: Option<String>,
+    tags_gen: u64,
+}
+
+impl PluginView {
+    pub(crate) fn new(plugins: Rc<Plugins>, plugin: &str, panel: &str) -> Self {
+        Self {
+            plugins,
+            plugin: plugin.to_owned(),
+            panel: panel.to_owned(),
+            cache: None,
+            tags_gen: 0,
+        }
+    }
+}
+
+impl AppView for PluginView {
+    fn title(&self) -> WidgetText {
+        RichText::new(format!("🔌 {}", self.panel))
+            .color(Color32::WHITE)
+            .into()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        if self.cache.is_none() || self.tags_gen != ctx.tags_generation() {
+            let tags = ctx.tags();
+            let content = self
+                .plugins
+                .get(&self.plugin)
+                .map(|p| {
+                    p.panel(
+                        &PluginCtx {
+                            code: ctx.code(),
+                            tags: tags.deref(),
+                        },
+                        &self.panel,
+                    )
+                })
+                .unwrap_or_default();
+            self.cache = Some(content);
+            self.tags_gen = ctx.tags_generation();
+        }
+
+        Frame::none()
+            .inner_margin(Margin::same(4.0))
+            .show(ui, |ui| {
+                if ui.button("Refresh").clicked() {
+                    self.cache = None;
+                }
+                ScrollArea::both()
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        ui.monospace(self.cache.as_deref().unwrap_or_default());
+                    });
+            });
+    }
+}
@@ -2,14 +2,19 @@ use std::ops::Deref;
 
 use eframe::egui::style::Margin;
 use eframe::egui::text::LayoutJob;
-use eframe::egui::{Color32, Frame, RichText, ScrollArea, TextStyle, Ui, WidgetText};
+use eframe::egui::{Color32, Frame, RichText, ScrollArea, TextEdit, TextStyle, Ui, WidgetText};
 
 use hlbc::types::RefString;
 
 use crate::{AppCtxHandle, AppView, ItemSelection};
 
 #[derive(Default)]
-pub(crate) struct StringsView;
+pub(crate) struct StringsView {
+    /// Only show strings containing this
+    search: String,
+    cache: Vec<RefString>,
+    cache_valid: bool,
+}
 
 impl AppView for StringsView {
     fn title(&self) -> WidgetText {
@@ -17,17 +22,34 @@ impl AppView for StringsView {
     }
 
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        // String list cache
+        if !self.cache_valid {
+            self.cache = if self.search.is_empty() {
+                (0..ctx.code().strings.len()).map(RefString).collect()
+            } else {
+                ctx.search().strings(ctx.code(), &self.search)
+            };
+            self.cache_valid = true;
+        }
+
         Frame::none()
             .inner_margin(Margin::same(4.0))
             .show(ui, |ui| {
-                let num = ctx.code().strings.len();
+                if ui
+                    .add(TextEdit::singleline(&mut self.search).hint_text("Search"))
+                    .changed()
+                {
+                    self.cache_valid = false;
+                }
+
+                ui.add_space(4.0);
 
                 ScrollArea::both().auto_shrink([false, false]).show_rows(
                     ui,
                     ui.text_style_height(&TextStyle::Body),
-                    num,
+                    self.cache.len(),
                     |ui, range| {
-                        for s in range.map(RefString) {
+                        for s in range.map(|i| self.cache[i]) {
                             let checked = match ctx.selected() {
                                 ItemSelection::String(s2) => s == s2,
                                 _ => false,
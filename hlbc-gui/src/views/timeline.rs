@@ -0,0 +1,156 @@
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use eframe::egui::style::Margin;
+use eframe::egui::{Color32, Frame, Grid, RichText, ScrollArea, Ui, WidgetText};
+use poll_promise::Promise;
+
+use hlbc::Bytecode;
+use hlbc_analysis::diff::{timeline, Change, FunctionHistory};
+
+use crate::views::AppView;
+use crate::{AppCtxHandle, ItemSelection};
+
+type Timeline = (Vec<String>, Vec<FunctionHistory>);
+
+/// Changes of each function across older versions of the opened file
+#[derive(Default)]
+pub(crate) struct TimelineView {
+    loader: Option<Promise<Result<Timeline, String>>>,
+    /// Version names, the opened file is the last one
+    versions: Vec<String>,
+    /// Functions that changed at least once
+    functions: Vec<FunctionHistory>,
+    error: Option<String>,
+}
+
+fn load(path: &Path) -> Result<Bytecode, String> {
+    fs::File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| Bytecode::load(&mut BufReader::new(f)).map_err(|e| e.to_string()))
+        .map_err(|e| format!("{} : {e}", path.display()))
+}
+
+/// Load the older versions then the current one and compute the timeline
+fn compute(mut files: Vec<PathBuf>, current: PathBuf) -> Result<Timeline, String> {
+    files.sort();
+    files.push(current);
+    let code = files
+        .iter()
+        .map(|p| load(p))
+        .collect::<Result<Vec<_>, _>>()?;
+    let versions = files
+        .iter()
+        .map(|p| {
+            p.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    let mut functions: Vec<FunctionHistory> = timeline(&code.iter().collect::<Vec<_>>())
+        .into_iter()
+        .filter(|f| !f.events.is_empty())
+        .collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((versions, functions))
+}
+
+impl AppView for TimelineView {
+    fn title(&self) -> WidgetText {
+        RichText::new("🕑 Timeline").color(Color32::WHITE).into()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        if let Some(loader) = self.loader.take() {
+            match loader.try_take() {
+                Ok(Ok((versions, functions))) => {
+                    self.versions = versions;
+                    self.functions = functions;
+                    self.error = None;
+                }
+                Ok(Err(e)) => self.error = Some(e),
+                Err(loader) => {
+                    self.loader = Some(loader);
+                    ui.ctx().request_repaint();
+                }
+            }
+        }
+
+        Frame::none()
+            .inner_margin(Margin::same(4.0))
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Select older versions")
+                        .on_hover_text(
+                            "Versions are ordered by file name, the opened file is the latest",
+                        )
+                        .clicked()
+                    {
+                        let current = PathBuf::from(ctx.file());
+                        self.loader = Some(Promise::spawn_thread("timeline", move || {
+                            match rfd::FileDialog::new().pick_files() {
+                                Some(files) => compute(files, current),
+                                None => Err("No file selected".to_owned()),
+                            }
+                        }));
+                    }
+                    if self.loader.is_some() {
+                        ui.spinner();
+                    }
+                });
+                if let Some(e) = &self.error {
+                    ui.colored_label(Color32::RED, e);
+                }
+                ui.add_space(4.0);
+
+                ScrollArea::both()
+                    .id_source("timeline_scroll_area")
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        Grid::new("timeline_grid")
+                            .striped(true)
+                            .num_columns(self.versions.len() + 1)
+                            .show(ui, |ui| {
+                                if self.versions.is_empty() {
+                                    return;
+                                }
+                                ui.label("Function");
+                                for v in &self.versions {
+                                    ui.label(v);
+                                }
+                                ui.end_row();
+                                for f in &self.functions {
+                                    match f.current {
+                                        Some(findex) => {
+                                            if ui.link(&f.name).clicked() {
+                                                ctx.set_selected(ItemSelection::Fun(findex));
+                                            }
+                                        }
+                                        None => {
+                                            ui.label(&f.name);
+                                        }
+                                    }
+                                    for v in 0..self.versions.len() {
+                                        match f.events.iter().find(|(i, _)| *i == v) {
+                                            Some((_, Change::Added)) => {
+                                                ui.colored_label(Color32::GREEN, "added")
+                                            }
+                                            Some((_, Change::Removed)) => {
+                                                ui.colored_label(Color32::RED, "removed")
+                                            }
+                                            Some((_, Change::Modified)) => {
+                                                ui.colored_label(Color32::YELLOW, "modified")
+                                            }
+                                            _ => ui.label(""),
+                                        };
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                    });
+            });
+    }
+}
@@ -0,0 +1,264 @@
+//! Plugins extending the cli with new commands and the gui with new panels.
+//!
+//! A plugin implements [Plugin] and is registered in a [Plugins] collection, either directly by a program embedding
+//! the cli or gui, or loaded from a dynamic library (feature `dynamic`) declared with [declare_plugin].
+//!
+//! Plugins don't depend on any ui framework : commands write text to a sink and panels return the text to display.
+//! ```
+//! use std::io::Write;
+//! use hlbc_plugin::{Plugin, PluginCommand, PluginCtx};
+//!
+//! struct Count;
+//!
+//! impl Plugin for Count {
+//!     fn name(&self) -> &str {
+//!         "count"
+//!     }
+//!
+//!     fn commands(&self) -> Vec<PluginCommand> {
+//!         vec![PluginCommand::new("count", "", "Count functions")]
+//!     }
+//!
+//!     fn run_command(&self, ctx: &PluginCtx, _: &str, _: &str, out: &mut dyn Write) -> std::io::Result<()> {
+//!         writeln!(out, "{} functions", ctx.code.functions.len())
+//!     }
+//! }
+//! ```
+
+use std::io;
+use std::io::Write;
+
+use hlbc::analysis::tags::Tags;
+use hlbc::Bytecode;
+
+/// Error while loading a plugin
+#[derive(thiserror::Error, Debug)]
+pub enum PluginError {
+    #[cfg(all(feature = "dynamic", not(target_arch = "wasm32")))]
+    #[error("Can't load plugin : {0}")]
+    Load(#[from] libloading::Error),
+    #[error("Plugin '{0}' is already registered")]
+    Duplicate(String),
+}
+
+/// Shared analysis context given to plugins
+pub struct PluginCtx<'a> {
+    pub code: &'a Bytecode,
+    pub tags: &'a Tags,
+}
+
+/// Description of a command added by a plugin
+#[derive(Debug, Clone)]
+pub struct PluginCommand {
+    /// Name used to call the command
+    pub name: String,
+    /// Arguments shown in the help message
+    pub usage: String,
+    /// Short help message
+    pub help: String,
+}
+
+impl PluginCommand {
+    pub fn new(name: &str, usage: &str, help: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            usage: usage.to_owned(),
+            help: help.to_owned(),
+        }
+    }
+}
+
+/// A plugin, every method has a default implementation doing nothing
+pub trait Plugin {
+    /// Unique name of the plugin
+    fn name(&self) -> &str;
+
+    /// Commands to add to the cli
+    fn commands(&self) -> Vec<PluginCommand> {
+        Vec::new()
+    }
+
+    /// Execute one of the commands of this plugin, `args` is the rest of the command line
+    fn run_command(
+        &self,
+        _ctx: &PluginCtx,
+        _command: &str,
+        _args: &str,
+        _out: &mut dyn Write,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Names of the panels to add to the gui
+    fn panels(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Text content of a panel
+    fn panel(&self, _ctx: &PluginCtx, _panel: &str) -> String {
+        String::new()
+    }
+}
+
+/// Collection of registered plugins
+#[derive(Default)]
+pub struct Plugins {
+    // Declared before the libraries so plugins are dropped first
+    plugins: Vec<Box<dyn Plugin>>,
+    #[cfg(all(feature = "dynamic", not(target_arch = "wasm32")))]
+    libs: Vec<libloading::Library>,
+}
+
+impl Plugins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, plugin: Box<dyn Plugin>) -> Result<(), PluginError> {
+        if self.get(plugin.name()).is_some() {
+            return Err(PluginError::Duplicate(plugin.name().to_owned()));
+        }
+        self.plugins.push(plugin);
+        Ok(())
+    }
+
+    /// Load a plugin from a dynamic library declared with [declare_plugin].
+    ///
+    /// # Safety
+    /// The library must have been built with the same compiler and the same version of hlbc-plugin.
+    /// It's running arbitrary code.
+    #[cfg(all(feature = "dynamic", not(target_arch = "wasm32")))]
+    pub unsafe fn load(&mut self, path: impl AsRef<std::ffi::OsStr>) -> Result<(), PluginError> {
+        let lib = libloading::Library::new(path)?;
+        let create: libloading::Symbol<extern "C" fn() -> *mut Box<dyn Plugin>> =
+            lib.get(b"_hlbc_plugin_create")?;
+        let plugin = *Box::from_raw(create());
+        self.libs.push(lib);
+        self.register(plugin)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Plugin> {
+        self.iter().find(|p| p.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Plugin> {
+        self.plugins.iter().map(|p| p.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Find the plugin providing a command
+    pub fn find_command(&self, command: &str) -> Option<&dyn Plugin> {
+        self.iter()
+            .find(|p| p.commands().iter().any(|c| c.name == command))
+    }
+}
+
+/// Export a plugin from a dynamic library, the library crate type must be `cdylib`.
+/// ```ignore
+/// hlbc_plugin::declare_plugin!(MyPlugin::new());
+/// ```
+/// The plugin is boxed twice to go through the C ABI as a thin pointer, [Plugins::load] takes ownership of it.
+#[macro_export]
+macro_rules! declare_plugin {
+    ($create:expr) => {
+        #[no_mangle]
+        pub extern "C" fn _hlbc_plugin_create() -> *mut Box<dyn $crate::Plugin> {
+            let plugin: Box<dyn $crate::Plugin> = Box::new($create);
+            Box::into_raw(Box::new(plugin))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Write;
+
+    use hlbc::analysis::tags::Tags;
+    use hlbc::builder::sample;
+
+    use crate::{Plugin, PluginCommand, PluginCtx, PluginError, Plugins};
+
+    struct Echo(&'static str);
+
+    impl Plugin for Echo {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn commands(&self) -> Vec<PluginCommand> {
+            vec![PluginCommand::new(
+                &format!("{}-echo", self.0),
+                "<text>",
+                "Print the text",
+            )]
+        }
+
+        fn run_command(
+            &self,
+            _ctx: &PluginCtx,
+            _command: &str,
+            args: &str,
+            out: &mut dyn Write,
+        ) -> io::Result<()> {
+            writeln!(out, "{args}")
+        }
+    }
+
+    mod exported {
+        crate::declare_plugin!(super::Echo("exported"));
+    }
+
+    #[test]
+    fn declare_plugin() {
+        // What Plugins::load does with the symbol of the library
+        let plugin = unsafe { *Box::from_raw(exported::_hlbc_plugin_create()) };
+        assert_eq!(plugin.name(), "exported");
+    }
+
+    #[test]
+    fn register() {
+        let mut plugins = Plugins::new();
+        assert!(plugins.is_empty());
+        plugins.register(Box::new(Echo("a"))).unwrap();
+        plugins.register(Box::new(Echo("b"))).unwrap();
+        assert!(matches!(
+            plugins.register(Box::new(Echo("a"))),
+            Err(PluginError::Duplicate(name)) if name == "a"
+        ));
+        assert_eq!(
+            plugins.iter().map(|p| p.name()).collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert_eq!(plugins.get("b").map(|p| p.name()), Some("b"));
+        assert!(plugins.get("c").is_none());
+    }
+
+    #[test]
+    fn find_command() {
+        let mut plugins = Plugins::new();
+        plugins.register(Box::new(Echo("a"))).unwrap();
+        plugins.register(Box::new(Echo("b"))).unwrap();
+        let plugin = plugins.find_command("b-echo").unwrap();
+        assert_eq!(plugin.name(), "b");
+        assert!(plugins.find_command("c-echo").is_none());
+
+        let code = sample();
+        let tags = Tags::new();
+        let ctx = PluginCtx {
+            code: &code,
+            tags: &tags,
+        };
+        let mut out = Vec::new();
+        plugin
+            .run_command(&ctx, "b-echo", "hello", &mut out)
+            .unwrap();
+        assert_eq!(out, b"hello\n");
+        // Nothing by default
+        assert!(plugin.panels().is_empty());
+        assert_eq!(plugin.panel(&ctx, "panel"), "");
+    }
+}
@@ -0,0 +1,21 @@
+//! Write the sample bytecode to a file, to try the tools without a compiled Haxe program.
+//!
+//! `cargo run --example sample -- sample.hl`
+
+use std::env;
+use std::fs;
+use std::io::BufWriter;
+
+use hlbc::builder::sample;
+
+fn main() -> hlbc::Result<()> {
+    let path = env::args().nth(1).unwrap_or_else(|| "sample.hl".to_owned());
+    let code = sample();
+    code.serialize(&mut BufWriter::new(fs::File::create(&path)?))?;
+    println!(
+        "Written {} functions and {} types to {path}",
+        code.functions.len(),
+        code.types.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,136 @@
+//! Notes attached to instructions.
+//!
+//! Analyses and users write notes about an instruction of a function (a finding, a comment), renderers display them
+//! next to the instruction. Each note has a severity and a source, the name of what produced it, so an analysis can
+//! replace its own notes without touching the others.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::types::RefFun;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Note {
+    pub severity: Severity,
+    /// What produced this note, e.g. `anomaly` or `user`
+    pub source: String,
+    pub message: String,
+}
+
+impl Note {
+    pub fn new(severity: Severity, source: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            source: source.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Note {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.severity, self.source, self.message)
+    }
+}
+
+/// Notes of every instruction, by function and instruction position
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    notes: BTreeMap<(RefFun, usize), Vec<Note>>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach a note to the instruction at `pos` in function `f`
+    pub fn add(&mut self, f: RefFun, pos: usize, note: Note) {
+        self.notes.entry((f, pos)).or_default().push(note);
+    }
+
+    /// Notes of an instruction
+    pub fn get(&self, f: RefFun, pos: usize) -> &[Note] {
+        self.notes
+            .get(&(f, pos))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Notes of a function with the position of their instruction, in instruction order
+    pub fn function(&self, f: RefFun) -> impl Iterator<Item = (usize, &Note)> + '_ {
+        self.notes
+            .range((f, 0)..=(f, usize::MAX))
+            .flat_map(|(&(_, pos), notes)| notes.iter().map(move |n| (pos, n)))
+    }
+
+    /// Remove the notes of a function coming from `source`
+    pub fn clear(&mut self, f: RefFun, source: &str) {
+        for notes in self.notes.range_mut((f, 0)..=(f, usize::MAX)) {
+            notes.1.retain(|n| n.source != source);
+        }
+        self.notes.retain(|_, notes| !notes.is_empty());
+    }
+
+    /// Every note, by function and instruction position
+    pub fn iter(&self) -> impl Iterator<Item = (RefFun, usize, &Note)> {
+        self.notes
+            .iter()
+            .flat_map(|(&(f, pos), notes)| notes.iter().map(move |n| (f, pos, n)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::annotations::{Annotations, Note, Severity};
+    use crate::types::RefFun;
+
+    #[test]
+    fn annotations() {
+        let mut notes = Annotations::new();
+        notes.add(RefFun(1), 4, Note::new(Severity::Info, "user", "decrypts"));
+        notes.add(
+            RefFun(1),
+            2,
+            Note::new(Severity::Warning, "anomaly", "weird"),
+        );
+        notes.add(
+            RefFun(2),
+            0,
+            Note::new(Severity::Warning, "anomaly", "weird"),
+        );
+        assert_eq!(notes.get(RefFun(1), 4)[0].message, "decrypts");
+        let positions: Vec<usize> = notes.function(RefFun(1)).map(|(pos, _)| pos).collect();
+        assert_eq!(positions, [2, 4]);
+
+        notes.clear(RefFun(1), "anomaly");
+        assert!(notes.get(RefFun(1), 2).is_empty());
+        assert_eq!(notes.iter().count(), 2);
+        assert_eq!(
+            notes.get(RefFun(1), 4)[0].to_string(),
+            "[info] user: decrypts"
+        );
+    }
+}
@@ -1,10 +1,12 @@
 use std::iter::repeat;
 
+use crate::opcodes::JumpOffset;
 use crate::types::{FunPtr, Reg};
 use crate::{Bytecode, Function, Native, Opcode, RefFun, RefType, Type, TypeObj};
 
-#[cfg(feature = "graph")]
-pub mod graph;
+pub mod annotations;
+pub mod names;
+pub mod tags;
 
 impl Bytecode {
     /// Iterate on every instruction of every function
@@ -15,6 +17,83 @@ impl Bytecode {
     }
 }
 
+impl Opcode {
+    /// Relative offset of a jump instruction (not including [Opcode::Switch])
+    pub fn jump_offset(&self) -> Option<JumpOffset> {
+        match *self {
+            Opcode::JTrue { offset, .. }
+            | Opcode::JFalse { offset, .. }
+            | Opcode::JNull { offset, .. }
+            | Opcode::JNotNull { offset, .. }
+            | Opcode::JSLt { offset, .. }
+            | Opcode::JSGte { offset, .. }
+            | Opcode::JSGt { offset, .. }
+            | Opcode::JSLte { offset, .. }
+            | Opcode::JULt { offset, .. }
+            | Opcode::JUGte { offset, .. }
+            | Opcode::JNotLt { offset, .. }
+            | Opcode::JNotGte { offset, .. }
+            | Opcode::JEq { offset, .. }
+            | Opcode::JNotEq { offset, .. }
+            | Opcode::JAlways { offset }
+            | Opcode::Trap { offset, .. } => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// Mutable access to the relative offset of a jump instruction (not including [Opcode::Switch])
+    pub fn jump_offset_mut(&mut self) -> Option<&mut JumpOffset> {
+        match self {
+            Opcode::JTrue { offset, .. }
+            | Opcode::JFalse { offset, .. }
+            | Opcode::JNull { offset, .. }
+            | Opcode::JNotNull { offset, .. }
+            | Opcode::JSLt { offset, .. }
+            | Opcode::JSGte { offset, .. }
+            | Opcode::JSGt { offset, .. }
+            | Opcode::JSLte { offset, .. }
+            | Opcode::JULt { offset, .. }
+            | Opcode::JUGte { offset, .. }
+            | Opcode::JNotLt { offset, .. }
+            | Opcode::JNotGte { offset, .. }
+            | Opcode::JEq { offset, .. }
+            | Opcode::JNotEq { offset, .. }
+            | Opcode::JAlways { offset }
+            | Opcode::Trap { offset, .. } => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// Absolute position of the jump target of this instruction at `pos` (not including [Opcode::Switch])
+    pub fn jump_target(&self, pos: usize) -> Option<usize> {
+        self.jump_offset()
+            .map(|offset| (pos as JumpOffset + offset + 1) as usize)
+    }
+
+    /// All the positions this instruction at `pos` can transfer control to, except the next instruction
+    pub fn jump_targets(&self, pos: usize) -> Vec<usize> {
+        match self {
+            Opcode::Switch { offsets, end, .. } => offsets
+                .iter()
+                .chain(std::iter::once(end))
+                .map(|offset| (pos as JumpOffset + offset + 1) as usize)
+                .collect(),
+            o => o.jump_target(pos).into_iter().collect(),
+        }
+    }
+
+    /// Returns true if the execution never continues to the next instruction
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self,
+            Opcode::JAlways { .. }
+                | Opcode::Ret { .. }
+                | Opcode::Throw { .. }
+                | Opcode::Rethrow { .. }
+        )
+    }
+}
+
 pub trait IsFromStd {
     /// Returns true if the object comes from the standard library.
     /// Requires debug info to be present as it's looking at file names.
@@ -0,0 +1,159 @@
+//! Synthesized names for anonymous types.
+//!
+//! Virtual types (anonymous structures and typedefs) have no name in the bytecode.
+//! We name them after the first place they're used at, in order of preference :
+//! 1. a class field of this type (`pos: { x, y }` becomes `Anon_Pos`)
+//! 2. a named function argument of this type
+//! 3. a named local variable of this type (from debug info)
+//! 4. their own field names (`{ x, y }` becomes `Anon_XY`)
+//!
+//! Names are made unique by appending a number. References out of bounds in a corrupted file are skipped.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::types::RefString;
+use crate::{Bytecode, RefType, Type};
+
+/// Prefix of every synthesized name
+pub const PREFIX: &str = "Anon";
+
+/// Name every virtual type in the bytecode
+pub fn virtual_names(code: &Bytecode) -> HashMap<RefType, String> {
+    let mut usages: HashMap<RefType, RefString> = HashMap::new();
+    let mut use_site = |ty: RefType, name: RefString| {
+        if name.0 != 0
+            && name.try_resolve(&code.strings).is_ok()
+            && matches!(ty.try_resolve(&code.types), Ok(Type::Virtual { .. }))
+        {
+            usages.entry(ty).or_insert(name);
+        }
+    };
+
+    for t in &code.types {
+        if let Type::Obj(obj) | Type::Struct(obj) = t {
+            for f in &obj.own_fields {
+                use_site(f.t, f.name);
+            }
+        }
+    }
+    for f in &code.functions {
+        let Some(fun_t) = f.t.resolve_as_fun(&code.types) else {
+            continue;
+        };
+        let nargs = fun_t.args.len();
+        let start = usize::from(f.is_method());
+        for (i, &ty) in fun_t.args.iter().enumerate().skip(start) {
+            if let Some(name) = f.assigns.as_ref().and_then(|a| {
+                a.iter()
+                    .filter(|&&(_, pos)| pos == 0)
+                    .nth(i - start)
+                    .map(|&(n, _)| n)
+            }) {
+                use_site(ty, name);
+            }
+        }
+        if let Some(assigns) = &f.assigns {
+            for &(name, pos) in assigns {
+                if pos > 0 {
+                    let dst = f.ops.get(pos - 1).and_then(|o| o.dst());
+                    if let Some(&ty) = dst
+                        .filter(|dst| dst.0 as usize >= nargs)
+                        .and_then(|dst| f.regs.get(dst.0 as usize))
+                    {
+                        use_site(ty, name);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut names = HashMap::new();
+    let mut taken = HashSet::new();
+    for (i, t) in code.types.iter().enumerate() {
+        let Type::Virtual { fields } = t else {
+            continue;
+        };
+        let base = match usages.get(&RefType(i)) {
+            Some(name) => format!("{PREFIX}_{}", pascal_case(name.resolve(&code.strings))),
+            None if fields.is_empty() => PREFIX.to_string(),
+            None => {
+                let mut base = format!("{PREFIX}_");
+                for f in fields.iter().take(3) {
+                    base.push_str(&pascal_case(
+                        f.name.try_resolve(&code.strings).unwrap_or(""),
+                    ));
+                }
+                base
+            }
+        };
+        let mut name = base.clone();
+        let mut n = 2;
+        while !taken.insert(name.clone()) {
+            name = format!("{base}{n}");
+            n += 1;
+        }
+        names.insert(RefType(i), name);
+    }
+    names
+}
+
+/// Turn an identifier into PascalCase, dropping any invalid character
+fn pascal_case(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut upper = true;
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            if upper {
+                out.push(c.to_ascii_uppercase());
+            } else {
+                out.push(c);
+            }
+            upper = false;
+        } else {
+            upper = true;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::names::pascal_case;
+    use crate::builder::BytecodeBuilder;
+    use crate::opcodes::Opcode;
+    use crate::types::{ObjField, Reg};
+    use crate::{RefType, Type};
+
+    #[test]
+    fn pascal() {
+        assert_eq!(pascal_case("pos"), "Pos");
+        assert_eq!(pascal_case("max_hp"), "MaxHp");
+        assert_eq!(pascal_case("$x"), "X");
+    }
+
+    #[test]
+    fn display_virtual() {
+        let mut b = BytecodeBuilder::new();
+        let i32 = b.ty(Type::I32);
+        let [x, y] = ["x", "y"].map(|n| b.string(n));
+        let fields = vec![ObjField { name: x, t: i32 }, ObjField { name: y, t: i32 }];
+        let pos = b.ty(Type::Virtual { fields });
+        let ref_pos = b.ty(Type::Ref(pos));
+        b.class("Entity", None, &[("pos", pos)], &[]);
+        let main = b.findex();
+        let main_t = b.fun_type(&[], RefType(0));
+        b.function(
+            main,
+            main_t,
+            vec![RefType(0)],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        b.entrypoint(main);
+        let code = b.build().unwrap();
+
+        assert_eq!(code.virtual_name(pos), Some("Anon_Pos"));
+        assert_eq!(code.virtual_name(ref_pos), None);
+        assert_eq!(pos.display(&code), "Anon_Pos");
+        assert_eq!(ref_pos.display(&code), "ref<Anon_Pos>");
+    }
+}
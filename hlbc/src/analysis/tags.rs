@@ -0,0 +1,254 @@
+//! User defined tags attached to functions and types.
+//!
+//! Tags are free-form labels (e.g. `network`, `crypto`, `todo`) used to triage large binaries.
+//! They are not part of the bytecode and are persisted in a sidecar text file, one tagged element per line :
+//! ```text
+//! fn@12 network crypto
+//! type@340 todo
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use crate::types::{RefFun, RefType};
+
+/// A bytecode element that can be tagged
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum TagTarget {
+    Fun(RefFun),
+    Type(RefType),
+}
+
+impl Display for TagTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TagTarget::Fun(fun) => write!(f, "fn@{}", fun.0),
+            TagTarget::Type(ty) => write!(f, "type@{}", ty.0),
+        }
+    }
+}
+
+impl FromStr for TagTarget {
+    type Err = String;
+
+    /// Parse a target like `fn@12` or `type@4`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, idx) = s
+            .split_once('@')
+            .ok_or_else(|| format!("Invalid tag target '{s}' (expected fn@idx or type@idx)"))?;
+        let idx = idx
+            .parse::<usize>()
+            .map_err(|e| format!("Invalid index in tag target '{s}' ({e})"))?;
+        match kind {
+            "fn" => Ok(TagTarget::Fun(RefFun(idx))),
+            "type" => Ok(TagTarget::Type(RefType(idx))),
+            other => Err(format!("Unknown tag target kind '{other}'")),
+        }
+    }
+}
+
+/// Returns true if the string can be used as a tag : not empty and no whitespace.
+pub fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty() && !tag.contains(char::is_whitespace)
+}
+
+/// Store for all tags of a bytecode file.
+///
+/// Tags attached by analyses (rules, entrypoints, profiles) are kept apart from the ones of the user : they are shown
+/// like the others but never saved, they are computed again each time the file is loaded.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Tags {
+    tags: BTreeMap<TagTarget, BTreeSet<String>>,
+    generated: BTreeMap<TagTarget, BTreeSet<String>>,
+}
+
+impl Tags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach a tag to an element. Returns false if the element already had this tag.
+    ///
+    /// Panics if the tag is not valid (see [is_valid_tag]).
+    pub fn add(&mut self, target: TagTarget, tag: impl Into<String>) -> bool {
+        let tag = tag.into();
+        assert!(is_valid_tag(&tag), "Invalid tag '{tag}'");
+        self.tags.entry(target).or_default().insert(tag)
+    }
+
+    /// Attach a tag computed by an analysis, it won't be saved. Returns false if the element already had this tag.
+    ///
+    /// Panics if the tag is not valid (see [is_valid_tag]).
+    pub fn add_generated(&mut self, target: TagTarget, tag: impl Into<String>) -> bool {
+        let tag = tag.into();
+        assert!(is_valid_tag(&tag), "Invalid tag '{tag}'");
+        !self.has(target, &tag) && self.generated.entry(target).or_default().insert(tag)
+    }
+
+    /// Detach a tag from an element. Returns false if the element didn't have this tag.
+    pub fn remove(&mut self, target: TagTarget, tag: &str) -> bool {
+        let user = remove_from(&mut self.tags, target, tag);
+        let generated = remove_from(&mut self.generated, target, tag);
+        user || generated
+    }
+
+    /// Returns true if the element has this tag
+    pub fn has(&self, target: TagTarget, tag: &str) -> bool {
+        [&self.tags, &self.generated]
+            .into_iter()
+            .any(|map| map.get(&target).map_or(false, |set| set.contains(tag)))
+    }
+
+    /// Tags of an element, sorted
+    pub fn tags_of(&self, target: TagTarget) -> impl Iterator<Item = &str> + '_ {
+        [&self.tags, &self.generated]
+            .into_iter()
+            .filter_map(|map| map.get(&target))
+            .flatten()
+            .map(String::as_str)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+    }
+
+    /// Every element having this tag
+    pub fn with_tag<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = TagTarget> + 'a {
+        self.iter()
+            .filter(move |(_, set)| set.contains(tag))
+            .map(|(target, _)| target)
+    }
+
+    /// Every tag in use with the number of elements having it
+    pub fn all(&self) -> BTreeMap<&str, usize> {
+        let mut all = BTreeMap::new();
+        for (_, set) in self.iter() {
+            for tag in set {
+                *all.entry(tag).or_insert(0) += 1;
+            }
+        }
+        all
+    }
+
+    /// Every tagged element with its tags
+    pub fn iter(&self) -> impl Iterator<Item = (TagTarget, BTreeSet<&str>)> {
+        self.tags
+            .keys()
+            .chain(self.generated.keys())
+            .copied()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|target| (target, self.tags_of(target).collect()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.generated.is_empty()
+    }
+
+    /// Returns true if there are tags to save, the ones attached by the user
+    pub fn has_user_tags(&self) -> bool {
+        !self.tags.is_empty()
+    }
+
+    /// Load tags from their text representation
+    pub fn load(r: impl BufRead) -> io::Result<Self> {
+        let mut tags = Tags::new();
+        for line in r.lines() {
+            let line = line?;
+            let mut parts = line.split_whitespace();
+            if let Some(target) = parts.next() {
+                let target = target
+                    .parse::<TagTarget>()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                for tag in parts {
+                    tags.add(target, tag);
+                }
+            }
+        }
+        Ok(tags)
+    }
+
+    /// Save the tags attached by the user to their text representation
+    pub fn save(&self, w: &mut impl Write) -> io::Result<()> {
+        for (target, set) in &self.tags {
+            write!(w, "{target}")?;
+            for tag in set {
+                write!(w, " {tag}")?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+fn remove_from(
+    map: &mut BTreeMap<TagTarget, BTreeSet<String>>,
+    target: TagTarget,
+    tag: &str,
+) -> bool {
+    if let Some(set) = map.get_mut(&target) {
+        let removed = set.remove(tag);
+        if set.is_empty() {
+            map.remove(&target);
+        }
+        removed
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::tags::{TagTarget, Tags};
+    use crate::types::{RefFun, RefType};
+
+    #[test]
+    fn roundtrip() {
+        let mut tags = Tags::new();
+        assert!(tags.add(TagTarget::Fun(RefFun(12)), "network"));
+        assert!(tags.add(TagTarget::Fun(RefFun(12)), "crypto"));
+        assert!(!tags.add(TagTarget::Fun(RefFun(12)), "crypto"));
+        assert!(tags.add(TagTarget::Type(RefType(4)), "todo"));
+
+        let mut out = Vec::new();
+        tags.save(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "fn@12 crypto network\ntype@4 todo\n"
+        );
+        assert_eq!(Tags::load(&out[..]).unwrap(), tags);
+    }
+
+    #[test]
+    fn remove() {
+        let mut tags = Tags::new();
+        tags.add(TagTarget::Fun(RefFun(1)), "todo");
+        assert!(tags.remove(TagTarget::Fun(RefFun(1)), "todo"));
+        assert!(!tags.remove(TagTarget::Fun(RefFun(1)), "todo"));
+        assert!(tags.is_empty());
+    }
+
+    #[test]
+    fn generated() {
+        let mut tags = Tags::new();
+        let f = TagTarget::Fun(RefFun(1));
+        assert!(tags.add_generated(f, "entrypoint"));
+        assert!(!tags.add_generated(f, "entrypoint"));
+        assert!(tags.add(f, "todo"));
+        assert!(tags.has(f, "entrypoint"));
+        assert_eq!(tags.tags_of(f).collect::<Vec<_>>(), ["entrypoint", "todo"]);
+        assert_eq!(tags.with_tag("entrypoint").collect::<Vec<_>>(), [f]);
+
+        // Only the tags of the user are saved
+        let mut out = Vec::new();
+        tags.save(&mut out).unwrap();
+        assert_eq!(String::from_utf8_lossy(&out), "fn@1 todo\n");
+
+        assert!(tags.remove(f, "entrypoint"));
+        assert!(tags.has_user_tags());
+        assert!(tags.remove(f, "todo"));
+        assert!(tags.is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+//! Bytecode inside containers : executables, Heaps `.pak` archives and zip archives.
+//!
+//! Games are distributed with the bytecode packed with their resources, [load] finds it in any `Read + Seek` source
+//! and parses the archive entry in place, without extracting it first. The container is recognized from its magic
+//! bytes, a plain bytecode is loaded as is. Zip archives need the `zip` feature.
+//! ```
+//! use std::io::Cursor;
+//!
+//! use hlbc::archive::{self, Container};
+//! use hlbc::builder::sample;
+//!
+//! let mut data = Vec::new();
+//! sample().serialize(&mut data).unwrap();
+//! let mut r = Cursor::new(data);
+//! assert_eq!(archive::detect(&mut r).unwrap(), Container::Bytecode);
+//! let code = archive::load(r, None).unwrap();
+//! assert_eq!(code.functions.len(), sample().functions.len());
+//! ```
+
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::{embedded, Bytecode, ParseError, Result};
+
+/// Formats the bytecode can be found in
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Container {
+    /// A plain bytecode file
+    Bytecode,
+    /// A PE or ELF executable with an [embedded] bytecode
+    Executable,
+    /// A Heaps resource archive
+    Pak,
+    /// A zip archive
+    Zip,
+}
+
+/// Recognize the container from its first bytes, the position is restored afterwards
+pub fn detect(r: &mut (impl Read + Seek)) -> Result<Container> {
+    let start = r.stream_position()?;
+    let mut magic = [0u8; 4];
+    let n = r.read(&mut magic)?;
+    r.seek(SeekFrom::Start(start))?;
+    Ok(match &magic[..n] {
+        [b'P', b'K', 3, 4] => Container::Zip,
+        [b'P', b'A', b'K', _] => Container::Pak,
+        m if embedded::format(m).is_some() => Container::Executable,
+        _ => Container::Bytecode,
+    })
+}
+
+/// Default names of the bytecode in an archive, `hlboot.dat` is the file the HashLink VM boots from
+fn is_bytecode_name(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name == "hlboot.dat" || name.ends_with(".hl")
+}
+
+/// Pick the entry to load, `entry` or the first one with the name of a bytecode
+fn select<'a>(mut paths: impl Iterator<Item = &'a str>, entry: Option<&str>) -> Result<&'a str> {
+    match entry {
+        Some(entry) => paths
+            .find(|p| *p == entry)
+            .ok_or_else(|| ParseError::MissingEntry(entry.to_owned()).into()),
+        None => paths
+            .find(|p| is_bytecode_name(p))
+            .ok_or_else(|| ParseError::NoBytecodeInArchive.into()),
+    }
+}
+
+/// Load the bytecode from any container, `entry` is the path of the bytecode in an archive. Without it, the first
+/// entry named `hlboot.dat` or ending with `.hl` is loaded.
+pub fn load(mut r: impl Read + Seek, entry: Option<&str>) -> Result<Bytecode> {
+    match detect(&mut r)? {
+        Container::Bytecode => Bytecode::load(BufReader::new(r)),
+        Container::Executable => {
+            let mut data = Vec::new();
+            r.read_to_end(&mut data)?;
+            embedded::extract(&data).map(|(_, code)| code)
+        }
+        Container::Pak => {
+            let entries = pak_entries(&mut r)?;
+            let path = select(entries.iter().map(|e| e.path.as_str()), entry)?;
+            let entry = entries.iter().find(|e| e.path == path).unwrap();
+            Bytecode::load(BufReader::new(open_pak_entry(r, entry)?))
+        }
+        #[cfg(feature = "zip")]
+        Container::Zip => {
+            let mut zip = zip::ZipArchive::new(r).map_err(zip_error)?;
+            let path = select(zip.file_names(), entry)?.to_owned();
+            let file = zip.by_name(&path).map_err(zip_error)?;
+            Bytecode::load(BufReader::new(file))
+        }
+        #[cfg(not(feature = "zip"))]
+        Container::Zip => {
+            Err(ParseError::InvalidArchive("zip archives need the zip feature".to_owned()).into())
+        }
+    }
+}
+
+/// A file in a `.pak` archive
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PakEntry {
+    /// Path in the archive, directories separated by `/`
+    pub path: String,
+    /// Position of the content from the start of the archive
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Directories can't be nested deeper in a `.pak` archive, protects against stack overflows on malformed files
+const MAX_PAK_DEPTH: usize = 64;
+
+fn invalid_pak(reason: &str) -> crate::Error {
+    ParseError::InvalidArchive(format!("pak : {reason}")).into()
+}
+
+/// Files of a `.pak` archive, as written by `hxd.fmt.pak.Build`
+pub fn pak_entries(r: &mut (impl Read + Seek)) -> Result<Vec<PakEntry>> {
+    let start = r.stream_position()?;
+    let mut magic = [0u8; 3];
+    r.read_exact(&mut magic)?;
+    if &magic != b"PAK" {
+        return Err(invalid_pak("invalid magic"));
+    }
+    let _version = r.read_u8()?;
+    let header_size = r.read_i32::<LittleEndian>()?;
+    let _data_size = r.read_i32::<LittleEndian>()?;
+    if header_size < 0 {
+        return Err(ParseError::InvalidSize(header_size).into());
+    }
+    let data = start + header_size as u64;
+
+    let mut entries = Vec::new();
+    read_pak_file(r, "", data, 0, &mut entries)?;
+    let mut marker = [0u8; 4];
+    r.read_exact(&mut marker)?;
+    if &marker != b"DATA" {
+        return Err(invalid_pak("corrupted header"));
+    }
+    Ok(entries)
+}
+
+/// Read an entry of the file tree, directories recursively
+fn read_pak_file(
+    r: &mut impl Read,
+    parent: &str,
+    data: u64,
+    depth: usize,
+    entries: &mut Vec<PakEntry>,
+) -> Result<()> {
+    if depth > MAX_PAK_DEPTH {
+        return Err(invalid_pak("directories nested too deep"));
+    }
+    let len = r.read_u8()? as usize;
+    let mut name = vec![0; len];
+    r.read_exact(&mut name)?;
+    let name = String::from_utf8_lossy(&name);
+    // The root directory has no name
+    let path = match (parent, depth) {
+        (_, 0) => String::new(),
+        ("", _) => name.into_owned(),
+        _ => format!("{parent}/{name}"),
+    };
+    let flags = r.read_u8()?;
+    if flags & 1 != 0 {
+        let count = r.read_i32::<LittleEndian>()?;
+        if count < 0 {
+            return Err(ParseError::InvalidSize(count).into());
+        }
+        for _ in 0..count {
+            read_pak_file(r, &path, data, depth + 1, entries)?;
+        }
+    } else {
+        // Positions above 2GB are written as doubles
+        let pos = if flags & 2 != 0 {
+            r.read_f64::<LittleEndian>()? as u64
+        } else {
+            r.read_i32::<LittleEndian>()? as u32 as u64
+        };
+        let size = r.read_i32::<LittleEndian>()? as u32 as u64;
+        let _checksum = r.read_i32::<LittleEndian>()?;
+        entries.push(PakEntry {
+            path,
+            offset: data + pos,
+            size,
+        });
+    }
+    Ok(())
+}
+
+/// Read the content of a file in a `.pak` archive
+pub fn open_pak_entry<R: Read + Seek>(mut r: R, entry: &PakEntry) -> Result<std::io::Take<R>> {
+    r.seek(SeekFrom::Start(entry.offset))?;
+    Ok(r.take(entry.size))
+}
+
+/// Paths of the files in a zip archive
+#[cfg(feature = "zip")]
+pub fn zip_entries(r: impl Read + Seek) -> Result<Vec<String>> {
+    let zip = zip::ZipArchive::new(r).map_err(zip_error)?;
+    Ok(zip.file_names().map(str::to_owned).collect())
+}
+
+#[cfg(feature = "zip")]
+fn zip_error(e: zip::result::ZipError) -> crate::Error {
+    match e {
+        zip::result::ZipError::Io(e) => e.into(),
+        e => ParseError::InvalidArchive(format!("zip : {e}")).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    use crate::archive::{detect, load, pak_entries, Container, PakEntry};
+    use crate::builder::sample;
+    use crate::{Error, ParseError};
+
+    fn pak_file(out: &mut Vec<u8>, name: &str, pos: i32, size: i32) {
+        out.push(name.len() as u8);
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.write_i32::<LittleEndian>(pos).unwrap();
+        out.write_i32::<LittleEndian>(size).unwrap();
+        out.write_i32::<LittleEndian>(0).unwrap();
+    }
+
+    /// `res/readme.txt` and `res/game.hl`
+    fn pak(bytecode: &[u8]) -> Vec<u8> {
+        let mut tree = vec![0, 1];
+        tree.write_i32::<LittleEndian>(1).unwrap();
+        tree.extend_from_slice(b"\x03res\x01");
+        tree.write_i32::<LittleEndian>(2).unwrap();
+        pak_file(&mut tree, "readme.txt", 0, 5);
+        pak_file(&mut tree, "game.hl", 5, bytecode.len() as i32);
+
+        let mut out = b"PAK\x01".to_vec();
+        out.write_i32::<LittleEndian>(tree.len() as i32 + 16)
+            .unwrap();
+        out.write_i32::<LittleEndian>(5 + bytecode.len() as i32)
+            .unwrap();
+        out.extend_from_slice(&tree);
+        out.extend_from_slice(b"DATA");
+        out.extend_from_slice(b"hello");
+        out.extend_from_slice(bytecode);
+        out
+    }
+
+    #[test]
+    fn load_from_pak() {
+        let mut bytecode = Vec::new();
+        sample().serialize(&mut bytecode).unwrap();
+        let data = pak(&bytecode);
+
+        let mut r = Cursor::new(&data);
+        assert_eq!(detect(&mut r).unwrap(), Container::Pak);
+        let entries = pak_entries(&mut r).unwrap();
+        assert_eq!(
+            entries[1],
+            PakEntry {
+                path: "res/game.hl".to_owned(),
+                offset: (data.len() - bytecode.len()) as u64,
+                size: bytecode.len() as u64,
+            }
+        );
+
+        let code = load(Cursor::new(&data), None).unwrap();
+        assert_eq!(code.functions.len(), sample().functions.len());
+        assert!(load(Cursor::new(&data), Some("res/game.hl")).is_ok());
+        assert!(matches!(
+            load(Cursor::new(&data), Some("game.hl")),
+            Err(Error::Parse {
+                kind: ParseError::MissingEntry(_),
+                ..
+            })
+        ));
+        // Not a bytecode
+        assert!(load(Cursor::new(&data), Some("res/readme.txt")).is_err());
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn load_from_zip() {
+        use std::io::Write;
+
+        use zip::write::FileOptions;
+
+        let mut bytecode = Vec::new();
+        sample().serialize(&mut bytecode).unwrap();
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("assets/logo.png", FileOptions::default())
+            .unwrap();
+        zip.write_all(b"not a bytecode").unwrap();
+        zip.start_file("hlboot.dat", FileOptions::default())
+            .unwrap();
+        zip.write_all(&bytecode).unwrap();
+        let data = zip.finish().unwrap().into_inner();
+
+        let mut r = Cursor::new(&data);
+        assert_eq!(detect(&mut r).unwrap(), Container::Zip);
+        let code = load(r, None).unwrap();
+        assert_eq!(code.functions.len(), sample().functions.len());
+    }
+}
@@ -0,0 +1,629 @@
+//! Assembler for the textual form of a function, the inverse of [Function::display].
+//!
+//! The listing printed for a function can be edited in a text editor and assembled back to instructions with
+//! [assemble], or written over the function with [reassemble]. Each line is :
+//! - an instruction, with an optional debug location and position prefix like `Main.hx:12    3: Add reg2 = reg0 + reg1`.
+//!   Instructions are numbered in the order of the listing, jump targets are these positions.
+//! - a register declaration like `reg3 i32@3`, only the type index after `@` is read. Registers can be retyped or
+//!   added at the end.
+//! - the function header, a blank line or a comment starting with `;`, which are ignored.
+//!
+//! Operands are read from the display of each instruction : registers, constant values (added to the pools if
+//! needed), elements referenced as `name@index` (only the index is read), field names resolved in the type of the
+//! object register and jump targets. The additional information of the display (like the type after `new`) is ignored.
+//! Instructions without a dedicated display use their debug representation, like
+//! `GetI8 { dst: Reg(2), bytes: Reg(0), index: Reg(1) }`.
+//! ```
+//! use hlbc::asm::assemble;
+//! use hlbc::builder::sample;
+//!
+//! let mut code = sample();
+//! let f = &code.functions[0];
+//! let (findex, listing, ops) = (f.findex, f.display(&code).to_string(), format!("{:?}", f.ops));
+//! let assembly = assemble(&mut code, findex, &listing).unwrap();
+//! assert_eq!(format!("{:?}", assembly.ops), ops);
+//! ```
+
+use crate::opcodes::{JumpOffset, OperandMut};
+use crate::types::{
+    Function, RefBytes, RefEnumConstruct, RefField, RefFun, RefFunKnown, RefGlobal, RefString,
+    RefType, Reg, ValBool,
+};
+use crate::{Bytecode, Opcode, Type};
+
+/// An error in the listing, lines start at 1
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("Line {line} : {kind}")]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum AsmErrorKind {
+    #[error("fn@{0} is not a function with code")]
+    NotAFunction(usize),
+    #[error("Unknown opcode {0}")]
+    UnknownOpcode(String),
+    #[error("Missing {operand} operand for {op}")]
+    MissingOperand {
+        op: &'static str,
+        operand: &'static str,
+    },
+    #[error("Invalid number {0}")]
+    InvalidNumber(String),
+    #[error("reg{0} is not declared")]
+    UnknownRegister(u32),
+    #[error("Registers can only be added at the end, expected reg{0}")]
+    RegisterOrder(usize),
+    #[error("Type index expected after '@'")]
+    MissingType,
+    #[error("No field {0} in the type of the object")]
+    UnknownField(String),
+    #[error("No construct {0} in the enum")]
+    UnknownConstruct(String),
+    #[error("Jump to {0} is outside of the function")]
+    InvalidTarget(i64),
+    #[error("The signature has {expected} arguments but only {got} registers are declared")]
+    MissingArguments { expected: usize, got: usize },
+    #[error("Argument reg{arg} has type @{got} but the signature expects @{expected}")]
+    ArgumentType {
+        arg: usize,
+        expected: usize,
+        got: usize,
+    },
+}
+
+/// Registers and instructions of an assembled function
+#[derive(Debug, Clone)]
+pub struct Assembly {
+    pub regs: Vec<RefType>,
+    pub ops: Vec<Opcode>,
+}
+
+impl Assembly {
+    /// Check the argument registers against the signature of `f`, and remove the registers declared after the
+    /// arguments that the instructions don't use. Returns the number of registers removed. Errors are on line 0.
+    pub fn fit(&mut self, code: &Bytecode, f: RefFun) -> Result<usize, AsmError> {
+        let err = |kind| AsmError { line: 0, kind };
+        let fun = function(code, f).ok_or_else(|| err(AsmErrorKind::NotAFunction(f.0)))?;
+        let args = fun
+            .t
+            .resolve_as_fun(&code.types)
+            .map_or(&[][..], |t| &t.args[..]);
+        if self.regs.len() < args.len() {
+            return Err(err(AsmErrorKind::MissingArguments {
+                expected: args.len(),
+                got: self.regs.len(),
+            }));
+        }
+        if let Some(arg) = (0..args.len()).find(|&i| self.regs[i] != args[i]) {
+            return Err(err(AsmErrorKind::ArgumentType {
+                arg,
+                expected: args[arg].0,
+                got: self.regs[arg].0,
+            }));
+        }
+        let used = self
+            .ops
+            .iter()
+            .flat_map(|o| o.regs())
+            .map(|r| r.0 as usize + 1)
+            .max()
+            .unwrap_or(0)
+            .max(args.len());
+        let removed = self.regs.len().saturating_sub(used);
+        self.regs.truncate(used);
+        Ok(removed)
+    }
+
+    /// Replace the registers and instructions of `f`
+    pub fn apply(self, code: &mut Bytecode, f: RefFun) {
+        let len = function(code, f).map_or(0, |f| f.ops.len());
+        code.edit().replace_ops(f, 0..len, self.ops);
+        if let RefFunKnown::Fun(x) = code.findexes[f.0] {
+            code.functions[x].regs = self.regs;
+        }
+    }
+}
+
+/// Assemble a listing for the function `f`. The registers of `f` are used unless the listing declares them.
+/// Missing constants are added to the pools.
+pub fn assemble(code: &mut Bytecode, f: RefFun, text: &str) -> Result<Assembly, AsmError> {
+    let mut regs = function(code, f)
+        .ok_or(AsmError {
+            line: 0,
+            kind: AsmErrorKind::NotAFunction(f.0),
+        })?
+        .regs
+        .clone();
+    let mut ops = Vec::new();
+    // Line and target of each jump, checked once every instruction is known
+    let mut targets = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let at = |kind| AsmError { line: i + 1, kind };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with("fn") {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let Some(reg) = words[0]
+            .strip_prefix("reg")
+            .and_then(|r| r.parse::<usize>().ok())
+        {
+            let ty = match tokenize(line).last() {
+                Some(Token::Ref(t)) => RefType(*t),
+                _ => return Err(at(AsmErrorKind::MissingType)),
+            };
+            if reg > regs.len() {
+                return Err(at(AsmErrorKind::RegisterOrder(regs.len())));
+            }
+            if reg == regs.len() {
+                regs.push(ty);
+            } else {
+                regs[reg] = ty;
+            }
+            continue;
+        }
+
+        // Skip the debug location and the position
+        let skip = words
+            .iter()
+            .take(2)
+            .position(|w| {
+                w.strip_suffix(':')
+                    .map_or(false, |n| n.parse::<usize>().is_ok())
+            })
+            .map_or(0, |p| p + 1);
+        let mut rest = line;
+        for _ in 0..skip {
+            rest = rest
+                .split_once(char::is_whitespace)
+                .map_or("", |(_, r)| r.trim_start());
+        }
+        let (name, operands) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let mut op = Opcode::from_name(name)
+            .ok_or_else(|| at(AsmErrorKind::UnknownOpcode(name.to_owned())))?;
+        let pos = ops.len();
+        let jumps = fill(code, &regs, &mut op, pos, operands).map_err(at)?;
+        targets.extend(jumps.into_iter().map(|t| (i + 1, t)));
+        ops.push(op);
+    }
+
+    for (line, t) in targets {
+        if t < 0 || t as usize >= ops.len() {
+            return Err(AsmError {
+                line,
+                kind: AsmErrorKind::InvalidTarget(t),
+            });
+        }
+    }
+    Ok(Assembly { regs, ops })
+}
+
+/// Assemble a listing and replace the registers and instructions of `f`, see [assemble]
+pub fn reassemble(code: &mut Bytecode, f: RefFun, text: &str) -> Result<(), AsmError> {
+    assemble(code, f, text)?.apply(code, f);
+    Ok(())
+}
+
+fn function(code: &Bytecode, f: RefFun) -> Option<&Function> {
+    match code.findexes.get(f.0)? {
+        RefFunKnown::Fun(x) => code.functions.get(*x),
+        RefFunKnown::Native(_) => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Reg(u32),
+    Num(String),
+    Str(String),
+    /// Index after a `@`, or in a debug representation like `RefType(3)`
+    Ref(usize),
+    /// Word after a `.`
+    Member(String),
+    Word(String),
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+fn tokenize(s: &str) -> Vec<Token> {
+    let chars: Vec<char> = s.chars().collect();
+    let take = |from: usize, f: fn(char) -> bool| -> String {
+        chars[from.min(chars.len())..]
+            .iter()
+            .take_while(|&&c| f(c))
+            .collect()
+    };
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied().unwrap_or(' ');
+        if c == '"' {
+            // Strings run to the last quote
+            let end = chars
+                .iter()
+                .rposition(|&c| c == '"')
+                .filter(|&e| e > i)
+                .unwrap_or(chars.len());
+            tokens.push(Token::Str(chars[i + 1..end].iter().collect()));
+            i = end + 1;
+        } else if c == '@' {
+            let n = take(i + 1, |c| c.is_ascii_digit());
+            i += 1 + n.len();
+            if let Ok(n) = n.parse() {
+                tokens.push(Token::Ref(n));
+            }
+        } else if c == '.' && is_word(next) {
+            let w = take(i + 1, is_word);
+            i += 1 + w.chars().count();
+            tokens.push(Token::Member(w));
+        } else if c.is_ascii_digit() || (c == '-' && (next.is_ascii_digit() || next == 'i')) {
+            let mut j = i + 1;
+            while j < chars.len()
+                && (chars[j].is_ascii_alphanumeric()
+                    || chars[j] == '.'
+                    || (matches!(chars[j], '-' | '+') && matches!(chars[j - 1], 'e' | 'E')))
+            {
+                j += 1;
+            }
+            tokens.push(Token::Num(chars[i..j].iter().collect()));
+            i = j;
+        } else if is_word(c) {
+            let w = take(i, is_word);
+            i += w.chars().count();
+            // Debug representation like Reg(3) or RefField(1)
+            if chars.get(i) == Some(&'(') && (w == "Reg" || w.starts_with("Ref")) {
+                let digits = take(i + 1, |c| c.is_ascii_digit());
+                let end = i + 1 + digits.len();
+                if let (Ok(n), Some(')')) = (digits.parse::<usize>(), chars.get(end)) {
+                    i = end + 1;
+                    tokens.push(if w == "Reg" {
+                        Token::Reg(n as u32)
+                    } else {
+                        Token::Ref(n)
+                    });
+                    continue;
+                }
+            }
+            match w.strip_prefix("reg").and_then(|n| n.parse().ok()) {
+                Some(r) if w[3..].chars().all(|c| c.is_ascii_digit()) => tokens.push(Token::Reg(r)),
+                _ => tokens.push(Token::Word(w)),
+            }
+        } else {
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Undo the escapes of the debug representation of a string
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('u') => {
+                let code: String = chars.by_ref().skip(1).take_while(|&c| c != '}').collect();
+                if let Some(c) = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    out.push(c);
+                }
+            }
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Set the operands of `op` from their textual form, returns the jump targets
+fn fill(
+    code: &mut Bytecode,
+    regs: &[RefType],
+    op: &mut Opcode,
+    pos: usize,
+    operands: &str,
+) -> Result<Vec<i64>, AsmErrorKind> {
+    let name = op.name();
+    let tokens = tokenize(operands);
+    macro_rules! queue {
+        ($variant:ident) => {
+            tokens
+                .iter()
+                .filter_map(|t| match t {
+                    Token::$variant(v) => Some(v.clone()),
+                    _ => None,
+                })
+                .collect::<std::collections::VecDeque<_>>()
+        };
+    }
+    let mut reg_tokens = queue!(Reg);
+    // The object of CallThis is displayed but isn't an operand
+    if matches!(op, Opcode::CallThis { .. }) && operands.contains("= reg0.") {
+        reg_tokens.remove(1);
+    }
+    let mut nums = queue!(Num);
+    let mut strs = queue!(Str);
+    let mut refs = queue!(Ref);
+    let members = queue!(Member);
+    let words = queue!(Word);
+    let missing = |operand| AsmErrorKind::MissingOperand { op: name, operand };
+    let reg = |r: u32| {
+        if (r as usize) < regs.len() {
+            Ok(Reg(r))
+        } else {
+            Err(AsmErrorKind::UnknownRegister(r))
+        }
+    };
+    let int = |n: String| n.parse::<i64>().map_err(|_| AsmErrorKind::InvalidNumber(n));
+    let mut jumps = Vec::new();
+    let mut offset = |target: i64| {
+        jumps.push(target);
+        (target - pos as i64 - 1) as JumpOffset
+    };
+
+    let mut operands = op.operands_mut();
+    // Lists take every remaining value except the ones of the next single operands
+    let count = |operands: &[OperandMut], f: fn(&OperandMut) -> bool| {
+        operands.iter().filter(|o| f(o)).count()
+    };
+    for i in 0..operands.len() {
+        let (done, next) = operands.split_at_mut(i + 1);
+        match &mut done[i] {
+            OperandMut::Reg(r) => {
+                **r = reg(reg_tokens.pop_front().ok_or_else(|| missing("register"))?)?
+            }
+            OperandMut::Regs(v) => {
+                let n = reg_tokens
+                    .len()
+                    .saturating_sub(count(next, |o| matches!(o, OperandMut::Reg(_))));
+                **v = reg_tokens.drain(..n).map(reg).collect::<Result<_, _>>()?;
+            }
+            OperandMut::Offset(o) => {
+                **o = offset(int(nums
+                    .pop_front()
+                    .ok_or_else(|| missing("jump target"))?)?)
+            }
+            OperandMut::Offsets(v) => {
+                let n = nums
+                    .len()
+                    .saturating_sub(count(next, |o| matches!(o, OperandMut::Offset(_))));
+                let mut offsets = Vec::with_capacity(n);
+                for t in nums.drain(..n) {
+                    offsets.push(offset(int(t)?));
+                }
+                **v = offsets;
+            }
+            OperandMut::Int(r) => {
+                let n = nums.pop_front().ok_or_else(|| missing("integer"))?;
+                let value = n
+                    .parse::<i32>()
+                    .map_err(|_| AsmErrorKind::InvalidNumber(n))?;
+                **r = code.edit().int(value);
+            }
+            OperandMut::Float(r) => {
+                let n = nums
+                    .pop_front()
+                    .or_else(|| words.iter().find(|w| *w == "NaN" || *w == "inf").cloned())
+                    .ok_or_else(|| missing("float"))?;
+                let value = n
+                    .parse::<f64>()
+                    .map_err(|_| AsmErrorKind::InvalidNumber(n))?;
+                **r = code.edit().float(value);
+            }
+            OperandMut::String(r) => {
+                **r = match strs.pop_front() {
+                    Some(s) if name == "String" => code.edit().string(&unescape(&s)),
+                    Some(s) => code.edit().string(&s),
+                    None => RefString(refs.pop_front().ok_or_else(|| missing("string"))?),
+                }
+            }
+            OperandMut::Bool(b) => {
+                **b = ValBool(
+                    words
+                        .iter()
+                        .find_map(|w| w.parse::<bool>().ok())
+                        .ok_or_else(|| missing("boolean"))?,
+                )
+            }
+            OperandMut::Bytes(r) => {
+                **r = RefBytes(refs.pop_front().ok_or_else(|| missing("bytes"))?)
+            }
+            OperandMut::Type(r) => **r = RefType(refs.pop_front().ok_or_else(|| missing("type"))?),
+            OperandMut::Fun(r) => {
+                **r = RefFun(refs.pop_front().ok_or_else(|| missing("function"))?)
+            }
+            OperandMut::Global(r) => {
+                **r = RefGlobal(refs.pop_front().ok_or_else(|| missing("global"))?)
+            }
+            // Resolved once the registers are known
+            OperandMut::Field(_) | OperandMut::Construct(_) => {}
+        }
+    }
+    drop(operands);
+
+    let regtype = |r: Reg| regs[r.0 as usize].resolve(&code.types);
+    let obj = match &*op {
+        Opcode::Field { obj, .. } | Opcode::SetField { obj, .. } => Some(*obj),
+        Opcode::GetThis { .. } | Opcode::SetThis { .. } | Opcode::CallThis { .. } => Some(Reg(0)),
+        Opcode::CallMethod { args, .. } => args.first().copied(),
+        _ => None,
+    };
+    let enum_reg = match &*op {
+        Opcode::MakeEnum { dst, .. } | Opcode::EnumAlloc { dst, .. } => Some(*dst),
+        Opcode::EnumField { value, .. } => Some(*value),
+        _ => None,
+    };
+    for o in op.operands_mut() {
+        match o {
+            OperandMut::Field(f) => {
+                let name = members.front().cloned();
+                *f = match (name, refs.front()) {
+                    (Some(name), _) => RefField(resolve_field(code, obj.map(regtype), &name)?),
+                    (None, Some(&r)) => RefField(r),
+                    (None, None) => return Err(missing("field")),
+                };
+            }
+            OperandMut::Construct(c) => {
+                let constructs = match enum_reg.map(regtype) {
+                    Some(Type::Enum { constructs, .. }) => constructs.as_slice(),
+                    _ => &[],
+                };
+                *c = words
+                    .iter()
+                    .find_map(|w| {
+                        w.strip_prefix('_')
+                            .and_then(|i| i.parse().ok())
+                            .or_else(|| {
+                                constructs.iter().position(|c| {
+                                    c.name.0 != 0 && c.name.resolve(&code.strings) == w
+                                })
+                            })
+                    })
+                    .map(RefEnumConstruct)
+                    .or_else(|| refs.front().map(|&r| RefEnumConstruct(r)))
+                    .ok_or_else(|| {
+                        AsmErrorKind::UnknownConstruct(words.back().cloned().unwrap_or_default())
+                    })?;
+            }
+            _ => {}
+        }
+    }
+    Ok(jumps)
+}
+
+/// Index of a field from its name, `fieldN` or its index
+fn resolve_field(code: &Bytecode, ty: Option<&Type>, name: &str) -> Result<usize, AsmErrorKind> {
+    if let Some(i) = name
+        .parse()
+        .ok()
+        .or_else(|| name.strip_prefix("field").and_then(|i| i.parse().ok()))
+    {
+        return Ok(i);
+    }
+    let fields = match ty {
+        Some(Type::Obj(obj) | Type::Struct(obj)) => obj.fields.as_slice(),
+        Some(Type::Virtual { fields }) => fields.as_slice(),
+        _ => &[],
+    };
+    fields
+        .iter()
+        .position(|f| f.name.resolve(&code.strings) == name)
+        .ok_or_else(|| AsmErrorKind::UnknownField(name.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{assemble, reassemble, AsmErrorKind};
+    use crate::builder::{sample, BytecodeBuilder};
+    use crate::types::{RefType, Reg};
+    use crate::{Opcode, Type};
+
+    #[test]
+    fn round_trip() {
+        let mut code = sample();
+        for i in 0..code.functions.len() {
+            let f = &code.functions[i];
+            let (findex, listing) = (f.findex, f.display(&code).to_string());
+            let (regs, ops) = (f.regs.clone(), format!("{:?}", f.ops));
+            let assembly = assemble(&mut code, findex, &listing).unwrap();
+            assert_eq!(assembly.regs, regs);
+            assert_eq!(format!("{:?}", assembly.ops), ops, "{listing}");
+        }
+    }
+
+    #[test]
+    fn edit_listing() {
+        let mut code = sample();
+        let f = &code.functions[0];
+        let (findex, nregs) = (f.findex, f.regs.len());
+        let int = code
+            .types
+            .iter()
+            .position(|t| *t == crate::Type::I32)
+            .unwrap();
+        let listing = format!(
+            "; hand written\nreg{nregs} i32@{int}\nInt reg{nregs} = 123456\nString reg{nregs} = \"a\\nb\"\n\
+             JSLt reg{nregs} < reg{nregs} jump to 0\nSwitch reg{nregs} jump to [0, 1] else 3\n"
+        );
+        reassemble(&mut code, findex, &listing).unwrap();
+        let f = &code.functions[0];
+        assert_eq!(f.regs.len(), nregs + 1);
+        assert_eq!(f.regs[nregs], RefType(int));
+        assert!(code.ints.contains(&123456));
+        assert!(code.strings.iter().any(|s| s == "a\nb"));
+        assert!(matches!(f.ops[2], Opcode::JSLt { offset: -3, .. }));
+        assert!(
+            matches!(&f.ops[3], Opcode::Switch { reg: Reg(r), offsets, end: -1 } if *r as usize == nregs && offsets == &[-4, -3])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut code = sample();
+        let findex = code.functions[0].findex;
+        let kind = |code: &mut _, text| assemble(code, findex, text).unwrap_err().kind;
+        assert_eq!(
+            kind(&mut code, "Nope reg0"),
+            AsmErrorKind::UnknownOpcode("Nope".to_owned())
+        );
+        assert_eq!(
+            kind(&mut code, "Mov reg0 = reg9999"),
+            AsmErrorKind::UnknownRegister(9999)
+        );
+        assert_eq!(
+            kind(&mut code, "JAlways jump to 5"),
+            AsmErrorKind::InvalidTarget(5)
+        );
+        assert!(matches!(
+            kind(&mut code, "Mov reg0 ="),
+            AsmErrorKind::MissingOperand { .. }
+        ));
+        let err = assemble(&mut code, findex, "Nop\n\nRet").unwrap_err();
+        assert_eq!(err.line, 3);
+    }
+
+    #[test]
+    fn fit() {
+        let mut b = BytecodeBuilder::new();
+        let [i32_, f64_] = [Type::I32, Type::F64].map(|t| b.ty(t));
+        let ty = b.fun_type(&[i32_], i32_);
+        let findex = b.findex();
+        b.function(findex, ty, vec![i32_], vec![Opcode::Ret { ret: Reg(0) }]);
+        let mut code = b.build().unwrap();
+
+        // The last register is not used anymore
+        let listing = format!(
+            "reg1 i32@{}\nreg2 i32@{}\nMov reg1 = reg0\nRet reg1",
+            i32_.0, i32_.0
+        );
+        let mut assembly = assemble(&mut code, findex, &listing).unwrap();
+        assert_eq!(assembly.fit(&code, findex), Ok(1));
+        assembly.apply(&mut code, findex);
+        assert_eq!(findex.resolve_as_fn(&code).unwrap().regs, [i32_, i32_]);
+
+        let listing = format!("reg0 f64@{}\nRet reg0", f64_.0);
+        let mut assembly = assemble(&mut code, findex, &listing).unwrap();
+        assert_eq!(
+            assembly.fit(&code, findex).unwrap_err().kind,
+            AsmErrorKind::ArgumentType {
+                arg: 0,
+                expected: i32_.0,
+                got: f64_.0
+            }
+        );
+    }
+}
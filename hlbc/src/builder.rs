@@ -0,0 +1,1111 @@
+//! Build bytecode from scratch.
+//!
+//! [BytecodeBuilder] creates the constant pools while you write the code, the resulting [Bytecode] is a valid module
+//! that can be saved and loaded again. [sample] builds a small module using every opcode and every kind of type,
+//! useful for tests and examples without relying on compiled Haxe programs.
+//!
+//! [FunctionBuilder] writes the code of a single function : registers are allocated by type and jumps target labels
+//! instead of offsets. The function is added to an existing bytecode with [FunctionBuilder::register], or its
+//! registers and instructions are given to [BytecodeBuilder::function].
+//! ```
+//! use hlbc::builder::BytecodeBuilder;
+//! use hlbc::prelude::*;
+//!
+//! let mut b = BytecodeBuilder::new();
+//! let i32_ = b.ty(Type::I32);
+//! let main_t = b.fun_type(&[], i32_);
+//! let main = b.findex();
+//! let answer = b.int(42);
+//! b.function(main, main_t, vec![i32_], vec![
+//!     Opcode::Int { dst: Reg(0), ptr: answer },
+//!     Opcode::Ret { ret: Reg(0) },
+//! ]);
+//! b.entrypoint(main);
+//! let code = b.build().unwrap();
+//! assert_eq!(code.functions.len(), 1);
+//! ```
+
+use std::collections::HashMap;
+
+use crate::opcodes::JumpOffset;
+use crate::types::{
+    ConstantDef, EnumConstruct, Function, Native, ObjField, ObjProto, RefBytes, RefEnumConstruct,
+    RefField, RefFloat, RefFun, RefGlobal, RefInt, RefString, RefType, Reg, Str, TypeFun, TypeObj,
+    ValBool,
+};
+use crate::{Bytecode, Opcode, Result, Type};
+
+/// Incrementally build a [Bytecode]
+#[derive(Debug)]
+pub struct BytecodeBuilder {
+    version: u8,
+    entrypoint: RefFun,
+    ints: Vec<i32>,
+    floats: Vec<f64>,
+    strings: Vec<Str>,
+    bytes: (Vec<u8>, Vec<usize>),
+    debug_file: Option<String>,
+    types: Vec<Type>,
+    globals: Vec<RefType>,
+    natives: Vec<Native>,
+    functions: Vec<Function>,
+    constants: Vec<ConstantDef>,
+    next_findex: usize,
+}
+
+impl Default for BytecodeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BytecodeBuilder {
+    /// Start a bytecode with the latest version, the first type is `Void`
+    pub fn new() -> Self {
+        Self {
+            version: 5,
+            entrypoint: RefFun(0),
+            ints: Vec::new(),
+            floats: Vec::new(),
+            strings: Vec::new(),
+            bytes: (Vec::new(), Vec::new()),
+            debug_file: None,
+            types: vec![Type::Void],
+            globals: Vec::new(),
+            natives: Vec::new(),
+            functions: Vec::new(),
+            constants: Vec::new(),
+            next_findex: 0,
+        }
+    }
+
+    /// Generate debug info, each instruction is on its own line in `file`
+    pub fn debug_info(&mut self, file: &str) -> &mut Self {
+        self.debug_file = Some(file.to_owned());
+        self
+    }
+
+    pub fn int(&mut self, value: i32) -> RefInt {
+        RefInt(intern(&mut self.ints, value))
+    }
+
+    pub fn float(&mut self, value: f64) -> RefFloat {
+        // Compare the bits so NaN can be interned too
+        match self
+            .floats
+            .iter()
+            .position(|f| f.to_bits() == value.to_bits())
+        {
+            Some(i) => RefFloat(i),
+            None => {
+                self.floats.push(value);
+                RefFloat(self.floats.len() - 1)
+            }
+        }
+    }
+
+    pub fn string(&mut self, value: &str) -> RefString {
+        match self.strings.iter().position(|s| s == value) {
+            Some(i) => RefString(i),
+            None => {
+                self.strings.push(value.into());
+                RefString(self.strings.len() - 1)
+            }
+        }
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> RefBytes {
+        let (data, pos) = &mut self.bytes;
+        pos.push(data.len());
+        data.extend_from_slice(value);
+        RefBytes(pos.len() - 1)
+    }
+
+    /// Add a type, or get the reference of an identical type
+    pub fn ty(&mut self, ty: Type) -> RefType {
+        RefType(intern(&mut self.types, ty))
+    }
+
+    pub fn fun_type(&mut self, args: &[RefType], ret: RefType) -> RefType {
+        self.ty(Type::Fun(TypeFun {
+            args: args.to_vec(),
+            ret,
+        }))
+    }
+
+    pub fn method_type(&mut self, args: &[RefType], ret: RefType) -> RefType {
+        self.ty(Type::Method(TypeFun {
+            args: args.to_vec(),
+            ret,
+        }))
+    }
+
+    /// Add a class, `fields` and `protos` are pairs of names and types or functions
+    pub fn class(
+        &mut self,
+        name: &str,
+        super_: Option<RefType>,
+        fields: &[(&str, RefType)],
+        protos: &[(&str, RefFun)],
+    ) -> RefType {
+        let obj = self.type_obj(name, super_, fields, protos);
+        self.ty(Type::Obj(obj))
+    }
+
+    /// Add a struct, same as a class but stored by value
+    pub fn structure(&mut self, name: &str, fields: &[(&str, RefType)]) -> RefType {
+        let obj = self.type_obj(name, None, fields, &[]);
+        self.ty(Type::Struct(obj))
+    }
+
+    pub fn virtual_type(&mut self, fields: &[(&str, RefType)]) -> RefType {
+        let fields = self.fields(fields);
+        self.ty(Type::Virtual { fields })
+    }
+
+    /// Add an enum, each construct is a name with the types of its parameters
+    pub fn enumeration(&mut self, name: &str, constructs: &[(&str, &[RefType])]) -> RefType {
+        let name = self.string(name);
+        let constructs = constructs
+            .iter()
+            .map(|&(name, params)| EnumConstruct {
+                name: self.string(name),
+                params: params.to_vec(),
+            })
+            .collect();
+        self.ty(Type::Enum {
+            name,
+            global: RefGlobal(0),
+            constructs,
+        })
+    }
+
+    fn fields(&mut self, fields: &[(&str, RefType)]) -> Vec<ObjField> {
+        fields
+            .iter()
+            .map(|&(name, t)| ObjField {
+                name: self.string(name),
+                t,
+            })
+            .collect()
+    }
+
+    fn type_obj(
+        &mut self,
+        name: &str,
+        super_: Option<RefType>,
+        fields: &[(&str, RefType)],
+        protos: &[(&str, RefFun)],
+    ) -> TypeObj {
+        TypeObj {
+            name: self.string(name),
+            super_,
+            global: RefGlobal(0),
+            own_fields: self.fields(fields),
+            protos: protos
+                .iter()
+                .map(|&(name, findex)| ObjProto {
+                    name: self.string(name),
+                    findex,
+                    pindex: -1,
+                })
+                .collect(),
+            bindings: HashMap::new(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn global(&mut self, ty: RefType) -> RefGlobal {
+        self.globals.push(ty);
+        RefGlobal(self.globals.len() - 1)
+    }
+
+    /// Initialize a global object, `fields` are indexes in the constant pool matching the type of each field
+    pub fn constant(&mut self, global: RefGlobal, fields: Vec<usize>) -> &mut Self {
+        self.constants.push(ConstantDef { global, fields });
+        self
+    }
+
+    /// Declare a native function
+    pub fn native(&mut self, lib: &str, name: &str, ty: RefType) -> RefFun {
+        let findex = self.findex();
+        let native = Native {
+            name: self.string(name),
+            lib: self.string(lib),
+            t: ty,
+            findex,
+        };
+        self.natives.push(native);
+        findex
+    }
+
+    /// Allocate a function index, so a function can be referenced before being defined
+    pub fn findex(&mut self) -> RefFun {
+        self.next_findex += 1;
+        RefFun(self.next_findex - 1)
+    }
+
+    /// Define the function allocated with [Self::findex]
+    pub fn function(
+        &mut self,
+        findex: RefFun,
+        ty: RefType,
+        regs: Vec<RefType>,
+        ops: Vec<Opcode>,
+    ) -> &mut Self {
+        let debug_info = self
+            .debug_file
+            .as_ref()
+            .map(|_| (1..=ops.len()).map(|line| (0, line)).collect());
+        self.functions.push(Function {
+            name: None,
+            t: ty,
+            findex,
+            regs,
+            ops,
+            debug_info,
+            assigns: self.debug_file.as_ref().map(|_| Vec::new()),
+            parent: None,
+        });
+        self
+    }
+
+    pub fn entrypoint(&mut self, findex: RefFun) -> &mut Self {
+        self.entrypoint = findex;
+        self
+    }
+
+    /// Assemble the bytecode. It goes through the serializer and the parser, so the result is exactly what would
+    /// be loaded from a file.
+    pub fn build(self) -> Result<Bytecode> {
+        let code = Bytecode {
+            version: self.version,
+            entrypoint: self.entrypoint,
+            ints: self.ints,
+            floats: self.floats,
+            strings: self.strings,
+            bytes: Some(self.bytes),
+            debug_files: self.debug_file.map(|f| vec![f]),
+            types: self.types,
+            globals: self.globals,
+            natives: self.natives,
+            functions: self.functions,
+            constants: Some(self.constants),
+            metadata: None,
+            findexes: Vec::new(),
+            fnames: HashMap::new(),
+            globals_initializers: HashMap::new(),
+            virtual_names: HashMap::new(),
+            global_offsets: Vec::new(),
+            warnings: Vec::new(),
+            source: None,
+        };
+        let mut data = Vec::new();
+        code.serialize(&mut data)?;
+        Bytecode::load(&mut data.as_slice())
+    }
+}
+
+/// A position in the code of a [FunctionBuilder], jumps can target it before it is placed
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Label(usize);
+
+/// A label used by a jump has never been placed with [FunctionBuilder::place]
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("A label is used by a jump but never placed")]
+pub struct UnplacedLabel(pub Label);
+
+/// Write the code of a function, see [builder](crate::builder)
+/// ```
+/// use hlbc::builder::{sample, FunctionBuilder};
+/// use hlbc::prelude::*;
+///
+/// let mut code = sample();
+/// let mut editor = code.edit();
+/// let i32_ = editor.ty(Type::I32);
+/// let abs_t = editor.ty(Type::Fun(TypeFun { args: vec![i32_], ret: i32_ }));
+/// let zero = editor.int(0);
+///
+/// // fn abs(x: i32) -> i32
+/// let mut f = FunctionBuilder::new(abs_t, &[i32_]);
+/// let (x, tmp) = (f.arg(0), f.reg(i32_));
+/// let positive = f.label();
+/// f.emit(Opcode::Int { dst: tmp, ptr: zero });
+/// f.jump(Opcode::JSGte { a: x, b: tmp, offset: 0 }, positive);
+/// f.emit(Opcode::Neg { dst: x, src: x });
+/// f.place(positive);
+/// f.emit(Opcode::Ret { ret: x });
+/// let abs = f.register(&mut code).unwrap();
+/// assert_eq!(abs.resolve_as_fn(&code).unwrap().ops.len(), 4);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FunctionBuilder {
+    ty: RefType,
+    regs: Vec<RefType>,
+    ops: Vec<Opcode>,
+    /// Position of each label once placed
+    labels: Vec<Option<usize>>,
+    /// Jumps to resolve : position of the jump, targets (the last one is the end of a switch)
+    jumps: Vec<(usize, Vec<Label>)>,
+}
+
+impl FunctionBuilder {
+    /// Start a function of type `ty`, the first registers are its arguments
+    pub fn new(ty: RefType, args: &[RefType]) -> Self {
+        Self {
+            ty,
+            regs: args.to_vec(),
+            ops: Vec::new(),
+            labels: Vec::new(),
+            jumps: Vec::new(),
+        }
+    }
+
+    /// Register of the argument `i`
+    pub fn arg(&self, i: usize) -> Reg {
+        Reg(i as u32)
+    }
+
+    /// Allocate a new register
+    pub fn reg(&mut self, ty: RefType) -> Reg {
+        self.regs.push(ty);
+        Reg(self.regs.len() as u32 - 1)
+    }
+
+    /// Position of the next instruction
+    pub fn pos(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn emit(&mut self, op: Opcode) -> &mut Self {
+        self.ops.push(op);
+        self
+    }
+
+    /// Create a label, place it with [Self::place]
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Place a label before the next instruction
+    pub fn place(&mut self, label: Label) -> &mut Self {
+        self.labels[label.0] = Some(self.ops.len());
+        self
+    }
+
+    /// Emit a jump (or a trap) to `target`, the offset in `op` is ignored
+    ///
+    /// # Panics
+    ///
+    /// If `op` doesn't jump.
+    pub fn jump(&mut self, op: Opcode, target: Label) -> &mut Self {
+        let mut op = op;
+        assert!(op.jump_offset_mut().is_some(), "{} doesn't jump", op.name());
+        self.jumps.push((self.ops.len(), vec![target]));
+        self.emit(op)
+    }
+
+    /// Emit a switch on `reg`, jumping to `targets[reg]` or to `end` if out of range
+    pub fn switch(&mut self, reg: Reg, targets: &[Label], end: Label) -> &mut Self {
+        let mut labels = targets.to_vec();
+        labels.push(end);
+        self.jumps.push((self.ops.len(), labels));
+        self.emit(Opcode::Switch {
+            reg,
+            offsets: vec![0; targets.len()],
+            end: 0,
+        })
+    }
+
+    /// Resolve the jumps, returns the type, the registers and the instructions of the function
+    pub fn finish(
+        mut self,
+    ) -> std::result::Result<(RefType, Vec<RefType>, Vec<Opcode>), UnplacedLabel> {
+        for (pos, targets) in self.jumps {
+            let mut offsets = targets
+                .into_iter()
+                .map(|label| {
+                    self.labels[label.0]
+                        .map(|target| target as JumpOffset - pos as JumpOffset - 1)
+                        .ok_or(UnplacedLabel(label))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            match &mut self.ops[pos] {
+                Opcode::Switch {
+                    offsets: switch,
+                    end,
+                    ..
+                } => {
+                    *end = offsets.pop().unwrap();
+                    *switch = offsets;
+                }
+                op => *op.jump_offset_mut().unwrap() = offsets[0],
+            }
+        }
+        Ok((self.ty, self.regs, self.ops))
+    }
+
+    /// Add the function to a bytecode, it can be called with the returned findex
+    pub fn register(self, code: &mut Bytecode) -> std::result::Result<RefFun, UnplacedLabel> {
+        let (ty, regs, ops) = self.finish()?;
+        Ok(code.edit().function(ty, regs, ops))
+    }
+}
+
+pub(crate) fn intern<T: PartialEq>(pool: &mut Vec<T>, value: T) -> usize {
+    match pool.iter().position(|v| *v == value) {
+        Some(i) => i,
+        None => {
+            pool.push(value);
+            pool.len() - 1
+        }
+    }
+}
+
+/// A small module using every opcode and every kind of type. It's not meant to be run.
+pub fn sample() -> Bytecode {
+    let mut b = BytecodeBuilder::new();
+    b.debug_info("Sample.hx");
+
+    let void = RefType(0);
+    let ui8 = b.ty(Type::UI8);
+    let ui16 = b.ty(Type::UI16);
+    let i32_ = b.ty(Type::I32);
+    let i64_ = b.ty(Type::I64);
+    let f32_ = b.ty(Type::F32);
+    let f64_ = b.ty(Type::F64);
+    let bool_ = b.ty(Type::Bool);
+    let bytes = b.ty(Type::Bytes);
+    let dyn_ = b.ty(Type::Dyn);
+    let array = b.ty(Type::Array);
+    let type_ = b.ty(Type::Type);
+    let dynobj = b.ty(Type::DynObj);
+    let ref_i32 = b.ty(Type::Ref(i32_));
+    let null_i32 = b.ty(Type::Null(i32_));
+    let tls_name = b.string("hl_tls");
+    let tls = b.ty(Type::Abstract { name: tls_name });
+
+    let arith = b.findex();
+    let memory = b.findex();
+    let objects = b.findex();
+    let length = b.findex();
+    let reset = b.findex();
+    let sum4 = b.findex();
+    let main = b.findex();
+
+    let point = b.class(
+        "Point",
+        None,
+        &[("x", i32_), ("y", i32_)],
+        &[("length", length), ("reset", reset)],
+    );
+    let vec2 = b.structure("Vec2", &[("x", f64_), ("y", f64_)]);
+    let packed = b.ty(Type::Packed(vec2));
+    let pos = b.virtual_type(&[("x", i32_), ("y", i32_)]);
+    let color = b.enumeration("Color", &[("Red", &[]), ("Rgb", &[i32_, i32_, i32_])]);
+
+    let fun_ii_i = b.fun_type(&[i32_, i32_], i32_);
+    let fun_b_v = b.fun_type(&[bytes, i32_], void);
+    let fun_p_d = b.fun_type(&[point], dyn_);
+    let fun_v_i = b.fun_type(&[], i32_);
+    let fun_4_i = b.fun_type(&[i32_, i32_, i32_, i32_], i32_);
+    let fun_v_v = b.fun_type(&[], void);
+    let method_p_i = b.method_type(&[point], i32_);
+    let method_p_v = b.method_type(&[point], void);
+
+    let g_origin = b.global(point);
+    let g_count = b.global(i32_);
+    let (zero, one) = (b.int(0), b.int(1));
+    b.constant(g_origin, vec![zero.0, one.0]);
+
+    let time_t = b.fun_type(&[], f64_);
+    let sys_time = b.native("std", "sys_time", time_t);
+    let print_t = b.fun_type(&[bytes], void);
+    let sys_print = b.native("std", "sys_print", print_t);
+    let blit_t = b.fun_type(&[bytes, i32_, bytes, i32_, i32_], void);
+    let bytes_blit = b.native("std", "bytes_blit", blit_t);
+    let upper_t = b.fun_type(&[bytes, i32_, i32_], bytes);
+    let ucs2_upper = b.native("std", "ucs2_upper", upper_t);
+
+    // Arithmetic, conversions and control flow
+    let half = b.float(0.5);
+    let (r0, r1, r2, r3, r4, r5, r6) = (Reg(0), Reg(1), Reg(2), Reg(3), Reg(4), Reg(5), Reg(6));
+    b.function(
+        arith,
+        fun_ii_i,
+        vec![i32_, i32_, i32_, f64_, bool_, f32_, i64_],
+        vec![
+            Opcode::Int { dst: r2, ptr: one },
+            Opcode::Add {
+                dst: r2,
+                a: r0,
+                b: r1,
+            },
+            Opcode::Sub {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::Mul {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::SDiv {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::UDiv {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::SMod {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::UMod {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::Shl {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::SShr {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::UShr {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::And {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::Or {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::Xor {
+                dst: r2,
+                a: r2,
+                b: r1,
+            },
+            Opcode::Neg { dst: r2, src: r2 },
+            Opcode::Incr { dst: r2 },
+            Opcode::Decr { dst: r2 },
+            Opcode::Mov { dst: r0, src: r2 },
+            Opcode::Float { dst: r3, ptr: half },
+            Opcode::ToSFloat { dst: r3, src: r2 },
+            Opcode::ToUFloat { dst: r5, src: r2 },
+            Opcode::ToInt { dst: r6, src: r3 },
+            Opcode::Bool {
+                dst: r4,
+                value: ValBool(true),
+            },
+            Opcode::Not { dst: r4, src: r4 },
+            Opcode::Label,
+            Opcode::JTrue {
+                cond: r4,
+                offset: 0,
+            },
+            Opcode::JFalse {
+                cond: r4,
+                offset: 0,
+            },
+            Opcode::JSLt {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::JSGte {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::JSGt {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::JSLte {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::JULt {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::JUGte {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::JNotLt {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::JNotGte {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::JEq {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::JNotEq {
+                a: r0,
+                b: r1,
+                offset: 0,
+            },
+            Opcode::Switch {
+                reg: r2,
+                offsets: vec![1],
+                end: 1,
+            },
+            Opcode::JAlways { offset: 0 },
+            Opcode::Ret { ret: r2 },
+        ],
+    );
+
+    // Raw memory, references and arrays
+    let hello = b.bytes(b"hello\0");
+    b.function(
+        memory,
+        fun_b_v,
+        vec![
+            bytes, i32_, ui8, ui16, f64_, ref_i32, i32_, array, dyn_, type_, bytes, void,
+        ],
+        vec![
+            Opcode::GetI8 {
+                dst: r2,
+                bytes: r0,
+                index: r1,
+            },
+            Opcode::GetI16 {
+                dst: r3,
+                bytes: r0,
+                index: r1,
+            },
+            Opcode::GetMem {
+                dst: r4,
+                bytes: r0,
+                index: r1,
+            },
+            Opcode::SetI8 {
+                bytes: r0,
+                index: r1,
+                src: r2,
+            },
+            Opcode::SetI16 {
+                bytes: r0,
+                index: r1,
+                src: r3,
+            },
+            Opcode::SetMem {
+                bytes: r0,
+                index: r1,
+                src: r4,
+            },
+            Opcode::Bytes {
+                dst: Reg(10),
+                ptr: hello,
+            },
+            Opcode::RefData {
+                dst: Reg(10),
+                src: Reg(7),
+            },
+            Opcode::RefOffset {
+                dst: Reg(10),
+                reg: Reg(10),
+                offset: r1,
+            },
+            Opcode::Ref { dst: r5, src: r1 },
+            Opcode::Unref { dst: r6, src: r5 },
+            Opcode::Setref { dst: r5, value: r6 },
+            Opcode::ArraySize {
+                dst: r6,
+                array: Reg(7),
+            },
+            Opcode::GetArray {
+                dst: Reg(8),
+                array: Reg(7),
+                index: r1,
+            },
+            Opcode::SetArray {
+                array: Reg(7),
+                index: r1,
+                src: Reg(8),
+            },
+            Opcode::NullCheck { reg: Reg(7) },
+            Opcode::Null { dst: Reg(8) },
+            Opcode::Type {
+                dst: Reg(9),
+                ty: point,
+            },
+            Opcode::GetType {
+                dst: Reg(9),
+                src: Reg(8),
+            },
+            Opcode::GetTID {
+                dst: r6,
+                src: Reg(9),
+            },
+            Opcode::Call3 {
+                dst: Reg(10),
+                fun: ucs2_upper,
+                arg0: r0,
+                arg1: r1,
+                arg2: r1,
+            },
+            Opcode::CallN {
+                dst: Reg(11),
+                fun: bytes_blit,
+                args: vec![r0, r1, Reg(10), r1, r1],
+            },
+            Opcode::Ret { ret: Reg(11) },
+        ],
+    );
+
+    // Objects, dynamics, closures, enums and exceptions
+    let name = b.string("name");
+    let x = RefField(0);
+    b.function(
+        objects,
+        fun_p_d,
+        vec![
+            point, point, i32_, pos, dyn_, dynobj, fun_v_i, fun_ii_i, color, null_i32, tls, vec2,
+            packed, void,
+        ],
+        vec![
+            Opcode::New { dst: r1 },
+            Opcode::Field {
+                dst: r2,
+                obj: r0,
+                field: x,
+            },
+            Opcode::SetField {
+                obj: r1,
+                field: x,
+                src: r2,
+            },
+            Opcode::CallMethod {
+                dst: r2,
+                field: RefField(0),
+                args: vec![r1],
+            },
+            Opcode::ToVirtual { dst: r3, src: r1 },
+            Opcode::ToDyn { dst: r4, src: r1 },
+            Opcode::SafeCast { dst: r1, src: r4 },
+            Opcode::UnsafeCast { dst: r1, src: r4 },
+            Opcode::New { dst: r5 },
+            Opcode::DynSet {
+                obj: r5,
+                field: name,
+                src: r4,
+            },
+            Opcode::DynGet {
+                dst: r4,
+                obj: r5,
+                field: name,
+            },
+            Opcode::StaticClosure {
+                dst: Reg(7),
+                fun: arith,
+            },
+            Opcode::InstanceClosure {
+                dst: r6,
+                fun: length,
+                obj: r1,
+            },
+            Opcode::VirtualClosure {
+                dst: r6,
+                obj: r3,
+                field: r2,
+            },
+            Opcode::CallClosure {
+                dst: r2,
+                fun: r6,
+                args: vec![],
+            },
+            Opcode::GetGlobal {
+                dst: r1,
+                global: g_origin,
+            },
+            Opcode::SetGlobal {
+                global: g_count,
+                src: r2,
+            },
+            Opcode::EnumAlloc {
+                dst: Reg(8),
+                construct: RefEnumConstruct(1),
+            },
+            Opcode::SetEnumField {
+                value: Reg(8),
+                field: RefField(0),
+                src: r2,
+            },
+            Opcode::MakeEnum {
+                dst: Reg(8),
+                construct: RefEnumConstruct(1),
+                args: vec![r2, r2, r2],
+            },
+            Opcode::EnumIndex {
+                dst: r2,
+                value: Reg(8),
+            },
+            Opcode::EnumField {
+                dst: r2,
+                value: Reg(8),
+                construct: RefEnumConstruct(1),
+                field: RefField(0),
+            },
+            Opcode::Trap { exc: r4, offset: 2 },
+            Opcode::Throw { exc: r4 },
+            Opcode::EndTrap { exc: r4 },
+            Opcode::JNull { reg: r4, offset: 0 },
+            Opcode::JNotNull { reg: r4, offset: 1 },
+            Opcode::Rethrow { exc: r4 },
+            Opcode::Assert,
+            Opcode::Nop,
+            Opcode::Ret { ret: r4 },
+        ],
+    );
+
+    // Methods
+    b.function(
+        length,
+        method_p_i,
+        vec![point, i32_, i32_],
+        vec![
+            Opcode::GetThis {
+                dst: r1,
+                field: RefField(0),
+            },
+            Opcode::GetThis {
+                dst: r2,
+                field: RefField(1),
+            },
+            Opcode::Mul {
+                dst: r1,
+                a: r1,
+                b: r1,
+            },
+            Opcode::Mul {
+                dst: r2,
+                a: r2,
+                b: r2,
+            },
+            Opcode::Add {
+                dst: r1,
+                a: r1,
+                b: r2,
+            },
+            Opcode::Ret { ret: r1 },
+        ],
+    );
+    b.function(
+        reset,
+        method_p_v,
+        vec![point, i32_, void],
+        vec![
+            Opcode::CallThis {
+                dst: r1,
+                field: RefField(0),
+                args: vec![],
+            },
+            Opcode::SetThis {
+                field: RefField(0),
+                src: r1,
+            },
+            Opcode::Ret { ret: r2 },
+        ],
+    );
+
+    b.function(
+        sum4,
+        fun_4_i,
+        vec![i32_, i32_, i32_, i32_],
+        vec![
+            Opcode::Add {
+                dst: r0,
+                a: r0,
+                b: r1,
+            },
+            Opcode::Add {
+                dst: r0,
+                a: r0,
+                b: r2,
+            },
+            Opcode::Add {
+                dst: r0,
+                a: r0,
+                b: r3,
+            },
+            Opcode::Ret { ret: r0 },
+        ],
+    );
+
+    // Entrypoint calling everything
+    let hello_str = b.string("Hello");
+    b.function(
+        main,
+        fun_v_v,
+        vec![void, f64_, bytes, i32_, point, dyn_],
+        vec![
+            Opcode::Call0 {
+                dst: r1,
+                fun: sys_time,
+            },
+            Opcode::String {
+                dst: r2,
+                ptr: hello_str,
+            },
+            Opcode::Call1 {
+                dst: r0,
+                fun: sys_print,
+                arg0: r2,
+            },
+            Opcode::Int { dst: r3, ptr: one },
+            Opcode::Call2 {
+                dst: r3,
+                fun: arith,
+                arg0: r3,
+                arg1: r3,
+            },
+            Opcode::Call2 {
+                dst: r0,
+                fun: memory,
+                arg0: r2,
+                arg1: r3,
+            },
+            Opcode::Call4 {
+                dst: r3,
+                fun: sum4,
+                arg0: r3,
+                arg1: r3,
+                arg2: r3,
+                arg3: r3,
+            },
+            Opcode::New { dst: r4 },
+            Opcode::Call1 {
+                dst: r5,
+                fun: objects,
+                arg0: r4,
+            },
+            Opcode::Ret { ret: r0 },
+        ],
+    );
+    b.entrypoint(main);
+
+    b.build().expect("The sample bytecode is invalid")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::mem::discriminant;
+
+    use crate::builder::{sample, FunctionBuilder, UnplacedLabel};
+    use crate::types::{Reg, TypeFun};
+    use crate::{Bytecode, Opcode, Type};
+
+    #[test]
+    fn sample_uses_every_opcode() {
+        let code = sample();
+        let used: HashSet<&str> = code
+            .functions
+            .iter()
+            .flat_map(|f| f.ops.iter().map(|o| o.name()))
+            .collect();
+        assert_eq!(used.len(), Opcode::NAMES.len());
+    }
+
+    #[test]
+    fn sample_has_every_type() {
+        let code = sample();
+        let kinds: HashSet<_> = code.types.iter().map(discriminant).collect();
+        assert_eq!(kinds.len(), 23);
+        assert!(kinds.contains(&discriminant(&Type::Packed(Default::default()))));
+    }
+
+    #[test]
+    fn sample_roundtrip() {
+        let code = sample();
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let mut again = Vec::new();
+        crate::Bytecode::load(&mut data.as_slice())
+            .unwrap()
+            .serialize(&mut again)
+            .unwrap();
+        assert_eq!(data, again);
+        for f in &code.functions {
+            f.display(&code).to_string();
+        }
+    }
+
+    #[test]
+    fn function_builder() {
+        let mut code = sample();
+        let mut editor = code.edit();
+        let i32_ = editor.ty(Type::I32);
+        let fun_t = editor.ty(Type::Fun(TypeFun {
+            args: vec![i32_],
+            ret: i32_,
+        }));
+        let ints: Vec<_> = (0..3).map(|i| editor.int(i * 10)).collect();
+
+        // Backward jump, forward jumps and a switch
+        let mut f = FunctionBuilder::new(fun_t, &[i32_]);
+        let (x, r) = (f.arg(0), f.reg(i32_));
+        let (start, cases, end) = (f.label(), [f.label(), f.label()], f.label());
+        f.place(start);
+        f.switch(x, &cases, end);
+        for (case, ptr) in cases.iter().zip(&ints) {
+            f.place(*case);
+            f.emit(Opcode::Int { dst: r, ptr: *ptr });
+            f.jump(Opcode::JAlways { offset: 0 }, end);
+        }
+        f.jump(Opcode::JAlways { offset: 0 }, start);
+        f.place(end);
+        f.emit(Opcode::Ret { ret: r });
+        let findex = f.register(&mut code).unwrap();
+
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let code = Bytecode::from_bytes(&data).unwrap();
+        let f = findex.resolve_as_fn(&code).unwrap();
+        assert_eq!(f.regs, [i32_, i32_]);
+        assert_eq!(f.ops[0].jump_targets(0), [1, 3, 6]);
+        assert_eq!(f.ops[2].jump_targets(2), [6]);
+        assert_eq!(f.ops[5].jump_targets(5), [0]);
+
+        let mut f = FunctionBuilder::new(fun_t, &[i32_]);
+        let nowhere = f.label();
+        f.jump(
+            Opcode::JTrue {
+                cond: Reg(0),
+                offset: 0,
+            },
+            nowhere,
+        );
+        assert_eq!(f.finish().unwrap_err(), UnplacedLabel(nowhere));
+    }
+}
@@ -0,0 +1,145 @@
+//! Readers for the bytes constant pool.
+//!
+//! The bytes pool (*since bytecode v5*) holds raw data referenced by the `Bytes` instruction : localization tables,
+//! embedded resources, lookup tables. [Bytecode::get_bytes] gives the data of an entry, the functions of this module
+//! decode the common encodings.
+//! ```
+//! use hlbc::builder::BytecodeBuilder;
+//! use hlbc::bytes::{hexdump, utf16};
+//!
+//! let mut b = BytecodeBuilder::new();
+//! let hello = b.bytes(&[b'h', 0, b'i', 0, 0, 0]);
+//! # let void = b.ty(hlbc::types::Type::Void);
+//! # let ty = b.fun_type(&[], void);
+//! # let main = b.findex();
+//! # b.function(main, ty, vec![void], vec![hlbc::opcodes::Opcode::Ret { ret: hlbc::types::Reg(0) }]);
+//! let code = b.build().unwrap();
+//! let data = code.get_bytes(hello).unwrap();
+//! assert_eq!(utf16(data).as_deref(), Some("hi"));
+//! assert_eq!(hexdump(data), "00000000  68 00 69 00 00 00                                h.i...\n");
+//! ```
+
+use std::fmt::Write;
+
+use crate::types::RefBytes;
+use crate::Bytecode;
+
+impl Bytecode {
+    /// Get a bytes constant, returns None if the reference is out of bounds or if the bytecode has no bytes pool
+    /// (before v5, the `Bytes` instruction references the string pool).
+    pub fn get_bytes(&self, b: RefBytes) -> Option<&[u8]> {
+        let (data, pos) = self.bytes.as_ref()?;
+        let start = *pos.get(b.0)?;
+        // Entries are usually in order, but nothing requires it
+        let end = pos
+            .iter()
+            .copied()
+            .filter(|&p| p > start)
+            .min()
+            .unwrap_or(data.len());
+        data.get(start..end)
+    }
+
+    /// Iterate on every bytes constant with its reference, empty without a bytes pool
+    pub fn iter_bytes(&self) -> impl Iterator<Item = (RefBytes, &[u8])> {
+        let count = self.bytes.as_ref().map_or(0, |(_, pos)| pos.len());
+        (0..count).filter_map(move |i| Some((RefBytes(i), self.get_bytes(RefBytes(i))?)))
+    }
+}
+
+/// Decode an UTF-16 (little endian) string, the way Haxe strings are stored. It stops at the first nul character.
+/// Returns None if the data has an odd length or isn't valid UTF-16.
+pub fn utf16(data: &[u8]) -> Option<String> {
+    if data.len() % 2 != 0 {
+        return None;
+    }
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&u| u != 0)
+        .collect();
+    String::from_utf16(&units).ok()
+}
+
+/// Decode little endian i32, trailing bytes are ignored
+pub fn i32_array(data: &[u8]) -> Vec<i32> {
+    data.chunks_exact(4)
+        .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// Decode little endian f32, trailing bytes are ignored
+pub fn f32_array(data: &[u8]) -> Vec<f32> {
+    data.chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// Decode little endian f64, trailing bytes are ignored
+pub fn f64_array(data: &[u8]) -> Vec<f64> {
+    data.chunks_exact(8)
+        .map(|c| f64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]))
+        .collect()
+}
+
+/// Classic hex dump : offset, 16 bytes in hex and their ASCII representation on each line
+pub fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", i * 16);
+        for j in 0..16 {
+            match line.get(j) {
+                Some(b) => {
+                    let _ = write!(out, " {b:02x}");
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  ");
+        out.extend(line.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::BytecodeBuilder;
+    use crate::bytes::{f32_array, hexdump, i32_array, utf16};
+    use crate::opcodes::Opcode;
+    use crate::types::{RefBytes, Reg, Type};
+
+    #[test]
+    fn readers() {
+        let mut b = BytecodeBuilder::new();
+        let ints = b.bytes(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        let floats = b.bytes(&1.5f32.to_le_bytes());
+        let text = b.bytes(&[0x3d, 0xd8, 0x00, 0xde, 0, 0]);
+        let void = b.ty(Type::Void);
+        let ty = b.fun_type(&[], void);
+        let main = b.findex();
+        b.function(main, ty, vec![void], vec![Opcode::Ret { ret: Reg(0) }]);
+        let code = b.build().unwrap();
+
+        assert_eq!(i32_array(code.get_bytes(ints).unwrap()), [1, -1]);
+        assert_eq!(f32_array(code.get_bytes(floats).unwrap()), [1.5]);
+        assert_eq!(utf16(code.get_bytes(text).unwrap()).unwrap(), "😀");
+        assert_eq!(utf16(&[0x3d, 0xd8]), None);
+        assert_eq!(utf16(&[1]), None);
+        assert_eq!(code.get_bytes(RefBytes(3)), None);
+        assert_eq!(code.iter_bytes().count(), 3);
+        assert_eq!(
+            hexdump(&(0..20).collect::<Vec<u8>>())
+                .lines()
+                .nth(1)
+                .unwrap(),
+            "00000010  10 11 12 13                                      ...."
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Values of the globals initialized from the constant pool.
+//!
+//! A [ConstantDef](crate::types::ConstantDef) only lists indexes, their meaning depends on the type of each field of the global : an index in
+//! the integer, float or string pool, a type or another global. [Bytecode::global_value] evaluates them the same way
+//! the Hashlink runtime does.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc::constants::ConstValue;
+//! use hlbc::types::RefGlobal;
+//!
+//! let code = sample();
+//! let origin = code.global_value(RefGlobal(0)).unwrap();
+//! assert!(matches!(origin, ConstValue::Obj { .. }));
+//! ```
+
+use crate::types::{RefGlobal, RefString, RefType, Type};
+use crate::Bytecode;
+
+/// Maximum depth of globals referencing other globals, constants should never be that deep
+const MAX_DEPTH: usize = 16;
+
+/// Value of a global initialized from the constant pool
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConstValue {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    /// A `hl.Bytes` field, pointing to a string of the pool
+    Bytes(RefString),
+    Type(RefType),
+    /// A Haxe `String`, its bytes and length fields folded into the string
+    String(RefString),
+    /// An object or a struct with the value of each of its own fields
+    Obj {
+        ty: RefType,
+        fields: Vec<ConstValue>,
+    },
+    /// Another global without initializer, its value is only known at runtime
+    Global(RefGlobal),
+}
+
+impl Bytecode {
+    /// Value of a global initialized from the constant pool, `None` if the global is initialized with code
+    pub fn global_value(&self, global: RefGlobal) -> Option<ConstValue> {
+        self.global_value_rec(global, 0)
+    }
+
+    fn global_value_rec(&self, global: RefGlobal, depth: usize) -> Option<ConstValue> {
+        let def = self
+            .constants
+            .as_ref()?
+            .get(*self.globals_initializers.get(&global)?)?;
+        let ty = *self.globals.get(global.0)?;
+        let obj = ty.resolve_as_obj(&self.types)?;
+        let fields = obj
+            .own_fields
+            .iter()
+            .zip(&def.fields)
+            .map(|(f, &idx)| {
+                Some(match f.t.resolve(&self.types) {
+                    Type::I32 => ConstValue::Int(*self.ints.get(idx)?),
+                    Type::F64 => ConstValue::Float(*self.floats.get(idx)?),
+                    Type::Bool => ConstValue::Bool(idx != 0),
+                    Type::Bytes => ConstValue::Bytes(RefString(idx)),
+                    Type::Type => ConstValue::Type(RefType(idx)),
+                    // Any other field takes the value of a global
+                    _ if depth < MAX_DEPTH => self
+                        .global_value_rec(RefGlobal(idx), depth + 1)
+                        .unwrap_or(ConstValue::Global(RefGlobal(idx))),
+                    _ => ConstValue::Global(RefGlobal(idx)),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        match fields.as_slice() {
+            [ConstValue::Bytes(s), ConstValue::Int(_)]
+                if obj.name.resolve(&self.strings) == "String" =>
+            {
+                Some(ConstValue::String(*s))
+            }
+            _ => Some(ConstValue::Obj { ty, fields }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{sample, BytecodeBuilder};
+    use crate::constants::ConstValue;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefGlobal, Reg, Type};
+
+    #[test]
+    fn global_values() {
+        let code = sample();
+        let origin = code.global_value(RefGlobal(0)).unwrap();
+        assert_eq!(
+            origin,
+            ConstValue::Obj {
+                ty: code.globals[0],
+                fields: vec![ConstValue::Int(0), ConstValue::Int(1)]
+            }
+        );
+        assert_eq!(origin.display(&code), "Point { x: 0, y: 1 }");
+        assert_eq!(code.global_value(RefGlobal(1)), None);
+
+        let mut b = BytecodeBuilder::new();
+        let bytes = b.ty(Type::Bytes);
+        let i32_ = b.ty(Type::I32);
+        let string = b.class("String", None, &[("bytes", bytes), ("length", i32_)], &[]);
+        let holder = b.class("Holder", None, &[("name", string)], &[]);
+        let (g_hello, g_holder) = (b.global(string), b.global(holder));
+        let hello = b.string("hello");
+        let five = b.int(5);
+        b.constant(g_hello, vec![hello.0, five.0]);
+        b.constant(g_holder, vec![g_hello.0]);
+        let init_t = b.fun_type(&[], i32_);
+        let init = b.findex();
+        b.function(init, init_t, vec![i32_], vec![Opcode::Ret { ret: Reg(0) }]);
+        b.entrypoint(init);
+        let code = b.build().unwrap();
+
+        assert_eq!(code.global_value(g_hello), Some(ConstValue::String(hello)));
+        let value = code.global_value(g_holder).unwrap();
+        assert_eq!(value.display(&code), "Holder { name: \"hello\" }");
+    }
+}
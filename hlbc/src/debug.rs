@@ -0,0 +1,162 @@
+//! Mapping between instructions and source lines.
+//!
+//! [Function::debug_info] gives the file and line of each instruction of a function. [DebugInfoIndex] maps them the
+//! other way, from a line of a source file to all the instructions compiled from it, and lists the source files
+//! referenced. Files can be given by their full path as stored in the bytecode or by a path suffix (`Main.hx` for
+//! `src/game/Main.hx`).
+//! ```
+//! use hlbc::builder::sample;
+//!
+//! let code = sample();
+//! let index = code.debug_info_index();
+//! let findex = code.functions[0].findex;
+//! assert_eq!(index.location(&code, findex, 1), Some(("Sample.hx", 2)));
+//! assert_eq!(index.ops_at("Sample.hx", 2)[0], (findex, 1));
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::types::{Function, RefFun};
+use crate::Bytecode;
+
+/// Instructions by source line, see [debug](crate::debug)
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfoIndex {
+    /// Debug file names, empty without debug information
+    files: Vec<String>,
+    /// Instructions (findex, position) at each (file, line), sorted
+    lines: BTreeMap<(usize, usize), Vec<(RefFun, usize)>>,
+}
+
+impl DebugInfoIndex {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut index = DebugInfoIndex {
+            files: code.debug_files.clone().unwrap_or_default(),
+            lines: BTreeMap::new(),
+        };
+        for f in &code.functions {
+            for (pos, &location) in f.debug_info.iter().flatten().enumerate() {
+                index
+                    .lines
+                    .entry(location)
+                    .or_default()
+                    .push((f.findex, pos));
+            }
+        }
+        for ops in index.lines.values_mut() {
+            ops.sort();
+        }
+        index
+    }
+
+    /// Source files referenced by at least one instruction, in the order of the debug files pool
+    pub fn files(&self) -> Vec<&str> {
+        let mut files: Vec<usize> = self.lines.keys().map(|&(file, _)| file).collect();
+        files.dedup();
+        files
+            .into_iter()
+            .filter_map(|file| self.files.get(file).map(String::as_str))
+            .collect()
+    }
+
+    /// Indexes of the debug files named `name` or ending with `/name`
+    fn matching(&self, name: &str) -> Vec<usize> {
+        let suffix = format!("/{}", name.trim_start_matches('/'));
+        self.files
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| {
+                let f = f.replace('\\', "/");
+                f == name || f.ends_with(&suffix)
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Instructions (findex, position) compiled from a line of a file, sorted
+    pub fn ops_at(&self, file: &str, line: usize) -> Vec<(RefFun, usize)> {
+        let mut ops: Vec<(RefFun, usize)> = self
+            .matching(file)
+            .into_iter()
+            .filter_map(|file| self.lines.get(&(file, line)))
+            .flatten()
+            .copied()
+            .collect();
+        ops.sort();
+        ops
+    }
+
+    /// Lines of a file with instructions, sorted
+    pub fn lines(&self, file: &str) -> Vec<usize> {
+        let mut lines: Vec<usize> = self
+            .matching(file)
+            .into_iter()
+            .flat_map(|file| {
+                self.lines
+                    .range((file, 0)..=(file, usize::MAX))
+                    .map(|(&(_, line), _)| line)
+            })
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// File and line of the instruction at `pos` in a function, None without debug information
+    pub fn location(&self, code: &Bytecode, findex: RefFun, pos: usize) -> Option<(&str, usize)> {
+        let (file, line) = *findex.resolve_as_fn(code)?.debug_info.as_ref()?.get(pos)?;
+        Some((self.files.get(file)?, line))
+    }
+}
+
+impl Bytecode {
+    /// Build an index of the instructions by source line, see [debug](crate::debug)
+    pub fn debug_info_index(&self) -> DebugInfoIndex {
+        DebugInfoIndex::new(self)
+    }
+}
+
+impl Function {
+    /// Debug file index and line of the instruction at `pos`
+    pub fn line_of(&self, pos: usize) -> Option<(usize, usize)> {
+        self.debug_info.as_ref()?.get(pos).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::BytecodeBuilder;
+    use crate::opcodes::Opcode;
+    use crate::types::{Reg, Type};
+
+    #[test]
+    fn lines() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let ty = b.fun_type(&[], void);
+        let [f, g] = [(); 2].map(|_| b.findex());
+        for findex in [f, g] {
+            let ops = vec![Opcode::Nop, Opcode::Ret { ret: Reg(0) }];
+            b.function(findex, ty, vec![void], ops);
+        }
+        let mut code = b.build().unwrap();
+        code.debug_files = Some(vec![
+            "src/game/Main.hx".to_owned(),
+            "Unused.hx".to_owned(),
+            "std/Std.hx".to_owned(),
+        ]);
+        code.functions[0].debug_info = Some(vec![(0, 42), (0, 43)]);
+        code.functions[1].debug_info = Some(vec![(2, 10), (0, 42)]);
+        let index = code.debug_info_index();
+
+        assert_eq!(index.files(), ["src/game/Main.hx", "std/Std.hx"]);
+        assert_eq!(index.ops_at("Main.hx", 42), [(f, 0), (g, 1)]);
+        assert_eq!(index.ops_at("game/Main.hx", 43), [(f, 1)]);
+        assert!(index.ops_at("ain.hx", 42).is_empty());
+        assert_eq!(index.lines("src/game/Main.hx"), [42, 43]);
+        assert!(index.lines("Unused.hx").is_empty());
+        assert_eq!(index.location(&code, g, 0), Some(("std/Std.hx", 10)));
+        assert_eq!(index.location(&code, g, 2), None);
+        assert_eq!(code.functions[0].line_of(1), Some((0, 43)));
+    }
+}
@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::ffi::CStr;
 use std::io::Read;
 
 use byteorder::{LittleEndian, ReadBytesExt};
@@ -8,8 +7,96 @@ use crate::types::{
     EnumConstruct, Function, Native, ObjField, ObjProto, RefField, RefString, RefType, Type,
     TypeFun, TypeObj,
 };
+use crate::{version, Error, ParseError, Result, Section};
 use crate::{ConstantDef, Opcode, RefFun, RefGlobal};
-use crate::{Error, Result};
+
+/// Limits enforced while parsing, so a malicious or corrupted file can't make the parser allocate gigabytes : counts
+/// read from the file are checked before allocating anything. The defaults are far above what the biggest games need.
+/// ```
+/// use hlbc::builder::sample;
+/// use hlbc::deser::ParseOptions;
+/// use hlbc::{Bytecode, Error, ParseError};
+///
+/// let mut data = Vec::new();
+/// sample().serialize(&mut data).unwrap();
+/// let opts = ParseOptions {
+///     max_function_ops: 4,
+///     ..ParseOptions::default()
+/// };
+/// assert!(matches!(
+///     Bytecode::load_with(&data[..], &opts),
+///     Err(Error::Parse {
+///         kind: ParseError::LimitExceeded { what: "instructions", .. },
+///         ..
+///     })
+/// ));
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseOptions {
+    /// Elements of each pool : ints, floats, bytes, types, globals, natives, functions and constants
+    pub max_elements: usize,
+    /// Strings of the string pool and debug file names
+    pub max_strings: usize,
+    /// Size in bytes of the strings and bytes blocks
+    pub max_block_size: usize,
+    /// Instructions of a function
+    pub max_function_ops: usize,
+    /// Elements of a list nested in an element : registers and variable assignments of a function, fields, methods
+    /// and bindings of a class, constructs of an enum and their parameters, fields of a virtual or of a constant.
+    ///
+    /// This is the number of elements of each of these lists, not a nesting depth : the bytecode format has no
+    /// recursive structure to limit.
+    pub max_nested: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_elements: 1 << 22,
+            max_strings: 1 << 22,
+            max_block_size: 1 << 28,
+            max_function_ops: 1 << 22,
+            max_nested: 1 << 20,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// No limit, for trusted files only
+    pub fn unlimited() -> Self {
+        Self {
+            max_elements: usize::MAX,
+            max_strings: usize::MAX,
+            max_block_size: usize::MAX,
+            max_function_ops: usize::MAX,
+            max_nested: usize::MAX,
+        }
+    }
+
+    /// Check a count read from the file against a limit
+    pub(crate) fn check(count: usize, limit: usize, what: &'static str) -> Result<usize> {
+        if count > limit {
+            Err(ParseError::LimitExceeded { what, count, limit }.into())
+        } else {
+            Ok(count)
+        }
+    }
+}
+
+/// Read a count and check it against a limit
+fn read_count(r: &mut impl Read, limit: usize, what: &'static str) -> Result<usize> {
+    ParseOptions::check(r.read_varu()? as usize, limit, what)
+}
+
+/// Read a block of `size` bytes, the buffer grows with the data actually read instead of trusting the size
+pub(crate) fn read_block(r: &mut impl Read, size: usize) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    r.by_ref().take(size as u64).read_to_end(&mut data)?;
+    if data.len() < size {
+        return Err(ParseError::UnexpectedEof.into());
+    }
+    Ok(data)
+}
 
 /// Extension trait to read bytecode elements from anything that implements [Read]
 pub trait ReadHlExt: ReadBytesExt {
@@ -18,7 +105,7 @@ pub trait ReadHlExt: ReadBytesExt {
     /// Read a variable size unsigned integer. Used internally by the other functions.
     fn read_varu(&mut self) -> Result<u32>;
     /// Read a strings block
-    fn read_strings(&mut self, nstrings: usize) -> Result<Vec<String>>;
+    fn read_strings<S: for<'a> From<&'a str>>(&mut self, nstrings: usize) -> Result<Vec<S>>;
     /// Read a field definition
     fn read_field(&mut self) -> Result<ObjField>;
     /// Read a type reference
@@ -57,28 +144,14 @@ impl<T: Read> ReadHlExt for T {
     fn read_varu(&mut self) -> Result<u32> {
         let i = self.read_vari()?;
         if i < 0 {
-            Err(Error::MalformedBytecode(format!(
-                "Got negative index '{i}' (expected > 0)"
-            )))
+            Err(ParseError::NegativeIndex(i).into())
         } else {
             Ok(i as u32)
         }
     }
 
-    fn read_strings(&mut self, nstrings: usize) -> Result<Vec<String>> {
-        let mut strings = Vec::with_capacity(nstrings);
-        let mut string_data = vec![0u8; self.read_i32::<LittleEndian>()? as usize];
-        self.read_exact(&mut string_data)?;
-        let mut acc = 0;
-        for _ in 0..nstrings {
-            let ssize = self.read_varu()? as usize + 1;
-            //println!("size: {ssize} {:?}", &string_data[acc..(acc + ssize)]);
-            let cstr =
-                unsafe { CStr::from_bytes_with_nul_unchecked(&string_data[acc..(acc + ssize)]) };
-            strings.push(cstr.to_string_lossy().to_string());
-            acc += ssize;
-        }
-        Ok(strings)
+    fn read_strings<S: for<'a> From<&'a str>>(&mut self, nstrings: usize) -> Result<Vec<S>> {
+        read_strings(self, nstrings, &ParseOptions::default())
     }
 
     fn read_field(&mut self) -> Result<ObjField> {
@@ -105,103 +178,11 @@ impl<T: Read> ReadHlExt for T {
     }
 
     fn read_type_obj(&mut self) -> Result<TypeObj> {
-        let name = RefString(self.read_vari()? as usize);
-        let super_ = self.read_vari()?;
-        let global = RefGlobal(self.read_varu()? as usize);
-        let nfields = self.read_varu()? as usize;
-        let nprotos = self.read_varu()? as usize;
-        let nbindings = self.read_varu()? as usize;
-        let mut own_fields = Vec::with_capacity(nfields);
-        for _ in 0..nfields {
-            own_fields.push(self.read_field()?);
-        }
-        let mut protos = Vec::with_capacity(nprotos);
-        for _ in 0..nprotos {
-            protos.push(ObjProto {
-                name: RefString(self.read_vari()? as usize),
-                findex: RefFun(self.read_varu()? as usize),
-                pindex: self.read_vari()?,
-            });
-        }
-        let mut bindings = HashMap::with_capacity(nbindings);
-        for _ in 0..nbindings {
-            bindings.insert(
-                RefField(self.read_varu()? as usize),
-                RefFun(self.read_varu()? as usize),
-            );
-        }
-        Ok(TypeObj {
-            name,
-            super_: if super_ < 0 {
-                None
-            } else {
-                Some(RefType(super_ as usize))
-            },
-            global,
-            own_fields,
-            fields: Vec::with_capacity(0),
-            protos,
-            bindings,
-        })
+        read_type_obj(self, &ParseOptions::default())
     }
 
     fn read_type(&mut self) -> Result<Type> {
-        match self.read_u8()? {
-            0 => Ok(Type::Void),
-            1 => Ok(Type::UI8),
-            2 => Ok(Type::UI16),
-            3 => Ok(Type::I32),
-            4 => Ok(Type::I64),
-            5 => Ok(Type::F32),
-            6 => Ok(Type::F64),
-            7 => Ok(Type::Bool),
-            8 => Ok(Type::Bytes),
-            9 => Ok(Type::Dyn),
-            10 => Ok(Type::Fun(self.read_type_fun()?)),
-            11 => Ok(Type::Obj(self.read_type_obj()?)),
-            12 => Ok(Type::Array),
-            13 => Ok(Type::Type),
-            14 => Ok(Type::Ref(self.read_type_ref()?)),
-            15 => {
-                let nfields = self.read_varu()? as usize;
-                let mut fields = Vec::with_capacity(nfields);
-                for _ in 0..nfields {
-                    fields.push(self.read_field()?);
-                }
-                Ok(Type::Virtual { fields })
-            }
-            16 => Ok(Type::DynObj),
-            17 => Ok(Type::Abstract {
-                name: RefString(self.read_vari()? as usize),
-            }),
-            18 => {
-                let name = RefString(self.read_vari()? as usize);
-                let global = RefGlobal(self.read_varu()? as usize);
-                let nconstructs = self.read_varu()? as usize;
-                let mut constructs = Vec::with_capacity(nconstructs);
-                for _ in 0..nconstructs {
-                    let name = RefString(self.read_vari()? as usize);
-                    let nparams = self.read_varu()? as usize;
-                    let mut params = Vec::with_capacity(nparams);
-                    for _ in 0..nparams {
-                        params.push(self.read_type_ref()?);
-                    }
-                    constructs.push(EnumConstruct { name, params })
-                }
-                Ok(Type::Enum {
-                    name,
-                    global,
-                    constructs,
-                })
-            }
-            19 => Ok(Type::Null(self.read_type_ref()?)),
-            20 => Ok(Type::Method(self.read_type_fun()?)),
-            21 => Ok(Type::Struct(self.read_type_obj()?)),
-            22 => Ok(Type::Packed(self.read_type_ref()?)),
-            other => Err(Error::MalformedBytecode(format!(
-                "Invalid type kind '{other}'"
-            ))),
-        }
+        read_type(self, &ParseOptions::default())
     }
 
     fn read_native(&mut self) -> Result<Native> {
@@ -214,88 +195,521 @@ impl<T: Read> ReadHlExt for T {
     }
 
     fn read_function(&mut self, has_debug: bool, version: u8) -> Result<Function> {
-        let t = self.read_type_ref()?;
-        let findex = RefFun(self.read_varu()? as usize);
-        let nregs = self.read_varu()? as usize;
-        let nops = self.read_varu()? as usize;
-        let mut regs = Vec::with_capacity(nregs);
-        for _ in 0..nregs {
-            regs.push(self.read_type_ref()?);
-        }
-        let mut ops = Vec::with_capacity(nops);
-        for _ in 0..nops {
-            ops.push(Opcode::decode(self)?);
-        }
+        read_function(self, has_debug, version, &ParseOptions::default())
+    }
 
-        // This is extracted from the hashlink source code, do not count on me to explain what it does
-        let debug_info = if has_debug {
-            let mut tmp = Vec::with_capacity(nops);
-            let mut currfile: i32 = -1;
-            let mut currline: i32 = 0;
-            let mut i = 0;
-            while i < nops {
-                let mut c = self.read_u8()? as i32;
-                if c & 1 != 0 {
-                    c >>= 1;
-                    currfile = (c << 8) | (self.read_u8()? as i32);
-                } else if c & 2 != 0 {
-                    let delta = c >> 6;
-                    let mut count = (c >> 2) & 15;
-                    while count > 0 {
-                        count -= 1;
-                        tmp.push((currfile as usize, currline as usize));
-                        i += 1;
-                    }
-                    currline += delta;
-                } else if c & 4 != 0 {
-                    currline += c >> 3;
-                    tmp.push((currfile as usize, currline as usize));
-                    i += 1;
-                } else {
-                    let b2 = self.read_u8()? as i32;
-                    let b3 = self.read_u8()? as i32;
-                    currline = (c >> 3) | (b2 << 5) | (b3 << 13);
-                    tmp.push((currfile as usize, currline as usize));
-                    i += 1;
-                }
-            }
-            Some(tmp)
-        } else {
+    fn read_constant_def(&mut self) -> Result<ConstantDef> {
+        read_constant_def(self, &ParseOptions::default())
+    }
+}
+
+/// [ReadHlExt::read_strings] with limits
+pub(crate) fn read_strings<S: for<'a> From<&'a str>>(
+    r: &mut impl Read,
+    nstrings: usize,
+    opts: &ParseOptions,
+) -> Result<Vec<S>> {
+    let nstrings = ParseOptions::check(nstrings, opts.max_strings, "strings")?;
+    let mut strings = Vec::with_capacity(nstrings);
+    let size = r.read_i32::<LittleEndian>()?;
+    if size < 0 {
+        return Err(ParseError::InvalidSize(size).into());
+    }
+    let size = ParseOptions::check(size as usize, opts.max_block_size, "bytes of strings")?;
+    let string_data = read_block(r, size)?;
+    let mut acc = 0;
+    for i in 0..nstrings {
+        let ssize = r.read_varu()? as usize + 1;
+        // Without the nul terminator
+        let s = string_data
+            .get(acc..(acc + ssize - 1))
+            .ok_or(ParseError::InvalidString(i))?;
+        strings.push(S::from(&String::from_utf8_lossy(s)));
+        acc += ssize;
+    }
+    Ok(strings)
+}
+
+/// [ReadHlExt::read_type_obj] with limits
+pub(crate) fn read_type_obj(r: &mut impl Read, opts: &ParseOptions) -> Result<TypeObj> {
+    let name = RefString(r.read_vari()? as usize);
+    let super_ = r.read_vari()?;
+    let global = RefGlobal(r.read_varu()? as usize);
+    let nfields = read_count(r, opts.max_nested, "fields")?;
+    let nprotos = read_count(r, opts.max_nested, "methods")?;
+    let nbindings = read_count(r, opts.max_nested, "bindings")?;
+    let mut own_fields = Vec::with_capacity(nfields);
+    for _ in 0..nfields {
+        own_fields.push(r.read_field()?);
+    }
+    let mut protos = Vec::with_capacity(nprotos);
+    for _ in 0..nprotos {
+        protos.push(ObjProto {
+            name: RefString(r.read_vari()? as usize),
+            findex: RefFun(r.read_varu()? as usize),
+            pindex: r.read_vari()?,
+        });
+    }
+    let mut bindings = HashMap::with_capacity(nbindings);
+    for _ in 0..nbindings {
+        bindings.insert(
+            RefField(r.read_varu()? as usize),
+            RefFun(r.read_varu()? as usize),
+        );
+    }
+    Ok(TypeObj {
+        name,
+        super_: if super_ < 0 {
             None
-        };
+        } else {
+            Some(RefType(super_ as usize))
+        },
+        global,
+        own_fields,
+        fields: Vec::with_capacity(0),
+        protos,
+        bindings,
+    })
+}
 
-        let assigns = if has_debug && version >= 3 {
-            let len = self.read_varu()? as usize;
-            let mut assigns = Vec::with_capacity(len);
-            for _ in 0..len {
-                assigns.push((
-                    RefString(self.read_varu()? as usize),
-                    self.read_vari()? as usize,
-                ));
+/// [ReadHlExt::read_type] with limits
+pub(crate) fn read_type(r: &mut impl Read, opts: &ParseOptions) -> Result<Type> {
+    match r.read_u8()? {
+        0 => Ok(Type::Void),
+        1 => Ok(Type::UI8),
+        2 => Ok(Type::UI16),
+        3 => Ok(Type::I32),
+        4 => Ok(Type::I64),
+        5 => Ok(Type::F32),
+        6 => Ok(Type::F64),
+        7 => Ok(Type::Bool),
+        8 => Ok(Type::Bytes),
+        9 => Ok(Type::Dyn),
+        10 => Ok(Type::Fun(r.read_type_fun()?)),
+        11 => Ok(Type::Obj(read_type_obj(r, opts)?)),
+        12 => Ok(Type::Array),
+        13 => Ok(Type::Type),
+        14 => Ok(Type::Ref(r.read_type_ref()?)),
+        15 => {
+            let nfields = read_count(r, opts.max_nested, "fields")?;
+            let mut fields = Vec::with_capacity(nfields);
+            for _ in 0..nfields {
+                fields.push(r.read_field()?);
             }
-            Some(assigns)
-        } else {
-            None
-        };
-        Ok(Function {
+            Ok(Type::Virtual { fields })
+        }
+        16 => Ok(Type::DynObj),
+        17 => Ok(Type::Abstract {
+            name: RefString(r.read_vari()? as usize),
+        }),
+        18 => {
+            let name = RefString(r.read_vari()? as usize);
+            let global = RefGlobal(r.read_varu()? as usize);
+            let nconstructs = read_count(r, opts.max_nested, "enum constructs")?;
+            let mut constructs = Vec::with_capacity(nconstructs);
+            for _ in 0..nconstructs {
+                let name = RefString(r.read_vari()? as usize);
+                let nparams = read_count(r, opts.max_nested, "enum construct parameters")?;
+                let mut params = Vec::with_capacity(nparams);
+                for _ in 0..nparams {
+                    params.push(r.read_type_ref()?);
+                }
+                constructs.push(EnumConstruct { name, params })
+            }
+            Ok(Type::Enum {
+                name,
+                global,
+                constructs,
+            })
+        }
+        19 => Ok(Type::Null(r.read_type_ref()?)),
+        20 => Ok(Type::Method(r.read_type_fun()?)),
+        21 => Ok(Type::Struct(read_type_obj(r, opts)?)),
+        22 => Ok(Type::Packed(r.read_type_ref()?)),
+        other => Err(ParseError::InvalidTypeKind(other).into()),
+    }
+}
+
+/// [ReadHlExt::read_function] with limits
+pub(crate) fn read_function(
+    r: &mut impl Read,
+    has_debug: bool,
+    version: u8,
+    opts: &ParseOptions,
+) -> Result<Function> {
+    let (mut f, nops) = read_function_header(r, opts)?;
+    read_function_body(r, &mut f, nops, has_debug, version, opts)?;
+    Ok(f)
+}
+
+/// [ReadHlExt::read_constant_def] with limits
+pub(crate) fn read_constant_def(r: &mut impl Read, opts: &ParseOptions) -> Result<ConstantDef> {
+    let global = RefGlobal(r.read_varu()? as usize);
+    let nfields = read_count(r, opts.max_nested, "constant fields")?;
+    let mut fields = Vec::with_capacity(nfields);
+    for _ in 0..nfields {
+        fields.push(r.read_varu()? as usize);
+    }
+    Ok(ConstantDef { global, fields })
+}
+
+/// Read the signature and registers of a function, returns the function without code and its number of instructions
+pub(crate) fn read_function_header(
+    r: &mut impl Read,
+    opts: &ParseOptions,
+) -> Result<(Function, usize)> {
+    let t = r.read_type_ref()?;
+    let findex = RefFun(r.read_varu()? as usize);
+    let nregs = read_count(r, opts.max_nested, "registers")?;
+    let nops = read_count(r, opts.max_function_ops, "instructions")?;
+    let mut regs = Vec::with_capacity(nregs);
+    for _ in 0..nregs {
+        regs.push(r.read_type_ref()?);
+    }
+    Ok((
+        Function {
             name: None,
             t,
             findex,
             regs,
-            ops,
-            debug_info,
-            assigns,
+            ops: Vec::new(),
+            debug_info: None,
+            assigns: None,
             parent: None,
-        })
+        },
+        nops,
+    ))
+}
+
+/// Read the instructions, debug info and assigns following the header of a function
+pub(crate) fn read_function_body(
+    r: &mut impl Read,
+    f: &mut Function,
+    nops: usize,
+    has_debug: bool,
+    version: u8,
+    opts: &ParseOptions,
+) -> Result<()> {
+    let mut ops = Vec::with_capacity(nops);
+    for _ in 0..nops {
+        ops.push(Opcode::decode(r)?);
     }
+    f.ops = ops;
+    f.debug_info = if has_debug {
+        let mut tmp = Vec::with_capacity(nops);
+        read_debug_info(r, nops, |file, line| tmp.push((file, line)))?;
+        Some(tmp)
+    } else {
+        None
+    };
+    f.assigns = if has_debug && version::has_assigns(version) {
+        let len = read_count(r, opts.max_nested, "variable assignments")?;
+        let mut assigns = Vec::with_capacity(len);
+        for _ in 0..len {
+            assigns.push((RefString(r.read_varu()? as usize), r.read_vari()? as usize));
+        }
+        Some(assigns)
+    } else {
+        None
+    };
+    Ok(())
+}
 
-    fn read_constant_def(&mut self) -> Result<ConstantDef> {
-        let global = RefGlobal(self.read_varu()? as usize);
-        let nfields = self.read_varu()? as usize;
-        let mut fields = Vec::with_capacity(nfields);
-        for _ in 0..nfields {
-            fields.push(self.read_varu()? as usize);
+/// Go past the body of a function without keeping anything
+pub(crate) fn skip_function_body(
+    r: &mut impl Read,
+    nops: usize,
+    has_debug: bool,
+    version: u8,
+) -> Result<()> {
+    for _ in 0..nops {
+        Opcode::decode(r)?;
+    }
+    if has_debug {
+        read_debug_info(r, nops, |_, _| {})?;
+        if version::has_assigns(version) {
+            for _ in 0..r.read_varu()? {
+                r.read_varu()?;
+                r.read_vari()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decode the file and line of each instruction
+fn read_debug_info(
+    r: &mut impl Read,
+    nops: usize,
+    mut push: impl FnMut(usize, usize),
+) -> Result<()> {
+    // This is extracted from the hashlink source code, do not count on me to explain what it does
+    let mut currfile: i32 = -1;
+    let mut currline: i32 = 0;
+    let mut i = 0;
+    while i < nops {
+        let mut c = r.read_u8()? as i32;
+        if c & 1 != 0 {
+            c >>= 1;
+            currfile = (c << 8) | (r.read_u8()? as i32);
+        } else if c & 2 != 0 {
+            let delta = c >> 6;
+            let mut count = (c >> 2) & 15;
+            while count > 0 {
+                count -= 1;
+                push(currfile as usize, currline as usize);
+                i += 1;
+            }
+            currline += delta;
+        } else if c & 4 != 0 {
+            currline += c >> 3;
+            push(currfile as usize, currline as usize);
+            i += 1;
+        } else {
+            let b2 = r.read_u8()? as i32;
+            let b3 = r.read_u8()? as i32;
+            currline = (c >> 3) | (b2 << 5) | (b3 << 13);
+            push(currfile as usize, currline as usize);
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Reader keeping track of the position in the stream and of the element being read, to locate parse errors
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    pos: u64,
+    section: Section,
+    index: Option<usize>,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            pos: 0,
+            section: Section::Header,
+            index: None,
+        }
+    }
+
+    pub(crate) fn pos(&self) -> u64 {
+        self.pos
+    }
+
+    /// Start reading the element `index` of `section`
+    pub(crate) fn enter(&mut self, section: Section, index: Option<usize>) {
+        self.section = section;
+        self.index = index;
+    }
+
+    /// Locate a parse error at the current position, `base` is the offset of the start of the stream in the file
+    pub(crate) fn locate_from(&self, base: u64, e: Error) -> Error {
+        e.at(base + self.pos, self.section, self.index)
+    }
+
+    pub(crate) fn locate(&self, e: Error) -> Error {
+        self.locate_from(0, e)
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::builder::sample;
+    use crate::deser::ParseOptions;
+    use crate::{version, Bytecode, Error, ParseError, Section};
+
+    #[test]
+    fn load_from_stream() {
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+
+        // e.g. an archive entry decompressed in chunks
+        let (first, second) = data.split_at(data.len() / 3);
+        let streamed = Bytecode::load(first.chain(second)).unwrap();
+        let mut written = Vec::new();
+        streamed.serialize(&mut written).unwrap();
+        assert_eq!(written, data);
+        assert_eq!(
+            Bytecode::from_bytes(&data).unwrap().functions.len(),
+            streamed.functions.len()
+        );
+    }
+
+    #[test]
+    fn load_corrupted() {
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+        // Every byte replaced, loading fails or succeeds with warnings but never panics
+        for i in 0..data.len() {
+            for v in [0x00, 0x01, 0x3f, 0x7f, 0xff] {
+                let mut corrupted = data.clone();
+                corrupted[i] = v;
+                let _ = Bytecode::from_bytes(&corrupted);
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn load_async() {
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+        let code = futures_lite::future::block_on(Bytecode::load_async(&data[..])).unwrap();
+        assert_eq!(code.functions.len(), sample().functions.len());
+
+        let truncated = &data[..data.len() / 2];
+        let e = futures_lite::future::block_on(Bytecode::load_async(truncated)).unwrap_err();
+        assert!(e.is_invalid_bytecode());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn load_parallel() {
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+        let code = Bytecode::from_bytes_parallel(&data).unwrap();
+        let mut written = Vec::new();
+        code.serialize(&mut written).unwrap();
+        assert_eq!(written, data);
+        assert_eq!(code.virtual_names, sample().virtual_names);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let code = sample();
+        let json = serde_json::to_string(&code).unwrap();
+        let decoded: Bytecode = serde_json::from_str(&json).unwrap();
+        let (mut data, mut written) = (Vec::new(), Vec::new());
+        code.serialize(&mut data).unwrap();
+        decoded.serialize(&mut written).unwrap();
+        assert_eq!(written, data);
+        assert_eq!(decoded.fnames, code.fnames);
+    }
+
+    #[test]
+    fn older_versions() {
+        for version in [2, 3, 4] {
+            let mut code = sample();
+            code.version = version;
+            code.bytes = None;
+            if !version::has_constants(version) {
+                code.constants = None;
+                code.globals_initializers.clear();
+            }
+            if !version::has_assigns(version) {
+                code.functions.iter_mut().for_each(|f| f.assigns = None);
+            }
+            let mut data = Vec::new();
+            code.serialize(&mut data).unwrap();
+            let loaded = Bytecode::from_bytes(&data).unwrap();
+            assert_eq!(loaded.version(), version);
+            assert_eq!(loaded.constants.is_some(), version::has_constants(version));
+            let mut written = Vec::new();
+            loaded.serialize(&mut written).unwrap();
+            assert_eq!(written, data);
+            let patched = crate::patch::patch(&data, &loaded).unwrap();
+            assert_eq!(patched.data, data);
+        }
+
+        // The pools must match the version
+        let mut code = sample();
+        code.version = 4;
+        assert!(code.serialize(&mut Vec::new()).is_err());
+
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+        data[3] = 1;
+        assert!(matches!(
+            Bytecode::from_bytes(&data),
+            Err(Error::Parse {
+                kind: ParseError::UnsupportedVersion(1),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn limits() {
+        // A header announcing 0x0fffffff ints in a 12 bytes file
+        let data = b"HLB\x04\x00\xcf\xff\xff\xff\x00\x00\x00";
+        assert!(matches!(
+            Bytecode::from_bytes(data),
+            Err(Error::Parse {
+                kind: ParseError::LimitExceeded {
+                    what: "ints",
+                    count: 0x0fff_ffff,
+                    ..
+                },
+                ..
+            })
+        ));
+
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+        let strict = ParseOptions {
+            max_strings: 2,
+            ..ParseOptions::default()
+        };
+        assert!(matches!(
+            Bytecode::load_with(&data[..], &strict),
+            Err(Error::Parse {
+                kind: ParseError::LimitExceeded {
+                    what: "strings",
+                    ..
+                },
+                section: Section::Strings,
+                ..
+            })
+        ));
+        assert!(Bytecode::load_with(&data[..], &ParseOptions::unlimited()).is_ok());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+
+        let truncated = &data[..data.len() / 2];
+        match Bytecode::load(&mut &truncated[..]) {
+            Err(Error::Parse { offset, kind, .. }) => {
+                assert_eq!(kind, ParseError::UnexpectedEof);
+                assert_eq!(offset, truncated.len() as u64);
+            }
+            other => panic!("{other:?}"),
+        }
+
+        let mut bad = data.clone();
+        bad[0] = b'X';
+        match Bytecode::load(&mut bad.as_slice()) {
+            Err(e @ Error::Parse { offset: 3, .. }) => assert!(e.is_invalid_bytecode()),
+            other => panic!("{other:?}"),
+        }
+
+        // Every truncation is located, function bodies are located to their index
+        let last = sample().functions.len() - 1;
+        let mut in_last = false;
+        for len in 0..data.len() {
+            match Bytecode::from_bytes(&data[..len]) {
+                Err(Error::Parse { section, index, .. }) => {
+                    in_last |= section == Section::Functions && index == Some(last)
+                }
+                Ok(_) => {}
+                other => panic!("{other:?}"),
+            }
         }
-        Ok(ConstantDef { global, fields })
+        assert!(in_last);
     }
 }
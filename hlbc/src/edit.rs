@@ -0,0 +1,476 @@
+//! Modify a loaded bytecode while keeping it consistent.
+//!
+//! Every structure of [Bytecode] is public, but modifying them by hand means keeping the acceleration structures, the
+//! debug information and the jump offsets in sync. [Editor] does the bookkeeping : elements are appended to the pools
+//! so existing references stay valid, and replacing instructions fixes the jumps crossing the modified range.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc::prelude::*;
+//!
+//! let mut code = sample();
+//! let findex = code.functions[0].findex;
+//! let mut editor = code.edit();
+//! let hello = editor.string("hello");
+//! editor.insert_ops(findex, 0, vec![Opcode::Nop, Opcode::Nop]);
+//! assert_eq!(code.strings[hello.0], "hello");
+//! ```
+//!
+//! Strings can be changed in place with [Bytecode::replace_string], and [Bytecode::remove_unused_strings] compacts
+//! the pool after removing references, like when translating the text of a game.
+
+use std::ops::Range;
+
+use crate::builder::intern;
+use crate::opcodes::{JumpOffset, OperandMut};
+use crate::types::{
+    ConstantDef, Function, Native, RefFloat, RefFun, RefFunKnown, RefGlobal, RefInt, RefString,
+    RefType, Reg, TypeObj,
+};
+use crate::{analysis, gc, version, Bytecode, Opcode, Type};
+
+/// Consistent modifications of a [Bytecode], see [Bytecode::edit]
+pub struct Editor<'a> {
+    code: &'a mut Bytecode,
+}
+
+impl Bytecode {
+    /// Modify this bytecode, see [edit](crate::edit)
+    pub fn edit(&mut self) -> Editor<'_> {
+        Editor { code: self }
+    }
+
+    /// Add a string to the pool if it isn't there yet
+    pub fn add_string(&mut self, value: &str) -> RefString {
+        self.edit().string(value)
+    }
+
+    /// Change the value of a string everywhere it is used, returns the previous value. Function names and virtual
+    /// type names are updated.
+    ///
+    /// # Panics
+    ///
+    /// If `s` is not in the string pool.
+    pub fn replace_string(&mut self, s: RefString, value: &str) -> String {
+        let old = String::from(std::mem::replace(&mut self.strings[s.0], value.into()));
+        if let Some(&x) = self.fnames.get(&old) {
+            if self.functions[x].name == Some(s) {
+                self.fnames.remove(&old);
+                self.fnames.insert(value.to_owned(), x);
+            }
+        }
+        self.virtual_names = analysis::names::virtual_names(self);
+        old
+    }
+
+    /// Remove the strings nothing refers to. The following strings are moved down and every reference to them
+    /// (instructions, types, natives, variable names and constants) is updated. The null string at index 0 is kept,
+    /// debug file names have their own pool. Returns the number of removed strings.
+    pub fn remove_unused_strings(&mut self) -> usize {
+        let mut used = vec![false; self.strings.len()];
+        used[0] = true;
+        self.visit_strings(|s| used[s.0] = true);
+        let (remap, removed) = gc::compact(&mut self.strings, &used);
+        self.visit_strings(|s| s.0 = remap[s.0]);
+        removed.len()
+    }
+
+    /// Call `f` on every reference to the string pool
+    pub(crate) fn visit_strings(&mut self, mut f: impl FnMut(&mut RefString)) {
+        // Before bytecode v5, Bytes refers to the strings pool
+        let bytes = !version::has_bytes(self.version);
+        for fun in &mut self.functions {
+            fun.name.iter_mut().for_each(&mut f);
+            for (name, _) in fun.assigns.iter_mut().flatten() {
+                f(name);
+            }
+            for o in &mut fun.ops {
+                for operand in o.operands_mut() {
+                    match operand {
+                        OperandMut::String(s) => f(s),
+                        OperandMut::Bytes(b) if bytes => {
+                            let mut s = RefString(b.0);
+                            f(&mut s);
+                            b.0 = s.0;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        for n in &mut self.natives {
+            f(&mut n.name);
+            f(&mut n.lib);
+        }
+        // Bytes fields of constants are strings
+        let strings: Vec<Vec<bool>> = self
+            .constants
+            .iter()
+            .flatten()
+            .map(|c| {
+                let obj = self.globals[c.global.0].resolve_as_obj(&self.types);
+                obj.map(|obj| &obj.own_fields[..])
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|field| matches!(field.t.resolve(&self.types), Type::Bytes))
+                    .collect()
+            })
+            .collect();
+        for (c, strings) in self.constants.iter_mut().flatten().zip(strings) {
+            for (v, _) in c.fields.iter_mut().zip(strings).filter(|(_, s)| *s) {
+                let mut s = RefString(*v);
+                f(&mut s);
+                *v = s.0;
+            }
+        }
+        for t in &mut self.types {
+            match t {
+                Type::Obj(obj) | Type::Struct(obj) => {
+                    f(&mut obj.name);
+                    for field in obj.own_fields.iter_mut().chain(&mut obj.fields) {
+                        f(&mut field.name);
+                    }
+                    for p in &mut obj.protos {
+                        f(&mut p.name);
+                    }
+                }
+                Type::Virtual { fields } => {
+                    for field in fields {
+                        f(&mut field.name);
+                    }
+                }
+                Type::Abstract { name } => f(name),
+                Type::Enum {
+                    name, constructs, ..
+                } => {
+                    f(name);
+                    for c in constructs {
+                        f(&mut c.name);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Editor<'_> {
+    pub fn int(&mut self, value: i32) -> RefInt {
+        RefInt(intern(&mut self.code.ints, value))
+    }
+
+    pub fn float(&mut self, value: f64) -> RefFloat {
+        // Compare the bits so NaN can be interned too
+        match self
+            .code
+            .floats
+            .iter()
+            .position(|f| f.to_bits() == value.to_bits())
+        {
+            Some(i) => RefFloat(i),
+            None => {
+                self.code.floats.push(value);
+                RefFloat(self.code.floats.len() - 1)
+            }
+        }
+    }
+
+    pub fn string(&mut self, value: &str) -> RefString {
+        match self.code.strings.iter().position(|s| s == value) {
+            Some(i) => RefString(i),
+            None => {
+                self.code.strings.push(value.into());
+                RefString(self.code.strings.len() - 1)
+            }
+        }
+    }
+
+    /// Add a type if it doesn't exist yet. The fields of objects are flattened and their methods named, like when
+    /// loading the bytecode.
+    pub fn ty(&mut self, ty: Type) -> RefType {
+        if let Some(i) = self.code.types.iter().position(|t| *t == ty) {
+            return RefType(i);
+        }
+        let t = RefType(self.code.types.len());
+        let is_virtual = matches!(ty, Type::Virtual { .. });
+        self.code.types.push(ty);
+        if let Some(obj) = self.code.types[t.0].get_type_obj() {
+            let mut fields = obj
+                .super_
+                .and_then(|s| s.resolve_as_obj(&self.code.types))
+                .map(|s| s.fields.clone())
+                .unwrap_or_default();
+            fields.extend_from_slice(&obj.own_fields);
+            let TypeObj {
+                protos, bindings, ..
+            } = obj;
+            let mut names: Vec<(RefFun, RefString)> =
+                protos.iter().map(|p| (p.findex, p.name)).collect();
+            names.extend(bindings.iter().map(|(fi, &f)| (f, fields[fi.0].name)));
+            self.code.types[t.0].get_type_obj_mut().unwrap().fields = fields;
+            for (f, name) in names {
+                if let RefFunKnown::Fun(x) = self.code.findexes[f.0] {
+                    self.code.functions[x].name = Some(name);
+                    self.code.functions[x].parent = Some(t);
+                    self.code
+                        .fnames
+                        .insert(name.resolve(&self.code.strings).to_owned(), x);
+                }
+            }
+        }
+        if is_virtual {
+            self.code.virtual_names = analysis::names::virtual_names(self.code);
+        }
+        t
+    }
+
+    pub fn global(&mut self, ty: RefType) -> RefGlobal {
+        self.code.globals.push(ty);
+        RefGlobal(self.code.globals.len() - 1)
+    }
+
+    /// Initialize a global object, `fields` are indexes in the constant pool matching the type of each field
+    pub fn constant(&mut self, global: RefGlobal, fields: Vec<usize>) {
+        let constants = self.code.constants.get_or_insert_with(Vec::new);
+        let def = ConstantDef { global, fields };
+        match self.code.globals_initializers.get(&global) {
+            Some(&i) => constants[i] = def,
+            None => {
+                constants.push(def);
+                self.code
+                    .globals_initializers
+                    .insert(global, constants.len() - 1);
+            }
+        }
+    }
+
+    /// Declare a native function
+    pub fn native(&mut self, lib: &str, name: &str, ty: RefType) -> RefFun {
+        let findex = RefFun(self.code.findexes.len());
+        let native = Native {
+            name: self.string(name),
+            lib: self.string(lib),
+            t: ty,
+            findex,
+        };
+        self.code.natives.push(native);
+        self.code
+            .findexes
+            .push(RefFunKnown::Native(self.code.natives.len() - 1));
+        findex
+    }
+
+    /// Add a function, it can be called with the returned findex
+    pub fn function(&mut self, ty: RefType, regs: Vec<RefType>, ops: Vec<Opcode>) -> RefFun {
+        let findex = RefFun(self.code.findexes.len());
+        let debug = self.code.debug_files.is_some();
+        self.code.functions.push(Function {
+            name: None,
+            t: ty,
+            findex,
+            regs,
+            debug_info: debug.then(|| vec![(0, 0); ops.len()]),
+            assigns: (debug && version::has_assigns(self.code.version)).then(Vec::new),
+            ops,
+            parent: None,
+        });
+        self.code
+            .findexes
+            .push(RefFunKnown::Fun(self.code.functions.len() - 1));
+        findex
+    }
+
+    /// Add a register to a function
+    ///
+    /// # Panics
+    ///
+    /// If `f` is not a function of this bytecode.
+    pub fn reg(&mut self, f: RefFun, ty: RefType) -> Reg {
+        let fun = match self.code.findexes[f.0] {
+            RefFunKnown::Fun(x) => &mut self.code.functions[x],
+            RefFunKnown::Native(_) => panic!("{f:?} is a native function"),
+        };
+        fun.regs.push(ty);
+        Reg(fun.regs.len() as u32 - 1)
+    }
+
+    /// Replace the instructions in `range` of a function with `ops`.
+    ///
+    /// Jumps outside the range are fixed to still target the same instructions, jumps to the start of the range or
+    /// inside it now target the first new instruction. Offsets of jumps in `ops` are left untouched. The new
+    /// instructions get the debug information of the first replaced instruction.
+    ///
+    /// # Panics
+    ///
+    /// If `f` is not a function of this bytecode or if the range is out of bounds.
+    pub fn replace_ops(&mut self, f: RefFun, range: Range<usize>, ops: Vec<Opcode>) {
+        let fun = match self.code.findexes[f.0] {
+            RefFunKnown::Fun(x) => &mut self.code.functions[x],
+            RefFunKnown::Native(_) => panic!("{f:?} is a native function"),
+        };
+        let Range { start, end } = range;
+        assert!(start <= end && end <= fun.ops.len(), "invalid range");
+        let delta = ops.len() as isize - (end - start) as isize;
+        // Instructions after the range are shifted, instructions in the range are gone
+        let moved = |pos: usize| -> usize {
+            if pos < start {
+                pos
+            } else if pos < end {
+                start
+            } else {
+                (pos as isize + delta) as usize
+            }
+        };
+        let target = |pos: usize| -> usize {
+            if pos == start {
+                start
+            } else {
+                moved(pos)
+            }
+        };
+        let fix = |pos: usize, offset: &mut JumpOffset| {
+            let t = (pos as JumpOffset + *offset + 1) as usize;
+            *offset = target(t) as JumpOffset - moved(pos) as JumpOffset - 1;
+        };
+        for (pos, o) in fun.ops.iter_mut().enumerate() {
+            if (start..end).contains(&pos) {
+                continue;
+            }
+            if let Opcode::Switch { offsets, end, .. } = o {
+                offsets.iter_mut().for_each(|offset| fix(pos, offset));
+                fix(pos, end);
+            } else if let Some(offset) = o.jump_offset_mut() {
+                fix(pos, offset);
+            }
+        }
+
+        let old_len = fun.ops.len();
+        let count = ops.len();
+        fun.ops.splice(start..end, ops);
+        if let Some(debug_info) = &mut fun.debug_info {
+            let info = debug_info
+                .get(start)
+                .or_else(|| debug_info.last())
+                .copied()
+                .unwrap_or((0, 0));
+            debug_info.splice(start..end, std::iter::repeat(info).take(count));
+        }
+        if let Some(assigns) = &mut fun.assigns {
+            for (_, pos) in assigns {
+                // Arguments have a negative position
+                if *pos < old_len {
+                    *pos = moved(*pos);
+                }
+            }
+        }
+    }
+
+    /// Insert instructions before the instruction at `pos`, see [Self::replace_ops]
+    pub fn insert_ops(&mut self, f: RefFun, pos: usize, ops: Vec<Opcode>) {
+        self.replace_ops(f, pos..pos, ops);
+    }
+
+    /// Remove instructions, see [Self::replace_ops]
+    pub fn remove_ops(&mut self, f: RefFun, range: Range<usize>) {
+        self.replace_ops(f, range, Vec::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::types::{Reg, TypeFun};
+    use crate::{Bytecode, Opcode, RefType, Type};
+
+    fn encode(ops: &[Opcode]) -> Vec<u8> {
+        ops.iter().flat_map(|o| o.to_bytes().unwrap()).collect()
+    }
+
+    #[test]
+    fn edit() {
+        let original = sample();
+        let mut code = sample();
+        let mut editor = code.edit();
+        let hello = editor.string("hello");
+        let void_t = editor.ty(Type::Fun(TypeFun {
+            args: Vec::new(),
+            ret: RefType(0),
+        }));
+        let new = editor.function(void_t, vec![RefType(0)], vec![Opcode::Ret { ret: Reg(0) }]);
+        let call = Opcode::Call0 {
+            dst: Reg(0),
+            fun: new,
+        };
+        for f in &original.functions {
+            code.edit().insert_ops(f.findex, 0, vec![call.clone()]);
+        }
+
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let mut code = Bytecode::load(&mut data.as_slice()).unwrap();
+        assert_eq!(code.strings[hello.0], "hello");
+        assert!(new.resolve_as_fn(&code).is_some());
+        for (old, f) in original.functions.iter().zip(&code.functions) {
+            assert_eq!(f.ops[0].to_bytes().unwrap(), call.to_bytes().unwrap());
+            assert_eq!(f.debug_info.as_ref().unwrap().len(), f.ops.len());
+            for (pos, o) in old.ops.iter().enumerate() {
+                // Jumps to the first instruction now go to the call
+                let targets: Vec<usize> = o
+                    .jump_targets(pos)
+                    .into_iter()
+                    .map(|t| if t > 0 { t + 1 } else { 0 })
+                    .collect();
+                assert_eq!(f.ops[pos + 1].jump_targets(pos + 1), targets);
+            }
+        }
+
+        for f in &original.functions {
+            code.edit().remove_ops(f.findex, 0..1);
+            let ops = &f.findex.resolve_as_fn(&code).unwrap().ops;
+            assert_eq!(encode(ops), encode(&f.ops));
+        }
+    }
+
+    #[test]
+    fn strings() {
+        let mut code = sample();
+        let x = code
+            .functions
+            .iter()
+            .position(|f| f.name.is_some())
+            .unwrap();
+        let name = code.functions[x].name.unwrap();
+        let old = code.replace_string(name, "renamed");
+        assert_eq!(code.fnames.get("renamed"), Some(&x));
+        assert!(!code.fnames.contains_key(&old));
+        assert_eq!(code.functions[x].name(&code), Some("renamed"));
+
+        let listing = |code: &Bytecode| {
+            let mut out: Vec<String> = code
+                .types
+                .iter()
+                .map(|t| t.display(code).to_string())
+                .collect();
+            out.extend(code.functions.iter().map(|f| f.display(code).to_string()));
+            out
+        };
+        let before = listing(&code);
+        let len = code.strings.len();
+        code.strings.insert(1, "unused".into());
+        // Shift every reference past the inserted string
+        code.visit_strings(|s| {
+            if s.0 >= 1 {
+                s.0 += 1
+            }
+        });
+        let added = code.add_string("also unused");
+        assert_eq!(code.add_string("also unused"), added);
+        assert_eq!(code.remove_unused_strings(), 2);
+        assert_eq!(code.strings.len(), len);
+        assert_eq!(listing(&code), before);
+
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let loaded = Bytecode::load(data.as_slice()).unwrap();
+        assert_eq!(listing(&loaded), before);
+    }
+}
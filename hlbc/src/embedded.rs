@@ -0,0 +1,134 @@
+//! Bytecode embedded in an executable.
+//!
+//! Games are often shipped as a HashLink executable (PE on Windows, ELF on Linux) carrying the bytecode, instead of
+//! the VM next to a `.hl` file. The executable is scanned for the `HLB` magic followed by a known version, then each
+//! match whose header is consistent with the size of the data is parsed until one loads.
+//! [Bytecode::from_file] does this automatically for executables.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc::embedded;
+//!
+//! let mut exe = b"MZ\x90\x00 program code".to_vec();
+//! sample().serialize(&mut exe).unwrap();
+//! exe.extend_from_slice(b"more program code");
+//!
+//! let (offset, code) = embedded::extract(&exe).unwrap();
+//! assert_eq!(offset, 17);
+//! assert_eq!(code.functions.len(), sample().functions.len());
+//! ```
+
+use crate::deser::ReadHlExt;
+use crate::{version, Bytecode, ParseError, Result};
+
+/// Executable formats the bytecode can be embedded in
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    /// Windows executable
+    Pe,
+    /// Linux executable
+    Elf,
+}
+
+/// Format of an executable, `None` if the data isn't one (a plain bytecode file for example)
+pub fn format(data: &[u8]) -> Option<Format> {
+    if data.starts_with(b"MZ") {
+        Some(Format::Pe)
+    } else if data.starts_with(b"\x7fELF") {
+        Some(Format::Elf)
+    } else {
+        None
+    }
+}
+
+/// Offsets of the headers that could start a bytecode, in order
+pub fn candidates(data: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    data.windows(4)
+        .enumerate()
+        .filter(|(_, w)| &w[..3] == b"HLB" && (version::MIN..=version::MAX).contains(&w[3]))
+        .map(|(i, _)| i)
+        .filter(|&i| plausible(&data[i + 4..]))
+}
+
+/// The element counts of the header fit in the data. The magic can appear by chance in the code of the VM, parsing
+/// a random header could try to allocate gigabytes.
+fn plausible(mut data: &[u8]) -> bool {
+    let len = data.len() as u64;
+    let mut counts = [0u64; 7];
+    if data.read_varu().is_err() {
+        return false;
+    }
+    for c in &mut counts {
+        match data.read_varu() {
+            Ok(n) => *c = n as u64,
+            Err(_) => return false,
+        }
+    }
+    let [nints, nfloats, nstrings, ntypes, nglobals, nnatives, nfunctions] = counts;
+    // Every element takes at least a byte
+    nints * 4 + nfloats * 8 + nstrings + ntypes + nglobals + nnatives + nfunctions <= len
+}
+
+/// Find and load the bytecode embedded in an executable, returns its offset in the data
+pub fn extract(data: &[u8]) -> Result<(usize, Bytecode)> {
+    let mut error = None;
+    for offset in candidates(data) {
+        match Bytecode::from_bytes(&data[offset..]) {
+            Ok(code) => return Ok((offset, code)),
+            Err(e) => error = Some(e),
+        }
+    }
+    // The error of the last candidate is more useful than nothing if the bytecode is corrupted
+    Err(error.unwrap_or_else(|| ParseError::NoEmbeddedBytecode.into()))
+}
+
+/// Load a bytecode file, or the bytecode embedded in an executable
+pub fn load(data: &[u8]) -> Result<Bytecode> {
+    if format(data).is_some() {
+        extract(data).map(|(_, code)| code)
+    } else {
+        Bytecode::from_bytes(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::embedded::{extract, format, load, Format};
+    use crate::{Error, ParseError};
+
+    #[test]
+    fn extract_from_elf() {
+        let mut bytecode = Vec::new();
+        sample().serialize(&mut bytecode).unwrap();
+
+        let mut exe = b"\x7fELF\x02\x01\x01".to_vec();
+        // The magic in the code of the VM, with a header too big for the file
+        exe.extend_from_slice(b"HLB\x05\x00\xff\xff\xff\x0f\x00\x00");
+        // A header that fits but isn't a bytecode
+        exe.extend_from_slice(b"HLB\x04\x00\x01\x00\x00\x00\x00\x00\x00\x00");
+        let offset = exe.len();
+        exe.extend_from_slice(&bytecode);
+        exe.extend_from_slice(&[0; 64]);
+
+        assert_eq!(format(&exe), Some(Format::Elf));
+        let (found, code) = extract(&exe).unwrap();
+        assert_eq!(found, offset);
+        assert_eq!(code.functions.len(), sample().functions.len());
+        assert!(code.metadata.is_none());
+
+        // Plain files are loaded as usual
+        assert_eq!(format(&bytecode), None);
+        assert_eq!(
+            load(&bytecode).unwrap().functions.len(),
+            code.functions.len()
+        );
+
+        assert!(matches!(
+            load(b"MZ\x90\x00 no bytecode here"),
+            Err(Error::Parse {
+                kind: ParseError::NoEmbeddedBytecode,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,605 @@
+//! Extract a function to a standalone bytecode file.
+//!
+//! The extracted module contains the function, every function it calls (transitively) and the types, globals,
+//! natives and constants they use, so it still passes the VM verification. Methods of the extracted classes that are
+//! never called are replaced by stubs returning a default value. A new entrypoint calls the function with default
+//! arguments.
+//!
+//! This is useful to create small reproducers of VM or decompiler bugs.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::types::{
+    ConstantDef, EnumConstruct, FunPtr, Function, Native, ObjField, ObjProto, RefFun, RefGlobal,
+    RefString, RefType, Reg, TypeFun, TypeObj,
+};
+use crate::{version, Bytecode, Opcode, Result, Type};
+
+/// Elements to visit
+enum Item {
+    Fun(RefFun),
+    /// A function only needed for its signature
+    Stub(RefFun),
+    Type(RefType),
+    Global(RefGlobal),
+}
+
+/// Elements of the original bytecode to keep
+#[derive(Default)]
+struct Deps {
+    funs: BTreeSet<usize>,
+    stubs: BTreeSet<usize>,
+    types: BTreeSet<usize>,
+    globals: BTreeSet<usize>,
+    ints: BTreeSet<usize>,
+    floats: BTreeSet<usize>,
+    strings: BTreeSet<usize>,
+    bytes: BTreeSet<usize>,
+}
+
+/// Pool a constant field value refers to
+pub(crate) enum ConstPool {
+    Int,
+    Float,
+    String,
+    Type,
+    Global,
+    Raw,
+}
+
+pub(crate) fn const_pool(code: &Bytecode, t: RefType) -> ConstPool {
+    match t.resolve(&code.types) {
+        Type::I32 => ConstPool::Int,
+        Type::F64 => ConstPool::Float,
+        Type::Bytes => ConstPool::String,
+        Type::Type => ConstPool::Type,
+        Type::Bool => ConstPool::Raw,
+        _ => ConstPool::Global,
+    }
+}
+
+/// Fields of the object stored in a global, used by the constant initializing it
+fn const_fields(code: &Bytecode, g: RefGlobal) -> &[ObjField] {
+    code.globals[g.0]
+        .resolve_as_obj(&code.types)
+        .map(|obj| obj.fields.as_slice())
+        .unwrap_or_default()
+}
+
+impl Deps {
+    fn collect(code: &Bytecode, root: RefFun) -> Self {
+        let mut deps = Deps::default();
+        // Void and the null string must stay at index 0
+        deps.types.insert(0);
+        if !code.strings.is_empty() {
+            deps.strings.insert(0);
+        }
+        let mut todo = vec![Item::Fun(root)];
+        while let Some(item) = todo.pop() {
+            match item {
+                Item::Fun(f) => {
+                    if deps.funs.insert(f.0) {
+                        deps.visit_fun(code, f, &mut todo);
+                    }
+                }
+                Item::Stub(f) => match f.resolve(code) {
+                    // Natives are cheap to keep
+                    FunPtr::Native(_) => todo.push(Item::Fun(f)),
+                    FunPtr::Fun(fun) => {
+                        if deps.stubs.insert(f.0) {
+                            todo.push(Item::Type(fun.t));
+                        }
+                    }
+                },
+                Item::Type(t) => {
+                    if deps.types.insert(t.0) {
+                        deps.visit_type(code, t, &mut todo);
+                    }
+                }
+                Item::Global(g) => {
+                    if deps.globals.insert(g.0) {
+                        deps.visit_global(code, g, &mut todo);
+                    }
+                }
+            }
+        }
+        deps.stubs = deps.stubs.difference(&deps.funs).copied().collect();
+        deps
+    }
+
+    fn visit_fun(&mut self, code: &Bytecode, f: RefFun, todo: &mut Vec<Item>) {
+        let fun = match f.resolve(code) {
+            FunPtr::Native(n) => {
+                self.strings.insert(n.lib.0);
+                self.strings.insert(n.name.0);
+                todo.push(Item::Type(n.t));
+                return;
+            }
+            FunPtr::Fun(fun) => fun,
+        };
+        todo.push(Item::Type(fun.t));
+        todo.extend(fun.regs.iter().map(|&t| Item::Type(t)));
+        if let Some(assigns) = &fun.assigns {
+            self.strings.extend(assigns.iter().map(|(s, _)| s.0));
+        }
+        for o in &fun.ops {
+            match o {
+                Opcode::Int { ptr, .. } => {
+                    self.ints.insert(ptr.0);
+                }
+                Opcode::Float { ptr, .. } => {
+                    self.floats.insert(ptr.0);
+                }
+                Opcode::Bytes { ptr, .. } => {
+                    if version::has_bytes(code.version) {
+                        self.bytes.insert(ptr.0);
+                    } else {
+                        self.strings.insert(ptr.0);
+                    }
+                }
+                Opcode::String { ptr, .. } => {
+                    self.strings.insert(ptr.0);
+                }
+                Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                    self.strings.insert(field.0);
+                }
+                Opcode::Call0 { fun, .. }
+                | Opcode::Call1 { fun, .. }
+                | Opcode::Call2 { fun, .. }
+                | Opcode::Call3 { fun, .. }
+                | Opcode::Call4 { fun, .. }
+                | Opcode::CallN { fun, .. }
+                | Opcode::StaticClosure { fun, .. }
+                | Opcode::InstanceClosure { fun, .. } => todo.push(Item::Fun(*fun)),
+                Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                    todo.push(Item::Global(*global))
+                }
+                Opcode::Type { ty, .. } => todo.push(Item::Type(*ty)),
+                _ => {}
+            }
+        }
+    }
+
+    fn visit_type(&mut self, code: &Bytecode, t: RefType, todo: &mut Vec<Item>) {
+        match t.resolve(&code.types) {
+            Type::Fun(fun) | Type::Method(fun) => {
+                todo.extend(fun.args.iter().map(|&t| Item::Type(t)));
+                todo.push(Item::Type(fun.ret));
+            }
+            Type::Obj(obj) | Type::Struct(obj) => {
+                self.strings.insert(obj.name.0);
+                todo.extend(obj.super_.map(Item::Type));
+                if obj.global.0 > 0 {
+                    todo.push(Item::Global(RefGlobal(obj.global.0 - 1)));
+                }
+                for f in &obj.own_fields {
+                    self.strings.insert(f.name.0);
+                    todo.push(Item::Type(f.t));
+                }
+                for p in &obj.protos {
+                    self.strings.insert(p.name.0);
+                    todo.push(Item::Stub(p.findex));
+                }
+                todo.extend(obj.bindings.values().map(|&f| Item::Stub(f)));
+            }
+            Type::Ref(inner) | Type::Null(inner) | Type::Packed(inner) => {
+                todo.push(Item::Type(*inner))
+            }
+            Type::Virtual { fields } => {
+                for f in fields {
+                    self.strings.insert(f.name.0);
+                    todo.push(Item::Type(f.t));
+                }
+            }
+            Type::Abstract { name } => {
+                self.strings.insert(name.0);
+            }
+            Type::Enum {
+                name,
+                global,
+                constructs,
+            } => {
+                self.strings.insert(name.0);
+                if global.0 > 0 {
+                    todo.push(Item::Global(RefGlobal(global.0 - 1)));
+                }
+                for c in constructs {
+                    self.strings.insert(c.name.0);
+                    todo.extend(c.params.iter().map(|&t| Item::Type(t)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_global(&mut self, code: &Bytecode, g: RefGlobal, todo: &mut Vec<Item>) {
+        todo.push(Item::Type(code.globals[g.0]));
+        let c = match code.globals_initializers.get(&g) {
+            Some(&i) => &code.constants.as_ref().unwrap()[i],
+            None => return,
+        };
+        for (f, &v) in const_fields(code, g).iter().zip(&c.fields) {
+            match const_pool(code, f.t) {
+                ConstPool::Int => {
+                    self.ints.insert(v);
+                }
+                ConstPool::Float => {
+                    self.floats.insert(v);
+                }
+                ConstPool::String => {
+                    self.strings.insert(v);
+                }
+                ConstPool::Type => todo.push(Item::Type(RefType(v))),
+                ConstPool::Global => todo.push(Item::Global(RefGlobal(v))),
+                ConstPool::Raw => {}
+            }
+        }
+    }
+}
+
+/// Maps indexes of the original bytecode to indexes in the extracted one
+struct Remap {
+    funs: HashMap<usize, usize>,
+    types: HashMap<usize, usize>,
+    globals: HashMap<usize, usize>,
+    ints: HashMap<usize, usize>,
+    floats: HashMap<usize, usize>,
+    strings: HashMap<usize, usize>,
+    bytes: HashMap<usize, usize>,
+}
+
+fn index(set: &BTreeSet<usize>) -> HashMap<usize, usize> {
+    set.iter()
+        .enumerate()
+        .map(|(new, &old)| (old, new))
+        .collect()
+}
+
+impl Remap {
+    fn ty(&self, t: RefType) -> RefType {
+        RefType(self.types[&t.0])
+    }
+
+    fn fun(&self, f: RefFun) -> RefFun {
+        RefFun(self.funs[&f.0])
+    }
+
+    /// For references where 0 means none
+    fn global1(&self, g: RefGlobal) -> RefGlobal {
+        if g.0 > 0 {
+            RefGlobal(self.globals[&(g.0 - 1)] + 1)
+        } else {
+            g
+        }
+    }
+
+    fn field(&self, f: &ObjField) -> ObjField {
+        ObjField {
+            name: RefString(self.strings[&f.name.0]),
+            t: self.ty(f.t),
+        }
+    }
+
+    fn type_fun(&self, fun: &TypeFun) -> TypeFun {
+        TypeFun {
+            args: fun.args.iter().map(|&t| self.ty(t)).collect(),
+            ret: self.ty(fun.ret),
+        }
+    }
+
+    fn type_obj(&self, obj: &TypeObj) -> TypeObj {
+        TypeObj {
+            name: RefString(self.strings[&obj.name.0]),
+            super_: obj.super_.map(|t| self.ty(t)),
+            global: self.global1(obj.global),
+            own_fields: obj.own_fields.iter().map(|f| self.field(f)).collect(),
+            protos: obj
+                .protos
+                .iter()
+                .map(|p| ObjProto {
+                    name: RefString(self.strings[&p.name.0]),
+                    findex: self.fun(p.findex),
+                    pindex: p.pindex,
+                })
+                .collect(),
+            bindings: obj
+                .bindings
+                .iter()
+                .map(|(&field, &f)| (field, self.fun(f)))
+                .collect(),
+            fields: Vec::new(),
+        }
+    }
+
+    fn type_(&self, t: &Type) -> Type {
+        match t {
+            Type::Fun(fun) => Type::Fun(self.type_fun(fun)),
+            Type::Method(fun) => Type::Method(self.type_fun(fun)),
+            Type::Obj(obj) => Type::Obj(self.type_obj(obj)),
+            Type::Struct(obj) => Type::Struct(self.type_obj(obj)),
+            Type::Ref(inner) => Type::Ref(self.ty(*inner)),
+            Type::Null(inner) => Type::Null(self.ty(*inner)),
+            Type::Packed(inner) => Type::Packed(self.ty(*inner)),
+            Type::Virtual { fields } => Type::Virtual {
+                fields: fields.iter().map(|f| self.field(f)).collect(),
+            },
+            Type::Abstract { name } => Type::Abstract {
+                name: RefString(self.strings[&name.0]),
+            },
+            Type::Enum {
+                name,
+                global,
+                constructs,
+            } => Type::Enum {
+                name: RefString(self.strings[&name.0]),
+                global: self.global1(*global),
+                constructs: constructs
+                    .iter()
+                    .map(|c| EnumConstruct {
+                        name: RefString(self.strings[&c.name.0]),
+                        params: c.params.iter().map(|&t| self.ty(t)).collect(),
+                    })
+                    .collect(),
+            },
+            other => other.clone(),
+        }
+    }
+
+    fn op(&self, op: &mut Opcode, version: u8) {
+        match op {
+            Opcode::Int { ptr, .. } => ptr.0 = self.ints[&ptr.0],
+            Opcode::Float { ptr, .. } => ptr.0 = self.floats[&ptr.0],
+            Opcode::Bytes { ptr, .. } => {
+                ptr.0 = if version::has_bytes(version) {
+                    self.bytes[&ptr.0]
+                } else {
+                    self.strings[&ptr.0]
+                }
+            }
+            Opcode::String { ptr, .. } => ptr.0 = self.strings[&ptr.0],
+            Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                field.0 = self.strings[&field.0]
+            }
+            Opcode::Call0 { fun, .. }
+            | Opcode::Call1 { fun, .. }
+            | Opcode::Call2 { fun, .. }
+            | Opcode::Call3 { fun, .. }
+            | Opcode::Call4 { fun, .. }
+            | Opcode::CallN { fun, .. }
+            | Opcode::StaticClosure { fun, .. }
+            | Opcode::InstanceClosure { fun, .. } => *fun = self.fun(*fun),
+            Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                global.0 = self.globals[&global.0]
+            }
+            Opcode::Type { ty, .. } => *ty = self.ty(*ty),
+            _ => {}
+        }
+    }
+}
+
+/// A function with the given type returning a default value
+fn stub(findex: RefFun, t: RefType, fun: &TypeFun, debug: bool) -> Function {
+    let mut regs = fun.args.clone();
+    regs.push(fun.ret);
+    let ops = vec![Opcode::Ret {
+        ret: Reg(fun.args.len() as u32),
+    }];
+    Function {
+        name: None,
+        t,
+        findex,
+        regs,
+        debug_info: debug.then(|| vec![(0, 0); ops.len()]),
+        assigns: debug.then(Vec::new),
+        ops,
+        parent: None,
+    }
+}
+
+/// Extract the function `root` and its dependencies to a standalone bytecode, whose entrypoint calls `root`.
+pub fn extract(code: &Bytecode, root: RefFun) -> Result<Bytecode> {
+    root.try_resolve(code)?;
+    let deps = Deps::collect(code, root);
+    let all: BTreeSet<usize> = deps.funs.union(&deps.stubs).copied().collect();
+    let remap = Remap {
+        funs: index(&all),
+        types: index(&deps.types),
+        globals: index(&deps.globals),
+        ints: index(&deps.ints),
+        floats: index(&deps.floats),
+        strings: index(&deps.strings),
+        bytes: index(&deps.bytes),
+    };
+    let debug = code.debug_files.is_some();
+
+    let mut types: Vec<Type> = deps
+        .types
+        .iter()
+        .map(|&t| remap.type_(&code.types[t]))
+        .collect();
+    let main_ty = Type::Fun(TypeFun {
+        args: Vec::new(),
+        ret: RefType(0),
+    });
+    let main_t = match types.iter().position(|t| *t == main_ty) {
+        Some(i) => RefType(i),
+        None => {
+            types.push(main_ty);
+            RefType(types.len() - 1)
+        }
+    };
+
+    let mut natives = Vec::new();
+    let mut functions = Vec::new();
+    for &f in &all {
+        let findex = remap.fun(RefFun(f));
+        match RefFun(f).resolve(code) {
+            FunPtr::Native(n) => natives.push(Native {
+                name: RefString(remap.strings[&n.name.0]),
+                lib: RefString(remap.strings[&n.lib.0]),
+                t: remap.ty(n.t),
+                findex,
+            }),
+            FunPtr::Fun(fun) if deps.stubs.contains(&f) => {
+                let t = remap.ty(fun.t);
+                if let Type::Fun(sig) | Type::Method(sig) = &types[t.0] {
+                    functions.push(stub(findex, t, sig, debug));
+                }
+            }
+            FunPtr::Fun(fun) => {
+                let mut fun = fun.clone();
+                fun.name = None;
+                fun.parent = None;
+                fun.findex = findex;
+                fun.t = remap.ty(fun.t);
+                for r in &mut fun.regs {
+                    *r = remap.ty(*r);
+                }
+                for o in &mut fun.ops {
+                    remap.op(o, code.version);
+                }
+                if let Some(assigns) = &mut fun.assigns {
+                    for (s, _) in assigns {
+                        s.0 = remap.strings[&s.0];
+                    }
+                }
+                functions.push(fun);
+            }
+        }
+    }
+
+    // Entrypoint calling the root function with default values
+    let root_t = match root.resolve(code) {
+        FunPtr::Fun(fun) => fun.t,
+        FunPtr::Native(n) => n.t,
+    };
+    let args = match &types[remap.ty(root_t).0] {
+        Type::Fun(sig) | Type::Method(sig) => sig.clone(),
+        _ => unreachable!("function without a function type"),
+    };
+    let nargs = args.args.len() as u32;
+    let mut main = stub(RefFun(all.len()), main_t, &args, debug);
+    main.regs.push(RefType(0));
+    main.ops = vec![
+        Opcode::CallN {
+            dst: Reg(nargs),
+            fun: remap.fun(root),
+            args: (0..nargs).map(Reg).collect(),
+        },
+        Opcode::Ret {
+            ret: Reg(nargs + 1),
+        },
+    ];
+    main.debug_info = debug.then(|| vec![(0, 0); main.ops.len()]);
+    let entrypoint = main.findex;
+    functions.push(main);
+
+    let bytes = code.bytes.as_ref().map(|(data, pos)| {
+        let mut new_data = Vec::new();
+        let mut new_pos = Vec::new();
+        for &b in &deps.bytes {
+            let start = pos[b];
+            let end = pos
+                .iter()
+                .copied()
+                .filter(|&p| p > start)
+                .min()
+                .unwrap_or(data.len());
+            new_pos.push(new_data.len());
+            new_data.extend_from_slice(&data[start..end]);
+        }
+        (new_data, new_pos)
+    });
+
+    let constants = code.constants.as_ref().map(|constants| {
+        constants
+            .iter()
+            .filter(|c| deps.globals.contains(&c.global.0))
+            .map(|c| ConstantDef {
+                global: RefGlobal(remap.globals[&c.global.0]),
+                fields: const_fields(code, c.global)
+                    .iter()
+                    .zip(&c.fields)
+                    .map(|(f, &v)| match const_pool(code, f.t) {
+                        ConstPool::Int => remap.ints[&v],
+                        ConstPool::Float => remap.floats[&v],
+                        ConstPool::String => remap.strings[&v],
+                        ConstPool::Type => remap.types[&v],
+                        ConstPool::Global => remap.globals[&v],
+                        ConstPool::Raw => v,
+                    })
+                    .collect(),
+            })
+            .collect()
+    });
+
+    let extracted = Bytecode {
+        version: code.version,
+        entrypoint,
+        ints: deps.ints.iter().map(|&i| code.ints[i]).collect(),
+        floats: deps.floats.iter().map(|&i| code.floats[i]).collect(),
+        strings: deps
+            .strings
+            .iter()
+            .map(|&i| code.strings[i].clone())
+            .collect(),
+        bytes,
+        debug_files: code.debug_files.clone(),
+        types,
+        globals: deps
+            .globals
+            .iter()
+            .map(|&g| remap.ty(code.globals[g]))
+            .collect(),
+        natives,
+        functions,
+        constants,
+        metadata: None,
+        warnings: Vec::new(),
+        findexes: Vec::new(),
+        fnames: HashMap::new(),
+        globals_initializers: HashMap::new(),
+        virtual_names: HashMap::new(),
+        global_offsets: Vec::new(),
+        source: None,
+    };
+    let mut data = Vec::new();
+    extracted.serialize(&mut data)?;
+    Bytecode::load(&mut data.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::extract::extract;
+    use crate::types::FunPtr;
+
+    #[test]
+    fn extract_sample() {
+        let code = sample();
+        for f in &code.functions {
+            let extracted = extract(&code, f.findex).unwrap();
+            assert!(extracted.functions.len() <= code.functions.len() + 1);
+            assert!(extracted.strings.len() <= code.strings.len());
+            let main = match extracted.entrypoint.resolve(&extracted) {
+                FunPtr::Fun(main) => main,
+                FunPtr::Native(_) => panic!("native entrypoint"),
+            };
+            let (_, _, root) = main.find_fun_refs().next().unwrap();
+            let root = root.resolve_as_fn(&extracted).unwrap();
+            assert_eq!(root.ops.len(), f.ops.len());
+            for (a, b) in root.find_fun_refs().zip(f.find_fun_refs()) {
+                assert_eq!(a.2.name(&extracted), b.2.name(&code));
+            }
+
+            let mut data = Vec::new();
+            extracted.serialize(&mut data).unwrap();
+            let mut again = Vec::new();
+            crate::Bytecode::load(&mut data.as_slice())
+                .unwrap()
+                .serialize(&mut again)
+                .unwrap();
+            assert_eq!(data, again);
+        }
+    }
+}
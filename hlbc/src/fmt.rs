@@ -1,5 +1,7 @@
 use std::fmt::{Display, Formatter, Result};
 
+use crate::analysis::annotations::Annotations;
+use crate::constants::ConstValue;
 use crate::opcodes::Opcode;
 use crate::types::{
     FunPtr, Function, Native, RefEnumConstruct, RefField, RefFloat, RefInt, RefString, RefType,
@@ -7,6 +9,35 @@ use crate::types::{
 };
 use crate::{Bytecode, RefFun};
 
+/// Context of the display of functions : the bytecode, with names given to some functions without modifying it (e.g.
+/// recognized by an analysis). The display methods of functions take any context, a [Bytecode] shows the debug names.
+pub trait FunctionNames {
+    fn bytecode(&self) -> &Bytecode;
+
+    /// Name shown instead of the debug name of a function, if any
+    fn function_name(&self, findex: RefFun) -> Option<&str>;
+}
+
+impl FunctionNames for Bytecode {
+    fn bytecode(&self) -> &Bytecode {
+        self
+    }
+
+    fn function_name(&self, _findex: RefFun) -> Option<&str> {
+        None
+    }
+}
+
+impl<T: FunctionNames + ?Sized> FunctionNames for &T {
+    fn bytecode(&self) -> &Bytecode {
+        (**self).bytecode()
+    }
+
+    fn function_name(&self, findex: RefFun) -> Option<&str> {
+        (**self).function_name(findex)
+    }
+}
+
 impl Display for Reg {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(f, "reg{}", self.0)
@@ -33,18 +64,47 @@ impl RefString {
 
 impl RefType {
     pub fn display(&self, ctx: &Bytecode) -> String {
-        self.resolve(&ctx.types).display(ctx)
+        self.display_rec(ctx, Vec::new())
     }
 
     pub fn display_id(&self, ctx: &Bytecode) -> String {
-        format!("{}@{}", self.resolve(&ctx.types).display(ctx), self.0)
+        format!("{}@{}", self.display(ctx), self.0)
     }
 
     fn display_rec(&self, ctx: &Bytecode, parents: Vec<*const Type>) -> String {
+        if let Some(name) = ctx.virtual_name(*self) {
+            return name.to_string();
+        }
         self.resolve(&ctx.types).display_rec(ctx, parents)
     }
 }
 
+impl ConstValue {
+    pub fn display(&self, ctx: &Bytecode) -> String {
+        match self {
+            ConstValue::Int(v) => v.to_string(),
+            ConstValue::Float(v) => v.to_string(),
+            ConstValue::Bool(v) => v.to_string(),
+            ConstValue::Bytes(s) => format!("bytes {:?}", s.resolve(&ctx.strings)),
+            ConstValue::Type(t) => t.display_id(ctx),
+            ConstValue::String(s) => format!("{:?}", s.resolve(&ctx.strings)),
+            ConstValue::Obj { ty, fields } => {
+                let names = ty
+                    .resolve_as_obj(&ctx.types)
+                    .map(|obj| obj.own_fields.as_slice())
+                    .unwrap_or_default();
+                let fields: Vec<String> = names
+                    .iter()
+                    .zip(fields)
+                    .map(|(f, v)| format!("{}: {}", f.name.display(ctx), v.display(ctx)))
+                    .collect();
+                format!("{} {{ {} }}", ty.display(ctx), fields.join(", "))
+            }
+            ConstValue::Global(g) => format!("global@{}", g.0),
+        }
+    }
+}
+
 impl RefField {
     pub fn display_obj(&self, parent: &Type, ctx: &Bytecode) -> impl Display {
         if let Some(obj) = parent.get_type_obj() {
@@ -69,10 +129,10 @@ impl RefEnumConstruct {
                 if name.0 != 0 {
                     name.display(ctx)
                 } else {
-                    "_".to_string()
+                    format!("_{}", self.0)
                 }
             }
-            _ => "_".to_string(),
+            _ => format!("_{}", self.0),
         }
     }
 }
@@ -115,7 +175,7 @@ impl Type {
             Type::Dyn => "dynamic".to_string(),
             Type::Fun(fun) => display_type_fun(fun, ctx, &parents),
             Type::Obj(TypeObj { name, .. }) => name.display(ctx),
-            Type::Array => "array".to_string(),
+            Type::Array => "hl.NativeArray".to_string(),
             Type::Type => "type".to_string(),
             Type::Ref(reftype) => {
                 format!("ref<{}>", reftype.display_rec(ctx, parents.clone()))
@@ -168,32 +228,32 @@ impl Type {
 }
 
 impl RefFun {
-    pub fn display_header<'a>(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
-        fmtools::fmt!({ self.resolve(ctx).display_header(ctx) })
+    pub fn display_header<'a, C: FunctionNames>(&'a self, ctx: &'a C) -> impl Display + 'a {
+        fmtools::fmt!({ self.resolve(ctx.bytecode()).display_header(ctx) })
     }
 
     /// Display something like `{name}@{findex}` for functions and `{lib}/{name}@{findex}` for natives.
-    pub fn display_id<'a>(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
-        fmtools::fmt!({ self.resolve(ctx).display_id(ctx) })
+    pub fn display_id<'a, C: FunctionNames>(&'a self, ctx: &'a C) -> impl Display + 'a {
+        fmtools::fmt!({ self.resolve(ctx.bytecode()).display_id(ctx) })
     }
 }
 
 impl<'a> FunPtr<'a> {
-    pub fn display_header(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
+    pub fn display_header<C: FunctionNames>(&'a self, ctx: &'a C) -> impl Display + 'a {
         fmtools::fmt! { move
             match self {
                 FunPtr::Fun(fun) => {{fun.display_header(ctx)}},
-                FunPtr::Native(n) => {{n.display_header(ctx)}},
+                FunPtr::Native(n) => {{n.display_header(ctx.bytecode())}},
             }
         }
     }
 
     /// Display something like `{name}@{findex}` for functions and `{lib}/{name}@{findex}` for natives.
-    pub fn display_id(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
+    pub fn display_id<C: FunctionNames>(&'a self, ctx: &'a C) -> impl Display + 'a {
         fmtools::fmt! { move
             match self {
                 FunPtr::Fun(fun) => {{fun.display_id(ctx)}},
-                FunPtr::Native(n) => {{n.display_id(ctx)}},
+                FunPtr::Native(n) => {{n.display_id(ctx.bytecode())}},
             }
         }
     }
@@ -221,13 +281,14 @@ impl Native {
 
 impl Opcode {
     /// This display is an enhanced assembly view, with nice printing and added information from the context
-    pub fn display(
+    pub fn display<C: FunctionNames>(
         &self,
-        ctx: &Bytecode,
+        names: &C,
         parent: &Function,
         pos: i32,
         align: usize,
     ) -> impl Display {
+        let ctx = names.bytecode();
         macro_rules! op {
             ($($arg:tt)*) => {
                 format!("{:<align$} {}", self.name(), format_args!($($arg)*))
@@ -239,7 +300,7 @@ impl Opcode {
             Opcode::Int { dst, ptr } => op!("{dst} = {}", ptr.display(ctx)),
             Opcode::Float { dst, ptr } => op!("{dst} = {}", ptr.display(ctx)),
             Opcode::Bool { dst, value } => op!("{dst} = {}", value.0),
-            Opcode::String { dst, ptr } => op!("{dst} = \"{}\"", ptr.display(ctx)),
+            Opcode::String { dst, ptr } => op!("{dst} = {:?}", ptr.resolve(&ctx.strings)),
             Opcode::Null { dst } => op!("{dst} = null"),
             Opcode::Add { dst, a, b } => op!("{dst} = {a} + {b}"),
             Opcode::Sub { dst, a, b } => op!("{dst} = {a} - {b}"),
@@ -258,21 +319,21 @@ impl Opcode {
             Opcode::Not { dst, src } => op!("{dst} = !{src}"),
             Opcode::Incr { dst } => op!("{dst}++"),
             Opcode::Decr { dst } => op!("{dst}--"),
-            Opcode::Call0 { dst, fun } => op!("{dst} = {}()", fun.display_id(ctx)),
-            Opcode::Call1 { dst, fun, arg0 } => op!("{dst} = {}({arg0})", fun.display_id(ctx)),
+            Opcode::Call0 { dst, fun } => op!("{dst} = {}()", fun.display_id(names)),
+            Opcode::Call1 { dst, fun, arg0 } => op!("{dst} = {}({arg0})", fun.display_id(names)),
             Opcode::Call2 {
                 dst,
                 fun,
                 arg0,
                 arg1,
-            } => op!("{dst} = {}({arg0}, {arg1})", fun.display_id(ctx)),
+            } => op!("{dst} = {}({arg0}, {arg1})", fun.display_id(names)),
             Opcode::Call3 {
                 dst,
                 fun,
                 arg0,
                 arg1,
                 arg2,
-            } => op!("{dst} = {}({arg0}, {arg1}, {arg2})", fun.display_id(ctx)),
+            } => op!("{dst} = {}({arg0}, {arg1}, {arg2})", fun.display_id(names)),
             Opcode::Call4 {
                 dst,
                 fun,
@@ -282,11 +343,11 @@ impl Opcode {
                 arg3,
             } => op!(
                 "{dst} = {}({arg0}, {arg1},{arg2}, {arg3})",
-                fun.display_id(ctx)
+                fun.display_id(names)
             ),
             Opcode::CallN { dst, fun, args } => {
                 let args: Vec<String> = args.iter().map(|r| format!("{}", r)).collect();
-                op!("{dst} = {}({})", fun.display_id(ctx), args.join(", "))
+                op!("{dst} = {}({})", fun.display_id(names), args.join(", "))
             }
             Opcode::CallMethod { dst, field, args } => {
                 let mut args = args.iter();
@@ -312,10 +373,10 @@ impl Opcode {
                 op!("{dst} = {fun}({})", args.join(", "))
             }
             Opcode::StaticClosure { dst, fun } => {
-                op!("{dst} = {}", fun.display_header(ctx))
+                op!("{dst} = {}", fun.display_header(names))
             }
             Opcode::InstanceClosure { dst, fun, obj } => {
-                op!("{dst} = {obj}.{}", fun.display_header(ctx))
+                op!("{dst} = {obj}.{}", fun.display_header(names))
             }
             Opcode::GetGlobal { dst, global } => {
                 op!("{dst} = global@{}", global.0)
@@ -398,6 +459,17 @@ impl Opcode {
             Opcode::JAlways { offset } => {
                 op!("jump to {}", pos + offset + 1)
             }
+            Opcode::Switch { reg, offsets, end } => {
+                let targets: Vec<String> = offsets
+                    .iter()
+                    .map(|offset| (pos + offset + 1).to_string())
+                    .collect();
+                op!(
+                    "{reg} jump to [{}] else {}",
+                    targets.join(", "),
+                    pos + end + 1
+                )
+            }
             Opcode::ToDyn { dst, src } => {
                 op!("{dst} = cast {src}")
             }
@@ -482,7 +554,7 @@ impl Opcode {
             } => {
                 op!(
                     "{dst} = ({value} as {}).{}",
-                    construct.display(parent.regs[dst.0 as usize], ctx),
+                    construct.display(parent.regs[value.0 as usize], ctx),
                     field.0
                 )
             }
@@ -495,18 +567,44 @@ impl Opcode {
 }
 
 impl Function {
-    pub fn display_header<'a>(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
-        fmtools::fmt!("fn "{self.display_id(ctx)}" "{self.t.display_id(ctx)})
+    pub fn display_header<'a, C: FunctionNames>(&'a self, ctx: &'a C) -> impl Display + 'a {
+        fmtools::fmt!("fn "{self.display_id(ctx)}" "{self.t.display_id(ctx.bytecode())})
     }
 
-    /// Display something like `{name}@{findex}`
-    pub fn display_id<'a>(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
-        fmtools::fmt!({self.name_default(ctx)}"@"{self.findex.0})
+    /// Display something like `{name}@{findex}`, the name given by the context comes first
+    pub fn display_id<'a, C: FunctionNames>(&'a self, ctx: &'a C) -> impl Display + 'a {
+        let name = ctx
+            .function_name(self.findex)
+            .unwrap_or_else(|| self.name_default(ctx.bytecode()));
+        fmtools::fmt!(move {name}"@"{self.findex.0})
     }
 
-    pub fn display<'a>(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
-        fmtools::fmt! {
-            {self.display_header(ctx)}" ("{self.regs.len()}" regs, "{self.ops.len()}" ops)\n"
+    pub fn display<'a, C: FunctionNames>(&'a self, ctx: &'a C) -> impl Display + 'a {
+        self.display_inner(ctx, None)
+    }
+
+    /// Display the function with the notes of each instruction below it
+    pub fn display_annotated<'a, C: FunctionNames>(
+        &'a self,
+        ctx: &'a C,
+        annotations: &'a Annotations,
+    ) -> impl Display + 'a {
+        self.display_inner(ctx, Some(annotations))
+    }
+
+    fn display_inner<'a, C: FunctionNames>(
+        &'a self,
+        names: &'a C,
+        annotations: Option<&'a Annotations>,
+    ) -> impl Display + 'a {
+        let ctx = names.bytecode();
+        let notes = move |i: usize| {
+            annotations
+                .map(|a| a.get(self.findex, i))
+                .unwrap_or_default()
+        };
+        fmtools::fmt! { move
+            {self.display_header(names)}" ("{self.regs.len()}" regs, "{self.ops.len()}" ops)\n"
             for (i, reg) in self.regs.iter().enumerate() {
                 "    reg"{i:<2}" "{reg.display_id(ctx)}"\n"
             }
@@ -516,13 +614,19 @@ impl Function {
                     .enumerate()
                     .zip(debug.iter())
                 {
-                    {ctx.debug_files.as_ref().unwrap()[*file as usize]:>12}":"{line:<3}" "{i:>3}": "{o.display(ctx, self, i as i32, 11)}"\n"
+                    {ctx.debug_files.as_ref().unwrap()[*file as usize]:>12}":"{line:<3}" "{i:>3}": "{o.display(names, self, i as i32, 11)}"\n"
+                    for n in notes(i) {
+                        {"":>22}"; "{n}"\n"
+                    }
                 }
             } else {
                 for (i, o) in self.ops
                     .iter()
                     .enumerate() {
-                    {i:>3}": "{o.display(ctx, self, i as i32, 11)}"\n"
+                    {i:>3}": "{o.display(names, self, i as i32, 11)}"\n"
+                    for n in notes(i) {
+                        "     ; "{n}"\n"
+                    }
                 }
             }
         }
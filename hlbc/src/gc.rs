@@ -0,0 +1,336 @@
+//! Removal of the unused elements of the constant pools.
+//!
+//! Patching a bytecode leaves strings, ints, floats and types nothing refers to anymore. [Bytecode::gc_pools] finds
+//! what is reachable from the functions, natives, globals and constants, removes the rest and moves the following
+//! elements down, updating every reference.
+//! ```
+//! use hlbc::builder::sample;
+//!
+//! let mut code = sample();
+//! code.add_string("not used anywhere");
+//! let report = code.gc_pools();
+//! assert!(report.strings.iter().any(|(_, s)| s == "not used anywhere"));
+//! ```
+//!
+//! Classes and enums with a global are kept even when unused, the VM registers them for reflection
+//! (`Type.resolveClass`). The bytes pool of version 5 is left as is.
+
+use std::fmt;
+use std::fmt::Display;
+
+use crate::opcodes::OperandMut;
+use crate::types::{RefType, Type};
+use crate::{analysis, Bytecode};
+
+/// Elements removed by [Bytecode::gc_pools] with their former index
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcReport {
+    pub strings: Vec<(usize, String)>,
+    pub ints: Vec<(usize, i32)>,
+    pub floats: Vec<(usize, f64)>,
+    /// Types as displayed before their removal
+    pub types: Vec<(usize, String)>,
+}
+
+impl GcReport {
+    /// Number of removed elements
+    pub fn len(&self) -> usize {
+        self.strings.len() + self.ints.len() + self.floats.len() + self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Display for GcReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Removed {} strings, {} ints, {} floats and {} types",
+            self.strings.len(),
+            self.ints.len(),
+            self.floats.len(),
+            self.types.len()
+        )?;
+        for (i, t) in &self.types {
+            writeln!(f, "type@{i} {t}")?;
+        }
+        for (i, v) in &self.ints {
+            writeln!(f, "int@{i} {v}")?;
+        }
+        for (i, v) in &self.floats {
+            writeln!(f, "float@{i} {v}")?;
+        }
+        for (i, s) in &self.strings {
+            writeln!(f, "string@{i} {s:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Pools the fields of a constant refer to
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Pool {
+    Int,
+    Float,
+    Type,
+}
+
+/// Keep the elements of `pool` marked as used, returns the new index of each element and the removed ones
+pub(crate) fn compact<T>(pool: &mut Vec<T>, used: &[bool]) -> (Vec<usize>, Vec<(usize, T)>) {
+    let mut remap = Vec::with_capacity(used.len());
+    let mut kept = Vec::with_capacity(pool.len());
+    let mut removed = Vec::new();
+    for (i, v) in pool.drain(..).enumerate() {
+        remap.push(kept.len());
+        if used[i] {
+            kept.push(v);
+        } else {
+            removed.push((i, v));
+        }
+    }
+    *pool = kept;
+    (remap, removed)
+}
+
+/// Call `f` on every type referenced by a type
+fn visit_type_refs(t: &mut Type, f: &mut impl FnMut(&mut RefType)) {
+    match t {
+        Type::Fun(fun) | Type::Method(fun) => {
+            fun.args.iter_mut().for_each(&mut *f);
+            f(&mut fun.ret);
+        }
+        Type::Obj(obj) | Type::Struct(obj) => {
+            obj.super_.iter_mut().for_each(&mut *f);
+            for field in obj.own_fields.iter_mut().chain(&mut obj.fields) {
+                f(&mut field.t);
+            }
+        }
+        Type::Virtual { fields } => {
+            for field in fields {
+                f(&mut field.t);
+            }
+        }
+        Type::Enum { constructs, .. } => {
+            for c in constructs {
+                c.params.iter_mut().for_each(&mut *f);
+            }
+        }
+        Type::Ref(inner) | Type::Null(inner) | Type::Packed(inner) => f(inner),
+        _ => {}
+    }
+}
+
+impl Bytecode {
+    /// Remove the strings, ints, floats and types nothing refers to and update every reference, see [gc](crate::gc)
+    pub fn gc_pools(&mut self) -> GcReport {
+        let mut report = GcReport {
+            types: self.gc_types(),
+            ..GcReport::default()
+        };
+
+        let pools = self.constant_pools();
+        let mut ints = vec![false; self.ints.len()];
+        let mut floats = vec![false; self.floats.len()];
+        self.visit_numbers(&pools, |pool, i| match pool {
+            Pool::Int => ints[*i] = true,
+            _ => floats[*i] = true,
+        });
+        let (int_remap, removed) = compact(&mut self.ints, &ints);
+        report.ints = removed;
+        let (float_remap, removed) = compact(&mut self.floats, &floats);
+        report.floats = removed;
+        self.visit_numbers(&pools, |pool, i| match pool {
+            Pool::Int => *i = int_remap[*i],
+            _ => *i = float_remap[*i],
+        });
+
+        let mut used = vec![false; self.strings.len()];
+        if let Some(u) = used.first_mut() {
+            *u = true;
+        }
+        self.visit_strings(|s| used[s.0] = true);
+        let (remap, removed) = compact(&mut self.strings, &used);
+        self.visit_strings(|s| s.0 = remap[s.0]);
+        report.strings = removed
+            .into_iter()
+            .map(|(i, s)| (i, s.to_string()))
+            .collect();
+        report
+    }
+
+    /// Remove the unreachable types, returns them displayed
+    fn gc_types(&mut self) -> Vec<(usize, String)> {
+        let mut used = vec![false; self.types.len()];
+        let mut stack: Vec<RefType> = self
+            .types
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| match t {
+                Type::Obj(obj) | Type::Struct(obj) => obj.global.0 > 0,
+                Type::Enum { global, .. } => global.0 > 0,
+                _ => false,
+            })
+            .map(|(i, _)| RefType(i))
+            .collect();
+        let pools = self.constant_pools();
+        self.visit_types(&pools, |t| stack.push(*t));
+        while let Some(t) = stack.pop() {
+            match used.get_mut(t.0) {
+                Some(u) if !*u => *u = true,
+                _ => continue,
+            }
+            let mut ty = self.types[t.0].clone();
+            visit_type_refs(&mut ty, &mut |r| stack.push(*r));
+        }
+
+        // Displayed while every reference is valid
+        let names: Vec<(usize, String)> = (0..self.types.len())
+            .filter(|&i| !used[i])
+            .map(|i| (i, RefType(i).display(self).to_string()))
+            .collect();
+        if names.is_empty() {
+            return names;
+        }
+        let (remap, _) = compact(&mut self.types, &used);
+        self.visit_types(&pools, |t| t.0 = remap[t.0]);
+        for t in &mut self.types {
+            visit_type_refs(t, &mut |r| r.0 = remap[r.0]);
+        }
+        self.virtual_names = analysis::names::virtual_names(self);
+        names
+    }
+
+    /// Pool of each field of each constant
+    fn constant_pools(&self) -> Vec<Vec<Option<Pool>>> {
+        self.constants
+            .iter()
+            .flatten()
+            .map(|c| {
+                let obj = self
+                    .globals
+                    .get(c.global.0)
+                    .and_then(|g| g.resolve_as_obj(&self.types));
+                obj.map(|obj| &obj.own_fields[..])
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|field| match self.get_type(field.t) {
+                        Some(Type::I32) => Some(Pool::Int),
+                        Some(Type::F64) => Some(Pool::Float),
+                        Some(Type::Type) => Some(Pool::Type),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Call `f` on every reference to the ints and floats pools
+    fn visit_numbers(&mut self, pools: &[Vec<Option<Pool>>], mut f: impl FnMut(Pool, &mut usize)) {
+        for fun in &mut self.functions {
+            for o in &mut fun.ops {
+                for operand in o.operands_mut() {
+                    match operand {
+                        OperandMut::Int(i) => f(Pool::Int, &mut i.0),
+                        OperandMut::Float(x) => f(Pool::Float, &mut x.0),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        for (c, pools) in self.constants.iter_mut().flatten().zip(pools) {
+            for (v, pool) in c.fields.iter_mut().zip(pools) {
+                if let Some(p @ (Pool::Int | Pool::Float)) = pool {
+                    f(*p, v);
+                }
+            }
+        }
+    }
+
+    /// Call `f` on every reference to a type outside of the types pool
+    fn visit_types(&mut self, pools: &[Vec<Option<Pool>>], mut f: impl FnMut(&mut RefType)) {
+        for fun in &mut self.functions {
+            f(&mut fun.t);
+            fun.regs.iter_mut().for_each(&mut f);
+            fun.parent.iter_mut().for_each(&mut f);
+            for o in &mut fun.ops {
+                for operand in o.operands_mut() {
+                    if let OperandMut::Type(t) = operand {
+                        f(t);
+                    }
+                }
+            }
+        }
+        for n in &mut self.natives {
+            f(&mut n.t);
+        }
+        self.globals.iter_mut().for_each(&mut f);
+        for (c, pools) in self.constants.iter_mut().flatten().zip(pools) {
+            for (v, pool) in c.fields.iter_mut().zip(pools) {
+                if *pool == Some(Pool::Type) {
+                    let mut t = RefType(*v);
+                    f(&mut t);
+                    *v = t.0;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{sample, BytecodeBuilder};
+    use crate::opcodes::Opcode;
+    use crate::types::{RefType, Reg, Type};
+    use crate::{verify, Bytecode};
+
+    #[test]
+    fn gc_pools() {
+        let mut code = sample();
+        let untouched = sample().gc_pools();
+
+        let name = code.add_string("Unused");
+        code.ints.push(123456);
+        code.floats.push(0.5);
+        let unused = code.edit().ty(Type::Abstract { name });
+        let ref_unused = code.edit().ty(Type::Ref(unused));
+
+        let report = code.gc_pools();
+        assert_eq!(report.len(), untouched.len() + 5);
+        assert!(report.ints.contains(&(sample().ints.len(), 123456)));
+        assert!(report.floats.contains(&(sample().floats.len(), 0.5)));
+        assert!(report.strings.iter().any(|(_, s)| s == "Unused"));
+        let types: Vec<usize> = report.types.iter().map(|(i, _)| *i).collect();
+        assert!(types.contains(&unused.0) && types.contains(&ref_unused.0));
+        assert!(report.to_string().starts_with("Removed"));
+
+        // References are still valid, the file is written and loaded back
+        assert!(verify::verify(&code).is_empty());
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let mut loaded = Bytecode::from_bytes(&data).unwrap();
+        assert_eq!(loaded.types.len(), code.types.len());
+        assert!(loaded.gc_pools().is_empty());
+        assert!(loaded.get_type(RefType(code.types.len())).is_none());
+    }
+
+    #[test]
+    fn gc_empty() {
+        // A single function and no strings
+        let mut b = BytecodeBuilder::new();
+        let main = b.findex();
+        let main_t = b.fun_type(&[], RefType(0));
+        b.function(
+            main,
+            main_t,
+            vec![RefType(0)],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        b.entrypoint(main);
+        let mut code = b.build().unwrap();
+        assert!(code.strings.is_empty());
+        code.gc_pools();
+        assert!(verify::verify(&code).is_empty());
+    }
+}
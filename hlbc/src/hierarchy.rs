@@ -0,0 +1,112 @@
+//! Queries on the class hierarchy.
+//!
+//! A [TypeObj] only knows its parent. These walk the hierarchy in both directions and find the methods overriding each
+//! other, matched by name like the Haxe compiler does.
+//! ```
+//! use hlbc::builder::BytecodeBuilder;
+//! use hlbc::prelude::*;
+//!
+//! let mut b = BytecodeBuilder::new();
+//! let base = b.class("Base", None, &[], &[]);
+//! let sub = b.class("Sub", Some(base), &[], &[]);
+//! let main_t = b.fun_type(&[], RefType(0));
+//! let main = b.findex();
+//! b.function(main, main_t, vec![RefType(0)], vec![Opcode::Ret { ret: Reg(0) }]);
+//! let code = b.build().unwrap();
+//! assert_eq!(code.supers(sub).collect::<Vec<_>>(), [base]);
+//! assert_eq!(code.subclasses(base), [sub]);
+//! ```
+
+use crate::types::{RefFun, RefType, TypeObj};
+use crate::Bytecode;
+
+impl Bytecode {
+    /// Parents of a class, from its direct parent up to the root of the hierarchy. Empty for other types.
+    pub fn supers(&self, ty: RefType) -> impl Iterator<Item = RefType> + '_ {
+        std::iter::successors(self.obj(ty).and_then(|o| o.super_), move |&s| {
+            self.obj(s).and_then(|o| o.super_)
+        })
+        // A malformed bytecode could have a cycle
+        .take(self.types.len())
+    }
+
+    /// Returns true if `sub` extends `parent`, directly or not. A class isn't its own subclass.
+    pub fn is_subclass(&self, sub: RefType, parent: RefType) -> bool {
+        self.supers(sub).any(|s| s == parent)
+    }
+
+    /// Classes extending `ty`, directly or not, sorted
+    pub fn subclasses(&self, ty: RefType) -> Vec<RefType> {
+        (0..self.types.len())
+            .map(RefType)
+            .filter(|&t| self.is_subclass(t, ty))
+            .collect()
+    }
+
+    /// Classes declaring a method named `name`, with the function bound to it, sorted by type. This includes the
+    /// first declaration and every override.
+    pub fn method_definitions(&self, name: &str) -> Vec<(RefType, RefFun)> {
+        self.iter_types()
+            .filter_map(|(t, ty)| {
+                let obj = ty.get_type_obj()?;
+                let p = obj
+                    .protos
+                    .iter()
+                    .find(|p| p.name.resolve(&self.strings) == name)?;
+                Some((t, p.findex))
+            })
+            .collect()
+    }
+
+    /// The closest parent declaring the method named `name` of `ty`, with the function bound to it. None if `ty`
+    /// doesn't override it.
+    pub fn overridden(&self, ty: RefType, name: &str) -> Option<(RefType, RefFun)> {
+        self.supers(ty).find_map(|s| {
+            self.obj(s)?
+                .protos
+                .iter()
+                .find(|p| p.name.resolve(&self.strings) == name)
+                .map(|p| (s, p.findex))
+        })
+    }
+
+    fn obj(&self, ty: RefType) -> Option<&TypeObj> {
+        self.get_type(ty)?.get_type_obj()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::BytecodeBuilder;
+    use crate::opcodes::Opcode;
+    use crate::types::{Reg, Type};
+
+    #[test]
+    fn hierarchy() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let [run, run_b, run_c] = [(); 3].map(|_| b.findex());
+        let a = b.class("A", None, &[], &[("run", run)]);
+        let bb = b.class("B", Some(a), &[], &[("run", run_b)]);
+        let c = b.class("C", Some(bb), &[], &[("run", run_c)]);
+        let other = b.class("Other", None, &[], &[]);
+        for (f, ty) in [(run, a), (run_b, bb), (run_c, c)] {
+            let t = b.method_type(&[ty], void);
+            b.function(f, t, vec![ty, void], vec![Opcode::Ret { ret: Reg(1) }]);
+        }
+        let code = b.build().unwrap();
+
+        assert_eq!(code.supers(c).collect::<Vec<_>>(), [bb, a]);
+        assert_eq!(code.supers(void).count(), 0);
+        assert!(code.is_subclass(c, a));
+        assert!(!code.is_subclass(a, a));
+        assert_eq!(code.subclasses(a), [bb, c]);
+        assert!(code.subclasses(other).is_empty());
+        assert_eq!(
+            code.method_definitions("run"),
+            [(a, run), (bb, run_b), (c, run_c)]
+        );
+        assert_eq!(code.overridden(c, "run"), Some((bb, run_b)));
+        assert_eq!(code.overridden(a, "run"), None);
+    }
+}
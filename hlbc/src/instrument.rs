@@ -0,0 +1,371 @@
+//! Value logging by instrumentation, printf-debugging for shipped programs without a debugger.
+//!
+//! A [Probe] is a position in a function and a list of registers. [instrument] inserts before that position the code
+//! printing the value of each register, the result can be serialized and run as is. By default values are printed to
+//! stdout with the natives of the HashLink std library (`std@value_to_string` and `std@sys_print`), so no additional
+//! library is required :
+//! ```text
+//! fn@12:4 r3=42 r5={x: 1, y: 2}
+//! ```
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc::instrument::{instrument, Logger, Probe};
+//! use hlbc::types::Reg;
+//!
+//! let mut code = sample();
+//! let findex = code.functions[0].findex;
+//! instrument(&mut code, &Logger::Print, &[Probe::new(findex, 0, vec![Reg(0)])]).unwrap();
+//! ```
+
+use crate::types::{FunPtr, RefFun, RefType, Reg, TypeFun};
+use crate::{Bytecode, Opcode, Type};
+
+/// Registers to log before an instruction
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Probe {
+    pub findex: RefFun,
+    /// The values are logged before this instruction is executed
+    pub pos: usize,
+    pub regs: Vec<Reg>,
+}
+
+impl Probe {
+    pub fn new(findex: RefFun, pos: usize, regs: Vec<Reg>) -> Self {
+        Self { findex, pos, regs }
+    }
+
+    /// Label printed before the values, like `fn@12:4`
+    pub fn label(&self) -> String {
+        format!("fn@{}:{}", self.findex.0, self.pos)
+    }
+}
+
+/// Where the values are sent
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Logger {
+    /// Print a line with the probe label and the values to stdout, with the natives of the std library
+    Print,
+    /// Call a function or a native of type `(i32, dyn) -> void` for each value, with the index of the probe and the
+    /// value
+    Call(RefFun),
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum InstrumentError {
+    #[error("fn@{} is not a function with code", findex.0)]
+    NotAFunction { findex: RefFun },
+    #[error("fn@{} has no instruction at {pos}", findex.0)]
+    InvalidPosition { findex: RefFun, pos: usize },
+    #[error("fn@{} has no register {}", findex.0, reg.0)]
+    InvalidRegister { findex: RefFun, reg: Reg },
+    #[error("fn@{} : register {} can't be converted to a dynamic value", findex.0, reg.0)]
+    UnsupportedRegister { findex: RefFun, reg: Reg },
+    #[error("fn@{} doesn't have the type (i32, dyn) -> void", findex.0)]
+    InvalidLogger { findex: RefFun },
+}
+
+/// Insert the code logging the registers of each probe. Probes are applied in order of decreasing position so their
+/// positions always refer to the original code.
+pub fn instrument(
+    code: &mut Bytecode,
+    logger: &Logger,
+    probes: &[Probe],
+) -> Result<(), InstrumentError> {
+    for p in probes {
+        let f = p
+            .findex
+            .resolve_as_fn(code)
+            .ok_or(InstrumentError::NotAFunction { findex: p.findex })?;
+        if p.pos >= f.ops.len() {
+            return Err(InstrumentError::InvalidPosition {
+                findex: p.findex,
+                pos: p.pos,
+            });
+        }
+        for &reg in &p.regs {
+            let ty = f
+                .regs
+                .get(reg.0 as usize)
+                .ok_or(InstrumentError::InvalidRegister {
+                    findex: p.findex,
+                    reg,
+                })?;
+            if boxing(&code.types[ty.0]).is_none() {
+                return Err(InstrumentError::UnsupportedRegister {
+                    findex: p.findex,
+                    reg,
+                });
+            }
+        }
+    }
+
+    let mut editor = code.edit();
+    let void = editor.ty(Type::Void);
+    let i32 = editor.ty(Type::I32);
+    let dyn_ = editor.ty(Type::Dyn);
+    let target = match *logger {
+        Logger::Print => {
+            let bytes = editor.ty(Type::Bytes);
+            let ref_i32 = editor.ty(Type::Ref(i32));
+            let print_t = editor.ty(Type::Fun(TypeFun {
+                args: vec![bytes],
+                ret: void,
+            }));
+            let to_string_t = editor.ty(Type::Fun(TypeFun {
+                args: vec![dyn_, ref_i32],
+                ret: bytes,
+            }));
+            Target::Print {
+                print: find_native(code, "std", "sys_print")
+                    .unwrap_or_else(|| code.edit().native("std", "sys_print", print_t)),
+                to_string: find_native(code, "std", "value_to_string")
+                    .unwrap_or_else(|| code.edit().native("std", "value_to_string", to_string_t)),
+                bytes,
+                ref_i32,
+            }
+        }
+        Logger::Call(fun) => {
+            let expected = TypeFun {
+                args: vec![i32, dyn_],
+                ret: void,
+            };
+            let ty = match fun.resolve(code) {
+                FunPtr::Fun(f) => f.t,
+                FunPtr::Native(n) => n.t,
+            };
+            if ty.resolve_as_fun(&code.types) != Some(&expected) {
+                return Err(InstrumentError::InvalidLogger { findex: fun });
+            }
+            Target::Call(fun)
+        }
+    };
+
+    let mut order: Vec<usize> = (0..probes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse((probes[i].findex, probes[i].pos)));
+    for i in order {
+        let p = &probes[i];
+        let ops = probe_ops(code, &target, i, p, [void, i32, dyn_]);
+        code.edit().insert_ops(p.findex, p.pos, ops);
+    }
+    Ok(())
+}
+
+/// Resolved [Logger]
+enum Target {
+    Print {
+        print: RefFun,
+        to_string: RefFun,
+        bytes: RefType,
+        ref_i32: RefType,
+    },
+    Call(RefFun),
+}
+
+/// Instructions logging the registers of a probe, the registers they need are added to the function
+fn probe_ops(
+    code: &mut Bytecode,
+    target: &Target,
+    index: usize,
+    p: &Probe,
+    [void, i32, dyn_]: [RefType; 3],
+) -> Vec<Opcode> {
+    let regs: Vec<(Reg, bool)> = {
+        let f = p.findex.resolve_as_fn(code).unwrap();
+        p.regs
+            .iter()
+            .map(|&r| (r, boxing(&code.types[f.regs[r.0 as usize].0]).unwrap()))
+            .collect()
+    };
+    let mut editor = code.edit();
+    let void_r = editor.reg(p.findex, void);
+    let dyn_r = editor.reg(p.findex, dyn_);
+    let int_r = editor.reg(p.findex, i32);
+    // Boxed if needed, pointers to dynamic values are passed as is
+    let value = |r: Reg, boxed: bool, ops: &mut Vec<Opcode>| {
+        if boxed {
+            ops.push(Opcode::ToDyn { dst: dyn_r, src: r });
+            dyn_r
+        } else {
+            r
+        }
+    };
+
+    let mut ops = Vec::new();
+    match *target {
+        Target::Print {
+            print,
+            to_string,
+            bytes,
+            ref_i32,
+        } => {
+            let bytes_r = editor.reg(p.findex, bytes);
+            let len_r = editor.reg(p.findex, ref_i32);
+            let mut print_str = |s: String, ops: &mut Vec<Opcode>| {
+                ops.push(Opcode::String {
+                    dst: bytes_r,
+                    ptr: editor.string(&s),
+                });
+                ops.push(Opcode::Call1 {
+                    dst: void_r,
+                    fun: print,
+                    arg0: bytes_r,
+                });
+            };
+            print_str(p.label(), &mut ops);
+            ops.push(Opcode::Ref {
+                dst: len_r,
+                src: int_r,
+            });
+            for (r, boxed) in regs {
+                print_str(format!(" r{}=", r.0), &mut ops);
+                let v = value(r, boxed, &mut ops);
+                ops.push(Opcode::Call2 {
+                    dst: bytes_r,
+                    fun: to_string,
+                    arg0: v,
+                    arg1: len_r,
+                });
+                ops.push(Opcode::Call1 {
+                    dst: void_r,
+                    fun: print,
+                    arg0: bytes_r,
+                });
+            }
+            print_str("\n".to_owned(), &mut ops);
+        }
+        Target::Call(fun) => {
+            let id = editor.int(index as i32);
+            ops.push(Opcode::Int {
+                dst: int_r,
+                ptr: id,
+            });
+            for (r, boxed) in regs {
+                let v = value(r, boxed, &mut ops);
+                ops.push(Opcode::Call2 {
+                    dst: void_r,
+                    fun,
+                    arg0: int_r,
+                    arg1: v,
+                });
+            }
+        }
+    }
+    ops
+}
+
+/// Whether a register of this type must be boxed to be passed as a dynamic value, `None` if it can't be
+fn boxing(ty: &Type) -> Option<bool> {
+    match ty {
+        Type::UI8 | Type::UI16 | Type::I32 | Type::I64 | Type::F32 | Type::F64 | Type::Bool => {
+            Some(true)
+        }
+        Type::Dyn
+        | Type::Fun(_)
+        | Type::Obj(_)
+        | Type::Array
+        | Type::Virtual { .. }
+        | Type::DynObj
+        | Type::Enum { .. }
+        | Type::Null(_)
+        | Type::Method(_) => Some(false),
+        _ => None,
+    }
+}
+
+fn find_native(code: &Bytecode, lib: &str, name: &str) -> Option<RefFun> {
+    code.natives
+        .iter()
+        .find(|n| n.lib.resolve(&code.strings) == lib && n.name.resolve(&code.strings) == name)
+        .map(|n| n.findex)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::instrument::{instrument, InstrumentError, Logger, Probe};
+    use crate::types::{RefType, Reg, TypeFun};
+    use crate::verify::verify;
+    use crate::{Bytecode, Opcode, Type};
+
+    #[test]
+    fn print() {
+        let mut code = sample();
+        let f = code
+            .functions
+            .iter()
+            .find(|f| f.regs.len() > 1 && f.ops.len() > 2)
+            .unwrap()
+            .clone();
+        let (n, nregs) = (f.ops.len(), f.regs.len());
+        let regs: Vec<Reg> = (0..nregs as u32)
+            .map(Reg)
+            .filter(|r| {
+                !matches!(
+                    code.types[f.regs[r.0 as usize].0],
+                    Type::Void | Type::Bytes | Type::Type | Type::Ref(_) | Type::Abstract { .. }
+                )
+            })
+            .collect();
+        let probes = [
+            Probe::new(f.findex, 0, regs.clone()),
+            Probe::new(f.findex, 2, regs.clone()),
+        ];
+        instrument(&mut code, &Logger::Print, &probes).unwrap();
+
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let code = Bytecode::from_bytes(&data).unwrap();
+        assert!(verify(&code).is_empty());
+        let instrumented = f.findex.resolve_as_fn(&code).unwrap();
+        assert!(instrumented.regs.len() > nregs);
+        let inserted = (instrumented.ops.len() - n) / 2;
+        assert_eq!(
+            instrumented.ops[inserted + 2 + inserted].name(),
+            f.ops[2].name()
+        );
+        let label = format!("fn@{}:2", f.findex.0);
+        assert!(code.strings.iter().any(|s| *s == label));
+    }
+
+    #[test]
+    fn call() {
+        let mut code = sample();
+        let f = code.functions[0].findex;
+        let logger_t = code.edit().ty(Type::Fun(TypeFun {
+            args: vec![RefType(0), RefType(0)],
+            ret: RefType(0),
+        }));
+        let bad = code.edit().native("test", "log", logger_t);
+        assert_eq!(
+            instrument(&mut code, &Logger::Call(bad), &[Probe::new(f, 0, vec![])]),
+            Err(InstrumentError::InvalidLogger { findex: bad })
+        );
+        assert!(matches!(
+            instrument(&mut code, &Logger::Print, &[Probe::new(f, 10000, vec![])]),
+            Err(InstrumentError::InvalidPosition { .. })
+        ));
+
+        let mut editor = code.edit();
+        let (void, i32, dyn_) = (
+            editor.ty(Type::Void),
+            editor.ty(Type::I32),
+            editor.ty(Type::Dyn),
+        );
+        let logger_t = editor.ty(Type::Fun(TypeFun {
+            args: vec![i32, dyn_],
+            ret: void,
+        }));
+        let log = editor.native("test", "log", logger_t);
+        let reg = Reg(code.functions[0].regs.len() as u32);
+        code.edit().reg(f, i32);
+        instrument(
+            &mut code,
+            &Logger::Call(log),
+            &[Probe::new(f, 0, vec![reg])],
+        )
+        .unwrap();
+        let ops = &f.resolve_as_fn(&code).unwrap().ops;
+        assert!(matches!(ops[1], Opcode::ToDyn { src, .. } if src == reg));
+        assert!(matches!(ops[2], Opcode::Call2 { fun, .. } if fun == log));
+    }
+}
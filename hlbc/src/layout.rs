@@ -0,0 +1,297 @@
+//! Memory layout of values in the HashLink VM, to find them in a running game.
+//!
+//! Computed like the VM does at startup (`hl_get_obj_rt` and the globals area of `hl_module_init`) for a 64 bits
+//! process. An object starts with a pointer to its type, then its fields, inherited ones first, each aligned on its
+//! own size. Structures have no type pointer. A packed structure (`Packed`) is stored inline, aligned on its largest
+//! field. Objects, structures, strings and every other reference take a pointer. The virtual methods of a class are
+//! listed by their slot in the virtual table of its type.
+//! ```
+//! use hlbc::builder::BytecodeBuilder;
+//! use hlbc::prelude::*;
+//!
+//! let mut b = BytecodeBuilder::new();
+//! let u8_ = b.ty(Type::UI8);
+//! let f64_ = b.ty(Type::F64);
+//! let point = b.class("Point", None, &[("tag", u8_), ("x", f64_)], &[]);
+//! let main_t = b.fun_type(&[], RefType(0));
+//! let main = b.findex();
+//! b.function(main, main_t, vec![RefType(0)], vec![Opcode::Ret { ret: Reg(0) }]);
+//! let code = b.build().unwrap();
+//!
+//! let layout = code.obj_layout(point).unwrap();
+//! let offsets: Vec<usize> = layout.fields.iter().map(|f| f.offset).collect();
+//! // After the type pointer, `x` is aligned on 8 bytes
+//! assert_eq!(offsets, [8, 16]);
+//! assert_eq!(layout.size, 24);
+//! ```
+
+use crate::types::{RefFun, RefGlobal, RefString, RefType, Type};
+use crate::Bytecode;
+
+/// Size of a pointer in the VM, only 64 bits processes are supported
+pub const POINTER_SIZE: usize = 8;
+
+/// Packed structures nested deeper than this are taken as pointers, a corrupted file could have a structure packing
+/// itself
+const MAX_PACKED_DEPTH: usize = 16;
+
+/// A field in an [ObjLayout]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FieldLayout {
+    pub name: RefString,
+    pub ty: RefType,
+    /// Offset from the start of the object
+    pub offset: usize,
+}
+
+/// A virtual method in an [ObjLayout]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MethodSlot {
+    /// Index in the virtual table, the [pindex](crate::types::ObjProto::pindex) of the method
+    pub slot: usize,
+    pub name: RefString,
+    /// Function called through this slot, the override of the class if it has one
+    pub findex: RefFun,
+}
+
+/// Position of the fields of a class or a structure
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ObjLayout {
+    /// Every field, in the order of [TypeObj::fields](crate::types::TypeObj::fields)
+    pub fields: Vec<FieldLayout>,
+    /// Size of an instance, without the padding the allocator adds
+    pub size: usize,
+    /// Alignment of an instance stored inline : its largest field, a pointer for a class
+    pub align: usize,
+    /// Virtual methods sorted by slot, inherited ones included
+    pub vtable: Vec<MethodSlot>,
+}
+
+impl ObjLayout {
+    /// Layout of a field by name, the last one if a subclass shadows it
+    pub fn field(&self, code: &Bytecode, name: &str) -> Option<&FieldLayout> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|f| f.name.resolve(&code.strings) == name)
+    }
+}
+
+impl Type {
+    /// Size of a value of this type when stored in a field, a register or a global
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Void => 0,
+            Type::UI8 | Type::Bool => 1,
+            Type::UI16 => 2,
+            Type::I32 | Type::F32 => 4,
+            Type::I64 | Type::F64 => 8,
+            _ => POINTER_SIZE,
+        }
+    }
+}
+
+/// Padding to add at `pos` to align a value on `align` bytes
+fn padding(pos: usize, align: usize) -> usize {
+    if align == 0 {
+        0
+    } else {
+        pos.wrapping_neg() & (align - 1)
+    }
+}
+
+impl Bytecode {
+    /// Size of a value of a type when stored in a field or a global : [Type::size], or the size of the structure
+    /// for a packed one. Pointer sized for an invalid reference.
+    pub fn type_size(&self, ty: RefType) -> usize {
+        self.value_layout(ty, 0).0
+    }
+
+    /// Size and alignment of a value stored in a field or a global
+    fn value_layout(&self, ty: RefType, depth: usize) -> (usize, usize) {
+        match self.get_type(ty) {
+            Some(Type::Packed(inner)) if depth < MAX_PACKED_DEPTH => self
+                .obj_layout_at(*inner, depth + 1)
+                .map_or((POINTER_SIZE, POINTER_SIZE), |l| {
+                    (l.size + padding(l.size, l.align), l.align)
+                }),
+            Some(Type::Packed(_)) | None => (POINTER_SIZE, POINTER_SIZE),
+            Some(t) => (t.size(), t.size().max(1)),
+        }
+    }
+
+    /// Layout of the instances of a class or a structure, of the structure for a packed one. None for other types.
+    pub fn obj_layout(&self, ty: RefType) -> Option<ObjLayout> {
+        self.obj_layout_at(ty, 0)
+    }
+
+    fn obj_layout_at(&self, ty: RefType, depth: usize) -> Option<ObjLayout> {
+        let t = self.get_type(ty)?;
+        if let Type::Packed(inner) = t {
+            return if depth < MAX_PACKED_DEPTH {
+                self.obj_layout_at(*inner, depth + 1)
+            } else {
+                None
+            };
+        }
+        t.get_type_obj()?;
+        let mut chain: Vec<RefType> = self.supers(ty).collect();
+        chain.reverse();
+        chain.push(ty);
+
+        let (mut size, mut align) = if matches!(t, Type::Struct(_)) {
+            (0, 1)
+        } else {
+            (POINTER_SIZE, POINTER_SIZE)
+        };
+        let mut fields = Vec::new();
+        let mut vtable: Vec<MethodSlot> = Vec::new();
+        for obj in chain
+            .iter()
+            .filter_map(|&c| self.get_type(c)?.get_type_obj())
+        {
+            for f in &obj.own_fields {
+                let (field_size, field_align) = self.value_layout(f.t, depth);
+                size += padding(size, field_align);
+                align = align.max(field_align);
+                fields.push(FieldLayout {
+                    name: f.name,
+                    ty: f.t,
+                    offset: size,
+                });
+                size += field_size;
+            }
+            // Overrides take the slot of the method they override
+            for p in obj.protos.iter().filter(|p| p.pindex >= 0) {
+                let method = MethodSlot {
+                    slot: p.pindex as usize,
+                    name: p.name,
+                    findex: p.findex,
+                };
+                match vtable.iter_mut().find(|m| m.slot == method.slot) {
+                    Some(m) => *m = method,
+                    None => vtable.push(method),
+                }
+            }
+        }
+        vtable.sort_unstable_by_key(|m| m.slot);
+        Some(ObjLayout {
+            fields,
+            size,
+            align,
+            vtable,
+        })
+    }
+
+    /// Offset of each global in the area the VM allocates for them, computed when loading in
+    /// [Self::global_offsets]
+    pub fn globals_layout(&self) -> Vec<usize> {
+        let mut size = 0;
+        self.globals
+            .iter()
+            .map(|&g| {
+                let (global_size, align) = self.value_layout(g, 0);
+                size += padding(size, align);
+                let offset = size;
+                size += global_size;
+                offset
+            })
+            .collect()
+    }
+
+    /// Offset of a global in the area the VM allocates for them. Globals added after loading are laid out again.
+    pub fn global_offset(&self, global: RefGlobal) -> Option<usize> {
+        match self.global_offsets.get(global.0) {
+            Some(&offset) if self.global_offsets.len() == self.globals.len() => Some(offset),
+            _ => self.globals_layout().get(global.0).copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::BytecodeBuilder;
+    use crate::layout::POINTER_SIZE;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefFun, RefGlobal, RefType, Reg, Type};
+
+    #[test]
+    fn layouts() {
+        let mut b = BytecodeBuilder::new();
+        let bool_ = b.ty(Type::Bool);
+        let u8_ = b.ty(Type::UI8);
+        let i32_ = b.ty(Type::I32);
+        let i64_ = b.ty(Type::I64);
+        let u16_ = b.ty(Type::UI16);
+        let [main, run, run_sub, jump] = [(); 4].map(|_| b.findex());
+        let base = b.class("Base", None, &[("alive", bool_)], &[("run", run)]);
+        let sub = b.class(
+            "Sub",
+            Some(base),
+            &[("hp", i32_), ("base", base)],
+            &[("run", run_sub), ("jump", jump)],
+        );
+        let pair = b.structure("Pair", &[("a", u16_), ("b", i64_)]);
+        let packed = b.ty(Type::Packed(pair));
+        let holder = b.class("Holder", None, &[("tag", u8_), ("pos", packed)], &[]);
+        b.global(bool_);
+        b.global(i64_);
+        b.global(u16_);
+        b.global(packed);
+        let main_t = b.fun_type(&[], RefType(0));
+        for f in [main, run, run_sub, jump] {
+            b.function(
+                f,
+                main_t,
+                vec![RefType(0)],
+                vec![Opcode::Ret { ret: Reg(0) }],
+            );
+        }
+        let mut code = b.build().unwrap();
+        let mut set_pindex = |ty: RefType, pindexes: &[i32]| {
+            let obj = code.types[ty.0].get_type_obj_mut().unwrap();
+            for (p, &i) in obj.protos.iter_mut().zip(pindexes) {
+                p.pindex = i;
+            }
+        };
+        set_pindex(base, &[0]);
+        set_pindex(sub, &[0, 1]);
+
+        let layout = code.obj_layout(sub).unwrap();
+        let offsets: Vec<usize> = layout.fields.iter().map(|f| f.offset).collect();
+        assert_eq!(offsets, [8, 12, 16]);
+        assert_eq!(layout.size, 16 + POINTER_SIZE);
+        assert_eq!(layout.field(&code, "hp").unwrap().ty, i32_);
+        // run is overridden in its slot
+        let vtable: Vec<(usize, RefFun)> =
+            layout.vtable.iter().map(|m| (m.slot, m.findex)).collect();
+        assert_eq!(vtable, [(0, run_sub), (1, jump)]);
+        assert_eq!(code.obj_layout(base).unwrap().vtable[0].findex, run);
+
+        // No type pointer in a structure
+        let layout = code.obj_layout(pair).unwrap();
+        let offsets: Vec<usize> = layout.fields.iter().map(|f| f.offset).collect();
+        assert_eq!(offsets, [0, 8]);
+        assert_eq!((layout.size, layout.align), (16, 8));
+        assert_eq!(code.obj_layout(packed), Some(layout));
+        assert!(code.obj_layout(i32_).is_none());
+
+        // A packed structure is stored inline
+        let layout = code.obj_layout(holder).unwrap();
+        let offsets: Vec<usize> = layout.fields.iter().map(|f| f.offset).collect();
+        assert_eq!(offsets, [8, 16]);
+        assert_eq!(layout.size, 32);
+        assert_eq!(code.type_size(packed), 16);
+
+        assert_eq!(code.globals_layout(), [0, 8, 16, 24]);
+        assert_eq!(code.global_offsets, code.globals_layout());
+        assert_eq!(code.global_offset(RefGlobal(3)), Some(24));
+        // Not in the cache
+        code.globals.push(bool_);
+        assert_eq!(code.global_offset(RefGlobal(4)), Some(40));
+
+        // A structure packing itself, only in a corrupted file
+        code.types[pair.0].get_type_obj_mut().unwrap().own_fields[1].t = packed;
+        assert!(code.obj_layout(pair).is_some());
+    }
+}
@@ -0,0 +1,169 @@
+//! Lazy loading for big bytecode files.
+//!
+//! [Bytecode::load] decodes every instruction of every function. For big games this takes a while and a lot of memory
+//! when only the list of functions is needed. [LazyBytecode] parses everything but the function bodies, those are
+//! decoded from the original data the first time they are accessed. With the `mmap` feature, [LazyBytecode::open]
+//! maps the file in memory instead of reading it.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc::lazy::LazyBytecode;
+//!
+//! let mut data = Vec::new();
+//! sample().serialize(&mut data).unwrap();
+//! let mut code = LazyBytecode::from_bytes(data).unwrap();
+//! assert!(code.bytecode().functions[0].ops.is_empty());
+//! assert!(!code.function(0).unwrap().ops.is_empty());
+//! ```
+
+use std::ops::Deref;
+
+use crate::deser::{self, CountingReader, ParseOptions};
+use crate::types::Function;
+use crate::{Bytecode, Result, Section};
+
+/// Bytecode whose function bodies are decoded on first access
+pub struct LazyBytecode {
+    code: Bytecode,
+    data: Data,
+    /// Header flags, for the warnings
+    flags: u32,
+    /// Offset of the body of each function and its number of instructions, `None` once decoded
+    bodies: Vec<Option<(u64, usize)>>,
+}
+
+impl LazyBytecode {
+    /// Parse a bytecode in memory, function bodies are decoded from it when needed
+    pub fn from_bytes(data: Vec<u8>) -> Result<LazyBytecode> {
+        Self::new(Data::Owned(data))
+    }
+
+    /// Map a file in memory and parse it, function bodies are decoded from the mapping when needed.
+    ///
+    /// The file must not be modified while it is mapped, function bodies would be decoded from the new content.
+    #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<LazyBytecode> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: see the doc comment, we can't prevent other processes from writing to the file
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::new(Data::Mapped(map))
+    }
+
+    fn new(data: Data) -> Result<LazyBytecode> {
+        let mut bodies = Vec::new();
+        let mut r = CountingReader::new(&*data);
+        let (code, flags) = Bytecode::read(&mut r, Some(&mut bodies), &ParseOptions::default())
+            .map_err(|e| r.locate(e))?;
+        Ok(LazyBytecode {
+            code,
+            data,
+            flags,
+            bodies: bodies.into_iter().map(Some).collect(),
+        })
+    }
+
+    /// The bytecode without the function bodies. Functions not accessed with [Self::function] have no instructions nor
+    /// debug info, `virtual_names`, `global_offsets` and `warnings` are only computed by [Self::load_all].
+    pub fn bytecode(&self) -> &Bytecode {
+        &self.code
+    }
+
+    /// Whether the body of the function at index `i` in the function pool has been decoded
+    pub fn is_loaded(&self, i: usize) -> bool {
+        self.bodies[i].is_none()
+    }
+
+    /// Function at index `i` in the function pool, its body is decoded the first time
+    pub fn function(&mut self, i: usize) -> Result<&Function> {
+        if let Some(body) = self.bodies[i] {
+            let has_debug = self.code.debug_files.is_some();
+            let version = self.code.version;
+            read_body_at(
+                &self.data,
+                i,
+                &mut self.code.functions[i],
+                body,
+                has_debug,
+                version,
+            )?;
+            self.bodies[i] = None;
+        }
+        Ok(&self.code.functions[i])
+    }
+
+    /// Decode every remaining function body, the result is the same as [Bytecode::load]
+    pub fn load_all(mut self) -> Result<Bytecode> {
+        for i in 0..self.bodies.len() {
+            self.function(i)?;
+        }
+        self.code.link(self.flags)?;
+        Ok(self.code)
+    }
+}
+
+/// Decode the body of the function `i` from the whole file, `body` is its offset and number of instructions
+pub(crate) fn read_body_at(
+    data: &[u8],
+    i: usize,
+    f: &mut Function,
+    (offset, nops): (u64, usize),
+    has_debug: bool,
+    version: u8,
+) -> Result<()> {
+    let mut r = CountingReader::new(&data[offset as usize..]);
+    r.enter(Section::Functions, Some(i));
+    deser::read_function_body(
+        &mut r,
+        f,
+        nops,
+        has_debug,
+        version,
+        &ParseOptions::default(),
+    )
+    .map_err(|e| r.locate_from(offset, e))
+}
+
+/// Where function bodies are decoded from
+enum Data {
+    Owned(Vec<u8>),
+    #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+    Mapped(memmap2::Mmap),
+}
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Data::Owned(data) => data,
+            #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+            Data::Mapped(map) => map,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::lazy::LazyBytecode;
+
+    #[test]
+    fn lazy_load() {
+        let mut data = Vec::new();
+        sample().serialize(&mut data).unwrap();
+
+        let mut lazy = LazyBytecode::from_bytes(data.clone()).unwrap();
+        let code = lazy.bytecode();
+        assert!(code.functions.iter().all(|f| f.ops.is_empty()));
+        assert_eq!(code.functions.len(), sample().functions.len());
+
+        let eager = sample();
+        let f = lazy.function(1).unwrap();
+        assert_eq!(f.ops.len(), eager.functions[1].ops.len());
+        assert_eq!(f.name, eager.functions[1].name);
+        assert!(lazy.is_loaded(1) && !lazy.is_loaded(0));
+
+        let mut written = Vec::new();
+        lazy.load_all().unwrap().serialize(&mut written).unwrap();
+        assert_eq!(written, data);
+    }
+}
@@ -11,32 +11,64 @@ use std::io::{Read, Write};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::deser::ReadHlExt;
+use crate::deser::{CountingReader, ParseOptions, ReadHlExt};
+use crate::metadata::Metadata;
 use crate::opcodes::Opcode;
 use crate::ser::WriteHlExt;
 use crate::types::{
-    ConstantDef, Function, Native, ObjField, RefFun, RefFunKnown, RefGlobal, RefType, Type, TypeObj,
+    ConstantDef, FunPtr, Function, Native, ObjField, RefFun, RefFunKnown, RefGlobal, RefString,
+    RefType, Reg, Str, Type, TypeObj,
 };
 
-/// Analysis functions and callgraph generation
+/// Helpers to analyze the code, virtual type names, annotations and tags.
+/// Program analyses (control flow, call graph, dataflow) are in the `hlbc-analysis` crate.
 pub mod analysis;
+pub mod archive;
+pub mod asm;
+pub mod builder;
+pub mod bytes;
+pub mod constants;
+pub mod debug;
 pub mod deser;
+pub mod edit;
+pub mod embedded;
+pub mod extract;
 /// Functions to display bytecode elements
 pub mod fmt;
+pub mod gc;
+pub mod hierarchy;
+pub mod instrument;
+pub mod layout;
+pub mod lazy;
+pub mod lookup;
+pub mod manifest;
+pub mod merge;
+pub mod metadata;
 /// Opcodes definitions.
 pub mod opcodes;
+pub mod patch;
+/// Re-exports of the most used items, prefer this over accessing the modules directly.
+pub mod prelude;
+pub mod reload;
 pub mod ser;
+pub mod stats;
+pub mod syntax;
+pub mod transform;
 /// Bytecode elements definitions.
 /// All the Ref* types in this modules are references to bytecode elements like constants or function.
 /// They are required since we cannot use rust references as that would make our structure self-referential.
 /// They makes the code look a bit more complicated than it actually is. Every Ref* struct is cheaply copyable.
 pub mod types;
+pub mod verify;
+pub mod version;
+pub mod warnings;
 
 /// Bytecode structure containing all the information.
 /// Every field is public for flexibility, but you aren't encouraged to modify them.
 ///
 /// We try to keep optimizations, and acceleration structures separated from the main data.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bytecode {
     /// Bytecode format version
     pub version: u8,
@@ -47,7 +79,7 @@ pub struct Bytecode {
     /// f64 constant pool
     pub floats: Vec<f64>,
     /// String constant pool
-    pub strings: Vec<String>,
+    pub strings: Vec<Str>,
     /// Bytes constant pool
     ///
     /// *Since bytecode v5*
@@ -66,6 +98,8 @@ pub struct Bytecode {
     ///
     /// *Since bytecode v4*
     pub constants: Option<Vec<ConstantDef>>,
+    /// Vendor metadata appended to the file, ignored by the VM
+    pub metadata: Option<Metadata>,
 
     // Fields below are not part of the data.
     // Those are acceleration structures used to speed up lookup.
@@ -74,58 +108,152 @@ pub struct Bytecode {
     /// Acceleration structure mapping function names to function indexes in the function pool
     pub fnames: HashMap<String, usize>,
     pub globals_initializers: HashMap<RefGlobal, usize>,
+    /// Synthesized names for virtual types, see [analysis::names]
+    pub virtual_names: HashMap<RefType, String>,
+    /// Offset of each global in the memory of the VM, see [layout]. Not serialized with serde.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub global_offsets: Vec<usize>,
+    /// Non-fatal problems found while loading, see [warnings]. Not serialized with serde.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub warnings: Vec<warnings::Warning>,
+    /// File the bytecode was loaded from with [Self::from_file], see [reload]. Not serialized with serde.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub source: Option<reload::Source>,
 }
 
 impl Bytecode {
-    /// Load the bytecode from any source.
-    /// Must be a valid hashlink bytecode binary.
-    pub fn load(r: &mut impl Read) -> Result<Bytecode> {
+    /// Load the bytecode from any source : a file, an entry of an archive, a buffer in memory ...
+    /// Must be a valid hashlink bytecode binary, parse errors carry the offset where reading stopped, the section and
+    /// the index of the element being read. A file referring to elements that don't exist is rejected with
+    /// [Error::InvalidReferences].
+    pub fn load(r: impl Read) -> Result<Bytecode> {
+        Self::load_with(r, &ParseOptions::default())
+    }
+
+    /// Load the bytecode with other [limits](ParseOptions) than the default ones, to read untrusted files with less
+    /// memory or big trusted files
+    pub fn load_with(r: impl Read, opts: &ParseOptions) -> Result<Bytecode> {
+        let mut r = CountingReader::new(r);
+        Self::read(&mut r, None, opts)
+            .and_then(|(mut code, flags)| {
+                code.link(flags)?;
+                Ok(code)
+            })
+            .map_err(|e| r.locate(e))
+    }
+
+    /// Load the bytecode from a buffer in memory
+    pub fn from_bytes(data: &[u8]) -> Result<Bytecode> {
+        Self::load(data)
+    }
+
+    /// Load the bytecode from a buffer in memory, function bodies are decoded on every core. Much faster than
+    /// [Self::from_bytes] for big files.
+    #[cfg(feature = "parallel")]
+    pub fn from_bytes_parallel(data: &[u8]) -> Result<Bytecode> {
+        use rayon::prelude::*;
+
+        let mut bodies = Vec::new();
+        let mut r = CountingReader::new(data);
+        let (mut code, flags) = Self::read(&mut r, Some(&mut bodies), &ParseOptions::default())
+            .map_err(|e| r.locate(e))?;
+        let has_debug = code.debug_files.is_some();
+        let version = code.version;
+        code.functions
+            .par_iter_mut()
+            .zip(bodies)
+            .enumerate()
+            .try_for_each(|(i, (f, body))| {
+                lazy::read_body_at(data, i, f, body, has_debug, version)
+            })?;
+        code.link(flags)?;
+        Ok(code)
+    }
+
+    /// Load the bytecode from a file, the file is recorded in [Self::source] to be reloaded with
+    /// [Self::reload_if_changed]. The file can be an executable with an [embedded] bytecode or an [archive].
+    #[cfg(feature = "fs")]
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Bytecode> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+        let mut code = archive::load(std::io::Cursor::new(&data), None)?;
+        code.source = Some(reload::Source::new(path, &data)?);
+        Ok(code)
+    }
+
+    /// Load the bytecode from an async source (network stream, archive entry, async file ...) without blocking the
+    /// executor while waiting for data. Works with any runtime, the data is parsed once it has been read entirely.
+    #[cfg(feature = "async")]
+    pub async fn load_async(mut r: impl futures_lite::AsyncRead + Unpin) -> Result<Bytecode> {
+        use futures_lite::AsyncReadExt;
+
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).await?;
+        Self::from_bytes(&data)
+    }
+
+    /// Parse the bytecode, function bodies are skipped if `bodies` is given and their location is stored in it
+    fn read<R: Read>(
+        r: &mut CountingReader<R>,
+        mut bodies: Option<&mut Vec<(u64, usize)>>,
+        opts: &ParseOptions,
+    ) -> Result<(Bytecode, u32)> {
         let mut header = [0u8; 3];
         r.read_exact(&mut header)?;
         if header != [b'H', b'L', b'B'] {
-            return Err(Error::InvalidMagic(header));
+            return Err(ParseError::InvalidMagic(header).into());
         }
         let version = r.read_u8()?;
-        if version < 4 {
-            return Err(Error::UnsupportedVersion(version));
+        if version < version::MIN {
+            return Err(ParseError::UnsupportedVersion(version).into());
         }
         let flags = r.read_varu()?;
         let has_debug = flags & 1 == 1;
-        let nints = r.read_varu()? as usize;
-        let nfloats = r.read_varu()? as usize;
+        let count = |r: &mut CountingReader<R>, what| {
+            ParseOptions::check(r.read_varu()? as usize, opts.max_elements, what)
+        };
+        let nints = count(r, "ints")?;
+        let nfloats = count(r, "floats")?;
         let nstrings = r.read_varu()? as usize;
-        let nbytes = if version >= 5 {
-            Some(r.read_varu()? as usize)
+        let nbytes = if version::has_bytes(version) {
+            Some(count(r, "bytes")?)
         } else {
             None
         };
-        let ntypes = r.read_varu()? as usize;
-        let nglobals = r.read_varu()? as usize;
-        let nnatives = r.read_varu()? as usize;
-        let nfunctions = r.read_varu()? as usize;
-        let nconstants = if version >= 4 {
-            Some(r.read_varu()? as usize)
+        let ntypes = count(r, "types")?;
+        let nglobals = count(r, "globals")?;
+        let nnatives = count(r, "natives")?;
+        let nfunctions = count(r, "functions")?;
+        let nconstants = if version::has_constants(version) {
+            Some(count(r, "constants")?)
         } else {
             None
         };
         let entrypoint = RefFun(r.read_varu()? as usize);
 
         let mut ints = vec![0i32; nints];
-        for i in ints.iter_mut() {
+        for (idx, i) in ints.iter_mut().enumerate() {
+            r.enter(Section::Ints, Some(idx));
             *i = r.read_i32::<LittleEndian>()?;
         }
 
         let mut floats = vec![0f64; nfloats];
-        for i in floats.iter_mut() {
+        for (idx, i) in floats.iter_mut().enumerate() {
+            r.enter(Section::Floats, Some(idx));
             *i = r.read_f64::<LittleEndian>()?;
         }
 
-        let strings = r.read_strings(nstrings)?;
+        r.enter(Section::Strings, None);
+        let strings = deser::read_strings(r, nstrings, opts)?;
 
         let bytes = if let Some(nbytes) = nbytes {
-            let size = r.read_i32::<LittleEndian>()? as usize;
-            let mut bytes = vec![0; size];
-            r.read_exact(&mut bytes)?;
+            r.enter(Section::Bytes, None);
+            let size = r.read_i32::<LittleEndian>()?;
+            if size < 0 {
+                return Err(ParseError::InvalidSize(size).into());
+            }
+            let size = ParseOptions::check(size as usize, opts.max_block_size, "bytes of bytes")?;
+            let bytes = deser::read_block(r, size)?;
             let mut pos = Vec::with_capacity(nbytes);
             for _ in 0..nbytes {
                 pos.push(r.read_varu()? as usize);
@@ -136,44 +264,106 @@ impl Bytecode {
         };
 
         let debug_files = if has_debug {
+            r.enter(Section::DebugFiles, None);
             let n = r.read_varu()? as usize;
-            Some(r.read_strings(n)?)
+            Some(deser::read_strings(r, n, opts)?)
         } else {
             None
         };
 
         let mut types = Vec::with_capacity(ntypes);
-        for _ in 0..ntypes {
-            types.push(r.read_type()?);
+        for i in 0..ntypes {
+            r.enter(Section::Types, Some(i));
+            types.push(deser::read_type(r, opts)?);
         }
 
         let mut globals = Vec::with_capacity(nglobals);
-        for _ in 0..nglobals {
+        for i in 0..nglobals {
+            r.enter(Section::Globals, Some(i));
             globals.push(r.read_type_ref()?);
         }
 
         let mut natives = Vec::with_capacity(nnatives);
-        for _ in 0..nnatives {
+        for i in 0..nnatives {
+            r.enter(Section::Natives, Some(i));
             natives.push(r.read_native()?);
         }
 
         let mut functions = Vec::with_capacity(nfunctions);
-        for _ in 0..nfunctions {
-            functions.push(r.read_function(has_debug, version)?);
+        for i in 0..nfunctions {
+            r.enter(Section::Functions, Some(i));
+            if let Some(bodies) = bodies.as_deref_mut() {
+                let (f, nops) = deser::read_function_header(r, opts)?;
+                bodies.push((r.pos(), nops));
+                deser::skip_function_body(r, nops, has_debug, version)?;
+                functions.push(f);
+            } else {
+                functions.push(deser::read_function(r, has_debug, version, opts)?);
+            }
         }
 
         let constants = if let Some(n) = nconstants {
             let mut constants = Vec::with_capacity(n);
-            for _ in 0..n {
-                constants.push(r.read_constant_def()?)
+            for i in 0..n {
+                r.enter(Section::Constants, Some(i));
+                constants.push(deser::read_constant_def(r, opts)?)
             }
             Some(constants)
         } else {
             None
         };
 
+        // Vendor metadata after the bytecode
+        r.enter(Section::Metadata, None);
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest)?;
+        let metadata = Metadata::parse(&rest);
+
         // Parsing is finished, we now build links between everything
 
+        let nfindexes = nfunctions + nnatives;
+        let findex_refs = functions.iter().map(|f| f.findex);
+        for findex in findex_refs
+            .chain(natives.iter().map(|n| n.findex))
+            .chain([entrypoint])
+        {
+            if findex.0 >= nfindexes {
+                return Err(ResolveError {
+                    kind: "function",
+                    index: findex.0,
+                    len: nfindexes,
+                }
+                .into());
+            }
+        }
+
+        // Checked here since resolving them while linking would panic
+        let ntypes = types.len();
+        for t in &types {
+            if let Some(obj) = t.get_type_obj() {
+                let refs = obj
+                    .super_
+                    .iter()
+                    .map(|s| ("type", s.0, ntypes))
+                    .chain(obj.protos.iter().flat_map(|p| {
+                        [
+                            ("string", p.name.0, strings.len()),
+                            ("function", p.findex.0, nfindexes),
+                        ]
+                    }))
+                    .chain(
+                        obj.bindings
+                            .values()
+                            .map(|findex| ("function", findex.0, nfindexes)),
+                    );
+                for (kind, index, len) in refs {
+                    if index >= len {
+                        return Err(ResolveError { kind, index, len }.into());
+                    }
+                }
+            }
+        }
+
         // Global function indexes
         let mut findexes = vec![RefFunKnown::Fun(0); nfunctions + nnatives];
         for (i, f) in functions.iter().enumerate() {
@@ -223,7 +413,7 @@ impl Bytecode {
                     }
                 }
                 for (fid, findex) in bindings {
-                    if let Some(field) = t.get_type_obj().map(|o| &o.fields[fid.0]) {
+                    if let Some(field) = t.get_type_obj().and_then(|o| o.fields.get(fid.0)) {
                         if let RefFunKnown::Fun(x) = findexes[findex.0] {
                             functions[x].name = Some(field.name);
                             functions[x].parent = Some(RefType(i));
@@ -259,7 +449,7 @@ impl Bytecode {
             HashMap::new()
         };
 
-        Ok(Bytecode {
+        let code = Bytecode {
             version,
             entrypoint,
             ints,
@@ -275,12 +465,35 @@ impl Bytecode {
             findexes,
             fnames,
             globals_initializers,
-        })
+            virtual_names: HashMap::new(),
+            global_offsets: Vec::new(),
+            metadata,
+            warnings: Vec::new(),
+            source: None,
+        };
+        Ok((code, flags))
+    }
+
+    /// Computations needing the code of every function. References are checked first, a loaded bytecode can be indexed
+    /// without checks.
+    fn link(&mut self, flags: u32) -> Result<()> {
+        let errors: Vec<_> = verify::verify(self)
+            .into_iter()
+            .filter(VerifyError::is_out_of_bounds)
+            .collect();
+        if !errors.is_empty() {
+            return Err(Error::InvalidReferences(errors));
+        }
+        self.virtual_names = analysis::names::virtual_names(self);
+        self.global_offsets = self.globals_layout();
+        self.warnings = warnings::check(self, flags);
+        Ok(())
     }
 
     /// Serialize the bytecode to any sink.
     /// Bytecode is serialized to the same format.
     pub fn serialize(&self, w: &mut impl Write) -> Result<()> {
+        self.check_version()?;
         w.write_all(&[b'H', b'L', b'B'])?;
         w.write_u8(self.version)?;
         w.write_vi32(if self.debug_files.is_some() { 1 } else { 0 })?;
@@ -326,6 +539,9 @@ impl Bytecode {
             w.write_native(n)?;
         }
         for f in &self.functions {
+            if self.debug_files.is_some() && f.debug_info.is_none() {
+                return Err(VerifyError::MissingDebugInfo { findex: f.findex }.into());
+            }
             w.write_function(f)?;
         }
         if let Some(constants) = &self.constants {
@@ -333,9 +549,63 @@ impl Bytecode {
                 w.write_constant_def(c)?;
             }
         }
+        if let Some(metadata) = &self.metadata {
+            metadata.write(w)?;
+        }
+        Ok(())
+    }
+
+    /// Bytecode format version
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Check the pools and the debug information exist if and only if the version has them
+    fn check_version(&self) -> Result<()> {
+        let v = self.version;
+        let mismatch = |what| Err(VerifyError::VersionMismatch { version: v, what }.into());
+        if self.bytes.is_some() != version::has_bytes(v) {
+            return mismatch("bytes pool");
+        }
+        if self.constants.is_some() != version::has_constants(v) {
+            return mismatch("constants pool");
+        }
+        let assigns = self.debug_files.is_some() && version::has_assigns(v);
+        if self
+            .functions
+            .iter()
+            .any(|f| f.assigns.is_some() != assigns)
+        {
+            return mismatch("variable assignments");
+        }
         Ok(())
     }
 
+    /// Returns true if the bytecode has been compiled with debug info (file and line information)
+    pub fn has_debug_info(&self) -> bool {
+        self.debug_files.is_some()
+    }
+
+    /// Get a type, returns None if the reference is out of bounds.
+    pub fn get_type(&self, ty: RefType) -> Option<&Type> {
+        self.types.get(ty.0)
+    }
+
+    /// Get a string constant, returns None if the reference is out of bounds.
+    pub fn get_string(&self, s: RefString) -> Option<&str> {
+        self.strings.get(s.0).map(|s| &**s)
+    }
+
+    /// Get a function or a native, returns None if the reference is out of bounds.
+    pub fn get_fun(&self, fun: RefFun) -> Option<FunPtr<'_>> {
+        fun.try_resolve(self).ok()
+    }
+
+    /// Iterate on every type with its reference
+    pub fn iter_types(&self) -> impl Iterator<Item = (RefType, &Type)> {
+        self.types.iter().enumerate().map(|(i, t)| (RefType(i), t))
+    }
+
     /// Get the entrypoint function.
     pub fn entrypoint(&self) -> &Function {
         self.entrypoint.resolve_as_fn(self).unwrap()
@@ -351,20 +621,239 @@ impl Bytecode {
     pub fn function_by_name(&self, name: &str) -> Option<&Function> {
         self.fnames.get(name).map(|&i| &self.functions[i])
     }
+
+    /// Get the synthesized name of a virtual type from this bytecode
+    pub fn virtual_name(&self, ty: RefType) -> Option<&str> {
+        self.virtual_names.get(&ty).map(String::as_str)
+    }
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Invalid magic bytes (expected {:?}, found {0:?})", [b'H', b'L', b'B'])]
-    InvalidMagic([u8; 3]),
-    #[error("Unsupported bytecode version {0} (expected < 4)")]
-    UnsupportedVersion(u8),
-    #[error("Malformed bytecode ({0})")]
-    MalformedBytecode(String),
+    /// The data is not a valid bytecode file. `index` is the element of the section being read, if any.
+    #[error("Malformed bytecode at offset {offset:#x} in {section}{} : {kind}", index.map_or(String::new(), |i| format!(" (element {i})")))]
+    Parse {
+        offset: u64,
+        section: Section,
+        index: Option<usize>,
+        kind: ParseError,
+    },
+    /// A reference to an element that doesn't exist
+    #[error(transparent)]
+    Resolve(#[from] ResolveError),
+    /// The bytecode structure is valid but its content can't be used
+    #[error(transparent)]
+    Verify(#[from] VerifyError),
+    /// The bytecode refers to elements that don't exist, every such [VerifyError] found while loading
+    #[error("{} invalid references, the first one is {}", .0.len(), .0[0])]
+    InvalidReferences(Vec<VerifyError>),
     #[error("Value '{value}' is too big to be serialized (expected < {limit})")]
     ValueOutOfBounds { value: i32, limit: u32 },
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 }
+
+impl Error {
+    /// Returns true if the error comes from the bytecode itself (a bad file) rather than from the environment
+    pub fn is_invalid_bytecode(&self) -> bool {
+        matches!(
+            self,
+            Error::Parse { .. }
+                | Error::Resolve(_)
+                | Error::Verify(_)
+                | Error::InvalidReferences(_)
+        )
+    }
+
+    /// Locate a parse error at `offset`, where reading stopped, in the element `index` of `section`
+    fn at(self, offset: u64, section: Section, index: Option<usize>) -> Error {
+        let kind = match self {
+            Error::Parse { kind, .. } => kind,
+            Error::IoError(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                ParseError::UnexpectedEof
+            }
+            e => return e,
+        };
+        Error::Parse {
+            offset,
+            section,
+            index,
+            kind,
+        }
+    }
+}
+
+/// Errors while reading the bytecode, see [Error::Parse] for the location
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum ParseError {
+    #[error("Invalid magic bytes (expected {:?}, found {0:?})", [b'H', b'L', b'B'])]
+    InvalidMagic([u8; 3]),
+    #[error("Unsupported bytecode version {0} (expected >= {})", version::MIN)]
+    UnsupportedVersion(u8),
+    #[error("Unexpected end of file")]
+    UnexpectedEof,
+    #[error("Got negative index '{0}' (expected > 0)")]
+    NegativeIndex(i32),
+    #[error("Invalid opcode '{0}'")]
+    InvalidOpcode(u8),
+    #[error("Invalid type kind '{0}'")]
+    InvalidTypeKind(u8),
+    #[error("String {0} is outside of the strings block")]
+    InvalidString(usize),
+    #[error("Invalid block size '{0}'")]
+    InvalidSize(i32),
+    #[error("No bytecode found in the executable")]
+    NoEmbeddedBytecode,
+    #[error("No bytecode found in the archive")]
+    NoBytecodeInArchive,
+    #[error("No entry '{0}' in the archive")]
+    MissingEntry(String),
+    #[error("Invalid archive ({0})")]
+    InvalidArchive(String),
+    #[error("Too many {what} ({count}, the limit is {limit})")]
+    LimitExceeded {
+        what: &'static str,
+        count: usize,
+        limit: usize,
+    },
+}
+
+impl From<ParseError> for Error {
+    /// The location is filled in by [Bytecode::load]
+    fn from(kind: ParseError) -> Self {
+        Error::Parse {
+            offset: 0,
+            section: Section::Header,
+            index: None,
+            kind,
+        }
+    }
+}
+
+/// Sections of a bytecode file, in order
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Section {
+    Header,
+    Ints,
+    Floats,
+    Strings,
+    Bytes,
+    DebugFiles,
+    Types,
+    Globals,
+    Natives,
+    Functions,
+    Constants,
+    Metadata,
+}
+
+impl std::fmt::Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Section::Header => "header",
+            Section::Ints => "ints",
+            Section::Floats => "floats",
+            Section::Strings => "strings",
+            Section::Bytes => "bytes",
+            Section::DebugFiles => "debug files",
+            Section::Types => "types",
+            Section::Globals => "globals",
+            Section::Natives => "natives",
+            Section::Functions => "functions",
+            Section::Constants => "constants",
+            Section::Metadata => "metadata",
+        })
+    }
+}
+
+/// A reference to an element outside of its pool
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("Invalid reference to {kind} {index} (there are {len})")]
+pub struct ResolveError {
+    /// Kind of the element
+    pub kind: &'static str,
+    pub index: usize,
+    /// Size of the pool
+    pub len: usize,
+}
+
+/// The bytecode is inconsistent
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum VerifyError {
+    #[error("fn@{} has type {} which is not a function type", findex.0, ty.0)]
+    NotAFunctionType { findex: RefFun, ty: RefType },
+    #[error("fn@{} : the jump at {pos} goes outside of the function", findex.0)]
+    JumpOutOfBounds { findex: RefFun, pos: usize },
+    #[error("fn@{} has no debug information", findex.0)]
+    MissingDebugInfo { findex: RefFun },
+    #[error("The {what} doesn't match the bytecode version {version}")]
+    VersionMismatch { version: u8, what: &'static str },
+    #[error("fn@{} is a native where a function with code is expected", findex.0)]
+    UnexpectedNative { findex: RefFun },
+    /// `pos` is the instruction, or `None` for the signature and the registers types
+    #[error("fn@{}{} : {error}", findex.0, pos.map_or(String::new(), |p| format!(" at {p}")))]
+    InvalidReference {
+        findex: RefFun,
+        pos: Option<usize>,
+        error: ResolveError,
+    },
+    #[error("fn@{} at {pos} : register {} doesn't exist (there are {nregs})", findex.0, reg.0)]
+    InvalidRegister {
+        findex: RefFun,
+        pos: usize,
+        reg: Reg,
+        nregs: usize,
+    },
+    #[error("fn@{} at {pos} : fn@{} called with {got} arguments (expected {expected})", findex.0, callee.0)]
+    ArityMismatch {
+        findex: RefFun,
+        pos: usize,
+        callee: RefFun,
+        expected: usize,
+        got: usize,
+    },
+    /// A reference outside of its pool in an element of the types, globals, natives or constants pools. `pool` and
+    /// `index` locate the element, e.g. `type` and 23.
+    #[error("{pool}@{index} : {error}")]
+    InvalidPoolReference {
+        pool: &'static str,
+        index: usize,
+        error: ResolveError,
+    },
+    /// A class or an enum of a merged bytecode doesn't match the type with the same name in the base
+    #[error("{name} doesn't match the type with the same name in the base : {reason}")]
+    IncompatibleType { name: String, reason: String },
+}
+
+impl VerifyError {
+    /// Function and instruction the problem was found in, if any
+    pub fn location(&self) -> Option<(RefFun, Option<usize>)> {
+        match *self {
+            VerifyError::NotAFunctionType { findex, .. }
+            | VerifyError::MissingDebugInfo { findex }
+            | VerifyError::UnexpectedNative { findex } => Some((findex, None)),
+            VerifyError::InvalidReference { findex, pos, .. } => Some((findex, pos)),
+            VerifyError::JumpOutOfBounds { findex, pos }
+            | VerifyError::InvalidRegister { findex, pos, .. }
+            | VerifyError::ArityMismatch { findex, pos, .. } => Some((findex, Some(pos))),
+            VerifyError::VersionMismatch { .. }
+            | VerifyError::InvalidPoolReference { .. }
+            | VerifyError::IncompatibleType { .. } => None,
+        }
+    }
+
+    /// Whether following the faulty element panics : a reference out of its pool, a register that doesn't exist, a jump
+    /// outside of the function or a function without a function type. Loading rejects bytecode with those.
+    pub fn is_out_of_bounds(&self) -> bool {
+        matches!(
+            self,
+            VerifyError::NotAFunctionType { .. }
+                | VerifyError::JumpOutOfBounds { .. }
+                | VerifyError::InvalidReference { .. }
+                | VerifyError::InvalidRegister { .. }
+                | VerifyError::InvalidPoolReference { .. }
+        )
+    }
+}
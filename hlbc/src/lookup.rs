@@ -0,0 +1,200 @@
+//! Function lookup by name.
+//!
+//! [Bytecode::fnames] only keeps one function per name and doesn't know about classes. [FunctionIndex] maps every
+//! name to all the functions and natives bearing it, resolves qualified names like `Player.update` and ranks
+//! approximate matches for interactive searches. Build it once and reuse it for every lookup.
+//! ```
+//! use hlbc::builder::BytecodeBuilder;
+//! use hlbc::prelude::*;
+//!
+//! let mut b = BytecodeBuilder::new();
+//! let update = b.findex();
+//! let player = b.class("game.Player", None, &[], &[("update", update)]);
+//! let t = b.method_type(&[player], RefType(0));
+//! b.function(update, t, vec![player], vec![Opcode::Ret { ret: Reg(0) }]);
+//! let code = b.build().unwrap();
+//! let index = code.function_index();
+//! assert_eq!(index.lookup(&code, "Player.update"), [update]);
+//! assert_eq!(index.search("plup"), [update]);
+//! ```
+
+use std::collections::HashMap;
+
+use crate::types::{FunPtr, Function, RefFun};
+use crate::Bytecode;
+
+/// Functions and natives by name, see [lookup](crate::lookup)
+#[derive(Debug, Clone, Default)]
+pub struct FunctionIndex {
+    names: HashMap<String, Vec<RefFun>>,
+    /// Lowercase name and qualified name of every named function, for the searches
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    findex: RefFun,
+    name: String,
+    qualified: String,
+}
+
+impl FunctionIndex {
+    pub fn new(code: &Bytecode) -> Self {
+        let mut index = FunctionIndex::default();
+        for f in &code.functions {
+            if let Some(name) = f.name(code) {
+                let qualified = match class_name(code, f) {
+                    Some(class) => format!("{class}.{name}"),
+                    None => name.to_owned(),
+                };
+                index.add(f.findex, name, qualified);
+            }
+        }
+        for n in &code.natives {
+            let name = n.name(code);
+            index.add(
+                n.findex,
+                name,
+                format!("{}.{name}", n.lib.resolve(&code.strings)),
+            );
+        }
+        for list in index.names.values_mut() {
+            list.sort();
+        }
+        index.entries.sort_by_key(|e| e.findex);
+        index
+    }
+
+    fn add(&mut self, findex: RefFun, name: &str, qualified: String) {
+        self.names.entry(name.to_owned()).or_default().push(findex);
+        self.entries.push(Entry {
+            findex,
+            name: name.to_lowercase(),
+            qualified: qualified.to_lowercase(),
+        });
+    }
+
+    /// Functions and natives named exactly `name`, sorted
+    pub fn get(&self, name: &str) -> &[RefFun] {
+        self.names.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// Functions named `name`, which can be qualified with its class (`Player.update`, `game.Player.update`) or the
+    /// library of a native (`std.string`). Sorted.
+    pub fn lookup(&self, code: &Bytecode, name: &str) -> Vec<RefFun> {
+        let (qualifier, name) = match name.rsplit_once('.') {
+            Some((q, n)) => (Some(q), n),
+            None => (None, name),
+        };
+        self.get(name)
+            .iter()
+            .copied()
+            .filter(|&f| match qualifier {
+                None => true,
+                Some(q) => owner(code, f).map_or(false, |owner| {
+                    owner == q || owner.ends_with(&format!(".{q}"))
+                }),
+            })
+            .collect()
+    }
+
+    /// Functions and natives whose qualified name approximately matches `query`, ignoring case, best matches first :
+    /// exact names, then names starting with the query, names containing it and finally names containing its
+    /// characters in order.
+    pub fn search(&self, query: &str) -> Vec<RefFun> {
+        let query = query.to_lowercase();
+        let mut found: Vec<(u8, usize, RefFun)> = self
+            .entries
+            .iter()
+            .filter_map(|e| {
+                let rank = if e.name == query || e.qualified == query {
+                    0
+                } else if e.name.starts_with(&query) {
+                    1
+                } else if e.qualified.contains(&query) {
+                    2
+                } else if is_subsequence(&query, &e.qualified) {
+                    3
+                } else {
+                    return None;
+                };
+                Some((rank, e.qualified.len(), e.findex))
+            })
+            .collect();
+        found.sort();
+        found.into_iter().map(|(_, _, f)| f).collect()
+    }
+}
+
+impl Bytecode {
+    /// Build an index to find functions by name, see [lookup](crate::lookup)
+    pub fn function_index(&self) -> FunctionIndex {
+        FunctionIndex::new(self)
+    }
+}
+
+/// Class of a method or a static function, the `$` of the type holding the statics is removed
+fn class_name<'a>(code: &'a Bytecode, f: &Function) -> Option<&'a str> {
+    let name = f
+        .parent?
+        .resolve_as_obj(&code.types)?
+        .name
+        .resolve(&code.strings);
+    Some(name.strip_prefix('$').unwrap_or(name))
+}
+
+/// Class of a function or library of a native
+fn owner(code: &Bytecode, findex: RefFun) -> Option<&str> {
+    match findex.resolve(code) {
+        FunPtr::Fun(f) => class_name(code, f),
+        FunPtr::Native(n) => Some(n.lib.resolve(&code.strings)),
+    }
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut chars = haystack.chars();
+    needle.chars().all(|c| chars.any(|h| h == c))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::BytecodeBuilder;
+    use crate::opcodes::Opcode;
+    use crate::types::{Reg, Type};
+
+    #[test]
+    fn lookup() {
+        let mut b = BytecodeBuilder::new();
+        let void = b.ty(Type::Void);
+        let [update, update_enemy, reset, main] = [(); 4].map(|_| b.findex());
+        let player = b.class("game.Player", None, &[], &[("update", update)]);
+        let enemy = b.class("Enemy", None, &[], &[("update", update_enemy)]);
+        b.class("$Enemy", None, &[], &[("reset", reset)]);
+        let ty = b.fun_type(&[], void);
+        let log = b.native("std", "update_log", ty);
+        for (f, this) in [(update, player), (update_enemy, enemy)] {
+            let t = b.method_type(&[this], void);
+            b.function(f, t, vec![this, void], vec![Opcode::Ret { ret: Reg(1) }]);
+        }
+        for f in [reset, main] {
+            b.function(f, ty, vec![void], vec![Opcode::Ret { ret: Reg(0) }]);
+        }
+        let code = b.build().unwrap();
+        let index = code.function_index();
+
+        assert_eq!(index.get("update"), [update, update_enemy]);
+        assert!(index.get("nothing").is_empty());
+        assert_eq!(index.lookup(&code, "update"), [update, update_enemy]);
+        assert_eq!(index.lookup(&code, "Player.update"), [update]);
+        assert_eq!(index.lookup(&code, "game.Player.update"), [update]);
+        assert!(index.lookup(&code, "ayer.update").is_empty());
+        // Statics are qualified with their class
+        assert_eq!(index.lookup(&code, "Enemy.reset"), [reset]);
+        assert_eq!(index.lookup(&code, "std.update_log"), [log]);
+
+        assert_eq!(index.search("UPDATE"), [update_enemy, update, log]);
+        assert_eq!(index.search("enemy."), [reset, update_enemy]);
+        assert_eq!(index.search("enup"), [update_enemy]);
+        assert!(index.search("xyz").is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+//! Provenance of a modified bytecode file.
+//!
+//! A manifest records what a tool changed in a bytecode file, to help troubleshooting mods. It is saved in a sidecar
+//! text file next to the output (see [sidecar_path]), one entry per line :
+//! ```text
+//! tool hlbc 0.5.0
+//! original 8a4b0e57c1f3d9a2
+//! output 13f0c2de44b8a671
+//! description Skip the intro
+//! function 12
+//! string 340
+//! ```
+
+use std::io;
+use std::io::{BufRead, Cursor, Write};
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+
+use crate::types::{RefFun, RefString};
+use crate::{Bytecode, Result};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Manifest {
+    /// Tool that produced the file
+    pub tool: String,
+    /// Hash of the original file
+    pub original: String,
+    /// Hash of the produced file
+    pub output: String,
+    /// What the modification does
+    pub description: String,
+    /// Functions with a different code
+    pub functions: Vec<RefFun>,
+    /// Strings with a different value
+    pub strings: Vec<RefString>,
+}
+
+impl Manifest {
+    /// Compare the original and the produced file to record what changed
+    pub fn new(original: &[u8], output: &[u8], description: &str) -> Result<Self> {
+        let old = Bytecode::load(&mut Cursor::new(original))?;
+        let new = Bytecode::load(&mut Cursor::new(output))?;
+        let code = |f: &crate::Function| -> Result<Vec<u8>> {
+            let mut buf = Vec::new();
+            for o in &f.ops {
+                o.encode(&mut buf)?;
+            }
+            Ok(buf)
+        };
+        let mut functions = Vec::new();
+        for f in &new.functions {
+            let changed = match old.functions.iter().find(|o| o.findex == f.findex) {
+                Some(o) => o.regs != f.regs || code(o)? != code(f)?,
+                None => true,
+            };
+            if changed {
+                functions.push(f.findex);
+            }
+        }
+        let strings = new
+            .strings
+            .iter()
+            .enumerate()
+            .filter(|&(i, s)| old.strings.get(i) != Some(s))
+            .map(|(i, _)| RefString(i))
+            .collect();
+        Ok(Self {
+            tool: concat!("hlbc ", env!("CARGO_PKG_VERSION")).to_owned(),
+            original: file_hash(original),
+            output: file_hash(output),
+            description: description.to_owned(),
+            functions,
+            strings,
+        })
+    }
+
+    /// Check the manifest describes this file
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.output == file_hash(data)
+    }
+
+    /// Load a manifest from its text representation
+    pub fn load(r: impl BufRead) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid manifest entry '{line}'"),
+            )
+        };
+        let mut manifest = Manifest::default();
+        for line in r.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+            match key {
+                "tool" => manifest.tool = value.to_owned(),
+                "original" => manifest.original = value.to_owned(),
+                "output" => manifest.output = value.to_owned(),
+                "description" => manifest.description = value.to_owned(),
+                "function" => manifest
+                    .functions
+                    .push(RefFun(value.parse().map_err(|_| invalid(&line))?)),
+                "string" => manifest
+                    .strings
+                    .push(RefString(value.parse().map_err(|_| invalid(&line))?)),
+                _ => return Err(invalid(&line)),
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Save the manifest to its text representation
+    pub fn save(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "tool {}", self.tool)?;
+        writeln!(w, "original {}", self.original)?;
+        writeln!(w, "output {}", self.output)?;
+        // Keep it on one line
+        writeln!(w, "description {}", self.description.replace('\n', " "))?;
+        for f in &self.functions {
+            writeln!(w, "function {}", f.0)?;
+        }
+        for s in &self.strings {
+            writeln!(w, "string {}", s.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Path of the manifest of a bytecode file
+#[cfg(feature = "fs")]
+pub fn sidecar_path(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".manifest");
+    PathBuf::from(name)
+}
+
+/// Hash of a whole file, stable across hlbc versions
+pub fn file_hash(data: &[u8]) -> String {
+    // FNV-1a
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    format!("{h:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::manifest::Manifest;
+    use crate::types::{RefString, Reg};
+    use crate::Opcode;
+
+    #[test]
+    fn changes() {
+        let mut code = sample();
+        let mut original = Vec::new();
+        code.serialize(&mut original).unwrap();
+        code.functions[1].ops[0] = Opcode::Ret { ret: Reg(0) };
+        code.strings[2] = "changed".into();
+        let mut output = Vec::new();
+        code.serialize(&mut output).unwrap();
+
+        let manifest = Manifest::new(&original, &output, "Test\npatch").unwrap();
+        assert_eq!(manifest.functions, [code.functions[1].findex]);
+        assert_eq!(manifest.strings, [RefString(2)]);
+        assert!(manifest.matches(&output));
+        assert!(!manifest.matches(&original));
+
+        let mut saved = Vec::new();
+        manifest.save(&mut saved).unwrap();
+        let loaded = Manifest::load(saved.as_slice()).unwrap();
+        assert_eq!(loaded.description, "Test patch");
+        assert_eq!(loaded.functions, manifest.functions);
+        assert_eq!(loaded.output, manifest.output);
+    }
+}
@@ -0,0 +1,1009 @@
+//! Merge a bytecode compiled separately, like a mod, into a base module.
+//!
+//! [Bytecode::merge] appends the functions, types, globals and constants of another bytecode to the base, existing
+//! references stay valid and the base can then call the new functions (with [edit](crate::edit) or the assembler).
+//! Both modules are usually compiled with their own copy of the standard library, what the base already has isn't
+//! duplicated :
+//! - strings, ints, floats and types with the same content are shared
+//! - classes, structures, enums and abstracts with the same name are the same type. The base wins : their methods
+//!   are the ones of the base, and the fields, methods and enum constructs used by the instructions are found by name
+//! - natives with the same library and name are the same function
+//!
+//! ```
+//! use hlbc::builder::sample;
+//!
+//! let mut code = sample();
+//! let report = code.merge(&sample()).unwrap();
+//! // Every type is shared, only the functions outside of a class are added
+//! assert_eq!(code.types.len(), sample().types.len());
+//! assert_eq!(code.functions.len(), sample().functions.len() + report.added_functions);
+//! ```
+//!
+//! The entrypoint of the merged bytecode isn't called, [MergeReport::entrypoint] is its findex in the result. It
+//! initializes the statics of the merged classes, but also the ones of the shared classes already initialized by the
+//! base.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::mem;
+
+use crate::extract::{const_pool, ConstPool};
+use crate::opcodes::OperandMut;
+use crate::types::{
+    ConstantDef, EnumConstruct, Function, Native, ObjField, ObjProto, RefEnumConstruct, RefField,
+    RefFun, RefGlobal, RefString, RefType, Reg, TypeFun, TypeObj,
+};
+use crate::{version, Bytecode, Opcode, Result, Type, VerifyError};
+
+/// Outcome of [Bytecode::merge]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MergeReport {
+    /// Findex in the result of each function and native of the merged bytecode, by their original findex
+    pub findexes: Vec<RefFun>,
+    /// Findex in the result of the entrypoint of the merged bytecode
+    pub entrypoint: RefFun,
+    /// Classes, structures, enums and abstracts found in the base by name
+    pub shared_types: Vec<String>,
+    /// Methods of the shared types and natives found in the base
+    pub shared_functions: usize,
+    pub added_functions: usize,
+    pub added_types: usize,
+    pub added_globals: usize,
+}
+
+impl Display for MergeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Added {} functions, {} types and {} globals, entrypoint is fn@{}",
+            self.added_functions, self.added_types, self.added_globals, self.entrypoint.0
+        )?;
+        writeln!(
+            f,
+            "Shared {} functions and {} types with the base",
+            self.shared_functions,
+            self.shared_types.len()
+        )?;
+        for t in &self.shared_types {
+            writeln!(f, "shared {t}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Classes, structures, enums and abstracts are identified by their name
+fn type_name(t: &Type) -> Option<RefString> {
+    match t {
+        Type::Obj(obj) | Type::Struct(obj) => Some(obj.name),
+        Type::Enum { name, .. } | Type::Abstract { name } => Some(*name),
+        _ => None,
+    }
+}
+
+/// Global holding the statics of a class or the constructs of an enum
+fn type_global(t: &Type) -> Option<RefGlobal> {
+    let global = match t {
+        Type::Obj(obj) | Type::Struct(obj) => obj.global,
+        Type::Enum { global, .. } => *global,
+        _ => return None,
+    };
+    // Offset by one, 0 is no global
+    (global.0 > 0).then(|| RefGlobal(global.0 - 1))
+}
+
+/// Add the values missing from `pool`, returns the index of each value in the pool
+fn intern_all<T: Clone, K: Hash + Eq>(
+    pool: &mut Vec<T>,
+    values: &[T],
+    key: impl Fn(&T) -> K,
+) -> Vec<usize> {
+    let mut index = HashMap::with_capacity(pool.len());
+    for (i, v) in pool.iter().enumerate() {
+        index.entry(key(v)).or_insert(i);
+    }
+    values
+        .iter()
+        .map(|v| {
+            *index.entry(key(v)).or_insert_with(|| {
+                pool.push(v.clone());
+                pool.len() - 1
+            })
+        })
+        .collect()
+}
+
+/// Function bound to a method or a field of a class of the base
+fn base_method(code: &Bytecode, t: RefType, name: &str) -> Option<RefFun> {
+    let obj = code.types[t.0].get_type_obj()?;
+    obj.protos
+        .iter()
+        .find(|p| p.name.resolve(&code.strings) == name)
+        .map(|p| p.findex)
+        .or_else(|| {
+            obj.bindings
+                .iter()
+                .find(|(fi, _)| {
+                    obj.fields
+                        .get(fi.0)
+                        .map_or(false, |f| f.name.resolve(&code.strings) == name)
+                })
+                .map(|(_, &f)| f)
+        })
+}
+
+fn incompatible(other: &Bytecode, t: RefType, reason: String) -> crate::Error {
+    VerifyError::IncompatibleType {
+        name: t.display(other).to_string(),
+        reason,
+    }
+    .into()
+}
+
+/// Maps indexes of the merged bytecode to indexes in the result
+struct Remap {
+    strings: Vec<RefString>,
+    ints: Vec<usize>,
+    floats: Vec<usize>,
+    bytes: Vec<usize>,
+    files: Vec<usize>,
+    /// None until the types referenced are known, for types without a name
+    types: Vec<Option<RefType>>,
+    globals: Vec<RefGlobal>,
+    funs: Vec<RefFun>,
+}
+
+impl Remap {
+    fn ty(&self, t: RefType) -> Option<RefType> {
+        self.types[t.0]
+    }
+
+    /// For types resolved already
+    fn known(&self, t: RefType) -> RefType {
+        self.types[t.0].expect("type not merged")
+    }
+
+    /// For references where 0 means none
+    fn global1(&self, g: RefGlobal) -> RefGlobal {
+        if g.0 > 0 {
+            RefGlobal(self.globals[g.0 - 1].0 + 1)
+        } else {
+            g
+        }
+    }
+
+    fn field(&self, f: &ObjField) -> Option<ObjField> {
+        Some(ObjField {
+            name: self.strings[f.name.0],
+            t: self.ty(f.t)?,
+        })
+    }
+
+    fn type_fun(&self, fun: &TypeFun) -> Option<TypeFun> {
+        Some(TypeFun {
+            args: fun
+                .args
+                .iter()
+                .map(|&t| self.ty(t))
+                .collect::<Option<_>>()?,
+            ret: self.ty(fun.ret)?,
+        })
+    }
+
+    /// Bindings are fixed once the fields are flattened
+    fn type_obj(&self, obj: &TypeObj) -> Option<TypeObj> {
+        Some(TypeObj {
+            name: self.strings[obj.name.0],
+            super_: match obj.super_ {
+                Some(t) => Some(self.ty(t)?),
+                None => None,
+            },
+            global: self.global1(obj.global),
+            own_fields: obj
+                .own_fields
+                .iter()
+                .map(|f| self.field(f))
+                .collect::<Option<_>>()?,
+            protos: obj
+                .protos
+                .iter()
+                .map(|p| ObjProto {
+                    name: self.strings[p.name.0],
+                    findex: self.funs[p.findex.0],
+                    pindex: p.pindex,
+                })
+                .collect(),
+            bindings: HashMap::new(),
+            fields: Vec::new(),
+        })
+    }
+
+    /// The type in the result, None if it references a type not resolved yet
+    fn type_(&self, t: &Type) -> Option<Type> {
+        Some(match t {
+            Type::Fun(fun) => Type::Fun(self.type_fun(fun)?),
+            Type::Method(fun) => Type::Method(self.type_fun(fun)?),
+            Type::Obj(obj) => Type::Obj(self.type_obj(obj)?),
+            Type::Struct(obj) => Type::Struct(self.type_obj(obj)?),
+            Type::Ref(inner) => Type::Ref(self.ty(*inner)?),
+            Type::Null(inner) => Type::Null(self.ty(*inner)?),
+            Type::Packed(inner) => Type::Packed(self.ty(*inner)?),
+            Type::Virtual { fields } => Type::Virtual {
+                fields: fields
+                    .iter()
+                    .map(|f| self.field(f))
+                    .collect::<Option<_>>()?,
+            },
+            Type::Abstract { name } => Type::Abstract {
+                name: self.strings[name.0],
+            },
+            Type::Enum {
+                name,
+                global,
+                constructs,
+            } => Type::Enum {
+                name: self.strings[name.0],
+                global: self.global1(*global),
+                constructs: constructs
+                    .iter()
+                    .map(|c| {
+                        Some(EnumConstruct {
+                            name: self.strings[c.name.0],
+                            params: c
+                                .params
+                                .iter()
+                                .map(|&t| self.ty(t))
+                                .collect::<Option<_>>()?,
+                        })
+                    })
+                    .collect::<Option<_>>()?,
+            },
+            other => other.clone(),
+        })
+    }
+
+    /// Field `f` of the class `t` of the merged bytecode, found by name in the result. Fields of virtual types are
+    /// unchanged.
+    fn obj_field(
+        &self,
+        code: &Bytecode,
+        other: &Bytecode,
+        t: RefType,
+        f: RefField,
+    ) -> Result<RefField> {
+        let name = match other.get_type(t).and_then(Type::get_type_obj) {
+            Some(obj) => match obj.fields.get(f.0) {
+                Some(field) => field.name.resolve(&other.strings),
+                None => return Ok(f),
+            },
+            None => return Ok(f),
+        };
+        code.types[self.known(t).0]
+            .get_type_obj()
+            .and_then(|obj| {
+                obj.fields
+                    .iter()
+                    .position(|f| f.name.resolve(&code.strings) == name)
+            })
+            .map(RefField)
+            .ok_or_else(|| incompatible(other, t, format!("no field {name} in the base")))
+    }
+
+    /// Method `m` of the class `t` of the merged bytecode, found by name in the result
+    fn obj_method(
+        &self,
+        code: &Bytecode,
+        other: &Bytecode,
+        t: RefType,
+        m: RefField,
+    ) -> Result<RefField> {
+        let name = match other.get_type(t).and_then(Type::get_type_obj) {
+            Some(obj) => match obj.protos.get(m.0) {
+                Some(p) => p.name.resolve(&other.strings),
+                None => return Ok(m),
+            },
+            None => return Ok(m),
+        };
+        code.types[self.known(t).0]
+            .get_type_obj()
+            .and_then(|obj| {
+                obj.protos
+                    .iter()
+                    .position(|p| p.name.resolve(&code.strings) == name)
+            })
+            .map(RefField)
+            .ok_or_else(|| incompatible(other, t, format!("no method {name} in the base")))
+    }
+
+    /// Construct `c` of the enum `t` of the merged bytecode, found by name in the result
+    fn construct(
+        &self,
+        code: &Bytecode,
+        other: &Bytecode,
+        t: RefType,
+        c: RefEnumConstruct,
+    ) -> Result<RefEnumConstruct> {
+        let name = match other.get_type(t) {
+            Some(Type::Enum { constructs, .. }) => match constructs.get(c.0) {
+                Some(construct) => construct.name.resolve(&other.strings),
+                None => return Ok(c),
+            },
+            _ => return Ok(c),
+        };
+        // Unnamed constructs are only found by index
+        if name.is_empty() {
+            return Ok(c);
+        }
+        match &code.types[self.known(t).0] {
+            Type::Enum { constructs, .. } => constructs
+                .iter()
+                .position(|c| c.name.resolve(&code.strings) == name),
+            _ => None,
+        }
+        .map(RefEnumConstruct)
+        .ok_or_else(|| incompatible(other, t, format!("no construct {name} in the base")))
+    }
+
+    fn op(&self, code: &Bytecode, other: &Bytecode, fun: &Function, op: &mut Opcode) -> Result<()> {
+        let reg = |r: Reg| fun.regs.get(r.0 as usize).copied();
+        // Indexes depending on the type of a register, the parameters of an enum construct are left as is
+        let mut field = None;
+        let mut construct = None;
+        match &*op {
+            Opcode::Field { obj, field: f, .. } | Opcode::SetField { obj, field: f, .. } => {
+                field = reg(*obj)
+                    .map(|t| self.obj_field(code, other, t, *f))
+                    .transpose()?;
+            }
+            Opcode::GetThis { field: f, .. } | Opcode::SetThis { field: f, .. } => {
+                field = reg(Reg(0))
+                    .map(|t| self.obj_field(code, other, t, *f))
+                    .transpose()?;
+            }
+            Opcode::CallMethod { field: f, args, .. } => {
+                field = args
+                    .first()
+                    .and_then(|&r| reg(r))
+                    .map(|t| self.obj_method(code, other, t, *f))
+                    .transpose()?;
+            }
+            Opcode::CallThis { field: f, .. } => {
+                field = reg(Reg(0))
+                    .map(|t| self.obj_method(code, other, t, *f))
+                    .transpose()?;
+            }
+            Opcode::MakeEnum {
+                dst, construct: c, ..
+            }
+            | Opcode::EnumAlloc { dst, construct: c } => {
+                construct = reg(*dst)
+                    .map(|t| self.construct(code, other, t, *c))
+                    .transpose()?;
+            }
+            Opcode::EnumField {
+                value,
+                construct: c,
+                ..
+            } => {
+                construct = reg(*value)
+                    .map(|t| self.construct(code, other, t, *c))
+                    .transpose()?;
+            }
+            _ => {}
+        }
+
+        let bytes = version::has_bytes(code.version);
+        for operand in op.operands_mut() {
+            match operand {
+                OperandMut::Int(i) => i.0 = self.ints[i.0],
+                OperandMut::Float(x) => x.0 = self.floats[x.0],
+                OperandMut::Bytes(b) if bytes => b.0 = self.bytes[b.0],
+                OperandMut::Bytes(b) => b.0 = self.strings[b.0].0,
+                OperandMut::String(s) => *s = self.strings[s.0],
+                OperandMut::Type(t) => *t = self.known(*t),
+                OperandMut::Fun(f) => *f = self.funs[f.0],
+                OperandMut::Global(g) => *g = self.globals[g.0],
+                OperandMut::Field(f) => {
+                    if let Some(new) = field {
+                        *f = new;
+                    }
+                }
+                OperandMut::Construct(c) => {
+                    if let Some(new) = construct {
+                        *c = new;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Flatten the fields of a class added to the result and give its new methods the slots after the ones of its parents
+fn layout_type(code: &mut Bytecode, t: usize, done: &mut [bool]) {
+    if done[t] {
+        return;
+    }
+    done[t] = true;
+    let super_ = match code.types[t].get_type_obj() {
+        Some(obj) => obj.super_,
+        None => return,
+    };
+    let mut fields = Vec::new();
+    let mut slots: Vec<(String, i32)> = Vec::new();
+    if let Some(s) = super_ {
+        layout_type(code, s.0, done);
+        if let Some(obj) = code.types[s.0].get_type_obj() {
+            fields = obj.fields.clone();
+        }
+        for c in std::iter::once(s).chain(code.supers(s)) {
+            if let Some(obj) = code.types[c.0].get_type_obj() {
+                slots.extend(
+                    obj.protos
+                        .iter()
+                        .map(|p| (p.name.resolve(&code.strings).to_owned(), p.pindex)),
+                );
+            }
+        }
+    }
+    let mut next = slots.iter().map(|&(_, i)| i + 1).max().unwrap_or(0).max(0);
+    let pindexes: Vec<i32> = code.types[t]
+        .get_type_obj()
+        .unwrap()
+        .protos
+        .iter()
+        .map(|p| {
+            if p.pindex < 0 {
+                return p.pindex;
+            }
+            // Overridden methods keep the slot of the parent
+            let name = p.name.resolve(&code.strings);
+            match slots.iter().find(|(n, i)| n == name && *i >= 0) {
+                Some(&(_, i)) => i,
+                None => {
+                    next += 1;
+                    next - 1
+                }
+            }
+        })
+        .collect();
+    let obj = code.types[t].get_type_obj_mut().unwrap();
+    fields.extend_from_slice(&obj.own_fields);
+    obj.fields = fields;
+    for (p, i) in obj.protos.iter_mut().zip(pindexes) {
+        p.pindex = i;
+    }
+}
+
+impl Bytecode {
+    /// Merge another bytecode into this one, see [merge](crate::merge). Nothing is changed if it fails, when a class
+    /// used by the other bytecode is shared but lacks a member in the base for example.
+    pub fn merge(&mut self, other: &Bytecode) -> Result<MergeReport> {
+        if other.version != self.version {
+            return Err(VerifyError::VersionMismatch {
+                version: self.version,
+                what: "merged bytecode",
+            }
+            .into());
+        }
+        let mut code = self.pools();
+
+        let strings = intern_all(&mut code.strings, &other.strings, |s| s.to_string());
+        let bytes = match (&mut code.bytes, &other.bytes) {
+            (Some((data, pos)), Some((other_data, other_pos))) => {
+                let start = data.len();
+                data.extend_from_slice(other_data);
+                other_pos
+                    .iter()
+                    .map(|&p| {
+                        pos.push(start + p);
+                        pos.len() - 1
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let files = match (&mut code.debug_files, &other.debug_files) {
+            (Some(files), Some(other_files)) => intern_all(files, other_files, String::clone),
+            _ => Vec::new(),
+        };
+        let mut m = Remap {
+            strings: strings.into_iter().map(RefString).collect(),
+            ints: intern_all(&mut code.ints, &other.ints, |&i| i),
+            floats: intern_all(&mut code.floats, &other.floats, |f| f.to_bits()),
+            bytes,
+            files,
+            types: vec![None; other.types.len()],
+            globals: Vec::new(),
+            funs: Vec::new(),
+        };
+
+        // Named types first, their content is known once every index is
+        let base_types = code.types.len();
+        let mut names = HashMap::new();
+        for (i, t) in code.types.iter().enumerate() {
+            if let Some(name) = type_name(t).map(|n| n.resolve(&code.strings)) {
+                if !name.is_empty() {
+                    names.entry(name.to_owned()).or_insert(i);
+                }
+            }
+        }
+        let mut shared = Vec::new();
+        let mut added = Vec::new();
+        for (i, t) in other.types.iter().enumerate() {
+            let name = match type_name(t) {
+                Some(name) => name.resolve(&other.strings),
+                None => continue,
+            };
+            match names.get(name).filter(|_| !name.is_empty()) {
+                Some(&b) => {
+                    if mem::discriminant(t) != mem::discriminant(&code.types[b]) {
+                        return Err(incompatible(
+                            other,
+                            RefType(i),
+                            "not the same kind of type in the base".to_owned(),
+                        ));
+                    }
+                    m.types[i] = Some(RefType(b));
+                    shared.push(i);
+                }
+                None => {
+                    m.types[i] = Some(RefType(code.types.len()));
+                    code.types.push(Type::Void);
+                    added.push(i);
+                }
+            }
+        }
+
+        // Other types are shared when they are identical once the types they reference are
+        let named_end = code.types.len();
+        loop {
+            let mut progress = false;
+            for (i, t) in other.types.iter().enumerate() {
+                if m.types[i].is_some() {
+                    continue;
+                }
+                if let Some(t) = m.type_(t) {
+                    let existing = code.types[..base_types]
+                        .iter()
+                        .position(|x| *x == t)
+                        .or_else(|| {
+                            code.types[named_end..]
+                                .iter()
+                                .position(|x| *x == t)
+                                .map(|p| p + named_end)
+                        });
+                    m.types[i] = Some(RefType(existing.unwrap_or_else(|| {
+                        code.types.push(t);
+                        code.types.len() - 1
+                    })));
+                    progress = true;
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+        // Types referencing each other are added as is
+        for (i, t) in m.types.iter_mut().enumerate() {
+            if t.is_none() {
+                *t = Some(RefType(code.types.len()));
+                code.types.push(Type::Void);
+                added.push(i);
+            }
+        }
+
+        // The globals of the shared types are the ones of the base
+        let base_globals = code.globals.len();
+        let mut globals = vec![None; other.globals.len()];
+        for &i in &shared {
+            if let (Some(g), Some(base)) = (
+                type_global(&other.types[i]),
+                type_global(&code.types[m.known(RefType(i)).0]),
+            ) {
+                if let Some(g) = globals.get_mut(g.0) {
+                    *g = Some(base);
+                }
+            }
+        }
+        m.globals = globals
+            .into_iter()
+            .zip(&other.globals)
+            .map(|(g, &t)| {
+                g.unwrap_or_else(|| {
+                    code.globals.push(m.known(t));
+                    RefGlobal(code.globals.len() - 1)
+                })
+            })
+            .collect();
+
+        // Natives by name and methods of the shared types are found in the base
+        let mut next = code
+            .natives
+            .iter()
+            .map(|n| n.findex.0)
+            .chain(code.functions.iter().map(|f| f.findex.0))
+            .max()
+            .map_or(0, |f| f + 1);
+        let natives: HashMap<(&str, &str), RefFun> = code
+            .natives
+            .iter()
+            .map(|n| {
+                (
+                    (n.lib.resolve(&code.strings), n.name.resolve(&code.strings)),
+                    n.findex,
+                )
+            })
+            .collect();
+        let mut funs = vec![RefFun(0); other.findexes.len()];
+        let mut shared_functions = 0;
+        let mut new_natives = Vec::new();
+        for n in &other.natives {
+            let key = (
+                n.lib.resolve(&other.strings),
+                n.name.resolve(&other.strings),
+            );
+            funs[n.findex.0] = match natives.get(&key) {
+                Some(&f) => {
+                    shared_functions += 1;
+                    f
+                }
+                None => {
+                    new_natives.push(n);
+                    next += 1;
+                    RefFun(next - 1)
+                }
+            };
+        }
+        let mut new_functions = Vec::new();
+        for f in &other.functions {
+            let base = f
+                .parent
+                .map(|p| m.known(p))
+                .filter(|p| p.0 < base_types)
+                .zip(f.name(other))
+                .and_then(|(p, name)| base_method(&code, p, name));
+            funs[f.findex.0] = match base {
+                Some(f) => {
+                    shared_functions += 1;
+                    f
+                }
+                None => {
+                    new_functions.push(f);
+                    next += 1;
+                    RefFun(next - 1)
+                }
+            };
+        }
+        m.funs = funs;
+
+        for &i in &added {
+            code.types[m.known(RefType(i)).0] = m.type_(&other.types[i]).unwrap();
+        }
+        let mut done = vec![false; code.types.len()];
+        done[..base_types].iter_mut().for_each(|d| *d = true);
+        for &i in &added {
+            layout_type(&mut code, m.known(RefType(i)).0, &mut done);
+        }
+        for &i in &added {
+            if let Some(obj) = other.types[i].get_type_obj() {
+                let bindings = obj
+                    .bindings
+                    .iter()
+                    .map(|(&fi, &f)| Ok((m.obj_field(&code, other, RefType(i), fi)?, m.funs[f.0])))
+                    .collect::<Result<_>>()?;
+                code.types[m.known(RefType(i)).0]
+                    .get_type_obj_mut()
+                    .unwrap()
+                    .bindings = bindings;
+            }
+        }
+
+        for n in &new_natives {
+            code.natives.push(Native {
+                name: m.strings[n.name.0],
+                lib: m.strings[n.lib.0],
+                t: m.known(n.t),
+                findex: m.funs[n.findex.0],
+            });
+        }
+        let debug = code.debug_files.is_some();
+        let assigns = debug && version::has_assigns(code.version);
+        let mut functions = Vec::with_capacity(new_functions.len());
+        for f in &new_functions {
+            let mut fun = (*f).clone();
+            fun.name = None;
+            fun.parent = None;
+            fun.findex = m.funs[f.findex.0];
+            fun.t = m.known(f.t);
+            for r in &mut fun.regs {
+                *r = m.known(*r);
+            }
+            for o in &mut fun.ops {
+                m.op(&code, other, f, o)?;
+            }
+            fun.debug_info = debug.then(|| match &f.debug_info {
+                Some(info) => info
+                    .iter()
+                    .map(|&(file, line)| (m.files.get(file).copied().unwrap_or(0), line))
+                    .collect(),
+                None => vec![(0, 0); f.ops.len()],
+            });
+            fun.assigns = assigns.then(|| {
+                f.assigns
+                    .iter()
+                    .flatten()
+                    .map(|&(s, pos)| (m.strings[s.0], pos))
+                    .collect()
+            });
+            functions.push(fun);
+        }
+        code.functions.extend(functions);
+
+        // Initializers of the new globals, the fields are found by name in the result
+        let mut constants = Vec::new();
+        for c in other.constants.iter().flatten() {
+            let global = m.globals[c.global.0];
+            if global.0 < base_globals {
+                continue;
+            }
+            let t = other.globals[c.global.0];
+            let fields = t
+                .resolve_as_obj(&other.types)
+                .map(|obj| obj.fields.as_slice())
+                .unwrap_or_default();
+            let values = code.globals[global.0]
+                .resolve_as_obj(&code.types)
+                .map(|obj| obj.fields.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .map(|field| {
+                    let name = field.name.resolve(&code.strings);
+                    let i = fields
+                        .iter()
+                        .position(|f| f.name.resolve(&other.strings) == name)
+                        .filter(|&i| i < c.fields.len())
+                        .ok_or_else(|| {
+                            incompatible(other, t, format!("no value for the field {name}"))
+                        })?;
+                    let v = c.fields[i];
+                    Ok(match const_pool(other, fields[i].t) {
+                        ConstPool::Int => m.ints[v],
+                        ConstPool::Float => m.floats[v],
+                        ConstPool::String => m.strings[v].0,
+                        ConstPool::Type => m.known(RefType(v)).0,
+                        ConstPool::Global => m.globals[v].0,
+                        ConstPool::Raw => v,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            constants.push(ConstantDef {
+                global,
+                fields: values,
+            });
+        }
+        if let Some(pool) = &mut code.constants {
+            pool.extend(constants);
+        }
+
+        let report = MergeReport {
+            entrypoint: m.funs[other.entrypoint.0],
+            findexes: m.funs,
+            shared_types: shared
+                .iter()
+                .filter_map(|&i| type_name(&other.types[i]))
+                .map(|name| name.resolve(&other.strings).to_owned())
+                .collect(),
+            shared_functions,
+            added_functions: new_natives.len() + new_functions.len(),
+            added_types: code.types.len() - base_types,
+            added_globals: code.globals.len() - base_globals,
+        };
+
+        // Loaded back to compute the acceleration structures
+        let mut data = Vec::new();
+        code.serialize(&mut data)?;
+        let mut merged = Bytecode::load(data.as_slice())?;
+        merged.source = self.source.take();
+        *self = merged;
+        Ok(report)
+    }
+
+    /// Copy of the pools, without the acceleration structures
+    fn pools(&self) -> Bytecode {
+        Bytecode {
+            version: self.version,
+            entrypoint: self.entrypoint,
+            ints: self.ints.clone(),
+            floats: self.floats.clone(),
+            strings: self.strings.clone(),
+            bytes: self.bytes.clone(),
+            debug_files: self.debug_files.clone(),
+            types: self.types.clone(),
+            globals: self.globals.clone(),
+            natives: self.natives.clone(),
+            functions: self.functions.clone(),
+            constants: self.constants.clone(),
+            metadata: self.metadata.clone(),
+            warnings: Vec::new(),
+            findexes: Vec::new(),
+            fnames: HashMap::new(),
+            globals_initializers: HashMap::new(),
+            virtual_names: HashMap::new(),
+            global_offsets: Vec::new(),
+            source: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{sample, BytecodeBuilder};
+    use crate::opcodes::Opcode;
+    use crate::types::{FunPtr, RefEnumConstruct, RefField, RefType, Reg, Type};
+    use crate::{verify, Bytecode, Error, VerifyError};
+
+    /// A mod for the sample declaring `Point` with its fields in another order, a subclass of it and `Color` with a
+    /// single construct
+    fn module(field: &str) -> Bytecode {
+        let mut b = BytecodeBuilder::new();
+        let void = RefType(0);
+        let i32_ = b.ty(Type::I32);
+        let bytes = b.ty(Type::Bytes);
+        let main = b.findex();
+        let length = b.findex();
+        let point = b.class(
+            "Point",
+            None,
+            &[("y", i32_), (field, i32_)],
+            &[("length", length)],
+        );
+        let point3 = b.class("Point3", Some(point), &[("z", i32_)], &[]);
+        let color = b.enumeration("Color", &[("Rgb", &[i32_, i32_, i32_])]);
+        let print_t = b.fun_type(&[bytes], void);
+        b.native("std", "sys_print", print_t);
+        let hook_t = b.fun_type(&[], void);
+        let hook = b.native("mod", "hook", hook_t);
+        let length_t = b.method_type(&[point], i32_);
+        b.function(
+            length,
+            length_t,
+            vec![point, i32_],
+            vec![
+                Opcode::GetThis {
+                    dst: Reg(1),
+                    field: RefField(1),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        let main_t = b.fun_type(&[], void);
+        b.function(
+            main,
+            main_t,
+            vec![point3, i32_, color, void, point],
+            vec![
+                Opcode::New { dst: Reg(0) },
+                Opcode::Field {
+                    dst: Reg(1),
+                    obj: Reg(0),
+                    field: RefField(2),
+                },
+                Opcode::SetField {
+                    obj: Reg(0),
+                    field: RefField(0),
+                    src: Reg(1),
+                },
+                Opcode::MakeEnum {
+                    dst: Reg(2),
+                    construct: RefEnumConstruct(0),
+                    args: vec![Reg(1), Reg(1), Reg(1)],
+                },
+                Opcode::CallMethod {
+                    dst: Reg(1),
+                    field: RefField(0),
+                    args: vec![Reg(4)],
+                },
+                Opcode::Call0 {
+                    dst: Reg(3),
+                    fun: hook,
+                },
+                Opcode::Field {
+                    dst: Reg(1),
+                    obj: Reg(4),
+                    field: RefField(1),
+                },
+                Opcode::Ret { ret: Reg(3) },
+            ],
+        );
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn merge_module() {
+        let mut code = sample();
+        let report = code.merge(&module("x")).unwrap();
+        assert!(report.shared_types.contains(&"Point".to_owned()));
+        assert!(report.shared_types.contains(&"Color".to_owned()));
+        // sys_print and Point.length
+        assert_eq!(report.shared_functions, 2);
+        assert_eq!(report.added_functions, 2);
+        assert_eq!(
+            code.functions.len() + code.natives.len(),
+            sample().functions.len() + sample().natives.len() + 2
+        );
+        assert!(verify::verify(&code).is_empty());
+
+        let main = match report.entrypoint.resolve(&code) {
+            FunPtr::Fun(main) => main,
+            FunPtr::Native(_) => panic!("native entrypoint"),
+        };
+        let point3 = main.regs[0];
+        let fields: Vec<&str> = point3
+            .resolve_as_obj(&code.types)
+            .unwrap()
+            .fields
+            .iter()
+            .map(|f| f.name.resolve(&code.strings))
+            .collect();
+        assert_eq!(fields, ["x", "y", "z"]);
+        assert!(matches!(
+            main.ops[1],
+            Opcode::Field {
+                field: RefField(2),
+                ..
+            }
+        ));
+        // y is the second field of the Point of the sample
+        assert!(matches!(
+            main.ops[2],
+            Opcode::SetField {
+                field: RefField(1),
+                ..
+            }
+        ));
+        // Rgb is the second construct of the Color of the sample
+        assert!(matches!(
+            main.ops[3],
+            Opcode::MakeEnum {
+                construct: RefEnumConstruct(1),
+                ..
+            }
+        ));
+        match &main.ops[5] {
+            Opcode::Call0 { fun, .. } => {
+                assert_eq!(fun.name(&code), Some("hook"));
+                assert!(fun.0 >= sample().findexes.len());
+            }
+            op => panic!("unexpected {op:?}"),
+        }
+        assert!(matches!(
+            main.ops[6],
+            Opcode::Field {
+                field: RefField(0),
+                ..
+            }
+        ));
+        let length = &sample().functions[sample().fnames["length"]];
+        assert_eq!(report.findexes[1], length.findex);
+
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        assert!(Bytecode::from_bytes(&data).is_ok());
+    }
+
+    #[test]
+    fn merge_incompatible() {
+        let mut code = sample();
+        let before = code.functions.len();
+        assert!(matches!(
+            code.merge(&module("w")),
+            Err(Error::Verify(VerifyError::IncompatibleType { .. }))
+        ));
+        // Nothing changed
+        assert_eq!(code.functions.len(), before);
+        assert!(code.merge(&module("x")).is_ok());
+    }
+}
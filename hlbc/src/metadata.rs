@@ -0,0 +1,138 @@
+//! Vendor metadata appended to a bytecode file.
+//!
+//! Tools can store their own data (rename maps, provenance, plugin data) in the bytecode file itself. The section is
+//! appended after the bytecode, where the VM stops reading, and is made of named entries followed by a trailer :
+//! ```text
+//! (key length: u32, key: utf8, value length: u32, value)*
+//! section length: u32
+//! "HLBCMETA"
+//! ```
+//! Integers are little endian. A file without the section is loaded with
+//! [Bytecode::metadata](crate::Bytecode::metadata) set to `None` and is written back without it.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::Result;
+
+/// Magic at the end of a file with metadata
+pub const MAGIC: &[u8; 8] = b"HLBCMETA";
+
+/// Named binary entries
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key).map(|v| v.as_slice())
+    }
+
+    /// Insert an entry, returns the previous value
+    pub fn insert(&mut self, key: impl Into<String>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.entries.insert(key.into(), value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Vec<u8>> {
+        self.entries.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Parse the section from the data following the bytecode. Returns `None` if there is no valid section.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let trailer = data.len().checked_sub(MAGIC.len() + 4)?;
+        let mut section = &data[data.len() - section_size(data)?..trailer];
+        let mut entries = BTreeMap::new();
+        while !section.is_empty() {
+            let key = String::from_utf8(take(&mut section)?.to_vec()).ok()?;
+            let value = take(&mut section)?.to_vec();
+            entries.insert(key, value);
+        }
+        Some(Self { entries })
+    }
+
+    /// Write the section, to be placed after the bytecode
+    pub fn write(&self, w: &mut impl Write) -> Result<()> {
+        let mut size = 0;
+        for (k, v) in &self.entries {
+            w.write_u32::<LittleEndian>(k.len() as u32)?;
+            w.write_all(k.as_bytes())?;
+            w.write_u32::<LittleEndian>(v.len() as u32)?;
+            w.write_all(v)?;
+            size += 8 + k.len() + v.len();
+        }
+        w.write_u32::<LittleEndian>(size as u32)?;
+        w.write_all(MAGIC)?;
+        Ok(())
+    }
+}
+
+/// Size of the section at the end of `data`, trailer included
+pub fn section_size(data: &[u8]) -> Option<usize> {
+    let trailer = data.len().checked_sub(MAGIC.len() + 4)?;
+    if &data[trailer + 4..] != MAGIC {
+        return None;
+    }
+    let size = u32::from_le_bytes(data[trailer..trailer + 4].try_into().ok()?) as usize;
+    if size > trailer {
+        return None;
+    }
+    Some(size + 4 + MAGIC.len())
+}
+
+/// Take a length prefixed slice
+fn take<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let value = data.get(4..4 + len)?;
+    *data = &data[4 + len..];
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::metadata::Metadata;
+    use crate::Bytecode;
+
+    #[test]
+    fn roundtrip() {
+        let mut code = sample();
+        let mut plain = Vec::new();
+        code.serialize(&mut plain).unwrap();
+        let loaded = Bytecode::load(&mut plain.as_slice()).unwrap();
+        assert!(loaded.metadata.is_none());
+        let mut again = Vec::new();
+        loaded.serialize(&mut again).unwrap();
+        assert_eq!(plain, again);
+
+        let mut metadata = Metadata::new();
+        metadata.insert("renames", b"fn@12 connect".to_vec());
+        metadata.insert("empty", Vec::new());
+        code.metadata = Some(metadata.clone());
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        assert!(data.starts_with(&plain));
+        let loaded = Bytecode::load(&mut data.as_slice()).unwrap();
+        assert_eq!(loaded.metadata, Some(metadata));
+        assert_eq!(Metadata::parse(b"garbage HLBCMETA"), None);
+    }
+}
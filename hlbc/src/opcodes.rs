@@ -6,9 +6,32 @@ use crate::types::{
 /// Offset for jump instruction. Can be negative, indicating a backward jump.
 pub type JumpOffset = i32;
 
+/// Mutable reference to an operand of an instruction, see [Opcode::operands_mut]
+#[derive(Debug)]
+pub enum OperandMut<'a> {
+    Reg(&'a mut Reg),
+    Regs(&'a mut Vec<Reg>),
+    Offset(&'a mut JumpOffset),
+    Offsets(&'a mut Vec<JumpOffset>),
+    Int(&'a mut RefInt),
+    Float(&'a mut RefFloat),
+    Bytes(&'a mut RefBytes),
+    String(&'a mut RefString),
+    Type(&'a mut RefType),
+    Bool(&'a mut ValBool),
+    Fun(&'a mut RefFun),
+    Field(&'a mut RefField),
+    Global(&'a mut RefGlobal),
+    Construct(&'a mut RefEnumConstruct),
+}
+
 /// Opcodes definitions. The fields are the opcode arguments.
 /// The methods for this struct are generated through a macro because there is no way I would have written code for 98 opcodes.
+///
+/// New opcodes may be added with new bytecode versions, so this enum is non exhaustive.
 #[derive(Debug, Clone, hlbc_derive::OpcodeHelper)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Opcode {
     /// Copy value from *src* into *dst*
     Mov {
@@ -556,3 +579,43 @@ pub enum Opcode {
     /// No-op
     Nop,
 }
+
+impl Opcode {
+    /// Encode this single instruction. Use [Self::encoded_size] to only get its size.
+    pub fn to_bytes(&self) -> crate::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(self.encoded_size());
+        self.encode(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::types::{RefInt, Reg};
+    use crate::Opcode;
+
+    #[test]
+    fn encoded_size() {
+        let code = sample();
+        let large = [
+            Opcode::Int {
+                dst: Reg(300),
+                ptr: RefInt(100000),
+            },
+            Opcode::JAlways { offset: -5000 },
+            Opcode::JAlways { offset: -100 },
+            Opcode::Switch {
+                reg: Reg(0),
+                offsets: vec![0x2000, 3],
+                end: 0,
+            },
+        ];
+        for o in code.functions.iter().flat_map(|f| &f.ops).chain(&large) {
+            let bytes = o.to_bytes().unwrap();
+            assert_eq!(o.encoded_size(), bytes.len(), "{o:?}");
+            let decoded = Opcode::decode(&mut bytes.as_slice()).unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{o:?}"));
+        }
+    }
+}
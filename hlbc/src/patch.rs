@@ -0,0 +1,360 @@
+//! In-place patching of a bytecode file.
+//!
+//! [patch] writes the modifications of a bytecode over its original file instead of serializing everything again.
+//! Every byte outside of the modified function bodies and strings is left untouched, so the output can be diffed
+//! against the original and distributed as a small binary patch.
+//!
+//! A function can be patched if its signature and register count are the same and its new code isn't larger than the
+//! original. Shorter code is padded with `Nop` (and wider encodings of the same values) so the instruction count and
+//! the debug information stay valid. A string can be patched if its new value has the same length in bytes. Strings
+//! added to the pool are ignored as long as they're not used by the code (e.g. names given by the analysis).
+//! The metadata section is replaced if it changed. Anything else requires a full [Bytecode::serialize].
+
+use std::io::{Cursor, Read};
+use std::ops::Range;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::deser::ReadHlExt;
+use crate::ser::vi32_size;
+use crate::types::{RefFun, RefString, Reg};
+use crate::{metadata, version};
+use crate::{Bytecode, Function, Opcode};
+
+/// The modifications can't be written in place
+#[derive(thiserror::Error, Debug)]
+pub enum PatchError {
+    #[error("Can't patch in place, {0} changed")]
+    Unsupported(&'static str),
+    #[error("Can't patch in place, {0} doesn't fit in its original space")]
+    DoesNotFit(String),
+    #[error(transparent)]
+    Bytecode(#[from] crate::Error),
+}
+
+/// Result of a successful [patch]
+#[derive(Debug, Clone)]
+pub struct Patched {
+    /// The patched file
+    pub data: Vec<u8>,
+    /// Functions rewritten
+    pub functions: Vec<RefFun>,
+    /// Strings rewritten
+    pub strings: Vec<RefString>,
+}
+
+/// Position of the patchable elements in a bytecode file
+struct Layout {
+    /// Position of each string
+    strings: Vec<usize>,
+    /// Registers and opcodes of each function
+    functions: Vec<Range<usize>>,
+}
+
+impl Layout {
+    fn scan(data: &[u8]) -> crate::Result<Self> {
+        let mut r = Cursor::new(data);
+        let mut header = [0u8; 4];
+        r.read_exact(&mut header)?;
+        let version = header[3];
+        let has_debug = r.read_varu()? & 1 == 1;
+        let nints = r.read_varu()? as u64;
+        let nfloats = r.read_varu()? as u64;
+        let nstrings = r.read_varu()? as usize;
+        let nbytes = if version::has_bytes(version) {
+            Some(r.read_varu()? as usize)
+        } else {
+            None
+        };
+        let ntypes = r.read_varu()?;
+        let nglobals = r.read_varu()?;
+        let nnatives = r.read_varu()?;
+        let nfunctions = r.read_varu()? as usize;
+        if version::has_constants(version) {
+            r.read_varu()?;
+        }
+        // Entrypoint
+        r.read_varu()?;
+        r.set_position(r.position() + nints * 4 + nfloats * 8);
+
+        let size = r.read_i32::<LittleEndian>()? as u64;
+        let mut pos = r.position() as usize;
+        r.set_position(r.position() + size);
+        let mut strings = Vec::with_capacity(nstrings);
+        for _ in 0..nstrings {
+            strings.push(pos);
+            pos += r.read_varu()? as usize + 1;
+        }
+
+        if let Some(nbytes) = nbytes {
+            let size = r.read_i32::<LittleEndian>()? as u64;
+            r.set_position(r.position() + size);
+            for _ in 0..nbytes {
+                r.read_varu()?;
+            }
+        }
+        if has_debug {
+            let n = r.read_varu()? as usize;
+            r.read_strings::<String>(n)?;
+        }
+        for _ in 0..ntypes {
+            r.read_type()?;
+        }
+        for _ in 0..nglobals {
+            r.read_type_ref()?;
+        }
+        for _ in 0..nnatives {
+            r.read_native()?;
+        }
+
+        let mut functions = Vec::with_capacity(nfunctions);
+        for _ in 0..nfunctions {
+            let start = r.position();
+            r.read_type_ref()?;
+            r.read_varu()?;
+            let nregs = r.read_varu()?;
+            let nops = r.read_varu()?;
+            let body = r.position() as usize;
+            for _ in 0..nregs {
+                r.read_type_ref()?;
+            }
+            for _ in 0..nops {
+                Opcode::decode(&mut r)?;
+            }
+            functions.push(body..r.position() as usize);
+            // Parse again to skip the debug info
+            r.set_position(start);
+            r.read_function(has_debug, version)?;
+        }
+        Ok(Self { strings, functions })
+    }
+}
+
+/// Write the modifications of `code` over `original`, the file it has been loaded from
+pub fn patch(original: &[u8], code: &Bytecode) -> Result<Patched, PatchError> {
+    let old = Bytecode::load(&mut Cursor::new(original))?;
+    check_unchanged(&old, code)?;
+    let layout = Layout::scan(original)?;
+    let mut patched = Patched {
+        data: original.to_vec(),
+        functions: Vec::new(),
+        strings: Vec::new(),
+    };
+
+    for (i, (o, s)) in old.strings.iter().zip(&code.strings).enumerate() {
+        if o != s {
+            if o.len() != s.len() {
+                return Err(PatchError::DoesNotFit(format!("string@{i}")));
+            }
+            let pos = layout.strings[i];
+            patched.data[pos..pos + s.len()].copy_from_slice(s.as_bytes());
+            patched.strings.push(RefString(i));
+        }
+    }
+
+    for ((o, f), range) in old
+        .functions
+        .iter()
+        .zip(&code.functions)
+        .zip(layout.functions)
+    {
+        if f.t != o.t || f.findex != o.findex || f.regs.len() != o.regs.len() {
+            return Err(PatchError::Unsupported("a function signature"));
+        }
+        if f.regs == o.regs && encode_ops(&f.ops)? == encode_ops(&o.ops)? {
+            continue;
+        }
+        let new_string = f.ops.iter().any(|o| match o {
+            Opcode::String { ptr: s, .. }
+            | Opcode::DynGet { field: s, .. }
+            | Opcode::DynSet { field: s, .. } => s.0 >= old.strings.len(),
+            _ => false,
+        });
+        if new_string {
+            return Err(PatchError::Unsupported("the string count"));
+        }
+        let body = encode_body(f, o.ops.len(), range.len())
+            .ok_or_else(|| PatchError::DoesNotFit(format!("fn@{}", f.findex.0)))?;
+        patched.data[range].copy_from_slice(&body);
+        patched.functions.push(f.findex);
+    }
+
+    if old.metadata != code.metadata {
+        let end = original.len()
+            - old
+                .metadata
+                .as_ref()
+                .and_then(|_| metadata::section_size(original))
+                .unwrap_or(0);
+        patched.data.truncate(end);
+        if let Some(m) = &code.metadata {
+            m.write(&mut patched.data)?;
+        }
+    }
+    Ok(patched)
+}
+
+/// Only strings and function bodies can be patched
+fn check_unchanged(old: &Bytecode, new: &Bytecode) -> Result<(), PatchError> {
+    let same_floats = old.floats.len() == new.floats.len()
+        && old
+            .floats
+            .iter()
+            .zip(&new.floats)
+            .all(|(a, b)| a.to_bits() == b.to_bits());
+    if old.version != new.version || old.entrypoint != new.entrypoint {
+        Err(PatchError::Unsupported("the header"))
+    } else if old.ints != new.ints || !same_floats || old.bytes != new.bytes {
+        Err(PatchError::Unsupported("a constant pool"))
+    } else if new.strings.len() < old.strings.len() {
+        Err(PatchError::Unsupported("the string count"))
+    } else if old.debug_files != new.debug_files {
+        Err(PatchError::Unsupported("the debug files"))
+    } else if old.types != new.types {
+        Err(PatchError::Unsupported("a type"))
+    } else if old.globals != new.globals {
+        Err(PatchError::Unsupported("a global"))
+    } else if old.natives != new.natives {
+        Err(PatchError::Unsupported("a native"))
+    } else if old.functions.len() != new.functions.len() {
+        Err(PatchError::Unsupported("the function count"))
+    } else if old.constants != new.constants {
+        Err(PatchError::Unsupported("a constant"))
+    } else {
+        Ok(())
+    }
+}
+
+fn encode_ops(ops: &[Opcode]) -> crate::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    for o in ops {
+        o.encode(&mut buf)?;
+    }
+    Ok(buf)
+}
+
+/// Encode the registers and opcodes of a function in exactly `size` bytes and `nops` instructions.
+/// Returns `None` if that's not possible.
+fn encode_body(f: &Function, nops: usize, size: usize) -> Option<Vec<u8>> {
+    let pads = nops.checked_sub(f.ops.len())?;
+    let min = f.regs.iter().map(|r| vi32_size(r.0 as i32)).sum::<usize>()
+        + f.ops.iter().map(Opcode::encoded_size).sum::<usize>()
+        + pads;
+    let mut slack = size.checked_sub(min)?;
+
+    // Padding instructions are a Nop, or a Mov of the first register on itself to take more space
+    let mut pad_sizes = vec![1; pads];
+    if !f.regs.is_empty() {
+        for s in &mut pad_sizes {
+            let extra = [8, 6, 5, 4, 3, 2]
+                .iter()
+                .copied()
+                .find(|e| *e <= slack)
+                .unwrap_or(0);
+            *s += extra;
+            slack -= extra;
+        }
+    }
+    // Registers types can take up to 4 bytes
+    let mut reg_sizes: Vec<usize> = f.regs.iter().map(|r| vi32_size(r.0 as i32)).collect();
+    for s in &mut reg_sizes {
+        let extra = [4 - *s, 2usize.saturating_sub(*s)]
+            .iter()
+            .copied()
+            .find(|e| *e <= slack)
+            .unwrap_or(0);
+        *s += extra;
+        slack -= extra;
+    }
+    if slack != 0 {
+        return None;
+    }
+
+    let mut buf = Vec::with_capacity(size);
+    for (r, s) in f.regs.iter().zip(reg_sizes) {
+        write_wide(&mut buf, r.0, s);
+    }
+    for o in &f.ops {
+        o.encode(&mut buf).ok()?;
+    }
+    for s in pad_sizes {
+        if s == 1 {
+            Opcode::Nop.encode(&mut buf).ok()?;
+        } else {
+            let (dst, src) = match s {
+                3 => (1, 1),
+                4 => (2, 1),
+                5 => (2, 2),
+                6 => (4, 1),
+                7 => (4, 2),
+                _ => (4, 4),
+            };
+            let mov = Opcode::Mov {
+                dst: Reg(0),
+                src: Reg(0),
+            };
+            buf.push(mov.to_bytes().ok()?[0]);
+            write_wide(&mut buf, 0, dst);
+            write_wide(&mut buf, 0, src);
+        }
+    }
+    debug_assert_eq!(buf.len(), size);
+    Some(buf)
+}
+
+/// Write a positive variable size integer on `size` bytes, the parser accepts these non-minimal encodings
+fn write_wide(buf: &mut Vec<u8>, value: usize, size: usize) {
+    match size {
+        1 => buf.push(value as u8),
+        2 => buf.extend_from_slice(&[0x80 | (value >> 8) as u8, value as u8]),
+        _ => buf.extend_from_slice(&[
+            0xC0 | (value >> 24) as u8,
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::metadata::Metadata;
+    use crate::patch::{patch, PatchError};
+    use crate::types::{RefString, Reg};
+    use crate::{Bytecode, Opcode};
+
+    #[test]
+    fn patch_in_place() {
+        let mut original = Vec::new();
+        sample().serialize(&mut original).unwrap();
+        let mut code = Bytecode::load(&mut original.as_slice()).unwrap();
+        let nops = code.functions[0].ops.len();
+        code.functions[0].ops = vec![Opcode::Ret { ret: Reg(0) }];
+        let s = code.strings.iter().position(|s| s == "Hello").unwrap();
+        code.strings[s] = "World".into();
+        code.strings.push("unused".into());
+        let mut metadata = Metadata::new();
+        metadata.insert("patch", b"test".to_vec());
+        code.metadata = Some(metadata);
+
+        let patched = patch(&original, &code).unwrap();
+        assert!(patched.data.starts_with(&original[..100]));
+        assert_eq!(patched.functions, [code.functions[0].findex]);
+        assert_eq!(patched.strings, [RefString(s)]);
+        let loaded = Bytecode::load(&mut patched.data.as_slice()).unwrap();
+        assert_eq!(loaded.strings[s], "World");
+        assert_eq!(loaded.metadata, code.metadata);
+        let f = &loaded.functions[0];
+        assert_eq!(f.ops.len(), nops);
+        assert!(matches!(f.ops[0], Opcode::Ret { .. }));
+        assert_eq!(f.regs, code.functions[0].regs);
+        assert_eq!(f.debug_info, code.functions[0].debug_info);
+
+        code.strings[s] = "Hello world".into();
+        assert!(matches!(
+            patch(&original, &code),
+            Err(PatchError::DoesNotFit(_))
+        ));
+    }
+}
@@ -0,0 +1,14 @@
+//! Re-exports of the most used items.
+//!
+//! ```
+//! use hlbc::prelude::*;
+//! ```
+
+pub use crate::analysis::IsFromStd;
+pub use crate::opcodes::{JumpOffset, Opcode};
+pub use crate::types::{
+    ConstantDef, EnumConstruct, FunPtr, Function, Native, ObjField, ObjProto, RefBytes,
+    RefEnumConstruct, RefField, RefFloat, RefFun, RefGlobal, RefInt, RefString, RefType, Reg, Type,
+    TypeFun, TypeObj, ValBool,
+};
+pub use crate::{Bytecode, Error, Result};
@@ -0,0 +1,317 @@
+//! Reload a bytecode when its file changes.
+//!
+//! Long running tools (the gui, the cli watch mode) keep a bytecode loaded while the game is recompiled.
+//! [Bytecode::reload_if_changed] checks the file the bytecode was loaded from and replaces it if it changed, pools with
+//! the same content keep their current value so names shared from them stay valid. [Watcher] polls a file and calls
+//! hooks with the [Changes] so each tool can refresh only what needs to be.
+//! ```no_run
+//! # use hlbc::Bytecode;
+//! # use hlbc::reload::Watcher;
+//! let mut code = Bytecode::from_file("game.hl")?;
+//! let mut watcher = Watcher::new("game.hl");
+//! watcher.on_change(|code, changes| {
+//!     println!("{} functions changed", changes.functions.len());
+//! });
+//! loop {
+//!     watcher.poll(&mut code)?;
+//!     std::thread::sleep(std::time::Duration::from_secs(1));
+//! }
+//! # Ok::<(), hlbc::Error>(())
+//! ```
+
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[cfg(feature = "fs")]
+use crate::manifest::file_hash;
+use crate::types::{Function, RefFun};
+#[cfg(feature = "fs")]
+use crate::Result;
+use crate::{archive, Bytecode};
+
+/// File a bytecode was loaded from, recorded by [Bytecode::from_file]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Source {
+    pub path: PathBuf,
+    /// Modification time, when the platform has one
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+    /// Hash of the content, see [file_hash](crate::manifest::file_hash)
+    pub hash: String,
+}
+
+impl Source {
+    /// Record the current state of a file with its content
+    #[cfg(feature = "fs")]
+    pub fn new(path: &Path, data: &[u8]) -> Result<Self> {
+        let meta = std::fs::metadata(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            hash: file_hash(data),
+        })
+    }
+
+    /// The modification time and length of the file are the recorded ones, the content isn't read
+    #[cfg(feature = "fs")]
+    pub fn is_fresh(&self, path: &Path) -> bool {
+        self.path == path
+            && std::fs::metadata(path)
+                .map(|meta| meta.len() == self.len && meta.modified().ok() == self.modified)
+                .unwrap_or(false)
+    }
+}
+
+/// Pools of a bytecode, functions excluded
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Pool {
+    Ints,
+    Floats,
+    Strings,
+    Bytes,
+    DebugFiles,
+    Types,
+    Globals,
+    Natives,
+    Constants,
+    Metadata,
+}
+
+/// Differences between a bytecode and its reloaded version
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Changes {
+    /// Pools with a different content, the others are kept
+    pub pools: Vec<Pool>,
+    /// Functions added or with a different signature, registers, code or debug info
+    pub functions: Vec<RefFun>,
+    /// Functions not in the new version
+    pub removed: Vec<RefFun>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty() && self.functions.is_empty() && self.removed.is_empty()
+    }
+
+    pub fn pool_changed(&self, pool: Pool) -> bool {
+        self.pools.contains(&pool)
+    }
+
+    /// Indexes computed on the whole bytecode (references, call graph) must be rebuilt
+    pub fn is_structural(&self) -> bool {
+        !self.functions.is_empty()
+            || !self.removed.is_empty()
+            || [Pool::Types, Pool::Globals, Pool::Natives]
+                .iter()
+                .any(|&p| self.pool_changed(p))
+    }
+}
+
+/// Encoded instructions, None if one can't be encoded
+fn encoded(f: &Function) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    for o in &f.ops {
+        o.encode(&mut buf).ok()?;
+    }
+    Some(buf)
+}
+
+/// Same code, names and parents excluded as they are changed by renames
+fn same_function(a: &Function, b: &Function) -> bool {
+    a.t == b.t
+        && a.regs == b.regs
+        && a.debug_info == b.debug_info
+        && a.assigns == b.assigns
+        && a.ops.len() == b.ops.len()
+        && encoded(a).map_or(false, |code| Some(code) == encoded(b))
+}
+
+impl Bytecode {
+    /// Replace this bytecode with a new version, keeping the pools with the same content. Returns what changed.
+    pub fn replace_with(&mut self, mut new: Bytecode) -> Changes {
+        let mut changes = Changes::default();
+        // Keep the current pool when the new one has the same content, record it as changed otherwise
+        macro_rules! reuse {
+            ($pool:expr, $field:ident) => {
+                reuse!($pool, $field, self.$field == new.$field)
+            };
+            ($pool:expr, $field:ident, $same:expr) => {
+                if $same {
+                    mem::swap(&mut self.$field, &mut new.$field);
+                } else {
+                    changes.pools.push($pool);
+                }
+            };
+        }
+        reuse!(Pool::Ints, ints);
+        // Bitwise to compare NaNs
+        reuse!(
+            Pool::Floats,
+            floats,
+            self.floats.len() == new.floats.len()
+                && self
+                    .floats
+                    .iter()
+                    .zip(&new.floats)
+                    .all(|(a, b)| a.to_bits() == b.to_bits())
+        );
+        reuse!(Pool::Strings, strings);
+        reuse!(Pool::Bytes, bytes);
+        reuse!(Pool::DebugFiles, debug_files);
+        if self.types == new.types {
+            mem::swap(&mut self.virtual_names, &mut new.virtual_names);
+        }
+        reuse!(Pool::Types, types);
+        reuse!(Pool::Globals, globals);
+        reuse!(Pool::Natives, natives);
+        reuse!(Pool::Constants, constants);
+        reuse!(Pool::Metadata, metadata);
+
+        for f in &new.functions {
+            let same = f
+                .findex
+                .resolve_as_fn(self)
+                .map_or(false, |old| same_function(old, f));
+            if !same {
+                changes.functions.push(f.findex);
+            }
+        }
+        changes.removed = self
+            .functions
+            .iter()
+            .map(|f| f.findex)
+            .filter(|findex| findex.resolve_as_fn(&new).is_none())
+            .collect();
+
+        *self = new;
+        changes
+    }
+
+    /// Reload the bytecode from a file if it differs from the one it was loaded from. The file is only read when its
+    /// modification time or length changed, and only parsed when its content changed. Returns None if nothing changed.
+    ///
+    /// A bytecode not loaded with [Self::from_file] is always reloaded.
+    #[cfg(feature = "fs")]
+    pub fn reload_if_changed(&mut self, path: impl AsRef<Path>) -> Result<Option<Changes>> {
+        let path = path.as_ref();
+        if self.source.as_ref().map_or(false, |s| s.is_fresh(path)) {
+            return Ok(None);
+        }
+        let data = std::fs::read(path)?;
+        let source = Source::new(path, &data)?;
+        if self
+            .source
+            .as_ref()
+            .map_or(false, |s| s.path == path && s.hash == source.hash)
+        {
+            // Touched but identical
+            self.source = Some(source);
+            return Ok(None);
+        }
+        let mut new = archive::load(std::io::Cursor::new(&data), None)?;
+        new.source = Some(source);
+        Ok(Some(self.replace_with(new)))
+    }
+}
+
+type Hook = Box<dyn FnMut(&Bytecode, &Changes)>;
+
+/// Polls a bytecode file and notifies hooks when it is reloaded
+pub struct Watcher {
+    path: PathBuf,
+    hooks: Vec<Hook>,
+}
+
+impl Watcher {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            hooks: Vec::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Register a function called with the new bytecode after every reload
+    pub fn on_change(&mut self, hook: impl FnMut(&Bytecode, &Changes) + 'static) -> &mut Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Reload the bytecode if the file changed and call the hooks
+    #[cfg(feature = "fs")]
+    pub fn poll(&mut self, code: &mut Bytecode) -> Result<Option<Changes>> {
+        let changes = code.reload_if_changed(&self.path)?;
+        if let Some(changes) = &changes {
+            for hook in &mut self.hooks {
+                hook(code, changes);
+            }
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use std::cell::Cell;
+    use std::fs;
+    use std::rc::Rc;
+
+    use crate::builder::sample;
+    use crate::reload::{Pool, Watcher};
+    use crate::types::Reg;
+    use crate::{Bytecode, Opcode};
+
+    #[test]
+    fn reload() {
+        let path = std::env::temp_dir().join(format!("hlbc-reload-{}.hl", std::process::id()));
+        let write = |code: &Bytecode| {
+            let mut data = Vec::new();
+            code.serialize(&mut data).unwrap();
+            fs::write(&path, data).unwrap();
+        };
+        let mut original = sample();
+        write(&original);
+
+        let mut code = Bytecode::from_file(&path).unwrap();
+        assert_eq!(code.source.as_ref().unwrap().path, path);
+        assert!(code.reload_if_changed(&path).unwrap().is_none());
+
+        let calls = Rc::new(Cell::new(0));
+        let mut watcher = Watcher::new(&path);
+        let c = calls.clone();
+        watcher.on_change(move |_, _| c.set(c.get() + 1));
+
+        // Same content written again
+        write(&original);
+        assert!(watcher.poll(&mut code).unwrap().is_none());
+        assert_eq!(calls.get(), 0);
+
+        let shared = code.strings[0].clone();
+        let f = original.functions.len() - 1;
+        let nop = Opcode::Mov {
+            dst: Reg(0),
+            src: Reg(0),
+        };
+        original.functions[f].ops.insert(0, nop);
+        if let Some(debug) = &mut original.functions[f].debug_info {
+            debug.insert(0, debug[0]);
+        }
+        write(&original);
+        let changes = watcher.poll(&mut code).unwrap().unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(changes.functions, [original.functions[f].findex]);
+        assert!(changes.removed.is_empty());
+        assert!(!changes.pool_changed(Pool::Strings));
+        assert!(changes.is_structural());
+        // The unchanged pool is the same
+        assert!(std::ptr::eq(&*shared, &*code.strings[0]));
+        assert_eq!(code.functions[f].ops.len(), original.functions[f].ops.len());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -12,7 +12,7 @@ pub trait WriteHlExt: WriteBytesExt {
     /// Write a variable size integer. Used internally by the other functions.
     fn write_vi32(&mut self, value: i32) -> Result<()>;
     /// Write a strings block
-    fn write_strings(&mut self, strings: &[String]) -> Result<()>;
+    fn write_strings(&mut self, strings: &[impl AsRef<str>]) -> Result<()>;
     /// Write a Fun or Method type
     fn write_type_fun(&mut self, fun: &TypeFun) -> Result<()>;
     /// Write a field definition
@@ -36,15 +36,15 @@ impl<T: Write> WriteHlExt for T {
             if value < 0x2000 {
                 self.write_u8(((value >> 8) | 0xA0) as u8)?;
                 self.write_u8((value & 0xFF) as u8)?;
-            } else if value >= 20000000 {
+            } else if value >= 0x20000000 {
                 return Err(Error::ValueOutOfBounds {
-                    value,
-                    limit: 20000000,
+                    value: -value,
+                    limit: 0x20000000,
                 });
             } else {
                 self.write_u8(((value >> 24) | 0xE0) as u8)?;
-                self.write_u8(((value >> 16) | 0xFF) as u8)?;
-                self.write_u8(((value >> 8) | 0xFF) as u8)?;
+                self.write_u8(((value >> 16) & 0xFF) as u8)?;
+                self.write_u8(((value >> 8) & 0xFF) as u8)?;
                 self.write_u8((value & 0xFF) as u8)?;
             }
         } else if value < 0x80 {
@@ -55,21 +55,21 @@ impl<T: Write> WriteHlExt for T {
         } else if value >= 0x20000000 {
             return Err(Error::ValueOutOfBounds {
                 value,
-                limit: 20000000,
+                limit: 0x20000000,
             });
         } else {
             self.write_u8(((value >> 24) | 0xC0) as u8)?;
-            self.write_u8(((value >> 16) | 0xFF) as u8)?;
-            self.write_u8(((value >> 8) | 0xFF) as u8)?;
+            self.write_u8(((value >> 16) & 0xFF) as u8)?;
+            self.write_u8(((value >> 8) & 0xFF) as u8)?;
             self.write_u8((value & 0xFF) as u8)?;
         }
         Ok(())
     }
 
-    fn write_strings(&mut self, strings: &[String]) -> Result<()> {
+    fn write_strings(&mut self, strings: &[impl AsRef<str>]) -> Result<()> {
         let cstr: Vec<CString> = strings
             .iter()
-            .map(|s| CString::new(s.as_bytes()).unwrap())
+            .map(|s| CString::new(s.as_ref().as_bytes()).unwrap())
             .collect();
         let size = cstr
             .iter()
@@ -239,8 +239,8 @@ impl<T: Write> WriteHlExt for T {
                         self.write_u8(((delta << 3) | 4) as u8)?;
                     } else {
                         self.write_u8((p << 3) as u8)?;
-                        self.write_u8((p << 5) as u8)?;
-                        self.write_u8((p << 13) as u8)?;
+                        self.write_u8((p >> 5) as u8)?;
+                        self.write_u8((p >> 13) as u8)?;
                     }
                     curpos = p;
                 }
@@ -268,6 +268,15 @@ impl<T: Write> WriteHlExt for T {
     }
 }
 
+/// Number of bytes taken by a variable size integer written with [WriteHlExt::write_vi32]
+pub fn vi32_size(value: i32) -> usize {
+    match value.unsigned_abs() {
+        0..=0x7F if value >= 0 => 1,
+        0..=0x1FFF => 2,
+        _ => 4,
+    }
+}
+
 fn flush_repeat(
     w: &mut impl Write,
     curpos: &mut usize,
@@ -289,3 +298,31 @@ fn flush_repeat(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::Bytecode;
+
+    #[test]
+    fn roundtrip() {
+        let mut code = sample();
+        let lines = [3, 3, 3, 40, 41, 5000, 5000, 70000, 2, 1];
+        let f = &mut code.functions[0];
+        f.debug_info = Some(
+            lines
+                .iter()
+                .cycle()
+                .take(f.ops.len())
+                .map(|&l| (0, l))
+                .collect(),
+        );
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let loaded = Bytecode::load(&mut data.as_slice()).unwrap();
+        assert_eq!(loaded.functions[0].debug_info, code.functions[0].debug_info);
+        let mut again = Vec::new();
+        loaded.serialize(&mut again).unwrap();
+        assert_eq!(data, again);
+    }
+}
@@ -0,0 +1,235 @@
+//! Opcode frequency and size statistics of a bytecode.
+//!
+//! [Stats] counts the instructions by opcode, the elements of every pool, the methods of each class and lists the
+//! largest functions. It is serializable with the `serde` feature, save the report of each build of a game and
+//! [Stats::compare] two of them to see what grew.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc::stats::Stats;
+//!
+//! let code = sample();
+//! let stats = Stats::new(&code, 5);
+//! assert_eq!(stats.pools.functions, code.functions.len());
+//! assert!(stats.compare(&stats).is_empty());
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::{Bytecode, Type};
+
+/// Number of elements in each pool
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PoolSizes {
+    pub ints: usize,
+    pub floats: usize,
+    pub strings: usize,
+    /// Total length of the strings in bytes
+    pub string_bytes: usize,
+    pub bytes: usize,
+    pub debug_files: usize,
+    pub types: usize,
+    pub globals: usize,
+    pub natives: usize,
+    pub functions: usize,
+    pub constants: usize,
+    /// Instructions of every function
+    pub ops: usize,
+    /// Registers of every function
+    pub regs: usize,
+}
+
+impl PoolSizes {
+    /// Every size with its name
+    pub fn entries(&self) -> [(&'static str, usize); 13] {
+        [
+            ("ints", self.ints),
+            ("floats", self.floats),
+            ("strings", self.strings),
+            ("string_bytes", self.string_bytes),
+            ("bytes", self.bytes),
+            ("debug_files", self.debug_files),
+            ("types", self.types),
+            ("globals", self.globals),
+            ("natives", self.natives),
+            ("functions", self.functions),
+            ("constants", self.constants),
+            ("ops", self.ops),
+            ("regs", self.regs),
+        ]
+    }
+}
+
+/// Size of a function
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionSize {
+    pub findex: usize,
+    pub name: String,
+    pub ops: usize,
+    pub regs: usize,
+    /// Size of the encoded instructions
+    pub bytes: usize,
+}
+
+/// Statistics of a whole bytecode
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stats {
+    /// Number of instructions by opcode name
+    pub opcodes: BTreeMap<String, usize>,
+    /// Size of the encoded instructions by opcode name
+    pub opcode_bytes: BTreeMap<String, usize>,
+    pub pools: PoolSizes,
+    /// Number of methods (protos and bindings) of each class with methods, by name
+    pub methods: BTreeMap<String, usize>,
+    /// Largest functions by number of instructions, largest first
+    pub largest: Vec<FunctionSize>,
+}
+
+/// Differences between two [Stats], only what changed. Positive values grew.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StatsDiff {
+    pub opcodes: BTreeMap<String, i64>,
+    pub pools: BTreeMap<String, i64>,
+    /// Classes missing from one side count as 0 methods
+    pub methods: BTreeMap<String, i64>,
+}
+
+impl StatsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.opcodes.is_empty() && self.pools.is_empty() && self.methods.is_empty()
+    }
+}
+
+/// Non zero differences of two maps
+fn delta<'a>(
+    new: impl Iterator<Item = (&'a str, usize)>,
+    old: impl Iterator<Item = (&'a str, usize)>,
+) -> BTreeMap<String, i64> {
+    let mut delta: BTreeMap<String, i64> = BTreeMap::new();
+    for (k, v) in new {
+        *delta.entry(k.to_owned()).or_default() += v as i64;
+    }
+    for (k, v) in old {
+        *delta.entry(k.to_owned()).or_default() -= v as i64;
+    }
+    delta.retain(|_, v| *v != 0);
+    delta
+}
+
+impl Stats {
+    /// Compute the statistics of a bytecode, with the `top` largest functions
+    pub fn new(code: &Bytecode, top: usize) -> Self {
+        let mut stats = Stats {
+            pools: PoolSizes {
+                ints: code.ints.len(),
+                floats: code.floats.len(),
+                strings: code.strings.len(),
+                string_bytes: code.strings.iter().map(|s| s.len()).sum(),
+                bytes: code.bytes.as_ref().map_or(0, |(_, offsets)| offsets.len()),
+                debug_files: code.debug_files.as_ref().map_or(0, Vec::len),
+                types: code.types.len(),
+                globals: code.globals.len(),
+                natives: code.natives.len(),
+                functions: code.functions.len(),
+                constants: code.constants.as_ref().map_or(0, Vec::len),
+                ops: 0,
+                regs: 0,
+            },
+            ..Stats::default()
+        };
+
+        let mut sizes = Vec::with_capacity(code.functions.len());
+        for f in &code.functions {
+            let mut bytes = 0;
+            for o in &f.ops {
+                let size = o.encoded_size();
+                bytes += size;
+                *stats.opcodes.entry(o.name().to_owned()).or_default() += 1;
+                *stats.opcode_bytes.entry(o.name().to_owned()).or_default() += size;
+            }
+            stats.pools.ops += f.ops.len();
+            stats.pools.regs += f.regs.len();
+            sizes.push((f, bytes));
+        }
+        sizes.sort_by(|(a, _), (b, _)| {
+            b.ops
+                .len()
+                .cmp(&a.ops.len())
+                .then(a.findex.0.cmp(&b.findex.0))
+        });
+        stats.largest = sizes
+            .into_iter()
+            .take(top)
+            .map(|(f, bytes)| FunctionSize {
+                findex: f.findex.0,
+                name: f.name_default(code).to_string(),
+                ops: f.ops.len(),
+                regs: f.regs.len(),
+                bytes,
+            })
+            .collect();
+
+        for t in &code.types {
+            if let Type::Obj(obj) | Type::Struct(obj) = t {
+                let methods = obj.protos.len() + obj.bindings.len();
+                if methods > 0 {
+                    *stats
+                        .methods
+                        .entry(obj.name.resolve(&code.strings).to_owned())
+                        .or_default() += methods;
+                }
+            }
+        }
+        stats
+    }
+
+    /// What changed since an older build
+    pub fn compare(&self, old: &Stats) -> StatsDiff {
+        StatsDiff {
+            opcodes: delta(counts(&self.opcodes), counts(&old.opcodes)),
+            pools: delta(
+                self.pools.entries().into_iter(),
+                old.pools.entries().into_iter(),
+            ),
+            methods: delta(counts(&self.methods), counts(&old.methods)),
+        }
+    }
+}
+
+fn counts(m: &BTreeMap<String, usize>) -> impl Iterator<Item = (&str, usize)> {
+    m.iter().map(|(k, v)| (k.as_str(), *v))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::stats::Stats;
+    use crate::types::Reg;
+    use crate::Opcode;
+
+    #[test]
+    fn compare() {
+        let mut code = sample();
+        let old = Stats::new(&code, 3);
+        assert_eq!(old.largest.len(), 3);
+        assert!(old.largest[0].ops >= old.largest[1].ops);
+        assert_eq!(old.pools.ops, old.opcodes.values().sum::<usize>());
+        assert_eq!(old.methods["Point"], 2);
+
+        code.functions[0].ops.insert(
+            0,
+            Opcode::Mov {
+                dst: Reg(0),
+                src: Reg(0),
+            },
+        );
+        let diff = Stats::new(&code, 3).compare(&old);
+        assert_eq!(diff.opcodes.len(), 1);
+        assert_eq!(diff.opcodes["Mov"], 1);
+        assert_eq!(diff.pools["ops"], 1);
+        assert!(diff.methods.is_empty());
+    }
+}
@@ -0,0 +1,441 @@
+//! Disassembly syntaxes.
+//!
+//! The same function can be displayed in several dialects implementing [Syntax] :
+//! - [Enhanced], the default display of [Function::display] with names and pseudo code resolved from the context.
+//! - [Canonical], one form per operand kind in the order of the instruction fields, accepted as is by the
+//!   [assembler](crate::asm). Meant for tools and diffs, not for reading.
+//! - [HlDump], raw indexes and relative jumps with lowercase opcode names, following the layout of `hl --dump` to
+//!   compare the output of hlbc with the upstream VM tooling. [HlDump::module] dumps a whole bytecode.
+//!
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc::syntax::Dialect;
+//!
+//! let code = sample();
+//! let f = &code.functions[0];
+//! let listing = f.display_with(&code, "canonical".parse::<Dialect>().unwrap().syntax()).to_string();
+//! assert!(listing.starts_with("fn "));
+//! ```
+
+use std::fmt;
+use std::fmt::{Display, Write};
+use std::str::FromStr;
+
+use crate::opcodes::OperandMut;
+use crate::types::Function;
+use crate::{Bytecode, Opcode};
+
+/// A textual form of instructions and functions
+pub trait Syntax {
+    /// Display the instruction at `pos` in `f`
+    fn op(&self, ctx: &Bytecode, f: &Function, pos: usize) -> String;
+
+    /// Display a function with its registers and instructions
+    fn function(&self, ctx: &Bytecode, f: &Function) -> String {
+        let mut out = format!("{}\n", f.display_header(ctx));
+        for (i, reg) in f.regs.iter().enumerate() {
+            let _ = writeln!(out, "    reg{i:<2} {}", reg.display_id(ctx));
+        }
+        for i in 0..f.ops.len() {
+            let _ = writeln!(out, "{i:>3}: {}", self.op(ctx, f, i));
+        }
+        out
+    }
+}
+
+/// The default syntax, see [Opcode::display] and [Function::display]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Enhanced;
+
+impl Syntax for Enhanced {
+    fn op(&self, ctx: &Bytecode, f: &Function, pos: usize) -> String {
+        f.ops[pos].display(ctx, f, pos as i32, 11).to_string()
+    }
+
+    fn function(&self, ctx: &Bytecode, f: &Function) -> String {
+        f.display(ctx).to_string()
+    }
+}
+
+/// Strict syntax of the assembler : `Name operand, operand` with every operand in the order of the instruction fields.
+/// Registers are `regN`, constants are written by value, other references as `kind@index`, enum constructs as `_N`
+/// and jumps to absolute positions.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Canonical;
+
+impl Syntax for Canonical {
+    fn op(&self, ctx: &Bytecode, f: &Function, pos: usize) -> String {
+        let op = &f.ops[pos];
+        let target = |offset: i32| (pos as i64 + offset as i64 + 1).to_string();
+        let regs = |regs: &[crate::types::Reg]| -> Vec<String> {
+            regs.iter().map(|r| r.to_string()).collect()
+        };
+        let mut operands = Vec::new();
+        for o in op.clone().operands_mut() {
+            match o {
+                OperandMut::Reg(r) => operands.push(r.to_string()),
+                OperandMut::Regs(v) => operands.extend(regs(v)),
+                OperandMut::Offset(o) => operands.push(target(*o)),
+                OperandMut::Offsets(v) => operands.extend(v.iter().map(|&o| target(o))),
+                OperandMut::Int(i) => operands.push(i.resolve(&ctx.ints).to_string()),
+                OperandMut::Float(x) => operands.push(x.resolve(&ctx.floats).to_string()),
+                OperandMut::Bytes(b) => operands.push(format!("bytes@{}", b.0)),
+                // Only the operand of String is unescaped by the assembler
+                OperandMut::String(s) if matches!(op, Opcode::String { .. }) => {
+                    operands.push(format!("{:?}", s.resolve(&ctx.strings)))
+                }
+                OperandMut::String(s) => operands.push(format!("\"{}\"", s.resolve(&ctx.strings))),
+                OperandMut::Type(t) => operands.push(format!("type@{}", t.0)),
+                OperandMut::Bool(b) => operands.push(b.0.to_string()),
+                OperandMut::Fun(fun) => operands.push(format!("fn@{}", fun.0)),
+                OperandMut::Field(field) => operands.push(format!("field@{}", field.0)),
+                OperandMut::Global(g) => operands.push(format!("global@{}", g.0)),
+                OperandMut::Construct(c) => operands.push(format!("_{}", c.0)),
+            }
+        }
+        if operands.is_empty() {
+            op.name().to_owned()
+        } else {
+            format!("{} {}", op.name(), operands.join(", "))
+        }
+    }
+
+    fn function(&self, ctx: &Bytecode, f: &Function) -> String {
+        let mut out = format!("fn {}@{} type@{}\n", f.name_default(ctx), f.findex.0, f.t.0);
+        for (i, reg) in f.regs.iter().enumerate() {
+            let _ = writeln!(out, "reg{i} type@{}", reg.0);
+        }
+        for i in 0..f.ops.len() {
+            let _ = writeln!(out, "{i}: {}", self.op(ctx, f, i));
+        }
+        out
+    }
+}
+
+/// Syntax of `hl --dump` : lowercase opcode names, raw register and pool indexes, relative jumps
+#[derive(Debug, Copy, Clone, Default)]
+pub struct HlDump;
+
+fn join<T: Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Syntax for HlDump {
+    fn op(&self, _ctx: &Bytecode, f: &Function, pos: usize) -> String {
+        let op = &f.ops[pos];
+        let name = op.name().to_lowercase();
+        let args = |args: &[crate::types::Reg]| join(&args.iter().map(|r| r.0).collect::<Vec<_>>());
+        match op {
+            Opcode::Bool { dst, value } => format!("{} {}", value.0, dst.0),
+            Opcode::Call0 { dst, fun } => format!("call {}, f@{}()", dst.0, fun.0),
+            Opcode::Call1 { dst, fun, arg0 } => {
+                format!("call {}, f@{}({})", dst.0, fun.0, arg0.0)
+            }
+            Opcode::Call2 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+            } => format!("call {}, f@{}({},{})", dst.0, fun.0, arg0.0, arg1.0),
+            Opcode::Call3 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+                arg2,
+            } => format!(
+                "call {}, f@{}({},{},{})",
+                dst.0, fun.0, arg0.0, arg1.0, arg2.0
+            ),
+            Opcode::Call4 {
+                dst,
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                arg3,
+            } => format!(
+                "call {}, f@{}({},{},{},{})",
+                dst.0, fun.0, arg0.0, arg1.0, arg2.0, arg3.0
+            ),
+            Opcode::CallN { dst, fun, args: a } => {
+                format!("call {}, f@{}({})", dst.0, fun.0, args(a))
+            }
+            Opcode::CallMethod {
+                dst,
+                field,
+                args: a,
+            } => match a.split_first() {
+                Some((obj, a)) => {
+                    format!("callmethod {}, {}[{}]({})", dst.0, obj.0, field.0, args(a))
+                }
+                None => "callmethod ???".to_owned(),
+            },
+            Opcode::CallThis {
+                dst,
+                field,
+                args: a,
+            } => {
+                format!("callthis {}, [{}]({})", dst.0, field.0, args(a))
+            }
+            Opcode::CallClosure { dst, fun, args: a } => {
+                format!("callclosure {}, {}({})", dst.0, fun.0, args(a))
+            }
+            Opcode::StaticClosure { dst, fun } => format!("staticclosure {}, f@{}", dst.0, fun.0),
+            Opcode::InstanceClosure { dst, fun, obj } => {
+                format!("instanceclosure {}, f@{}({})", dst.0, fun.0, obj.0)
+            }
+            Opcode::VirtualClosure { dst, obj, field } => {
+                format!("virtualclosure {}, {}[{}]", dst.0, obj.0, field.0)
+            }
+            Opcode::GetGlobal { dst, global } => format!("global {}, {}", dst.0, global.0),
+            Opcode::SetGlobal { global, src } => format!("setglobal {}, {}", global.0, src.0),
+            Opcode::Field { dst, obj, field } => {
+                format!("field {},{}[{}]", dst.0, obj.0, field.0)
+            }
+            Opcode::SetField { obj, field, src } => {
+                format!("setfield {}[{}],{}", obj.0, field.0, src.0)
+            }
+            Opcode::GetThis { dst, field } => format!("getthis {},[{}]", dst.0, field.0),
+            Opcode::SetThis { field, src } => format!("setthis [{}],{}", field.0, src.0),
+            Opcode::DynGet { dst, obj, field } => {
+                format!("dynget {},{}[@{}]", dst.0, obj.0, field.0)
+            }
+            Opcode::DynSet { obj, field, src } => {
+                format!("dynset {}[@{}],{}", obj.0, field.0, src.0)
+            }
+            Opcode::GetI8 { dst, bytes, index }
+            | Opcode::GetI16 { dst, bytes, index }
+            | Opcode::GetMem { dst, bytes, index }
+            | Opcode::GetArray {
+                dst,
+                array: bytes,
+                index,
+            } => format!("{name} {},{}[{}]", dst.0, bytes.0, index.0),
+            Opcode::SetI8 { bytes, index, src }
+            | Opcode::SetI16 { bytes, index, src }
+            | Opcode::SetMem { bytes, index, src }
+            | Opcode::SetArray {
+                array: bytes,
+                index,
+                src,
+            } => format!("{name} {}[{}],{}", bytes.0, index.0, src.0),
+            Opcode::Ref { dst, src } => format!("ref {},&{}", dst.0, src.0),
+            Opcode::Unref { dst, src } => format!("unref {},*{}", dst.0, src.0),
+            Opcode::Setref { dst, value } => format!("setref *{},{}", dst.0, value.0),
+            Opcode::MakeEnum {
+                dst,
+                construct,
+                args: a,
+            } => format!("makeenum {}, {}({})", dst.0, construct.0, args(a)),
+            Opcode::EnumAlloc { dst, construct } => {
+                format!("enumalloc {}, {}", dst.0, construct.0)
+            }
+            Opcode::EnumIndex { dst, value } => format!("enumindex {}, {}", dst.0, value.0),
+            Opcode::EnumField {
+                dst,
+                value,
+                construct,
+                field,
+            } => format!(
+                "enumfield {}, {}[{}:{}]",
+                dst.0, value.0, construct.0, field.0
+            ),
+            Opcode::SetEnumField { value, field, src } => {
+                format!("setenumfield {}[{}], {}", value.0, field.0, src.0)
+            }
+            Opcode::Switch { reg, offsets, end } => {
+                format!("switch {} [{}] {}", reg.0, join(offsets), end)
+            }
+            Opcode::Trap { exc, offset } => format!("trap {}, {}", exc.0, offset),
+            _ => {
+                let mut operands = Vec::new();
+                for o in op.clone().operands_mut() {
+                    match o {
+                        OperandMut::Reg(r) => operands.push(r.0.to_string()),
+                        OperandMut::Regs(v) => operands.extend(v.iter().map(|r| r.0.to_string())),
+                        OperandMut::Offset(o) => operands.push(o.to_string()),
+                        OperandMut::Offsets(v) => operands.extend(v.iter().map(|o| o.to_string())),
+                        OperandMut::Int(i) => operands.push(format!("@{}", i.0)),
+                        OperandMut::Float(x) => operands.push(format!("@{}", x.0)),
+                        OperandMut::Bytes(b) => operands.push(format!("@{}", b.0)),
+                        OperandMut::String(s) => operands.push(format!("@{}", s.0)),
+                        OperandMut::Type(t) => operands.push(t.0.to_string()),
+                        OperandMut::Bool(b) => operands.push(b.0.to_string()),
+                        OperandMut::Fun(fun) => operands.push(format!("f@{}", fun.0)),
+                        OperandMut::Field(field) => operands.push(field.0.to_string()),
+                        OperandMut::Global(g) => operands.push(g.0.to_string()),
+                        OperandMut::Construct(c) => operands.push(c.0.to_string()),
+                    }
+                }
+                if operands.is_empty() {
+                    name
+                } else {
+                    format!("{name} {}", operands.join(","))
+                }
+            }
+        }
+    }
+
+    fn function(&self, ctx: &Bytecode, f: &Function) -> String {
+        let mut out = format!(
+            "\tfun@{}({:X}h) {}\n",
+            f.findex.0,
+            f.findex.0,
+            f.t.display(ctx)
+        );
+        if let (Some(debug), Some(files)) = (&f.debug_info, &ctx.debug_files) {
+            if let Some(&(file, line)) = debug.first() {
+                let file = files.get(file).map_or("?", String::as_str);
+                let _ = writeln!(out, "\t; {file}:{line} ({})", f.name_default(ctx));
+            }
+        }
+        for (i, reg) in f.regs.iter().enumerate() {
+            let _ = writeln!(out, "\t\tr{i} {}", reg.display(ctx));
+        }
+        for i in 0..f.ops.len() {
+            let line = f.debug_info.as_ref().map_or(0, |d| d[i].1);
+            let _ = writeln!(out, "\t\t.{line:<5} @{i:X} {}", self.op(ctx, f, i));
+        }
+        out
+    }
+}
+
+impl HlDump {
+    /// Dump a whole bytecode : the pools, then the functions
+    pub fn module(&self, code: &Bytecode) -> String {
+        let mut out = format!("hl v{}\nentry @{}\n", code.version, code.entrypoint.0);
+        let _ = writeln!(out, "{} strings", code.strings.len());
+        for (i, s) in code.strings.iter().enumerate() {
+            let _ = writeln!(out, "\t@{i} : {}", s.escape_default());
+        }
+        if let Some((_, offsets)) = &code.bytes {
+            let _ = writeln!(out, "{} bytes", offsets.len());
+            for (i, offset) in offsets.iter().enumerate() {
+                let _ = writeln!(out, "\t@{i} : {offset}");
+            }
+        }
+        let _ = writeln!(out, "{} ints", code.ints.len());
+        for (i, v) in code.ints.iter().enumerate() {
+            let _ = writeln!(out, "\t@{i} : {v}");
+        }
+        let _ = writeln!(out, "{} floats", code.floats.len());
+        for (i, v) in code.floats.iter().enumerate() {
+            let _ = writeln!(out, "\t@{i} : {v}");
+        }
+        let _ = writeln!(out, "{} globals", code.globals.len());
+        for (i, g) in code.globals.iter().enumerate() {
+            let _ = writeln!(out, "\t@{i} : {}", g.display(code));
+        }
+        let _ = writeln!(out, "{} natives", code.natives.len());
+        for (i, n) in code.natives.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "\t@{i} native {}@{} {} f@{}",
+                n.lib.resolve(&code.strings),
+                n.name.resolve(&code.strings),
+                n.t.display(code),
+                n.findex.0
+            );
+        }
+        let _ = writeln!(out, "{} functions", code.functions.len());
+        for f in &code.functions {
+            out.push_str(&self.function(code, f));
+        }
+        if let Some(constants) = &code.constants {
+            let _ = writeln!(out, "{} constants", constants.len());
+            for c in constants {
+                let _ = writeln!(out, "\t@{} {}", c.global.0, join(&c.fields));
+            }
+        }
+        out
+    }
+}
+
+/// Built-in syntaxes, by name
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Dialect {
+    #[default]
+    Enhanced,
+    Canonical,
+    HlDump,
+}
+
+impl Dialect {
+    pub const ALL: [Dialect; 3] = [Dialect::Enhanced, Dialect::Canonical, Dialect::HlDump];
+
+    pub fn syntax(self) -> &'static dyn Syntax {
+        match self {
+            Dialect::Enhanced => &Enhanced,
+            Dialect::Canonical => &Canonical,
+            Dialect::HlDump => &HlDump,
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Dialect::ALL
+            .into_iter()
+            .find(|d| d.to_string() == s)
+            .ok_or_else(|| format!("Unknown syntax '{s}', expected enhanced, canonical or hldump"))
+    }
+}
+
+impl Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Dialect::Enhanced => "enhanced",
+            Dialect::Canonical => "canonical",
+            Dialect::HlDump => "hldump",
+        })
+    }
+}
+
+impl Function {
+    /// Display the function in another syntax, see [crate::syntax]
+    pub fn display_with<'a>(
+        &'a self,
+        ctx: &'a Bytecode,
+        syntax: &'a dyn Syntax,
+    ) -> impl Display + 'a {
+        fmtools::fmt!({ syntax.function(ctx, self) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::builder::sample;
+    use crate::syntax::{Canonical, Dialect, HlDump, Syntax};
+
+    #[test]
+    fn canonical_round_trip() {
+        let mut code = sample();
+        for i in 0..code.functions.len() {
+            let f = &code.functions[i];
+            let (findex, listing) = (f.findex, Canonical.function(&code, f));
+            let (regs, ops) = (f.regs.clone(), format!("{:?}", f.ops));
+            let assembly = assemble(&mut code, findex, &listing).unwrap();
+            assert_eq!(assembly.regs, regs);
+            assert_eq!(format!("{:?}", assembly.ops), ops, "{listing}");
+        }
+    }
+
+    #[test]
+    fn hl_dump() {
+        let code = sample();
+        let dump = HlDump.module(&code);
+        assert!(dump.starts_with(&format!("hl v{}\n", code.version)));
+        assert!(dump.contains(&format!("{} functions\n", code.functions.len())));
+        assert!(dump.contains("\t@0 : "));
+        assert_eq!("hldump".parse::<Dialect>(), Ok(Dialect::HlDump));
+        assert!("intel".parse::<Dialect>().is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//! Whole program transforms for release builds.
+//!
+//! These run after the Haxe compiler, on the final bytecode :
+//! - [strip_debug_info] removes the file names, line numbers and variable names
+//! - [optimize] removes the instructions doing nothing (`Nop`, moves of a register to itself, jumps to the next
+//!   instruction)
+//! - [anonymize] replaces the names of classes and enums, and the names of enum constructors
+//!
+//! Each one keeps the bytecode valid, it can be serialized and run afterward.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc::transform::{optimize, strip_debug_info};
+//!
+//! let mut code = sample();
+//! strip_debug_info(&mut code);
+//! optimize(&mut code);
+//! assert!(!code.has_debug_info());
+//! code.serialize(&mut Vec::new()).unwrap();
+//! ```
+
+use std::collections::HashMap;
+
+use crate::types::{RefFun, RefString, Str};
+use crate::{analysis, Bytecode, Opcode, Type};
+
+/// Remove the debug information : debug file names, line numbers and variable names. Stack traces only show the
+/// function indexes afterward. Returns the number of strings removed from the pool.
+pub fn strip_debug_info(code: &mut Bytecode) -> usize {
+    code.debug_files = None;
+    for f in &mut code.functions {
+        f.debug_info = None;
+        f.assigns = None;
+    }
+    code.remove_unused_strings()
+}
+
+/// Result of [optimize]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Optimized {
+    /// Number of functions modified
+    pub functions: usize,
+    /// Number of instructions removed
+    pub removed: usize,
+}
+
+/// Remove the instructions without effect. Jumps are fixed to still target the same instructions.
+pub fn optimize(code: &mut Bytecode) -> Optimized {
+    let mut stats = Optimized::default();
+    let findexes: Vec<RefFun> = code.functions.iter().map(|f| f.findex).collect();
+    for (i, findex) in findexes.into_iter().enumerate() {
+        let mut removed = 0;
+        // Removing an instruction can turn a jump over it into a jump to the next instruction
+        loop {
+            let useless: Vec<usize> = code.functions[i]
+                .ops
+                .iter()
+                .enumerate()
+                .filter(|(_, o)| is_useless(o))
+                .map(|(pos, _)| pos)
+                .collect();
+            if useless.is_empty() {
+                break;
+            }
+            removed += useless.len();
+            // From the end so the positions stay valid
+            for &pos in useless.iter().rev() {
+                code.edit().remove_ops(findex, pos..pos + 1);
+            }
+        }
+        if removed > 0 {
+            stats.functions += 1;
+            stats.removed += removed;
+        }
+    }
+    stats
+}
+
+fn is_useless(op: &Opcode) -> bool {
+    match op {
+        Opcode::Nop => true,
+        Opcode::Mov { dst, src } => dst == src,
+        Opcode::JAlways { offset } => *offset == 0,
+        _ => false,
+    }
+}
+
+/// Result of [anonymize]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Anonymized {
+    /// Number of classes and enums renamed
+    pub types: usize,
+    /// Number of enum constructors renamed
+    pub constructs: usize,
+}
+
+/// Replace the names of classes and enums with `T<n>`, a class and the type holding its statics get the same name.
+/// Enum constructors are named `C<n>` in each enum. Fields and methods keep their names, they are needed for dynamic
+/// accesses. The names registered for reflection (`Type.getClassName`) are string constants and are kept, but
+/// `Std.string` on an enum value shows the new names. Returns the number of renamed elements.
+pub fn anonymize(code: &mut Bytecode) -> Anonymized {
+    let mut stats = Anonymized::default();
+    let mut pool: HashMap<Str, usize> = code
+        .strings
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.clone(), i))
+        .collect();
+    let mut intern = |strings: &mut Vec<Str>, value: String| -> RefString {
+        RefString(*pool.entry(value.into()).or_insert_with_key(|value| {
+            strings.push(value.clone());
+            strings.len() - 1
+        }))
+    };
+    // Class names without the `$` of statics
+    let mut names: HashMap<String, String> = HashMap::new();
+    let strings = &mut code.strings;
+    for t in &mut code.types {
+        match t {
+            Type::Obj(obj) | Type::Struct(obj) => {
+                let name = &strings[obj.name.0];
+                let (prefix, base) = match name.strip_prefix('$') {
+                    Some(base) => ("$", base),
+                    None => ("", &**name),
+                };
+                let count = names.len();
+                let anon = names
+                    .entry(base.to_owned())
+                    .or_insert_with(|| format!("T{count}"));
+                let anon = format!("{prefix}{anon}");
+                obj.name = intern(strings, anon);
+                stats.types += 1;
+            }
+            Type::Enum {
+                name, constructs, ..
+            } => {
+                let count = names.len();
+                let anon = names
+                    .entry(strings[name.0].to_string())
+                    .or_insert_with(|| format!("T{count}"))
+                    .clone();
+                *name = intern(strings, anon);
+                for (i, c) in constructs.iter_mut().enumerate() {
+                    // Unnamed constructors stay unnamed
+                    if c.name.0 != 0 {
+                        c.name = intern(strings, format!("C{i}"));
+                        stats.constructs += 1;
+                    }
+                }
+                stats.types += 1;
+            }
+            _ => {}
+        }
+    }
+    code.remove_unused_strings();
+    code.virtual_names = analysis::names::virtual_names(code);
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{sample, BytecodeBuilder};
+    use crate::opcodes::Opcode;
+    use crate::transform::{anonymize, optimize, strip_debug_info, Optimized};
+    use crate::types::{Reg, Type};
+    use crate::Bytecode;
+
+    fn reload(code: &Bytecode) -> Bytecode {
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        Bytecode::from_bytes(&data).unwrap()
+    }
+
+    #[test]
+    fn strip() {
+        let mut code = sample();
+        assert!(code.has_debug_info());
+        strip_debug_info(&mut code);
+        let code = reload(&code);
+        assert!(!code.has_debug_info());
+        assert!(code.functions.iter().all(|f| f.debug_info.is_none()));
+    }
+
+    #[test]
+    fn peephole() {
+        let mut b = BytecodeBuilder::new();
+        let i32_ = b.ty(Type::I32);
+        let ty = b.fun_type(&[i32_], i32_);
+        let findex = b.findex();
+        let ops = vec![
+            Opcode::JAlways { offset: 1 },
+            Opcode::Nop,
+            Opcode::Mov {
+                dst: Reg(0),
+                src: Reg(0),
+            },
+            Opcode::JFalse {
+                cond: Reg(0),
+                offset: 2,
+            },
+            Opcode::Mov {
+                dst: Reg(1),
+                src: Reg(0),
+            },
+            Opcode::Nop,
+            Opcode::Ret { ret: Reg(1) },
+        ];
+        b.function(findex, ty, vec![i32_, i32_], ops);
+        let mut code = b.build().unwrap();
+        assert_eq!(
+            optimize(&mut code),
+            Optimized {
+                functions: 1,
+                removed: 4
+            }
+        );
+        let f = findex.resolve_as_fn(&code).unwrap();
+        // The conditional jump still skips the move
+        let expected = [
+            Opcode::JFalse {
+                cond: Reg(0),
+                offset: 1,
+            },
+            Opcode::Mov {
+                dst: Reg(1),
+                src: Reg(0),
+            },
+            Opcode::Ret { ret: Reg(1) },
+        ];
+        assert_eq!(format!("{:?}", f.ops), format!("{expected:?}"));
+    }
+
+    #[test]
+    fn anonymous() {
+        let mut code = sample();
+        let stats = anonymize(&mut code);
+        let code = reload(&code);
+        assert!(stats.types > 0);
+        for t in &code.types {
+            if let Some(obj) = t.get_type_obj() {
+                let name = obj.name.resolve(&code.strings);
+                assert!(name.trim_start_matches('$').starts_with('T'), "{name}");
+            }
+        }
+    }
+}
@@ -1,55 +1,207 @@
+use std::borrow::Borrow;
 use std::collections::HashMap;
-
-use crate::{Bytecode, Opcode};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::{Bytecode, Opcode, ResolveError};
+
+/// Get an element of a pool, for the `try_resolve` methods
+fn lookup<'a, T>(pool: &'a [T], kind: &'static str, index: usize) -> Result<&'a T, ResolveError> {
+    pool.get(index).ok_or(ResolveError {
+        kind,
+        index,
+        len: pool.len(),
+    })
+}
 
 /// A register argument
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Reg(pub u32);
 
 /// A reference to the i32 constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefInt(pub usize);
 
 impl RefInt {
     pub fn resolve(&self, ints: &[i32]) -> i32 {
         ints[self.0]
     }
+
+    /// Like [RefInt::resolve], with an error instead of a panic if the reference is out of bounds
+    pub fn try_resolve(&self, ints: &[i32]) -> Result<i32, ResolveError> {
+        Ok(*lookup(ints, "int", self.0)?)
+    }
 }
 
 /// A reference to the f64 constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefFloat(pub usize);
 
 impl RefFloat {
     pub fn resolve(&self, floats: &[f64]) -> f64 {
         floats[self.0]
     }
+
+    /// Like [RefFloat::resolve], with an error instead of a panic if the reference is out of bounds
+    pub fn try_resolve(&self, floats: &[f64]) -> Result<f64, ResolveError> {
+        Ok(*lookup(floats, "float", self.0)?)
+    }
 }
 
 /// A reference to the bytes constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefBytes(pub usize);
 
 /// Reference to the string constant pool
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefString(pub usize);
 
 impl RefString {
-    pub fn resolve<'a>(&self, strings: &'a [String]) -> &'a str {
+    pub fn resolve<'a>(&self, strings: &'a [Str]) -> &'a str {
         &strings[self.0]
     }
+
+    /// Like [RefString::resolve], with an error instead of a panic if the reference is out of bounds
+    pub fn try_resolve<'a>(&self, strings: &'a [Str]) -> Result<&'a str, ResolveError> {
+        lookup(strings, "string", self.0).map(|s| &**s)
+    }
+
+    /// Get the string as a [Str] to keep it around, without copying it
+    pub fn resolve_shared(&self, strings: &[Str]) -> Str {
+        strings[self.0].clone()
+    }
+}
+
+/// An immutable string of the string pool. Names are copied around a lot by the analyses and the decompiler, cloning
+/// a [Str] only increments a reference count.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Str(Arc<str>);
+
+impl Deref for Str {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Str {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Str {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Str {
+    fn default() -> Self {
+        Str::from("")
+    }
+}
+
+impl From<&str> for Str {
+    fn from(s: &str) -> Self {
+        Str(Arc::from(s))
+    }
+}
+
+impl From<String> for Str {
+    fn from(s: String) -> Self {
+        Str(Arc::from(s))
+    }
+}
+
+impl From<Str> for String {
+    fn from(s: Str) -> Self {
+        s.0.as_ref().to_owned()
+    }
+}
+
+impl fmt::Debug for Str {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for Str {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl PartialEq<str> for Str {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Str {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Str {
+    fn eq(&self, other: &String) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl PartialEq<Str> for str {
+    fn eq(&self, other: &Str) -> bool {
+        self == &*other.0
+    }
+}
+
+impl PartialEq<Str> for &str {
+    fn eq(&self, other: &Str) -> bool {
+        *self == &*other.0
+    }
+}
+
+impl PartialEq<Str> for String {
+    fn eq(&self, other: &Str) -> bool {
+        **self == *other.0
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Str {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Str {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Str::from)
+    }
 }
 
 /// An inline bool value
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValBool(pub bool);
 
 /// A reference to a global
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefGlobal(pub usize);
 
 /// An object field definition
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ObjField {
     /// Field name
     pub name: RefString,
@@ -59,21 +211,24 @@ pub struct ObjField {
 
 /// A reference to an object field
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefField(pub usize);
 
 /// An object method definition
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ObjProto {
     /// Method name
     pub name: RefString,
     /// Function bound to this method
     pub findex: RefFun,
-    /// Don't know what this is used for
+    /// Slot of the method in the virtual table of the class, -1 if it isn't virtual
     pub pindex: i32,
 }
 
 /// An enum variant definition
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnumConstruct {
     /// Variant name, can be null (pointing to 0)
     // TODO wrap this in an option
@@ -84,10 +239,12 @@ pub struct EnumConstruct {
 
 /// A reference to an enum variant
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefEnumConstruct(pub usize);
 
 /// Common type for [Type::Fun] and [Type::Method]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeFun {
     pub args: Vec<RefType>,
     pub ret: RefType,
@@ -95,6 +252,7 @@ pub struct TypeFun {
 
 /// Common type for [Type::Obj] and [Type::Struct]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeObj {
     pub name: RefString,
     pub super_: Option<RefType>,
@@ -123,7 +281,11 @@ impl TypeObj {
 }
 
 /// Type available in the hashlink type system. Every type is one of those.
+///
+/// New types may be added with new bytecode versions, so this enum is non exhaustive.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Type {
     Void,
     UI8,
@@ -185,7 +347,8 @@ impl Type {
 }
 
 /// Reference to a type in the constant pool
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefType(pub usize);
 
 impl RefType {
@@ -193,35 +356,39 @@ impl RefType {
         &types[self.0]
     }
 
+    /// Like [RefType::resolve], with an error instead of a panic if the reference is out of bounds
+    pub fn try_resolve<'a>(&self, types: &'a [Type]) -> Result<&'a Type, ResolveError> {
+        lookup(types, "type", self.0)
+    }
+
     pub fn is_void(&self) -> bool {
         self.0 == 0
     }
 
+    /// `None` if the type isn't a function type or doesn't exist
     pub fn resolve_as_fun<'a>(&self, types: &'a [Type]) -> Option<&'a TypeFun> {
-        match self.resolve(types) {
-            Type::Fun(fun) => Some(fun),
-            Type::Method(fun) => Some(fun),
-            _ => None,
-        }
+        self.try_resolve(types).ok()?.get_type_fun()
     }
 
+    /// `None` if the type isn't an object type or doesn't exist
     pub fn resolve_as_obj<'a>(&self, types: &'a [Type]) -> Option<&'a TypeObj> {
-        self.resolve(types).get_type_obj()
+        self.try_resolve(types).ok()?.get_type_obj()
     }
 
     pub fn field<'a>(&self, field: RefField, code: &'a Bytecode) -> Option<&'a ObjField> {
         self.resolve_as_obj(&code.types)
-            .map(|obj| &obj.fields[field.0])
+            .and_then(|obj| obj.fields.get(field.0))
     }
 
     pub fn method<'a>(&self, meth: usize, code: &'a Bytecode) -> Option<&'a ObjProto> {
         self.resolve_as_obj(&code.types)
-            .map(|obj| &obj.protos[meth])
+            .and_then(|obj| obj.protos.get(meth))
     }
 }
 
 /// A native function reference. Contains no code but indicates the library from where to load it.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Native {
     /// Native function name
     pub name: RefString,
@@ -253,6 +420,7 @@ impl Native {
 
 /// A function definition with its code.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Function {
     pub name: Option<RefString>,
     pub t: RefType,
@@ -321,11 +489,11 @@ impl Function {
     }
 
     /// Uses the assigns to find the name of a variable
-    pub fn var_name(&self, code: &Bytecode, pos: usize) -> Option<String> {
+    pub fn var_name(&self, code: &Bytecode, pos: usize) -> Option<Str> {
         self.assigns.as_ref().and_then(|a| {
             a.iter().find_map(|&(s, i)| {
                 if pos + 1 == i {
-                    Some(s.resolve(&code.strings).to_owned())
+                    Some(s.resolve_shared(&code.strings))
                 } else {
                     None
                 }
@@ -343,6 +511,7 @@ impl Function {
 
 /// Index reference to a function or a native in the pool (findex)
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RefFun(pub usize);
 
 impl RefFun {
@@ -350,9 +519,15 @@ impl RefFun {
         code.findexes[self.0].resolve(code)
     }
 
-    /// Useful when you already know you should be getting a Function
+    /// Like [RefFun::resolve], with an error instead of a panic if the reference is out of bounds
+    pub fn try_resolve<'a>(&self, code: &'a Bytecode) -> Result<FunPtr<'a>, ResolveError> {
+        lookup(&code.findexes, "function", self.0).map(|f| f.resolve(code))
+    }
+
+    /// Useful when you already know you should be getting a Function. `None` for a native or a function that doesn't
+    /// exist.
     pub fn resolve_as_fn<'a>(&self, code: &'a Bytecode) -> Option<&'a Function> {
-        code.findexes[self.0].resolve_as_fn(code)
+        code.findexes.get(self.0)?.resolve_as_fn(code)
     }
 
     pub fn name<'a>(&self, code: &'a Bytecode) -> Option<&'a str> {
@@ -387,6 +562,7 @@ impl RefFun {
 
 // Index reference to either a function or a native.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RefFunKnown {
     Fun(usize),
     Native(usize),
@@ -433,8 +609,54 @@ impl FunPtr<'_> {
 }
 
 /// A constant definition
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConstantDef {
     pub global: RefGlobal,
     pub fields: Vec<usize>,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::types::{FunPtr, RefField, RefFun, RefInt, RefString, RefType};
+    use crate::ResolveError;
+
+    #[test]
+    fn try_resolve_out_of_bounds() {
+        let code = sample();
+        assert_eq!(
+            RefString(1).try_resolve(&code.strings),
+            Ok(RefString(1).resolve(&code.strings))
+        );
+        assert_eq!(
+            RefString(code.strings.len()).try_resolve(&code.strings),
+            Err(ResolveError {
+                kind: "string",
+                index: code.strings.len(),
+                len: code.strings.len(),
+            })
+        );
+        assert!(RefInt(code.ints.len()).try_resolve(&code.ints).is_err());
+        assert!(RefType(usize::MAX).try_resolve(&code.types).is_err());
+        assert!(RefType(usize::MAX).resolve_as_obj(&code.types).is_none());
+
+        let main = code.entrypoint;
+        assert!(matches!(main.try_resolve(&code), Ok(FunPtr::Fun(f)) if f.findex == main));
+        let missing = RefFun(code.findexes.len());
+        assert!(matches!(
+            missing.try_resolve(&code),
+            Err(ResolveError {
+                kind: "function",
+                ..
+            })
+        ));
+        assert!(missing.resolve_as_fn(&code).is_none());
+
+        let (point, _) = code
+            .iter_types()
+            .find(|(_, t)| t.get_type_obj().is_some())
+            .unwrap();
+        assert!(point.field(RefField(1000), &code).is_none());
+    }
+}
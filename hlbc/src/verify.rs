@@ -0,0 +1,441 @@
+//! Consistency checks of the code of a bytecode.
+//!
+//! The parser only checks the structure of the file, a corrupted or hand-patched file can still have instructions
+//! using registers or constants that don't exist, or types referring to types that don't exist. Loading runs [verify]
+//! and rejects such a file, an edited bytecode can be checked again before an analysis, the display or the decompiler
+//! panic on it.
+//! ```
+//! use hlbc::builder::sample;
+//! use hlbc::verify::verify;
+//!
+//! assert!(verify(&sample()).is_empty());
+//! ```
+
+use crate::types::{FunPtr, Function, RefFun, RefType};
+use crate::{Bytecode, Opcode, ResolveError, Type, VerifyError};
+
+/// Check the types, globals, natives, constants and every function, returns all the problems found
+pub fn verify(code: &Bytecode) -> Vec<VerifyError> {
+    let mut errors = verify_types(code);
+    errors.extend(code.functions.iter().flat_map(|f| verify_function(code, f)));
+    errors
+}
+
+/// Check the references of the types, globals, natives and constants, which the display of any element using them
+/// follows
+pub fn verify_types(code: &Bytecode) -> Vec<VerifyError> {
+    let mut errors = Vec::new();
+    let ntypes = code.types.len();
+    let nstrings = code.strings.len();
+    // Globals of classes and enums are shifted by one, 0 is none
+    let nglobals = code.globals.len();
+    let mut check = |pool, index, kind, i: usize, len: usize| {
+        if i >= len {
+            errors.push(VerifyError::InvalidPoolReference {
+                pool,
+                index,
+                error: ResolveError {
+                    kind,
+                    index: i,
+                    len,
+                },
+            });
+        }
+    };
+
+    for (i, t) in code.types.iter().enumerate() {
+        let refs: Vec<RefType> = match t {
+            Type::Fun(fun) | Type::Method(fun) => {
+                fun.args.iter().copied().chain([fun.ret]).collect()
+            }
+            Type::Ref(r) | Type::Null(r) | Type::Packed(r) => vec![*r],
+            Type::Virtual { fields } => fields.iter().map(|f| f.t).collect(),
+            Type::Obj(obj) | Type::Struct(obj) => obj
+                .super_
+                .into_iter()
+                .chain(obj.own_fields.iter().map(|f| f.t))
+                .collect(),
+            Type::Enum { constructs, .. } => constructs
+                .iter()
+                .flat_map(|c| c.params.iter().copied())
+                .collect(),
+            _ => Vec::new(),
+        };
+        for r in refs {
+            check("type", i, "type", r.0, ntypes);
+        }
+        match t {
+            Type::Obj(obj) | Type::Struct(obj) => {
+                check("type", i, "string", obj.name.0, nstrings);
+                check("type", i, "global", obj.global.0, nglobals + 1);
+                for f in &obj.own_fields {
+                    check("type", i, "string", f.name.0, nstrings);
+                }
+                for p in &obj.protos {
+                    check("type", i, "string", p.name.0, nstrings);
+                    check("type", i, "function", p.findex.0, code.findexes.len());
+                }
+                for (field, fun) in &obj.bindings {
+                    check("type", i, "field", field.0, obj.fields.len());
+                    check("type", i, "function", fun.0, code.findexes.len());
+                }
+            }
+            Type::Virtual { fields } => {
+                for f in fields {
+                    check("type", i, "string", f.name.0, nstrings);
+                }
+            }
+            Type::Abstract { name } => check("type", i, "string", name.0, nstrings),
+            Type::Enum {
+                name,
+                global,
+                constructs,
+            } => {
+                check("type", i, "string", name.0, nstrings);
+                check("type", i, "global", global.0, nglobals + 1);
+                for c in constructs {
+                    check("type", i, "string", c.name.0, nstrings);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (i, g) in code.globals.iter().enumerate() {
+        check("global", i, "type", g.0, ntypes);
+    }
+
+    for (i, n) in code.natives.iter().enumerate() {
+        check("native", i, "string", n.lib.0, nstrings);
+        check("native", i, "string", n.name.0, nstrings);
+        check("native", i, "type", n.t.0, ntypes);
+    }
+    for n in &code.natives {
+        if matches!(code.types.get(n.t.0), Some(t) if !matches!(t, Type::Fun(_) | Type::Method(_)))
+        {
+            errors.push(VerifyError::NotAFunctionType {
+                findex: n.findex,
+                ty: n.t,
+            });
+        }
+    }
+
+    for (i, c) in code.constants.iter().flatten().enumerate() {
+        let mut check = |kind, index, len| {
+            if index >= len {
+                errors.push(VerifyError::InvalidPoolReference {
+                    pool: "constant",
+                    index: i,
+                    error: ResolveError { kind, index, len },
+                });
+            }
+        };
+        check("global", c.global.0, nglobals);
+        let Some(obj) = code
+            .globals
+            .get(c.global.0)
+            .and_then(|g| g.resolve_as_obj(&code.types))
+        else {
+            continue;
+        };
+        for (f, &value) in obj.own_fields.iter().zip(&c.fields) {
+            match code.types.get(f.t.0) {
+                Some(Type::I32) => check("int", value, code.ints.len()),
+                Some(Type::F64) => check("float", value, code.floats.len()),
+                Some(Type::Bool) => {}
+                Some(Type::Bytes) => check("string", value, nstrings),
+                Some(Type::Type) => check("type", value, ntypes),
+                _ => check("global", value, nglobals),
+            }
+        }
+    }
+    errors
+}
+
+/// Check the signature, registers and instructions of a function
+pub fn verify_function(code: &Bytecode, f: &Function) -> Vec<VerifyError> {
+    let mut errors = Vec::new();
+    let findex = f.findex;
+    let invalid = |pos: Option<usize>, kind: &'static str, index: usize, len: usize| {
+        VerifyError::InvalidReference {
+            findex,
+            pos,
+            error: ResolveError { kind, index, len },
+        }
+    };
+
+    match code.types.get(f.t.0) {
+        Some(Type::Fun(_) | Type::Method(_)) => {}
+        Some(_) => errors.push(VerifyError::NotAFunctionType { findex, ty: f.t }),
+        None => errors.push(invalid(None, "type", f.t.0, code.types.len())),
+    }
+    for r in &f.regs {
+        if r.0 >= code.types.len() {
+            errors.push(invalid(None, "type", r.0, code.types.len()));
+        }
+    }
+    let nstrings = code.strings.len();
+    for &(name, _) in f.assigns.iter().flatten() {
+        if name.0 >= nstrings {
+            errors.push(invalid(None, "string", name.0, nstrings));
+        }
+    }
+    // Only the first one, the whole debug info is likely corrupted
+    let nfiles = code.debug_files.as_ref().map_or(0, |files| files.len());
+    if let Some((pos, &(file, _))) = f
+        .debug_info
+        .iter()
+        .flatten()
+        .enumerate()
+        .find(|(_, &(file, _))| file >= nfiles)
+    {
+        errors.push(invalid(Some(pos), "debug file", file, nfiles));
+    }
+
+    for (pos, o) in f.ops.iter().enumerate() {
+        let regs = o.regs();
+        if let Some(&reg) = regs.iter().find(|r| r.0 as usize >= f.regs.len()) {
+            errors.push(VerifyError::InvalidRegister {
+                findex,
+                pos,
+                reg,
+                nregs: f.regs.len(),
+            });
+            // Other checks need the register types
+            continue;
+        }
+        // Negative targets wrap around
+        if o.jump_targets(pos).into_iter().any(|t| t >= f.ops.len()) {
+            errors.push(VerifyError::JumpOutOfBounds { findex, pos });
+        }
+
+        let (kind, index, len) = match *o {
+            Opcode::Int { ptr, .. } => ("int", ptr.0, code.ints.len()),
+            Opcode::Float { ptr, .. } => ("float", ptr.0, code.floats.len()),
+            Opcode::Bytes { ptr, .. } => match &code.bytes {
+                Some((_, bytes)) => ("bytes", ptr.0, bytes.len()),
+                // Bytes are strings before v5
+                None => ("string", ptr.0, nstrings),
+            },
+            Opcode::String { ptr, .. } => ("string", ptr.0, nstrings),
+            Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                ("string", field.0, nstrings)
+            }
+            Opcode::Type { ty, .. } => ("type", ty.0, code.types.len()),
+            Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                ("global", global.0, code.globals.len())
+            }
+            Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } => {
+                ("function", fun.0, code.findexes.len())
+            }
+            Opcode::Field { obj, field, .. } | Opcode::SetField { obj, field, .. } => {
+                ("field", field.0, field_count(code, f.regtype(obj)))
+            }
+            Opcode::GetThis { field, .. } | Opcode::SetThis { field, .. } => {
+                ("field", field.0, field_count(code, f.regs[0]))
+            }
+            _ => match call(o) {
+                Some((fun, _)) => ("function", fun.0, code.findexes.len()),
+                None => continue,
+            },
+        };
+        if index >= len {
+            errors.push(invalid(Some(pos), kind, index, len));
+            continue;
+        }
+
+        if let Some((callee, got)) = call(o) {
+            if let Some(expected) = arity(code, callee) {
+                if expected != got {
+                    errors.push(VerifyError::ArityMismatch {
+                        findex,
+                        pos,
+                        callee,
+                        expected,
+                        got,
+                    });
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// Function called directly and the number of arguments
+fn call(o: &Opcode) -> Option<(RefFun, usize)> {
+    match o {
+        Opcode::Call0 { fun, .. } => Some((*fun, 0)),
+        Opcode::Call1 { fun, .. } => Some((*fun, 1)),
+        Opcode::Call2 { fun, .. } => Some((*fun, 2)),
+        Opcode::Call3 { fun, .. } => Some((*fun, 3)),
+        Opcode::Call4 { fun, .. } => Some((*fun, 4)),
+        Opcode::CallN { fun, args, .. } => Some((*fun, args.len())),
+        _ => None,
+    }
+}
+
+/// Number of arguments of a function, if its type is valid
+fn arity(code: &Bytecode, f: RefFun) -> Option<usize> {
+    let ty = match code.get_fun(f)? {
+        FunPtr::Fun(f) => f.t,
+        FunPtr::Native(n) => n.t,
+    };
+    match code.types.get(ty.0)? {
+        Type::Fun(fun) | Type::Method(fun) => Some(fun.args.len()),
+        _ => None,
+    }
+}
+
+/// Number of fields of an object or a virtual, unlimited for other types as we can't know
+fn field_count(code: &Bytecode, ty: RefType) -> usize {
+    match code.types.get(ty.0) {
+        Some(Type::Virtual { fields }) => fields.len(),
+        Some(t) => t.get_type_obj().map_or(usize::MAX, |obj| obj.fields.len()),
+        None => usize::MAX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::types::{RefGlobal, RefInt, RefString, RefType, Reg, TypeFun};
+    use crate::verify::{verify, verify_function, verify_types};
+    use crate::{Opcode, ResolveError, Type, VerifyError};
+
+    #[test]
+    fn verify_patched() {
+        let mut code = sample();
+        assert_eq!(verify(&code), []);
+
+        let main = code.entrypoint;
+        let f = &mut code.functions[0];
+        let nregs = f.regs.len();
+        let findex = f.findex;
+        f.ops.insert(
+            0,
+            Opcode::Int {
+                dst: Reg(nregs as u32),
+                ptr: RefInt(0),
+            },
+        );
+        f.ops.insert(
+            1,
+            Opcode::Int {
+                dst: Reg(0),
+                ptr: RefInt(1000),
+            },
+        );
+        f.ops.insert(
+            2,
+            Opcode::Call1 {
+                dst: Reg(0),
+                fun: main,
+                arg0: Reg(0),
+            },
+        );
+        f.ops.insert(3, Opcode::JAlways { offset: 1000 });
+        let errors = verify(&code);
+        assert!(matches!(
+            errors[0],
+            VerifyError::InvalidRegister { pos: 0, reg, .. } if reg.0 as usize == nregs
+        ));
+        assert!(matches!(
+            &errors[1],
+            VerifyError::InvalidReference { pos: Some(1), error, .. } if error.kind == "int"
+        ));
+        assert!(matches!(
+            errors[2],
+            VerifyError::ArityMismatch {
+                pos: 2,
+                expected: 0,
+                got: 1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[3],
+            VerifyError::JumpOutOfBounds { findex: f, pos: 3 } if f == findex
+        ));
+
+        // Debug names and files, followed by the display
+        let f = &mut code.functions[1];
+        f.assigns = Some(vec![(RefString(100000), 0)]);
+        f.debug_info = Some(vec![(1000, 1); f.ops.len()]);
+        let errors = verify_function(&code, &code.functions[1]);
+        assert!(matches!(
+            &errors[..],
+            [
+                VerifyError::InvalidReference { pos: None, error: names, .. },
+                VerifyError::InvalidReference { pos: Some(0), error: files, .. },
+            ] if names.kind == "string" && files.kind == "debug file"
+        ));
+    }
+
+    #[test]
+    fn verify_patched_types() {
+        let mut code = sample();
+        assert_eq!(verify_types(&code), []);
+
+        let ntypes = code.types.len() + 1;
+        code.types.push(Type::Fun(TypeFun {
+            args: vec![RefType(0)],
+            ret: RefType(ntypes + 55),
+        }));
+        code.globals.push(RefType(ntypes));
+        code.natives[0].name = RefString(code.strings.len());
+        let nglobals = code.globals.len();
+        code.constants.as_mut().unwrap()[0].global = RefGlobal(nglobals);
+        let errors = verify_types(&code);
+        assert_eq!(
+            errors,
+            [
+                VerifyError::InvalidPoolReference {
+                    pool: "type",
+                    index: ntypes - 1,
+                    error: ResolveError {
+                        kind: "type",
+                        index: ntypes + 55,
+                        len: ntypes,
+                    },
+                },
+                VerifyError::InvalidPoolReference {
+                    pool: "global",
+                    index: code.globals.len() - 1,
+                    error: ResolveError {
+                        kind: "type",
+                        index: ntypes,
+                        len: ntypes,
+                    },
+                },
+                VerifyError::InvalidPoolReference {
+                    pool: "native",
+                    index: 0,
+                    error: ResolveError {
+                        kind: "string",
+                        index: code.strings.len(),
+                        len: code.strings.len(),
+                    },
+                },
+                VerifyError::InvalidPoolReference {
+                    pool: "constant",
+                    index: 0,
+                    error: ResolveError {
+                        kind: "global",
+                        index: nglobals,
+                        len: nglobals,
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            format!(
+                "type@{} : Invalid reference to type {} (there are {ntypes})",
+                ntypes - 1,
+                ntypes + 55
+            )
+        );
+        // Checked before the functions
+        assert_eq!(verify(&code)[..errors.len()], errors);
+    }
+}
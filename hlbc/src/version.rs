@@ -0,0 +1,30 @@
+//! Differences between the versions of the bytecode format.
+//!
+//! | Version | Changes                                                                |
+//! |---------|------------------------------------------------------------------------|
+//! | 2       | Oldest version loaded by HashLink                                      |
+//! | 3       | Variable assignments in the debug information                          |
+//! | 4       | Constants pool, initializing globals                                   |
+//! | 5       | Bytes pool, `Opcode::Bytes` refers to it (and not to the strings pool) |
+//!
+//! Files more recent than [MAX] are loaded as if they were [MAX], with a warning.
+
+/// Oldest supported version
+pub const MIN: u8 = 2;
+/// Latest known version
+pub const MAX: u8 = 5;
+
+/// Functions with debug information have variable assignments
+pub const fn has_assigns(version: u8) -> bool {
+    version >= 3
+}
+
+/// The constants pool exists
+pub const fn has_constants(version: u8) -> bool {
+    version >= 4
+}
+
+/// The bytes pool exists, `Opcode::Bytes` refers to it instead of the strings pool
+pub const fn has_bytes(version: u8) -> bool {
+    version >= 5
+}
@@ -0,0 +1,192 @@
+//! Non-fatal problems found while loading a bytecode.
+//!
+//! Files produced by the Haxe compiler don't have any of these, a warning usually means the file has been modified by
+//! another tool (or obfuscated) and that some analysis results may be surprising. They are collected by
+//! [Bytecode::load] in [Bytecode::warnings].
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::types::RefFun;
+use crate::{version, Bytecode, Opcode, Type};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Warning {
+    /// The version is more recent than what we know, the file may be parsed incorrectly
+    UnknownVersion(u8),
+    /// Header flags other than the debug flag
+    UnknownFlags(u32),
+    /// Several functions or natives share a findex, only the last one can be called
+    DuplicateFindex(RefFun),
+    /// No function or native has this findex
+    MissingFindex(RefFun),
+    /// A function or a native has a findex greater than the number of functions and natives
+    InvalidFindex(RefFun),
+    /// Entries of a constant pool never used by an instruction or a constant
+    UnusedConstants { pool: &'static str, count: usize },
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::UnknownVersion(v) => write!(
+                f,
+                "Unknown bytecode version {v} (latest known is {})",
+                version::MAX
+            ),
+            Warning::UnknownFlags(flags) => write!(f, "Unknown header flags {flags:#x}"),
+            Warning::DuplicateFindex(findex) => {
+                write!(f, "Multiple functions with findex {}", findex.0)
+            }
+            Warning::MissingFindex(findex) => write!(f, "No function with findex {}", findex.0),
+            Warning::InvalidFindex(findex) => write!(f, "Findex {} is out of range", findex.0),
+            Warning::UnusedConstants { pool, count } => {
+                write!(f, "{count} unused constants in the {pool} pool")
+            }
+        }
+    }
+}
+
+impl Warning {
+    /// Function and instruction the warning is about, if any
+    pub fn location(&self) -> Option<(RefFun, Option<usize>)> {
+        match *self {
+            Warning::DuplicateFindex(findex) | Warning::MissingFindex(findex) => {
+                Some((findex, None))
+            }
+            Warning::UnknownVersion(_)
+            | Warning::UnknownFlags(_)
+            | Warning::InvalidFindex(_)
+            | Warning::UnusedConstants { .. } => None,
+        }
+    }
+}
+
+/// Inspect a freshly loaded bytecode, `flags` are the header flags
+pub(crate) fn check(code: &Bytecode, flags: u32) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    if code.version > version::MAX {
+        warnings.push(Warning::UnknownVersion(code.version));
+    }
+    if flags & !1 != 0 {
+        warnings.push(Warning::UnknownFlags(flags & !1));
+    }
+
+    let mut uses = vec![0usize; code.findexes.len()];
+    for findex in code
+        .functions
+        .iter()
+        .map(|f| f.findex)
+        .chain(code.natives.iter().map(|n| n.findex))
+    {
+        match uses.get_mut(findex.0) {
+            Some(n) => *n += 1,
+            None => warnings.push(Warning::InvalidFindex(findex)),
+        }
+    }
+    for (i, n) in uses.into_iter().enumerate() {
+        match n {
+            0 => warnings.push(Warning::MissingFindex(RefFun(i))),
+            1 => {}
+            _ => warnings.push(Warning::DuplicateFindex(RefFun(i))),
+        }
+    }
+
+    let mut ints = vec![false; code.ints.len()];
+    let mut floats = vec![false; code.floats.len()];
+    let mut bytes = vec![false; code.bytes.as_ref().map_or(0, |(_, pos)| pos.len())];
+    for (_, (_, o)) in code.ops() {
+        let used = match *o {
+            Opcode::Int { ptr, .. } => ints.get_mut(ptr.0),
+            Opcode::Float { ptr, .. } => floats.get_mut(ptr.0),
+            Opcode::Bytes { ptr, .. } => bytes.get_mut(ptr.0),
+            _ => None,
+        };
+        if let Some(used) = used {
+            *used = true;
+        }
+    }
+    for c in code.constants.iter().flatten() {
+        let fields = code.globals[c.global.0]
+            .resolve_as_obj(&code.types)
+            .map(|obj| obj.fields.as_slice())
+            .unwrap_or_default();
+        for (field, &v) in fields.iter().zip(&c.fields) {
+            let used = match field.t.try_resolve(&code.types) {
+                Ok(Type::I32) => ints.get_mut(v),
+                Ok(Type::F64) => floats.get_mut(v),
+                _ => None,
+            };
+            if let Some(used) = used {
+                *used = true;
+            }
+        }
+    }
+    for (pool, used) in [("ints", ints), ("floats", floats), ("bytes", bytes)] {
+        let count = used.iter().filter(|u| !**u).count();
+        if count > 0 {
+            warnings.push(Warning::UnusedConstants { pool, count });
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::sample;
+    use crate::types::{RefFun, RefGlobal};
+    use crate::warnings::{check, Warning};
+    use crate::{Bytecode, Error, ResolveError, VerifyError};
+
+    #[test]
+    fn warnings() {
+        let mut code = sample();
+        assert_eq!(code.warnings, []);
+
+        let missing = code.natives[0].findex;
+        code.natives[0].findex = code.functions[0].findex;
+        code.ints.push(1234);
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        let loaded = Bytecode::load(&mut data.as_slice()).unwrap();
+        let duplicate = code.functions[0].findex;
+        assert!(loaded
+            .warnings
+            .contains(&Warning::DuplicateFindex(duplicate)));
+        assert!(loaded.warnings.contains(&Warning::MissingFindex(missing)));
+        assert!(loaded.warnings.contains(&Warning::UnusedConstants {
+            pool: "ints",
+            count: 1
+        }));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut code = sample();
+        code.constants.as_mut().unwrap()[0].global = RefGlobal(1000);
+        let mut data = Vec::new();
+        code.serialize(&mut data).unwrap();
+        // An error rather than a warning, the display indexes the globals
+        let Err(Error::InvalidReferences(errors)) = Bytecode::load(&mut data.as_slice()) else {
+            panic!("loaded a constant of a global that doesn't exist");
+        };
+        assert_eq!(
+            errors,
+            [VerifyError::InvalidPoolReference {
+                pool: "constant",
+                index: 0,
+                error: ResolveError {
+                    kind: "global",
+                    index: 1000,
+                    len: code.globals.len()
+                }
+            }]
+        );
+
+        // Rejected by the parser, only possible after an edit
+        let mut code = sample();
+        let invalid = RefFun(code.findexes.len() + 10);
+        code.natives[0].findex = invalid;
+        assert!(check(&code, 0).contains(&Warning::InvalidFindex(invalid)));
+    }
+}